@@ -3,7 +3,7 @@
 
 use aptos_channels::{self, aptos_channel, message_queues::QueueStyle};
 use aptos_config::{
-    config::{NetworkConfig, NodeConfig},
+    config::{NetworkConfig, NodeConfig, ProtocolPreferenceProfile},
     network_id::NetworkId,
 };
 use aptos_consensus::network_interface::{ConsensusMsg, DIRECT_SEND, RPC};
@@ -13,6 +13,7 @@ use aptos_mempool::network::MempoolSyncMsg;
 use aptos_network::{
     application::{
         interface::{NetworkClient, NetworkServiceEvents},
+        peer_report::{start_peer_reporter, PEER_REPORT_INTERVAL},
         storage::PeerMetadataStorage,
     },
     protocols::network::{
@@ -55,13 +56,15 @@ pub fn consensus_network_configuration() -> NetworkApplicationConfig {
     let direct_send_protocols: Vec<ProtocolId> = DIRECT_SEND.into();
     let rpc_protocols: Vec<ProtocolId> = RPC.into();
 
+    // Consensus messages don't wait behind the bulk traffic of other protocols
     let network_client_config =
-        NetworkClientConfig::new(direct_send_protocols.clone(), rpc_protocols.clone());
+        NetworkClientConfig::new(direct_send_protocols.clone(), rpc_protocols.clone()).isolated();
     let network_service_config = NetworkServiceConfig::new(
         direct_send_protocols,
         rpc_protocols,
         aptos_channel::Config::new(CONSENSUS_NETWORK_CHANNEL_BUFFER_SIZE)
             .queue_style(QueueStyle::FIFO)
+            .fair_queuing(CONSENSUS_NETWORK_CHANNEL_BUFFER_SIZE)
             .counters(&aptos_consensus::counters::PENDING_CONSENSUS_NETWORK_EVENTS),
     );
     NetworkApplicationConfig::new(network_client_config, network_service_config)
@@ -156,6 +159,17 @@ pub fn setup_networks_and_get_interfaces(
     // Create the global peer metadata storage
     let peer_metadata_storage = PeerMetadataStorage::new(&network_ids);
 
+    // Gather the protocol preference profile of each network (e.g., by role)
+    let protocol_preference_profiles: HashMap<_, _> = network_configs
+        .iter()
+        .map(|network_config| {
+            (
+                network_config.network_id,
+                network_config.protocol_preference_profile,
+            )
+        })
+        .collect();
+
     // Create each network and register the application handles
     let mut network_runtimes = vec![];
     let mut consensus_network_handle = None;
@@ -219,6 +233,16 @@ pub fn setup_networks_and_get_interfaces(
         );
     }
 
+    // Periodically report the health of the peers (e.g., to telemetry)
+    if let Some(runtime) = network_runtimes.first() {
+        start_peer_reporter(
+            peer_metadata_storage.clone(),
+            TimeService::real(),
+            PEER_REPORT_INTERVAL,
+            runtime.handle(),
+        );
+    }
+
     // Transform all network handles into application interfaces
     let (consensus_interfaces, mempool_interfaces, storage_service_interfaces) =
         transform_network_handles_into_interfaces(
@@ -227,6 +251,7 @@ pub fn setup_networks_and_get_interfaces(
             mempool_network_handles,
             storage_service_network_handles,
             peer_metadata_storage,
+            protocol_preference_profiles,
         );
 
     (
@@ -273,6 +298,7 @@ fn transform_network_handles_into_interfaces(
     mempool_network_handles: Vec<ApplicationNetworkHandle<MempoolSyncMsg>>,
     storage_service_network_handles: Vec<ApplicationNetworkHandle<StorageServiceMessage>>,
     peer_metadata_storage: Arc<PeerMetadataStorage>,
+    protocol_preference_profiles: HashMap<NetworkId, ProtocolPreferenceProfile>,
 ) -> (
     Option<ApplicationNetworkInterfaces<ConsensusMsg>>,
     ApplicationNetworkInterfaces<MempoolSyncMsg>,
@@ -283,17 +309,20 @@ fn transform_network_handles_into_interfaces(
             vec![consensus_network_handle],
             consensus_network_configuration(),
             peer_metadata_storage.clone(),
+            protocol_preference_profiles.clone(),
         )
     });
     let mempool_interfaces = create_network_interfaces(
         mempool_network_handles,
         mempool_network_configuration(),
         peer_metadata_storage.clone(),
+        protocol_preference_profiles.clone(),
     );
     let storage_service_interfaces = create_network_interfaces(
         storage_service_network_handles,
         storage_service_network_configuration(node_config),
         peer_metadata_storage,
+        protocol_preference_profiles,
     );
 
     (
@@ -311,6 +340,7 @@ fn create_network_interfaces<
     network_handles: Vec<ApplicationNetworkHandle<T>>,
     network_application_config: NetworkApplicationConfig,
     peer_metadata_storage: Arc<PeerMetadataStorage>,
+    protocol_preference_profiles: HashMap<NetworkId, ProtocolPreferenceProfile>,
 ) -> ApplicationNetworkInterfaces<T> {
     // Gather the network senders and events
     let mut network_senders = HashMap::new();
@@ -323,12 +353,16 @@ fn create_network_interfaces<
 
     // Create the network client
     let network_client_config = network_application_config.network_client_config;
-    let network_client = NetworkClient::new(
+    let mut network_client = NetworkClient::new(
         network_client_config.direct_send_protocols_and_preferences,
         network_client_config.rpc_protocols_and_preferences,
         network_senders,
         peer_metadata_storage,
-    );
+    )
+    .with_protocol_preference_profiles(protocol_preference_profiles);
+    if let Some(window) = network_client_config.broadcast_dedup_window {
+        network_client = network_client.with_broadcast_dedup(window, TimeService::real());
+    }
 
     // Create the network service events
     let network_service_events = NetworkServiceEvents::new(network_and_events);
@@ -36,6 +36,10 @@ pub const NETWORK_CHANNEL_SIZE: usize = 1024;
 pub const PING_INTERVAL_MS: u64 = 10_000;
 pub const PING_TIMEOUT_MS: u64 = 20_000;
 pub const PING_FAILURES_TOLERATED: u64 = 3;
+pub const NAT_OBSERVATION_INTERVAL_MS: u64 = 60_000; /* 1 minute */
+pub const NAT_TRAVERSAL_RPC_TIMEOUT_MS: u64 = 10_000;
+pub const PEER_EXCHANGE_INTERVAL_MS: u64 = 300_000; /* 5 minutes */
+pub const PEER_EXCHANGE_RPC_TIMEOUT_MS: u64 = 10_000;
 pub const CONNECTIVITY_CHECK_INTERVAL_MS: u64 = 5000;
 pub const MAX_CONCURRENT_NETWORK_REQS: usize = 100;
 pub const MAX_CONNECTION_DELAY_MS: u64 = 60_000; /* 1 minute */
@@ -47,7 +51,13 @@ pub const MAX_APPLICATION_MESSAGE_SIZE: usize =
     (MAX_MESSAGE_SIZE - MAX_MESSAGE_METADATA_SIZE) - MESSAGE_PADDING_SIZE; /* The message size that applications should check against */
 pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024; /* 4 MiB large messages will be chunked into multiple frames and streamed */
 pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024; /* 64 MiB */
+pub const MAX_REASSEMBLY_SIZE: usize = 128 * 1024 * 1024; /* 128 MiB per peer to reassemble fragmented messages */
 pub const CONNECTION_BACKOFF_BASE: u64 = 2;
+pub const CONNECTION_BACKOFF_DELAY_MS: u64 = 1000;
+pub const CONNECTION_BACKOFF_JITTER_MS: u64 = 100;
+pub const MAX_TRACKED_PEERS: usize = 10_000;
+pub const PEER_METADATA_RETENTION_SECS: u64 = 86_400; /* 1 day */
+pub const PEER_METADATA_GC_INTERVAL_SECS: u64 = 60;
 pub const IP_BYTE_BUCKET_RATE: usize = 102400 /* 100 KiB */;
 pub const IP_BYTE_BUCKET_SIZE: usize = IP_BYTE_BUCKET_RATE;
 pub const INBOUND_TCP_RX_BUFFER_SIZE: u32 = 3 * 1024 * 1024; // 3MB ~6MB/s with 500ms latency
@@ -62,6 +72,10 @@ pub struct NetworkConfig {
     pub max_connection_delay_ms: u64,
     // Base for outbound connection backoff
     pub connection_backoff_base: u64,
+    // The rest of the outbound dial retry policy (delay unit, jitter, max attempts)
+    pub dial_backoff: DialBackoffConfig,
+    // The maximum number of concurrent dials to the peers of each role
+    pub dial_concurrency: DialConcurrencyConfig,
     // Rate to check connectivity to connected peers
     pub connectivity_check_interval_ms: u64,
     // Size of all network channels
@@ -92,26 +106,81 @@ pub struct NetworkConfig {
     pub seed_addrs: HashMap<PeerId, Vec<NetworkAddress>>,
     // The initial peers to connect to prior to onchain discovery
     pub seeds: PeerSet,
+    // An optional file (in the same format as `seeds`) that is polled for changes.
+    // When the file changes, its peers replace the seed peers at runtime, so that
+    // seeds can be rotated without restarting the node.
+    pub seeds_file: Option<FileDiscovery>,
+    // Persists the known peers (addresses, scores and last-seen times) to disk, so
+    // that they can be reconnected to quickly after a restart. Ignored on the
+    // validator network, whose peers come from the on-chain validator set.
+    pub peer_persistence: Option<PeerPersistenceConfig>,
+    // Bounds the metadata (e.g., scores) kept for disconnected peers, so that it
+    // doesn't grow without bound on long-running public networks
+    pub peer_metadata_gc: PeerMetadataGcConfig,
     // The maximum size of an inbound or outbound request frame
     pub max_frame_size: usize,
     // Enables proxy protocol on incoming connections to get original source addresses
     pub enable_proxy_protocol: bool,
+    // The SOCKS5 proxy through which outbound connections are established, e.g., for
+    // nodes in egress-restricted environments
+    pub socks5_proxy: Option<Socks5ProxyConfig>,
+    // The protocol used to secure and authenticate connections. All peers of the
+    // network must use the same protocol.
+    pub transport_security: TransportSecurity,
     // Interval to send healthcheck pings to peers
     pub ping_interval_ms: u64,
+    // Interval up to which the pings to stable peers are backed off (the pings to new
+    // and flapping peers are sent every `ping_interval_ms`). If not specified, all
+    // peers are pinged every `ping_interval_ms`.
+    pub max_ping_interval_ms: Option<u64>,
     // Timeout until a healthcheck ping is rejected
     pub ping_timeout_ms: u64,
     // Number of failed healthcheck pings until a peer is marked unhealthy
     pub ping_failures_tolerated: u64,
+    // Number of failed healthcheck pings until a peer is marked as `Disconnecting`, so
+    // that applications stop selecting it before it's disconnected, or `None` to only
+    // mark it once it's disconnected
+    pub dead_peer_missed_pings: Option<u64>,
+    // TCP keepalive probes of the connections, or `None` to keep the OS defaults
+    pub tcp_keepalive: Option<TcpKeepaliveConfig>,
+    // Enables NAT traversal (i.e., reflexive address discovery and hole punching)
+    pub enable_nat_traversal: bool,
+    // Interval to ask peers for our observed address, when NAT traversal is enabled
+    pub nat_observation_interval_ms: u64,
+    // Timeout of NAT traversal requests (including relayed hole punches)
+    pub nat_traversal_rpc_timeout_ms: u64,
+    // Enables the peer exchange protocol, i.e., learning about other public peers
+    // from the connected peers. Ignored on the validator network.
+    pub enable_peer_exchange: bool,
+    // Interval to ask peers for the public peers they know, when peer exchange is enabled
+    pub peer_exchange_interval_ms: u64,
+    // Timeout of peer exchange requests
+    pub peer_exchange_rpc_timeout_ms: u64,
     // Maximum number of outbound connections, limited by ConnectivityManager
     pub max_outbound_connections: usize,
     // Maximum number of outbound connections, limited by PeerManager
     pub max_inbound_connections: usize,
+    // How to make room for new public peers once a connection limit is reached
+    pub connection_eviction_policy: EvictionPolicy,
     // Inbound rate limiting configuration, if not specified, no rate limiting
     pub inbound_rate_limit_config: Option<RateLimitConfig>,
     // Outbound rate limiting configuration, if not specified, no rate limiting
     pub outbound_rate_limit_config: Option<RateLimitConfig>,
+    // Per-peer and per-network bandwidth caps, in addition to the per-IP rate limits
+    pub bandwidth_limit_config: BandwidthLimitConfig,
     // The maximum size of an inbound or outbound message (it may be divided into multiple frame)
     pub max_message_size: usize,
+    // The maximum message sizes of individual protocols, by protocol name (e.g.,
+    // "ConsensusRpcBcs"). These can only tighten `max_message_size`, and can be
+    // updated at runtime through the network's size limits handle.
+    pub max_protocol_message_sizes: HashMap<String, usize>,
+    // The maximum size of a message that is fragmented for (and reassembled by) peers
+    // that support fragmentation. This also caps the memory used to reassemble the
+    // inbound messages of each peer.
+    pub max_reassembly_size: usize,
+    // How the network clients of the applications order their protocols when picking
+    // one for a peer of this network (e.g., compressed-first on public networks)
+    pub protocol_preference_profile: ProtocolPreferenceProfile,
 }
 
 impl Default for NetworkConfig {
@@ -133,21 +202,42 @@ impl NetworkConfig {
             runtime_threads: None,
             seed_addrs: HashMap::new(),
             seeds: PeerSet::default(),
+            seeds_file: None,
+            peer_persistence: None,
+            peer_metadata_gc: PeerMetadataGcConfig::default(),
             max_frame_size: MAX_FRAME_SIZE,
             enable_proxy_protocol: false,
+            socks5_proxy: None,
+            transport_security: TransportSecurity::Noise,
             max_connection_delay_ms: MAX_CONNECTION_DELAY_MS,
             connectivity_check_interval_ms: CONNECTIVITY_CHECK_INTERVAL_MS,
             network_channel_size: NETWORK_CHANNEL_SIZE,
             max_concurrent_network_reqs: MAX_CONCURRENT_NETWORK_REQS,
             connection_backoff_base: CONNECTION_BACKOFF_BASE,
+            dial_backoff: DialBackoffConfig::default(),
+            dial_concurrency: DialConcurrencyConfig::default(),
             ping_interval_ms: PING_INTERVAL_MS,
+            max_ping_interval_ms: None,
             ping_timeout_ms: PING_TIMEOUT_MS,
             ping_failures_tolerated: PING_FAILURES_TOLERATED,
+            dead_peer_missed_pings: None,
+            tcp_keepalive: None,
+            enable_nat_traversal: false,
+            nat_observation_interval_ms: NAT_OBSERVATION_INTERVAL_MS,
+            nat_traversal_rpc_timeout_ms: NAT_TRAVERSAL_RPC_TIMEOUT_MS,
+            enable_peer_exchange: false,
+            peer_exchange_interval_ms: PEER_EXCHANGE_INTERVAL_MS,
+            peer_exchange_rpc_timeout_ms: PEER_EXCHANGE_RPC_TIMEOUT_MS,
             max_outbound_connections: MAX_FULLNODE_OUTBOUND_CONNECTIONS,
             max_inbound_connections: MAX_INBOUND_CONNECTIONS,
+            connection_eviction_policy: EvictionPolicy::Reject,
             inbound_rate_limit_config: None,
             outbound_rate_limit_config: None,
+            bandwidth_limit_config: BandwidthLimitConfig::default(),
             max_message_size: MAX_MESSAGE_SIZE,
+            max_protocol_message_sizes: HashMap::new(),
+            max_reassembly_size: MAX_REASSEMBLY_SIZE,
+            protocol_preference_profile: ProtocolPreferenceProfile::default(),
             inbound_rx_buffer_size_bytes: Some(INBOUND_TCP_RX_BUFFER_SIZE),
             inbound_tx_buffer_size_bytes: Some(INBOUND_TCP_TX_BUFFER_SIZE),
             outbound_rx_buffer_size_bytes: Some(OUTBOUND_TCP_RX_BUFFER_SIZE),
@@ -439,6 +529,204 @@ impl Default for RateLimitConfig {
     }
 }
 
+/// The protocol used to secure and authenticate the connections of a network. In
+/// both cases, peers are identified by their x25519 network keys.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportSecurity {
+    /// The Noise IK handshake
+    Noise,
+    /// TLS 1.3 with self-signed certificates bound to the x25519 network keys. Meant
+    /// for deployments whose compliance requirements mandate TLS.
+    Tls,
+}
+
+impl Default for TransportSecurity {
+    fn default() -> Self {
+        TransportSecurity::Noise
+    }
+}
+
+/// How to make room for a connection to or from a public (i.e., untrusted) peer once
+/// the connection limit of the network is reached. Trusted peers are never evicted.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Reject new inbound connections
+    Reject,
+    /// Evict the public peer with the lowest score, if it's lower than the score of
+    /// the new peer
+    LowestScore,
+    /// Evict the public peer that has been idle the longest, if it has been idle for
+    /// long enough
+    LongestIdle,
+}
+
+/// How the network clients order the protocols of an application (e.g., the
+/// encodings of consensus messages) when picking one for a peer. Protocols of
+/// the same encoding keep the order of the application.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolPreferenceProfile {
+    /// Use the order of the application
+    #[default]
+    Application,
+    /// Prefer compressed protocols, e.g., where bandwidth is scarcer than CPU
+    CompressedFirst,
+    /// Prefer uncompressed (BCS) protocols, e.g., where CPU is scarcer than bandwidth
+    BcsFirst,
+}
+
+/// Bandwidth caps of the remote peers and of the network as a whole. Unlike the
+/// `RateLimitConfig`s, which are keyed by IP address, a peer's caps are shared by all
+/// of its connections. Caps that aren't specified aren't enforced.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthLimitConfig {
+    /// Upload cap of each remote peer
+    pub peer_upload: Option<ByteRateLimit>,
+    /// Download cap of each remote peer
+    pub peer_download: Option<ByteRateLimit>,
+    /// Upload cap shared by all remote peers of the network
+    pub network_upload: Option<ByteRateLimit>,
+    /// Download cap shared by all remote peers of the network
+    pub network_download: Option<ByteRateLimit>,
+}
+
+/// How failed outbound dials are retried. The n-th consecutive retry of a peer is
+/// delayed by `base_delay_ms * connection_backoff_base^n` (capped by the
+/// `max_connection_delay_ms`), plus a random jitter.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DialBackoffConfig {
+    /// The unit of the exponential backoff delays
+    pub base_delay_ms: u64,
+    /// The maximum random jitter added to each delay, so that dials are spread out
+    pub jitter_ms: u64,
+    /// The number of consecutive failed dials after which a peer is no longer
+    /// dialed (until its addresses change). If not specified, dials never stop.
+    pub max_attempts: Option<u32>,
+    /// Whether the backoff of a peer starts over once it's connected. Otherwise,
+    /// peers that repeatedly disconnect keep being redialed with longer delays.
+    pub reset_on_success: bool,
+}
+
+impl Default for DialBackoffConfig {
+    fn default() -> Self {
+        Self {
+            base_delay_ms: CONNECTION_BACKOFF_DELAY_MS,
+            jitter_ms: CONNECTION_BACKOFF_JITTER_MS,
+            max_attempts: None,
+            reset_on_success: true,
+        }
+    }
+}
+
+/// The maximum number of concurrent pending dials to the peers of each role, so that
+/// the public peers can't hold up the dials to validators and VFNs (which are dialed
+/// first). Budgets that aren't specified aren't enforced.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DialConcurrencyConfig {
+    /// Budget of the dials to validators
+    pub validators: Option<usize>,
+    /// Budget of the dials to validator full nodes
+    pub validator_fullnodes: Option<usize>,
+    /// Budget of the dials to all other (i.e., public) peers
+    pub public_peers: Option<usize>,
+}
+
+/// The TCP keepalive probes of the connections of a network, which detect dead
+/// connections even when there is no traffic
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpKeepaliveConfig {
+    /// The idle time after which the first probe is sent
+    pub time_secs: u64,
+    /// The time between unanswered probes
+    pub interval_secs: u64,
+    /// The number of unanswered probes after which the connection is dropped
+    pub retries: u32,
+}
+
+impl Default for TcpKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            time_secs: 60,
+            interval_secs: 10,
+            retries: 3,
+        }
+    }
+}
+
+/// A SOCKS5 proxy for the outbound connections of a network. DNS names are
+/// resolved by the proxy.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Socks5ProxyConfig {
+    /// The address of the proxy, e.g., "127.0.0.1:1080"
+    pub address: String,
+    /// The username, if the proxy requires authentication
+    #[serde(default)]
+    pub username: Option<String>,
+    /// The password, if the proxy requires authentication
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl Socks5ProxyConfig {
+    /// Returns the username and password, if both are set
+    pub fn credentials(&self) -> Option<(String, String)> {
+        self.username.clone().zip(self.password.clone())
+    }
+}
+
+/// Where and how often the known peers of a network are persisted
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerPersistenceConfig {
+    /// The file the known peers are written to (and loaded from on startup)
+    pub path: PathBuf,
+    /// How often the known peers are written to the file
+    pub interval_secs: u64,
+    /// Peers that haven't been seen for longer than this are forgotten
+    pub max_age_secs: u64,
+}
+
+/// How long, and for how many peers, the metadata of disconnected peers (e.g., their
+/// scores and dial backoffs) is kept. The metadata of connected peers is never
+/// collected, and neither are peer bans, which expire on their own.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerMetadataGcConfig {
+    /// The maximum number of peers (connected or not) whose metadata is kept. The
+    /// metadata of the peers disconnected the longest is collected first.
+    pub max_tracked_peers: usize,
+    /// The metadata of peers disconnected for longer than this is collected
+    pub retention_secs: u64,
+    /// How often the metadata is collected
+    pub interval_secs: u64,
+}
+
+impl Default for PeerMetadataGcConfig {
+    fn default() -> Self {
+        Self {
+            max_tracked_peers: MAX_TRACKED_PEERS,
+            retention_secs: PEER_METADATA_RETENTION_SECS,
+            interval_secs: PEER_METADATA_GC_INTERVAL_SECS,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ByteRateLimit {
+    /// Maximum number of bytes/s
+    pub byte_bucket_rate: usize,
+    /// Maximum burst of bytes
+    pub byte_bucket_size: usize,
+}
+
 pub type PeerSet = HashMap<PeerId, Peer>;
 
 // TODO: Combine with RoleType?
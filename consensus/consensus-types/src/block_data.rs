@@ -11,6 +11,7 @@ use aptos_crypto_derive::{BCSCryptoHash, CryptoHasher};
 use aptos_types::{
     aggregate_signature::AggregateSignature,
     block_info::BlockInfo,
+    equivocation::RoundMessage,
     ledger_info::{LedgerInfo, LedgerInfoWithSignatures},
 };
 use mirai_annotations::*;
@@ -237,6 +238,18 @@ impl BlockData {
     }
 }
 
+/// Proposers sign a single block per round, so two signed blocks of the same round and author
+/// are evidence of equivocation.
+impl RoundMessage for BlockData {
+    fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    fn round(&self) -> Round {
+        self.round
+    }
+}
+
 #[test]
 fn test_reconfiguration_suffix() {
     use aptos_types::{
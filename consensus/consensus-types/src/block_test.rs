@@ -19,7 +19,7 @@ use aptos_types::{
     ledger_info::{LedgerInfo, LedgerInfoWithPartialSignatures},
     on_chain_config::ValidatorSet,
     validator_signer::ValidatorSigner,
-    validator_verifier::{random_validator_verifier, ValidatorVerifier},
+    validator_verifier::{random_validator_verifier, ValidatorVerifierBuilder},
 };
 use std::sync::Arc;
 
@@ -47,7 +47,7 @@ fn test_nil_block() {
     assert!(nil_block.is_nil_block());
     assert!(nil_block.author().is_none());
 
-    let dummy_verifier = Arc::new(ValidatorVerifier::new(vec![]));
+    let dummy_verifier = Arc::new(ValidatorVerifierBuilder::new(false).build().1);
     assert!(nil_block
         .validate_signature(dummy_verifier.as_ref())
         .is_ok());
@@ -26,7 +26,7 @@ use aptos_types::{
     proof::AccumulatorExtensionProof,
     proptest_types::{AccountInfoUniverse, BlockInfoGen},
     transaction::SignedTransaction,
-    validator_verifier::{ValidatorConsensusInfo, ValidatorVerifier},
+    validator_verifier::ValidatorVerifierBuilder,
 };
 use proptest::prelude::*;
 use rand::{rngs::StdRng, SeedableRng};
@@ -164,14 +164,16 @@ prop_compose! {
     )(
         include_epoch_state in any::<bool>(),
         epoch in any::<u64>(),
-        validator_infos in prop::collection::vec(
-            any::<ValidatorConsensusInfo>(),
+        voting_powers in prop::collection::vec(
+            any::<u64>(),
             0..MAX_NUM_ADDR_TO_VALIDATOR_INFO
         ),
     ) -> Option<EpochState> {
-        let verifier = ValidatorVerifier::new(
-            validator_infos,
-        );
+        let mut builder = ValidatorVerifierBuilder::new(false);
+        for voting_power in voting_powers {
+            builder.add_validator(voting_power);
+        }
+        let verifier = builder.build().1;
         if include_epoch_state {
             Some(EpochState {
                 epoch,
@@ -706,12 +706,17 @@ impl EpochManager {
     }
 
     async fn start_new_epoch(&mut self, payload: OnChainConfigPayload) {
-        let validator_set: ValidatorSet = payload
-            .get()
-            .expect("failed to get ValidatorSet from payload");
-        let epoch_state = EpochState {
-            epoch: payload.epoch(),
-            verifier: (&validator_set).into(),
+        let epoch_state = match epoch_state_from_payload(&payload) {
+            Ok(epoch_state) => epoch_state,
+            Err(error) => {
+                // Don't crash the node on a malformed validator set, stay in the current epoch
+                error!(
+                    epoch = payload.epoch(),
+                    error = ?error,
+                    "[EpochManager] Failed to start new epoch"
+                );
+                return;
+            },
         };
 
         let onchain_config: anyhow::Result<OnChainConsensusConfig> = payload.get();
@@ -964,3 +969,72 @@ impl EpochManager {
         }
     }
 }
+
+/// Returns the state of the epoch of the payload, or an error if its validator set is missing or
+/// inconsistent (e.g., with duplicate validators or validator indices).
+fn epoch_state_from_payload(payload: &OnChainConfigPayload) -> anyhow::Result<EpochState> {
+    let validator_set: ValidatorSet = payload
+        .get()
+        .context("[EpochManager] Failed to get ValidatorSet from payload")?;
+    let verifier = ValidatorVerifier::try_from(&validator_set)
+        .context("[EpochManager] Failed to convert ValidatorSet into a ValidatorVerifier")?;
+    Ok(EpochState {
+        epoch: payload.epoch(),
+        verifier,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::epoch_manager::epoch_state_from_payload;
+    use aptos_types::{
+        on_chain_config::{OnChainConfig, OnChainConfigPayload, ValidatorSet},
+        validator_info::ValidatorInfo,
+        validator_signer::ValidatorSigner,
+    };
+    use std::{collections::HashMap, sync::Arc};
+
+    fn payload(validator_set: &ValidatorSet) -> OnChainConfigPayload {
+        let mut configs = HashMap::new();
+        configs.insert(
+            ValidatorSet::CONFIG_ID,
+            bcs::to_bytes(validator_set).unwrap(),
+        );
+        OnChainConfigPayload::new(2, Arc::new(configs))
+    }
+
+    #[test]
+    fn epoch_state_from_inconsistent_validator_set() {
+        let validator_signers: Vec<_> = (0..2).map(|i| ValidatorSigner::random([i; 32])).collect();
+        let validator_set = |indices: &[u64]| {
+            ValidatorSet::new(
+                validator_signers
+                    .iter()
+                    .zip(indices)
+                    .map(|(signer, index)| {
+                        ValidatorInfo::new_with_test_network_keys(
+                            signer.author(),
+                            signer.public_key(),
+                            1,
+                            *index,
+                        )
+                    })
+                    .collect(),
+            )
+        };
+
+        let epoch_state = epoch_state_from_payload(&payload(&validator_set(&[0, 1]))).unwrap();
+        assert_eq!(epoch_state.epoch, 2);
+        assert_eq!(epoch_state.verifier.len(), 2);
+
+        // Two validators with the same index
+        assert!(epoch_state_from_payload(&payload(&validator_set(&[0, 0]))).is_err());
+        // A validator index out of range
+        assert!(epoch_state_from_payload(&payload(&validator_set(&[0, 2]))).is_err());
+        // No validator set at all
+        assert!(
+            epoch_state_from_payload(&OnChainConfigPayload::new(2, Arc::new(HashMap::new())))
+                .is_err()
+        );
+    }
+}
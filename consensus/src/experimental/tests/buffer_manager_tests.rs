@@ -218,7 +218,7 @@ async fn loopback_commit_vote(
     verifier: &ValidatorVerifier,
 ) {
     match self_loop_rx.next().await {
-        Some(Event::Message(author, msg)) => {
+        Some(Event::Message(author, msg, _)) => {
             if matches!(msg, ConsensusMsg::CommitVoteMsg(_)) {
                 let event: UnverifiedEvent = msg.into();
                 // verify the message and send the message into self loop
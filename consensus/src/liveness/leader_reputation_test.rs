@@ -23,7 +23,8 @@ use aptos_types::{
     epoch_state::EpochState,
     event::EventKey,
     transaction::Version,
-    validator_verifier::{ValidatorConsensusInfo, ValidatorVerifier},
+    validator_signer::ValidatorSigner,
+    validator_verifier::ValidatorVerifierBuilder,
 };
 use claims::assert_err;
 use itertools::Itertools;
@@ -651,25 +652,23 @@ fn test_extract_epoch_to_proposers_impl() {
     fn create_epoch_state(
         epoch: u64,
         authors: &[Author],
-        public_key: &bls12381::PublicKey,
+        private_key: &bls12381::PrivateKey,
     ) -> EpochState {
+        let mut builder = ValidatorVerifierBuilder::new(false);
+        for author in authors {
+            builder.add_signer(ValidatorSigner::new(*author, private_key.clone()), 1);
+        }
         EpochState {
             epoch,
-            verifier: ValidatorVerifier::new(
-                authors
-                    .iter()
-                    .map(|author| ValidatorConsensusInfo::new(*author, public_key.clone(), 1))
-                    .collect::<Vec<_>>(),
-            ),
+            verifier: builder.build().1,
         }
     }
 
     let private_key = KeyGen::from_os_rng().generate_bls12381_private_key();
-    let public_key = bls12381::PublicKey::from(&private_key);
     let authors: Vec<AccountAddress> = (0..7).map(|_| AccountAddress::random()).sorted().collect();
 
     let epoch_states = (0..7)
-        .map(|i| create_epoch_state(i as u64, &[authors[i]], &public_key))
+        .map(|i| create_epoch_state(i as u64, &[authors[i]], &private_key))
         .collect::<Vec<_>>();
 
     // last EpochState needs to be for current epoch:
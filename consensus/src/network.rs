@@ -24,7 +24,7 @@ use aptos_consensus_types::{
 use aptos_logger::prelude::*;
 use aptos_network::{
     application::interface::{NetworkClient, NetworkServiceEvents},
-    protocols::{network::Event, rpc::error::RpcError},
+    protocols::{network::Event, rpc::error::RpcError, wire::messaging::v1::new_correlation_id},
     ProtocolId,
 };
 use aptos_types::{
@@ -159,7 +159,7 @@ impl NetworkSender {
     async fn broadcast(&mut self, msg: ConsensusMsg) {
         fail_point!("consensus::send::any", |_| ());
         // Directly send the message to ourself without going through network.
-        let self_msg = Event::Message(self.author, msg.clone());
+        let self_msg = Event::Message(self.author, msg.clone(), new_correlation_id());
         if let Err(err) = self.self_sender.send(self_msg).await {
             error!("Error broadcasting to self: {:?}", err);
         }
@@ -199,7 +199,7 @@ impl NetworkSender {
         let mut self_sender = self.self_sender.clone();
         for peer in recipients {
             if self.author == peer {
-                let self_msg = Event::Message(self.author, msg.clone());
+                let self_msg = Event::Message(self.author, msg.clone(), new_correlation_id());
                 if let Err(err) = self_sender.send(self_msg).await {
                     error!(error = ?err, "Error delivering a self msg");
                 }
@@ -408,7 +408,7 @@ impl NetworkTask {
     pub async fn start(mut self) {
         while let Some(message) = self.all_events.next().await {
             match message {
-                Event::Message(peer_id, msg) => {
+                Event::Message(peer_id, msg, _) => {
                     counters::CONSENSUS_RECEIVED_MSGS
                         .with_label_values(&[msg.name()])
                         .inc();
@@ -435,7 +435,7 @@ impl NetworkTask {
                         },
                     }
                 },
-                Event::RpcRequest(peer_id, msg, protocol, callback) => match msg {
+                Event::RpcRequest(peer_id, msg, protocol, callback, _) => match msg {
                     ConsensusMsg::BlockRetrievalRequest(request) => {
                         counters::CONSENSUS_RECEIVED_MSGS
                             .with_label_values(&["BlockRetrievalRequest"])
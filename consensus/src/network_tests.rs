@@ -162,6 +162,8 @@ impl NetworkPlayground {
                         protocol_id: outbound_req.protocol_id,
                         data: outbound_req.data,
                         res_tx: outbound_req.res_tx,
+                        deadline: None,
+                        correlation_id: outbound_req.correlation_id,
                     };
 
                     node_consensus_tx
@@ -797,6 +799,8 @@ mod tests {
         let bad_msg = PeerManagerNotification::RecvMessage(peer_id, Message {
             protocol_id,
             mdata: Bytes::from_static(b"\xde\xad\xbe\xef"),
+            priority: protocol_id.default_priority(),
+            correlation_id: 0,
         });
 
         peer_mgr_notifs_tx
@@ -813,6 +817,8 @@ mod tests {
             protocol_id,
             data: Bytes::from(serde_json::to_vec(&liveness_check_msg).unwrap()),
             res_tx,
+            deadline: None,
+            correlation_id: 0,
         });
 
         peer_mgr_notifs_tx
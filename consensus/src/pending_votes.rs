@@ -152,8 +152,15 @@ impl PendingVotes {
                                 ledger_info_with_sig,
                             )))
                         },
-                        Err(e) => VoteReceptionResult::ErrorAggregatingSignature(e),
-                    }
+                        Err(e) => {
+                            // Drop the malformed signature (if identified), so that the QC can
+                            // still be formed with the following votes
+                            if let VerifyError::FailedToAggregateSignature(Some(author)) = &e {
+                                li_with_sig.remove_signature(*author);
+                            }
+                            VoteReceptionResult::ErrorAggregatingSignature(e)
+                        },
+                    };
                 },
 
                 // not enough votes
@@ -138,7 +138,7 @@ fn create_node_for_fuzzing() -> RoundManager {
 
     let epoch_state = EpochState {
         epoch: 1,
-        verifier: storage.get_validator_set().into(),
+        verifier: storage.get_validator_set().try_into().unwrap(),
     };
     let network = NetworkSender::new(
         signer.author(),
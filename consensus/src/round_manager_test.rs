@@ -47,7 +47,7 @@ use aptos_network::{
     protocols::{
         network,
         network::{Event, NetworkEvents, NewNetworkEvents, NewNetworkSender},
-        wire::handshake::v1::ProtocolIdSet,
+        wire::{handshake::v1::ProtocolIdSet, messaging::v1::new_correlation_id},
     },
     transport::ConnectionMetadata,
     ProtocolId,
@@ -178,7 +178,7 @@ impl NodeSetup {
     ) -> Self {
         let epoch_state = EpochState {
             epoch: 1,
-            verifier: storage.get_validator_set().into(),
+            verifier: storage.get_validator_set().try_into().unwrap(),
         };
         let validators = epoch_state.verifier.clone();
         let (network_reqs_tx, network_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
@@ -321,8 +321,8 @@ impl NodeSetup {
 
     pub async fn next_network_message(&mut self) -> ConsensusMsg {
         match self.next_network_event().await {
-            Event::Message(_, msg) => msg,
-            Event::RpcRequest(_, msg, _, _) => panic!(
+            Event::Message(_, msg, _) => msg,
+            Event::RpcRequest(_, msg, _, _, _) => panic!(
                 "Unexpected event, got RpcRequest, expected Message: {:?} on node {}",
                 msg,
                 self.identity_desc()
@@ -333,11 +333,13 @@ impl NodeSetup {
 
     pub fn no_next_msg(&mut self) {
         match self.poll_next_network_event() {
-            Some(Event::RpcRequest(_, msg, _, _)) | Some(Event::Message(_, msg)) => panic!(
-                "Unexpected Consensus Message: {:?} on node {}",
-                msg,
-                self.identity_desc()
-            ),
+            Some(Event::RpcRequest(_, msg, _, _, _)) | Some(Event::Message(_, msg, _)) => {
+                panic!(
+                    "Unexpected Consensus Message: {:?} on node {}",
+                    msg,
+                    self.identity_desc()
+                )
+            },
             Some(_) => panic!("Unexpected Network Event"),
             None => {},
         }
@@ -378,7 +380,7 @@ impl NodeSetup {
 
     pub async fn poll_block_retreival(&mut self) -> Option<IncomingBlockRetrievalRequest> {
         match self.poll_next_network_event() {
-            Some(Event::RpcRequest(_, msg, protocol, response_sender)) => match msg {
+            Some(Event::RpcRequest(_, msg, protocol, response_sender, _)) => match msg {
                 ConsensusMsg::BlockRetrievalRequest(v) => Some(IncomingBlockRetrievalRequest {
                     req: *v,
                     protocol,
@@ -390,7 +392,7 @@ impl NodeSetup {
                     self.identity_desc()
                 ),
             },
-            Some(Event::Message(_, msg)) => panic!(
+            Some(Event::Message(_, msg, _)) => panic!(
                 "Unexpected Consensus Message: {:?} on node {}",
                 msg,
                 self.identity_desc()
@@ -1762,11 +1764,13 @@ pub fn forking_retrieval_test() {
             node.pending_network_events.push(Event::Message(
                 node.signer.author(),
                 ConsensusMsg::ProposalMsg(Box::new(proposal)),
+                new_correlation_id(),
             ));
         }
         behind_node_obj.pending_network_events.push(Event::Message(
             behind_node_obj.signer.author(),
             ConsensusMsg::ProposalMsg(Box::new(proposal_msg)),
+            new_correlation_id(),
         ));
 
         nodes.push(behind_node_obj);
@@ -1796,7 +1800,11 @@ pub fn forking_retrieval_test() {
             let peer = nodes[proposal_node].signer.author();
             nodes[proposal_node]
                 .pending_network_events
-                .push(Event::Message(peer, ConsensusMsg::ProposalMsg(msg)))
+                .push(Event::Message(
+                    peer,
+                    ConsensusMsg::ProposalMsg(msg),
+                    new_correlation_id(),
+                ))
         },
         _ => panic!("unexpected network message {:?}", next_message),
     }
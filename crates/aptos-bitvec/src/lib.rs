@@ -63,7 +63,7 @@ const MAX_BUCKETS: usize = 8192;
 /// assert!(intersection.is_set(2));
 /// assert_eq!(false, intersection.is_set(3));
 /// ```
-#[derive(Clone, Default, Debug, Eq, PartialEq, Serialize)]
+#[derive(Clone, Default, Debug, Eq, Hash, PartialEq, Serialize)]
 pub struct BitVec {
     #[serde(with = "serde_bytes")]
     inner: Vec<u8>,
@@ -214,6 +214,36 @@ impl From<Vec<bool>> for BitVec {
     }
 }
 
+impl From<&BitVec> for Vec<bool> {
+    /// Returns all the bits of the buckets, i.e., the bits are padded to a multiple of 8
+    fn from(bitvec: &BitVec) -> Self {
+        (0..bitvec.num_buckets() * BUCKET_SIZE)
+            .map(|pos| bitvec.is_set(pos as u16))
+            .collect()
+    }
+}
+
+/// Serde compatibility shim for fields that used to be a `Vec<bool>` (one byte per bit), and are
+/// now a `BitVec` (one byte per 8 bits), e.g., `#[serde(with = "aptos_bitvec::legacy_bool_vec")]`.
+/// It (de)serializes a `BitVec` in the old format, so that data in the old format remains
+/// readable (and writable) until it's migrated to the compact format.
+pub mod legacy_bool_vec {
+    use super::{BitVec, BUCKET_SIZE, MAX_BUCKETS};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bitvec: &BitVec, serializer: S) -> Result<S::Ok, S::Error> {
+        Vec::<bool>::from(bitvec).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BitVec, D::Error> {
+        let bits = Vec::<bool>::deserialize(deserializer)?;
+        if bits.len() > MAX_BUCKETS * BUCKET_SIZE {
+            return Err(D::Error::custom(format!("BitVec too long: {}", bits.len())));
+        }
+        Ok(BitVec::from(bits))
+    }
+}
+
 impl<'de> Deserialize<'de> for BitVec {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -421,5 +451,27 @@ mod test {
             assert_eq!(bitvec, back);
         }
 
+        #[test]
+        fn test_legacy_bool_vec_roundtrip(bits in vec(any::<bool>(), 0..u16::MAX as usize)) {
+            #[derive(Debug, Deserialize, PartialEq, Serialize)]
+            struct LegacyBitmap(#[serde(with = "legacy_bool_vec")] BitVec);
+
+            // Bitmaps in the old format are read as is
+            let legacy_bytes = bcs::to_bytes(&bits).unwrap();
+            let LegacyBitmap(bitvec) = bcs::from_bytes(&legacy_bytes).unwrap();
+            assert_eq!(bitvec, BitVec::from(bits.clone()));
+
+            // Bitmaps written in the old format only gain padding bits
+            let padded_bits = Vec::<bool>::from(&bitvec);
+            assert_eq!(&padded_bits[..bits.len()], &bits[..]);
+            assert!(padded_bits[bits.len()..].iter().all(|bit| !bit));
+            let bytes = bcs::to_bytes(&LegacyBitmap(bitvec.clone())).unwrap();
+            assert_eq!(bcs::from_bytes::<LegacyBitmap>(&bytes).unwrap(), LegacyBitmap(bitvec.clone()));
+
+            // The compact format is 8x smaller (up to the length prefix)
+            let compact_bytes = bcs::to_bytes(&bitvec).unwrap();
+            assert!(compact_bytes.len() <= legacy_bytes.len() / BUCKET_SIZE + 4);
+        }
+
     }
 }
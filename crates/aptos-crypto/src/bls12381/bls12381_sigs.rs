@@ -27,10 +27,15 @@ use crate::{
 };
 use anyhow::{anyhow, Result};
 use aptos_crypto_derive::{DeserializeKey, SerializeKey};
-use blst::BLST_ERROR;
+use blst::{blst_scalar, BLST_ERROR};
+use rand::RngCore;
 use serde::Serialize;
 use std::{convert::TryFrom, fmt};
 
+/// The size (in bits) of the random scalars used to batch-verify signature shares. A batch with
+/// invalid shares verifies with probability at most 2^{-64}.
+const BATCH_VERIFICATION_RANDOM_BITS: usize = 64;
+
 #[derive(Clone, Eq, SerializeKey, DeserializeKey)]
 /// Either (1) a BLS signature share from an individual signer, (2) a BLS multisignature or (3) a
 /// BLS aggregate signature
@@ -118,6 +123,72 @@ impl Signature {
         self.verify_aggregate_arbitrary_msg(&msgs_refs, pks)
     }
 
+    /// Verifies the signature shares in `keys_and_signatures` on the same message at once, i.e.,
+    /// that each signature is a valid signature share on `message` under its public key.
+    /// Unlike verifying a multisignature aggregated from the shares, this does not pass if some
+    /// shares are invalid but happen to sum up to a valid multisignature: each pairing equation is
+    /// scaled by a random (non-zero) 64-bit scalar before they are combined. An empty batch
+    /// trivially verifies.
+    ///
+    /// If the batch fails to verify, the caller must verify the shares one by one to find out
+    /// which are invalid.
+    ///
+    /// WARNING: This function assumes that the public keys have been subgroup-checked by the caller
+    /// implicitly when verifying their proof-of-possession (PoP) in `ProofOfPossession::verify`.
+    /// The signature shares are subgroup-checked.
+    pub fn batch_verify_arbitrary_msg(
+        message: &[u8],
+        keys_and_signatures: &[(&PublicKey, &Signature)],
+    ) -> Result<()> {
+        if keys_and_signatures.is_empty() {
+            return Ok(());
+        }
+
+        let msgs = vec![message; keys_and_signatures.len()];
+        let pks = keys_and_signatures
+            .iter()
+            .map(|(pk, _)| &pk.pubkey)
+            .collect::<Vec<&blst::min_pk::PublicKey>>();
+        let sigs = keys_and_signatures
+            .iter()
+            .map(|(_, sig)| &sig.sig)
+            .collect::<Vec<&blst::min_pk::Signature>>();
+        let rands = (0..keys_and_signatures.len())
+            .map(|_| Self::random_scalar())
+            .collect::<Vec<blst_scalar>>();
+
+        let result = blst::min_pk::Signature::verify_multiple_aggregate_signatures(
+            &msgs,
+            DST_BLS_SIG_IN_G2_WITH_POP,
+            &pks,
+            false,
+            &sigs,
+            true,
+            &rands,
+            BATCH_VERIFICATION_RANDOM_BITS,
+        );
+
+        if result == BLST_ERROR::BLST_SUCCESS {
+            Ok(())
+        } else {
+            Err(anyhow!("{:?}", result))
+        }
+    }
+
+    /// Returns a random non-zero scalar of `BATCH_VERIFICATION_RANDOM_BITS` bits.
+    fn random_scalar() -> blst_scalar {
+        let mut rng = rand::thread_rng();
+        let mut random = 0;
+        while random == 0 {
+            random = rng.next_u64();
+        }
+
+        // The scalar is encoded in little-endian
+        let mut b = [0u8; 32];
+        b[..8].copy_from_slice(&random.to_le_bytes());
+        blst_scalar { b }
+    }
+
     /// Return a dummy signature for testing.
     #[cfg(any(test, feature = "fuzzing"))]
     pub fn dummy_signature() -> Self {
@@ -168,6 +239,18 @@ impl traits::Signature for Signature {
     fn to_bytes(&self) -> Vec<u8> {
         self.to_bytes().to_vec()
     }
+
+    /// Serializes the message of type `T` to bytes and calls `Signature::batch_verify_arbitrary_msg`.
+    fn batch_verify<T: CryptoHash + Serialize>(
+        message: &T,
+        keys_and_signatures: Vec<(PublicKey, Self)>,
+    ) -> Result<()> {
+        let keys_and_signatures = keys_and_signatures
+            .iter()
+            .map(|(pk, sig)| (pk, sig))
+            .collect::<Vec<_>>();
+        Self::batch_verify_arbitrary_msg(&signing_message(message)?, &keys_and_signatures)
+    }
 }
 
 impl ValidCryptoMaterial for Signature {
@@ -0,0 +1,124 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides APIs for threshold BLS signatures [^Bold03]: the secret key of a group is
+//! shared among `n` players (e.g., by a distributed key generation (DKG) protocol), such that the
+//! share of player `i` is the evaluation at `i` of a secret polynomial of degree `t - 1`, whose
+//! evaluation at zero is the secret key of the group. Each player signs with its share (i.e., as
+//! with an ordinary `PrivateKey`), and any `t` signature shares on the same message combine into
+//! the signature of the group, by Lagrange interpolation "in the exponent". The group signature is
+//! an ordinary BLS signature under the public key of the group, whichever players signed.
+//!
+//! Players are numbered from 1, as the evaluation at 0 is the secret key of the group.
+//!
+//! The signature shares are NOT verified when combined: invalid shares combine into an invalid
+//! group signature. Callers verify the shares under the public keys of the players' shares first.
+//!
+//! The scalar and point arithmetic (which is constant-time) is the one of the `bls12_381` crate,
+//! since the safe API of `blst` only adds signatures.
+
+use crate::bls12381::bls12381_sigs::Signature;
+use anyhow::{anyhow, bail, ensure, Result};
+use bls12_381::{G2Affine, G2Projective, Scalar};
+use std::collections::HashSet;
+
+/// The index of a player, i.e., the point at which the polynomial is evaluated for its share
+pub type PlayerIndex = u64;
+
+/// Returns the Lagrange coefficients of the evaluations at the (distinct, non-zero) player indices
+/// for interpolating the evaluation at zero, i.e., `prod_{j != i} x_j / (x_j - x_i)` for each `x_i`.
+fn lagrange_coefficients_at_zero(indices: &[PlayerIndex]) -> Vec<Scalar> {
+    indices
+        .iter()
+        .map(|i| {
+            let x_i = Scalar::from(*i);
+            let (numerator, denominator) = indices.iter().filter(|j| *j != i).fold(
+                (Scalar::one(), Scalar::one()),
+                |(numerator, denominator), j| {
+                    let x_j = Scalar::from(*j);
+                    (numerator * x_j, denominator * (x_j - x_i))
+                },
+            );
+            let inverse: Option<Scalar> = denominator.invert().into();
+            numerator * inverse.expect("The player indices are distinct")
+        })
+        .collect()
+}
+
+/// Returns the point of G2 of the signature
+fn signature_to_point(sig: &blst::min_pk::Signature) -> Result<G2Affine> {
+    Option::from(G2Affine::from_compressed(&sig.to_bytes()))
+        .ok_or_else(|| anyhow!("Malformed signature share"))
+}
+
+/// Returns the signature of the point of G2
+fn point_to_signature(point: &G2Projective) -> Result<blst::min_pk::Signature> {
+    blst::min_pk::Signature::from_bytes(&G2Affine::from(point).to_compressed())
+        .map_err(|e| anyhow!("{:?}", e))
+}
+
+/// Combines the signature shares of the players (on the same message) into the signature of the
+/// group. There must be (at least) as many shares as the threshold of the group, from distinct
+/// players, for the group signature to be valid. The shares aren't verified (see module docs).
+pub fn combine_signature_shares(shares: &[(PlayerIndex, &Signature)]) -> Result<Signature> {
+    ensure!(!shares.is_empty(), "No signature shares to combine");
+    let indices: Vec<_> = shares.iter().map(|(index, _)| *index).collect();
+    if indices.contains(&0) {
+        bail!("Player indices start at 1");
+    }
+    if indices.iter().collect::<HashSet<_>>().len() != indices.len() {
+        bail!("Signature shares of the same player can't be combined");
+    }
+
+    let mut sum = G2Projective::identity();
+    for ((_, share), coefficient) in shares.iter().zip(lagrange_coefficients_at_zero(&indices)) {
+        sum += signature_to_point(&share.sig)? * coefficient;
+    }
+    Ok(Signature {
+        sig: point_to_signature(&sum)?,
+    })
+}
+
+/// Deals the shares of a random group secret key to `num_players` players (numbered from 1), any
+/// `threshold` of which can sign for the group, as a DKG would. Returns the secret key of the group
+/// and the shares, by player. For testing only, since the dealer knows the secret key of the group.
+#[cfg(any(test, feature = "fuzzing"))]
+pub fn deal_shares_for_testing<R>(
+    threshold: usize,
+    num_players: usize,
+    rng: &mut R,
+) -> (
+    crate::bls12381::PrivateKey,
+    Vec<crate::bls12381::PrivateKey>,
+)
+where
+    R: ::rand::RngCore + ::rand::CryptoRng,
+{
+    assert!((1..=num_players).contains(&threshold));
+    // The coefficients of the secret polynomial, from the constant one (i.e., the group's secret)
+    let coefficients: Vec<_> = (0..threshold)
+        .map(|_| {
+            let mut bytes = [0u8; 64];
+            rng.fill_bytes(&mut bytes);
+            Scalar::from_bytes_wide(&bytes)
+        })
+        .collect();
+    let evaluate = |x: Scalar| {
+        coefficients
+            .iter()
+            .rev()
+            .fold(Scalar::zero(), |result, coefficient| {
+                result * x + coefficient
+            })
+    };
+    // Secret keys are big-endian, whereas scalars are little-endian
+    let to_private_key = |scalar: Scalar| {
+        let mut bytes = scalar.to_bytes();
+        bytes.reverse();
+        crate::bls12381::PrivateKey::try_from(&bytes[..]).expect("The scalar is a valid secret key")
+    };
+    let shares = (1..=num_players as u64)
+        .map(|index| to_private_key(evaluate(Scalar::from(index))))
+        .collect();
+    (to_private_key(evaluate(Scalar::zero())), shares)
+}
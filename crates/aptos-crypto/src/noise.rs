@@ -705,6 +705,42 @@ impl NoiseSession {
         // return a subslice of the buffer representing the decrypted plaintext
         Ok(buffer)
     }
+
+    /// rotates the key used to encrypt messages to the other peer (post-handshake),
+    /// as per the Rekey() function of the Noise specification. The other peer must
+    /// rotate its read key at the same position in the stream of messages.
+    pub fn rekey_write(&mut self) -> Result<(), NoiseError> {
+        if !self.valid {
+            return Err(NoiseError::SessionClosed);
+        }
+        self.write_key = rekey(&self.write_key)?;
+        Ok(())
+    }
+
+    /// rotates the key used to decrypt messages from the other peer (post-handshake),
+    /// once the other peer signaled that it rotated its write key
+    pub fn rekey_read(&mut self) -> Result<(), NoiseError> {
+        if !self.valid {
+            return Err(NoiseError::SessionClosed);
+        }
+        self.read_key = rekey(&self.read_key)?;
+        Ok(())
+    }
+}
+
+/// REKEY(k) of the Noise specification: the first 32 bytes of the encryption of
+/// 32 zero bytes, with the maximum nonce (which is never used for messages)
+fn rekey(key: &[u8]) -> Result<Vec<u8>, NoiseError> {
+    let mut nonce = [0u8; 4].to_vec();
+    nonce.extend_from_slice(&u64::MAX.to_be_bytes());
+    let nonce = aead::Nonce::assume_unique_for_key(
+        nonce.try_into().expect("Incorrect AES256-GCM nonce length"),
+    );
+    let mut new_key = vec![0u8; 32];
+    aes_key(key)
+        .seal_in_place_separate_tag(nonce, aead::Aad::empty(), &mut new_key)
+        .map_err(|_| NoiseError::Encrypt)?;
+    Ok(new_key)
 }
 
 impl std::fmt::Debug for NoiseSession {
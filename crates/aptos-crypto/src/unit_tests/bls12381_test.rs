@@ -3,7 +3,7 @@
 
 use crate::{
     bls12381,
-    bls12381::{PrivateKey, ProofOfPossession, PublicKey},
+    bls12381::{bls12381_threshold, PrivateKey, ProofOfPossession, PublicKey},
     test_utils::{random_subset, KeyPair, TestAptosCrypto},
     validatable::{Validatable, Validate},
     Signature, SigningKey, Uniform,
@@ -142,6 +142,138 @@ fn bls12381_multisig_should_verify() {
     assert!(multisig.verify(&message_wrong, &aggpk).is_err());
 }
 
+/// Tests that signature shares on a message m from n signers batch-verify on m, but fail to
+/// batch-verify on a different message m', or if any share is invalid (even if the invalid shares
+/// aggregate into a valid multisignature).
+#[test]
+fn bls12381_batch_verify_sigshares() {
+    let mut rng = OsRng;
+
+    let message = random_message_for_signing(&mut rng);
+    let message_wrong = random_message_for_signing(&mut rng);
+
+    let key_pairs = bls12381_keygen(10, &mut rng);
+    let keys_and_signatures = key_pairs
+        .iter()
+        .map(|keys| {
+            (
+                keys.public_key.clone(),
+                keys.private_key.sign(&message).unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // the empty batch trivially verifies
+    assert!(bls12381::Signature::batch_verify(&message, vec![]).is_ok());
+
+    // the shares should batch-verify on the correct message
+    assert!(bls12381::Signature::batch_verify(&message, keys_and_signatures.clone()).is_ok());
+
+    // the shares should not batch-verify on an incorrect message
+    assert!(
+        bls12381::Signature::batch_verify(&message_wrong, keys_and_signatures.clone()).is_err()
+    );
+
+    // a single share on an incorrect message should fail the batch
+    let mut with_wrong_share = keys_and_signatures.clone();
+    with_wrong_share[3].1 = key_pairs[3].private_key.sign(&message_wrong).unwrap();
+    assert!(bls12381::Signature::batch_verify(&message, with_wrong_share).is_err());
+
+    // swapping two shares keeps their multisignature valid, but should fail the batch
+    let mut swapped_shares = keys_and_signatures;
+    let share = swapped_shares[0].1.clone();
+    swapped_shares[0].1 = swapped_shares[1].1.clone();
+    swapped_shares[1].1 = share;
+    let multisig =
+        bls12381::Signature::aggregate(swapped_shares.iter().map(|(_, sig)| sig.clone()).collect())
+            .unwrap();
+    let aggpk = PublicKey::aggregate(swapped_shares.iter().map(|(pk, _)| pk).collect()).unwrap();
+    assert!(multisig.verify(&message, &aggpk).is_ok());
+    assert!(bls12381::Signature::batch_verify(&message, swapped_shares).is_err());
+}
+
+/// Tests that the signature shares on a message m of any t out of n players combine into the
+/// signature of the group on m (i.e., the signature under the secret key of the group), whereas
+/// fewer shares, or the shares of the same player, don't.
+#[test]
+fn bls12381_threshold_sigshares_combine() {
+    let mut rng = OsRng;
+
+    let message = random_message_for_signing(&mut rng);
+    let (group_key, shares) = bls12381_threshold::deal_shares_for_testing(3, 5, &mut rng);
+    let group_public_key = PublicKey::from(&group_key);
+    let sigshares: Vec<_> = shares
+        .iter()
+        .map(|share| share.sign(&message).unwrap())
+        .collect();
+    // players are numbered from 1
+    let sigshares_of = |players: &[u64]| {
+        players
+            .iter()
+            .map(|player| (*player, &sigshares[*player as usize - 1]))
+            .collect::<Vec<_>>()
+    };
+
+    // any 3 (or more) shares combine into the (deterministic) signature of the group
+    let group_sig = group_key.sign(&message).unwrap();
+    for players in [&[1, 2, 3][..], &[5, 1, 3], &[2, 4, 5], &[1, 2, 3, 4, 5]] {
+        let combined_sig =
+            bls12381_threshold::combine_signature_shares(&sigshares_of(players)).unwrap();
+        assert_eq!(combined_sig, group_sig);
+        assert!(combined_sig.verify(&message, &group_public_key).is_ok());
+    }
+
+    // fewer shares combine into an invalid signature
+    let combined_sig =
+        bls12381_threshold::combine_signature_shares(&sigshares_of(&[1, 4])).unwrap();
+    assert!(combined_sig.verify(&message, &group_public_key).is_err());
+
+    // shares of the same player, of player 0, or no shares at all, can't be combined
+    let mut duplicate_sigshares = sigshares_of(&[1, 2]);
+    duplicate_sigshares.push((1, &sigshares[0]));
+    assert!(bls12381_threshold::combine_signature_shares(&duplicate_sigshares).is_err());
+    assert!(bls12381_threshold::combine_signature_shares(&[(0, &sigshares[0])]).is_err());
+    assert!(bls12381_threshold::combine_signature_shares(&[]).is_err());
+}
+
+/// Tests that the signature shares of the players, for shares of a polynomial computed
+/// independently (i.e., with big integers, modulo the order of the groups), combine into the
+/// signature of the group. This cross-checks the scalar arithmetic of the combination.
+#[test]
+fn bls12381_threshold_known_vector() {
+    // The evaluations at 0 (the secret key of the group) and at 1..=5 (the shares) of a secret
+    // polynomial of degree 2
+    let private_key = |hex: &str| PrivateKey::try_from(&hex::decode(hex).unwrap()[..]).unwrap();
+    let group_key = private_key("022be60b0da6b179c6349221d280939b943adf17b75f553a2329e61dcfe62df2");
+    let shares: Vec<_> = [
+        "48ab2730ff40aa14132b01c0b65ab5165d8cca5b7896b4a7158a165f468fd89a",
+        "4621bae99d03c4e5eae0bf1dde644fed86c668a523b14576f726fd6ba56f5ca4",
+        "6e7d4888108d7f37808fa241543f3c2663a55df7b8ad63a8c8009b41ec84ba11",
+        "4dd028b930405bc0a0fdd3230e49a1bba06c0650378cb33d8816efe31bcff0e0",
+        "580802d025b9d7c97f6529cb162558b290d805b1a04d90343769fb4e33510112",
+    ]
+    .iter()
+    .map(|hex| private_key(hex))
+    .collect();
+
+    let message = b"Threshold signature test vector";
+    let sigshares: Vec<_> = shares
+        .iter()
+        .map(|share| share.sign_arbitrary_message(message))
+        .collect();
+    let group_sig = group_key.sign_arbitrary_message(message);
+    for players in [&[1, 2, 3][..], &[5, 3, 1], &[2, 4, 5], &[1, 2, 3, 4, 5]] {
+        let sigshares_of_players: Vec<_> = players
+            .iter()
+            .map(|player| (*player, &sigshares[*player as usize - 1]))
+            .collect();
+        assert_eq!(
+            bls12381_threshold::combine_signature_shares(&sigshares_of_players).unwrap(),
+            group_sig
+        );
+    }
+}
+
 /// Tests signature (de)serialization
 #[test]
 fn bls12381_serialize_sig() {
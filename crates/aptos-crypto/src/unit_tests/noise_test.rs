@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    noise::{handshake_init_msg_len, handshake_resp_msg_len, NoiseConfig, MAX_SIZE_NOISE_MSG},
+    noise::{
+        handshake_init_msg_len, handshake_resp_msg_len, NoiseConfig, NoiseSession,
+        MAX_SIZE_NOISE_MSG,
+    },
     test_utils::TEST_SEED,
     x25519, Uniform as _,
 };
@@ -303,6 +306,34 @@ fn test_vectors() {
     }
 }
 
+#[test]
+fn rekey() {
+    let mut sender = NoiseSession::new_for_testing();
+    let mut receiver = NoiseSession::new_for_testing();
+    let send = |sender: &mut NoiseSession| {
+        let mut message = b"payload".to_vec();
+        let auth_tag = sender.write_message_in_place(&mut message).unwrap();
+        message.extend_from_slice(&auth_tag);
+        message
+    };
+
+    // messages are decrypted once both peers rotated the key
+    let mut message = send(&mut sender);
+    receiver.read_message_in_place(&mut message).unwrap();
+    sender.rekey_write().unwrap();
+    receiver.rekey_read().unwrap();
+    let mut message = send(&mut sender);
+    assert_eq!(
+        receiver.read_message_in_place(&mut message).unwrap(),
+        b"payload"
+    );
+
+    // but not if only the sender did
+    sender.rekey_write().unwrap();
+    let mut message = send(&mut sender);
+    receiver.read_message_in_place(&mut message).unwrap_err();
+}
+
 // Negative tests
 // --------------
 //
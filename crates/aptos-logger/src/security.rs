@@ -76,6 +76,10 @@ pub enum SecurityEvent {
     /// A failed noise handshake that's either a clear bug or indicates some
     /// security issue.
     NoiseHandshake,
+
+    /// A failed TLS handshake that's either a clear bug or indicates some
+    /// security issue.
+    TlsHandshake,
 }
 
 impl Schema for SecurityEvent {
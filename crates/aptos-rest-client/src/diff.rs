@@ -0,0 +1,228 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{error::RestError, types::Resource, Client, Response};
+use aptos_types::account_address::AccountAddress;
+use move_core_types::language_storage::StructTag;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A single JSON patch operation (RFC 6902), restricted to the operations
+/// needed to describe the difference between two JSON documents.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JsonPatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+}
+
+/// A resource that exists at both versions, but with different data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangedResource {
+    pub resource_type: StructTag,
+    pub before: Value,
+    pub after: Value,
+    /// The patch that transforms `before` into `after`
+    pub patch: Vec<JsonPatchOp>,
+}
+
+/// The difference between the resources of an account at two ledger versions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceDiff {
+    pub added: Vec<Resource>,
+    pub removed: Vec<Resource>,
+    pub changed: Vec<ChangedResource>,
+}
+
+impl ResourceDiff {
+    /// Computes the diff between two sets of resources
+    pub fn new(before: Vec<Resource>, after: Vec<Resource>) -> Self {
+        let mut before: BTreeMap<StructTag, Value> = before
+            .into_iter()
+            .map(|resource| (resource.resource_type, resource.data))
+            .collect();
+
+        let mut diff = ResourceDiff::default();
+        for resource in after {
+            match before.remove(&resource.resource_type) {
+                None => diff.added.push(resource),
+                Some(old_data) if old_data != resource.data => {
+                    let patch = json_diff(&old_data, &resource.data);
+                    diff.changed.push(ChangedResource {
+                        resource_type: resource.resource_type,
+                        before: old_data,
+                        after: resource.data,
+                        patch,
+                    });
+                },
+                Some(_) => {},
+            }
+        }
+        diff.removed = before
+            .into_iter()
+            .map(|(resource_type, data)| Resource {
+                resource_type,
+                data,
+            })
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Client {
+    /// Fetches the resources of an account at two ledger versions and returns
+    /// what was added, removed and changed between them. The returned state is
+    /// the one received when fetching at `to_version`.
+    pub async fn diff_account_resources(
+        &self,
+        address: AccountAddress,
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<Response<ResourceDiff>, RestError> {
+        let before = self
+            .get_account_resources_at_version(address, from_version)
+            .await?
+            .into_inner();
+        let after = self
+            .get_account_resources_at_version(address, to_version)
+            .await?;
+        Ok(after.map(|after| ResourceDiff::new(before, after)))
+    }
+}
+
+/// Computes a JSON patch turning `before` into `after`. Objects are diffed
+/// key by key, everything else (including arrays) is replaced as a whole.
+pub fn json_diff(before: &Value, after: &Value) -> Vec<JsonPatchOp> {
+    let mut patch = Vec::new();
+    json_diff_inner("", before, after, &mut patch);
+    patch
+}
+
+fn json_diff_inner(path: &str, before: &Value, after: &Value, patch: &mut Vec<JsonPatchOp>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (key, old_value) in before {
+                let child_path = format!("{}/{}", path, escape_pointer_token(key));
+                match after.get(key) {
+                    Some(new_value) => json_diff_inner(&child_path, old_value, new_value, patch),
+                    None => patch.push(JsonPatchOp::Remove { path: child_path }),
+                }
+            }
+            for (key, new_value) in after {
+                if !before.contains_key(key) {
+                    patch.push(JsonPatchOp::Add {
+                        path: format!("{}/{}", path, escape_pointer_token(key)),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        },
+        (before, after) if before != after => patch.push(JsonPatchOp::Replace {
+            path: path.to_string(),
+            value: after.clone(),
+        }),
+        _ => {},
+    }
+}
+
+/// Escapes a key for use as a JSON pointer reference token (RFC 6901)
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn assert_patch(before: Value, after: Value, expected: Vec<JsonPatchOp>) {
+        let patch = json_diff(&before, &after);
+        // The order of the operations follows the (unspecified) order of the keys
+        assert_eq!(patch.len(), expected.len(), "{:?}", patch);
+        for op in &expected {
+            assert!(patch.contains(op), "{:?} is missing from {:?}", op, patch);
+        }
+    }
+
+    #[test]
+    fn identical_documents_have_an_empty_patch() {
+        let document = json!({"coin": {"value": "10"}, "frozen": false});
+        assert!(json_diff(&document, &document).is_empty());
+    }
+
+    #[test]
+    fn nested_objects_are_diffed_key_by_key() {
+        assert_patch(
+            json!({"coin": {"value": "10", "frozen": false}, "sequence_number": "1"}),
+            json!({"coin": {"value": "20", "frozen": false}, "sequence_number": "1"}),
+            vec![JsonPatchOp::Replace {
+                path: "/coin/value".to_string(),
+                value: json!("20"),
+            }],
+        );
+    }
+
+    #[test]
+    fn keys_are_added_and_removed() {
+        assert_patch(
+            json!({"coin": {"value": "10"}, "old": 1}),
+            json!({"coin": {"value": "10", "frozen": true}, "new": [1]}),
+            vec![
+                JsonPatchOp::Remove {
+                    path: "/old".to_string(),
+                },
+                JsonPatchOp::Add {
+                    path: "/coin/frozen".to_string(),
+                    value: json!(true),
+                },
+                JsonPatchOp::Add {
+                    path: "/new".to_string(),
+                    value: json!([1]),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn arrays_and_type_changes_are_replaced_as_a_whole() {
+        assert_patch(
+            json!({"events": [1, 2, 3], "handle": {"id": 1}}),
+            json!({"events": [1, 2, 4], "handle": "1"}),
+            vec![
+                JsonPatchOp::Replace {
+                    path: "/events".to_string(),
+                    value: json!([1, 2, 4]),
+                },
+                JsonPatchOp::Replace {
+                    path: "/handle".to_string(),
+                    value: json!("1"),
+                },
+            ],
+        );
+        // Documents that aren't objects are replaced at the root
+        assert_patch(json!([1]), json!([2]), vec![JsonPatchOp::Replace {
+            path: "".to_string(),
+            value: json!([2]),
+        }]);
+    }
+
+    #[test]
+    fn keys_are_escaped_as_pointer_tokens() {
+        assert_patch(
+            json!({"a/b": {"c~d": 1}}),
+            json!({"a/b": {"c~d": 2}}),
+            vec![JsonPatchOp::Replace {
+                path: "/a~1b/c~0d".to_string(),
+                value: json!(2),
+            }],
+        );
+        // `~` is escaped first, so that `~1` isn't mistaken for an escaped `/`
+        assert_eq!(escape_pointer_token("~1/"), "~01~1");
+    }
+}
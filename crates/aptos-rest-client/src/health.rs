@@ -0,0 +1,175 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{error::RestError, Client};
+use aptos_api_types::IndexResponse;
+use aptos_infallible::{duration_since_epoch, RwLock};
+use std::{sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time::Instant};
+
+/// A typed liveness report for a single node, built from the `-/healthy`
+/// probe and the index response of the node's API.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeHealth {
+    /// Whether the node answered the `-/healthy` probe successfully
+    pub healthy: bool,
+    pub chain_id: u8,
+    pub epoch: u64,
+    pub ledger_version: u64,
+    pub oldest_ledger_version: u64,
+    pub ledger_timestamp_usecs: u64,
+    pub block_height: u64,
+    pub is_validator: bool,
+    /// Git hash of the build serving the API, if the node reports one
+    pub git_hash: Option<String>,
+    /// How far the node's latest ledger timestamp trails the local clock
+    pub ledger_lag: Duration,
+    /// Round trip time of the index request
+    pub latency: Duration,
+}
+
+impl NodeHealth {
+    pub fn from_index(
+        index: &IndexResponse,
+        healthy: bool,
+        latency: Duration,
+        now: Duration,
+    ) -> Self {
+        let ledger_timestamp_usecs: u64 = index.ledger_timestamp.into();
+        let ledger_lag = now.saturating_sub(Duration::from_micros(ledger_timestamp_usecs));
+        Self {
+            healthy,
+            chain_id: index.chain_id,
+            epoch: index.epoch.into(),
+            ledger_version: index.ledger_version.into(),
+            oldest_ledger_version: index.oldest_ledger_version.into(),
+            ledger_timestamp_usecs,
+            block_height: index.block_height.into(),
+            is_validator: index.node_role.is_validator(),
+            git_hash: index.git_hash.clone(),
+            ledger_lag,
+            latency,
+        }
+    }
+
+    /// Returns true iff the node is healthy and its ledger lag is within the given bound
+    pub fn is_live(&self, max_ledger_lag: Duration) -> bool {
+        self.healthy && self.ledger_lag <= max_ledger_lag
+    }
+}
+
+impl Client {
+    /// Probes the node and returns a parsed liveness report. A failing
+    /// `-/healthy` check is reported through `NodeHealth::healthy` rather than
+    /// as an error; only a failing index request is an error.
+    pub async fn node_health(&self) -> Result<NodeHealth, RestError> {
+        let healthy = self
+            .inner
+            .get(self.build_path("-/healthy")?)
+            .send()
+            .await
+            .map(|response| response.status().is_success())
+            .unwrap_or(false);
+        let start = Instant::now();
+        let index = self.get_index().await?.into_inner();
+        let latency = start.elapsed();
+        Ok(NodeHealth::from_index(
+            &index,
+            healthy,
+            latency,
+            duration_since_epoch(),
+        ))
+    }
+}
+
+/// The latest probe result for one endpoint tracked by a `HealthProber`
+#[derive(Clone, Debug)]
+pub struct EndpointHealth {
+    pub client: Client,
+    /// The last report, or `None` if the endpoint has not been probed
+    /// successfully yet (or the last probe failed)
+    pub last_report: Option<NodeHealth>,
+}
+
+/// Periodically probes a set of endpoints in the background and keeps the
+/// latest `NodeHealth` for each, so callers can pick an endpoint to use.
+pub struct HealthProber {
+    endpoints: Arc<RwLock<Vec<EndpointHealth>>>,
+    interval: Duration,
+}
+
+impl HealthProber {
+    pub fn new(clients: Vec<Client>, interval: Duration) -> Self {
+        let endpoints = clients
+            .into_iter()
+            .map(|client| EndpointHealth {
+                client,
+                last_report: None,
+            })
+            .collect();
+        Self {
+            endpoints: Arc::new(RwLock::new(endpoints)),
+            interval,
+        }
+    }
+
+    /// Probes every endpoint once and records the results
+    pub async fn probe_once(&self) {
+        probe_all(&self.endpoints).await
+    }
+
+    /// Spawns a background task that probes every endpoint on each interval.
+    /// The task runs until the returned handle is aborted.
+    pub fn start(&self) -> JoinHandle<()> {
+        let endpoints = self.endpoints.clone();
+        let interval = self.interval;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                probe_all(&endpoints).await;
+            }
+        })
+    }
+
+    /// Returns a snapshot of the latest probe results
+    pub fn snapshot(&self) -> Vec<EndpointHealth> {
+        self.endpoints.read().clone()
+    }
+
+    /// Returns the live endpoint with the highest ledger version, breaking
+    /// ties by the lowest latency.
+    pub fn best_endpoint(&self, max_ledger_lag: Duration) -> Option<Client> {
+        self.endpoints
+            .read()
+            .iter()
+            .filter_map(|endpoint| {
+                endpoint
+                    .last_report
+                    .as_ref()
+                    .filter(|report| report.is_live(max_ledger_lag))
+                    .map(|report| (report, &endpoint.client))
+            })
+            .max_by(|(a, _), (b, _)| {
+                a.ledger_version
+                    .cmp(&b.ledger_version)
+                    .then_with(|| b.latency.cmp(&a.latency))
+            })
+            .map(|(_, client)| client.clone())
+    }
+}
+
+async fn probe_all(endpoints: &RwLock<Vec<EndpointHealth>>) {
+    let clients: Vec<Client> = endpoints
+        .read()
+        .iter()
+        .map(|endpoint| endpoint.client.clone())
+        .collect();
+    let reports =
+        futures::future::join_all(clients.iter().map(|client| client.node_health())).await;
+
+    let mut endpoints = endpoints.write();
+    for (endpoint, report) in endpoints.iter_mut().zip(reports) {
+        endpoint.last_report = report.ok();
+    }
+}
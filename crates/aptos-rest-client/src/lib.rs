@@ -4,11 +4,15 @@
 extern crate core;
 
 pub mod aptos;
+pub mod diff;
+pub use diff::ResourceDiff;
 pub mod error;
 pub mod faucet;
 pub use faucet::FaucetClient;
+pub mod health;
+pub use health::{HealthProber, NodeHealth};
 pub mod response;
-pub use response::Response;
+pub use response::{PageInfo, Response};
 pub mod state;
 pub mod types;
 
@@ -552,7 +556,9 @@ impl Client {
     /// * `timeout_from_call`:
     ///     When an absolute timeout for this function is needed,
     ///     irrespective of whether expiry time is reached.
-    async fn wait_for_transaction_by_hash_inner<F, Fut, T>(
+    /// * `on_progress`:
+    ///     Invoked after every poll with the state the transaction was observed in.
+    async fn wait_for_transaction_by_hash_inner<F, Fut, T, P>(
         &self,
         hash: HashValue,
         expiration_timestamp_secs: u64,
@@ -560,10 +566,12 @@ impl Client {
 
         timeout_from_call: Option<Duration>,
         fetch: F,
+        mut on_progress: P,
     ) -> AptosResult<Response<T>>
     where
         F: Fn(HashValue) -> Fut,
         Fut: Future<Output = AptosResult<WaitForTransactionResult<T>>>,
+        P: FnMut(TransactionWaitState),
     {
         const DEFAULT_DELAY: Duration = Duration::from_millis(500);
         let mut reached_mempool = false;
@@ -572,15 +580,18 @@ impl Client {
             let mut chain_timestamp_usecs = None;
             match fetch(hash).await {
                 Ok(WaitForTransactionResult::Success(result)) => {
+                    on_progress(TransactionWaitState::Committed);
                     return Ok(result);
                 },
                 Ok(WaitForTransactionResult::FailedExecution(vm_status)) => {
+                    on_progress(TransactionWaitState::Committed);
                     return Err(anyhow!(
                         "Transaction committed on chain, but failed execution: {}",
                         vm_status
                     ))?;
                 },
                 Ok(WaitForTransactionResult::Pending(state)) => {
+                    on_progress(TransactionWaitState::Pending);
                     reached_mempool = true;
                     if expiration_timestamp_secs <= state.timestamp_usecs / 1_000_000 {
                         return Err(anyhow!("Transaction expired. It is guaranteed it will not be committed on chain.").into());
@@ -588,6 +599,7 @@ impl Client {
                     chain_timestamp_usecs = Some(state.timestamp_usecs);
                 },
                 Ok(WaitForTransactionResult::NotFound(error)) => {
+                    on_progress(TransactionWaitState::NotFound);
                     if let RestError::Api(aptos_error_response) = error {
                         if let Some(state) = aptos_error_response.state {
                             if expiration_timestamp_secs <= state.timestamp_usecs / 1_000_000 {
@@ -681,6 +693,30 @@ impl Client {
         max_server_lag_wait: Option<Duration>,
         timeout_from_call: Option<Duration>,
     ) -> AptosResult<Response<Transaction>> {
+        self.wait_for_transaction_by_hash_with_progress(
+            hash,
+            expiration_timestamp_secs,
+            max_server_lag_wait,
+            timeout_from_call,
+            |_| {},
+        )
+        .await
+    }
+
+    /// Works like `wait_for_transaction_by_hash`, but invokes `on_progress` on every
+    /// poll with the state the transaction was observed in, e.g. to drive a
+    /// "seen by mempool -> committed" progress indicator.
+    pub async fn wait_for_transaction_by_hash_with_progress<P>(
+        &self,
+        hash: HashValue,
+        expiration_timestamp_secs: u64,
+        max_server_lag_wait: Option<Duration>,
+        timeout_from_call: Option<Duration>,
+        on_progress: P,
+    ) -> AptosResult<Response<Transaction>>
+    where
+        P: FnMut(TransactionWaitState),
+    {
         self.wait_for_transaction_by_hash_inner(
             hash,
             expiration_timestamp_secs,
@@ -711,6 +747,7 @@ impl Client {
                     Ok(WaitForTransactionResult::NotFound(error_response))
                 }
             },
+            on_progress,
         )
         .await
     }
@@ -754,6 +791,7 @@ impl Client {
                     Ok(WaitForTransactionResult::NotFound(error_response))
                 }
             },
+            |_| {},
         )
         .await
     }
@@ -799,7 +837,7 @@ impl Client {
 
         let response = request.send().await?;
 
-        self.json(response).await
+        Ok(self.json(response).await?.with_limit(limit.map(u64::from)))
     }
 
     pub async fn get_transactions_bcs(
@@ -890,7 +928,7 @@ impl Client {
 
         let response = request.send().await?;
 
-        self.json(response).await
+        Ok(self.json(response).await?.with_limit(limit))
     }
 
     pub async fn get_account_transactions_bcs(
@@ -916,6 +954,27 @@ impl Client {
         .await
     }
 
+    /// Fetches a single page of resources starting at the given cursor. The
+    /// cursor for the next page is available via `Response::page_info`.
+    pub async fn get_account_resources_page(
+        &self,
+        address: AccountAddress,
+        cursor: Option<String>,
+        limit: u64,
+        ledger_version: Option<u64>,
+    ) -> AptosResult<Response<Vec<Resource>>> {
+        let url = self.build_url_for_pagination(
+            &format!("accounts/{}/resources", address),
+            limit,
+            ledger_version,
+            cursor,
+        )?;
+        Ok(self
+            .get::<Vec<Resource>>(url)
+            .await?
+            .with_limit(Some(limit)))
+    }
+
     pub async fn get_account_resources_bcs(
         &self,
         address: AccountAddress,
@@ -1123,7 +1182,7 @@ impl Client {
         }
 
         let response = request.send().await?;
-        self.json(response).await
+        Ok(self.json(response).await?.with_limit(limit.map(u64::from)))
     }
 
     pub async fn get_account_events_bcs(
@@ -1367,7 +1426,10 @@ impl Client {
         }
 
         let response = request.send().await?;
-        self.check_and_parse_bcs_response(response).await
+        Ok(self
+            .check_and_parse_bcs_response(response)
+            .await?
+            .with_limit(limit.map(u64::from)))
     }
 
     async fn check_and_parse_bcs_response(
@@ -1581,6 +1643,17 @@ pub struct GasEstimationParams {
     pub estimated_gas_price: u64,
 }
 
+/// The state a transaction was observed in while waiting for it to commit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionWaitState {
+    /// The node does not know about the transaction (yet)
+    NotFound,
+    /// The transaction is in mempool, waiting to be executed
+    Pending,
+    /// The transaction was committed on chain, successfully or not
+    Committed,
+}
+
 enum WaitForTransactionResult<T> {
     NotFound(RestError),
     FailedExecution(String),
@@ -7,11 +7,39 @@ use crate::state::State;
 pub struct Response<T> {
     inner: T,
     state: State,
+    limit: Option<u64>,
+}
+
+/// Pagination metadata for a single page returned by the API
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageInfo {
+    /// The `X-Aptos-Cursor` to pass as `start` to fetch the next page, if any
+    pub cursor: Option<String>,
+    /// The limit that was requested for this page, if any
+    pub limit: Option<u64>,
+    /// The number of items in this page
+    pub count: usize,
+}
+
+impl PageInfo {
+    pub fn has_next_page(&self) -> bool {
+        self.cursor.is_some()
+    }
 }
 
 impl<T> Response<T> {
     pub fn new(inner: T, state: State) -> Self {
-        Self { inner, state }
+        Self {
+            inner,
+            state,
+            limit: None,
+        }
+    }
+
+    /// Records the page limit that was requested to produce this response
+    pub fn with_limit(mut self, limit: Option<u64>) -> Self {
+        self.limit = limit;
+        self
     }
 
     pub fn inner(&self) -> &T {
@@ -30,13 +58,24 @@ impl<T> Response<T> {
         (self.inner, self.state)
     }
 
+    /// The cursor returned in the `X-Aptos-Cursor` header, if any
+    pub fn cursor(&self) -> Option<&str> {
+        self.state.cursor.as_deref()
+    }
+
+    /// The page limit that was requested, if known
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
     pub fn and_then<U, E, F>(self, f: F) -> Result<Response<U>, E>
     where
         F: FnOnce(T) -> Result<U, E>,
     {
+        let limit = self.limit;
         let (inner, state) = self.into_parts();
         match f(inner) {
-            Ok(new_inner) => Ok(Response::new(new_inner, state)),
+            Ok(new_inner) => Ok(Response::new(new_inner, state).with_limit(limit)),
             Err(err) => Err(err),
         }
     }
@@ -45,7 +84,18 @@ impl<T> Response<T> {
     where
         F: FnOnce(T) -> U,
     {
+        let limit = self.limit;
         let (inner, state) = self.into_parts();
-        Response::new(f(inner), state)
+        Response::new(f(inner), state).with_limit(limit)
+    }
+}
+
+impl<T> Response<Vec<T>> {
+    pub fn page_info(&self) -> PageInfo {
+        PageInfo {
+            cursor: self.state.cursor.clone(),
+            limit: self.limit,
+            count: self.inner.len(),
+        }
     }
 }
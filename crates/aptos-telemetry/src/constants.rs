@@ -36,6 +36,7 @@ pub(crate) const MAINNET_TELEMETRY_SERVICE_URL: &str = "https://telemetry.mainne
 pub(crate) const NODE_BUILD_INFO_FREQ_SECS: u64 = 60 * 60; // 60 minutes
 pub(crate) const NODE_CORE_METRICS_FREQ_SECS: u64 = 30; // 30 seconds
 pub(crate) const NODE_NETWORK_METRICS_FREQ_SECS: u64 = 60; // 1 minute
+pub(crate) const NODE_PEER_REPORTS_FREQ_SECS: u64 = 60; // 1 minute
 pub(crate) const NODE_SYS_INFO_FREQ_SECS: u64 = 5 * 60; // 5 minutes
 pub(crate) const NODE_CONFIG_FREQ_SECS: u64 = 60 * 60; // 60 minutes
 
@@ -8,6 +8,8 @@ use std::collections::BTreeMap;
 
 /// Network metrics event name
 const APTOS_NODE_NETWORK_METRICS: &str = "APTOS_NODE_NETWORK_METRICS";
+/// Peer reports event name
+const APTOS_NODE_PEER_REPORTS: &str = "APTOS_NODE_PEER_REPORTS";
 
 /// Network metric keys
 const NETWORK_INBOUND_CONNECTIONS: &str = "network_inbound_connections";
@@ -29,6 +31,28 @@ pub(crate) async fn create_network_metric_telemetry_event() -> TelemetryEvent {
     }
 }
 
+/// Collects and sends the latest peer reports via telemetry
+pub(crate) async fn create_peer_reports_telemetry_event() -> TelemetryEvent {
+    TelemetryEvent {
+        name: APTOS_NODE_PEER_REPORTS.into(),
+        params: get_peer_reports(),
+    }
+}
+
+/// Returns the latest report of each connected peer (JSON encoded), keyed by
+/// the network and id of the peer
+pub fn get_peer_reports() -> BTreeMap<String, String> {
+    aptos_network::application::peer_report::latest_peer_reports()
+        .into_iter()
+        .filter_map(|report| {
+            let key = format!("{}_{}", report.network_id, report.peer_id);
+            serde_json::to_string(&report)
+                .ok()
+                .map(|report| (key, report))
+        })
+        .collect()
+}
+
 /// Used to expose network metrics for the node
 pub fn get_network_metrics() -> BTreeMap<String, String> {
     let mut network_metrics: BTreeMap<String, String> = BTreeMap::new();
@@ -4,10 +4,14 @@
 #![forbid(unsafe_code)]
 
 use crate::{
-    constants::*, core_metrics::create_core_metric_telemetry_event, metrics,
-    network_metrics::create_network_metric_telemetry_event, sender::TelemetrySender,
+    constants::*,
+    core_metrics::create_core_metric_telemetry_event,
+    metrics,
+    network_metrics::{create_network_metric_telemetry_event, create_peer_reports_telemetry_event},
+    sender::TelemetrySender,
     system_information::create_system_info_telemetry_event,
-    telemetry_log_sender::TelemetryLogSender, utils::create_build_info_telemetry_event,
+    telemetry_log_sender::TelemetryLogSender,
+    utils::create_build_info_telemetry_event,
 };
 use aptos_config::config::NodeConfig;
 use aptos_logger::{
@@ -283,46 +287,56 @@ async fn custom_event_sender(
     node_config: NodeConfig,
     build_info: BTreeMap<String, String>,
 ) {
-    futures::future::join5(
-        // Periodically send build information
-        run_function_periodically(NODE_BUILD_INFO_FREQ_SECS, || {
-            send_build_information(
+    futures::future::join(
+        futures::future::join5(
+            // Periodically send build information
+            run_function_periodically(NODE_BUILD_INFO_FREQ_SECS, || {
+                send_build_information(
+                    peer_id.clone(),
+                    chain_id.to_string(),
+                    build_info.clone(),
+                    telemetry_sender.clone(),
+                )
+            }),
+            // Periodically send system information
+            run_function_periodically(NODE_SYS_INFO_FREQ_SECS, || {
+                send_system_information(
+                    peer_id.clone(),
+                    chain_id.to_string(),
+                    telemetry_sender.clone(),
+                )
+            }),
+            // Periodically send node core metrics
+            run_function_periodically(NODE_CORE_METRICS_FREQ_SECS, || {
+                send_node_core_metrics(
+                    peer_id.clone(),
+                    chain_id.to_string(),
+                    &node_config,
+                    telemetry_sender.clone(),
+                )
+            }),
+            // Periodically send node network metrics
+            run_function_periodically(NODE_NETWORK_METRICS_FREQ_SECS, || {
+                send_node_network_metrics(
+                    peer_id.clone(),
+                    chain_id.to_string(),
+                    telemetry_sender.clone(),
+                )
+            }),
+            run_function_periodically(NODE_CONFIG_FREQ_SECS, || {
+                send_node_config(
+                    peer_id.clone(),
+                    chain_id.to_string(),
+                    &node_config,
+                    telemetry_sender.clone(),
+                )
+            }),
+        ),
+        // Periodically send the peer reports
+        run_function_periodically(NODE_PEER_REPORTS_FREQ_SECS, || {
+            send_peer_reports(
                 peer_id.clone(),
                 chain_id.to_string(),
-                build_info.clone(),
-                telemetry_sender.clone(),
-            )
-        }),
-        // Periodically send system information
-        run_function_periodically(NODE_SYS_INFO_FREQ_SECS, || {
-            send_system_information(
-                peer_id.clone(),
-                chain_id.to_string(),
-                telemetry_sender.clone(),
-            )
-        }),
-        // Periodically send node core metrics
-        run_function_periodically(NODE_CORE_METRICS_FREQ_SECS, || {
-            send_node_core_metrics(
-                peer_id.clone(),
-                chain_id.to_string(),
-                &node_config,
-                telemetry_sender.clone(),
-            )
-        }),
-        // Periodically send node network metrics
-        run_function_periodically(NODE_NETWORK_METRICS_FREQ_SECS, || {
-            send_node_network_metrics(
-                peer_id.clone(),
-                chain_id.to_string(),
-                telemetry_sender.clone(),
-            )
-        }),
-        run_function_periodically(NODE_CONFIG_FREQ_SECS, || {
-            send_node_config(
-                peer_id.clone(),
-                chain_id.to_string(),
-                &node_config,
                 telemetry_sender.clone(),
             )
         }),
@@ -389,6 +403,16 @@ async fn send_node_network_metrics(
     send_telemetry_event_with_ip(peer_id, chain_id, telemetry_sender, telemetry_event).await;
 }
 
+/// Sends the latest peer reports via telemetry
+async fn send_peer_reports(
+    peer_id: String,
+    chain_id: String,
+    telemetry_sender: Option<TelemetrySender>,
+) {
+    let telemetry_event = create_peer_reports_telemetry_event().await;
+    send_telemetry_event_with_ip(peer_id, chain_id, telemetry_sender, telemetry_event).await;
+}
+
 /// Collects and sends the system information via telemetry
 async fn send_system_information(
     peer_id: String,
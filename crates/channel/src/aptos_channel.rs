@@ -198,6 +198,7 @@ pub struct Config {
     pub queue_style: QueueStyle,
     pub max_capacity: usize,
     pub counters: Option<&'static IntCounterVec>,
+    pub max_capacity_per_group: Option<usize>,
 }
 
 impl Config {
@@ -210,6 +211,7 @@ impl Config {
             queue_style: QueueStyle::FIFO,
             max_capacity,
             counters: None,
+            max_capacity_per_group: None,
         }
     }
 
@@ -226,8 +228,40 @@ impl Config {
         self
     }
 
-    pub fn build<K: Eq + Hash + Clone, M>(self) -> (Sender<K, M>, Receiver<K, M>) {
-        new(self.queue_style, self.max_capacity, self.counters)
+    /// Enables fair queuing among groups of keys: messages are dequeued
+    /// round-robin among the groups (rather than among the keys), and each group
+    /// can hold at most `max_capacity_per_group` messages across all its keys.
+    /// Messages dropped because their group is full are counted as
+    /// `dropped_over_quota`. Keys are grouped by the function passed to
+    /// [`Config::build_grouped`]; with [`Config::build`], each key is its own
+    /// group. Defaults to disabled.
+    pub fn fair_queuing(mut self, max_capacity_per_group: usize) -> Self {
+        self.max_capacity_per_group = Some(max_capacity_per_group);
+        self
+    }
+
+    pub fn build<K: Eq + Hash + Clone + 'static, M>(self) -> (Sender<K, M>, Receiver<K, M>) {
+        self.build_grouped(K::clone)
+    }
+
+    /// Builds the channel, grouping its keys by `group_of` if fair queuing is
+    /// enabled (e.g., grouping `(PeerId, ProtocolId)` keys by peer).
+    pub fn build_grouped<K: Eq + Hash + Clone + 'static, G: Hash + 'static, M>(
+        self,
+        group_of: fn(&K) -> G,
+    ) -> (Sender<K, M>, Receiver<K, M>) {
+        let max_queue_size_per_key =
+            NonZeroUsize!(self.max_capacity, "aptos_channel cannot be of size 0");
+        let mut internal_queue =
+            PerKeyQueue::new(self.queue_style, max_queue_size_per_key, self.counters);
+        if let Some(max_capacity_per_group) = self.max_capacity_per_group {
+            let max_queue_size_per_group = NonZeroUsize!(
+                max_capacity_per_group,
+                "aptos_channel groups cannot be of size 0"
+            );
+            internal_queue = internal_queue.group_keys(group_of, max_queue_size_per_group);
+        }
+        new_with_queue(internal_queue)
     }
 }
 
@@ -239,8 +273,18 @@ pub fn new<K: Eq + Hash + Clone, M>(
 ) -> (Sender<K, M>, Receiver<K, M>) {
     let max_queue_size_per_key =
         NonZeroUsize!(max_queue_size_per_key, "aptos_channel cannot be of size 0");
+    new_with_queue(PerKeyQueue::new(
+        queue_style,
+        max_queue_size_per_key,
+        counters,
+    ))
+}
+
+fn new_with_queue<K: Eq + Hash + Clone, M>(
+    internal_queue: PerKeyQueue<K, (M, Option<oneshot::Sender<ElementStatus<M>>>)>,
+) -> (Sender<K, M>, Receiver<K, M>) {
     let shared_state = Arc::new(Mutex::new(SharedState {
-        internal_queue: PerKeyQueue::new(queue_style, max_queue_size_per_key, counters),
+        internal_queue,
         waker: None,
         num_senders: 1,
         receiver_dropped: false,
@@ -3,9 +3,9 @@
 
 use aptos_metrics_core::IntCounterVec;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{hash_map::RandomState, HashMap, VecDeque},
     fmt::{Debug, Formatter, Result},
-    hash::Hash,
+    hash::{BuildHasher, Hash, Hasher},
     num::NonZeroUsize,
 };
 
@@ -42,6 +42,12 @@ impl Default for QueueStyle {
 /// fashion among keys.
 ///
 /// If there are no messages, in any of the queues, `None` is returned.
+///
+/// Optionally, the keys can be grouped (e.g., the `(PeerId, ProtocolId)` keys
+/// of inbound network messages by peer) for fair queuing: messages are then
+/// picked round-robin among groups (and among the keys of each group), and each
+/// group can only hold a bounded number of messages across all its keys. This
+/// way, a group with many keys can't claim a larger share of the queue.
 pub(crate) struct PerKeyQueue<K: Eq + Hash + Clone, T> {
     /// QueueStyle for the messages stored per key
     queue_style: QueueStyle,
@@ -60,6 +66,37 @@ pub(crate) struct PerKeyQueue<K: Eq + Hash + Clone, T> {
     /// Optional counters for recording # enqueued, # dequeued, and # dropped
     /// messages
     counters: Option<&'static IntCounterVec>,
+    /// The groups of keys, if fair queuing is enabled
+    groups: Option<KeyGroups<K>>,
+}
+
+/// The queue of the keys with pending messages of a group
+struct GroupQueue<K> {
+    /// The keys of the group with pending messages, in round-robin order
+    keys: VecDeque<K>,
+    /// The number of pending messages across all keys of the group
+    num_messages: usize,
+}
+
+impl<K> Default for GroupQueue<K> {
+    fn default() -> Self {
+        Self {
+            keys: VecDeque::new(),
+            num_messages: 0,
+        }
+    }
+}
+
+/// The state of fair queuing among groups of keys
+struct KeyGroups<K> {
+    /// Maps a key to (the hash of) its group
+    group_of: Box<dyn Fn(&K) -> u64 + Send + Sync>,
+    /// Maximum number of messages to store per group
+    max_queue_size: NonZeroUsize,
+    /// The queues of the groups with pending messages
+    queues: HashMap<u64, GroupQueue<K>>,
+    /// A round-robin queue of the groups with pending messages
+    round_robin_queue: VecDeque<u64>,
 }
 
 impl<K: Eq + Hash + Clone, T> Debug for PerKeyQueue<K, T> {
@@ -68,6 +105,10 @@ impl<K: Eq + Hash + Clone, T> Debug for PerKeyQueue<K, T> {
             .field("queue_style", &self.queue_style)
             .field("max_queue_size", &self.max_queue_size)
             .field("num_popped_since_gc", &self.num_popped_since_gc)
+            .field(
+                "max_queue_size_per_group",
+                &self.groups.as_ref().map(|groups| groups.max_queue_size),
+            )
             .finish()
     }
 }
@@ -87,16 +128,52 @@ impl<K: Eq + Hash + Clone, T> PerKeyQueue<K, T> {
             round_robin_queue: VecDeque::new(),
             num_popped_since_gc: 0,
             counters,
+            groups: None,
         }
     }
 
+    /// Enables fair queuing among the groups of keys returned by `group_of`,
+    /// storing at most `max_queue_size_per_group` messages per group.
+    pub(crate) fn group_keys<G: Hash + 'static>(
+        mut self,
+        group_of: fn(&K) -> G,
+        max_queue_size_per_group: NonZeroUsize,
+    ) -> Self
+    where
+        K: 'static,
+    {
+        // Groups are identified by their (randomly keyed) hashes, so that the
+        // group type doesn't leak into the channel's type
+        let hash_builder = RandomState::new();
+        let group_of = move |key: &K| {
+            let mut hasher = hash_builder.build_hasher();
+            group_of(key).hash(&mut hasher);
+            hasher.finish()
+        };
+        self.groups = Some(KeyGroups {
+            group_of: Box::new(group_of),
+            max_queue_size: max_queue_size_per_group,
+            queues: HashMap::new(),
+            round_robin_queue: VecDeque::new(),
+        });
+        self
+    }
+
     /// Given a key, pops the message from its queue and returns the message
     /// It also returns a boolean indicating whether the keys queue is empty
     /// after popping the message
     fn pop_from_key_queue(&mut self, key: &K) -> (Option<T>, bool) {
-        if let Some(q) = self.per_key_queue.get_mut(key) {
+        Self::pop_from_queue(&mut self.per_key_queue, self.queue_style, key)
+    }
+
+    fn pop_from_queue(
+        per_key_queue: &mut HashMap<K, VecDeque<T>>,
+        queue_style: QueueStyle,
+        key: &K,
+    ) -> (Option<T>, bool) {
+        if let Some(q) = per_key_queue.get_mut(key) {
             // Extract message from the key's queue
-            let retval = match self.queue_style {
+            let retval = match queue_style {
                 QueueStyle::FIFO | QueueStyle::KLAST => q.pop_front(),
                 QueueStyle::LIFO => q.pop_back(),
             };
@@ -114,6 +191,10 @@ impl<K: Eq + Hash + Clone, T> PerKeyQueue<K, T> {
             c.with_label_values(&["enqueued"]).inc();
         }
 
+        if self.groups.is_some() {
+            return self.push_grouped(key, message);
+        }
+
         let key_message_queue = self
             .per_key_queue
             .entry(key.clone())
@@ -151,21 +232,74 @@ impl<K: Eq + Hash + Clone, T> PerKeyQueue<K, T> {
         }
     }
 
+    /// push a message to its key's queue, if its group isn't full.
+    /// Returns Some(T) if the new or an existing element was dropped. Returns None otherwise.
+    fn push_grouped(&mut self, key: K, message: T) -> Option<T> {
+        let groups = self.groups.as_mut().expect("Keys must be grouped");
+        let group = (groups.group_of)(&key);
+        let group_queue = groups.queues.entry(group).or_default();
+        let key_message_queue = self
+            .per_key_queue
+            .entry(key.clone())
+            .or_insert_with(|| VecDeque::with_capacity(1));
+
+        let is_key_full = key_message_queue.len() >= self.max_queue_size.get();
+        let is_group_full = group_queue.num_messages >= groups.max_queue_size.get();
+        if is_key_full || is_group_full {
+            if let Some(c) = self.counters.as_ref() {
+                let label = if is_key_full {
+                    "dropped"
+                } else {
+                    "dropped_over_quota"
+                };
+                c.with_label_values(&[label]).inc();
+            }
+            // A key without pending messages can't make room in its group
+            if key_message_queue.is_empty() {
+                if group_queue.keys.is_empty() {
+                    groups.queues.remove(&group);
+                }
+                return Some(message);
+            }
+            return match self.queue_style {
+                // Drop the newest message for FIFO
+                QueueStyle::FIFO => Some(message),
+                // Drop the oldest message (of the same key) for LIFO
+                QueueStyle::LIFO | QueueStyle::KLAST => {
+                    let oldest = key_message_queue.pop_front();
+                    key_message_queue.push_back(message);
+                    oldest
+                },
+            };
+        }
+
+        // Add the key to its group's round-robin queue, and the group to the
+        // round-robin queue of groups, if they're not already there
+        if key_message_queue.is_empty() {
+            if group_queue.keys.is_empty() {
+                groups.round_robin_queue.push_back(group);
+            }
+            group_queue.keys.push_back(key);
+        }
+        key_message_queue.push_back(message);
+        group_queue.num_messages += 1;
+        None
+    }
+
     /// pop a message from the appropriate queue in per_key_queue
     /// remove the key from the round_robin_queue if it has no more messages
     pub(crate) fn pop(&mut self) -> Option<T> {
-        let key = match self.round_robin_queue.pop_front() {
-            Some(v) => v,
-            _ => {
-                return None;
-            },
+        let message = if self.groups.is_some() {
+            self.pop_grouped()?
+        } else {
+            let key = self.round_robin_queue.pop_front()?;
+            let (message, is_q_empty) = self.pop_from_key_queue(&key);
+            if !is_q_empty {
+                self.round_robin_queue.push_back(key);
+            }
+            message
         };
 
-        let (message, is_q_empty) = self.pop_from_key_queue(&key);
-        if !is_q_empty {
-            self.round_robin_queue.push_back(key);
-        }
-
         if message.is_some() {
             if let Some(c) = self.counters.as_ref() {
                 c.with_label_values(&["dequeued"]).inc();
@@ -200,6 +334,33 @@ impl<K: Eq + Hash + Clone, T> PerKeyQueue<K, T> {
         message
     }
 
+    /// pop a message from the next key of the next group, in round-robin order.
+    /// Returns None if there are no messages.
+    fn pop_grouped(&mut self) -> Option<Option<T>> {
+        let groups = self.groups.as_mut().expect("Keys must be grouped");
+        let group = groups.round_robin_queue.pop_front()?;
+        let group_queue = groups
+            .queues
+            .get_mut(&group)
+            .expect("Pending groups must have a queue");
+        let key = group_queue.keys.pop_front()?;
+
+        let (message, is_q_empty) =
+            Self::pop_from_queue(&mut self.per_key_queue, self.queue_style, &key);
+        if message.is_some() {
+            group_queue.num_messages -= 1;
+        }
+        if !is_q_empty {
+            group_queue.keys.push_back(key);
+        }
+        if group_queue.keys.is_empty() {
+            groups.queues.remove(&group);
+        } else {
+            groups.round_robin_queue.push_back(group);
+        }
+        Some(message)
+    }
+
     /// Garbage collect any empty per-key-queues.
     fn remove_empty_queues(&mut self) {
         self.per_key_queue.retain(|_key, queue| !queue.is_empty());
@@ -209,5 +370,9 @@ impl<K: Eq + Hash + Clone, T> PerKeyQueue<K, T> {
     pub(crate) fn clear(&mut self) {
         self.per_key_queue.clear();
         self.round_robin_queue.clear();
+        if let Some(groups) = self.groups.as_mut() {
+            groups.queues.clear();
+            groups.round_robin_queue.clear();
+        }
     }
 }
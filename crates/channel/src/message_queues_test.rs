@@ -275,3 +275,40 @@ fn test_message_queue_clear() {
     });
     assert_eq!(q.pop().unwrap().msg, "msg3".to_string());
 }
+
+#[test]
+fn test_fair_queuing() {
+    // Keys are (validator, protocol) pairs, grouped by validator
+    let mut q = PerKeyQueue::new(QueueStyle::FIFO, NonZeroUsize!(3), None).group_keys(
+        |(validator, _): &(AccountAddress, u8)| *validator,
+        NonZeroUsize!(4),
+    );
+    let validator1 = AccountAddress::new([0u8; AccountAddress::LENGTH]);
+    let validator2 = AccountAddress::new([1u8; AccountAddress::LENGTH]);
+
+    // Validator 1 sends on two protocols, and is limited to 4 messages overall
+    for i in 0..3 {
+        assert_eq!(q.push((validator1, 0), format!("v1-a{}", i)), None);
+    }
+    assert_eq!(q.push((validator1, 1), "v1-b0".to_string()), None);
+    assert_eq!(
+        q.push((validator1, 1), "v1-b1".to_string()),
+        Some("v1-b1".to_string())
+    );
+    // Validator 2 has a quota of its own
+    for i in 0..2 {
+        assert_eq!(q.push((validator2, 0), format!("v2-a{}", i)), None);
+    }
+
+    // Messages are popped round-robin among validators first, and among the
+    // protocols of each validator second
+    let popped: Vec<_> = std::iter::from_fn(|| q.pop()).collect();
+    assert_eq!(popped, vec![
+        "v1-a0", "v2-a0", "v1-b0", "v2-a1", "v1-a1", "v1-a2"
+    ]);
+
+    // Popping frees up the validator's quota
+    assert_eq!(q.push((validator1, 1), "v1-b2".to_string()), None);
+    assert_eq!(q.pop(), Some("v1-b2".to_string()));
+    assert_eq!(q.pop(), None);
+}
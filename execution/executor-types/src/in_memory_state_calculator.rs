@@ -188,7 +188,7 @@ impl InMemoryStateCalculator {
 
         Ok(EpochState {
             epoch: configuration.epoch(),
-            verifier: (&validator_set).into(),
+            verifier: (&validator_set).try_into()?,
         })
     }
 
@@ -28,11 +28,7 @@ use aptos_network::{
 };
 use aptos_types::on_chain_config::OnChainConfigPayload;
 use aptos_vm_validator::vm_validator::TransactionValidation;
-use futures::{
-    channel::mpsc,
-    stream::{select_all, FuturesUnordered},
-    StreamExt,
-};
+use futures::{channel::mpsc, stream::FuturesUnordered, StreamExt};
 use std::{
     sync::Arc,
     time::{Duration, Instant, SystemTime},
@@ -59,12 +55,7 @@ pub(crate) async fn coordinator<NetworkClient, TransactionValidator>(
     ));
 
     // Transform events to also include the network id
-    let network_events: Vec<_> = network_service_events
-        .into_network_and_events()
-        .into_iter()
-        .map(|(network_id, events)| events.map(move |event| (network_id, event)))
-        .collect();
-    let mut events = select_all(network_events).fuse();
+    let mut events = network_service_events.into_unified_stream();
     let mut scheduled_broadcasts = FuturesUnordered::new();
 
     // Use a BoundedExecutor to restrict only `workers_available` concurrent
@@ -285,7 +276,7 @@ async fn handle_network_event<NetworkClient, TransactionValidator>(
             smp.network_interface.disable_peer(peer);
             notify_subscribers(SharedMempoolNotification::PeerStateChange, &smp.subscribers);
         },
-        Event::Message(peer_id, msg) => {
+        Event::Message(peer_id, msg, _) => {
             counters::shared_mempool_event_inc("message");
             match msg {
                 MempoolSyncMsg::BroadcastTransactionsRequest {
@@ -341,7 +332,8 @@ async fn handle_network_event<NetworkClient, TransactionValidator>(
                 },
             }
         },
-        Event::RpcRequest(peer_id, _msg, _, _res_tx) => {
+        Event::RpcRequest(peer_id, _msg, _, _, _)
+        | Event::StreamingRpcRequest(peer_id, _msg, _, _) => {
             counters::unexpected_msg_count_inc(&network_id);
             sample!(
                 SampleRate::Duration(Duration::from_secs(60)),
@@ -259,6 +259,8 @@ impl MempoolNode {
                 PeerManagerNotification::RecvMessage(remote_peer_id, Message {
                     protocol_id,
                     mdata: data,
+                    priority: protocol_id.default_priority(),
+                    correlation_id: 0,
                 }),
                 None,
             ),
@@ -268,6 +270,8 @@ impl MempoolNode {
                     protocol_id,
                     data,
                     res_tx,
+                    deadline: None,
+                    correlation_id: 0,
                 });
                 (notif, Some(res_rx))
             },
@@ -400,6 +404,8 @@ impl MempoolNode {
             let notif = PeerManagerNotification::RecvMessage(peer_id, Message {
                 protocol_id,
                 mdata: bytes.into(),
+                priority: protocol_id.default_priority(),
+                correlation_id: 0,
             });
             inbound_handle
                 .inbound_message_sender
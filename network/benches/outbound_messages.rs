@@ -0,0 +1,114 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Benchmarks of the outbound message path, from an application payload to
+//! the bytes written to the socket.
+
+use aptos_network::{
+    constants::{MAX_FRAME_SIZE, MAX_MESSAGE_SIZE},
+    protocols::{
+        stream::OutboundStream,
+        wire::{
+            handshake::v1::ProtocolId,
+            messaging::v1::{
+                network_message_frame_codec, DirectSendMsg, MultiplexMessage,
+                MultiplexMessageEncoder, MultiplexMessageSink, NetworkMessage,
+            },
+        },
+    },
+};
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::{executor::block_on, io, FutureExt, SinkExt, StreamExt};
+use tokio_util::codec::Encoder;
+
+/// The payload sizes to benchmark, up to the size of a large consensus block
+const PAYLOAD_SIZES: [usize; 3] = [64 * 1024, 1024 * 1024, 3 * 1024 * 1024];
+
+fn direct_send(payload: Bytes) -> NetworkMessage {
+    NetworkMessage::DirectSendMsg(DirectSendMsg {
+        protocol_id: ProtocolId::ConsensusDirectSendBcs,
+        priority: 0,
+        raw_msg: payload,
+    })
+}
+
+/// Encodes a single message into a frame, as the sink does before writing it
+fn encode_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_frame");
+    for size in PAYLOAD_SIZES {
+        let message = MultiplexMessage::Message(direct_send(Bytes::from(vec![7u8; size])));
+        group.throughput(Throughput::Bytes(size as u64));
+
+        // The previous path: serialize into an intermediate frame, then copy
+        // the frame into the write buffer
+        group.bench_with_input(
+            BenchmarkId::new("serialize_then_copy", size),
+            &message,
+            |b, message| {
+                let mut codec = network_message_frame_codec(MAX_FRAME_SIZE);
+                b.iter(|| {
+                    let mut dst = BytesMut::new();
+                    let frame = Bytes::from(bcs::to_bytes(black_box(message)).unwrap());
+                    codec.encode(frame, &mut dst).unwrap();
+                    dst
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("serialize_in_place", size),
+            &message,
+            |b, message| {
+                let mut encoder = MultiplexMessageEncoder::new(MAX_FRAME_SIZE);
+                b.iter(|| {
+                    let mut dst = BytesMut::new();
+                    encoder.encode(black_box(message), &mut dst).unwrap();
+                    dst
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Sends a message that is broadcast to many peers through a sink, sharing its payload
+fn send_to_sink(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send_to_sink");
+    for size in PAYLOAD_SIZES {
+        let payload = Bytes::from(vec![7u8; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            let mut sink = MultiplexMessageSink::new(io::sink(), MAX_FRAME_SIZE, None);
+            b.iter(|| {
+                let message = MultiplexMessage::Message(direct_send(payload.clone()));
+                block_on(sink.send(&message)).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Fragments a message that is larger than a single frame
+fn stream_message(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stream_message");
+    for size in [8 * 1024 * 1024, 32 * 1024 * 1024] {
+        let payload = Bytes::from(vec![7u8; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            let (stream_tx, mut stream_rx) = aptos_channels::new_test(1024);
+            let mut outbound_stream =
+                OutboundStream::new(MAX_FRAME_SIZE, MAX_MESSAGE_SIZE, stream_tx);
+            b.iter(|| {
+                block_on(outbound_stream.stream_message(direct_send(payload.clone()))).unwrap();
+                while let Some(Some(message)) = stream_rx.next().now_or_never() {
+                    black_box(message);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode_frame, send_to_sink, stream_message);
+criterion_main!(benches);
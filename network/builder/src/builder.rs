@@ -11,7 +11,9 @@
 //! long as the latter is in its trusted peers set.
 use aptos_config::{
     config::{
-        DiscoveryMethod, NetworkConfig, Peer, PeerRole, PeerSet, RateLimitConfig, RoleType,
+        BandwidthLimitConfig, DialBackoffConfig, DialConcurrencyConfig, DiscoveryMethod,
+        EvictionPolicy, FileDiscovery, NetworkConfig, Peer, PeerMetadataGcConfig,
+        PeerPersistenceConfig, PeerRole, PeerSet, RateLimitConfig, RoleType, TransportSecurity,
         CONNECTION_BACKOFF_BASE, CONNECTIVITY_CHECK_INTERVAL_MS, MAX_CONCURRENT_NETWORK_REQS,
         MAX_CONNECTION_DELAY_MS, MAX_FRAME_SIZE, MAX_FULLNODE_OUTBOUND_CONNECTIONS,
         MAX_INBOUND_CONNECTIONS, NETWORK_CHANNEL_SIZE,
@@ -22,22 +24,30 @@ use aptos_crypto::x25519::PublicKey;
 use aptos_event_notifications::{EventSubscriptionService, ReconfigNotificationListener};
 use aptos_infallible::RwLock;
 use aptos_logger::prelude::*;
-use aptos_netcore::transport::tcp::TCPBufferCfg;
+use aptos_netcore::transport::{
+    socks5::Socks5Proxy,
+    tcp::{TCPBufferCfg, TcpKeepaliveCfg},
+};
 use aptos_network::{
-    application::storage::PeerMetadataStorage,
+    application::{
+        interface::NetworkClient, metadata_gc::PeerMetadataGc, persistence::PeerPersistence,
+        storage::PeerMetadataStorage,
+    },
     connectivity_manager::{builder::ConnectivityManagerBuilder, ConnectivityRequest},
-    constants::MAX_MESSAGE_SIZE,
+    constants::{MAX_MESSAGE_SIZE, MAX_REASSEMBLY_SIZE},
     logging::NetworkSchema,
     peer_manager::{
         builder::{AuthenticationMode, PeerManagerBuilder},
-        ConnectionRequestSender,
+        ConnectionRequestSender, ProtocolAcls, ProtocolMessageSizeLimits,
     },
     protocols::{
         health_checker::{self, builder::HealthCheckerBuilder},
+        nat_traversal::{self, builder::NatTraversalBuilder, NatTraversalClient, NatTraversalMsg},
         network::{
             NetworkApplicationConfig, NetworkClientConfig, NetworkServiceConfig, NewNetworkEvents,
             NewNetworkSender,
         },
+        peer_exchange::{self, builder::PeerExchangeBuilder},
     },
 };
 use aptos_network_discovery::DiscoveryChangeListener;
@@ -71,6 +81,10 @@ pub struct NetworkBuilder {
     discovery_listeners: Option<Vec<DiscoveryChangeListener>>,
     connectivity_manager_builder: Option<ConnectivityManagerBuilder>,
     health_checker_builder: Option<HealthCheckerBuilder>,
+    nat_traversal_builder: Option<NatTraversalBuilder>,
+    peer_exchange_builder: Option<PeerExchangeBuilder>,
+    peer_persistence: Option<PeerPersistence>,
+    peer_metadata_gc: Option<PeerMetadataGc>,
     peer_manager_builder: PeerManagerBuilder,
     peer_metadata_storage: Arc<PeerMetadataStorage>,
 }
@@ -89,12 +103,17 @@ impl NetworkBuilder {
         authentication_mode: AuthenticationMode,
         max_frame_size: usize,
         max_message_size: usize,
+        max_reassembly_size: usize,
         enable_proxy_protocol: bool,
+        transport_security: TransportSecurity,
         network_channel_size: usize,
         max_concurrent_network_reqs: usize,
         inbound_connection_limit: usize,
+        outbound_connection_limit: Option<usize>,
+        eviction_policy: EvictionPolicy,
         inbound_rate_limit_config: Option<RateLimitConfig>,
         outbound_rate_limit_config: Option<RateLimitConfig>,
+        bandwidth_limit_config: BandwidthLimitConfig,
         tcp_buffer_cfg: TCPBufferCfg,
     ) -> Self {
         // A network cannot exist without a PeerManager
@@ -111,10 +130,15 @@ impl NetworkBuilder {
             max_concurrent_network_reqs,
             max_frame_size,
             max_message_size,
+            max_reassembly_size,
             enable_proxy_protocol,
+            transport_security,
             inbound_connection_limit,
+            outbound_connection_limit,
+            eviction_policy,
             inbound_rate_limit_config,
             outbound_rate_limit_config,
+            bandwidth_limit_config,
             tcp_buffer_cfg,
         );
 
@@ -126,6 +150,10 @@ impl NetworkBuilder {
             discovery_listeners: None,
             connectivity_manager_builder: None,
             health_checker_builder: None,
+            nat_traversal_builder: None,
+            peer_exchange_builder: None,
+            peer_persistence: None,
+            peer_metadata_gc: None,
             peer_manager_builder,
             peer_metadata_storage,
         }
@@ -153,12 +181,17 @@ impl NetworkBuilder {
             authentication_mode,
             MAX_FRAME_SIZE,
             MAX_MESSAGE_SIZE,
+            MAX_REASSEMBLY_SIZE,
             false, /* Disable proxy protocol */
+            TransportSecurity::Noise,
             NETWORK_CHANNEL_SIZE,
             MAX_CONCURRENT_NETWORK_REQS,
             MAX_INBOUND_CONNECTIONS,
             None,
+            EvictionPolicy::Reject,
+            None,
             None,
+            BandwidthLimitConfig::default(),
             TCPBufferCfg::default(),
         );
 
@@ -168,6 +201,8 @@ impl NetworkBuilder {
             MAX_FULLNODE_OUTBOUND_CONNECTIONS,
             CONNECTION_BACKOFF_BASE,
             MAX_CONNECTION_DELAY_MS,
+            &DialBackoffConfig::default(),
+            &DialConcurrencyConfig::default(),
             CONNECTIVITY_CHECK_INTERVAL_MS,
             NETWORK_CHANNEL_SIZE,
             mutual_authentication,
@@ -199,6 +234,14 @@ impl NetworkBuilder {
 
         let trusted_peers = Arc::new(RwLock::new(HashMap::new()));
 
+        // Like the connectivity manager, only limit outbound connections of non-validator
+        // networks
+        let outbound_connection_limit = if !config.network_id.is_validator_network() {
+            Some(config.max_outbound_connections)
+        } else {
+            None
+        };
+
         let mut network_builder = NetworkBuilder::new(
             chain_id,
             trusted_peers.clone(),
@@ -209,12 +252,17 @@ impl NetworkBuilder {
             authentication_mode,
             config.max_frame_size,
             config.max_message_size,
+            config.max_reassembly_size,
             config.enable_proxy_protocol,
+            config.transport_security,
             config.network_channel_size,
             config.max_concurrent_network_reqs,
             config.max_inbound_connections,
+            outbound_connection_limit,
+            config.connection_eviction_policy,
             config.inbound_rate_limit_config,
             config.outbound_rate_limit_config,
+            config.bandwidth_limit_config,
             TCPBufferCfg::new_configs(
                 config.inbound_rx_buffer_size_bytes,
                 config.inbound_tx_buffer_size_bytes,
@@ -223,12 +271,41 @@ impl NetworkBuilder {
             ),
         );
 
+        network_builder.set_protocol_message_size_limits(ProtocolMessageSizeLimits::from_config(
+            &config.max_protocol_message_sizes,
+        ));
+
+        if let Some(socks5_proxy) = &config.socks5_proxy {
+            network_builder.set_socks5_proxy(Socks5Proxy::new(
+                socks5_proxy.address.clone(),
+                socks5_proxy.credentials(),
+            ));
+        }
+
+        if let Some(tcp_keepalive) = &config.tcp_keepalive {
+            network_builder.set_tcp_keepalive(TcpKeepaliveCfg {
+                time: Duration::from_secs(tcp_keepalive.time_secs),
+                interval: Duration::from_secs(tcp_keepalive.interval_secs),
+                retries: tcp_keepalive.retries,
+            });
+        }
+
         network_builder.add_connection_monitoring(
             config.ping_interval_ms,
+            config.max_ping_interval_ms,
             config.ping_timeout_ms,
             config.ping_failures_tolerated,
+            config.dead_peer_missed_pings,
         );
 
+        if config.enable_nat_traversal {
+            network_builder.add_nat_traversal(
+                pubkey,
+                config.nat_observation_interval_ms,
+                config.nat_traversal_rpc_timeout_ms,
+            );
+        }
+
         // Always add a connectivity manager to keep track of known peers
         let seeds = merge_seeds(config);
 
@@ -238,6 +315,8 @@ impl NetworkBuilder {
             config.max_outbound_connections,
             config.connection_backoff_base,
             config.max_connection_delay_ms,
+            &config.dial_backoff,
+            &config.dial_concurrency,
             config.connectivity_check_interval_ms,
             config.network_channel_size,
             config.mutual_authentication,
@@ -277,6 +356,25 @@ impl NetworkBuilder {
             network_builder.discovery_listeners.as_ref().unwrap().len()
         );
 
+        if let Some(seeds_file) = &config.seeds_file {
+            network_builder.add_seed_file_listener(seeds_file);
+        }
+
+        network_builder.add_peer_metadata_gc(config.peer_metadata_gc);
+
+        // The validator network only connects to the (on-chain) validator set
+        if !config.network_id.is_validator_network() {
+            if let Some(peer_persistence_config) = &config.peer_persistence {
+                network_builder.add_peer_persistence(peer_persistence_config.clone());
+            }
+            if config.enable_peer_exchange {
+                network_builder.add_peer_exchange(
+                    config.peer_exchange_interval_ms,
+                    config.peer_exchange_rpc_timeout_ms,
+                );
+            }
+        }
+
         network_builder
     }
 
@@ -318,6 +416,38 @@ impl NetworkBuilder {
             );
         }
 
+        if let Some(nat_traversal_builder) = self.nat_traversal_builder.as_mut() {
+            nat_traversal_builder.start(executor);
+            debug!(
+                NetworkSchema::new(&self.network_context),
+                "{} Started NAT traversal", self.network_context
+            );
+        }
+
+        if let Some(peer_exchange_builder) = self.peer_exchange_builder.as_mut() {
+            peer_exchange_builder.start(executor);
+            debug!(
+                NetworkSchema::new(&self.network_context),
+                "{} Started peer exchange", self.network_context
+            );
+        }
+
+        if let Some(peer_persistence) = self.peer_persistence.take() {
+            peer_persistence.start(executor);
+            debug!(
+                NetworkSchema::new(&self.network_context),
+                "{} Started peer persistence", self.network_context
+            );
+        }
+
+        if let Some(peer_metadata_gc) = self.peer_metadata_gc.take() {
+            peer_metadata_gc.start(executor);
+            debug!(
+                NetworkSchema::new(&self.network_context),
+                "{} Started peer metadata garbage collection", self.network_context
+            );
+        }
+
         if let Some(discovery_listeners) = self.discovery_listeners.take() {
             discovery_listeners
                 .into_iter()
@@ -340,6 +470,51 @@ impl NetworkBuilder {
         self.peer_manager_builder.listen_address()
     }
 
+    /// Returns a handle to the protocol access control lists of the network,
+    /// which applications can use to restrict the peers allowed to send them
+    /// messages (e.g., only validators for consensus).
+    pub fn protocol_acls(&self) -> ProtocolAcls {
+        self.peer_manager_builder.protocol_acls()
+    }
+
+    /// Uses the given protocol access control lists for the network, so that
+    /// the same rules can be shared (and updated) across networks.
+    pub fn set_protocol_acls(&mut self, protocol_acls: ProtocolAcls) -> &mut Self {
+        self.peer_manager_builder.set_protocol_acls(protocol_acls);
+        self
+    }
+
+    /// Returns a handle to the per-protocol message size limits of the network,
+    /// which can be updated at runtime (e.g., on a config reload, or when an
+    /// on-chain config changes) without restarting the node.
+    pub fn protocol_message_size_limits(&self) -> ProtocolMessageSizeLimits {
+        self.peer_manager_builder.protocol_message_size_limits()
+    }
+
+    /// Uses the given per-protocol message size limits for the network, so that
+    /// the same limits can be shared (and updated) across networks.
+    pub fn set_protocol_message_size_limits(
+        &mut self,
+        limits: ProtocolMessageSizeLimits,
+    ) -> &mut Self {
+        self.peer_manager_builder
+            .set_protocol_message_size_limits(limits);
+        self
+    }
+
+    /// Establishes the outbound (TCP) connections of the network through the
+    /// given SOCKS5 proxy.
+    pub fn set_socks5_proxy(&mut self, socks5_proxy: Socks5Proxy) -> &mut Self {
+        self.peer_manager_builder.set_socks5_proxy(socks5_proxy);
+        self
+    }
+
+    /// Sends TCP keepalive probes on the (TCP) connections of the network.
+    pub fn set_tcp_keepalive(&mut self, tcp_keepalive: TcpKeepaliveCfg) -> &mut Self {
+        self.peer_manager_builder.set_tcp_keepalive(tcp_keepalive);
+        self
+    }
+
     /// Add a [`network::connectivity_manager::ConnectivityManager`] to the network.
     ///
     /// [`network::connectivity_manager::ConnectivityManager`] is responsible for ensuring that we are connected
@@ -356,6 +531,8 @@ impl NetworkBuilder {
         max_outbound_connections: usize,
         connection_backoff_base: u64,
         max_connection_delay_ms: u64,
+        dial_backoff: &DialBackoffConfig,
+        dial_concurrency: &DialConcurrencyConfig,
         connectivity_check_interval_ms: u64,
         channel_size: usize,
         mutual_authentication: bool,
@@ -376,11 +553,14 @@ impl NetworkBuilder {
             connectivity_check_interval_ms,
             connection_backoff_base,
             max_connection_delay_ms,
+            dial_backoff,
+            dial_concurrency,
             channel_size,
             ConnectionRequestSender::new(self.peer_manager_builder.connection_reqs_tx()),
             pm_conn_mgr_notifs_rx,
             outbound_connection_limit,
             mutual_authentication,
+            self.peer_metadata_storage.clone(),
         ));
         self
     }
@@ -429,12 +609,56 @@ impl NetworkBuilder {
             .push(listener);
     }
 
+    /// Reload the seed peers from the given file whenever it changes
+    fn add_seed_file_listener(&mut self, seeds_file: &FileDiscovery) {
+        let conn_mgr_reqs_tx = self
+            .conn_mgr_reqs_tx()
+            .expect("ConnectivityManager must exist");
+        let listener = DiscoveryChangeListener::seed_file(
+            self.network_context,
+            conn_mgr_reqs_tx,
+            seeds_file.path.as_path(),
+            Duration::from_secs(seeds_file.interval_secs),
+            self.time_service.clone(),
+        );
+        self.discovery_listeners
+            .as_mut()
+            .expect("Can only add listeners before starting")
+            .push(listener);
+    }
+
+    /// Persist the known peers of the network, and restore them on startup
+    fn add_peer_persistence(&mut self, config: PeerPersistenceConfig) {
+        let conn_mgr_reqs_tx = self
+            .conn_mgr_reqs_tx()
+            .expect("ConnectivityManager must exist");
+        self.peer_persistence = Some(PeerPersistence::new(
+            self.network_context,
+            self.peer_metadata_storage.clone(),
+            config,
+            conn_mgr_reqs_tx,
+            self.time_service.clone(),
+        ));
+    }
+
+    /// Garbage collect the metadata of the long-disconnected peers of the network
+    fn add_peer_metadata_gc(&mut self, config: PeerMetadataGcConfig) {
+        self.peer_metadata_gc = Some(PeerMetadataGc::new(
+            self.network_context,
+            self.peer_metadata_storage.clone(),
+            config,
+            self.time_service.clone(),
+        ));
+    }
+
     /// Add a HealthChecker to the network.
     fn add_connection_monitoring(
         &mut self,
         ping_interval_ms: u64,
+        max_ping_interval_ms: Option<u64>,
         ping_timeout_ms: u64,
         ping_failures_tolerated: u64,
+        dead_peer_missed_pings: Option<u64>,
     ) -> &mut Self {
         // Initialize and start HealthChecker.
         let (hc_network_tx, hc_network_rx) =
@@ -443,8 +667,10 @@ impl NetworkBuilder {
             self.network_context(),
             self.time_service.clone(),
             ping_interval_ms,
+            max_ping_interval_ms,
             ping_timeout_ms,
             ping_failures_tolerated,
+            dead_peer_missed_pings,
             hc_network_tx,
             hc_network_rx,
             self.peer_metadata_storage.clone(),
@@ -456,6 +682,68 @@ impl NetworkBuilder {
         self
     }
 
+    /// Add NAT traversal (i.e., reflexive address discovery and hole punching)
+    /// to the network.
+    fn add_nat_traversal(
+        &mut self,
+        identity_pubkey: PublicKey,
+        observation_interval_ms: u64,
+        rpc_timeout_ms: u64,
+    ) -> &mut Self {
+        let (nat_network_tx, nat_network_rx) =
+            self.add_client_and_service(&nat_traversal::nat_traversal_network_config());
+        self.nat_traversal_builder = Some(NatTraversalBuilder::new(
+            self.network_context(),
+            self.time_service.clone(),
+            identity_pubkey,
+            observation_interval_ms,
+            rpc_timeout_ms,
+            nat_network_tx,
+            nat_network_rx,
+            self.peer_metadata_storage.clone(),
+        ));
+        debug!(
+            NetworkSchema::new(&self.network_context),
+            "{} Created NAT traversal", self.network_context
+        );
+        self
+    }
+
+    /// Add the peer exchange protocol to the network, to learn about other
+    /// public peers from the connected peers.
+    fn add_peer_exchange(&mut self, exchange_interval_ms: u64, rpc_timeout_ms: u64) -> &mut Self {
+        let conn_mgr_reqs_tx = self
+            .conn_mgr_reqs_tx()
+            .expect("ConnectivityManager must exist");
+        let (pex_network_tx, pex_network_rx) =
+            self.add_client_and_service(&peer_exchange::peer_exchange_network_config());
+        self.peer_exchange_builder = Some(PeerExchangeBuilder::new(
+            self.network_context(),
+            self.time_service.clone(),
+            exchange_interval_ms,
+            rpc_timeout_ms,
+            pex_network_tx,
+            pex_network_rx,
+            self.peer_metadata_storage.clone(),
+            conn_mgr_reqs_tx,
+        ));
+        debug!(
+            NetworkSchema::new(&self.network_context),
+            "{} Created peer exchange", self.network_context
+        );
+        self
+    }
+
+    /// Returns a handle to query our reflexive addresses and request hole
+    /// punches, if NAT traversal is enabled
+    pub fn nat_traversal_client(
+        &self,
+    ) -> Option<NatTraversalClient<NetworkClient<NatTraversalMsg>>> {
+        self.nat_traversal_builder
+            .as_ref()
+            .map(NatTraversalBuilder::client)
+    }
+
     /// Register a new client and service application with the network. Return
     /// the client interface for sending messages and the service interface
     /// for handling network requests.
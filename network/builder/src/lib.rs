@@ -9,5 +9,6 @@ pub mod builder;
 // factoring of DummyNetwork requires use of network_builder.  A holistic review of the
 // network directory is needed to break internal circular dependencies.
 pub mod dummy;
+pub mod netbench;
 #[cfg(test)]
 mod test;
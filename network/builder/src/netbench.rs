@@ -0,0 +1,317 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! A load-testing harness for the network stack.
+//!
+//! `run_netbench` sets up two nodes connected over the real stack (i.e., TCP,
+//! noise and the peer manager, see `dummy::setup_network`), drives a
+//! configurable direct-send or RPC load from the dialer to the listener, and
+//! reports the achieved throughput and latency percentiles. This allows
+//! transport and channel changes to be benchmarked without a full testnet.
+
+use crate::dummy::{setup_network, DummyMsg, DummyNetwork, DummyNetworkEvents};
+use aptos_config::network_id::PeerNetworkId;
+use aptos_network::{
+    application::interface::{NetworkClient, NetworkClientInterface},
+    protocols::network::Event,
+};
+use futures::StreamExt;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+
+/// The time the listener keeps receiving messages once the senders are done
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// The size of the send timestamp at the start of each message
+const TIMESTAMP_SIZE: usize = 8;
+
+/// The type of messages sent by the load generator
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NetbenchMode {
+    /// Direct-send messages, whose latency is measured by the receiver
+    DirectSend,
+    /// RPCs, whose latency (i.e., round-trip time) is measured by the sender
+    Rpc,
+}
+
+#[derive(Clone, Debug)]
+pub struct NetbenchConfig {
+    pub mode: NetbenchMode,
+    /// The size of the payload of each message
+    pub message_size: usize,
+    /// The number of messages each sender sends per second, or `None` to send
+    /// as fast as possible (for RPCs, one at a time)
+    pub messages_per_sec: Option<u64>,
+    /// The number of concurrent senders driving the load
+    pub fanout: usize,
+    /// The time during which the load is driven
+    pub duration: Duration,
+    pub rpc_timeout: Duration,
+}
+
+impl Default for NetbenchConfig {
+    fn default() -> Self {
+        Self {
+            mode: NetbenchMode::DirectSend,
+            message_size: 1024,
+            messages_per_sec: Some(1000),
+            fanout: 1,
+            duration: Duration::from_secs(10),
+            rpc_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The latency percentiles of the delivered messages
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyPercentiles {
+    fn from_latencies(mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        Self {
+            p50: percentile(&latencies, 0.5),
+            p90: percentile(&latencies, 0.9),
+            p99: percentile(&latencies, 0.99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns the given percentile of the given sorted latencies
+fn percentile(sorted_latencies: &[Duration], percentile: f64) -> Duration {
+    if sorted_latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted_latencies.len() - 1) as f64 * percentile).round() as usize;
+    sorted_latencies[index]
+}
+
+#[derive(Clone, Debug)]
+pub struct NetbenchReport {
+    /// The number of messages handed to the network
+    pub messages_sent: u64,
+    /// The number of messages that couldn't be handed to the network (e.g.,
+    /// because the queue was full) or whose RPC failed
+    pub send_failures: u64,
+    /// The number of direct-send messages received, or of RPC responses received
+    pub messages_delivered: u64,
+    /// The payload bytes of the delivered messages
+    pub bytes_delivered: u64,
+    /// The time from the start of the load to the last delivery
+    pub elapsed: Duration,
+    pub latency: LatencyPercentiles,
+}
+
+impl NetbenchReport {
+    pub fn messages_per_sec(&self) -> f64 {
+        self.messages_delivered as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes_delivered as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for NetbenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent: {}, failed: {}, delivered: {} ({:.0} msg/s, {:.2} MiB/s), \
+             latency p50: {:?}, p90: {:?}, p99: {:?}, max: {:?}",
+            self.messages_sent,
+            self.send_failures,
+            self.messages_delivered,
+            self.messages_per_sec(),
+            self.bytes_per_sec() / (1024.0 * 1024.0),
+            self.latency.p50,
+            self.latency.p90,
+            self.latency.p99,
+            self.latency.max,
+        )
+    }
+}
+
+/// The statistics of a single sender
+#[derive(Default)]
+struct SenderStats {
+    messages_sent: u64,
+    send_failures: u64,
+    /// The RTT of each successful RPC
+    rpc_latencies: Vec<Duration>,
+}
+
+/// The statistics of the receiver
+#[derive(Default)]
+struct ReceiverStats {
+    /// The latency of each received direct-send message
+    latencies: Vec<Duration>,
+    last_received_at: Option<Instant>,
+}
+
+/// Sets up two connected nodes and drives the configured load from the dialer
+/// to the listener, returning the measured throughput and latencies
+pub fn run_netbench(config: &NetbenchConfig) -> NetbenchReport {
+    let DummyNetwork {
+        runtime,
+        dialer_events: _dialer_events,
+        dialer_network_client,
+        listener_peer,
+        listener_events,
+        ..
+    } = setup_network();
+
+    runtime.block_on(async move {
+        let start = Instant::now();
+        let (senders_done_tx, senders_done_rx) = oneshot::channel();
+        let receiver = tokio::spawn(receive_load(listener_events, start, senders_done_rx));
+        let senders: Vec<_> = (0..config.fanout.max(1))
+            .map(|_| {
+                tokio::spawn(send_load(
+                    dialer_network_client.clone(),
+                    listener_peer,
+                    config.clone(),
+                    start,
+                ))
+            })
+            .collect();
+
+        let mut report = NetbenchReport {
+            messages_sent: 0,
+            send_failures: 0,
+            messages_delivered: 0,
+            bytes_delivered: 0,
+            elapsed: Duration::ZERO,
+            latency: LatencyPercentiles::default(),
+        };
+        let mut rpc_latencies = vec![];
+        for sender in senders {
+            let sender_stats = sender.await.expect("Sender task panicked");
+            report.messages_sent += sender_stats.messages_sent;
+            report.send_failures += sender_stats.send_failures;
+            rpc_latencies.extend(sender_stats.rpc_latencies);
+        }
+        let senders_done_at = Instant::now();
+        let _ = senders_done_tx.send(());
+        let receiver_stats = receiver.await.expect("Receiver task panicked");
+
+        let (latencies, done_at) = match config.mode {
+            NetbenchMode::DirectSend => (
+                receiver_stats.latencies,
+                receiver_stats.last_received_at.unwrap_or(senders_done_at),
+            ),
+            NetbenchMode::Rpc => (rpc_latencies, senders_done_at),
+        };
+        report.messages_delivered = latencies.len() as u64;
+        report.bytes_delivered = report.messages_delivered * config.message_size as u64;
+        report.elapsed = done_at.saturating_duration_since(start);
+        report.latency = LatencyPercentiles::from_latencies(latencies);
+        report
+    })
+}
+
+/// Sends messages to the given peer for the configured duration, at the
+/// configured rate
+async fn send_load(
+    network_client: NetworkClient<DummyMsg>,
+    peer: PeerNetworkId,
+    config: NetbenchConfig,
+    start: Instant,
+) -> SenderStats {
+    let mut stats = SenderStats::default();
+    let send_interval = config
+        .messages_per_sec
+        .map(|messages_per_sec| Duration::from_secs_f64(1.0 / messages_per_sec.max(1) as f64));
+    let deadline = Instant::now() + config.duration;
+    let mut next_send_at = Instant::now();
+    while Instant::now() < deadline {
+        match send_interval {
+            Some(send_interval) => {
+                tokio::time::sleep_until(next_send_at.into()).await;
+                next_send_at += send_interval;
+            },
+            // Let the network tasks run between sends
+            None => tokio::task::yield_now().await,
+        }
+
+        let message = new_message(start, config.message_size);
+        stats.messages_sent += 1;
+        match config.mode {
+            NetbenchMode::DirectSend => {
+                if network_client.send_to_peer(message, peer).is_err() {
+                    stats.send_failures += 1;
+                }
+            },
+            NetbenchMode::Rpc => {
+                let sent_at = Instant::now();
+                match network_client
+                    .send_to_peer_rpc(message, config.rpc_timeout, peer)
+                    .await
+                {
+                    Ok(_) => stats.rpc_latencies.push(sent_at.elapsed()),
+                    Err(_) => stats.send_failures += 1,
+                }
+            },
+        }
+    }
+    stats
+}
+
+/// Receives the messages (and responds to the RPCs) until the senders are
+/// done, and the remaining messages are drained
+async fn receive_load(
+    mut events: DummyNetworkEvents,
+    start: Instant,
+    mut senders_done: oneshot::Receiver<()>,
+) -> ReceiverStats {
+    let mut stats = ReceiverStats::default();
+    loop {
+        tokio::select! {
+            Some(event) = events.next() => handle_event(event, start, &mut stats),
+            _ = &mut senders_done => break,
+        }
+    }
+    while let Ok(Some(event)) = tokio::time::timeout(DRAIN_TIMEOUT, events.next()).await {
+        handle_event(event, start, &mut stats);
+    }
+    stats
+}
+
+fn handle_event(event: Event<DummyMsg>, start: Instant, stats: &mut ReceiverStats) {
+    match event {
+        Event::Message(_, message, _) => {
+            let now = Instant::now();
+            let sent_at = start + sent_since_start(&message);
+            stats.latencies.push(now.saturating_duration_since(sent_at));
+            stats.last_received_at = Some(now);
+        },
+        Event::RpcRequest(_, _, _, response_tx, _) => {
+            // Respond with an empty message, so that the RTT reflects the request
+            let response = bcs::to_bytes(&DummyMsg(vec![])).unwrap();
+            let _ = response_tx.send(Ok(response.into()));
+        },
+        _ => {},
+    }
+}
+
+/// Creates a message of (at least) the given size, starting with the time it
+/// was created at (relative to the start of the load)
+fn new_message(start: Instant, message_size: usize) -> DummyMsg {
+    let sent_since_start = start.elapsed().as_nanos() as u64;
+    let mut payload = sent_since_start.to_le_bytes().to_vec();
+    payload.resize(message_size.max(TIMESTAMP_SIZE), 0);
+    DummyMsg(payload)
+}
+
+/// Returns the time a message was created at, relative to the start of the load
+fn sent_since_start(message: &DummyMsg) -> Duration {
+    let timestamp = message.0[..TIMESTAMP_SIZE].try_into().unwrap();
+    Duration::from_nanos(u64::from_le_bytes(timestamp))
+}
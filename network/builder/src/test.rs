@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Integration tests for validator_network.
-use crate::dummy::{setup_network, DummyMsg};
+use crate::{
+    dummy::{setup_network, DummyMsg},
+    netbench::{run_netbench, NetbenchConfig, NetbenchMode},
+};
 use aptos_network::{application::interface::NetworkClientInterface, protocols::network::Event};
 use futures::{future::join, StreamExt};
 use std::time::Duration;
@@ -32,7 +35,7 @@ fn test_direct_send() {
             .send_to_peer(msg_clone.clone(), listener_peer)
             .unwrap();
         match listener_events.next().await.unwrap() {
-            Event::Message(peer_id, msg) => {
+            Event::Message(peer_id, msg, _) => {
                 assert_eq!(peer_id, dialer_peer.peer_id());
                 assert_eq!(msg, msg_clone);
             },
@@ -46,7 +49,7 @@ fn test_direct_send() {
             .send_to_peer(msg.clone(), dialer_peer)
             .unwrap();
         match dialer_events.next().await.unwrap() {
-            Event::Message(peer_id, incoming_msg) => {
+            Event::Message(peer_id, incoming_msg, _) => {
                 assert_eq!(peer_id, listener_peer.peer_id());
                 assert_eq!(incoming_msg, msg);
             },
@@ -76,7 +79,7 @@ fn test_rpc() {
         dialer_sender.send_to_peer_rpc(msg_clone.clone(), Duration::from_secs(10), listener_peer);
     let f_respond = async move {
         match listener_events.next().await.unwrap() {
-            Event::RpcRequest(peer_id, msg, _, rs) => {
+            Event::RpcRequest(peer_id, msg, _, rs, _) => {
                 assert_eq!(peer_id, dialer_peer.peer_id());
                 assert_eq!(msg, msg_clone);
                 rs.send(Ok(bcs::to_bytes(&msg).unwrap().into())).unwrap();
@@ -94,7 +97,7 @@ fn test_rpc() {
         listener_sender.send_to_peer_rpc(msg_clone.clone(), Duration::from_secs(10), dialer_peer);
     let f_respond = async move {
         match dialer_events.next().await.unwrap() {
-            Event::RpcRequest(peer_id, msg, _, rs) => {
+            Event::RpcRequest(peer_id, msg, _, rs, _) => {
                 assert_eq!(peer_id, listener_peer.peer_id());
                 assert_eq!(msg, msg_clone);
                 rs.send(Ok(bcs::to_bytes(&msg).unwrap().into())).unwrap();
@@ -106,3 +109,28 @@ fn test_rpc() {
     let (res_msg, _) = tn.runtime.block_on(join(f_send, f_respond));
     assert_eq!(res_msg.unwrap(), msg);
 }
+
+#[test]
+fn test_netbench() {
+    ::aptos_logger::Logger::init_for_testing();
+    for mode in [NetbenchMode::DirectSend, NetbenchMode::Rpc] {
+        let config = NetbenchConfig {
+            mode,
+            message_size: 4 * 1024,
+            messages_per_sec: Some(100),
+            fanout: 2,
+            duration: Duration::from_millis(500),
+            ..NetbenchConfig::default()
+        };
+        let report = run_netbench(&config);
+        assert!(report.messages_sent > 0, "{}", report);
+        assert!(report.messages_delivered > 0, "{}", report);
+        assert!(report.messages_delivered <= report.messages_sent);
+        assert_eq!(
+            report.bytes_delivered,
+            report.messages_delivered * config.message_size as u64
+        );
+        assert!(report.latency.p50 <= report.latency.p99);
+        assert!(report.latency.p99 <= report.latency.max);
+    }
+}
@@ -145,6 +145,50 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_seed_file_listener() {
+        let path = TempPath::new();
+        path.create_as_file().unwrap();
+        let path = Arc::new(path);
+        write_peer_set(&PeerSet::new(), path.as_ref().as_ref());
+
+        let (conn_mgr_reqs_tx, mut conn_mgr_reqs_rx) = aptos_channels::new(
+            1,
+            &aptos_network::counters::PENDING_CONNECTIVITY_MANAGER_REQUESTS,
+        );
+        let listener = DiscoveryChangeListener::seed_file(
+            NetworkContext::mock(),
+            conn_mgr_reqs_tx,
+            path.as_ref().as_ref(),
+            Duration::from_millis(5),
+            TimeService::real(),
+        );
+        spawn_named!("[Network] Listener Task", Box::pin(listener).run());
+
+        if let Some(ConnectivityRequest::UpdateSeedPeers(actual_peers)) =
+            conn_mgr_reqs_rx.next().await
+        {
+            assert_eq!(PeerSet::new(), actual_peers)
+        } else {
+            panic!("No message sent by discovery")
+        }
+
+        // Only changes to the file are sent
+        let mut peers = PeerSet::new();
+        peers.insert(
+            PeerId::random(),
+            Peer::new(vec![], HashSet::new(), PeerRole::Upstream),
+        );
+        write_peer_set(&peers, path.as_ref().as_ref());
+        if let Some(ConnectivityRequest::UpdateSeedPeers(actual_peers)) =
+            conn_mgr_reqs_rx.next().await
+        {
+            assert_eq!(peers, actual_peers)
+        } else {
+            panic!("No message sent by discovery")
+        }
+    }
+
     #[tokio::test]
     async fn test_no_file() {
         let path = TempPath::new();
@@ -50,14 +50,26 @@ enum DiscoveryChangeStream {
     Rest(RestStream),
 }
 
+/// The discovered peers, and the epoch of the validator set they were
+/// extracted from (for on-chain discovery)
+type DiscoveryUpdate = (Option<u64>, PeerSet);
+
 impl Stream for DiscoveryChangeStream {
-    type Item = Result<PeerSet, DiscoveryError>;
+    type Item = Result<DiscoveryUpdate, DiscoveryError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let without_epoch =
+            |update: Result<PeerSet, DiscoveryError>| update.map(|peers| (None, peers));
         match self.get_mut() {
-            Self::ValidatorSet(stream) => Pin::new(stream).poll_next(cx),
-            Self::File(stream) => Pin::new(stream).poll_next(cx),
-            Self::Rest(stream) => Pin::new(stream).poll_next(cx),
+            Self::ValidatorSet(stream) => Pin::new(stream).poll_next(cx).map(|update| {
+                update.map(|update| update.map(|(epoch, peers)| (Some(epoch), peers)))
+            }),
+            Self::File(stream) => Pin::new(stream)
+                .poll_next(cx)
+                .map(|update| update.map(without_epoch)),
+            Self::Rest(stream) => Pin::new(stream)
+                .poll_next(cx)
+                .map(|update| update.map(without_epoch)),
         }
     }
 }
@@ -102,6 +114,28 @@ impl DiscoveryChangeListener {
         }
     }
 
+    /// Polls the given file for seed peers. Unlike file discovery, changes to
+    /// the file replace the seed peers (and only changes are sent).
+    pub fn seed_file(
+        network_context: NetworkContext,
+        update_channel: aptos_channels::Sender<ConnectivityRequest>,
+        file_path: &Path,
+        interval_duration: Duration,
+        time_service: TimeService,
+    ) -> Self {
+        let source_stream = DiscoveryChangeStream::File(FileStream::new(
+            file_path,
+            interval_duration,
+            time_service,
+        ));
+        DiscoveryChangeListener {
+            discovery_source: DiscoverySource::Config,
+            network_context,
+            update_channel,
+            source_stream,
+        }
+    }
+
     pub fn rest(
         network_context: NetworkContext,
         update_channel: aptos_channels::Sender<ConnectivityRequest>,
@@ -132,20 +166,33 @@ impl DiscoveryChangeListener {
         let discovery_source = self.discovery_source;
         let mut update_channel = self.update_channel.clone();
         let source_stream = &mut self.source_stream;
+        let mut last_seeds = None;
         info!(
             NetworkSchema::new(&network_context),
             "{} Starting {} Discovery", network_context, discovery_source
         );
 
         while let Some(update) = source_stream.next().await {
-            if let Ok(update) = update {
+            if let Ok((epoch, update)) = update {
                 trace!(
                     NetworkSchema::new(&network_context),
                     "{} Sending update: {:?}",
                     network_context,
                     update
                 );
-                let request = ConnectivityRequest::UpdateDiscoveredPeers(discovery_source, update);
+                let request = if discovery_source == DiscoverySource::Config {
+                    // Seed reloads trigger an immediate connectivity check, so
+                    // only send them when the seeds actually changed
+                    if last_seeds.as_ref() == Some(&update) {
+                        continue;
+                    }
+                    last_seeds = Some(update.clone());
+                    ConnectivityRequest::UpdateSeedPeers(update)
+                } else if let Some(epoch) = epoch {
+                    ConnectivityRequest::UpdateValidatorSet(epoch, update)
+                } else {
+                    ConnectivityRequest::UpdateDiscoveredPeers(discovery_source, update)
+                };
                 if let Err(error) = update_channel.try_send(request) {
                     inc_by_with_context(&DISCOVERY_COUNTS, &network_context, "send_failure", 1);
                     warn!(
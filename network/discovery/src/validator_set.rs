@@ -65,7 +65,8 @@ impl ValidatorSetStream {
             .set(mismatch);
     }
 
-    fn extract_updates(&mut self, payload: OnChainConfigPayload) -> PeerSet {
+    /// Returns the epoch of the validator set, and its peers
+    fn extract_updates(&mut self, payload: OnChainConfigPayload) -> (u64, PeerSet) {
         let _process_timer = EVENT_PROCESSING_LOOP_BUSY_DURATION_S.start_timer();
 
         let node_set: ValidatorSet = payload
@@ -87,12 +88,12 @@ impl ValidatorSetStream {
             peer_set.len() as u64,
         );
 
-        peer_set
+        (payload.epoch(), peer_set)
     }
 }
 
 impl Stream for ValidatorSetStream {
-    type Item = Result<PeerSet, DiscoveryError>;
+    type Item = Result<(u64, PeerSet), DiscoveryError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         Pin::new(&mut self.reconfig_events)
@@ -1,7 +1,7 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::transport::Transport;
+use crate::transport::{PeerAddr, Transport};
 use aptos_memsocket::{MemoryListener, MemorySocket};
 use aptos_types::{
     network_address::{parse_memory, NetworkAddress, Protocol},
@@ -10,6 +10,7 @@ use aptos_types::{
 use futures::{future, stream::Stream};
 use std::{
     io,
+    net::SocketAddr,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -67,6 +68,12 @@ impl Transport for MemoryTransport {
     }
 }
 
+impl PeerAddr for MemorySocket {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
 #[must_use = "streams do nothing unless polled"]
 #[derive(Debug)]
 pub struct Listener {
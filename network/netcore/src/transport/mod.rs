@@ -14,14 +14,16 @@
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use futures::{future::Future, stream::Stream};
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, net::SocketAddr};
 
 pub mod and_then;
 pub mod boxed;
 #[cfg(any(test, feature = "testing", feature = "fuzzing"))]
 pub mod memory;
 pub mod proxy_protocol;
+pub mod socks5;
 pub mod tcp;
+pub mod websocket;
 
 /// Origin of how a Connection was established.
 #[derive(Clone, Copy, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -114,6 +116,13 @@ pub trait Transport {
         Self: Sized;
 }
 
+/// A socket which may know the address of the remote end of the connection.
+/// In-memory sockets, for example, don't have one.
+pub trait PeerAddr {
+    /// Returns the socket address of the remote end of the connection, if any
+    fn peer_addr(&self) -> Option<SocketAddr>;
+}
+
 impl<T: ?Sized> TransportExt for T where T: Transport {}
 
 /// An extension trait for [`Transport`]s that provides a variety of convenient
@@ -0,0 +1,391 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! # A client for SOCKS5 proxies
+//! <https://www.rfc-editor.org/rfc/rfc1928>
+//!
+//! ## Limitations
+//! - Only supports the CONNECT command
+//! - Only supports no authentication, and username/password authentication
+//!   (<https://www.rfc-editor.org/rfc/rfc1929>)
+//!
+//! DNS names are resolved by the proxy rather than locally, so that names only
+//! known to the proxy (e.g., behind Tor-style relays) can be dialed, and so that
+//! no DNS queries leak outside of the proxy.
+
+use aptos_types::network_address::{parse_dns_tcp, parse_ip_tcp, NetworkAddress};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::{fmt, io, net::IpAddr};
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USERNAME_PASSWORD: u8 = 0x02;
+const AUTH_NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const USERNAME_PASSWORD_SUCCESS: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const RESERVED: u8 = 0x00;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN_NAME: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// A SOCKS5 proxy through which outbound connections are established
+#[derive(Clone, Eq, PartialEq)]
+pub struct Socks5Proxy {
+    /// The address of the proxy, i.e., `host:port`
+    pub address: String,
+    /// The username and password, if the proxy requires authentication
+    pub credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new(address: String, credentials: Option<(String, String)>) -> Self {
+        Self {
+            address,
+            credentials,
+        }
+    }
+}
+
+// The credentials are never logged
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Proxy")
+            .field("address", &self.address)
+            .field("authenticated", &self.credentials.is_some())
+            .finish()
+    }
+}
+
+/// Asks the proxy at the other end of the given stream to connect to the given
+/// address. Once this returns, the stream is connected to the address.
+pub async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut T,
+    proxy: &Socks5Proxy,
+    addr: &NetworkAddress,
+) -> io::Result<()> {
+    // Encode the destination first, so that invalid addresses fail early
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, RESERVED];
+    let protos = addr.as_slice();
+    let port = if let Some(((ip, port), _)) = parse_ip_tcp(protos) {
+        match ip {
+            IpAddr::V4(ip) => {
+                request.push(ATYP_IPV4);
+                request.extend_from_slice(&ip.octets());
+            },
+            IpAddr::V6(ip) => {
+                request.push(ATYP_IPV6);
+                request.extend_from_slice(&ip.octets());
+            },
+        }
+        port
+    } else if let Some(((_, name, port), _)) = parse_dns_tcp(protos) {
+        let name = name.as_ref().as_bytes();
+        request.push(ATYP_DOMAIN_NAME);
+        request.push(
+            u8::try_from(name.len())
+                .map_err(|_| invalid_input(format!("SOCKS5: DNS name is too long: '{}'", addr)))?,
+        );
+        request.extend_from_slice(name);
+        port
+    } else {
+        return Err(invalid_input(format!(
+            "SOCKS5: Invalid NetworkAddress: '{}'",
+            addr
+        )));
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+
+    authenticate(stream, proxy).await?;
+
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    // The reply contains the address the proxy bound, which isn't needed
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    check_version(reply[0])?;
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "SOCKS5: Proxy failed to connect to '{}': {}",
+                addr,
+                reply_error(reply[1])
+            ),
+        ));
+    }
+    let bound_addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN_NAME => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        },
+        atyp => {
+            return Err(invalid_data(format!(
+                "SOCKS5: Unsupported address type: {}",
+                atyp
+            )))
+        },
+    };
+    // The bound address, followed by the bound port
+    let mut bound_addr = vec![0u8; bound_addr_len + 2];
+    stream.read_exact(&mut bound_addr).await?;
+    Ok(())
+}
+
+/// Negotiates the authentication method with the proxy, and authenticates if
+/// required
+async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut T,
+    proxy: &Socks5Proxy,
+) -> io::Result<()> {
+    let greeting: &[u8] = if proxy.credentials.is_some() {
+        &[SOCKS_VERSION, 2, AUTH_NONE, AUTH_USERNAME_PASSWORD]
+    } else {
+        &[SOCKS_VERSION, 1, AUTH_NONE]
+    };
+    stream.write_all(greeting).await?;
+    stream.flush().await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    check_version(choice[0])?;
+    match (choice[1], &proxy.credentials) {
+        (AUTH_NONE, _) => Ok(()),
+        (AUTH_USERNAME_PASSWORD, Some((username, password))) => {
+            let username = username.as_bytes();
+            let password = password.as_bytes();
+            if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+                return Err(invalid_input(
+                    "SOCKS5: Username or password is too long".to_string(),
+                ));
+            }
+            let mut request = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+            request.extend_from_slice(username);
+            request.push(password.len() as u8);
+            request.extend_from_slice(password);
+            stream.write_all(&request).await?;
+            stream.flush().await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] == USERNAME_PASSWORD_SUCCESS {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5: Proxy rejected the credentials",
+                ))
+            }
+        },
+        (AUTH_NO_ACCEPTABLE_METHODS, _) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5: Proxy accepts none of the offered authentication methods",
+        )),
+        (method, _) => Err(invalid_data(format!(
+            "SOCKS5: Proxy chose an unexpected authentication method: {}",
+            method
+        ))),
+    }
+}
+
+fn check_version(version: u8) -> io::Result<()> {
+    if version == SOCKS_VERSION {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "SOCKS5: Unexpected version: {}",
+            version
+        )))
+    }
+}
+
+/// Returns the description of the given (unsuccessful) reply
+fn reply_error(reply: u8) -> &'static str {
+    match reply {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aptos_memsocket::MemorySocket;
+    use futures::{executor::block_on, future::join};
+    use std::str::FromStr;
+
+    const TEST_DATA: &[u8; 4] = &[0xDE, 0xAD, 0xBE, 0xEF];
+
+    /// Plays the proxy side of the handshake, asserting that the client sends
+    /// the expected messages, and replies with the given reply code
+    async fn serve(
+        proxy: &mut MemorySocket,
+        expected_greeting: &[u8],
+        expected_auth: Option<&[u8]>,
+        expected_request: &[u8],
+        reply: u8,
+    ) {
+        let mut greeting = vec![0u8; expected_greeting.len()];
+        proxy.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, expected_greeting);
+        match expected_auth {
+            Some(expected_auth) => {
+                proxy
+                    .write_all(&[SOCKS_VERSION, AUTH_USERNAME_PASSWORD])
+                    .await
+                    .unwrap();
+                let mut auth = vec![0u8; expected_auth.len()];
+                proxy.read_exact(&mut auth).await.unwrap();
+                assert_eq!(auth, expected_auth);
+                proxy
+                    .write_all(&[USERNAME_PASSWORD_VERSION, USERNAME_PASSWORD_SUCCESS])
+                    .await
+                    .unwrap();
+            },
+            None => proxy.write_all(&[SOCKS_VERSION, AUTH_NONE]).await.unwrap(),
+        }
+
+        let mut request = vec![0u8; expected_request.len()];
+        proxy.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, expected_request);
+        proxy
+            .write_all(&[
+                SOCKS_VERSION,
+                reply,
+                RESERVED,
+                ATYP_IPV4,
+                10,
+                0,
+                0,
+                1,
+                0x1F,
+                0x90,
+            ])
+            .await
+            .unwrap();
+        if reply == REPLY_SUCCEEDED {
+            proxy.write_all(TEST_DATA).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn connect_to_ip_address() {
+        let (mut client, mut proxy) = MemorySocket::new_pair();
+        let socks5_proxy = Socks5Proxy::new("127.0.0.1:1080".to_string(), None);
+        let addr = NetworkAddress::from_str("/ip4/1.2.3.4/tcp/6180").unwrap();
+
+        let server = serve(
+            &mut proxy,
+            &[SOCKS_VERSION, 1, AUTH_NONE],
+            None,
+            &[
+                SOCKS_VERSION,
+                CMD_CONNECT,
+                RESERVED,
+                ATYP_IPV4,
+                1,
+                2,
+                3,
+                4,
+                0x18,
+                0x24,
+            ],
+            REPLY_SUCCEEDED,
+        );
+        let client = async move {
+            connect(&mut client, &socks5_proxy, &addr).await.unwrap();
+
+            // The stream is now connected to the address
+            let mut data = [0u8; 4];
+            client.read_exact(&mut data).await.unwrap();
+            assert_eq!(&data, TEST_DATA);
+        };
+        block_on(join(server, client));
+    }
+
+    #[test]
+    fn connect_to_dns_name_with_credentials() {
+        let (mut client, mut proxy) = MemorySocket::new_pair();
+        let socks5_proxy = Socks5Proxy::new(
+            "127.0.0.1:1080".to_string(),
+            Some(("user".to_string(), "pw".to_string())),
+        );
+        let addr = NetworkAddress::from_str("/dns/aptos.dev/tcp/6180").unwrap();
+
+        let mut expected_auth = vec![USERNAME_PASSWORD_VERSION, 4];
+        expected_auth.extend_from_slice(b"user");
+        expected_auth.push(2);
+        expected_auth.extend_from_slice(b"pw");
+        let mut expected_request = vec![SOCKS_VERSION, CMD_CONNECT, RESERVED, ATYP_DOMAIN_NAME, 9];
+        expected_request.extend_from_slice(b"aptos.dev");
+        expected_request.extend_from_slice(&[0x18, 0x24]);
+
+        let server = serve(
+            &mut proxy,
+            &[SOCKS_VERSION, 2, AUTH_NONE, AUTH_USERNAME_PASSWORD],
+            Some(&expected_auth),
+            &expected_request,
+            REPLY_SUCCEEDED,
+        );
+        let client = async move {
+            connect(&mut client, &socks5_proxy, &addr).await.unwrap();
+            let mut data = [0u8; 4];
+            client.read_exact(&mut data).await.unwrap();
+            assert_eq!(&data, TEST_DATA);
+        };
+        block_on(join(server, client));
+    }
+
+    #[test]
+    fn connect_failure() {
+        let (mut client, mut proxy) = MemorySocket::new_pair();
+        let socks5_proxy = Socks5Proxy::new("127.0.0.1:1080".to_string(), None);
+        let addr = NetworkAddress::from_str("/ip4/1.2.3.4/tcp/6180").unwrap();
+
+        let server = serve(
+            &mut proxy,
+            &[SOCKS_VERSION, 1, AUTH_NONE],
+            None,
+            &[
+                SOCKS_VERSION,
+                CMD_CONNECT,
+                RESERVED,
+                ATYP_IPV4,
+                1,
+                2,
+                3,
+                4,
+                0x18,
+                0x24,
+            ],
+            0x05,
+        );
+        let client = async move {
+            let error = connect(&mut client, &socks5_proxy, &addr)
+                .await
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        };
+        block_on(join(server, client));
+    }
+}
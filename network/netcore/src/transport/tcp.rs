@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! TCP Transport
-use crate::transport::Transport;
+use crate::transport::{
+    socks5::{self, Socks5Proxy},
+    PeerAddr, Transport,
+};
 use aptos_proxy::Proxy;
 use aptos_types::{
     network_address::{parse_dns_tcp, parse_ip_tcp, parse_tcp, IpFilter, NetworkAddress},
@@ -12,7 +15,7 @@ use futures::{
     future::{self, Either, Future},
     io::{AsyncRead, AsyncWrite},
     ready,
-    stream::Stream,
+    stream::{FuturesUnordered, Stream, StreamExt},
 };
 use std::{
     fmt::Debug,
@@ -20,6 +23,7 @@ use std::{
     net::SocketAddr,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -28,6 +32,11 @@ use tokio::{
 use tokio_util::compat::Compat;
 use url::Url;
 
+/// The delay after which a connection attempt to the next resolved address is
+/// started in parallel, if the previous attempts haven't completed yet (see
+/// [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305) "Happy Eyeballs").
+pub const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TCPBufferCfg {
     inbound_rx_buffer_bytes: Option<u32>,
@@ -61,6 +70,17 @@ impl TCPBufferCfg {
     }
 }
 
+/// The TCP keepalive probes of a connection
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TcpKeepaliveCfg {
+    /// The idle time after which the first probe is sent
+    pub time: Duration,
+    /// The time between unanswered probes
+    pub interval: Duration,
+    /// The number of unanswered probes after which the connection is dropped
+    pub retries: u32,
+}
+
 /// Transport to build TCP connections
 #[derive(Debug, Clone, Default)]
 pub struct TcpTransport {
@@ -70,6 +90,11 @@ pub struct TcpTransport {
     pub nodelay: Option<bool>,
 
     pub tcp_buff_cfg: TCPBufferCfg,
+    /// TCP keepalive to set for opened sockets, or `None` to keep default.
+    pub keepalive: Option<TcpKeepaliveCfg>,
+    /// The SOCKS5 proxy through which outbound connections are established, if
+    /// any. Takes precedence over the HTTP proxy of the environment.
+    pub socks5_proxy: Option<Socks5Proxy>,
 }
 
 impl TcpTransport {
@@ -82,12 +107,29 @@ impl TcpTransport {
             stream.set_nodelay(nodelay)?;
         }
 
+        if let Some(keepalive) = self.keepalive {
+            let params = socket2::TcpKeepalive::new().with_time(keepalive.time);
+            #[cfg(any(target_os = "linux", target_vendor = "apple"))]
+            let params = params
+                .with_interval(keepalive.interval)
+                .with_retries(keepalive.retries);
+            socket2::SockRef::from(stream).set_tcp_keepalive(&params)?;
+        }
+
         Ok(())
     }
 
     pub fn set_tcp_buffers(&mut self, configs: &TCPBufferCfg) {
         self.tcp_buff_cfg = *configs;
     }
+
+    pub fn set_keepalive(&mut self, keepalive: Option<TcpKeepaliveCfg>) {
+        self.keepalive = keepalive;
+    }
+
+    pub fn set_socks5_proxy(&mut self, socks5_proxy: Option<Socks5Proxy>) {
+        self.socks5_proxy = socks5_proxy;
+    }
 }
 
 impl Transport for TcpTransport {
@@ -146,6 +188,13 @@ impl Transport for TcpTransport {
             .or_else(|| parse_dns_tcp(protos).map(|_| ()))
             .ok_or_else(|| invalid_addr_error(&addr))?;
 
+        if let Some(socks5_proxy) = &self.socks5_proxy {
+            return Ok(TcpOutbound {
+                inner: Box::pin(connect_via_socks5_proxy(socks5_proxy.clone(), addr)),
+                config: self.clone(),
+            });
+        }
+
         let proxy = Proxy::new();
 
         let proxy_addr = {
@@ -232,16 +281,15 @@ pub async fn resolve_and_connect(
         connect_with_config(port, ipaddr, tcp_buff_cfg).await
     } else if let Some(((ip_filter, dns_name, port), _addr_suffix)) = parse_dns_tcp(protos) {
         // resolve dns name and filter
-        let socketaddr_iter = resolve_with_filter(ip_filter, dns_name.as_ref(), port).await?;
-        let mut last_err = None;
-
-        // try to connect until the first succeeds
-        for socketaddr in socketaddr_iter {
-            match connect_with_config(socketaddr.port(), socketaddr.ip(), tcp_buff_cfg).await {
-                Ok(stream) => return Ok(stream),
-                Err(err) => last_err = Some(err),
-            }
-        }
+        let socketaddrs = resolve_with_filter(ip_filter, dns_name.as_ref(), port)
+            .await?
+            .collect();
+
+        // race the connection attempts, alternating between ip families
+        let last_err = match connect_happy_eyeballs(socketaddrs, tcp_buff_cfg).await {
+            Ok(stream) => return Ok(stream),
+            Err(last_err) => last_err,
+        };
 
         Err(last_err.unwrap_or_else(|| {
             io::Error::new(
@@ -258,6 +306,57 @@ pub async fn resolve_and_connect(
     }
 }
 
+/// Orders the addresses so that the ip families alternate, starting with the
+/// family of the first (i.e., preferred) address.
+fn interleave_ip_families(socketaddrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let prefer_ipv6 = socketaddrs.first().map_or(false, SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) = socketaddrs
+        .into_iter()
+        .partition(|socketaddr| socketaddr.is_ipv6() == prefer_ipv6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (preferred, other) => interleaved.extend(preferred.into_iter().chain(other)),
+        }
+    }
+}
+
+/// Connects to the first address that accepts the connection, using "Happy
+/// Eyeballs": the next connection attempt is started as soon as the previous
+/// one fails, or after [`HAPPY_EYEBALLS_DELAY`], so that an unreachable ip
+/// family doesn't stall the dial. If all attempts fail, the last error (if
+/// any) is returned.
+async fn connect_happy_eyeballs(
+    socketaddrs: Vec<SocketAddr>,
+    tcp_buff_cfg: TCPBufferCfg,
+) -> Result<TcpStream, Option<io::Error>> {
+    let mut socketaddrs = interleave_ip_families(socketaddrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        // Start the next attempt, if any
+        if let Some(socketaddr) = socketaddrs.next() {
+            attempts.push(connect_with_config(
+                socketaddr.port(),
+                socketaddr.ip(),
+                tcp_buff_cfg,
+            ));
+        }
+
+        // Wait for an attempt to complete, or for the next attempt to be due
+        match tokio::time::timeout(HAPPY_EYEBALLS_DELAY, attempts.next()).await {
+            Ok(Some(Ok(stream))) => return Ok(stream),
+            Ok(Some(Err(err))) => last_err = Some(err),
+            Ok(None) => return Err(last_err),
+            Err(_) => {},
+        }
+    }
+}
+
 async fn connect_via_proxy(proxy_addr: String, addr: NetworkAddress) -> io::Result<TcpStream> {
     let protos = addr.as_slice();
 
@@ -306,6 +405,17 @@ async fn connect_via_proxy(proxy_addr: String, addr: NetworkAddress) -> io::Resu
     }
 }
 
+async fn connect_via_socks5_proxy(
+    socks5_proxy: Socks5Proxy,
+    addr: NetworkAddress,
+) -> io::Result<TcpStream> {
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    let mut stream = TcpStream::connect(&socks5_proxy.address).await?.compat();
+    socks5::connect(&mut stream, &socks5_proxy, &addr).await?;
+    Ok(stream.into_inner())
+}
+
 fn invalid_addr_error(addr: &NetworkAddress) -> io::Error {
     io::Error::new(
         io::ErrorKind::InvalidInput,
@@ -378,6 +488,12 @@ impl TcpSocket {
     }
 }
 
+impl PeerAddr for TcpSocket {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner.get_ref().peer_addr().ok()
+    }
+}
+
 impl AsyncRead for TcpSocket {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -463,6 +579,51 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_interleave_ip_families() {
+        let ipv4_a: SocketAddr = "1.1.1.1:80".parse().unwrap();
+        let ipv4_b: SocketAddr = "2.2.2.2:80".parse().unwrap();
+        let ipv6_a: SocketAddr = "[::1]:80".parse().unwrap();
+        let ipv6_b: SocketAddr = "[::2]:80".parse().unwrap();
+
+        assert_eq!(
+            interleave_ip_families(vec![ipv6_a, ipv6_b, ipv4_a, ipv4_b]),
+            vec![ipv6_a, ipv4_a, ipv6_b, ipv4_b]
+        );
+        assert_eq!(interleave_ip_families(vec![ipv4_a, ipv4_b, ipv6_a]), vec![
+            ipv4_a, ipv6_a, ipv4_b
+        ]);
+        assert_eq!(interleave_ip_families(vec![ipv4_a, ipv4_b]), vec![
+            ipv4_a, ipv4_b
+        ]);
+        assert!(interleave_ip_families(vec![]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_happy_eyeballs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listen_addr = listener.local_addr().unwrap();
+
+        // Find an address that refuses connections
+        let closed_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // The failed attempt falls back to the next address
+        let stream = connect_happy_eyeballs(vec![closed_addr, listen_addr], TCPBufferCfg::new())
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listen_addr);
+
+        // If all attempts fail, the last error is returned
+        let result = connect_happy_eyeballs(vec![closed_addr], TCPBufferCfg::new()).await;
+        assert!(matches!(result, Err(Some(_))));
+        let result = connect_happy_eyeballs(vec![], TCPBufferCfg::new()).await;
+        assert!(matches!(result, Err(None)));
+    }
+
     #[test]
     fn test_resolve_with_filter() {
         let rt = Runtime::new().unwrap();
@@ -0,0 +1,293 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! WebSocket Transport
+//!
+//! Wraps the [`TcpTransport`] in WebSocket framing, so that AptosNet connections
+//! can get through environments that only allow HTTP(S) traffic (e.g., corporate
+//! proxies or browser-based light clients). The WebSocket stream is exposed as a
+//! plain byte stream, so the usual Noise and Handshake upgrades are applied on top
+//! of it. TLS (i.e., `wss`) is expected to be terminated in front of the node, for
+//! example by a load balancer listening on 443.
+//!
+//! WebSocket addresses are TCP addresses followed by `/ws`, e.g.:
+//!
+//! `/ip4/<ipaddr>/tcp/<port>/ws` or
+//! `/dns/<name>/tcp/<port>/ws`
+
+use crate::transport::{
+    tcp::{TcpSocket, TcpTransport},
+    PeerAddr, Transport,
+};
+use aptos_types::{
+    network_address::{parse_tcp, parse_ws, NetworkAddress, Protocol},
+    PeerId,
+};
+use bytes::{Buf, Bytes};
+use futures::{
+    future::Future,
+    io::{AsyncRead, AsyncWrite},
+    ready,
+    sink::Sink,
+    stream::{Stream, TryStreamExt},
+};
+use std::{
+    convert::TryFrom,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio_tungstenite::{
+    tungstenite::{self, Message},
+    WebSocketStream,
+};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt};
+
+/// Transport to build WebSocket connections on top of TCP connections
+#[derive(Debug, Clone, Default)]
+pub struct WsTransport {
+    tcp_transport: TcpTransport,
+}
+
+impl WsTransport {
+    pub fn new(tcp_transport: TcpTransport) -> Self {
+        Self { tcp_transport }
+    }
+}
+
+impl Transport for WsTransport {
+    type Error = io::Error;
+    type Inbound = Pin<Box<dyn Future<Output = io::Result<WsSocket>> + Send + 'static>>;
+    type Listener =
+        Pin<Box<dyn Stream<Item = io::Result<(Self::Inbound, NetworkAddress)>> + Send + 'static>>;
+    type Outbound = Pin<Box<dyn Future<Output = io::Result<WsSocket>> + Send + 'static>>;
+    type Output = WsSocket;
+
+    fn listen_on(
+        &self,
+        addr: NetworkAddress,
+    ) -> Result<(Self::Listener, NetworkAddress), Self::Error> {
+        // Only `/<ip>/tcp/<port>/ws` is allowed, and the tcp transport
+        // listens on the address without the `/ws` suffix.
+        let protos = addr.as_slice();
+        let tcp_addr = match parse_tcp(protos) {
+            Some((_, suffix)) if parse_ws(suffix).map_or(false, <[_]>::is_empty) => {
+                NetworkAddress::try_from(protos[..2].to_vec())
+                    .map_err(|_| invalid_addr_error(&addr))?
+            },
+            _ => return Err(invalid_addr_error(&addr)),
+        };
+
+        let (listener, listen_addr) = self.tcp_transport.listen_on(tcp_addr)?;
+        let listen_addr = append_ws(&listen_addr)?;
+
+        let listener = listener.map_ok(|(inbound, dialer_addr)| {
+            let inbound: Self::Inbound = Box::pin(async move {
+                let socket = inbound.await?;
+                let stream = tokio_tungstenite::accept_async(socket.compat())
+                    .await
+                    .map_err(into_io_error)?;
+                Ok(WsSocket::new(stream))
+            });
+            (inbound, dialer_addr)
+        });
+
+        Ok((Box::pin(listener), listen_addr))
+    }
+
+    fn dial(&self, peer_id: PeerId, addr: NetworkAddress) -> Result<Self::Outbound, Self::Error> {
+        let protos = addr.as_slice();
+
+        // The address must be a tcp address followed by `/ws`. Any trailing
+        // protocols are left for the upper layers.
+        let (host, port) = match parse_tcp(protos) {
+            Some(((host, port), suffix)) if parse_ws(suffix).is_some() => (host, port),
+            _ => return Err(invalid_addr_error(&addr)),
+        };
+        let host = match protos.first() {
+            Some(Protocol::Ip6(_)) => format!("[{}]", host),
+            _ => host,
+        };
+        let url = format!("ws://{}:{}/", host, port);
+
+        let tcp_addr = NetworkAddress::try_from(protos[..2].to_vec())
+            .map_err(|_| invalid_addr_error(&addr))?;
+        let outbound = self.tcp_transport.dial(peer_id, tcp_addr)?;
+
+        Ok(Box::pin(async move {
+            let socket = outbound.await?;
+            let (stream, _response) = tokio_tungstenite::client_async(url, socket.compat())
+                .await
+                .map_err(into_io_error)?;
+            Ok(WsSocket::new(stream))
+        }))
+    }
+}
+
+/// Appends the `/ws` protocol to the given tcp address
+fn append_ws(addr: &NetworkAddress) -> io::Result<NetworkAddress> {
+    let mut protos = addr.as_slice().to_vec();
+    protos.push(Protocol::Ws);
+    NetworkAddress::try_from(protos).map_err(|_| invalid_addr_error(addr))
+}
+
+fn invalid_addr_error(addr: &NetworkAddress) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "Invalid NetworkAddress: '{}', expected format: '/<ip or dns>/tcp/<port>/ws'",
+            addr
+        ),
+    )
+}
+
+fn into_io_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(error) => error,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::BrokenPipe, error)
+        },
+        error => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
+
+/// A byte stream on top of a WebSocket connection
+///
+/// Every write is sent as a single binary WebSocket message, and reads return
+/// the payloads of the received binary messages. Control messages (e.g., pings)
+/// are handled by the WebSocket stream itself.
+#[derive(Debug)]
+pub struct WsSocket {
+    inner: WebSocketStream<Compat<TcpSocket>>,
+    /// The unread part of the last received message
+    read_buffer: Bytes,
+}
+
+impl WsSocket {
+    fn new(inner: WebSocketStream<Compat<TcpSocket>>) -> Self {
+        Self {
+            inner,
+            read_buffer: Bytes::new(),
+        }
+    }
+}
+
+impl PeerAddr for WsSocket {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner.get_ref().get_ref().peer_addr()
+    }
+}
+
+impl AsyncRead for WsSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if !self.read_buffer.is_empty() {
+                let len = std::cmp::min(buf.len(), self.read_buffer.len());
+                buf[..len].copy_from_slice(&self.read_buffer[..len]);
+                self.read_buffer.advance(len);
+                return Poll::Ready(Ok(len));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_next(context)) {
+                Some(Ok(Message::Binary(data))) => self.read_buffer = Bytes::from(data),
+                // The remote closed the connection
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(0)),
+                // Ignore any other messages
+                Some(Ok(_)) => {},
+                Some(Err(error)) => return Poll::Ready(Err(into_io_error(error))),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for WsSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(Pin::new(&mut self.inner).poll_ready(context)).map_err(into_io_error)?;
+        Pin::new(&mut self.inner)
+            .start_send(Message::Binary(buf.to_vec()))
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(context)
+            .map_err(into_io_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_close(context)
+            .map_err(into_io_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::{ConnectionOrigin, TransportExt};
+    use futures::{
+        future::{join, FutureExt},
+        io::{AsyncReadExt, AsyncWriteExt},
+        stream::StreamExt,
+    };
+
+    #[tokio::test]
+    async fn simple_listen_and_dial() -> Result<(), ::std::io::Error> {
+        let t = WsTransport::default().and_then(|mut out, _addr, origin| async move {
+            match origin {
+                ConnectionOrigin::Inbound => {
+                    out.write_all(b"Earth").await?;
+                    let mut buf = [0; 3];
+                    out.read_exact(&mut buf).await?;
+                    assert_eq!(&buf, b"Air");
+                },
+                ConnectionOrigin::Outbound => {
+                    let mut buf = [0; 5];
+                    out.read_exact(&mut buf).await?;
+                    assert_eq!(&buf, b"Earth");
+                    out.write_all(b"Air").await?;
+                },
+            }
+            Ok(())
+        });
+
+        let (listener, addr) = t.listen_on("/ip4/127.0.0.1/tcp/0/ws".parse().unwrap())?;
+        assert!(matches!(addr.as_slice().last(), Some(Protocol::Ws)));
+
+        let peer_id = PeerId::random();
+        let dial = t.dial(peer_id, addr)?;
+        let listener = listener.into_future().then(|(maybe_result, _stream)| {
+            let (incoming, _addr) = maybe_result.unwrap().unwrap();
+            incoming.map(Result::unwrap)
+        });
+
+        let (outgoing, _incoming) = join(dial, listener).await;
+        assert!(outgoing.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_multiaddrs() {
+        let t = WsTransport::default();
+
+        let result = t.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap());
+        assert!(result.is_err());
+
+        let result = t.listen_on("/memory/0".parse().unwrap());
+        assert!(result.is_err());
+
+        let peer_id = PeerId::random();
+        let result = t.dial(peer_id, "/ip4/127.0.0.1/tcp/22".parse().unwrap());
+        assert!(result.is_err());
+    }
+}
@@ -80,6 +80,7 @@ impl PeerMonitoringServiceNetworkEvents {
                 PeerMonitoringServiceMessage::Request(request),
                 protocol_id,
                 response_tx,
+                _,
             ) => {
                 let response_tx = ResponseSender::new(response_tx);
                 Some((peer_id, protocol_id, request, response_tx))
@@ -79,6 +79,7 @@ async fn test_get_connected_peers() {
         MessagingProtocolVersion::V1,
         ProtocolIdSet::empty(),
         PeerRole::Unknown,
+        None,
     );
     let peer_info = PeerInfo::new(connection_metadata);
     peer_metadata_storage.insert(peer_network_id, peer_info);
@@ -173,6 +174,8 @@ impl MockClient {
             protocol_id,
             data: request_data.into(),
             res_tx: request_sender,
+            deadline: None,
+            correlation_id: 0,
         };
         let request_notification = PeerManagerNotification::RecvRpc(peer_id, inbound_rpc);
 
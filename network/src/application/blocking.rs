@@ -0,0 +1,107 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! A synchronous facade over the network client.
+//!
+//! The [`BlockingNetworkClient`] wraps a [`NetworkClientInterface`] (by default,
+//! the [`NetworkClient`]) and blocks the calling thread on the given runtime
+//! handle until each request completes. It allows synchronous components (and
+//! FFI consumers) to send messages and RPCs without being restructured around
+//! async. It must not be used from within an async context: the calls would
+//! block the runtime's worker threads, so they fail instead.
+
+use crate::application::{
+    error::Error,
+    interface::{NetworkClient, NetworkClientInterface, NetworkMessageTrait},
+};
+use aptos_config::network_id::PeerNetworkId;
+use std::{future::Future, marker::PhantomData, time::Duration};
+use tokio::runtime::Handle;
+
+/// A network client whose requests block the calling thread until they complete
+#[derive(Clone, Debug)]
+pub struct BlockingNetworkClient<Message, Client = NetworkClient<Message>> {
+    network_client: Client,
+    runtime: Handle,
+    _marker: PhantomData<Message>,
+}
+
+impl<Message: NetworkMessageTrait, Client: NetworkClientInterface<Message>>
+    BlockingNetworkClient<Message, Client>
+{
+    /// Creates a blocking client that drives the requests of the given client
+    /// on the given runtime
+    pub fn new(network_client: Client, runtime: Handle) -> Self {
+        Self {
+            network_client,
+            runtime,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying (async) network client
+    pub fn network_client(&self) -> &Client {
+        &self.network_client
+    }
+
+    /// Blocks the calling thread on the runtime until the future completes, or
+    /// fails if the caller is itself running within an async context
+    fn block_on<F: Future<Output = Result<T, Error>>, T>(&self, future: F) -> Result<T, Error> {
+        if Handle::try_current().is_ok() {
+            return Err(Error::UnexpectedError(
+                "The blocking network client can't be used from within an async context".into(),
+            ));
+        }
+        self.runtime.block_on(future)
+    }
+
+    /// Disconnects from the specified peer, see
+    /// [`NetworkClientInterface::disconnect_from_peer`]
+    pub fn disconnect_from_peer(&self, peer: PeerNetworkId) -> Result<(), Error> {
+        self.block_on(self.network_client.disconnect_from_peer(peer))
+    }
+
+    /// Sends the given message to the specified peer. Sending doesn't block, see
+    /// [`NetworkClientInterface::send_to_peer`].
+    pub fn send_to_peer(&self, message: Message, peer: PeerNetworkId) -> Result<(), Error> {
+        self.network_client.send_to_peer(message, peer)
+    }
+
+    /// Sends the given message to each of the specified peers. Sending doesn't
+    /// block, see [`NetworkClientInterface::send_to_peers`].
+    pub fn send_to_peers(&self, message: Message, peers: &[PeerNetworkId]) -> Result<(), Error> {
+        self.network_client.send_to_peers(message, peers)
+    }
+
+    /// Sends the given RPC to the specified peer, and blocks until the peer
+    /// responds or the timeout elapses, see
+    /// [`NetworkClientInterface::send_to_peer_rpc`]
+    pub fn send_to_peer_rpc(
+        &self,
+        message: Message,
+        rpc_timeout: Duration,
+        peer: PeerNetworkId,
+    ) -> Result<Message, Error> {
+        self.block_on(
+            self.network_client
+                .send_to_peer_rpc(message, rpc_timeout, peer),
+        )
+    }
+
+    /// Sends the given RPC to each of the specified peers concurrently, and
+    /// blocks until all of them respond or time out, see
+    /// [`NetworkClientInterface::send_to_peers_rpc`]
+    pub fn send_to_peers_rpc(
+        &self,
+        message: Message,
+        rpc_timeout: Duration,
+        peers: &[PeerNetworkId],
+    ) -> Result<Vec<(PeerNetworkId, Result<Message, Error>)>, Error> {
+        self.block_on(async {
+            Ok(self
+                .network_client
+                .send_to_peers_rpc(message, rpc_timeout, peers)
+                .await)
+        })
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Observability of the caches of the network layer.
+//!
+//! The caches of the [`PeerMetadataStorage`] (the connected peers supporting
+//! each protocol list) and of the network clients (the protocols of each peer)
+//! record their hits, misses, invalidations and sizes with a
+//! [`CacheStatsRecorder`]. The stats are exported as metrics, and included in
+//! the [`PeerMetadataSnapshot`] read by the node inspection service, so that
+//! cache regressions are detectable in production.
+//!
+//! [`PeerMetadataStorage`]: crate::application::storage::PeerMetadataStorage
+//! [`PeerMetadataSnapshot`]: crate::application::types::PeerMetadataSnapshot
+
+use crate::counters;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// The cache of the connected peers that support each protocol list
+pub const SUPPORTED_PEERS_CACHE: &str = "supported_peers";
+/// The cache of the protocols supported by each peer (shared by all the
+/// network clients of the storage)
+pub const PEER_PROTOCOLS_CACHE: &str = "peer_protocols";
+
+/// The stats of a cache, since the node started
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// The number of entries invalidated (or updated) because the underlying
+    /// peer metadata changed
+    pub invalidations: u64,
+    /// The current number of entries
+    pub size: u64,
+}
+
+/// Records the stats of a cache, and exports them as metrics
+#[derive(Debug)]
+pub struct CacheStatsRecorder {
+    cache: &'static str,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+    size: AtomicI64,
+}
+
+impl CacheStatsRecorder {
+    pub fn new(cache: &'static str) -> Self {
+        Self {
+            cache,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+            size: AtomicI64::new(0),
+        }
+    }
+
+    /// Returns the name of the cache
+    pub fn cache(&self) -> &'static str {
+        self.cache
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        counters::cache_operations(self.cache, counters::HIT_LABEL).inc();
+    }
+
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        counters::cache_operations(self.cache, counters::MISS_LABEL).inc();
+    }
+
+    pub fn record_invalidations(&self, num_entries: u64) {
+        if num_entries > 0 {
+            self.invalidations.fetch_add(num_entries, Ordering::Relaxed);
+            counters::cache_operations(self.cache, counters::INVALIDATED_LABEL).inc_by(num_entries);
+        }
+    }
+
+    /// Records that a cache instance grew (or shrank) from `old_len` to
+    /// `new_len` entries. The size is the total over all the instances.
+    pub fn record_resize(&self, old_len: usize, new_len: usize) {
+        if old_len != new_len {
+            let delta = new_len as i64 - old_len as i64;
+            let size = self.size.fetch_add(delta, Ordering::Relaxed) + delta;
+            counters::cache_size(self.cache).set(size);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            size: self.size.load(Ordering::Relaxed).max(0) as u64,
+        }
+    }
+}
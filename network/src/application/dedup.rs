@@ -0,0 +1,158 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deduplication of broadcast messages by digest.
+//!
+//! Gossiping applications (e.g., mempool and consensus) often end up sending
+//! the same message to a peer several times, e.g., when a message is relayed
+//! by multiple neighbours. A [`MessageDeduplicator`] remembers the digests of
+//! the messages recently exchanged with each peer, so that identical messages
+//! within a configurable window can be skipped (outbound) or dropped (inbound).
+
+use aptos_config::network_id::PeerNetworkId;
+use aptos_crypto::HashValue;
+use aptos_infallible::Mutex;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// The number of remembered digests below which expired digests aren't pruned
+const MIN_PRUNE_THRESHOLD: usize = 1024;
+
+/// The digests remembered by a deduplicator
+struct SeenDigests {
+    /// The time each digest was first seen (within the window) for each peer
+    first_seen: HashMap<(PeerNetworkId, HashValue), Instant>,
+    /// The number of remembered digests at which expired digests are pruned
+    prune_threshold: usize,
+}
+
+/// Remembers the digests of the messages exchanged with each peer, to detect
+/// identical messages within a window
+pub struct MessageDeduplicator {
+    window: Duration,
+    time_service: TimeService,
+    seen: Mutex<SeenDigests>,
+}
+
+impl MessageDeduplicator {
+    pub fn new(window: Duration, time_service: TimeService) -> Self {
+        Self {
+            window,
+            time_service,
+            seen: Mutex::new(SeenDigests {
+                first_seen: HashMap::new(),
+                prune_threshold: MIN_PRUNE_THRESHOLD,
+            }),
+        }
+    }
+
+    /// Returns the digest of the given (serialized) message
+    pub fn digest(data: &[u8]) -> HashValue {
+        HashValue::sha3_256_of(data)
+    }
+
+    /// Returns true iff the message with the given digest was recorded for the
+    /// peer within the window
+    pub fn is_duplicate(&self, peer: PeerNetworkId, digest: HashValue) -> bool {
+        let now = self.time_service.now();
+        self.seen
+            .lock()
+            .first_seen
+            .get(&(peer, digest))
+            .map_or(false, |first_seen| {
+                now.saturating_duration_since(*first_seen) < self.window
+            })
+    }
+
+    /// Records that the message with the given digest was exchanged with the
+    /// peer. The window of a duplicate message isn't extended, so identical
+    /// messages are still exchanged once per window.
+    pub fn record(&self, peer: PeerNetworkId, digest: HashValue) {
+        let now = self.time_service.now();
+        let window = self.window;
+        let mut seen = self.seen.lock();
+        let first_seen = seen.first_seen.entry((peer, digest)).or_insert(now);
+        if now.saturating_duration_since(*first_seen) >= window {
+            *first_seen = now;
+        }
+
+        // Prune the expired digests once the number of remembered digests has
+        // doubled since the last pruning, to bound the memory usage
+        if seen.first_seen.len() >= seen.prune_threshold {
+            seen.first_seen
+                .retain(|_, first_seen| now.saturating_duration_since(*first_seen) < window);
+            seen.prune_threshold = MIN_PRUNE_THRESHOLD.max(2 * seen.first_seen.len());
+        }
+    }
+
+    /// Records the message with the given digest, and returns true iff it
+    /// was already recorded for the peer within the window
+    pub fn check_and_record(&self, peer: PeerNetworkId, digest: HashValue) -> bool {
+        if self.is_duplicate(peer, digest) {
+            return true;
+        }
+        self.record(peer, digest);
+        false
+    }
+}
+
+impl fmt::Debug for MessageDeduplicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageDeduplicator")
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aptos_config::network_id::NetworkId;
+    use aptos_types::PeerId;
+
+    #[test]
+    fn duplicates_are_detected_within_the_window() {
+        let time_service = TimeService::mock();
+        let mock_time = time_service.clone().into_mock();
+        let deduplicator = MessageDeduplicator::new(Duration::from_secs(10), time_service);
+        let peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+        let other_peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+        let digest = MessageDeduplicator::digest(b"hello");
+
+        assert!(!deduplicator.check_and_record(peer, digest));
+        assert!(deduplicator.check_and_record(peer, digest));
+
+        // Other peers and messages are unaffected
+        assert!(!deduplicator.check_and_record(other_peer, digest));
+        assert!(!deduplicator.check_and_record(peer, MessageDeduplicator::digest(b"world")));
+
+        // Duplicates don't extend the window
+        mock_time.advance_secs(5);
+        assert!(deduplicator.check_and_record(peer, digest));
+        mock_time.advance_secs(5);
+        assert!(!deduplicator.check_and_record(peer, digest));
+        assert!(deduplicator.is_duplicate(peer, digest));
+    }
+
+    #[test]
+    fn expired_digests_are_pruned() {
+        let time_service = TimeService::mock();
+        let mock_time = time_service.clone().into_mock();
+        let deduplicator = MessageDeduplicator::new(Duration::from_secs(10), time_service);
+        let peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+
+        for i in 0..MIN_PRUNE_THRESHOLD - 1 {
+            deduplicator.record(peer, MessageDeduplicator::digest(&i.to_le_bytes()));
+        }
+        mock_time.advance_secs(10);
+        deduplicator.record(peer, MessageDeduplicator::digest(b"fresh"));
+
+        let seen = deduplicator.seen.lock();
+        assert_eq!(seen.first_seen.len(), 1);
+        assert_eq!(seen.prune_threshold, MIN_PRUNE_THRESHOLD);
+    }
+}
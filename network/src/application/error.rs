@@ -1,29 +1,81 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{error::NetworkError, protocols::network::RpcError};
+use crate::{
+    error::{NetworkError, NetworkErrorKind},
+    protocols::network::RpcError,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+/// The errors returned to network applications. Errors that may succeed when
+/// retried (e.g., with another peer, or after a backoff) are retryable, see
+/// [`Error::is_retryable`].
 #[derive(Clone, Debug, Deserialize, Error, PartialEq, Eq, Serialize)]
 pub enum Error {
+    #[error("Request canceled: {0}")]
+    Canceled(String),
     #[error("Network error encountered: {0}")]
     NetworkError(String),
+    #[error("No common protocol with peer: {0}")]
+    NoCommonProtocol(String),
+    #[error("Not connected to peer: {0}")]
+    NoConnection(String),
+    #[error("Peer disconnected: {0}")]
+    PeerDisconnected(String),
+    #[error("Queue full: {0}")]
+    QueueFull(String),
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
     #[error("Rpc error encountered: {0}")]
     RpcError(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
     #[error("Unexpected error encountered: {0}")]
     UnexpectedError(String),
 }
 
+impl Error {
+    /// Returns true iff the request may succeed if it is retried: the peer
+    /// wasn't (or is no longer) connected, or the request was dropped because
+    /// of timeouts, full queues or rate limits. Other errors (e.g., a missing
+    /// common protocol or a malformed response) are returned again on retries.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NoConnection(_)
+            | Error::PeerDisconnected(_)
+            | Error::QueueFull(_)
+            | Error::RateLimited(_)
+            | Error::Timeout(_) => true,
+            Error::Canceled(_)
+            | Error::NetworkError(_)
+            | Error::NoCommonProtocol(_)
+            | Error::RpcError(_)
+            | Error::UnexpectedError(_) => false,
+        }
+    }
+}
+
 impl From<NetworkError> for Error {
     fn from(error: NetworkError) -> Self {
-        Error::NetworkError(error.to_string())
+        match error.kind() {
+            Some(NetworkErrorKind::NotConnected) => Error::NoConnection(error.to_string()),
+            _ => Error::NetworkError(error.to_string()),
+        }
     }
 }
 
 impl From<RpcError> for Error {
     fn from(error: RpcError) -> Self {
-        Error::RpcError(error.to_string())
+        match error {
+            RpcError::NotConnected(_) => Error::NoConnection(error.to_string()),
+            RpcError::TimedOut | RpcError::Expired => Error::Timeout(error.to_string()),
+            RpcError::TooManyPending(_) | RpcError::Busy => Error::QueueFull(error.to_string()),
+            RpcError::UnexpectedResponseChannelCancel | RpcError::MpscSendError(_) => {
+                Error::PeerDisconnected(error.to_string())
+            },
+            error => Error::RpcError(error.to_string()),
+        }
     }
 }
 
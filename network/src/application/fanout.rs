@@ -0,0 +1,70 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Staggered (epidemic-style) fanout of broadcasts.
+//!
+//! Instead of sending a large message to all peers at once, a broadcast can be
+//! sent to a few peers immediately and to the rest only after a delay. Peers
+//! that acknowledge or relay the message in the meantime (e.g., because they
+//! already received it from one of the first peers) are marked as seen on the
+//! [`FanoutHandle`], and are skipped once the delay has elapsed.
+
+use aptos_config::network_id::PeerNetworkId;
+use aptos_infallible::Mutex;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+/// The policy of a staggered broadcast
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FanoutPolicy {
+    /// The number of peers (in the given order) the message is sent to immediately
+    pub immediate_peers: usize,
+    /// The delay after which the message is sent to the remaining (unseen) peers
+    pub delay: Duration,
+}
+
+impl FanoutPolicy {
+    pub fn new(immediate_peers: usize, delay: Duration) -> Self {
+        Self {
+            immediate_peers,
+            delay,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FanoutState {
+    seen: HashSet<PeerNetworkId>,
+    canceled: bool,
+}
+
+/// A handle to a staggered broadcast, used to prevent the delayed sends
+#[derive(Clone, Debug, Default)]
+pub struct FanoutHandle {
+    state: Arc<Mutex<FanoutState>>,
+}
+
+impl FanoutHandle {
+    /// Marks the message as seen by the peer (e.g., because the peer
+    /// acknowledged or relayed it), so that it isn't sent the message later
+    pub fn mark_seen(&self, peer: PeerNetworkId) {
+        self.state.lock().seen.insert(peer);
+    }
+
+    /// Cancels all delayed sends of the broadcast
+    pub fn cancel(&self) {
+        self.state.lock().canceled = true;
+    }
+
+    /// Returns the given peers that haven't seen the message (none, if the
+    /// broadcast was canceled)
+    pub(crate) fn unseen_peers(&self, peers: Vec<PeerNetworkId>) -> Vec<PeerNetworkId> {
+        let state = self.state.lock();
+        if state.canceled {
+            return vec![];
+        }
+        peers
+            .into_iter()
+            .filter(|peer| !state.seen.contains(peer))
+            .collect()
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Common predicates for [`PeerMetadataStorage::get_peers_matching`].
+//!
+//! Predicates can be combined with [`and`], e.g., to select the connected
+//! upstream peers of the public network:
+//! `and(is_connected(), and(has_role(PeerRole::Upstream), on_network(NetworkId::Public)))`.
+//!
+//! [`PeerMetadataStorage::get_peers_matching`]: crate::application::storage::PeerMetadataStorage::get_peers_matching
+
+use crate::application::types::PeerInfo;
+use aptos_config::{
+    config::PeerRole,
+    network_id::{NetworkId, PeerNetworkId},
+};
+use aptos_netcore::transport::ConnectionOrigin;
+
+/// Matches the peers that are connected
+pub fn is_connected() -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    |_, peer_info| peer_info.is_connected()
+}
+
+/// Matches the peers with the given role
+pub fn has_role(role: PeerRole) -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    move |_, peer_info| peer_info.active_connection.role == role
+}
+
+/// Matches the peers of the given network
+pub fn on_network(network_id: NetworkId) -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    move |peer, _| peer.network_id() == network_id
+}
+
+/// Matches the peers whose connection was established in the given direction
+pub fn with_origin(origin: ConnectionOrigin) -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    move |_, peer_info| peer_info.active_connection.origin == origin
+}
+
+/// Matches the peers that reported a distance from the validators of at most
+/// the given distance
+pub fn within_distance_from_validators(
+    max_distance: u64,
+) -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    move |_, peer_info| {
+        peer_info
+            .monitoring_metadata
+            .distance_from_validators
+            .map_or(false, |distance| distance <= max_distance)
+    }
+}
+
+/// Matches the peers that reported a node version of at least the given
+/// (dot-separated, numeric) version, e.g., "1.2.3". Peers with unknown or
+/// malformed versions never match.
+pub fn with_min_node_version(min_version: &str) -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    let min_version = parse_node_version(min_version);
+    move |_, peer_info| {
+        let version = peer_info
+            .monitoring_metadata
+            .node_version
+            .as_deref()
+            .and_then(parse_node_version);
+        match (&version, &min_version) {
+            (Some(version), Some(min_version)) => version >= min_version,
+            _ => false,
+        }
+    }
+}
+
+/// Matches the peers that match both predicates
+pub fn and(
+    first: impl Fn(&PeerNetworkId, &PeerInfo) -> bool,
+    second: impl Fn(&PeerNetworkId, &PeerInfo) -> bool,
+) -> impl Fn(&PeerNetworkId, &PeerInfo) -> bool {
+    move |peer, peer_info| first(peer, peer_info) && second(peer, peer_info)
+}
+
+/// Parses a dot-separated, numeric version (e.g., "1.2.3") into its components
+fn parse_node_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect()
+}
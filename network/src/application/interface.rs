@@ -2,18 +2,43 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    application::{error::Error, storage::PeerMetadataStorage},
+    application::{
+        dedup::MessageDeduplicator,
+        error::Error,
+        fanout::{FanoutHandle, FanoutPolicy},
+        protocol_cache::{
+            PeerProtocolCache, DEFAULT_PROTOCOL_CACHE_SIZE, DEFAULT_PROTOCOL_CACHE_TTL,
+        },
+        rate_limit::{OutboundRateLimitConfig, OutboundRateLimiter},
+        selection::PeerSelector,
+        storage::PeerMetadataStorage,
+    },
+    counters,
     protocols::{
-        network::{Message, NetworkEvents, NetworkSender},
+        network::{Event, Message, NetworkEvents, NetworkSender},
         wire::handshake::v1::{ProtocolId, ProtocolIdSet},
     },
 };
-use aptos_config::network_id::{NetworkId, PeerNetworkId};
+use aptos_config::{
+    config::ProtocolPreferenceProfile,
+    network_id::{NetworkId, PeerNetworkId},
+};
 use aptos_logger::{prelude::*, sample, sample::SampleRate};
+use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::network_address::NetworkAddress;
 use async_trait::async_trait;
+use futures::{
+    future::join_all,
+    stream::{select_all, FusedStream, Stream, StreamExt},
+};
 use itertools::Itertools;
-use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
 
 /// A simple definition to handle all the trait bounds for messages.
 // TODO: we should remove the duplication across the different files
@@ -39,26 +64,121 @@ pub trait NetworkClientInterface<Message: NetworkMessageTrait>: Clone + Send + S
     // TODO: support disconnect reasons.
     async fn disconnect_from_peer(&self, _peer: PeerNetworkId) -> Result<(), Error>;
 
+    /// Requests that the network connection for the specified peer is
+    /// gracefully disconnected: no new requests are sent to the peer, and the
+    /// connection is closed once the pending ones completed (or the drain
+    /// timeout elapsed).
+    async fn disconnect_from_peer_gracefully(
+        &self,
+        _peer: PeerNetworkId,
+        _reason: String,
+        _drain_timeout: Duration,
+    ) -> Result<(), Error>;
+
+    /// Gracefully disconnects from all peers, on all networks, and stops
+    /// accepting new connections (e.g., before restarting the node).
+    async fn drain_all(&self, _reason: String, _drain_timeout: Duration) -> Result<(), Error>;
+
     /// Returns a handle to the global `PeerMetadataStorage`
     fn get_peer_metadata_storage(&self) -> Arc<PeerMetadataStorage>;
 
+    /// Waits until the outbound queue of the specified peer has space for more
+    /// messages, so that applications can apply backpressure instead of having
+    /// their messages dropped. Returns an error if the peer isn't (or is no
+    /// longer) connected.
+    async fn reserve_send_capacity(&self, _peer: PeerNetworkId) -> Result<(), Error>;
+
     /// Sends the given message to the specified peer. Note: this
     /// method does not guarantee message delivery or handle responses.
+    /// Returns a `RateLimited` error if the outbound rate limit is exceeded.
     fn send_to_peer(&self, _message: Message, _peer: PeerNetworkId) -> Result<(), Error>;
 
+    /// Sends the given message to the specified peer, and waits for the peer to
+    /// acknowledge that the message was enqueued for its application (or for
+    /// the timeout to elapse). Fails if the peer doesn't support direct-send
+    /// acks. Returns a `RateLimited` error if the outbound rate limit is
+    /// exceeded.
+    async fn send_to_peer_with_ack(
+        &self,
+        _message: Message,
+        _ack_timeout: Duration,
+        _peer: PeerNetworkId,
+    ) -> Result<(), Error>;
+
     /// Sends the given message to each peer in the specified peer list.
     /// Note: this method does not guarantee message delivery or handle responses.
+    /// Peers that exceed the outbound rate limit are skipped, and a
+    /// `RateLimited` error is returned once the message is sent to the rest.
     fn send_to_peers(&self, _message: Message, _peers: &[PeerNetworkId]) -> Result<(), Error>;
 
+    /// Sends the given message to the peers in the specified peer list with a
+    /// staggered fanout: the message is sent to the first `immediate_peers`
+    /// peers right away, and to the rest once the policy's delay has elapsed.
+    /// Peers marked as seen on the returned handle in the meantime are skipped.
+    /// Errors of the delayed sends are logged. Must be called from within a
+    /// tokio runtime.
+    fn send_to_peers_with_fanout(
+        &self,
+        _message: Message,
+        _peers: &[PeerNetworkId],
+        _fanout_policy: FanoutPolicy,
+    ) -> Result<FanoutHandle, Error>;
+
     /// Sends the given message to the specified peer with the corresponding
     /// timeout. Awaits a response from the peer, or hits the timeout
-    /// (whichever occurs first).
+    /// (whichever occurs first). If the outbound rate limit is exceeded,
+    /// this waits for a permit before sending.
     async fn send_to_peer_rpc(
         &self,
         _message: Message,
         _rpc_timeout: Duration,
         _peer: PeerNetworkId,
     ) -> Result<Message, Error>;
+
+    /// Sends the given message to the specified peer, like `send_to_peer_rpc`,
+    /// but stops waiting for the response once the given token is canceled
+    /// (e.g., because the response was already received from another peer).
+    /// Canceling frees the outbound RPC slot immediately and returns a
+    /// `Canceled` error.
+    async fn send_to_peer_rpc_with_cancellation(
+        &self,
+        _message: Message,
+        _rpc_timeout: Duration,
+        _peer: PeerNetworkId,
+        _cancellation: CancellationToken,
+    ) -> Result<Message, Error>;
+
+    /// Returns the connected peers that support at least one of the
+    /// protocols of this client, ranked by the given peer selector (from the
+    /// most to the least preferred).
+    fn get_available_peers_ranked(&self, _peer_selector: &dyn PeerSelector) -> Vec<PeerNetworkId>;
+
+    /// Sends the given message with the specified protocol to a connected
+    /// peer chosen by the given peer selector. If the RPC fails (e.g., it times out
+    /// or the peer disconnects), it is retried against a different peer, up
+    /// to `max_attempts` attempts in total. Returns the peer that responded,
+    /// together with its response, or the error of the last attempt.
+    async fn send_rpc_with_retries(
+        &self,
+        _message: Message,
+        _protocol_id: ProtocolId,
+        _rpc_timeout: Duration,
+        _peer_selector: &dyn PeerSelector,
+        _max_attempts: usize,
+    ) -> Result<(PeerNetworkId, Message), Error>;
+
+    /// Sends the given message to each (distinct) peer in the specified peer
+    /// list concurrently, with the same timeout for every RPC. Awaits all
+    /// responses (or timeouts) and returns the result for each peer, in the
+    /// order of their first occurrence in the list.
+    /// Note: failures are reported per peer, so a partial failure does not
+    /// affect the results of the other peers.
+    async fn send_to_peers_rpc(
+        &self,
+        _message: Message,
+        _rpc_timeout: Duration,
+        _peers: &[PeerNetworkId],
+    ) -> Vec<(PeerNetworkId, Result<Message, Error>)>;
 }
 
 /// A network component that can be used by client applications (e.g., consensus,
@@ -69,6 +189,11 @@ pub struct NetworkClient<Message> {
     rpc_protocols_and_preferences: Vec<ProtocolId>, // Protocols are sorted by preference (highest to lowest)
     network_senders: HashMap<NetworkId, NetworkSender<Message>>,
     peer_metadata_storage: Arc<PeerMetadataStorage>,
+    outbound_rate_limiter: Arc<OutboundRateLimiter>,
+    broadcast_deduplicator: Option<Arc<MessageDeduplicator>>,
+    protocol_cache: Arc<PeerProtocolCache>,
+    protocol_preference_profiles: HashMap<NetworkId, ProtocolPreferenceProfile>,
+    time_service: TimeService,
 }
 
 impl<Message: NetworkMessageTrait + Clone> NetworkClient<Message> {
@@ -78,14 +203,59 @@ impl<Message: NetworkMessageTrait + Clone> NetworkClient<Message> {
         network_senders: HashMap<NetworkId, NetworkSender<Message>>,
         peer_metadata_storage: Arc<PeerMetadataStorage>,
     ) -> Self {
+        let time_service = TimeService::real();
         Self {
             direct_send_protocols_and_preferences,
             rpc_protocols_and_preferences,
             network_senders,
+            protocol_cache: new_protocol_cache(&peer_metadata_storage, time_service.clone()),
             peer_metadata_storage,
+            outbound_rate_limiter: Arc::new(OutboundRateLimiter::default()),
+            broadcast_deduplicator: None,
+            protocol_preference_profiles: HashMap::new(),
+            time_service,
         }
     }
 
+    /// Replaces the time service used to schedule delayed sends and to expire
+    /// the cached peer protocols (e.g., with a mock time service in tests)
+    pub fn with_time_service(mut self, time_service: TimeService) -> Self {
+        self.protocol_cache = new_protocol_cache(&self.peer_metadata_storage, time_service.clone());
+        self.time_service = time_service;
+        self
+    }
+
+    /// Enforces the given outbound rate limits (per protocol) on all messages
+    /// sent by this client. Protocols without a limit are not limited.
+    pub fn with_outbound_rate_limits(
+        mut self,
+        rate_limits: HashMap<ProtocolId, OutboundRateLimitConfig>,
+    ) -> Self {
+        self.outbound_rate_limiter = Arc::new(OutboundRateLimiter::new(rate_limits));
+        self
+    }
+
+    /// Skips re-sending identical messages to the same peer within the given
+    /// window when broadcasting with `send_to_peers`. Messages sent to a single
+    /// peer with `send_to_peer` are never skipped.
+    pub fn with_broadcast_dedup(mut self, window: Duration, time_service: TimeService) -> Self {
+        self.broadcast_deduplicator =
+            Some(Arc::new(MessageDeduplicator::new(window, time_service)));
+        self
+    }
+
+    /// Reorders the protocols of this client by the given profile of each
+    /// network when picking one for a peer of the network (e.g., compressed
+    /// protocols first on public networks). Networks without a profile use the
+    /// order of the client's protocols.
+    pub fn with_protocol_preference_profiles(
+        mut self,
+        profiles: HashMap<NetworkId, ProtocolPreferenceProfile>,
+    ) -> Self {
+        self.protocol_preference_profiles = profiles;
+        self
+    }
+
     /// Returns the network sender for the specified network ID
     fn get_sender_for_network_id(
         &self,
@@ -98,34 +268,90 @@ impl<Message: NetworkMessageTrait + Clone> NetworkClient<Message> {
 
     /// Identify the supported protocols from the specified peer's connection
     fn get_supported_protocols(&self, peer: &PeerNetworkId) -> Result<ProtocolIdSet, Error> {
-        let peer_metadata_storage = self.get_peer_metadata_storage();
-        peer_metadata_storage
-            .read(*peer)
-            .map(|peer_info| peer_info.active_connection.application_protocols)
-            .ok_or_else(|| {
-                Error::UnexpectedError(format!("Peer info not found for peer: {:?}", peer))
-            })
+        self.protocol_cache
+            .get_supported_protocols(peer)
+            .ok_or_else(|| Error::NoConnection(format!("Peer info not found for peer: {:?}", peer)))
     }
 
     /// Selects the preferred protocol for the specified peer. The preferred protocols
-    /// should be sorted from most to least preferable.
+    /// should be sorted from most to least preferable, and are reordered by the
+    /// preference profile of the peer's network (if any).
     fn get_preferred_protocol_for_peer(
         &self,
         peer: &PeerNetworkId,
         preferred_protocols: &[ProtocolId],
     ) -> Result<ProtocolId, Error> {
         let protocols_supported_by_peer = self.get_supported_protocols(peer)?;
-        for protocol in preferred_protocols {
-            if protocols_supported_by_peer.contains(*protocol) {
-                return Ok(*protocol);
-            }
+        let profile = self
+            .protocol_preference_profiles
+            .get(&peer.network_id())
+            .copied()
+            .unwrap_or_default();
+        let preferred_protocol = preferred_protocols
+            .iter()
+            .enumerate()
+            .filter(|(_, protocol)| protocols_supported_by_peer.contains(**protocol))
+            .min_by_key(|(index, protocol)| (protocol.preference_rank(profile), *index));
+        if let Some((_, protocol)) = preferred_protocol {
+            return Ok(*protocol);
         }
-        Err(Error::NetworkError(format!(
+        Err(Error::NoCommonProtocol(format!(
             "None of the preferred protocols are supported by this peer! \
             Peer: {:?}, supported protocols: {:?}",
             peer, protocols_supported_by_peer
         )))
     }
+
+    /// Returns the connected peers (across the networks of this client)
+    /// that support at least one of the given protocols
+    fn get_connected_peers_with_protocols(&self, protocols: &[ProtocolId]) -> Vec<PeerNetworkId> {
+        self.network_senders
+            .keys()
+            .flat_map(|network_id| {
+                self.peer_metadata_storage
+                    .get_connected_supported_peers(*network_id, protocols)
+            })
+            .collect()
+    }
+
+    /// Sends the given rpc to the specified peer using the given protocol
+    async fn send_rpc_with_protocol(
+        &self,
+        message: Message,
+        protocol_id: ProtocolId,
+        rpc_timeout: Duration,
+        peer: PeerNetworkId,
+    ) -> Result<Message, Error> {
+        let network_sender = self.get_sender_for_network_id(&peer.network_id())?;
+        self.outbound_rate_limiter
+            .acquire(protocol_id, &peer)
+            .await?;
+        let start_time = Instant::now();
+        let result = network_sender
+            .send_rpc(peer.peer_id(), protocol_id, message, rpc_timeout)
+            .await;
+        let latency = result
+            .as_ref()
+            .ok()
+            .map(|_| start_time.elapsed().as_secs_f64());
+        counters::application_rpc_completed(peer.network_id(), protocol_id, latency);
+        self.peer_metadata_storage
+            .record_rpc_result(&peer, latency.is_some());
+        Ok(result?)
+    }
+}
+
+/// Creates the cache of the protocols of the peers in the given storage
+fn new_protocol_cache(
+    peer_metadata_storage: &Arc<PeerMetadataStorage>,
+    time_service: TimeService,
+) -> Arc<PeerProtocolCache> {
+    Arc::new(PeerProtocolCache::new(
+        peer_metadata_storage.clone(),
+        DEFAULT_PROTOCOL_CACHE_SIZE,
+        DEFAULT_PROTOCOL_CACHE_TTL,
+        time_service,
+    ))
 }
 
 #[async_trait]
@@ -142,29 +368,129 @@ impl<Message: NetworkMessageTrait> NetworkClientInterface<Message> for NetworkCl
         Ok(network_sender.disconnect_peer(peer.peer_id()).await?)
     }
 
+    async fn disconnect_from_peer_gracefully(
+        &self,
+        peer: PeerNetworkId,
+        reason: String,
+        drain_timeout: Duration,
+    ) -> Result<(), Error> {
+        let network_sender = self.get_sender_for_network_id(&peer.network_id())?;
+        Ok(network_sender
+            .disconnect_peer_gracefully(peer.peer_id(), reason, drain_timeout)
+            .await?)
+    }
+
+    async fn drain_all(&self, reason: String, drain_timeout: Duration) -> Result<(), Error> {
+        let results = join_all(
+            self.network_senders
+                .values()
+                .map(|network_sender| network_sender.drain_all(reason.clone(), drain_timeout)),
+        )
+        .await;
+        for result in results {
+            result?;
+        }
+        Ok(())
+    }
+
     fn get_peer_metadata_storage(&self) -> Arc<PeerMetadataStorage> {
         self.peer_metadata_storage.clone()
     }
 
+    async fn reserve_send_capacity(&self, peer: PeerNetworkId) -> Result<(), Error> {
+        let outbound_queue = self
+            .peer_metadata_storage
+            .get_outbound_queue(&peer)
+            .ok_or_else(|| Error::NoConnection(format!("Peer {} is not connected", peer)))?;
+        outbound_queue.wait_for_capacity().await;
+        if outbound_queue.is_closed() {
+            return Err(Error::PeerDisconnected(format!(
+                "Peer {} disconnected",
+                peer
+            )));
+        }
+        Ok(())
+    }
+
     fn send_to_peer(&self, message: Message, peer: PeerNetworkId) -> Result<(), Error> {
         let network_sender = self.get_sender_for_network_id(&peer.network_id())?;
         let direct_send_protocol_id = self
             .get_preferred_protocol_for_peer(&peer, &self.direct_send_protocols_and_preferences)?;
+        self.outbound_rate_limiter
+            .try_acquire(direct_send_protocol_id, &peer)?;
         Ok(network_sender.send_to(peer.peer_id(), direct_send_protocol_id, message)?)
     }
 
+    async fn send_to_peer_with_ack(
+        &self,
+        message: Message,
+        ack_timeout: Duration,
+        peer: PeerNetworkId,
+    ) -> Result<(), Error> {
+        let network_sender = self.get_sender_for_network_id(&peer.network_id())?;
+        let direct_send_protocol_id = self
+            .get_preferred_protocol_for_peer(&peer, &self.direct_send_protocols_and_preferences)?;
+        self.outbound_rate_limiter
+            .try_acquire(direct_send_protocol_id, &peer)?;
+        Ok(network_sender
+            .send_to_with_ack(
+                peer.peer_id(),
+                direct_send_protocol_id,
+                message,
+                ack_timeout,
+            )
+            .await?)
+    }
+
     fn send_to_peers(&self, message: Message, peers: &[PeerNetworkId]) -> Result<(), Error> {
+        // Identify the message by its digest, if broadcasts are deduplicated
+        let deduplication = match &self.broadcast_deduplicator {
+            Some(deduplicator) => {
+                let data = bcs::to_bytes(&message).map_err(|error| {
+                    Error::UnexpectedError(format!("Failed to serialize message: {}", error))
+                })?;
+                Some((deduplicator, MessageDeduplicator::digest(&data)))
+            },
+            None => None,
+        };
+
         // Sort peers by protocol
         let mut peers_per_protocol = HashMap::new();
         let mut peers_without_a_protocol = vec![];
+        let mut rate_limited_peers = vec![];
         for peer in peers {
             match self
                 .get_preferred_protocol_for_peer(peer, &self.direct_send_protocols_and_preferences)
             {
-                Ok(protocol) => peers_per_protocol
-                    .entry(protocol)
-                    .or_insert_with(Vec::new)
-                    .push(peer),
+                Ok(protocol) => {
+                    // Skip peers that were recently sent the same message
+                    if let Some((deduplicator, digest)) = &deduplication {
+                        if deduplicator.is_duplicate(*peer, *digest) {
+                            counters::deduplicated_messages(
+                                peer.network_id(),
+                                protocol,
+                                counters::OUTBOUND_LABEL,
+                            )
+                            .inc();
+                            continue;
+                        }
+                    }
+                    if self
+                        .outbound_rate_limiter
+                        .try_acquire(protocol, peer)
+                        .is_err()
+                    {
+                        rate_limited_peers.push(peer);
+                        continue;
+                    }
+                    if let Some((deduplicator, digest)) = &deduplication {
+                        deduplicator.record(*peer, *digest);
+                    }
+                    peers_per_protocol
+                        .entry(protocol)
+                        .or_insert_with(Vec::new)
+                        .push(peer)
+                },
                 Err(_) => peers_without_a_protocol.push(peer),
             }
         }
@@ -191,21 +517,151 @@ impl<Message: NetworkMessageTrait> NetworkClientInterface<Message> for NetworkCl
                 network_sender.send_to_many(peer_ids, protocol_id, message.clone())?;
             }
         }
+
+        if !rate_limited_peers.is_empty() {
+            return Err(Error::RateLimited(format!(
+                "Outbound rate limit exceeded for peers: {:?}",
+                rate_limited_peers
+            )));
+        }
         Ok(())
     }
 
+    fn send_to_peers_with_fanout(
+        &self,
+        message: Message,
+        peers: &[PeerNetworkId],
+        fanout_policy: FanoutPolicy,
+    ) -> Result<FanoutHandle, Error> {
+        let fanout_handle = FanoutHandle::default();
+        let num_immediate_peers = fanout_policy.immediate_peers.min(peers.len());
+        let (immediate_peers, delayed_peers) = peers.split_at(num_immediate_peers);
+        if delayed_peers.is_empty() {
+            self.send_to_peers(message, immediate_peers)?;
+            return Ok(fanout_handle);
+        }
+
+        // Schedule the delayed sends before sending to the first peers (so that
+        // the delay isn't affected by how long the immediate sends take)
+        let delay = self.time_service.sleep(fanout_policy.delay);
+        let network_client = self.clone();
+        let delayed_message = message.clone();
+        let delayed_peers = delayed_peers.to_vec();
+        let delayed_fanout_handle = fanout_handle.clone();
+        tokio::spawn(async move {
+            delay.await;
+            let unseen_peers = delayed_fanout_handle.unseen_peers(delayed_peers);
+            if unseen_peers.is_empty() {
+                return;
+            }
+            if let Err(error) = network_client.send_to_peers(delayed_message, &unseen_peers) {
+                warn!(
+                    "Failed to send a delayed broadcast to peers: {:?}. Error: {:?}",
+                    unseen_peers, error
+                );
+            }
+        });
+
+        self.send_to_peers(message, immediate_peers)?;
+        Ok(fanout_handle)
+    }
+
     async fn send_to_peer_rpc(
         &self,
         message: Message,
         rpc_timeout: Duration,
         peer: PeerNetworkId,
     ) -> Result<Message, Error> {
-        let network_sender = self.get_sender_for_network_id(&peer.network_id())?;
         let rpc_protocol_id =
             self.get_preferred_protocol_for_peer(&peer, &self.rpc_protocols_and_preferences)?;
-        Ok(network_sender
-            .send_rpc(peer.peer_id(), rpc_protocol_id, message, rpc_timeout)
-            .await?)
+        self.send_rpc_with_protocol(message, rpc_protocol_id, rpc_timeout, peer)
+            .await
+    }
+
+    async fn send_to_peer_rpc_with_cancellation(
+        &self,
+        message: Message,
+        rpc_timeout: Duration,
+        peer: PeerNetworkId,
+        cancellation: CancellationToken,
+    ) -> Result<Message, Error> {
+        // Dropping the rpc future drops the response channel, which the peer
+        // notices and uses to clean up the pending request.
+        tokio::select! {
+            result = self.send_to_peer_rpc(message, rpc_timeout, peer) => result,
+            _ = cancellation.cancelled() => Err(Error::Canceled(format!(
+                "Rpc to peer {:?} was canceled by the application",
+                peer
+            ))),
+        }
+    }
+
+    fn get_available_peers_ranked(&self, peer_selector: &dyn PeerSelector) -> Vec<PeerNetworkId> {
+        let protocols: Vec<_> = self
+            .direct_send_protocols_and_preferences
+            .iter()
+            .chain(self.rpc_protocols_and_preferences.iter())
+            .copied()
+            .collect();
+        let peers = self.get_connected_peers_with_protocols(&protocols);
+        peer_selector.rank_peers(peers, &self.peer_metadata_storage)
+    }
+
+    async fn send_rpc_with_retries(
+        &self,
+        message: Message,
+        protocol_id: ProtocolId,
+        rpc_timeout: Duration,
+        peer_selector: &dyn PeerSelector,
+        max_attempts: usize,
+    ) -> Result<(PeerNetworkId, Message), Error> {
+        let mut last_error = Error::NoConnection(format!(
+            "No connected peers support the protocol: {:?}",
+            protocol_id
+        ));
+        let peers = peer_selector.rank_peers(
+            self.get_connected_peers_with_protocols(&[protocol_id]),
+            &self.peer_metadata_storage,
+        );
+        for peer in peers.into_iter().take(max_attempts) {
+            match self
+                .send_rpc_with_protocol(message.clone(), protocol_id, rpc_timeout, peer)
+                .await
+            {
+                Ok(response) => return Ok((peer, response)),
+                // Timeouts and disconnects are worth retrying on another peer
+                Err(error) if error.is_retryable() => {
+                    sample!(
+                        SampleRate::Duration(Duration::from_secs(10)),
+                        warn!(
+                            "Rpc to peer {:?} failed, retrying on another peer. Error: {:?}",
+                            peer, error
+                        )
+                    );
+                    last_error = error;
+                },
+                Err(error) => return Err(error),
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn send_to_peers_rpc(
+        &self,
+        message: Message,
+        rpc_timeout: Duration,
+        peers: &[PeerNetworkId],
+    ) -> Vec<(PeerNetworkId, Result<Message, Error>)> {
+        let mut distinct_peers = HashSet::new();
+        let peers = peers.iter().filter(|peer| distinct_peers.insert(**peer));
+        let rpc_futures = peers.map(|peer| {
+            let message = message.clone();
+            async move {
+                let result = self.send_to_peer_rpc(message, rpc_timeout, *peer).await;
+                (*peer, result)
+            }
+        });
+        join_all(rpc_futures).await
     }
 }
 
@@ -224,4 +680,20 @@ impl<Message> NetworkServiceEvents<Message> {
     pub fn into_network_and_events(self) -> HashMap<NetworkId, NetworkEvents<Message>> {
         self.network_and_events
     }
+
+    /// Consumes and returns a single stream of the events of all networks,
+    /// each tagged with the network it was received on. The networks are
+    /// polled in turn, so a busy network can't starve the others.
+    pub fn into_unified_stream(
+        self,
+    ) -> impl Stream<Item = (NetworkId, Event<Message>)> + FusedStream
+    where
+        Message: Unpin,
+    {
+        select_all(
+            self.network_and_events
+                .into_iter()
+                .map(|(network_id, events)| events.map(move |event| (network_id, event))),
+        )
+    }
 }
@@ -0,0 +1,69 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Garbage collection of the metadata of disconnected peers.
+//!
+//! The [`PeerMetadataStorage`] keeps some metadata (e.g., scores and dial
+//! backoffs) of peers after they disconnect, so that it survives reconnections.
+//! On long-running public full nodes, which see many short-lived peers, this
+//! metadata would grow without bound. The [`PeerMetadataGc`] actor periodically
+//! collects the metadata of the peers disconnected for longer than the
+//! retention, and bounds the number of tracked peers of its network.
+
+use crate::{application::storage::PeerMetadataStorage, logging::NetworkSchema};
+use aptos_config::{config::PeerMetadataGcConfig, network_id::NetworkContext};
+use aptos_logger::prelude::*;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use futures::StreamExt;
+use std::{sync::Arc, time::Duration};
+use tokio::runtime::Handle;
+
+/// Periodically collects the metadata of the long-disconnected peers of a network
+pub struct PeerMetadataGc {
+    network_context: NetworkContext,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    config: PeerMetadataGcConfig,
+    time_service: TimeService,
+}
+
+impl PeerMetadataGc {
+    pub fn new(
+        network_context: NetworkContext,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+        config: PeerMetadataGcConfig,
+        time_service: TimeService,
+    ) -> Self {
+        Self {
+            network_context,
+            peer_metadata_storage,
+            config,
+            time_service,
+        }
+    }
+
+    pub fn start(self, executor: &Handle) {
+        spawn_named!("[Network] PeerMetadataGc", executor, self.run());
+    }
+
+    async fn run(self) {
+        let ticker = self
+            .time_service
+            .interval(Duration::from_secs(self.config.interval_secs));
+        tokio::pin!(ticker);
+        while ticker.next().await.is_some() {
+            let num_collected = self.peer_metadata_storage.garbage_collect(
+                self.network_context.network_id(),
+                self.config.max_tracked_peers,
+                Duration::from_secs(self.config.retention_secs),
+            );
+            if num_collected > 0 {
+                debug!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Collected the metadata of {} disconnected peers",
+                    self.network_context,
+                    num_collected
+                );
+            }
+        }
+    }
+}
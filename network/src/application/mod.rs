@@ -1,8 +1,21 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod blocking;
+pub mod cache_stats;
+pub mod dedup;
 pub mod error;
+pub mod fanout;
+pub mod filters;
 pub mod interface;
+pub mod metadata_gc;
+pub mod peer_report;
+pub mod persistence;
+pub mod protocol_cache;
+pub mod rate_limit;
+pub mod response_cache;
+pub mod scoring;
+pub mod selection;
 pub mod storage;
 pub mod types;
 
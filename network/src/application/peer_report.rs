@@ -0,0 +1,48 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Periodic per-peer reports of the network health.
+//!
+//! The peer reporter assembles a [`PeerReport`] of every connected peer (its
+//! traffic, rpc success rate, score and latency) from the
+//! [`PeerMetadataStorage`] on an interval, and publishes the latest reports
+//! process-wide. Like the network counters, the reports are read (and pushed)
+//! by the telemetry service, so the network layer doesn't depend on it.
+
+use crate::application::{storage::PeerMetadataStorage, types::PeerReport};
+use aptos_infallible::RwLock;
+use aptos_logger::prelude::*;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use std::{sync::Arc, time::Duration};
+use tokio::runtime::Handle;
+
+/// The default interval at which peer reports are assembled
+pub const PEER_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The latest peer reports of this node
+static LATEST_PEER_REPORTS: Lazy<RwLock<Vec<PeerReport>>> = Lazy::new(|| RwLock::new(vec![]));
+
+/// Returns the latest peer reports, or no reports if the reporter isn't running
+pub fn latest_peer_reports() -> Vec<PeerReport> {
+    LATEST_PEER_REPORTS.read().clone()
+}
+
+/// Spawns the task assembling the reports of the peers in the given storage on
+/// every interval
+pub fn start_peer_reporter(
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    time_service: TimeService,
+    interval: Duration,
+    executor: &Handle,
+) {
+    executor.spawn(async move {
+        let mut ticker = time_service.interval(interval);
+        while ticker.next().await.is_some() {
+            let peer_reports = peer_metadata_storage.peer_reports();
+            debug!("Assembled the reports of {} peers", peer_reports.len());
+            *LATEST_PEER_REPORTS.write() = peer_reports;
+        }
+    });
+}
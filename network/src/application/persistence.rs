@@ -0,0 +1,330 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Persistence of the known peers of a network across restarts.
+//!
+//! The [`PeerPersistence`] actor periodically writes the dialable addresses,
+//! roles and scores of the connected peers to a file. On startup, the peers in
+//! the file that were seen recently enough are handed to the connectivity
+//! manager (as the lowest priority discovery source) and their scores are
+//! restored, so that public full nodes don't have to rediscover the network
+//! from the seeds after every restart.
+
+use crate::{
+    application::storage::PeerMetadataStorage,
+    connectivity_manager::{ConnectivityRequest, DiscoverySource},
+    logging::NetworkSchema,
+};
+use aptos_config::{
+    config::{Peer, PeerPersistenceConfig, PeerRole, PeerSet},
+    network_id::{NetworkContext, PeerNetworkId},
+};
+use aptos_logger::prelude::*;
+use aptos_netcore::transport::ConnectionOrigin;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use aptos_types::{network_address::NetworkAddress, PeerId};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::runtime::Handle;
+
+/// A known peer, as persisted across restarts
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PersistedPeer {
+    /// The addresses the peer was last successfully dialed on
+    pub addresses: Vec<NetworkAddress>,
+    pub role: PeerRole,
+    pub score: f64,
+    /// The unix time (in seconds) the peer was last connected
+    pub last_seen_secs: u64,
+}
+
+pub type PersistedPeers = BTreeMap<PeerId, PersistedPeer>;
+
+/// Loads the persisted peers from the given file
+pub fn load_peers(path: &Path) -> io::Result<PersistedPeers> {
+    let contents = std::fs::read_to_string(path)?;
+    serde_json::from_str(&contents)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Writes the peers to the given file. The file is replaced atomically, so
+/// that a crash while writing doesn't lose the previously persisted peers.
+pub fn save_peers(path: &Path, peers: &PersistedPeers) -> io::Result<()> {
+    let contents = serde_json::to_vec_pretty(peers)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path)
+}
+
+/// Periodically persists the known peers of a network, and restores them on startup
+pub struct PeerPersistence {
+    network_context: NetworkContext,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    config: PeerPersistenceConfig,
+    conn_mgr_reqs_tx: aptos_channels::Sender<ConnectivityRequest>,
+    time_service: TimeService,
+    known_peers: PersistedPeers,
+}
+
+impl PeerPersistence {
+    pub fn new(
+        network_context: NetworkContext,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+        config: PeerPersistenceConfig,
+        conn_mgr_reqs_tx: aptos_channels::Sender<ConnectivityRequest>,
+        time_service: TimeService,
+    ) -> Self {
+        Self {
+            network_context,
+            peer_metadata_storage,
+            config,
+            conn_mgr_reqs_tx,
+            time_service,
+            known_peers: PersistedPeers::new(),
+        }
+    }
+
+    pub fn start(self, executor: &Handle) {
+        spawn_named!("[Network] PeerPersistence", executor, self.run());
+    }
+
+    async fn run(mut self) {
+        self.restore();
+
+        let ticker = self
+            .time_service
+            .interval(Duration::from_secs(self.config.interval_secs));
+        tokio::pin!(ticker);
+        while ticker.next().await.is_some() {
+            self.update_known_peers();
+            self.persist();
+        }
+    }
+
+    fn now_secs(&self) -> u64 {
+        self.time_service.now_unix_time().as_secs()
+    }
+
+    /// Loads the persisted peers, drops the stale ones and hands the rest to
+    /// the connectivity manager
+    fn restore(&mut self) {
+        let known_peers = match load_peers(&self.config.path) {
+            Ok(known_peers) => known_peers,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return,
+            Err(error) => {
+                warn!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Failed to load the persisted peers from {:?}: {}",
+                    self.network_context,
+                    self.config.path,
+                    error
+                );
+                return;
+            },
+        };
+
+        let now_secs = self.now_secs();
+        let max_age_secs = self.config.max_age_secs;
+        self.known_peers = known_peers
+            .into_iter()
+            .filter(|(_, peer)| now_secs.saturating_sub(peer.last_seen_secs) <= max_age_secs)
+            .collect();
+
+        let network_id = self.network_context.network_id();
+        let mut peer_set = PeerSet::new();
+        for (peer_id, peer) in &self.known_peers {
+            self.peer_metadata_storage
+                .restore_peer_score(PeerNetworkId::new(network_id, *peer_id), peer.score);
+            peer_set.insert(
+                *peer_id,
+                Peer::new(peer.addresses.clone(), HashSet::new(), peer.role),
+            );
+        }
+
+        info!(
+            NetworkSchema::new(&self.network_context),
+            "{} Restored {} persisted peers",
+            self.network_context,
+            peer_set.len()
+        );
+        let request =
+            ConnectivityRequest::UpdateDiscoveredPeers(DiscoverySource::Persisted, peer_set);
+        if let Err(error) = self.conn_mgr_reqs_tx.try_send(request) {
+            warn!(
+                NetworkSchema::new(&self.network_context),
+                "{} Failed to send the persisted peers {:?}", self.network_context, error
+            );
+        }
+    }
+
+    /// Merges the currently connected peers into the known peers. Only the
+    /// addresses of outbound connections are known to be dialable, so inbound
+    /// peers only refresh existing entries.
+    fn update_known_peers(&mut self) {
+        let now_secs = self.now_secs();
+        let network_id = self.network_context.network_id();
+        for (peer_network_id, peer_info) in self.peer_metadata_storage.read_all(network_id) {
+            if !peer_info.is_connected() {
+                continue;
+            }
+            let metadata = peer_info.active_connection;
+            let score = self.peer_metadata_storage.get_peer_score(&peer_network_id);
+            let peer_id = peer_network_id.peer_id();
+            if metadata.origin == ConnectionOrigin::Outbound {
+                self.known_peers.insert(peer_id, PersistedPeer {
+                    addresses: vec![metadata.addr],
+                    role: metadata.role,
+                    score,
+                    last_seen_secs: now_secs,
+                });
+            } else if let Some(peer) = self.known_peers.get_mut(&peer_id) {
+                peer.score = score;
+                peer.last_seen_secs = now_secs;
+            }
+        }
+
+        let max_age_secs = self.config.max_age_secs;
+        self.known_peers
+            .retain(|_, peer| now_secs.saturating_sub(peer.last_seen_secs) <= max_age_secs);
+    }
+
+    fn persist(&self) {
+        if let Err(error) = save_peers(&self.config.path, &self.known_peers) {
+            warn!(
+                NetworkSchema::new(&self.network_context),
+                "{} Failed to persist the known peers to {:?}: {}",
+                self.network_context,
+                self.config.path,
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{application::types::PeerInfo, counters, transport::ConnectionMetadata};
+    use aptos_config::network_id::NetworkId;
+    use aptos_temppath::TempPath;
+    use aptos_time_service::MockTimeService;
+
+    fn create_persistence(
+        path: &Path,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+    ) -> (
+        PeerPersistence,
+        aptos_channels::Receiver<ConnectivityRequest>,
+        MockTimeService,
+    ) {
+        let (conn_mgr_reqs_tx, conn_mgr_reqs_rx) =
+            aptos_channels::new(1, &counters::PENDING_CONNECTIVITY_MANAGER_REQUESTS);
+        let time_service = TimeService::mock();
+        let persistence = PeerPersistence::new(
+            NetworkContext::mock(),
+            peer_metadata_storage,
+            PeerPersistenceConfig {
+                path: path.to_path_buf(),
+                interval_secs: 60,
+                max_age_secs: 3600,
+            },
+            conn_mgr_reqs_tx,
+            time_service.clone(),
+        );
+        (persistence, conn_mgr_reqs_rx, time_service.into_mock())
+    }
+
+    #[test]
+    fn persisted_peers_are_restored() {
+        let path = TempPath::new();
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let (mut persistence, _, _) =
+            create_persistence(path.path(), peer_metadata_storage.clone());
+
+        // Connect to a peer and persist it
+        let peer_id = PeerId::random();
+        let metadata = ConnectionMetadata::mock_with_role_and_origin(
+            peer_id,
+            PeerRole::Validator,
+            ConnectionOrigin::Outbound,
+        );
+        let peer_network_id = PeerNetworkId::new(NetworkId::Validator, peer_id);
+        peer_metadata_storage.insert(peer_network_id, PeerInfo::new(metadata.clone()));
+        persistence.update_known_peers();
+        persistence.persist();
+
+        let persisted_peers = load_peers(path.path()).unwrap();
+        assert_eq!(persisted_peers.get(&peer_id).unwrap().addresses, vec![
+            metadata.addr.clone()
+        ]);
+
+        // A restarted node hands the peer to the connectivity manager
+        let (mut restarted, mut conn_mgr_reqs_rx, _) =
+            create_persistence(path.path(), PeerMetadataStorage::test());
+        restarted.restore();
+        assert_eq!(restarted.known_peers, persisted_peers);
+        match conn_mgr_reqs_rx.try_next().unwrap().unwrap() {
+            ConnectivityRequest::UpdateDiscoveredPeers(DiscoverySource::Persisted, peers) => {
+                let peer = peers.get(&peer_id).unwrap();
+                assert_eq!(peer.addresses, vec![metadata.addr]);
+                assert_eq!(peer.role, PeerRole::Validator);
+            },
+            request => panic!("Unexpected request: {:?}", request),
+        }
+    }
+
+    #[test]
+    fn inbound_peers_are_not_persisted() {
+        let path = TempPath::new();
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let (mut persistence, _, _) =
+            create_persistence(path.path(), peer_metadata_storage.clone());
+
+        let peer_id = PeerId::random();
+        peer_metadata_storage.insert(
+            PeerNetworkId::new(NetworkId::Validator, peer_id),
+            PeerInfo::new(ConnectionMetadata::mock(peer_id)),
+        );
+        persistence.update_known_peers();
+        assert!(persistence.known_peers.is_empty());
+    }
+
+    #[test]
+    fn stale_peers_are_dropped() {
+        let path = TempPath::new();
+        let peer_id = PeerId::random();
+        let mut known_peers = PersistedPeers::new();
+        known_peers.insert(peer_id, PersistedPeer {
+            addresses: vec![NetworkAddress::mock()],
+            role: PeerRole::Unknown,
+            score: 70.0,
+            last_seen_secs: 0,
+        });
+        save_peers(path.path(), &known_peers).unwrap();
+
+        // Peers seen within the max age are restored (with their scores)
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let (mut persistence, _, mock_time) =
+            create_persistence(path.path(), peer_metadata_storage.clone());
+        mock_time.advance_secs(3600);
+        persistence.restore();
+        assert_eq!(persistence.known_peers, known_peers);
+        let peer_network_id = PeerNetworkId::new(NetworkId::Validator, peer_id);
+        assert!(peer_metadata_storage.get_peer_score(&peer_network_id) > 60.0);
+
+        // Older peers are dropped
+        let (mut persistence, _, mock_time) =
+            create_persistence(path.path(), PeerMetadataStorage::test());
+        mock_time.advance_secs(3601);
+        persistence.restore();
+        assert!(persistence.known_peers.is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! A cache of the protocols supported by each peer.
+//!
+//! Selecting the preferred protocol of a peer for every message requires the
+//! protocols the peer supports, which are otherwise read from the
+//! [`PeerMetadataStorage`] each time. The [`PeerProtocolCache`] keeps them in a
+//! bounded LRU, whose entries expire after a TTL and are invalidated as soon
+//! as the storage reports that the protocols of the peer changed (e.g., on a
+//! reconnect) or that the peer disconnected. Its stats are recorded with the
+//! protocol cache stats of the storage.
+
+use crate::{
+    application::{
+        cache_stats::CacheStatsRecorder, storage::PeerMetadataStorage, types::PeerEvent,
+    },
+    protocols::wire::handshake::v1::ProtocolIdSet,
+};
+use aptos_config::network_id::PeerNetworkId;
+use aptos_infallible::Mutex;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use lru::LruCache;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// The default maximum number of peers in the cache
+pub const DEFAULT_PROTOCOL_CACHE_SIZE: usize = 1024;
+/// The default time after which a cached entry is read again from the storage
+pub const DEFAULT_PROTOCOL_CACHE_TTL: Duration = Duration::from_secs(300);
+
+struct CacheState {
+    /// The protocols of each peer, and the time they were read from the storage
+    entries: LruCache<PeerNetworkId, (ProtocolIdSet, Instant)>,
+    /// The storage events, used to invalidate the entries of updated peers
+    peer_events: broadcast::Receiver<PeerEvent>,
+}
+
+impl CacheState {
+    /// Invalidates the entries of the peers whose protocols changed since the
+    /// last call. If events were missed, all entries are invalidated.
+    fn process_peer_events(&mut self, stats: &CacheStatsRecorder) {
+        loop {
+            let (peer, protocols) = match self.peer_events.try_recv() {
+                Ok(PeerEvent::PeerConnected(peer, connection))
+                | Ok(PeerEvent::ConnectionMigrated(peer, connection)) => {
+                    (peer, Some(connection.application_protocols))
+                },
+                Ok(PeerEvent::MetadataUpdated(peer, peer_info)) => (
+                    peer,
+                    Some(peer_info.active_connection.application_protocols),
+                ),
+                Ok(PeerEvent::PeerDisconnected(peer, _)) => (peer, None),
+                // The peer is invalidated once it's disconnected
+                Ok(PeerEvent::PeerBanned(..)) => continue,
+                Err(TryRecvError::Lagged(_)) => {
+                    stats.record_invalidations(self.entries.len() as u64);
+                    self.entries.clear();
+                    continue;
+                },
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return,
+            };
+            let unchanged = match (self.entries.peek(&peer), protocols) {
+                (Some((cached, _)), Some(protocols)) => *cached == protocols,
+                _ => false,
+            };
+            if !unchanged && self.entries.pop(&peer).is_some() {
+                stats.record_invalidations(1);
+            }
+        }
+    }
+}
+
+/// A bounded cache of the protocols supported by each connected peer
+pub struct PeerProtocolCache {
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    ttl: Duration,
+    time_service: TimeService,
+    state: Mutex<CacheState>,
+}
+
+impl PeerProtocolCache {
+    pub fn new(
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+        capacity: usize,
+        ttl: Duration,
+        time_service: TimeService,
+    ) -> Self {
+        let peer_events = peer_metadata_storage.subscribe();
+        Self {
+            peer_metadata_storage,
+            ttl,
+            time_service,
+            state: Mutex::new(CacheState {
+                entries: LruCache::new(capacity),
+                peer_events,
+            }),
+        }
+    }
+
+    /// Returns the protocols supported by the given peer, or `None` if the
+    /// peer isn't connected
+    pub fn get_supported_protocols(&self, peer: &PeerNetworkId) -> Option<ProtocolIdSet> {
+        // The lock is held while reading the storage, so that an entry read
+        // before an update can't be inserted after the update was processed
+        let mut state = self.state.lock();
+        let num_entries = state.entries.len();
+        let protocols = self.read_supported_protocols(&mut state, peer);
+        self.stats().record_resize(num_entries, state.entries.len());
+        protocols
+    }
+
+    fn stats(&self) -> &CacheStatsRecorder {
+        self.peer_metadata_storage.protocol_cache_stats()
+    }
+
+    fn read_supported_protocols(
+        &self,
+        state: &mut CacheState,
+        peer: &PeerNetworkId,
+    ) -> Option<ProtocolIdSet> {
+        let stats = self.stats();
+        state.process_peer_events(stats);
+
+        let now = self.time_service.now();
+        if let Some((protocols, read_at)) = state.entries.get(peer) {
+            if now.saturating_duration_since(*read_at) < self.ttl {
+                stats.record_hit();
+                return Some(protocols.clone());
+            }
+        }
+        stats.record_miss();
+
+        match self.peer_metadata_storage.read(*peer) {
+            Some(peer_info) => {
+                let protocols = peer_info.active_connection.application_protocols;
+                state.entries.put(*peer, (protocols.clone(), now));
+                Some(protocols)
+            },
+            None => {
+                state.entries.pop(peer);
+                None
+            },
+        }
+    }
+}
+
+impl Drop for PeerProtocolCache {
+    fn drop(&mut self) {
+        let num_entries = self.state.lock().entries.len();
+        self.stats().record_resize(num_entries, 0);
+    }
+}
+
+impl fmt::Debug for PeerProtocolCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerProtocolCache")
+            .field("ttl", &self.ttl)
+            .field("len", &self.state.lock().entries.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::cache_stats::CacheStats, protocols::wire::handshake::v1::ProtocolId,
+        transport::ConnectionMetadata,
+    };
+    use aptos_config::network_id::NetworkId;
+    use aptos_types::PeerId;
+
+    fn connect_peer(
+        peer_metadata_storage: &PeerMetadataStorage,
+        peer_id: PeerId,
+        protocols: &[ProtocolId],
+    ) -> ConnectionMetadata {
+        let mut connection = ConnectionMetadata::mock(peer_id);
+        connection.application_protocols = ProtocolIdSet::from_iter(protocols.iter().copied());
+        peer_metadata_storage.insert_connection(NetworkId::Validator, connection.clone());
+        connection
+    }
+
+    #[test]
+    fn entries_are_invalidated_when_protocols_change() {
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let cache = PeerProtocolCache::new(
+            peer_metadata_storage.clone(),
+            DEFAULT_PROTOCOL_CACHE_SIZE,
+            DEFAULT_PROTOCOL_CACHE_TTL,
+            TimeService::mock(),
+        );
+        let peer_id = PeerId::random();
+        let peer = PeerNetworkId::new(NetworkId::Validator, peer_id);
+        assert_eq!(cache.get_supported_protocols(&peer), None);
+
+        connect_peer(&peer_metadata_storage, peer_id, &[ProtocolId::MempoolRpc]);
+        assert_eq!(
+            cache.get_supported_protocols(&peer),
+            Some(ProtocolIdSet::from_iter([ProtocolId::MempoolRpc]))
+        );
+
+        // A reconnect with other protocols invalidates the entry
+        let connection = connect_peer(&peer_metadata_storage, peer_id, &[
+            ProtocolId::ConsensusRpcBcs,
+        ]);
+        assert_eq!(
+            cache.get_supported_protocols(&peer),
+            Some(ProtocolIdSet::from_iter([ProtocolId::ConsensusRpcBcs]))
+        );
+
+        // As does a disconnect
+        peer_metadata_storage.remove_connection(NetworkId::Validator, &connection);
+        assert_eq!(cache.get_supported_protocols(&peer), None);
+        assert_eq!(cache.state.lock().entries.len(), 0);
+    }
+
+    #[test]
+    fn stats_are_recorded() {
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let cache = PeerProtocolCache::new(
+            peer_metadata_storage.clone(),
+            DEFAULT_PROTOCOL_CACHE_SIZE,
+            DEFAULT_PROTOCOL_CACHE_TTL,
+            TimeService::mock(),
+        );
+        let peer_id = PeerId::random();
+        let peer = PeerNetworkId::new(NetworkId::Validator, peer_id);
+        let connection = connect_peer(&peer_metadata_storage, peer_id, &[ProtocolId::MempoolRpc]);
+        let stats = || peer_metadata_storage.protocol_cache_stats().stats();
+
+        cache.get_supported_protocols(&peer);
+        cache.get_supported_protocols(&peer);
+        assert_eq!(stats(), CacheStats {
+            hits: 1,
+            misses: 1,
+            invalidations: 0,
+            size: 1,
+        });
+
+        // Disconnects invalidate the entry
+        peer_metadata_storage.remove_connection(NetworkId::Validator, &connection);
+        cache.get_supported_protocols(&peer);
+        assert_eq!(stats(), CacheStats {
+            hits: 1,
+            misses: 2,
+            invalidations: 1,
+            size: 0,
+        });
+
+        // Dropped caches no longer count towards the size
+        connect_peer(&peer_metadata_storage, peer_id, &[ProtocolId::MempoolRpc]);
+        cache.get_supported_protocols(&peer);
+        assert_eq!(stats().size, 1);
+        drop(cache);
+        assert_eq!(stats().size, 0);
+    }
+
+    #[test]
+    fn entries_expire_and_are_bounded() {
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let time_service = TimeService::mock();
+        let mock_time = time_service.clone().into_mock();
+        let cache = PeerProtocolCache::new(
+            peer_metadata_storage.clone(),
+            2,
+            Duration::from_secs(10),
+            time_service,
+        );
+        let peers: Vec<_> = (0..3)
+            .map(|_| {
+                let peer_id = PeerId::random();
+                connect_peer(&peer_metadata_storage, peer_id, &[ProtocolId::MempoolRpc]);
+                PeerNetworkId::new(NetworkId::Validator, peer_id)
+            })
+            .collect();
+
+        // Entries are read again from the storage once they expire
+        let read_at = mock_time.now();
+        cache.get_supported_protocols(&peers[0]);
+        mock_time.advance_secs(10);
+        cache.get_supported_protocols(&peers[0]);
+        let reread_at = cache.state.lock().entries.peek(&peers[0]).unwrap().1;
+        assert_eq!(reread_at, read_at + Duration::from_secs(10));
+
+        // The least recently used peers are evicted
+        cache.get_supported_protocols(&peers[1]);
+        cache.get_supported_protocols(&peers[0]);
+        cache.get_supported_protocols(&peers[2]);
+        let state = cache.state.lock();
+        assert_eq!(state.entries.len(), 2);
+        assert!(state.entries.contains(&peers[0]));
+        assert!(!state.entries.contains(&peers[1]));
+    }
+}
@@ -0,0 +1,442 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{application::error::Error, protocols::wire::handshake::v1::ProtocolId};
+use aptos_config::network_id::PeerNetworkId;
+use aptos_infallible::Mutex;
+use aptos_rate_limiter::rate_limit::{Bucket, SharedBucket, TokenBucketRateLimiter};
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const OUTBOUND_RATE_LIMITER_LABEL: &str = "outbound_protocol";
+const INBOUND_MESSAGES_RATE_LIMITER_LABEL: &str = "inbound_protocol_messages";
+const INBOUND_BYTES_RATE_LIMITER_LABEL: &str = "inbound_protocol_bytes";
+
+/// The number of peers with recorded violations below which expired
+/// violations aren't pruned
+const MIN_VIOLATIONS_PRUNE_THRESHOLD: usize = 1024;
+
+/// A token bucket limit, in messages (or in bytes, for byte limits)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// The maximum number of messages that can be sent in a burst
+    pub bucket_size: usize,
+    /// The number of messages per second that are added to the bucket
+    pub fill_rate: usize,
+}
+
+/// The outbound rate limits for a single protocol. The aggregate limit
+/// applies to all messages sent with the protocol, and the per-peer limit
+/// applies to the messages sent to each individual peer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OutboundRateLimitConfig {
+    pub aggregate: Option<RateLimit>,
+    pub per_peer: Option<RateLimit>,
+}
+
+/// The rate limiters for a single protocol
+struct ProtocolRateLimiter {
+    config: OutboundRateLimitConfig,
+    aggregate: Option<SharedBucket>,
+    per_peer: Option<TokenBucketRateLimiter<PeerNetworkId>>,
+}
+
+impl ProtocolRateLimiter {
+    fn new(protocol_id: ProtocolId, config: OutboundRateLimitConfig) -> Self {
+        let aggregate = config.aggregate.map(|limit| {
+            Arc::new(Mutex::new(Bucket::new(
+                OUTBOUND_RATE_LIMITER_LABEL.to_string(),
+                protocol_id.to_string(),
+                protocol_id.to_string(),
+                limit.bucket_size,
+                limit.bucket_size,
+                limit.fill_rate,
+                None,
+            )))
+        });
+        let per_peer = config.per_peer.map(|limit| {
+            TokenBucketRateLimiter::new(
+                OUTBOUND_RATE_LIMITER_LABEL,
+                protocol_id.to_string(),
+                100,
+                limit.bucket_size,
+                limit.fill_rate,
+                None,
+            )
+        });
+        Self {
+            config,
+            aggregate,
+            per_peer,
+        }
+    }
+
+    /// Attempts to take a token from the per-peer and aggregate buckets.
+    /// On failure, returns the time at which a token may be available (or
+    /// `None` if it never will be).
+    fn try_acquire(&self, peer: &PeerNetworkId) -> Result<(), Option<Instant>> {
+        let peer_bucket = self.per_peer.as_ref().map(|limiter| limiter.bucket(*peer));
+        if let Some(peer_bucket) = &peer_bucket {
+            peer_bucket.lock().acquire_all_tokens(1)?;
+        }
+        if let Some(aggregate) = &self.aggregate {
+            if let Err(retry_time) = aggregate.lock().acquire_all_tokens(1) {
+                // Give back the per-peer token, as the message won't be sent
+                if let Some(peer_bucket) = &peer_bucket {
+                    peer_bucket.lock().return_tokens(1);
+                }
+                return Err(retry_time);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enforces per-protocol (and optionally per-peer) token bucket limits on
+/// outbound messages, so that a single noisy application can't starve the
+/// connections it shares with other applications. Protocols without a
+/// configured limit are never limited.
+#[derive(Default)]
+pub struct OutboundRateLimiter {
+    limiters: HashMap<ProtocolId, ProtocolRateLimiter>,
+}
+
+impl OutboundRateLimiter {
+    pub fn new(configs: HashMap<ProtocolId, OutboundRateLimitConfig>) -> Self {
+        let limiters = configs
+            .into_iter()
+            .map(|(protocol_id, config)| {
+                (protocol_id, ProtocolRateLimiter::new(protocol_id, config))
+            })
+            .collect();
+        Self { limiters }
+    }
+
+    /// Takes a permit to send a message with the given protocol to the given
+    /// peer, or returns a `RateLimited` error if none is available.
+    pub fn try_acquire(&self, protocol_id: ProtocolId, peer: &PeerNetworkId) -> Result<(), Error> {
+        match self.limiters.get(&protocol_id) {
+            Some(limiter) => limiter
+                .try_acquire(peer)
+                .map_err(|_| rate_limited_error(protocol_id, peer)),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits until a permit to send a message with the given protocol to the
+    /// given peer is available, and takes it. Returns a `RateLimited` error
+    /// only if the limits can never be satisfied.
+    pub async fn acquire(
+        &self,
+        protocol_id: ProtocolId,
+        peer: &PeerNetworkId,
+    ) -> Result<(), Error> {
+        let limiter = match self.limiters.get(&protocol_id) {
+            Some(limiter) => limiter,
+            None => return Ok(()),
+        };
+        loop {
+            match limiter.try_acquire(peer) {
+                Ok(()) => return Ok(()),
+                Err(Some(retry_time)) => {
+                    tokio::time::sleep_until(tokio::time::Instant::from_std(retry_time)).await
+                },
+                Err(None) => return Err(rate_limited_error(protocol_id, peer)),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for OutboundRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.limiters
+                    .iter()
+                    .map(|(protocol_id, limiter)| (protocol_id, &limiter.config)),
+            )
+            .finish()
+    }
+}
+
+/// The inbound rate limits for a single protocol, which apply to the messages
+/// received from each individual peer. Messages beyond the limits are dropped,
+/// and peers that exceed the limits `max_violations` times within the
+/// `violation_window` are put in the penalty box for the `penalty_duration`,
+/// i.e., they are disconnected and their connections are rejected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InboundRateLimitConfig {
+    /// The limit on the number of messages received from each peer
+    pub messages: Option<RateLimit>,
+    /// The limit on the number of bytes received from each peer. Messages
+    /// larger than the bucket size are always dropped.
+    pub bytes: Option<RateLimit>,
+    pub max_violations: usize,
+    pub violation_window: Duration,
+    pub penalty_duration: Duration,
+}
+
+impl Default for InboundRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            messages: None,
+            bytes: None,
+            max_violations: 10,
+            violation_window: Duration::from_secs(60),
+            penalty_duration: Duration::from_secs(300),
+        }
+    }
+}
+
+/// The outcome of checking an inbound message against the rate limits
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InboundRateLimitDecision {
+    /// The message is within the limits, and should be delivered
+    Allow,
+    /// The message exceeds the limits, and should be dropped
+    Throttle,
+    /// The message exceeds the limits, and the peer exceeded them too often:
+    /// the message should be dropped and the peer penalized until the given time
+    Penalize(Instant),
+}
+
+/// The violations of a single peer within the current window
+struct Violations {
+    window_start: Instant,
+    count: usize,
+}
+
+/// The violations recorded by an inbound rate limiter
+struct RecordedViolations {
+    per_peer: HashMap<PeerNetworkId, Violations>,
+    /// The number of peers with recorded violations at which expired
+    /// violations are pruned
+    prune_threshold: usize,
+}
+
+/// Enforces per-peer token bucket limits on the messages (and bytes) received
+/// with a single protocol, and keeps track of the peers that repeatedly
+/// exceed them.
+pub struct InboundRateLimiter {
+    config: InboundRateLimitConfig,
+    messages: Option<TokenBucketRateLimiter<PeerNetworkId>>,
+    bytes: Option<TokenBucketRateLimiter<PeerNetworkId>>,
+    time_service: TimeService,
+    violations: Mutex<RecordedViolations>,
+}
+
+impl InboundRateLimiter {
+    pub fn new(
+        protocol_id: ProtocolId,
+        config: InboundRateLimitConfig,
+        time_service: TimeService,
+    ) -> Self {
+        let new_limiter = |label, limit: RateLimit| {
+            TokenBucketRateLimiter::new(
+                label,
+                protocol_id.to_string(),
+                100,
+                limit.bucket_size,
+                limit.fill_rate,
+                None,
+            )
+        };
+        Self {
+            config,
+            messages: config
+                .messages
+                .map(|limit| new_limiter(INBOUND_MESSAGES_RATE_LIMITER_LABEL, limit)),
+            bytes: config
+                .bytes
+                .map(|limit| new_limiter(INBOUND_BYTES_RATE_LIMITER_LABEL, limit)),
+            time_service,
+            violations: Mutex::new(RecordedViolations {
+                per_peer: HashMap::new(),
+                prune_threshold: MIN_VIOLATIONS_PRUNE_THRESHOLD,
+            }),
+        }
+    }
+
+    /// Checks a message of the given size received from the peer against the
+    /// limits, and records a violation if it exceeds them
+    pub fn check(&self, peer: &PeerNetworkId, num_bytes: usize) -> InboundRateLimitDecision {
+        if self.try_acquire(peer, num_bytes) {
+            return InboundRateLimitDecision::Allow;
+        }
+
+        let now = self.time_service.now();
+        let window = self.config.violation_window;
+        let mut violations = self.violations.lock();
+        let peer_violations = violations.per_peer.entry(*peer).or_insert(Violations {
+            window_start: now,
+            count: 0,
+        });
+        if now.saturating_duration_since(peer_violations.window_start) >= window {
+            peer_violations.window_start = now;
+            peer_violations.count = 0;
+        }
+        peer_violations.count += 1;
+        if peer_violations.count >= self.config.max_violations {
+            violations.per_peer.remove(peer);
+            return InboundRateLimitDecision::Penalize(now + self.config.penalty_duration);
+        }
+
+        // Prune the expired violations once the number of peers with recorded
+        // violations has doubled since the last pruning, to bound the memory usage
+        if violations.per_peer.len() >= violations.prune_threshold {
+            violations.per_peer.retain(|_, peer_violations| {
+                now.saturating_duration_since(peer_violations.window_start) < window
+            });
+            violations.prune_threshold =
+                MIN_VIOLATIONS_PRUNE_THRESHOLD.max(2 * violations.per_peer.len());
+        }
+        InboundRateLimitDecision::Throttle
+    }
+
+    /// Attempts to take the message and byte tokens from the peer's buckets
+    fn try_acquire(&self, peer: &PeerNetworkId, num_bytes: usize) -> bool {
+        let message_bucket = self.messages.as_ref().map(|limiter| limiter.bucket(*peer));
+        if let Some(message_bucket) = &message_bucket {
+            if message_bucket.lock().acquire_all_tokens(1).is_err() {
+                return false;
+            }
+        }
+        if let Some(bytes) = &self.bytes {
+            if bytes
+                .bucket(*peer)
+                .lock()
+                .acquire_all_tokens(num_bytes)
+                .is_err()
+            {
+                // Give back the message token, as the message won't be delivered
+                if let Some(message_bucket) = &message_bucket {
+                    message_bucket.lock().return_tokens(1);
+                }
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Debug for InboundRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InboundRateLimiter")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+fn rate_limited_error(protocol_id: ProtocolId, peer: &PeerNetworkId) -> Error {
+    Error::RateLimited(format!(
+        "Outbound rate limit exceeded for protocol: {:?}, peer: {:?}",
+        protocol_id, peer
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aptos_config::network_id::NetworkId;
+    use aptos_time_service::MockTimeService;
+    use aptos_types::PeerId;
+
+    fn inbound_rate_limiter(
+        config: InboundRateLimitConfig,
+    ) -> (InboundRateLimiter, MockTimeService) {
+        let time_service = TimeService::mock();
+        let mock_time = time_service.clone().into_mock();
+        let limiter = InboundRateLimiter::new(ProtocolId::MempoolDirectSend, config, time_service);
+        (limiter, mock_time)
+    }
+
+    #[test]
+    fn inbound_messages_are_throttled_per_peer() {
+        let (limiter, _) = inbound_rate_limiter(InboundRateLimitConfig {
+            messages: Some(RateLimit {
+                bucket_size: 2,
+                fill_rate: 1,
+            }),
+            ..Default::default()
+        });
+        let peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+        let other_peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+
+        assert_eq!(limiter.check(&peer, 10), InboundRateLimitDecision::Allow);
+        assert_eq!(limiter.check(&peer, 10), InboundRateLimitDecision::Allow);
+        assert_eq!(limiter.check(&peer, 10), InboundRateLimitDecision::Throttle);
+
+        // Other peers have their own buckets
+        assert_eq!(
+            limiter.check(&other_peer, 10),
+            InboundRateLimitDecision::Allow
+        );
+    }
+
+    #[test]
+    fn inbound_bytes_are_throttled_per_peer() {
+        let (limiter, _) = inbound_rate_limiter(InboundRateLimitConfig {
+            messages: Some(RateLimit {
+                bucket_size: 10,
+                fill_rate: 1,
+            }),
+            bytes: Some(RateLimit {
+                bucket_size: 100,
+                fill_rate: 1,
+            }),
+            ..Default::default()
+        });
+        let peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+
+        assert_eq!(limiter.check(&peer, 60), InboundRateLimitDecision::Allow);
+        assert_eq!(limiter.check(&peer, 60), InboundRateLimitDecision::Throttle);
+        // Messages larger than the bucket are always throttled
+        assert_eq!(
+            limiter.check(&peer, 101),
+            InboundRateLimitDecision::Throttle
+        );
+
+        // The message tokens of throttled messages are given back
+        for _ in 0..9 {
+            assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Allow);
+        }
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Throttle);
+    }
+
+    #[test]
+    fn repeated_violations_are_penalized() {
+        let (limiter, mock_time) = inbound_rate_limiter(InboundRateLimitConfig {
+            messages: Some(RateLimit {
+                bucket_size: 1,
+                fill_rate: 1,
+            }),
+            bytes: None,
+            max_violations: 3,
+            violation_window: Duration::from_secs(10),
+            penalty_duration: Duration::from_secs(60),
+        });
+        let peer = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Allow);
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Throttle);
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Throttle);
+
+        // Violations outside of the window are forgotten
+        mock_time.advance_secs(10);
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Throttle);
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Throttle);
+        let now = mock_time.now();
+        assert_eq!(
+            limiter.check(&peer, 1),
+            InboundRateLimitDecision::Penalize(now + Duration::from_secs(60))
+        );
+
+        // The violations start over after a penalty
+        assert_eq!(limiter.check(&peer, 1), InboundRateLimitDecision::Throttle);
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server-side caching of rpc responses.
+//!
+//! Some rpc services receive many identical requests, e.g., the storage
+//! service is asked for the same chunk by many peers that are syncing. An
+//! [`RpcResponseCache`] remembers the successful responses to each request
+//! (by digest) for a configurable TTL, so that identical requests are served
+//! without being handed to the application again. Identical requests that
+//! arrive before the first response is cached are still handed to the
+//! application.
+
+use crate::protocols::rpc::{error::RpcError, InboundRpcRequest};
+use aptos_crypto::HashValue;
+use aptos_infallible::Mutex;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use bytes::Bytes;
+use futures::channel::oneshot;
+use lru::LruCache;
+use std::{
+    fmt, mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The response cache of a single rpc protocol
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RpcResponseCacheConfig {
+    /// The maximum number of cached responses
+    pub capacity: usize,
+    /// The time after which a cached response is no longer served
+    pub ttl: Duration,
+}
+
+impl Default for RpcResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            ttl: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A bounded cache of the responses to recent rpc requests, keyed by the
+/// digest of the request
+pub struct RpcResponseCache {
+    ttl: Duration,
+    time_service: TimeService,
+    /// The cached responses, and the time they were cached
+    responses: Mutex<LruCache<HashValue, (Bytes, Instant)>>,
+}
+
+impl RpcResponseCache {
+    pub fn new(config: RpcResponseCacheConfig, time_service: TimeService) -> Self {
+        Self {
+            ttl: config.ttl,
+            time_service,
+            responses: Mutex::new(LruCache::new(config.capacity)),
+        }
+    }
+
+    /// Returns the digest of the given (serialized) request
+    pub fn digest(data: &[u8]) -> HashValue {
+        HashValue::sha3_256_of(data)
+    }
+
+    /// Returns the cached response to the request with the given digest, if
+    /// it hasn't expired
+    pub fn get(&self, digest: &HashValue) -> Option<Bytes> {
+        let now = self.time_service.now();
+        let mut responses = self.responses.lock();
+        let (response, cached_at) = responses.get(digest)?.clone();
+        if now.saturating_duration_since(cached_at) < self.ttl {
+            Some(response)
+        } else {
+            responses.pop(digest);
+            None
+        }
+    }
+
+    /// Caches the response to the request with the given digest
+    pub fn insert(&self, digest: HashValue, response: Bytes) {
+        let now = self.time_service.now();
+        self.responses.lock().put(digest, (response, now));
+    }
+
+    /// Responds to the given request from the cache, and returns `None`, if an
+    /// identical request was recently answered. Otherwise, returns the request
+    /// to be handed to the application, whose (successful) response will be
+    /// cached on its way back to the peer.
+    pub fn handle_request(
+        self: &Arc<Self>,
+        mut request: InboundRpcRequest,
+    ) -> Option<InboundRpcRequest> {
+        let digest = Self::digest(&request.data);
+        if let Some(response) = self.get(&digest) {
+            // The peer may no longer be waiting for the response
+            let _ = request.res_tx.send(Ok(response));
+            return None;
+        }
+
+        let (res_tx, res_rx) = oneshot::channel::<Result<Bytes, RpcError>>();
+        let peer_res_tx = mem::replace(&mut request.res_tx, res_tx);
+        let cache = self.clone();
+        tokio::spawn(async move {
+            // If the application drops the request, so is the peer's response channel
+            if let Ok(response) = res_rx.await {
+                if let Ok(response) = &response {
+                    cache.insert(digest, response.clone());
+                }
+                let _ = peer_res_tx.send(response);
+            }
+        });
+        Some(request)
+    }
+}
+
+impl fmt::Debug for RpcResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcResponseCache")
+            .field("ttl", &self.ttl)
+            .field("len", &self.responses.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ProtocolId;
+
+    fn new_request(
+        data: &'static [u8],
+    ) -> (
+        InboundRpcRequest,
+        oneshot::Receiver<Result<Bytes, RpcError>>,
+    ) {
+        let (res_tx, res_rx) = oneshot::channel();
+        let request = InboundRpcRequest {
+            protocol_id: ProtocolId::StorageServiceRpc,
+            data: Bytes::from_static(data),
+            res_tx,
+            deadline: None,
+            correlation_id: 0,
+        };
+        (request, res_rx)
+    }
+
+    #[tokio::test]
+    async fn identical_requests_are_served_from_the_cache() {
+        let time_service = TimeService::mock();
+        let mock_time = time_service.clone().into_mock();
+        let cache = Arc::new(RpcResponseCache::new(
+            RpcResponseCacheConfig {
+                capacity: 16,
+                ttl: Duration::from_secs(10),
+            },
+            time_service,
+        ));
+
+        // The first request is handed to the application, and its response cached
+        let (request, res_rx) = new_request(b"chunk");
+        let request = cache.handle_request(request).unwrap();
+        request
+            .res_tx
+            .send(Ok(Bytes::from_static(b"response")))
+            .unwrap();
+        assert_eq!(
+            res_rx.await.unwrap().unwrap(),
+            Bytes::from_static(b"response")
+        );
+
+        // Identical requests are served from the cache, until the response expires
+        let (request, res_rx) = new_request(b"chunk");
+        assert!(cache.handle_request(request).is_none());
+        assert_eq!(
+            res_rx.await.unwrap().unwrap(),
+            Bytes::from_static(b"response")
+        );
+        mock_time.advance_secs(10);
+        let (request, _res_rx) = new_request(b"chunk");
+        assert!(cache.handle_request(request).is_some());
+
+        // Errors aren't cached
+        let (request, res_rx) = new_request(b"other chunk");
+        let request = cache.handle_request(request).unwrap();
+        request
+            .res_tx
+            .send(Err(RpcError::InvalidRpcResponse))
+            .unwrap();
+        assert!(res_rx.await.unwrap().is_err());
+        let (request, _res_rx) = new_request(b"other chunk");
+        assert!(cache.handle_request(request).is_some());
+    }
+}
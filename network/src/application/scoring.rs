@@ -0,0 +1,117 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{Duration, Instant};
+
+/// Scores for peer rankings based on observed behavior.
+pub const MAX_SCORE: f64 = 100.0;
+pub const MIN_SCORE: f64 = 0.0;
+pub const STARTING_SCORE: f64 = 50.0;
+/// Add this score on a successful (and timely) RPC response.
+const SUCCESSFUL_RESPONSE_DELTA: f64 = 1.0;
+/// Responses slower than this are treated as not useful.
+const SLOW_RESPONSE_THRESHOLD: Duration = Duration::from_secs(5);
+/// Not necessarily malicious, but not super useful (e.g., slow responses).
+const NOT_USEFUL_MULTIPLIER: f64 = 0.95;
+/// The RPC failed or timed out.
+const FAILURE_MULTIPLIER: f64 = 0.9;
+/// Likely to be a malicious message.
+const INVALID_MESSAGE_MULTIPLIER: f64 = 0.8;
+/// The time it takes for a score to move halfway back to the starting score.
+const SCORE_DECAY_HALF_LIFE: Duration = Duration::from_secs(300);
+/// The weight of the newest sample in the moving average of RPC latencies.
+const LATENCY_SMOOTHING_FACTOR: f64 = 0.25;
+
+/// A signal about a peer's behavior, reported by applications
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PeerScoreEvent {
+    /// An RPC to the peer completed successfully with the given latency
+    RpcSuccess(Duration),
+    /// An RPC to the peer failed (e.g., the peer returned an error)
+    RpcFailure,
+    /// An RPC to the peer timed out
+    RpcTimeout,
+    /// The peer sent a message that failed validation
+    InvalidMessage,
+}
+
+/// A score for a single peer that aggregates behavioral signals. Scores
+/// decay back towards `STARTING_SCORE` over time, so that old events
+/// (good or bad) matter less than recent ones. The score also tracks a
+/// moving average of the peer's RPC latency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerScore {
+    score: f64,
+    last_updated: Instant,
+    average_latency: Option<Duration>,
+}
+
+impl PeerScore {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            score: STARTING_SCORE,
+            last_updated: now,
+            average_latency: None,
+        }
+    }
+
+    /// Creates a score with the given value (e.g., one persisted before a
+    /// restart), clamped to the valid range
+    pub fn restore(score: f64, now: Instant) -> Self {
+        Self {
+            score: score.clamp(MIN_SCORE, MAX_SCORE),
+            last_updated: now,
+            average_latency: None,
+        }
+    }
+
+    /// Returns the score at the given time (after decay)
+    pub fn score_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_updated);
+        let half_lives = elapsed.as_secs_f64() / SCORE_DECAY_HALF_LIFE.as_secs_f64();
+        STARTING_SCORE + (self.score - STARTING_SCORE) * 0.5_f64.powf(half_lives)
+    }
+
+    /// Returns the current score
+    pub fn score(&self) -> f64 {
+        self.score_at(Instant::now())
+    }
+
+    /// Returns the moving average of the peer's successful RPC latencies,
+    /// or `None` if no RPC to the peer has succeeded yet
+    pub fn average_latency(&self) -> Option<Duration> {
+        self.average_latency
+    }
+
+    /// Updates the score according to the given event at the given time
+    pub fn update_at(&mut self, event: PeerScoreEvent, now: Instant) {
+        if let PeerScoreEvent::RpcSuccess(latency) = event {
+            self.average_latency = Some(match self.average_latency {
+                Some(average_latency) => {
+                    average_latency.mul_f64(1.0 - LATENCY_SMOOTHING_FACTOR)
+                        + latency.mul_f64(LATENCY_SMOOTHING_FACTOR)
+                },
+                None => latency,
+            });
+        }
+        let score = self.score_at(now);
+        self.score = match event {
+            PeerScoreEvent::RpcSuccess(latency) if latency <= SLOW_RESPONSE_THRESHOLD => {
+                f64::min(score + SUCCESSFUL_RESPONSE_DELTA, MAX_SCORE)
+            },
+            PeerScoreEvent::RpcSuccess(_) => f64::max(score * NOT_USEFUL_MULTIPLIER, MIN_SCORE),
+            PeerScoreEvent::RpcFailure | PeerScoreEvent::RpcTimeout => {
+                f64::max(score * FAILURE_MULTIPLIER, MIN_SCORE)
+            },
+            PeerScoreEvent::InvalidMessage => {
+                f64::max(score * INVALID_MESSAGE_MULTIPLIER, MIN_SCORE)
+            },
+        };
+        self.last_updated = now;
+    }
+
+    /// Updates the score according to the given event
+    pub fn update(&mut self, event: PeerScoreEvent) {
+        self.update_at(event, Instant::now())
+    }
+}
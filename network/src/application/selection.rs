@@ -0,0 +1,147 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::application::{scoring::MIN_SCORE, storage::PeerMetadataStorage};
+use aptos_config::network_id::PeerNetworkId;
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    cmp::Ordering,
+    fmt::Debug,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+    time::Duration,
+};
+
+/// The latency assumed for peers without any successful RPCs, so that new
+/// peers still get picked by the latency-weighted selector.
+const UNKNOWN_PEER_LATENCY: Duration = Duration::from_millis(500);
+/// The smallest weight a peer can have, so that every peer can be picked.
+const MIN_PEER_WEIGHT: f64 = 1e-6;
+
+/// A strategy for choosing between peers. Selectors rank a set of candidate
+/// peers (from most to least preferred) using the signals collected in the
+/// `PeerMetadataStorage`, so that the same selection logic can be shared by
+/// all applications.
+pub trait PeerSelector: Debug + Send + Sync {
+    /// Orders the given peers from the most to the least preferred
+    fn rank_peers(
+        &self,
+        peers: Vec<PeerNetworkId>,
+        peer_metadata_storage: &PeerMetadataStorage,
+    ) -> Vec<PeerNetworkId>;
+}
+
+/// Ranks peers uniformly at random
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomPeerSelector;
+
+impl PeerSelector for RandomPeerSelector {
+    fn rank_peers(
+        &self,
+        mut peers: Vec<PeerNetworkId>,
+        _peer_metadata_storage: &PeerMetadataStorage,
+    ) -> Vec<PeerNetworkId> {
+        peers.shuffle(&mut rand::thread_rng());
+        peers
+    }
+}
+
+/// Ranks peers in turn, so that each call prefers the peer after the one
+/// preferred by the previous call
+#[derive(Debug, Default)]
+pub struct RoundRobinPeerSelector {
+    next_index: AtomicUsize,
+}
+
+impl PeerSelector for RoundRobinPeerSelector {
+    fn rank_peers(
+        &self,
+        mut peers: Vec<PeerNetworkId>,
+        _peer_metadata_storage: &PeerMetadataStorage,
+    ) -> Vec<PeerNetworkId> {
+        if peers.is_empty() {
+            return peers;
+        }
+        peers.sort();
+        let next_index = self.next_index.fetch_add(1, AtomicOrdering::Relaxed);
+        peers.rotate_left(next_index % peers.len());
+        peers
+    }
+}
+
+/// Ranks peers by score, from the highest to the lowest
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HighestScorePeerSelector;
+
+impl PeerSelector for HighestScorePeerSelector {
+    fn rank_peers(
+        &self,
+        peers: Vec<PeerNetworkId>,
+        peer_metadata_storage: &PeerMetadataStorage,
+    ) -> Vec<PeerNetworkId> {
+        let mut peers_and_scores: Vec<_> = peers
+            .into_iter()
+            .map(|peer| (peer, peer_metadata_storage.get_peer_score(&peer)))
+            .collect();
+        peers_and_scores.sort_by(|(_, score_a), (_, score_b)| {
+            score_b.partial_cmp(score_a).unwrap_or(Ordering::Equal)
+        });
+        peers_and_scores.into_iter().map(|(peer, _)| peer).collect()
+    }
+}
+
+/// Ranks peers randomly, where the chance of a peer being preferred is
+/// proportional to its score
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScoreWeightedPeerSelector;
+
+impl PeerSelector for ScoreWeightedPeerSelector {
+    fn rank_peers(
+        &self,
+        peers: Vec<PeerNetworkId>,
+        peer_metadata_storage: &PeerMetadataStorage,
+    ) -> Vec<PeerNetworkId> {
+        weighted_shuffle(peers, |peer| {
+            peer_metadata_storage.get_peer_score(peer) - MIN_SCORE
+        })
+    }
+}
+
+/// Ranks peers randomly, where the chance of a peer being preferred is
+/// inversely proportional to its average RPC latency
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencyWeightedPeerSelector;
+
+impl PeerSelector for LatencyWeightedPeerSelector {
+    fn rank_peers(
+        &self,
+        peers: Vec<PeerNetworkId>,
+        peer_metadata_storage: &PeerMetadataStorage,
+    ) -> Vec<PeerNetworkId> {
+        weighted_shuffle(peers, |peer| {
+            let latency = peer_metadata_storage
+                .get_peer_latency(peer)
+                .unwrap_or(UNKNOWN_PEER_LATENCY);
+            1.0 / latency.as_secs_f64().max(f64::EPSILON)
+        })
+    }
+}
+
+/// Shuffles the peers so that peers with higher weights are more likely to
+/// come first (i.e., weighted random sampling without replacement).
+fn weighted_shuffle<F: Fn(&PeerNetworkId) -> f64>(
+    peers: Vec<PeerNetworkId>,
+    weight: F,
+) -> Vec<PeerNetworkId> {
+    let mut rng = rand::thread_rng();
+    let mut peers_and_keys: Vec<_> = peers
+        .into_iter()
+        .map(|peer| {
+            let weight = weight(&peer).max(MIN_PEER_WEIGHT);
+            let key = rng.gen::<f64>().powf(1.0 / weight);
+            (peer, key)
+        })
+        .collect();
+    peers_and_keys
+        .sort_by(|(_, key_a), (_, key_b)| key_b.partial_cmp(key_a).unwrap_or(Ordering::Equal));
+    peers_and_keys.into_iter().map(|(peer, _)| peer).collect()
+}
@@ -2,7 +2,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    application::types::{PeerInfo, PeerState},
+    application::{
+        cache_stats::{
+            CacheStats, CacheStatsRecorder, PEER_PROTOCOLS_CACHE, SUPPORTED_PEERS_CACHE,
+        },
+        scoring::{PeerScore, PeerScoreEvent, STARTING_SCORE},
+        types::{
+            DialBackoffState, PeerBan, PeerEvent, PeerInfo, PeerMetadataSnapshot,
+            PeerMonitoringMetadata, PeerReport, PeerSnapshot, PeerState, PeerTraffic, PingStats,
+        },
+    },
+    counters,
+    peer_manager::{OutboundQueue, OutboundQueueDepth},
+    protocols::wire::handshake::v1::ProtocolId,
     transport::ConnectionMetadata,
 };
 use aptos_config::{
@@ -10,12 +22,24 @@ use aptos_config::{
     network_id::{NetworkId, PeerNetworkId},
 };
 use aptos_infallible::RwLock;
+use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::{account_address::AccountAddress, PeerId};
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     fmt::Debug,
     sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::sync::broadcast;
+
+/// The number of peer events buffered for each subscriber. Subscribers that
+/// fall further behind will miss events (and be notified that they lagged).
+const PEER_EVENT_CHANNEL_SIZE: usize = 1024;
+
+/// The number of versions a peer may lag behind the highest advertised ledger
+/// version, and still be considered close to the chain tip when ranking peers
+pub const MAX_RANKED_LEDGER_VERSION_LAG: u64 = 10_000;
 
 // TODO: refactor and clean up this interface.
 
@@ -24,6 +48,31 @@ use std::{
 #[derive(Debug)]
 pub struct PeerMetadataStorage {
     storage: HashMap<NetworkId, RwLock<HashMap<PeerId, PeerInfo>>>,
+    peer_scores: RwLock<HashMap<PeerNetworkId, PeerScore>>,
+    /// The peers in the penalty box, and the time at which their penalty expires
+    penalty_box: RwLock<HashMap<PeerNetworkId, Instant>>,
+    /// The peers banned by the applications, and why (and until when)
+    banned_peers: RwLock<HashMap<PeerNetworkId, PeerBan>>,
+    /// The dial backoff of the peers being (re)dialed
+    dial_backoffs: RwLock<HashMap<PeerNetworkId, DialBackoffState>>,
+    /// The last epoch in which each peer was part of the on-chain validator set
+    validator_epochs: RwLock<HashMap<PeerNetworkId, u64>>,
+    /// The connected peers that support at least one of the (sorted) protocols of
+    /// each queried protocol list. Entries are updated incrementally as peers
+    /// connect, disconnect or change state, always while holding the network lock.
+    supported_peers: RwLock<HashMap<(NetworkId, Vec<ProtocolId>), HashSet<PeerId>>>,
+    supported_peers_cache_stats: CacheStatsRecorder,
+    /// The stats of the peer protocol caches of the network clients
+    protocol_cache_stats: CacheStatsRecorder,
+    /// The outbound queue of the active connection with each peer
+    outbound_queues: RwLock<HashMap<PeerNetworkId, Arc<OutboundQueue>>>,
+    /// The traffic exchanged over the active connection with each peer
+    peer_traffic: RwLock<HashMap<PeerNetworkId, PeerTraffic>>,
+    /// The time at which each disconnected peer with metadata (e.g., a score)
+    /// was disconnected, so that its metadata can be garbage collected
+    disconnected_peers: RwLock<HashMap<PeerNetworkId, Instant>>,
+    peer_event_sender: broadcast::Sender<PeerEvent>,
+    time_service: TimeService,
 }
 
 impl PeerMetadataStorage {
@@ -34,8 +83,30 @@ impl PeerMetadataStorage {
 
     /// Create a new `PeerMetadataStorage` `NetworkId`s must be known at construction time
     pub fn new(network_ids: &[NetworkId]) -> Arc<PeerMetadataStorage> {
+        Self::new_with_time_service(network_ids, TimeService::real())
+    }
+
+    /// Create a new `PeerMetadataStorage`, whose peer bans expire according to
+    /// the given time service
+    pub fn new_with_time_service(
+        network_ids: &[NetworkId],
+        time_service: TimeService,
+    ) -> Arc<PeerMetadataStorage> {
         let mut peer_metadata_storage = PeerMetadataStorage {
             storage: HashMap::new(),
+            peer_scores: RwLock::new(HashMap::new()),
+            penalty_box: RwLock::new(HashMap::new()),
+            banned_peers: RwLock::new(HashMap::new()),
+            dial_backoffs: RwLock::new(HashMap::new()),
+            validator_epochs: RwLock::new(HashMap::new()),
+            supported_peers: RwLock::new(HashMap::new()),
+            supported_peers_cache_stats: CacheStatsRecorder::new(SUPPORTED_PEERS_CACHE),
+            protocol_cache_stats: CacheStatsRecorder::new(PEER_PROTOCOLS_CACHE),
+            outbound_queues: RwLock::new(HashMap::new()),
+            peer_traffic: RwLock::new(HashMap::new()),
+            disconnected_peers: RwLock::new(HashMap::new()),
+            peer_event_sender: broadcast::channel(PEER_EVENT_CHANNEL_SIZE).0,
+            time_service,
         };
         network_ids.iter().for_each(|network_id| {
             peer_metadata_storage
@@ -49,6 +120,19 @@ impl PeerMetadataStorage {
         self.storage.keys().copied()
     }
 
+    /// Returns a receiver of all peer events (i.e., connections, disconnections
+    /// and metadata updates) that happen after the call.
+    pub fn subscribe(&self) -> broadcast::Receiver<PeerEvent> {
+        self.peer_event_sender.subscribe()
+    }
+
+    /// Notifies the subscribers of the given event. Events are sent while
+    /// holding the network lock, so that they are delivered in order.
+    fn notify_subscribers(&self, peer_event: PeerEvent) {
+        // An error only means that there are no subscribers
+        let _ = self.peer_event_sender.send(peer_event);
+    }
+
     /// Handle common logic of getting a network
     fn get_network(&self, network_id: NetworkId) -> &RwLock<HashMap<AccountAddress, PeerInfo>> {
         self.storage
@@ -81,6 +165,25 @@ impl PeerMetadataStorage {
             .collect()
     }
 
+    /// Returns the peers (across all networks) that match the given predicate,
+    /// without copying their metadata. See `application::filters` for the
+    /// common predicates.
+    pub fn get_peers_matching<F: Fn(&PeerNetworkId, &PeerInfo) -> bool>(
+        &self,
+        predicate: F,
+    ) -> Vec<PeerNetworkId> {
+        let mut peers = vec![];
+        for (network_id, network) in &self.storage {
+            for (peer_id, peer_info) in network.read().iter() {
+                let peer = PeerNetworkId::new(*network_id, *peer_id);
+                if predicate(&peer, peer_info) {
+                    peers.push(peer);
+                }
+            }
+        }
+        peers
+    }
+
     pub fn keys(&self, network_id: NetworkId) -> Vec<PeerNetworkId> {
         let network = self.get_network(network_id);
         network
@@ -105,18 +208,26 @@ impl PeerMetadataStorage {
 
     /// Insert new entry
     pub fn insert(&self, peer_network_id: PeerNetworkId, new_value: PeerInfo) {
-        let _ = self
-            .get_network(peer_network_id.network_id())
-            .write()
-            .insert(peer_network_id.peer_id(), new_value);
+        let mut network = self.get_network(peer_network_id.network_id()).write();
+        let _ = network.insert(peer_network_id.peer_id(), new_value.clone());
+        self.update_supported_peers(peer_network_id, Some(&new_value));
+        self.disconnected_peers.write().remove(&peer_network_id);
+        self.notify_subscribers(PeerEvent::MetadataUpdated(peer_network_id, new_value));
     }
 
     /// Remove old entries
     pub fn remove(&self, peer_network_id: &PeerNetworkId) {
-        let _ = self
-            .get_network(peer_network_id.network_id())
-            .write()
-            .remove(&peer_network_id.peer_id());
+        let mut network = self.get_network(peer_network_id.network_id()).write();
+        if let Some(peer_info) = network.remove(&peer_network_id.peer_id()) {
+            self.update_supported_peers(*peer_network_id, None);
+            self.disconnected_peers
+                .write()
+                .insert(*peer_network_id, self.time_service.now());
+            self.notify_subscribers(PeerEvent::PeerDisconnected(
+                *peer_network_id,
+                peer_info.active_connection,
+            ));
+        }
     }
 
     pub fn insert_connection(
@@ -124,12 +235,51 @@ impl PeerMetadataStorage {
         network_id: NetworkId,
         connection_metadata: ConnectionMetadata,
     ) {
-        let network = self.get_network(network_id);
+        let mut network = self.get_network(network_id).write();
+        let peer_network_id = PeerNetworkId::new(network_id, connection_metadata.remote_peer_id);
         network
-            .write()
             .entry(connection_metadata.remote_peer_id)
             .and_modify(|entry| entry.active_connection = connection_metadata.clone())
-            .or_insert_with(|| PeerInfo::new(connection_metadata));
+            .or_insert_with(|| PeerInfo::new(connection_metadata.clone()));
+        self.update_supported_peers(
+            peer_network_id,
+            network.get(&connection_metadata.remote_peer_id),
+        );
+        self.peer_traffic
+            .write()
+            .insert(peer_network_id, PeerTraffic::default());
+        self.disconnected_peers.write().remove(&peer_network_id);
+        self.notify_subscribers(PeerEvent::PeerConnected(
+            peer_network_id,
+            connection_metadata,
+        ));
+    }
+
+    /// Replaces the connection of a migrating peer with its new connection,
+    /// and marks the peer as connected again
+    pub fn migrate_connection(
+        &self,
+        network_id: NetworkId,
+        connection_metadata: ConnectionMetadata,
+    ) {
+        let mut network = self.get_network(network_id).write();
+        let peer_network_id = PeerNetworkId::new(network_id, connection_metadata.remote_peer_id);
+        network
+            .entry(connection_metadata.remote_peer_id)
+            .and_modify(|entry| {
+                entry.status = PeerState::Connected;
+                entry.active_connection = connection_metadata.clone();
+            })
+            .or_insert_with(|| PeerInfo::new(connection_metadata.clone()));
+        self.update_supported_peers(
+            peer_network_id,
+            network.get(&connection_metadata.remote_peer_id),
+        );
+        self.disconnected_peers.write().remove(&peer_network_id);
+        self.notify_subscribers(PeerEvent::ConnectionMigrated(
+            peer_network_id,
+            connection_metadata,
+        ));
     }
 
     pub fn remove_connection(
@@ -137,25 +287,100 @@ impl PeerMetadataStorage {
         network_id: NetworkId,
         connection_metadata: &ConnectionMetadata,
     ) {
-        let network = self.get_network(network_id);
+        let mut network = self.get_network(network_id).write();
 
         // Don't remove the peer if the connection doesn't match!
-        if let Entry::Occupied(entry) = network.write().entry(connection_metadata.remote_peer_id) {
+        if let Entry::Occupied(entry) = network.entry(connection_metadata.remote_peer_id) {
             // For now, remove the peer entirely, we could in the future have multiple connections for a peer
             if entry.get().active_connection.connection_id == connection_metadata.connection_id {
-                entry.remove();
+                let peer_info = entry.remove();
+                let peer_network_id =
+                    PeerNetworkId::new(network_id, connection_metadata.remote_peer_id);
+                self.update_supported_peers(peer_network_id, None);
+                self.outbound_queues.write().remove(&peer_network_id);
+                self.peer_traffic.write().remove(&peer_network_id);
+                self.disconnected_peers
+                    .write()
+                    .insert(peer_network_id, self.time_service.now());
+                self.notify_subscribers(PeerEvent::PeerDisconnected(
+                    peer_network_id,
+                    peer_info.active_connection,
+                ));
+            }
+        }
+    }
+
+    /// Replaces the metadata of the peer's active connection, if it's still the
+    /// given connection. Subscribers aren't notified, as the metadata is
+    /// updated frequently (e.g., the smoothed RTT).
+    pub fn update_connection_metadata(
+        &self,
+        network_id: NetworkId,
+        connection_metadata: ConnectionMetadata,
+    ) {
+        let mut network = self.get_network(network_id).write();
+        if let Some(peer_info) = network.get_mut(&connection_metadata.remote_peer_id) {
+            if peer_info.active_connection.connection_id == connection_metadata.connection_id {
+                peer_info.active_connection = connection_metadata;
             }
         }
     }
 
+    /// Returns the metadata of the peer's active connection, including the
+    /// negotiated wire features and the smoothed RTT
+    pub fn get_connection_metadata(
+        &self,
+        peer_network_id: &PeerNetworkId,
+    ) -> Option<ConnectionMetadata> {
+        self.read(*peer_network_id)
+            .map(|peer_info| peer_info.active_connection)
+    }
+
+    /// Registers the outbound queue of the peer's active connection
+    pub fn insert_outbound_queue(
+        &self,
+        peer_network_id: PeerNetworkId,
+        outbound_queue: Arc<OutboundQueue>,
+    ) {
+        self.outbound_queues
+            .write()
+            .insert(peer_network_id, outbound_queue);
+    }
+
+    /// Returns the outbound queue of the peer's active connection
+    pub fn get_outbound_queue(
+        &self,
+        peer_network_id: &PeerNetworkId,
+    ) -> Option<Arc<OutboundQueue>> {
+        self.outbound_queues.read().get(peer_network_id).cloned()
+    }
+
+    /// Returns the number of messages (and bytes) queued for the peer, but not
+    /// yet sent. Applications can use it to slow down before messages to the
+    /// peer are dropped.
+    pub fn get_outbound_queue_depth(
+        &self,
+        peer_network_id: &PeerNetworkId,
+    ) -> Option<OutboundQueueDepth> {
+        self.outbound_queues
+            .read()
+            .get(peer_network_id)
+            .map(|outbound_queue| outbound_queue.depth())
+    }
+
     pub fn update_peer_state(
         &self,
         peer_network_id: PeerNetworkId,
         peer_state: PeerState,
     ) -> Result<(), Error> {
-        let network = self.get_network(peer_network_id.network_id());
-        if let Entry::Occupied(mut entry) = network.write().entry(peer_network_id.peer_id()) {
+        let mut network = self.get_network(peer_network_id.network_id()).write();
+        if let Entry::Occupied(mut entry) = network.entry(peer_network_id.peer_id()) {
             entry.get_mut().status = peer_state;
+            self.update_supported_peers(peer_network_id, Some(entry.get()));
+            self.notify_subscribers(PeerEvent::MetadataUpdated(
+                peer_network_id,
+                entry.get().clone(),
+            ));
             Ok(())
         } else {
             Err(Error::Unexpected(format!(
@@ -164,4 +389,574 @@ impl PeerMetadataStorage {
             )))
         }
     }
+
+    /// Returns the connected peers of the given network that support at least
+    /// one of the given protocols. The result of each protocol list is cached,
+    /// and kept up to date as peers connect and disconnect.
+    pub fn get_connected_supported_peers(
+        &self,
+        network_id: NetworkId,
+        protocols: &[ProtocolId],
+    ) -> Vec<PeerNetworkId> {
+        let mut protocols = protocols.to_vec();
+        protocols.sort();
+        protocols.dedup();
+        let key = (network_id, protocols);
+        let into_peer_network_ids = |peer_ids: &HashSet<PeerId>| {
+            peer_ids
+                .iter()
+                .map(|peer_id| PeerNetworkId::new(network_id, *peer_id))
+                .collect()
+        };
+        if let Some(peer_ids) = self.supported_peers.read().get(&key) {
+            self.supported_peers_cache_stats.record_hit();
+            return into_peer_network_ids(peer_ids);
+        }
+        self.supported_peers_cache_stats.record_miss();
+
+        // Populate the entry while holding the network lock, so that no update
+        // can be missed between reading the peers and inserting the entry
+        let network = self.get_network(network_id).read();
+        let mut supported_peers = self.supported_peers.write();
+        let num_entries = supported_peers.len();
+        let peer_ids = supported_peers
+            .entry(key)
+            .or_insert_with_key(|(_, protocols)| {
+                network
+                    .iter()
+                    .filter(|(_, peer_info)| is_supported_peer(peer_info, protocols))
+                    .map(|(peer_id, _)| *peer_id)
+                    .collect()
+            });
+        let peer_network_ids = into_peer_network_ids(peer_ids);
+        self.supported_peers_cache_stats
+            .record_resize(num_entries, supported_peers.len());
+        peer_network_ids
+    }
+
+    /// Adds the given peer to (or removes it from) the cached protocol lists
+    /// it now supports (or no longer supports). Must be called while holding
+    /// the write lock of the peer's network.
+    fn update_supported_peers(&self, peer_network_id: PeerNetworkId, peer_info: Option<&PeerInfo>) {
+        let mut supported_peers = self.supported_peers.write();
+        let mut num_updated_entries = 0;
+        for ((network_id, protocols), peer_ids) in supported_peers.iter_mut() {
+            if *network_id != peer_network_id.network_id() {
+                continue;
+            }
+            let updated =
+                if peer_info.map_or(false, |peer_info| is_supported_peer(peer_info, protocols)) {
+                    peer_ids.insert(peer_network_id.peer_id())
+                } else {
+                    peer_ids.remove(&peer_network_id.peer_id())
+                };
+            if updated {
+                num_updated_entries += 1;
+            }
+        }
+        self.supported_peers_cache_stats
+            .record_invalidations(num_updated_entries);
+    }
+
+    /// Updates the metadata of the given (connected) peer reported by the peer
+    /// monitoring service. Like pings, these updates are too frequent to be
+    /// worth an event each.
+    pub fn update_peer_monitoring_metadata(
+        &self,
+        peer_network_id: PeerNetworkId,
+        monitoring_metadata: PeerMonitoringMetadata,
+    ) {
+        let mut network = self.get_network(peer_network_id.network_id()).write();
+        if let Some(peer_info) = network.get_mut(&peer_network_id.peer_id()) {
+            peer_info.monitoring_metadata = monitoring_metadata;
+        }
+    }
+
+    /// Returns the connected peers (of all networks) that support the given
+    /// protocol, from the best to the worst upstream peer. Peers close to the
+    /// chain tip (i.e., within `MAX_RANKED_LEDGER_VERSION_LAG` versions of the
+    /// highest advertised ledger version) come first, then peers are ranked by
+    /// their distance from the validators and finally by their latency. Peers
+    /// that didn't report (or weren't measured) rank last on each criterion.
+    pub fn get_best_peers_for(&self, protocol: ProtocolId) -> Vec<PeerNetworkId> {
+        let mut candidates = vec![];
+        for network_id in self.networks() {
+            let network = self.get_network(network_id).read();
+            for (peer_id, peer_info) in network.iter() {
+                if is_supported_peer(peer_info, &[protocol]) {
+                    candidates.push((
+                        PeerNetworkId::new(network_id, *peer_id),
+                        peer_info.monitoring_metadata.clone(),
+                    ));
+                }
+            }
+        }
+
+        let highest_synced_version = candidates
+            .iter()
+            .filter_map(|(_, metadata)| metadata.highest_synced_version)
+            .max();
+        let mut ranked_peers: Vec<_> = candidates
+            .into_iter()
+            .map(|(peer, metadata)| {
+                let is_behind = match (metadata.highest_synced_version, highest_synced_version) {
+                    (Some(version), Some(highest_version)) => {
+                        highest_version - version > MAX_RANKED_LEDGER_VERSION_LAG
+                    },
+                    (None, Some(_)) => true,
+                    (_, None) => false,
+                };
+                let distance = metadata.distance_from_validators.unwrap_or(u64::MAX);
+                let latency = metadata
+                    .average_latency
+                    .or_else(|| self.get_peer_latency(&peer))
+                    .unwrap_or(Duration::MAX);
+                ((is_behind, distance, latency), peer)
+            })
+            .collect();
+        ranked_peers.sort();
+        ranked_peers.into_iter().map(|(_, peer)| peer).collect()
+    }
+
+    /// Records the result of a HealthChecker ping to the given peer (i.e., its
+    /// RTT, or `None` if the ping failed). Subscribers aren't notified, as
+    /// pings are too frequent to be worth an event each.
+    pub fn record_ping_result(&self, peer_network_id: PeerNetworkId, rtt: Option<Duration>) {
+        let mut network = self.get_network(peer_network_id.network_id()).write();
+        if let Some(peer_info) = network.get_mut(&peer_network_id.peer_id()) {
+            match rtt {
+                Some(rtt) => peer_info.ping_stats.record_success(rtt),
+                None => peer_info.ping_stats.record_failure(),
+            }
+        }
+    }
+
+    /// Returns the ping statistics of the given (connected) peer
+    pub fn get_peer_ping_stats(&self, peer_network_id: &PeerNetworkId) -> Option<PingStats> {
+        let network = self.get_network(peer_network_id.network_id());
+        network
+            .read()
+            .get(&peer_network_id.peer_id())
+            .map(|peer_info| peer_info.ping_stats.clone())
+    }
+
+    /// Returns the estimated availability of the given (connected) peer, i.e.,
+    /// the fraction of its latest HealthChecker pings that succeeded, or `None`
+    /// if it wasn't pinged yet
+    pub fn get_peer_availability(&self, peer_network_id: &PeerNetworkId) -> Option<f64> {
+        self.get_peer_ping_stats(peer_network_id)
+            .and_then(|ping_stats| ping_stats.availability())
+    }
+
+    /// Updates the score of the given peer according to the observed event.
+    /// Scores are kept across reconnects, so that a misbehaving peer can't
+    /// reset its score by reconnecting.
+    pub fn update_peer_score(&self, peer_network_id: PeerNetworkId, event: PeerScoreEvent) {
+        let now = Instant::now();
+        self.peer_scores
+            .write()
+            .entry(peer_network_id)
+            .or_insert_with(|| PeerScore::new(now))
+            .update_at(event, now);
+    }
+
+    /// Restores the score of the given peer (e.g., from before a restart).
+    /// Scores that were already recorded since are kept.
+    pub fn restore_peer_score(&self, peer_network_id: PeerNetworkId, score: f64) {
+        self.peer_scores
+            .write()
+            .entry(peer_network_id)
+            .or_insert_with(|| PeerScore::restore(score, Instant::now()));
+    }
+
+    /// Returns the current score of the given peer (peers without any
+    /// recorded events have the starting score).
+    pub fn get_peer_score(&self, peer_network_id: &PeerNetworkId) -> f64 {
+        self.peer_scores
+            .read()
+            .get(peer_network_id)
+            .map(|peer_score| peer_score.score())
+            .unwrap_or(STARTING_SCORE)
+    }
+
+    /// Puts the given peer in the penalty box until the given time (e.g., after
+    /// repeatedly exceeding the inbound rate limits). A later existing penalty
+    /// isn't shortened. Expired penalties are pruned along the way.
+    pub fn penalize_peer(&self, peer_network_id: PeerNetworkId, until: Instant, now: Instant) {
+        let mut penalty_box = self.penalty_box.write();
+        penalty_box.retain(|_, expiry| *expiry > now);
+        let expiry = penalty_box.entry(peer_network_id).or_insert(until);
+        *expiry = (*expiry).max(until);
+    }
+
+    /// Returns true iff the given peer is in the penalty box at the given time
+    pub fn is_peer_penalized(&self, peer_network_id: &PeerNetworkId, now: Instant) -> bool {
+        self.penalty_box
+            .read()
+            .get(peer_network_id)
+            .map_or(false, |expiry| *expiry > now)
+    }
+
+    /// Bans the given peer for the given duration (e.g., after it sent invalid
+    /// consensus messages or spam): the peer is disconnected, and its connections
+    /// (in both directions) are refused until the ban expires. The reason of the
+    /// latest ban is kept, and a later existing expiry isn't shortened.
+    pub fn ban_peer(&self, peer_network_id: PeerNetworkId, duration: Duration, reason: String) {
+        let now = self.time_service.now();
+        let expiry = now + duration;
+        let mut banned_peers = self.banned_peers.write();
+        banned_peers.retain(|_, ban| ban.expiry > now);
+        let ban = match banned_peers.entry(peer_network_id) {
+            Entry::Occupied(mut entry) => {
+                let ban = entry.get_mut();
+                ban.reason = reason;
+                ban.expiry = ban.expiry.max(expiry);
+                ban.clone()
+            },
+            Entry::Vacant(entry) => entry.insert(PeerBan { reason, expiry }).clone(),
+        };
+        // The peer managers disconnect the peer on receipt of the event
+        self.notify_subscribers(PeerEvent::PeerBanned(peer_network_id, ban));
+    }
+
+    /// Lifts the ban of the given peer, if any
+    pub fn unban_peer(&self, peer_network_id: &PeerNetworkId) {
+        self.banned_peers.write().remove(peer_network_id);
+    }
+
+    /// Returns the ban of the given peer, if it hasn't expired
+    pub fn get_peer_ban(&self, peer_network_id: &PeerNetworkId) -> Option<PeerBan> {
+        let now = self.time_service.now();
+        self.banned_peers
+            .read()
+            .get(peer_network_id)
+            .filter(|ban| ban.expiry > now)
+            .cloned()
+    }
+
+    /// Returns true iff the given peer is currently banned
+    pub fn is_peer_banned(&self, peer_network_id: &PeerNetworkId) -> bool {
+        self.get_peer_ban(peer_network_id).is_some()
+    }
+
+    /// Returns all peers that are currently banned, and their bans
+    pub fn get_banned_peers(&self) -> HashMap<PeerNetworkId, PeerBan> {
+        let now = self.time_service.now();
+        self.banned_peers
+            .read()
+            .iter()
+            .filter(|(_, ban)| ban.expiry > now)
+            .map(|(peer_network_id, ban)| (*peer_network_id, ban.clone()))
+            .collect()
+    }
+
+    /// Records the dial backoff of the given peer
+    pub fn update_dial_backoff(&self, peer_network_id: PeerNetworkId, state: DialBackoffState) {
+        self.dial_backoffs.write().insert(peer_network_id, state);
+    }
+
+    /// Forgets the dial backoff of the given peer (e.g., once its backoff is reset)
+    pub fn remove_dial_backoff(&self, peer_network_id: &PeerNetworkId) {
+        self.dial_backoffs.write().remove(peer_network_id);
+    }
+
+    /// Returns the dial backoff of the given peer, if it's being (re)dialed
+    pub fn get_dial_backoff(&self, peer_network_id: &PeerNetworkId) -> Option<DialBackoffState> {
+        self.dial_backoffs.read().get(peer_network_id).copied()
+    }
+
+    /// Records an outbound message of the given size to the peer. Only the
+    /// traffic of connected peers is recorded.
+    pub fn record_outbound_message(&self, peer_network_id: &PeerNetworkId, num_bytes: usize) {
+        if let Some(traffic) = self.peer_traffic.write().get_mut(peer_network_id) {
+            traffic.messages_sent += 1;
+            traffic.bytes_sent += num_bytes as u64;
+        }
+    }
+
+    /// Records an inbound message of the given size from the peer
+    pub fn record_inbound_message(&self, peer_network_id: &PeerNetworkId, num_bytes: usize) {
+        if let Some(traffic) = self.peer_traffic.write().get_mut(peer_network_id) {
+            traffic.messages_received += 1;
+            traffic.bytes_received += num_bytes as u64;
+        }
+    }
+
+    /// Records the result of an outbound rpc of an application to the peer
+    pub fn record_rpc_result(&self, peer_network_id: &PeerNetworkId, succeeded: bool) {
+        if let Some(traffic) = self.peer_traffic.write().get_mut(peer_network_id) {
+            if succeeded {
+                traffic.rpcs_succeeded += 1;
+            } else {
+                traffic.rpcs_failed += 1;
+            }
+        }
+    }
+
+    /// Returns the traffic exchanged with the peer over its active connection
+    pub fn get_peer_traffic(&self, peer_network_id: &PeerNetworkId) -> Option<PeerTraffic> {
+        self.peer_traffic.read().get(peer_network_id).copied()
+    }
+
+    /// Returns a report of the health of each connected peer, sorted by network
+    /// and peer id
+    pub fn peer_reports(&self) -> Vec<PeerReport> {
+        let now = Instant::now();
+        let mut peer_reports = Vec::new();
+        for network_id in self.networks() {
+            for (peer_network_id, peer_info) in self.read_all(network_id) {
+                if !peer_info.is_connected() {
+                    continue;
+                }
+                let traffic = self.get_peer_traffic(&peer_network_id).unwrap_or_default();
+                let score = self
+                    .peer_scores
+                    .read()
+                    .get(&peer_network_id)
+                    .map_or(STARTING_SCORE, |peer_score| peer_score.score_at(now));
+                peer_reports.push(PeerReport {
+                    network_id,
+                    peer_id: peer_network_id.peer_id(),
+                    role: peer_info.active_connection.role,
+                    origin: peer_info.active_connection.origin,
+                    traffic,
+                    rpc_success_rate: traffic.rpc_success_rate(),
+                    score,
+                    latency: self.get_peer_latency(&peer_network_id),
+                });
+            }
+        }
+        peer_reports.sort_by_key(|report| (report.network_id, report.peer_id));
+        peer_reports
+    }
+
+    /// Records that the given peers are part of the validator set of the epoch.
+    /// Older epochs never overwrite newer ones.
+    pub fn update_validator_epochs<I: IntoIterator<Item = PeerId>>(
+        &self,
+        network_id: NetworkId,
+        epoch: u64,
+        validators: I,
+    ) {
+        let mut validator_epochs = self.validator_epochs.write();
+        for peer_id in validators {
+            let last_known_epoch = validator_epochs
+                .entry(PeerNetworkId::new(network_id, peer_id))
+                .or_insert(epoch);
+            *last_known_epoch = epoch.max(*last_known_epoch);
+        }
+    }
+
+    /// Returns the last epoch in which the given peer was part of the validator
+    /// set, if it ever was (since the node started)
+    pub fn get_last_known_epoch(&self, peer_network_id: &PeerNetworkId) -> Option<u64> {
+        self.validator_epochs.read().get(peer_network_id).copied()
+    }
+
+    /// Returns the latency of the given peer: the moving average of the
+    /// HealthChecker ping RTT if it was measured (as it reflects the network
+    /// quality alone), otherwise the moving average of the RPC latency. Returns
+    /// `None` if neither a ping nor an RPC to the peer has succeeded yet.
+    pub fn get_peer_latency(&self, peer_network_id: &PeerNetworkId) -> Option<Duration> {
+        self.get_peer_ping_stats(peer_network_id)
+            .and_then(|ping_stats| ping_stats.average_rtt)
+            .or_else(|| {
+                self.peer_scores
+                    .read()
+                    .get(peer_network_id)
+                    .and_then(|peer_score| peer_score.average_latency())
+            })
+    }
+
+    /// Returns a serializable snapshot of all the peers in storage, for
+    /// debugging. Peers for which `is_trusted_peer` returns true are marked
+    /// as trusted.
+    pub fn snapshot<F: Fn(&PeerNetworkId) -> bool>(
+        &self,
+        is_trusted_peer: F,
+    ) -> PeerMetadataSnapshot {
+        let now = Instant::now();
+        let peer_scores = self.peer_scores.read();
+        let networks = self
+            .storage
+            .iter()
+            .map(|(network_id, network)| {
+                let mut peers: Vec<_> = network
+                    .read()
+                    .iter()
+                    .map(|(peer_id, peer_info)| {
+                        let peer_network_id = PeerNetworkId::new(*network_id, *peer_id);
+                        let peer_score = peer_scores.get(&peer_network_id);
+                        PeerSnapshot {
+                            peer_id: *peer_id,
+                            peer_info: peer_info.clone(),
+                            protocols: peer_info
+                                .active_connection
+                                .application_protocols
+                                .iter()
+                                .collect(),
+                            is_trusted: is_trusted_peer(&peer_network_id),
+                            score: peer_score
+                                .map(|peer_score| peer_score.score_at(now))
+                                .unwrap_or(STARTING_SCORE),
+                            average_latency: peer_score
+                                .and_then(|peer_score| peer_score.average_latency()),
+                        }
+                    })
+                    .collect();
+                peers.sort_by_key(|peer| peer.peer_id);
+                (*network_id, peers)
+            })
+            .collect();
+        PeerMetadataSnapshot {
+            networks,
+            num_scored_peers: peer_scores.len(),
+            num_event_subscribers: self.peer_event_sender.receiver_count(),
+            cache_stats: self.cache_stats(),
+        }
+    }
+
+    /// Returns the stats recorder of the peer protocol caches of the network
+    /// clients of this storage
+    pub fn protocol_cache_stats(&self) -> &CacheStatsRecorder {
+        &self.protocol_cache_stats
+    }
+
+    /// Returns the stats of the caches of the storage and its network clients,
+    /// keyed by cache name
+    pub fn cache_stats(&self) -> BTreeMap<String, CacheStats> {
+        [
+            &self.supported_peers_cache_stats,
+            &self.protocol_cache_stats,
+        ]
+        .into_iter()
+        .map(|recorder| (recorder.cache().to_string(), recorder.stats()))
+        .collect()
+    }
+
+    /// Collects the metadata (i.e., the scores, dial backoffs and validator
+    /// epochs) of the disconnected peers of the network that were disconnected
+    /// for longer than the retention. If more than `max_tracked_peers` peers are
+    /// still tracked, the metadata of the peers disconnected the longest is
+    /// collected too. Peer bans and penalties aren't collected, as they expire.
+    /// Returns the number of peers whose metadata was collected.
+    pub fn garbage_collect(
+        &self,
+        network_id: NetworkId,
+        max_tracked_peers: usize,
+        retention: Duration,
+    ) -> usize {
+        let now = self.time_service.now();
+        let connected_peers: HashSet<PeerId> = self
+            .get_network(network_id)
+            .read()
+            .keys()
+            .copied()
+            .collect();
+        let mut peer_scores = self.peer_scores.write();
+        let mut dial_backoffs = self.dial_backoffs.write();
+        let mut validator_epochs = self.validator_epochs.write();
+        let mut disconnected_peers = self.disconnected_peers.write();
+
+        // The peers with metadata that were never connected (e.g., validators
+        // that were only dialed) are considered disconnected from now on
+        for peer_network_id in peer_scores
+            .keys()
+            .chain(dial_backoffs.keys())
+            .chain(validator_epochs.keys())
+        {
+            if peer_network_id.network_id() == network_id
+                && !connected_peers.contains(&peer_network_id.peer_id())
+            {
+                disconnected_peers.entry(*peer_network_id).or_insert(now);
+            }
+        }
+
+        // Collect the expired peers, and the peers disconnected the longest
+        // beyond the capacity
+        let mut candidates: Vec<_> = disconnected_peers
+            .iter()
+            .filter(|(peer_network_id, _)| peer_network_id.network_id() == network_id)
+            .map(|(peer_network_id, disconnected_at)| (*peer_network_id, *disconnected_at))
+            .collect();
+        candidates.sort_by_key(|(_, disconnected_at)| *disconnected_at);
+        let max_disconnected_peers = max_tracked_peers.saturating_sub(connected_peers.len());
+        let num_excess_peers = candidates.len().saturating_sub(max_disconnected_peers);
+        let mut num_collected = 0;
+        for (index, (peer_network_id, disconnected_at)) in candidates.into_iter().enumerate() {
+            let reason = if now.saturating_duration_since(disconnected_at) >= retention {
+                counters::EXPIRED_LABEL
+            } else if index < num_excess_peers {
+                counters::CAPACITY_LABEL
+            } else {
+                break;
+            };
+            peer_scores.remove(&peer_network_id);
+            dial_backoffs.remove(&peer_network_id);
+            validator_epochs.remove(&peer_network_id);
+            disconnected_peers.remove(&peer_network_id);
+            counters::collected_peer_metadata(network_id, reason).inc();
+            num_collected += 1;
+        }
+
+        counters::peer_metadata_entries(network_id, "connected_peers", connected_peers.len());
+        counters::peer_metadata_entries(
+            network_id,
+            "disconnected_peers",
+            num_network_entries(&disconnected_peers, network_id),
+        );
+        counters::peer_metadata_entries(
+            network_id,
+            "peer_scores",
+            num_network_entries(&peer_scores, network_id),
+        );
+        counters::peer_metadata_entries(
+            network_id,
+            "dial_backoffs",
+            num_network_entries(&dial_backoffs, network_id),
+        );
+        counters::peer_metadata_entries(
+            network_id,
+            "validator_epochs",
+            num_network_entries(&validator_epochs, network_id),
+        );
+        num_collected
+    }
+
+    /// Returns all connected peers on the given network, together with their
+    /// scores, sorted from the highest to the lowest score.
+    pub fn get_peers_by_score(&self, network_id: NetworkId) -> Vec<(PeerNetworkId, f64)> {
+        let now = Instant::now();
+        let peer_scores = self.peer_scores.read();
+        let mut peers_and_scores: Vec<_> = self
+            .read_filtered(network_id, |(_, peer_info)| peer_info.is_connected())
+            .into_keys()
+            .map(|peer_network_id| {
+                let score = peer_scores
+                    .get(&peer_network_id)
+                    .map(|peer_score| peer_score.score_at(now))
+                    .unwrap_or(STARTING_SCORE);
+                (peer_network_id, score)
+            })
+            .collect();
+        peers_and_scores.sort_by(|(_, score_a), (_, score_b)| {
+            score_b.partial_cmp(score_a).unwrap_or(Ordering::Equal)
+        });
+        peers_and_scores
+    }
+}
+
+/// Returns the number of entries of the given network in the map
+fn num_network_entries<V>(map: &HashMap<PeerNetworkId, V>, network_id: NetworkId) -> usize {
+    map.keys()
+        .filter(|peer_network_id| peer_network_id.network_id() == network_id)
+        .count()
+}
+
+/// Returns true iff the peer is connected and supports one of the protocols
+fn is_supported_peer(peer_info: &PeerInfo, protocols: &[ProtocolId]) -> bool {
+    peer_info.is_connected()
+        && protocols
+            .iter()
+            .any(|protocol| peer_info.supports_protocol(*protocol))
 }
@@ -3,16 +3,57 @@
 
 use crate::{
     application::{
-        interface::{NetworkClient, NetworkClientInterface},
+        blocking::BlockingNetworkClient,
+        cache_stats::{CacheStats, PEER_PROTOCOLS_CACHE, SUPPORTED_PEERS_CACHE},
+        error::Error,
+        fanout::FanoutPolicy,
+        filters::{
+            and, has_role, is_connected, on_network, with_min_node_version, with_origin,
+            within_distance_from_validators,
+        },
+        interface::{NetworkClient, NetworkClientInterface, NetworkServiceEvents},
+        rate_limit::{OutboundRateLimitConfig, OutboundRateLimiter, RateLimit},
+        scoring::{PeerScore, PeerScoreEvent, STARTING_SCORE},
+        selection::{
+            HighestScorePeerSelector, LatencyWeightedPeerSelector, PeerSelector,
+            RandomPeerSelector, RoundRobinPeerSelector, ScoreWeightedPeerSelector,
+        },
         storage::PeerMetadataStorage,
-        types::{PeerInfo, PeerState},
+        types::{
+            PeerBan, PeerEvent, PeerInfo, PeerMonitoringMetadata, PeerState, PeerTraffic,
+            PING_AVAILABILITY_WINDOW, PING_RTT_BUCKETS_MS,
+        },
+    },
+    counters,
+    error::{NetworkError, NetworkErrorKind},
+    peer_manager::{
+        ConnectionNotification, ConnectionRequestSender, OutboundQueue, OutboundQueueDepth,
+        PeerManagerRequest, PeerManagerRequestSender,
+    },
+    protocols::{
+        network::{
+            Event, NetworkEvents, NetworkSender, NewNetworkEvents, NewNetworkSender, RpcError,
+        },
+        wire::handshake::v1::{ProtocolId, ProtocolIdSet},
     },
     transport::ConnectionMetadata,
 };
-use aptos_config::network_id::{NetworkId, PeerNetworkId};
+use aptos_channels::{aptos_channel, message_queues::QueueStyle};
+use aptos_config::{
+    config::{PeerRole, ProtocolPreferenceProfile},
+    network_id::{NetworkContext, NetworkId, PeerNetworkId},
+};
+use aptos_netcore::transport::ConnectionOrigin;
+use aptos_time_service::TimeService;
 use aptos_types::PeerId;
+use futures::{stream::FusedStream, FutureExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone, Serialize, Deserialize)]
 struct DummyMessage {}
@@ -119,3 +160,1292 @@ fn test_interface() {
         connected_peers(network_client.get_peer_metadata_storage(), network_id).len()
     );
 }
+
+#[test]
+fn test_peers_by_score() {
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let network_id = NetworkId::Validator;
+
+    // Insert 3 connected peers
+    let peers: Vec<_> = (0..3)
+        .map(|_| {
+            let peer_id = PeerId::random();
+            peer_metadata_storage.insert_connection(network_id, ConnectionMetadata::mock(peer_id));
+            PeerNetworkId::new(network_id, peer_id)
+        })
+        .collect();
+
+    // Reward the first peer and penalize the last peer
+    peer_metadata_storage.update_peer_score(
+        peers[0],
+        PeerScoreEvent::RpcSuccess(Duration::from_millis(10)),
+    );
+    peer_metadata_storage.update_peer_score(peers[2], PeerScoreEvent::InvalidMessage);
+
+    // Verify the peers are ordered by score
+    let peers_by_score = peer_metadata_storage.get_peers_by_score(network_id);
+    let ordered_peers: Vec<_> = peers_by_score.iter().map(|(peer, _)| *peer).collect();
+    assert_eq!(ordered_peers, peers);
+    assert!(peer_metadata_storage.get_peer_score(&peers[0]) > STARTING_SCORE);
+    assert!(peer_metadata_storage.get_peer_score(&peers[2]) < STARTING_SCORE);
+
+    // Disconnecting peers are not returned
+    peer_metadata_storage
+        .update_peer_state(peers[0], PeerState::Disconnecting)
+        .unwrap();
+    assert_eq!(
+        2,
+        peer_metadata_storage.get_peers_by_score(network_id).len()
+    );
+}
+
+#[test]
+fn test_peer_score_decay() {
+    let now = Instant::now();
+    let mut peer_score = PeerScore::new(now);
+    peer_score.update_at(PeerScoreEvent::RpcTimeout, now);
+    let penalized_score = peer_score.score_at(now);
+    assert!(penalized_score < STARTING_SCORE);
+
+    // The score moves back towards the starting score over time
+    let later_score = peer_score.score_at(now + Duration::from_secs(600));
+    assert!(later_score > penalized_score);
+    assert!(later_score < STARTING_SCORE);
+}
+
+#[test]
+fn test_outbound_rate_limits() {
+    let limited_protocol = ProtocolId::MempoolDirectSend;
+    let rate_limit = RateLimit {
+        bucket_size: 2,
+        fill_rate: 1,
+    };
+    let outbound_rate_limiter = OutboundRateLimiter::new(HashMap::from([(
+        limited_protocol,
+        OutboundRateLimitConfig {
+            aggregate: Some(RateLimit {
+                bucket_size: 3,
+                fill_rate: 1,
+            }),
+            per_peer: Some(rate_limit),
+        },
+    )]));
+    let peer_1 = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+    let peer_2 = PeerNetworkId::new(NetworkId::Public, PeerId::random());
+
+    // Each peer is limited by its own bucket
+    for _ in 0..rate_limit.bucket_size {
+        outbound_rate_limiter
+            .try_acquire(limited_protocol, &peer_1)
+            .unwrap();
+    }
+    assert!(matches!(
+        outbound_rate_limiter.try_acquire(limited_protocol, &peer_1),
+        Err(Error::RateLimited(_))
+    ));
+
+    // All peers are limited by the aggregate bucket
+    outbound_rate_limiter
+        .try_acquire(limited_protocol, &peer_2)
+        .unwrap();
+    assert!(matches!(
+        outbound_rate_limiter.try_acquire(limited_protocol, &peer_2),
+        Err(Error::RateLimited(_))
+    ));
+
+    // Protocols without limits are never limited
+    for _ in 0..10 {
+        outbound_rate_limiter
+            .try_acquire(ProtocolId::ConsensusRpcBcs, &peer_1)
+            .unwrap();
+    }
+}
+
+/// Creates a network client for the given network, and returns it along
+/// with the receiver of the requests it sends to the peer manager
+fn build_network_client(
+    network_id: NetworkId,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+) -> (
+    NetworkClient<DummyMessage>,
+    aptos_channel::Receiver<(PeerId, ProtocolId), PeerManagerRequest>,
+) {
+    let (peer_mgr_reqs_tx, peer_mgr_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let (connection_reqs_tx, _connection_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+    let network_sender = NetworkSender::new(
+        PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+        ConnectionRequestSender::new(connection_reqs_tx),
+    );
+    let network_client = NetworkClient::new(
+        vec![],
+        vec![ProtocolId::ConsensusRpcBcs],
+        HashMap::from([(network_id, network_sender)]),
+        peer_metadata_storage,
+    );
+    (network_client, peer_mgr_reqs_rx)
+}
+
+/// Inserts a connection for a new peer that supports the given protocol
+fn insert_peer_with_protocol(
+    peer_metadata_storage: &PeerMetadataStorage,
+    network_id: NetworkId,
+    protocol_id: ProtocolId,
+) -> PeerNetworkId {
+    let peer_id = PeerId::random();
+    let mut connection = ConnectionMetadata::mock(peer_id);
+    connection.application_protocols = ProtocolIdSet::from_iter([protocol_id]);
+    peer_metadata_storage.insert_connection(network_id, connection);
+    PeerNetworkId::new(network_id, peer_id)
+}
+
+#[test]
+fn test_broadcast_dedup() {
+    let network_id = NetworkId::Public;
+    let protocol_id = ProtocolId::MempoolDirectSend;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (peer_mgr_reqs_tx, mut peer_mgr_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let (connection_reqs_tx, _connection_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+    let network_sender = NetworkSender::new(
+        PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+        ConnectionRequestSender::new(connection_reqs_tx),
+    );
+    let time_service = TimeService::mock();
+    let network_client: NetworkClient<u64> = NetworkClient::new(
+        vec![protocol_id],
+        vec![],
+        HashMap::from([(network_id, network_sender)]),
+        peer_metadata_storage.clone(),
+    )
+    .with_broadcast_dedup(Duration::from_secs(10), time_service.clone());
+    let peer_1 = insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let peer_2 = insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let mut recipients = || {
+        let mut recipients = vec![];
+        while let Some(Some(request)) = peer_mgr_reqs_rx.next().now_or_never() {
+            match request {
+                PeerManagerRequest::SendDirectSend(peer_id, _) => recipients.push(peer_id),
+                request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+            }
+        }
+        recipients.sort();
+        recipients
+    };
+
+    // Identical messages are only sent once to each peer within the window
+    network_client.send_to_peers(1, &[peer_1]).unwrap();
+    assert_eq!(recipients(), vec![peer_1.peer_id()]);
+    network_client.send_to_peers(1, &[peer_1, peer_2]).unwrap();
+    assert_eq!(recipients(), vec![peer_2.peer_id()]);
+    network_client.send_to_peers(2, &[peer_1]).unwrap();
+    assert_eq!(recipients(), vec![peer_1.peer_id()]);
+
+    // Messages sent directly to a peer are never skipped
+    network_client.send_to_peer(1, peer_1).unwrap();
+    assert_eq!(recipients(), vec![peer_1.peer_id()]);
+
+    // Once the window has passed, the message is sent again
+    time_service.into_mock().advance_secs(10);
+    network_client.send_to_peers(1, &[peer_1, peer_2]).unwrap();
+    let mut expected_recipients = vec![peer_1.peer_id(), peer_2.peer_id()];
+    expected_recipients.sort();
+    assert_eq!(recipients(), expected_recipients);
+}
+
+#[tokio::test]
+async fn test_staggered_fanout() {
+    let network_id = NetworkId::Public;
+    let protocol_id = ProtocolId::MempoolDirectSend;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (peer_mgr_reqs_tx, mut peer_mgr_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let (connection_reqs_tx, _connection_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+    let network_sender = NetworkSender::new(
+        PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+        ConnectionRequestSender::new(connection_reqs_tx),
+    );
+    let time_service = TimeService::mock();
+    let network_client: NetworkClient<u64> = NetworkClient::new(
+        vec![protocol_id],
+        vec![],
+        HashMap::from([(network_id, network_sender)]),
+        peer_metadata_storage.clone(),
+    )
+    .with_time_service(time_service.clone());
+    let peers: Vec<_> = (0..4)
+        .map(|_| insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id))
+        .collect();
+
+    // The message is only sent to the first peers immediately
+    let delay = Duration::from_secs(1);
+    let fanout_handle = network_client
+        .send_to_peers_with_fanout(1, &peers, FanoutPolicy::new(2, delay))
+        .unwrap();
+    let mut recipients = vec![];
+    while let Some(Some(request)) = peer_mgr_reqs_rx.next().now_or_never() {
+        match request {
+            PeerManagerRequest::SendDirectSend(peer_id, _) => recipients.push(peer_id),
+            request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+        }
+    }
+    recipients.sort();
+    let mut expected_recipients = vec![peers[0].peer_id(), peers[1].peer_id()];
+    expected_recipients.sort();
+    assert_eq!(recipients, expected_recipients);
+
+    // Once the delay has elapsed, it's sent to the remaining peers that
+    // haven't seen it
+    fanout_handle.mark_seen(peers[2]);
+    time_service.into_mock().advance_async(delay).await;
+    match peer_mgr_reqs_rx.next().await.unwrap() {
+        PeerManagerRequest::SendDirectSend(peer_id, _) => assert_eq!(peer_id, peers[3].peer_id()),
+        request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+    }
+    assert!(peer_mgr_reqs_rx.next().now_or_never().is_none());
+}
+
+#[tokio::test]
+async fn test_cancel_outbound_rpc() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (network_client, mut peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    let peer = insert_peer_with_protocol(
+        &peer_metadata_storage,
+        network_id,
+        ProtocolId::ConsensusRpcBcs,
+    );
+
+    // Send the rpc, and cancel it once the request reaches the peer manager
+    let cancellation = CancellationToken::new();
+    let rpc = network_client.send_to_peer_rpc_with_cancellation(
+        DummyMessage {},
+        Duration::from_secs(60),
+        peer,
+        cancellation.clone(),
+    );
+    let cancel = async {
+        let request = peer_mgr_reqs_rx.next().await.unwrap();
+        let mut rpc_request = match request {
+            PeerManagerRequest::SendRpc(_, rpc_request) => rpc_request,
+            request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+        };
+        cancellation.cancel();
+
+        // The response channel is dropped without waiting for the timeout
+        rpc_request.res_tx.cancellation().await;
+    };
+    let (result, ()) = futures::join!(rpc, cancel);
+    assert!(matches!(result, Err(Error::Canceled(_))));
+}
+
+#[tokio::test]
+async fn test_rpc_retries_on_alternate_peers() {
+    let network_id = NetworkId::Validator;
+    let protocol_id = ProtocolId::ConsensusRpcBcs;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (network_client, mut peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    let peer_1 = insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let peer_2 = insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let _peer_3 =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+
+    // Make peer 1 the preferred peer
+    peer_metadata_storage.update_peer_score(peer_1, PeerScoreEvent::RpcSuccess(Duration::ZERO));
+
+    // The first peer times out, and the second one responds
+    let rpc = network_client.send_rpc_with_retries(
+        DummyMessage {},
+        protocol_id,
+        Duration::from_secs(1),
+        &HighestScorePeerSelector,
+        3,
+    );
+    let respond = async {
+        for (expected_peer, response) in [
+            (peer_1, Err(RpcError::TimedOut)),
+            (
+                peer_2,
+                Ok(protocol_id.to_bytes(&DummyMessage {}).unwrap().into()),
+            ),
+        ] {
+            match peer_mgr_reqs_rx.next().await.unwrap() {
+                PeerManagerRequest::SendRpc(peer_id, rpc_request) => {
+                    assert_eq!(peer_id, expected_peer.peer_id());
+                    assert_eq!(rpc_request.protocol_id, protocol_id);
+                    rpc_request.res_tx.send(response).unwrap();
+                },
+                request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+            }
+        }
+    };
+    let (result, ()) = futures::join!(rpc, respond);
+    assert_eq!(result.unwrap().0, peer_2);
+
+    // Only the peers that support the protocol are tried
+    let rpc = network_client.send_rpc_with_retries(
+        DummyMessage {},
+        protocol_id,
+        Duration::from_secs(1),
+        &RandomPeerSelector,
+        3,
+    );
+    let respond = async {
+        for _ in 0..2 {
+            match peer_mgr_reqs_rx.next().await.unwrap() {
+                PeerManagerRequest::SendRpc(_, rpc_request) => {
+                    rpc_request.res_tx.send(Err(RpcError::TimedOut)).unwrap();
+                },
+                request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+            }
+        }
+    };
+    let (result, ()) = futures::join!(rpc, respond);
+    assert!(matches!(result, Err(Error::Timeout(_))));
+
+    // Errors that aren't retryable are returned right away
+    let rpc = network_client.send_rpc_with_retries(
+        DummyMessage {},
+        protocol_id,
+        Duration::from_secs(1),
+        &RandomPeerSelector,
+        3,
+    );
+    let respond = async {
+        match peer_mgr_reqs_rx.next().await.unwrap() {
+            PeerManagerRequest::SendRpc(_, rpc_request) => {
+                rpc_request
+                    .res_tx
+                    .send(Err(RpcError::InvalidRpcResponse))
+                    .unwrap();
+            },
+            request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+        }
+    };
+    let (result, ()) = futures::join!(rpc, respond);
+    assert!(matches!(result, Err(Error::RpcError(_))));
+    assert!(peer_mgr_reqs_rx.next().now_or_never().is_none());
+}
+
+#[tokio::test]
+async fn test_send_to_peers_rpc() {
+    let network_id = NetworkId::Validator;
+    let protocol_id = ProtocolId::ConsensusRpcBcs;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (network_client, mut peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    let responding_peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let timed_out_peer = insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let disconnected_peer = PeerNetworkId::new(network_id, PeerId::random());
+
+    // The duplicate peer is only sent a single rpc
+    let rpc = network_client.send_to_peers_rpc(DummyMessage {}, Duration::from_secs(1), &[
+        responding_peer,
+        disconnected_peer,
+        timed_out_peer,
+        responding_peer,
+    ]);
+    let respond = async {
+        for _ in 0..2 {
+            match peer_mgr_reqs_rx.next().await.unwrap() {
+                PeerManagerRequest::SendRpc(peer_id, rpc_request) => {
+                    let response = if peer_id == responding_peer.peer_id() {
+                        Ok(protocol_id.to_bytes(&DummyMessage {}).unwrap().into())
+                    } else {
+                        assert_eq!(peer_id, timed_out_peer.peer_id());
+                        Err(RpcError::TimedOut)
+                    };
+                    rpc_request.res_tx.send(response).unwrap();
+                },
+                request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+            }
+        }
+    };
+    let (results, ()) = futures::join!(rpc, respond);
+    assert!(peer_mgr_reqs_rx.next().now_or_never().is_none());
+
+    // Each peer has its own result, in the order of the peers
+    assert_eq!(
+        results.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+        vec![responding_peer, disconnected_peer, timed_out_peer]
+    );
+    assert!(results[0].1.is_ok());
+    assert!(matches!(results[1].1, Err(Error::NoConnection(_))));
+    assert!(matches!(results[2].1, Err(Error::Timeout(_))));
+}
+
+#[test]
+fn test_blocking_network_client() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let network_id = NetworkId::Validator;
+    let protocol_id = ProtocolId::ConsensusRpcBcs;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (network_client, mut peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    let peer = insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let blocking_client = BlockingNetworkClient::new(network_client, runtime.handle().clone());
+
+    // The rpc blocks the calling thread until the peer responds
+    runtime.spawn(async move {
+        match peer_mgr_reqs_rx.next().await.unwrap() {
+            PeerManagerRequest::SendRpc(_, rpc_request) => {
+                let response = protocol_id.to_bytes(&DummyMessage {}).unwrap();
+                rpc_request.res_tx.send(Ok(response.into())).unwrap();
+            },
+            request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+        }
+    });
+    let response = blocking_client.send_to_peer_rpc(DummyMessage {}, Duration::from_secs(10), peer);
+    assert!(response.is_ok());
+
+    // Blocking from within an async context fails, rather than blocking the runtime
+    let result = runtime.block_on(async {
+        blocking_client.send_to_peer_rpc(DummyMessage {}, Duration::from_secs(10), peer)
+    });
+    assert!(matches!(result, Err(Error::UnexpectedError(_))));
+}
+
+#[test]
+fn test_error_retryability() {
+    let peer_id = PeerId::random();
+    for (rpc_error, retryable) in [
+        (RpcError::NotConnected(peer_id), true),
+        (RpcError::TimedOut, true),
+        (RpcError::TooManyPending(100), true),
+        (RpcError::Busy, true),
+        (RpcError::Expired, true),
+        (RpcError::UnexpectedResponseChannelCancel, true),
+        (RpcError::InvalidRpcResponse, false),
+    ] {
+        let error = Error::from(rpc_error);
+        assert_eq!(error.is_retryable(), retryable, "{:?}", error);
+    }
+    assert!(matches!(Error::from(RpcError::TimedOut), Error::Timeout(_)));
+
+    // Disconnected peers are reported as such, other network errors aren't retried
+    let error = Error::from(NetworkError::from(NetworkErrorKind::NotConnected));
+    assert!(matches!(error, Error::NoConnection(_)));
+    assert!(error.is_retryable());
+    let error = Error::from(NetworkError::from(NetworkErrorKind::BcsError));
+    assert!(matches!(error, Error::NetworkError(_)));
+    assert!(!error.is_retryable());
+}
+
+#[test]
+fn test_no_common_protocol() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (network_client, _peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    let peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+    let unknown_peer = PeerNetworkId::new(network_id, PeerId::random());
+
+    let error = network_client
+        .send_to_peer(DummyMessage {}, peer)
+        .unwrap_err();
+    assert!(matches!(error, Error::NoCommonProtocol(_)));
+    assert!(!error.is_retryable());
+    let error = network_client
+        .send_to_peer(DummyMessage {}, unknown_peer)
+        .unwrap_err();
+    assert!(matches!(error, Error::NoConnection(_)));
+    assert!(error.is_retryable());
+}
+
+#[test]
+fn test_protocol_preference_profiles() {
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (peer_mgr_reqs_tx, mut peer_mgr_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 10, None);
+    let (connection_reqs_tx, _connection_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+    let network_sender = NetworkSender::new(
+        PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+        ConnectionRequestSender::new(connection_reqs_tx),
+    );
+    let network_client: NetworkClient<u64> = NetworkClient::new(
+        vec![
+            ProtocolId::ConsensusDirectSendJson,
+            ProtocolId::ConsensusDirectSendCompressed,
+            ProtocolId::ConsensusDirectSendBcs,
+        ],
+        vec![],
+        HashMap::from([
+            (NetworkId::Validator, network_sender.clone()),
+            (NetworkId::Public, network_sender.clone()),
+            (NetworkId::Vfn, network_sender),
+        ]),
+        peer_metadata_storage.clone(),
+    )
+    .with_protocol_preference_profiles(HashMap::from([
+        (NetworkId::Validator, ProtocolPreferenceProfile::BcsFirst),
+        (
+            NetworkId::Public,
+            ProtocolPreferenceProfile::CompressedFirst,
+        ),
+    ]));
+    let mut sent_protocol = |peer: PeerNetworkId| {
+        network_client.send_to_peer(0, peer).unwrap();
+        match peer_mgr_reqs_rx.next().now_or_never() {
+            Some(Some(PeerManagerRequest::SendDirectSend(_, message))) => message.protocol_id,
+            request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+        }
+    };
+
+    // Each network picks the protocol preferred by its profile
+    let all_protocols = ProtocolIdSet::from_iter([
+        ProtocolId::ConsensusDirectSendJson,
+        ProtocolId::ConsensusDirectSendCompressed,
+        ProtocolId::ConsensusDirectSendBcs,
+    ]);
+    let insert_peer = |network_id, protocols: ProtocolIdSet| {
+        let peer_id = PeerId::random();
+        let mut connection = ConnectionMetadata::mock(peer_id);
+        connection.application_protocols = protocols;
+        peer_metadata_storage.insert_connection(network_id, connection);
+        PeerNetworkId::new(network_id, peer_id)
+    };
+    let validator_peer = insert_peer(NetworkId::Validator, all_protocols.clone());
+    let public_peer = insert_peer(NetworkId::Public, all_protocols.clone());
+    let vfn_peer = insert_peer(NetworkId::Vfn, all_protocols);
+    assert_eq!(
+        sent_protocol(validator_peer),
+        ProtocolId::ConsensusDirectSendBcs
+    );
+    assert_eq!(
+        sent_protocol(public_peer),
+        ProtocolId::ConsensusDirectSendCompressed
+    );
+    assert_eq!(sent_protocol(vfn_peer), ProtocolId::ConsensusDirectSendJson);
+
+    // Less preferred protocols are picked if the peer doesn't support the others
+    let json_peer = insert_peer(
+        NetworkId::Validator,
+        ProtocolIdSet::from_iter([ProtocolId::ConsensusDirectSendJson]),
+    );
+    assert_eq!(
+        sent_protocol(json_peer),
+        ProtocolId::ConsensusDirectSendJson
+    );
+}
+
+#[test]
+fn test_peer_selectors() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let (network_client, _peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    let mut peers: Vec<_> = (0..3)
+        .map(|_| {
+            insert_peer_with_protocol(
+                &peer_metadata_storage,
+                network_id,
+                ProtocolId::ConsensusRpcBcs,
+            )
+        })
+        .collect();
+    peers.sort();
+
+    // Peers without any of the client's protocols are not available
+    insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+
+    // The round-robin selector prefers each peer in turn
+    let round_robin = RoundRobinPeerSelector::default();
+    for i in 0..6 {
+        let ranked_peers = network_client.get_available_peers_ranked(&round_robin);
+        assert_eq!(ranked_peers.len(), peers.len());
+        assert_eq!(ranked_peers[0], peers[i % peers.len()]);
+    }
+
+    // The highest score selector prefers the peers with the best scores
+    peer_metadata_storage.update_peer_score(peers[2], PeerScoreEvent::RpcSuccess(Duration::ZERO));
+    peer_metadata_storage.update_peer_score(peers[0], PeerScoreEvent::InvalidMessage);
+    assert_eq!(
+        network_client.get_available_peers_ranked(&HighestScorePeerSelector),
+        vec![peers[2], peers[1], peers[0]]
+    );
+
+    // The random selectors rank all the available peers
+    let selectors: [&dyn PeerSelector; 3] = [
+        &RandomPeerSelector,
+        &ScoreWeightedPeerSelector,
+        &LatencyWeightedPeerSelector,
+    ];
+    for selector in selectors {
+        let mut ranked_peers = network_client.get_available_peers_ranked(selector);
+        ranked_peers.sort();
+        assert_eq!(ranked_peers, peers);
+    }
+}
+
+#[test]
+fn test_peer_latency() {
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let peer = PeerNetworkId::new(NetworkId::Validator, PeerId::random());
+    assert_eq!(peer_metadata_storage.get_peer_latency(&peer), None);
+
+    // The latency is a moving average of the successful rpcs
+    peer_metadata_storage
+        .update_peer_score(peer, PeerScoreEvent::RpcSuccess(Duration::from_millis(100)));
+    assert_eq!(
+        peer_metadata_storage.get_peer_latency(&peer),
+        Some(Duration::from_millis(100))
+    );
+    peer_metadata_storage
+        .update_peer_score(peer, PeerScoreEvent::RpcSuccess(Duration::from_millis(500)));
+    peer_metadata_storage.update_peer_score(peer, PeerScoreEvent::RpcTimeout);
+    let latency = peer_metadata_storage.get_peer_latency(&peer).unwrap();
+    assert!(latency > Duration::from_millis(100) && latency < Duration::from_millis(500));
+}
+
+#[test]
+fn test_connected_supported_peers() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let protocols = [ProtocolId::MempoolRpc, ProtocolId::ConsensusRpcBcs];
+    let supported_peers = || {
+        let mut peers = peer_metadata_storage.get_connected_supported_peers(network_id, &protocols);
+        peers.sort();
+        peers
+    };
+    assert!(supported_peers().is_empty());
+
+    // Connecting peers updates the cached entry
+    let mempool_peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+    let consensus_peer = insert_peer_with_protocol(
+        &peer_metadata_storage,
+        network_id,
+        ProtocolId::ConsensusRpcBcs,
+    );
+    let _other_peer = insert_peer_with_protocol(
+        &peer_metadata_storage,
+        network_id,
+        ProtocolId::HealthCheckerRpc,
+    );
+    let mut expected_peers = vec![mempool_peer, consensus_peer];
+    expected_peers.sort();
+    assert_eq!(supported_peers(), expected_peers);
+
+    // The order of the protocols doesn't matter
+    let mut reversed_peers = peer_metadata_storage.get_connected_supported_peers(network_id, &[
+        ProtocolId::ConsensusRpcBcs,
+        ProtocolId::MempoolRpc,
+    ]);
+    reversed_peers.sort();
+    assert_eq!(reversed_peers, expected_peers);
+
+    // Peers that aren't connected (or are gone) are removed
+    peer_metadata_storage
+        .update_peer_state(mempool_peer, PeerState::Disconnecting)
+        .unwrap();
+    assert_eq!(supported_peers(), vec![consensus_peer]);
+    peer_metadata_storage.remove(&consensus_peer);
+    assert!(supported_peers().is_empty());
+
+    // And added back once they are
+    peer_metadata_storage
+        .update_peer_state(mempool_peer, PeerState::Connected)
+        .unwrap();
+    assert_eq!(supported_peers(), vec![mempool_peer]);
+}
+
+#[test]
+fn test_peers_matching_filters() {
+    let peer_metadata_storage =
+        PeerMetadataStorage::new(&[NetworkId::Validator, NetworkId::Public]);
+    let insert_peer = |network_id, role, origin, distance, version: &str| {
+        let peer_id = PeerId::random();
+        let peer = PeerNetworkId::new(network_id, peer_id);
+        peer_metadata_storage.insert_connection(
+            network_id,
+            ConnectionMetadata::mock_with_role_and_origin(peer_id, role, origin),
+        );
+        peer_metadata_storage.update_peer_monitoring_metadata(peer, PeerMonitoringMetadata {
+            distance_from_validators: Some(distance),
+            node_version: Some(version.into()),
+            ..PeerMonitoringMetadata::default()
+        });
+        peer
+    };
+    let validator = insert_peer(
+        NetworkId::Validator,
+        PeerRole::Validator,
+        ConnectionOrigin::Outbound,
+        0,
+        "1.2.0",
+    );
+    let vfn = insert_peer(
+        NetworkId::Public,
+        PeerRole::ValidatorFullNode,
+        ConnectionOrigin::Outbound,
+        1,
+        "1.10.1",
+    );
+    let pfn = insert_peer(
+        NetworkId::Public,
+        PeerRole::Unknown,
+        ConnectionOrigin::Inbound,
+        2,
+        "1.1",
+    );
+    let matching = |predicate: &dyn Fn(&PeerNetworkId, &PeerInfo) -> bool| {
+        let mut peers = peer_metadata_storage.get_peers_matching(predicate);
+        peers.sort();
+        peers
+    };
+    let sorted = |mut peers: Vec<PeerNetworkId>| {
+        peers.sort();
+        peers
+    };
+
+    assert_eq!(matching(&is_connected()), sorted(vec![validator, vfn, pfn]));
+    assert_eq!(matching(&has_role(PeerRole::Validator)), vec![validator]);
+    assert_eq!(
+        matching(&on_network(NetworkId::Public)),
+        sorted(vec![vfn, pfn])
+    );
+    assert_eq!(matching(&with_origin(ConnectionOrigin::Inbound)), vec![pfn]);
+    assert_eq!(
+        matching(&within_distance_from_validators(1)),
+        sorted(vec![validator, vfn])
+    );
+    // Versions are compared numerically, component by component
+    assert_eq!(
+        matching(&with_min_node_version("1.2")),
+        sorted(vec![validator, vfn])
+    );
+    assert_eq!(
+        matching(&and(
+            on_network(NetworkId::Public),
+            with_origin(ConnectionOrigin::Outbound)
+        )),
+        vec![vfn]
+    );
+
+    // Disconnecting peers don't match
+    peer_metadata_storage
+        .update_peer_state(pfn, PeerState::Disconnecting)
+        .unwrap();
+    assert_eq!(matching(&is_connected()), sorted(vec![validator, vfn]));
+}
+
+#[test]
+fn test_best_peers_for() {
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let insert_peer = |protocol, monitoring_metadata| {
+        let peer_id = PeerId::random();
+        let peer = PeerNetworkId::new(NetworkId::Validator, peer_id);
+        let mut connection = ConnectionMetadata::mock(peer_id);
+        connection.application_protocols = ProtocolIdSet::from_iter([protocol]);
+        peer_metadata_storage.insert_connection(NetworkId::Validator, connection);
+        peer_metadata_storage.update_peer_monitoring_metadata(peer, monitoring_metadata);
+        peer
+    };
+    let monitoring_metadata = |distance, version, latency_ms| PeerMonitoringMetadata {
+        distance_from_validators: Some(distance),
+        highest_synced_version: Some(version),
+        average_latency: Some(Duration::from_millis(latency_ms)),
+        ..PeerMonitoringMetadata::default()
+    };
+
+    // A validator that is far behind the chain tip
+    let lagging_peer = insert_peer(
+        ProtocolId::MempoolDirectSend,
+        monitoring_metadata(0, 1_000, 10),
+    );
+    let slow_peer = insert_peer(
+        ProtocolId::MempoolDirectSend,
+        monitoring_metadata(1, 50_000, 100),
+    );
+    let fast_peer = insert_peer(
+        ProtocolId::MempoolDirectSend,
+        monitoring_metadata(1, 45_000, 20),
+    );
+    let unknown_peer = insert_peer(
+        ProtocolId::MempoolDirectSend,
+        PeerMonitoringMetadata::default(),
+    );
+    let unsupported_peer = insert_peer(
+        ProtocolId::ConsensusRpcBcs,
+        monitoring_metadata(0, 50_000, 1),
+    );
+
+    assert_eq!(
+        peer_metadata_storage.get_best_peers_for(ProtocolId::MempoolDirectSend),
+        vec![fast_peer, slow_peer, lagging_peer, unknown_peer]
+    );
+    assert_eq!(
+        peer_metadata_storage.get_best_peers_for(ProtocolId::ConsensusRpcBcs),
+        vec![unsupported_peer]
+    );
+
+    // Disconnecting peers aren't returned
+    peer_metadata_storage
+        .update_peer_state(fast_peer, PeerState::Disconnecting)
+        .unwrap();
+    assert_eq!(
+        peer_metadata_storage.get_best_peers_for(ProtocolId::MempoolDirectSend),
+        vec![slow_peer, lagging_peer, unknown_peer]
+    );
+}
+
+#[test]
+fn test_peer_penalty_box() {
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let peer = PeerNetworkId::new(NetworkId::Validator, PeerId::random());
+    let other_peer = PeerNetworkId::new(NetworkId::Validator, PeerId::random());
+    let now = Instant::now();
+    assert!(!peer_metadata_storage.is_peer_penalized(&peer, now));
+
+    peer_metadata_storage.penalize_peer(peer, now + Duration::from_secs(60), now);
+    assert!(peer_metadata_storage.is_peer_penalized(&peer, now));
+    assert!(!peer_metadata_storage.is_peer_penalized(&other_peer, now));
+
+    // A shorter penalty doesn't shorten the existing one
+    peer_metadata_storage.penalize_peer(peer, now + Duration::from_secs(10), now);
+    assert!(peer_metadata_storage.is_peer_penalized(&peer, now + Duration::from_secs(30)));
+    assert!(!peer_metadata_storage.is_peer_penalized(&peer, now + Duration::from_secs(60)));
+}
+
+#[test]
+fn test_peer_bans() {
+    let time_service = TimeService::mock();
+    let mock_time = time_service.clone().into_mock();
+    let peer_metadata_storage =
+        PeerMetadataStorage::new_with_time_service(&[NetworkId::Validator], time_service);
+    let mut peer_events = peer_metadata_storage.subscribe();
+    let peer = PeerNetworkId::new(NetworkId::Validator, PeerId::random());
+    let other_peer = PeerNetworkId::new(NetworkId::Validator, PeerId::random());
+    assert!(!peer_metadata_storage.is_peer_banned(&peer));
+
+    // Bans are recorded with their reason, and announced to the subscribers
+    peer_metadata_storage.ban_peer(peer, Duration::from_secs(60), "spam".into());
+    let ban = peer_metadata_storage.get_peer_ban(&peer).unwrap();
+    assert_eq!(ban.reason, "spam");
+    assert_eq!(
+        peer_events.try_recv().unwrap(),
+        PeerEvent::PeerBanned(peer, ban.clone())
+    );
+    assert!(!peer_metadata_storage.is_peer_banned(&other_peer));
+
+    // A shorter ban updates the reason, but doesn't shorten the existing ban
+    peer_metadata_storage.ban_peer(peer, Duration::from_secs(10), "invalid vote".into());
+    mock_time.advance_secs(30);
+    assert_eq!(
+        peer_metadata_storage.get_peer_ban(&peer),
+        Some(PeerBan {
+            reason: "invalid vote".into(),
+            expiry: ban.expiry,
+        })
+    );
+    assert_eq!(peer_metadata_storage.get_banned_peers().len(), 1);
+
+    // Bans expire, or can be lifted
+    mock_time.advance_secs(30);
+    assert!(!peer_metadata_storage.is_peer_banned(&peer));
+    peer_metadata_storage.ban_peer(other_peer, Duration::from_secs(60), "spam".into());
+    peer_metadata_storage.unban_peer(&other_peer);
+    assert!(peer_metadata_storage.get_banned_peers().is_empty());
+}
+
+#[test]
+fn test_peer_metadata_garbage_collection() {
+    let time_service = TimeService::mock();
+    let mock_time = time_service.clone().into_mock();
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage =
+        PeerMetadataStorage::new_with_time_service(&[network_id], time_service);
+    let retention = Duration::from_secs(60);
+
+    // Connects the peer (and records its epoch), and disconnects it if requested
+    let add_peer = |disconnect: bool| {
+        let connection = ConnectionMetadata::mock(PeerId::random());
+        let peer = PeerNetworkId::new(network_id, connection.remote_peer_id);
+        peer_metadata_storage.insert_connection(network_id, connection.clone());
+        peer_metadata_storage.update_validator_epochs(network_id, 1, [peer.peer_id()]);
+        if disconnect {
+            peer_metadata_storage.remove_connection(network_id, &connection);
+        }
+        peer
+    };
+    let connected_peer = add_peer(false);
+    let old_peer = add_peer(true);
+    mock_time.advance_secs(30);
+    let recent_peer = add_peer(true);
+    assert_eq!(
+        peer_metadata_storage.garbage_collect(network_id, 10, retention),
+        0
+    );
+
+    // The metadata of peers disconnected for longer than the retention is collected
+    mock_time.advance_secs(40);
+    assert_eq!(
+        peer_metadata_storage.garbage_collect(network_id, 10, retention),
+        1
+    );
+    assert_eq!(peer_metadata_storage.get_last_known_epoch(&old_peer), None);
+    assert_eq!(
+        peer_metadata_storage.get_last_known_epoch(&recent_peer),
+        Some(1)
+    );
+
+    // Beyond the capacity, the peers disconnected the longest are collected first
+    mock_time.advance_secs(1);
+    let newest_peer = add_peer(true);
+    assert_eq!(
+        peer_metadata_storage.garbage_collect(network_id, 2, retention),
+        1
+    );
+    assert_eq!(
+        peer_metadata_storage.get_last_known_epoch(&recent_peer),
+        None
+    );
+    assert_eq!(
+        peer_metadata_storage.get_last_known_epoch(&newest_peer),
+        Some(1)
+    );
+
+    // The metadata of connected peers is never collected
+    mock_time.advance_secs(120);
+    assert_eq!(
+        peer_metadata_storage.garbage_collect(network_id, 0, retention),
+        1
+    );
+    assert_eq!(
+        peer_metadata_storage.get_last_known_epoch(&connected_peer),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_peer_ping_stats() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+    peer_metadata_storage
+        .update_peer_score(peer, PeerScoreEvent::RpcSuccess(Duration::from_millis(100)));
+
+    // Pings are bucketed by their RTT
+    peer_metadata_storage.record_ping_result(peer, Some(Duration::from_millis(5)));
+    peer_metadata_storage.record_ping_result(peer, Some(Duration::from_millis(5000)));
+    peer_metadata_storage.record_ping_result(peer, None);
+    let ping_stats = peer_metadata_storage.get_peer_ping_stats(&peer).unwrap();
+    assert_eq!(ping_stats.num_successes(), 2);
+    assert_eq!(ping_stats.rtt_histogram[0], 1);
+    assert_eq!(ping_stats.rtt_histogram[PING_RTT_BUCKETS_MS.len()], 1);
+    assert_eq!(ping_stats.min_rtt, Some(Duration::from_millis(5)));
+    assert_eq!(ping_stats.max_rtt, Some(Duration::from_millis(5000)));
+    assert_eq!(ping_stats.total_failures, 1);
+    assert_eq!(ping_stats.consecutive_failures, 1);
+
+    // The availability is estimated over the latest pings
+    assert_eq!(
+        peer_metadata_storage.get_peer_availability(&peer),
+        Some(2.0 / 3.0)
+    );
+    for _ in 0..PING_AVAILABILITY_WINDOW {
+        peer_metadata_storage.record_ping_result(peer, Some(Duration::from_millis(5)));
+    }
+    assert_eq!(
+        peer_metadata_storage.get_peer_availability(&peer),
+        Some(1.0)
+    );
+
+    // The ping RTT takes precedence over the RPC latency
+    assert_eq!(
+        peer_metadata_storage.get_peer_latency(&peer),
+        ping_stats.average_rtt
+    );
+}
+
+#[test]
+fn test_connection_metadata_updates() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+    let mut connection = peer_metadata_storage
+        .get_connection_metadata(&peer)
+        .unwrap();
+    assert_eq!(connection.smoothed_rtt, None);
+
+    // The smoothed RTT of the active connection is updated
+    connection.record_rtt_sample(Duration::from_millis(80));
+    connection.record_rtt_sample(Duration::from_millis(160));
+    assert_eq!(connection.smoothed_rtt, Some(Duration::from_millis(90)));
+    peer_metadata_storage.update_connection_metadata(network_id, connection.clone());
+    assert_eq!(
+        peer_metadata_storage.get_connection_metadata(&peer),
+        Some(connection.clone())
+    );
+
+    // Updates of stale connections are ignored
+    let mut stale_connection = ConnectionMetadata::mock(peer.peer_id());
+    stale_connection.record_rtt_sample(Duration::from_millis(500));
+    peer_metadata_storage.update_connection_metadata(network_id, stale_connection);
+    assert_eq!(
+        peer_metadata_storage.get_connection_metadata(&peer),
+        Some(connection)
+    );
+}
+
+#[test]
+fn test_outbound_queue_depth() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+    assert_eq!(peer_metadata_storage.get_outbound_queue_depth(&peer), None);
+
+    // The depth of the active connection's queue is exposed
+    let outbound_queue = Arc::new(OutboundQueue::new(16));
+    peer_metadata_storage.insert_outbound_queue(peer, outbound_queue.clone());
+    assert_eq!(
+        peer_metadata_storage.get_outbound_queue_depth(&peer),
+        Some(OutboundQueueDepth::default())
+    );
+    assert!(Arc::ptr_eq(
+        &peer_metadata_storage.get_outbound_queue(&peer).unwrap(),
+        &outbound_queue
+    ));
+
+    // The queue is removed along with the connection
+    let connection = peer_metadata_storage
+        .get_connection_metadata(&peer)
+        .unwrap();
+    peer_metadata_storage.remove_connection(network_id, &connection);
+    assert_eq!(peer_metadata_storage.get_outbound_queue_depth(&peer), None);
+}
+
+#[test]
+fn test_peer_reports() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+
+    // The traffic exchanged with the peer is reported
+    peer_metadata_storage.record_outbound_message(&peer, 100);
+    peer_metadata_storage.record_inbound_message(&peer, 10);
+    peer_metadata_storage.record_inbound_message(&peer, 20);
+    for succeeded in [true, true, true, false] {
+        peer_metadata_storage.record_rpc_result(&peer, succeeded);
+    }
+    peer_metadata_storage
+        .update_peer_score(peer, PeerScoreEvent::RpcSuccess(Duration::from_millis(100)));
+    let peer_reports = peer_metadata_storage.peer_reports();
+    assert_eq!(peer_reports.len(), 1);
+    let report = &peer_reports[0];
+    assert_eq!(report.peer_id, peer.peer_id());
+    assert_eq!(report.traffic, PeerTraffic {
+        messages_sent: 1,
+        messages_received: 2,
+        bytes_sent: 100,
+        bytes_received: 30,
+        rpcs_succeeded: 3,
+        rpcs_failed: 1,
+    });
+    assert_eq!(report.rpc_success_rate, Some(0.75));
+    assert!(report.score > STARTING_SCORE);
+    assert_eq!(report.latency, Some(Duration::from_millis(100)));
+
+    // The traffic is forgotten along with the connection
+    let connection = peer_metadata_storage
+        .get_connection_metadata(&peer)
+        .unwrap();
+    peer_metadata_storage.remove_connection(network_id, &connection);
+    peer_metadata_storage.record_inbound_message(&peer, 10);
+    assert_eq!(peer_metadata_storage.get_peer_traffic(&peer), None);
+    assert!(peer_metadata_storage.peer_reports().is_empty());
+}
+
+#[test]
+fn test_peer_event_subscription() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let mut peer_events = peer_metadata_storage.subscribe();
+
+    // Connecting a peer notifies the subscribers
+    let peer_id = PeerId::random();
+    let peer_network_id = PeerNetworkId::new(network_id, peer_id);
+    let connection = ConnectionMetadata::mock(peer_id);
+    peer_metadata_storage.insert_connection(network_id, connection.clone());
+    assert_eq!(
+        peer_events.try_recv().unwrap(),
+        PeerEvent::PeerConnected(peer_network_id, connection.clone())
+    );
+
+    // Updating the peer state notifies the subscribers
+    peer_metadata_storage
+        .update_peer_state(peer_network_id, PeerState::Disconnecting)
+        .unwrap();
+    match peer_events.try_recv().unwrap() {
+        PeerEvent::MetadataUpdated(updated_peer, peer_info) => {
+            assert_eq!(updated_peer, peer_network_id);
+            assert_eq!(peer_info.status, PeerState::Disconnecting);
+        },
+        peer_event => panic!("Unexpected peer event: {:?}", peer_event),
+    }
+
+    // Removing a stale connection doesn't notify the subscribers
+    peer_metadata_storage.remove_connection(network_id, &ConnectionMetadata::mock(peer_id));
+    assert!(peer_events.try_recv().is_err());
+
+    // Removing the active connection notifies the subscribers
+    peer_metadata_storage.remove_connection(network_id, &connection);
+    assert_eq!(
+        peer_events.try_recv().unwrap(),
+        PeerEvent::PeerDisconnected(peer_network_id, connection)
+    );
+}
+
+#[test]
+fn test_peer_metadata_snapshot() {
+    let network_id = NetworkId::Validator;
+    let peer_metadata_storage = PeerMetadataStorage::test();
+    let _peer_events = peer_metadata_storage.subscribe();
+    let trusted_peer =
+        insert_peer_with_protocol(&peer_metadata_storage, network_id, ProtocolId::MempoolRpc);
+    let untrusted_peer = insert_peer_with_protocol(
+        &peer_metadata_storage,
+        network_id,
+        ProtocolId::ConsensusRpcBcs,
+    );
+    peer_metadata_storage.update_peer_score(
+        untrusted_peer,
+        PeerScoreEvent::RpcSuccess(Duration::from_millis(10)),
+    );
+    for _ in 0..2 {
+        peer_metadata_storage.get_connected_supported_peers(network_id, &[ProtocolId::MempoolRpc]);
+    }
+
+    let snapshot = peer_metadata_storage.snapshot(|peer| *peer == trusted_peer);
+    assert_eq!(snapshot.num_scored_peers, 1);
+    assert_eq!(snapshot.num_event_subscribers, 1);
+    assert_eq!(snapshot.cache_stats[SUPPORTED_PEERS_CACHE], CacheStats {
+        hits: 1,
+        misses: 1,
+        invalidations: 0,
+        size: 1,
+    });
+    assert_eq!(
+        snapshot.cache_stats[PEER_PROTOCOLS_CACHE],
+        CacheStats::default()
+    );
+    let peers = snapshot.networks.get(&network_id).unwrap();
+    assert_eq!(peers.len(), 2);
+    for peer in peers {
+        if peer.peer_id == trusted_peer.peer_id() {
+            assert!(peer.is_trusted);
+            assert_eq!(peer.protocols, vec![ProtocolId::MempoolRpc]);
+            assert_eq!(peer.score, STARTING_SCORE);
+            assert_eq!(peer.average_latency, None);
+        } else {
+            assert!(!peer.is_trusted);
+            assert_eq!(peer.protocols, vec![ProtocolId::ConsensusRpcBcs]);
+            assert!(peer.score > STARTING_SCORE);
+            assert_eq!(peer.average_latency, Some(Duration::from_millis(10)));
+        }
+    }
+
+    // The snapshot can be serialized for the inspection service
+    serde_json::to_string(&snapshot).unwrap();
+}
+
+#[tokio::test]
+async fn test_application_rpc_metrics() {
+    // Use a network and protocol that no other test uses, as metrics are global
+    let network_id = NetworkId::Public;
+    let protocol_id = ProtocolId::StorageServiceRpc;
+    let peer_metadata_storage = PeerMetadataStorage::new(&[network_id]);
+    let (network_client, mut peer_mgr_reqs_rx) =
+        build_network_client(network_id, peer_metadata_storage.clone());
+    insert_peer_with_protocol(&peer_metadata_storage, network_id, protocol_id);
+    let metrics_before = counters::protocol_metrics(network_id, protocol_id);
+
+    // Send one rpc that fails and one that succeeds
+    for response in [
+        Err(RpcError::TimedOut),
+        Ok(protocol_id.to_bytes(&DummyMessage {}).unwrap().into()),
+    ] {
+        let rpc = network_client.send_rpc_with_retries(
+            DummyMessage {},
+            protocol_id,
+            Duration::from_secs(1),
+            &RandomPeerSelector,
+            1,
+        );
+        let respond = async {
+            match peer_mgr_reqs_rx.next().await.unwrap() {
+                PeerManagerRequest::SendRpc(_, rpc_request) => {
+                    rpc_request.res_tx.send(response).unwrap();
+                },
+                request => panic!("Unexpected PeerManagerRequest: {:?}", request),
+            }
+        };
+        let _ = futures::join!(rpc, respond);
+    }
+
+    // Verify the metrics can be read in-process
+    let metrics_after = counters::protocol_metrics(network_id, protocol_id);
+    assert_eq!(metrics_after.rpcs_failed, metrics_before.rpcs_failed + 1);
+    assert_eq!(
+        metrics_after.rpcs_succeeded,
+        metrics_before.rpcs_succeeded + 1
+    );
+    assert!(metrics_after.rpc_latency_sum >= metrics_before.rpc_latency_sum);
+}
+
+#[tokio::test]
+async fn test_unified_event_stream() {
+    let mut network_and_events = HashMap::new();
+    let mut connection_notifs_txs = HashMap::new();
+    for network_id in [NetworkId::Validator, NetworkId::Vfn] {
+        let (_, peer_mgr_notifs_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
+        let (connection_notifs_tx, connection_notifs_rx) =
+            aptos_channel::new(QueueStyle::FIFO, 8, None);
+        network_and_events.insert(
+            network_id,
+            NetworkEvents::<DummyMessage>::new(peer_mgr_notifs_rx, connection_notifs_rx),
+        );
+        connection_notifs_txs.insert(network_id, connection_notifs_tx);
+    }
+    let mut events = NetworkServiceEvents::new(network_and_events).into_unified_stream();
+
+    // Three peers connect on the validator network, and one on the VFN network
+    let new_peer = |network_id: NetworkId| {
+        let peer_id = PeerId::random();
+        let notif = ConnectionNotification::NewPeer(
+            ConnectionMetadata::mock(peer_id),
+            NetworkContext::mock(),
+        );
+        connection_notifs_txs
+            .get(&network_id)
+            .unwrap()
+            .push(peer_id, notif)
+            .unwrap();
+    };
+    for _ in 0..3 {
+        new_peer(NetworkId::Validator);
+    }
+    new_peer(NetworkId::Vfn);
+
+    // The events are tagged with their network, and the networks are polled in turn
+    let mut first_networks = HashSet::new();
+    for _ in 0..2 {
+        let (network_id, event) = events.next().await.unwrap();
+        assert!(matches!(event, Event::NewPeer(_)));
+        first_networks.insert(network_id);
+    }
+    assert_eq!(first_networks.len(), 2);
+    for _ in 0..2 {
+        let (network_id, _) = events.next().await.unwrap();
+        assert_eq!(network_id, NetworkId::Validator);
+    }
+
+    // The stream ends once all networks are closed
+    drop(connection_notifs_txs);
+    assert!(events.next().await.is_none());
+    assert!(events.is_terminated());
+}
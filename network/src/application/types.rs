@@ -1,8 +1,21 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{protocols::wire::handshake::v1::ProtocolId, transport::ConnectionMetadata};
+use crate::{
+    application::cache_stats::CacheStats, protocols::wire::handshake::v1::ProtocolId,
+    transport::ConnectionMetadata,
+};
+use aptos_config::{
+    config::PeerRole,
+    network_id::{NetworkId, PeerNetworkId},
+};
+use aptos_netcore::transport::ConnectionOrigin;
+use aptos_types::PeerId;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
 
 /// Errors related to the peer layer in the `NetworkInterface`
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -15,6 +28,12 @@ pub enum PeerError {
 pub struct PeerInfo {
     pub status: PeerState,
     pub active_connection: ConnectionMetadata,
+    /// The ping statistics of the active connection, measured by the HealthChecker
+    #[serde(default)]
+    pub ping_stats: PingStats,
+    /// The metadata of the peer reported by the peer monitoring service
+    #[serde(default)]
+    pub monitoring_metadata: PeerMonitoringMetadata,
 }
 
 impl PeerInfo {
@@ -22,6 +41,8 @@ impl PeerInfo {
         PeerInfo {
             status: PeerState::Connected,
             active_connection: connection_metadata,
+            ping_stats: PingStats::default(),
+            monitoring_metadata: PeerMonitoringMetadata::default(),
         }
     }
 
@@ -36,6 +57,120 @@ impl PeerInfo {
     }
 }
 
+/// The metadata of a peer reported by the peer monitoring service. Fields are
+/// `None` until they are first reported.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PeerMonitoringMetadata {
+    /// The distance of the peer from the validators (0 for a validator)
+    pub distance_from_validators: Option<u64>,
+    /// The version of the node software run by the peer (e.g., "1.2.3")
+    pub node_version: Option<String>,
+    /// The highest ledger version the peer advertised as synced
+    pub highest_synced_version: Option<u64>,
+    /// The average latency to the peer measured by the peer monitoring service
+    pub average_latency: Option<Duration>,
+}
+
+/// The upper bounds (in milliseconds) of the ping RTT histogram buckets. The
+/// last bucket of the histogram counts the pings slower than all bounds.
+pub const PING_RTT_BUCKETS_MS: [u64; 7] = [10, 25, 50, 100, 250, 500, 1000];
+
+/// The weight of the latest RTT in the moving average
+const PING_RTT_SMOOTHING_FACTOR: f64 = 0.2;
+
+/// The number of latest pings over which the availability of a peer is estimated
+pub const PING_AVAILABILITY_WINDOW: u32 = u64::BITS;
+
+/// Round-trip time and failure statistics of the HealthChecker pings to a peer
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PingStats {
+    /// The number of successful pings in each RTT bucket (see `PING_RTT_BUCKETS_MS`)
+    pub rtt_histogram: [u64; PING_RTT_BUCKETS_MS.len() + 1],
+    /// The moving average of the RTT, or `None` if no ping succeeded yet
+    pub average_rtt: Option<Duration>,
+    pub min_rtt: Option<Duration>,
+    pub max_rtt: Option<Duration>,
+    pub total_failures: u64,
+    /// The number of failed pings since the last successful one
+    pub consecutive_failures: u64,
+    /// The outcomes of the latest pings (see `PING_AVAILABILITY_WINDOW`), with
+    /// the latest one in the lowest bit (set if the ping succeeded)
+    #[serde(default)]
+    pub recent_outcomes: u64,
+    /// The number of pings whose outcome is in `recent_outcomes`
+    #[serde(default)]
+    pub num_recent_outcomes: u32,
+}
+
+impl PingStats {
+    pub fn record_success(&mut self, rtt: Duration) {
+        let rtt_ms = rtt.as_millis() as u64;
+        let bucket = PING_RTT_BUCKETS_MS
+            .iter()
+            .position(|bound| rtt_ms <= *bound)
+            .unwrap_or(PING_RTT_BUCKETS_MS.len());
+        self.rtt_histogram[bucket] += 1;
+        self.average_rtt = Some(match self.average_rtt {
+            Some(average_rtt) => {
+                average_rtt.mul_f64(1.0 - PING_RTT_SMOOTHING_FACTOR)
+                    + rtt.mul_f64(PING_RTT_SMOOTHING_FACTOR)
+            },
+            None => rtt,
+        });
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+        self.max_rtt = Some(self.max_rtt.map_or(rtt, |max_rtt| max_rtt.max(rtt)));
+        self.consecutive_failures = 0;
+        self.record_outcome(true);
+    }
+
+    pub fn record_failure(&mut self) {
+        self.total_failures += 1;
+        self.consecutive_failures += 1;
+        self.record_outcome(false);
+    }
+
+    fn record_outcome(&mut self, success: bool) {
+        self.recent_outcomes = (self.recent_outcomes << 1) | u64::from(success);
+        self.num_recent_outcomes = (self.num_recent_outcomes + 1).min(PING_AVAILABILITY_WINDOW);
+    }
+
+    /// Returns the estimated availability of the peer, i.e., the fraction of
+    /// the latest pings that succeeded, or `None` if it wasn't pinged yet
+    pub fn availability(&self) -> Option<f64> {
+        if self.num_recent_outcomes == 0 {
+            return None;
+        }
+        Some(f64::from(self.recent_outcomes.count_ones()) / f64::from(self.num_recent_outcomes))
+    }
+
+    /// Returns the total number of successful pings
+    pub fn num_successes(&self) -> u64 {
+        self.rtt_histogram.iter().sum()
+    }
+}
+
+/// The outbound dial backoff of a disconnected peer, tracked by the
+/// ConnectivityManager
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DialBackoffState {
+    /// The number of consecutive dials to the peer (including the pending one)
+    pub attempts: u32,
+    /// The delay before the latest dial
+    pub last_delay: Duration,
+    /// Whether the peer is no longer dialed, as it reached the max attempts
+    pub exhausted: bool,
+}
+
+/// A ban of a misbehaving peer (e.g., one that sent invalid consensus
+/// messages), whose connections are refused until the ban expires
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeerBan {
+    /// Why the peer was banned, for inspection
+    pub reason: String,
+    /// The time at which the ban expires
+    pub expiry: Instant,
+}
+
 /// The current state of a `Peer` at any one time
 /// TODO: Allow nodes that are unhealthy to stay connected
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
@@ -43,4 +178,85 @@ pub enum PeerState {
     Connected,
     Disconnecting,
     Disconnected,
+    /// The connection is being re-established after a local address change
+    Migrating,
+}
+
+/// A change to the peers tracked by the `PeerMetadataStorage`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PeerEvent {
+    /// A new connection to the peer was established
+    PeerConnected(PeerNetworkId, ConnectionMetadata),
+    /// The connection to the peer was closed
+    PeerDisconnected(PeerNetworkId, ConnectionMetadata),
+    /// The metadata (e.g., the state) of a connected peer was updated
+    MetadataUpdated(PeerNetworkId, PeerInfo),
+    /// The connection to the peer was replaced by the given connection after
+    /// a local address change, without the peer being disconnected
+    ConnectionMigrated(PeerNetworkId, ConnectionMetadata),
+    /// The peer was banned, and is about to be disconnected
+    PeerBanned(PeerNetworkId, PeerBan),
+}
+
+/// A serializable view of a single peer, used for debugging
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PeerSnapshot {
+    pub peer_id: PeerId,
+    pub peer_info: PeerInfo,
+    /// The application protocols supported by the peer (in a readable form)
+    pub protocols: Vec<ProtocolId>,
+    /// Whether the peer is in the trusted peer set of the network
+    pub is_trusted: bool,
+    pub score: f64,
+    pub average_latency: Option<Duration>,
+}
+
+/// A serializable view of the `PeerMetadataStorage`, used for debugging
+/// connectivity (e.g., by the node inspection service)
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PeerMetadataSnapshot {
+    /// The peers of each network, sorted by peer id
+    pub networks: BTreeMap<NetworkId, Vec<PeerSnapshot>>,
+    /// The number of peers with a recorded score (including disconnected peers)
+    pub num_scored_peers: usize,
+    /// The number of active peer event subscribers
+    pub num_event_subscribers: usize,
+    /// The stats of the network caches, keyed by cache name
+    pub cache_stats: BTreeMap<String, CacheStats>,
+}
+
+/// The traffic exchanged with a peer over its active connection
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PeerTraffic {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// The outbound rpcs of the applications that succeeded
+    pub rpcs_succeeded: u64,
+    /// The outbound rpcs of the applications that failed (or timed out)
+    pub rpcs_failed: u64,
+}
+
+impl PeerTraffic {
+    /// Returns the fraction of the outbound rpcs that succeeded, or `None` if
+    /// no rpc was sent to the peer
+    pub fn rpc_success_rate(&self) -> Option<f64> {
+        let rpcs = self.rpcs_succeeded + self.rpcs_failed;
+        (rpcs > 0).then(|| self.rpcs_succeeded as f64 / rpcs as f64)
+    }
+}
+
+/// A report of the health of a connected peer, periodically pushed to
+/// telemetry for fleet-wide visibility into the network
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PeerReport {
+    pub network_id: NetworkId,
+    pub peer_id: PeerId,
+    pub role: PeerRole,
+    pub origin: ConnectionOrigin,
+    pub traffic: PeerTraffic,
+    pub rpc_success_rate: Option<f64>,
+    pub score: f64,
+    pub latency: Option<Duration>,
 }
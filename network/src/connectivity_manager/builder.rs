@@ -2,11 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    connectivity_manager::{ConnectivityManager, ConnectivityRequest},
+    application::storage::PeerMetadataStorage,
+    connectivity_manager::{ConnectivityManager, ConnectivityRequest, DialRetryPolicy},
     counters,
     peer_manager::{conn_notifs_channel, ConnectionRequestSender},
 };
-use aptos_config::{config::PeerSet, network_id::NetworkContext};
+use aptos_config::{
+    config::{DialBackoffConfig, DialConcurrencyConfig, PeerSet},
+    network_id::NetworkContext,
+};
 use aptos_infallible::RwLock;
 use aptos_time_service::TimeService;
 use std::{sync::Arc, time::Duration};
@@ -21,6 +25,7 @@ pub struct ConnectivityManagerBuilder {
 }
 
 impl ConnectivityManagerBuilder {
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         network_context: NetworkContext,
         time_service: TimeService,
@@ -29,11 +34,14 @@ impl ConnectivityManagerBuilder {
         connectivity_check_interval_ms: u64,
         backoff_base: u64,
         max_connection_delay_ms: u64,
+        dial_backoff: &DialBackoffConfig,
+        dial_concurrency: &DialConcurrencyConfig,
         channel_size: usize,
         connection_reqs_tx: ConnectionRequestSender,
         connection_notifs_rx: conn_notifs_channel::Receiver,
         outbound_connection_limit: Option<usize>,
         mutual_authentication: bool,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
     ) -> Self {
         let (conn_mgr_reqs_tx, conn_mgr_reqs_rx) = aptos_channels::new(
             channel_size,
@@ -51,8 +59,10 @@ impl ConnectivityManagerBuilder {
                 connection_notifs_rx,
                 conn_mgr_reqs_rx,
                 Duration::from_millis(connectivity_check_interval_ms),
-                ExponentialBackoff::from_millis(backoff_base).factor(1000),
-                Duration::from_millis(max_connection_delay_ms),
+                ExponentialBackoff::from_millis(backoff_base).factor(dial_backoff.base_delay_ms),
+                DialRetryPolicy::new(max_connection_delay_ms, dial_backoff),
+                peer_metadata_storage,
+                *dial_concurrency,
                 outbound_connection_limit,
                 mutual_authentication,
             )),
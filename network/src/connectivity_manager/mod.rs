@@ -9,11 +9,13 @@
 //! Consensus actor informs the ConnectivityManager of eligible nodes.
 //!
 //! Different discovery sources notify the ConnectivityManager of updates to
-//! peers' addresses. Currently, there are 2 discovery sources (ordered by
+//! peers' addresses. Currently, there are 4 main discovery sources (ordered by
 //! decreasing dial priority, i.e., first is highest priority):
 //!
 //! 1. Onchain discovery protocol
 //! 2. Seed peers from config
+//! 3. Peers learned from connected peers (peer exchange)
+//! 4. Peers persisted before the last restart
 //!
 //! In other words, if a we have some addresses discovered via onchain discovery
 //! and some seed addresses from our local config, we will try the onchain
@@ -27,14 +29,18 @@
 //! using a relay protocol.
 
 use crate::{
+    application::{storage::PeerMetadataStorage, types::DialBackoffState},
     counters,
     logging::NetworkSchema,
     peer_manager::{self, conn_notifs_channel, ConnectionRequestSender, PeerManagerError},
     transport::ConnectionMetadata,
 };
 use aptos_config::{
-    config::{Peer, PeerRole, PeerSet},
-    network_id::NetworkContext,
+    config::{
+        DialBackoffConfig, DialConcurrencyConfig, Peer, PeerRole, PeerSet,
+        CONNECTION_BACKOFF_JITTER_MS, MAX_CONNECTION_DELAY_MS,
+    },
+    network_id::{NetworkContext, PeerNetworkId},
 };
 use aptos_crypto::x25519;
 use aptos_infallible::RwLock;
@@ -73,7 +79,7 @@ mod test;
 /// are spun up around the same time. Similarly, it smears the dials out in time
 /// to avoid spiky load / thundering herd issues where all dial requests happen
 /// around the same time at startup.
-const MAX_CONNECTION_DELAY_JITTER: Duration = Duration::from_millis(100);
+const MAX_CONNECTION_DELAY_JITTER: Duration = Duration::from_millis(CONNECTION_BACKOFF_JITTER_MS);
 
 /// The amount of time to try other peers until dialing this peer again.
 ///
@@ -107,8 +113,12 @@ pub struct ConnectivityManager<TBackoff> {
     connectivity_check_interval: Duration,
     /// Backoff strategy.
     backoff_strategy: TBackoff,
-    /// Maximum delay b/w 2 consecutive attempts to connect with a disconnected peer.
-    max_delay: Duration,
+    /// How failed dials are retried, in addition to the backoff strategy.
+    dial_retry_policy: DialRetryPolicy,
+    /// Where the dial backoff of each peer is published.
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    /// The maximum number of concurrent pending dials of each `DialPriority`.
+    dial_concurrency: DialConcurrencyConfig,
     /// A local counter incremented on receiving an incoming message. Printing this in debugging
     /// allows for easy debugging.
     event_id: u32,
@@ -118,10 +128,12 @@ pub struct ConnectivityManager<TBackoff> {
     rng: SmallRng,
     /// Whether we are using mutual authentication or not
     mutual_authentication: bool,
+    /// The epoch of the latest on-chain validator set, if any was received
+    validator_set_epoch: Option<u64>,
 }
 
 /// Different sources for peer addresses, ordered by priority (Onchain=highest,
-/// Config=lowest).
+/// Persisted=lowest).
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, Hash, PartialEq, Ord, PartialOrd, NumVariants, Serialize)]
 pub enum DiscoverySource {
@@ -129,6 +141,8 @@ pub enum DiscoverySource {
     File,
     Rest,
     Config,
+    PeerExchange,
+    Persisted,
 }
 
 impl fmt::Debug for DiscoverySource {
@@ -143,7 +157,9 @@ impl fmt::Display for DiscoverySource {
             DiscoverySource::OnChainValidatorSet => "OnChainValidatorSet",
             DiscoverySource::File => "File",
             DiscoverySource::Config => "Config",
+            DiscoverySource::PeerExchange => "PeerExchange",
             DiscoverySource::Rest => "Rest",
+            DiscoverySource::Persisted => "Persisted",
         })
     }
 }
@@ -153,6 +169,13 @@ impl fmt::Display for DiscoverySource {
 pub enum ConnectivityRequest {
     /// Update set of discovered peers and associated info
     UpdateDiscoveredPeers(DiscoverySource, PeerSet),
+    /// Update the on-chain validator set of the given epoch. Validators that
+    /// left the set are pruned, and their connections closed right away.
+    UpdateValidatorSet(u64, PeerSet),
+    /// Replace the seed peers (from config) at runtime, e.g., to rotate the
+    /// address of a peer without restarting the node. The trusted peers are
+    /// updated accordingly, and peers are dialed or disconnected right away.
+    UpdateSeedPeers(PeerSet),
     /// Gets current size of connected peers. This is useful in tests.
     #[serde(skip)]
     GetConnectedSize(oneshot::Sender<usize>),
@@ -256,6 +279,38 @@ impl PartialOrd for DiscoveredPeer {
     }
 }
 
+/// The order in which peers are dialed, by role: validators first (to heal the
+/// validator mesh asap), then validator full nodes, then public peers.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum DialPriority {
+    Validator,
+    ValidatorFullNode,
+    Public,
+}
+
+impl DialPriority {
+    fn of(role: PeerRole) -> Self {
+        match role {
+            PeerRole::Validator => DialPriority::Validator,
+            PeerRole::ValidatorFullNode => DialPriority::ValidatorFullNode,
+            PeerRole::PreferredUpstream
+            | PeerRole::Upstream
+            | PeerRole::Downstream
+            | PeerRole::Known
+            | PeerRole::Unknown => DialPriority::Public,
+        }
+    }
+
+    /// The maximum number of concurrent pending dials of this priority, if any
+    fn budget(self, config: &DialConcurrencyConfig) -> Option<usize> {
+        match self {
+            DialPriority::Validator => config.validators,
+            DialPriority::ValidatorFullNode => config.validator_fullnodes,
+            DialPriority::Public => config.public_peers,
+        }
+    }
+}
+
 impl From<&DiscoveredPeer> for Peer {
     fn from(peer: &DiscoveredPeer) -> Self {
         Peer::new(peer.addrs.union(), peer.keys.union(), peer.role)
@@ -288,6 +343,46 @@ struct DialState<TBackoff> {
     /// The index of the next address to dial. Index of an address in the `DiscoveredPeer`'s
     /// `addrs` entry.
     addr_idx: usize,
+    /// The number of consecutive dials, since the peer was last connected (or its
+    /// dial state was reset).
+    attempts: u32,
+}
+
+/// How failed dials to a peer are retried, in addition to the backoff strategy
+/// (i.e., the sequence of delays).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DialRetryPolicy {
+    /// Maximum delay b/w 2 consecutive attempts to connect with a disconnected peer.
+    pub max_delay: Duration,
+    /// The maximum random jitter added to each dial delay.
+    pub max_jitter: Duration,
+    /// The number of consecutive dials after which a peer is no longer dialed,
+    /// until its addresses change. If `None`, dials never stop.
+    pub max_attempts: Option<u32>,
+    /// Whether the backoff of a peer is reset once it's connected.
+    pub reset_on_success: bool,
+}
+
+impl DialRetryPolicy {
+    pub fn new(max_connection_delay_ms: u64, config: &DialBackoffConfig) -> Self {
+        Self {
+            max_delay: Duration::from_millis(max_connection_delay_ms),
+            max_jitter: Duration::from_millis(config.jitter_ms),
+            max_attempts: config.max_attempts,
+            reset_on_success: config.reset_on_success,
+        }
+    }
+}
+
+impl Default for DialRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_delay: Duration::from_millis(MAX_CONNECTION_DELAY_MS),
+            max_jitter: MAX_CONNECTION_DELAY_JITTER,
+            max_attempts: None,
+            reset_on_success: true,
+        }
+    }
 }
 
 /////////////////////////
@@ -299,6 +394,7 @@ where
     TBackoff: Iterator<Item = Duration> + Clone,
 {
     /// Creates a new instance of the [`ConnectivityManager`] actor.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         network_context: NetworkContext,
         time_service: TimeService,
@@ -309,7 +405,9 @@ where
         requests_rx: aptos_channels::Receiver<ConnectivityRequest>,
         connectivity_check_interval: Duration,
         backoff_strategy: TBackoff,
-        max_delay: Duration,
+        dial_retry_policy: DialRetryPolicy,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+        dial_concurrency: DialConcurrencyConfig,
         outbound_connection_limit: Option<usize>,
         mutual_authentication: bool,
     ) -> Self {
@@ -337,11 +435,14 @@ where
             dial_states: HashMap::new(),
             connectivity_check_interval,
             backoff_strategy,
-            max_delay,
+            dial_retry_policy,
+            peer_metadata_storage,
+            dial_concurrency,
             event_id: 0,
             outbound_connection_limit,
             rng: SmallRng::from_entropy(),
             mutual_authentication,
+            validator_set_epoch: None,
         };
 
         // set the initial config addresses and pubkeys
@@ -374,7 +475,17 @@ where
                     self.check_connectivity(&mut pending_dials).await;
                 },
                 req = self.requests_rx.select_next_some() => {
+                    let check_connectivity = matches!(
+                        req,
+                        ConnectivityRequest::UpdateSeedPeers(_)
+                            | ConnectivityRequest::UpdateValidatorSet(..)
+                    );
                     self.handle_request(req);
+                    // Apply seed and validator set changes without waiting for
+                    // the next tick
+                    if check_connectivity {
+                        self.check_connectivity(&mut pending_dials).await;
+                    }
                 },
                 maybe_notif = self.connection_notifs_rx.next() => {
                     // Shutdown the connectivity manager when the PeerManager
@@ -500,6 +611,7 @@ where
                     && !self.connected.contains_key(peer_id) // The node is not already connected.
                     && !self.dial_queue.contains_key(peer_id) // There is no pending dial to this node.
                     && roles_to_dial.contains(&peer.role) // We can dial this role
+                    && !self.has_exhausted_dial_attempts(peer_id) // We haven't given up on the node.
             })
             .collect();
 
@@ -507,9 +619,13 @@ where
         // Shuffle so we don't get stuck on certain peers
         eligible.shuffle(&mut self.rng);
 
-        // Sort by peer priority
-        eligible
-            .sort_by(|(_, peer), (_, other)| peer.partial_cmp(other).unwrap_or(Ordering::Equal));
+        // Sort by dial priority (i.e., validators, then VFNs, then public peers),
+        // then by peer priority
+        eligible.sort_by(|(_, peer), (_, other)| {
+            DialPriority::of(peer.role)
+                .cmp(&DialPriority::of(other.role))
+                .then_with(|| peer.partial_cmp(other).unwrap_or(Ordering::Equal))
+        });
 
         // Limit the number of dialed connections from a Full Node
         // This does not limit the number of incoming connections
@@ -531,14 +647,40 @@ where
             num_eligible
         };
 
-        // Take peers to connect to in priority order
+        // Take peers to connect to in priority order, within the dial budget of
+        // their role
+        let mut pending_dials = self.num_pending_dials_by_priority();
         eligible
             .iter()
+            .filter(|(_, peer)| {
+                let priority = DialPriority::of(peer.role);
+                let num_pending_dials = pending_dials.entry(priority).or_default();
+                let within_budget = priority
+                    .budget(&self.dial_concurrency)
+                    .map_or(true, |budget| *num_pending_dials < budget);
+                if within_budget {
+                    *num_pending_dials += 1;
+                }
+                within_budget
+            })
             .take(to_connect)
             .map(|(peer_id, peer)| (**peer_id, (*peer).clone()))
             .collect()
     }
 
+    /// Returns the number of queued dials of each `DialPriority`
+    fn num_pending_dials_by_priority(&self) -> HashMap<DialPriority, usize> {
+        let mut pending_dials = HashMap::new();
+        for peer_id in self.dial_queue.keys() {
+            if let Some(peer) = self.discovered_peers.0.get(peer_id) {
+                *pending_dials
+                    .entry(DialPriority::of(peer.role))
+                    .or_default() += 1;
+            }
+        }
+        pending_dials
+    }
+
     fn queue_dial_peer<'a>(
         &'a mut self,
         peer_id: PeerId,
@@ -565,8 +707,23 @@ where
 
         // Using the DialState's backoff strategy, compute the delay until
         // the next dial attempt for this peer.
-        let dial_delay = dial_state.next_backoff_delay(self.max_delay);
+        let dial_delay = dial_state.next_backoff_delay(
+            self.dial_retry_policy.max_delay,
+            self.dial_retry_policy.max_jitter,
+        );
         let f_delay = self.time_service.sleep(dial_delay);
+        let exhausted = self
+            .dial_retry_policy
+            .max_attempts
+            .map_or(false, |max_attempts| dial_state.attempts >= max_attempts);
+        self.peer_metadata_storage.update_dial_backoff(
+            PeerNetworkId::new(self.network_context.network_id(), peer_id),
+            DialBackoffState {
+                attempts: dial_state.attempts,
+                last_delay: dial_delay,
+                exhausted,
+            },
+        );
 
         let (cancel_tx, cancel_rx) = oneshot::channel();
 
@@ -641,6 +798,23 @@ where
     fn reset_dial_state(&mut self, peer_id: &PeerId) {
         if let Some(dial_state) = self.dial_states.get_mut(peer_id) {
             *dial_state = DialState::new(self.backoff_strategy.clone());
+            self.peer_metadata_storage
+                .remove_dial_backoff(&PeerNetworkId::new(
+                    self.network_context.network_id(),
+                    *peer_id,
+                ));
+        }
+    }
+
+    /// Returns true iff the peer was dialed the max number of times, without
+    /// getting connected
+    fn has_exhausted_dial_attempts(&self, peer_id: &PeerId) -> bool {
+        match (
+            self.dial_retry_policy.max_attempts,
+            self.dial_states.get(peer_id),
+        ) {
+            (Some(max_attempts), Some(dial_state)) => dial_state.attempts >= max_attempts,
+            _ => false,
         }
     }
 
@@ -662,6 +836,29 @@ where
                 );
                 self.handle_update_discovered_peers(src, discovered_peers);
             },
+            ConnectivityRequest::UpdateValidatorSet(epoch, validators) => {
+                info!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Received the validator set of epoch {}: {} validators",
+                    self.network_context,
+                    epoch,
+                    validators.len(),
+                );
+                self.handle_update_validator_set(epoch, validators);
+            },
+            ConnectivityRequest::UpdateSeedPeers(seeds) => {
+                info!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Reloading seed peers: {} peers",
+                    self.network_context,
+                    seeds.len(),
+                );
+                counters::seed_peer_reloads(&self.network_context).inc();
+                self.handle_update_discovered_peers(
+                    DiscoverySource::Config,
+                    with_address_pubkeys(seeds),
+                );
+            },
             ConnectivityRequest::GetDialQueueSize(sender) => {
                 sender.send(self.dial_queue.len()).unwrap();
             },
@@ -762,19 +959,81 @@ where
 
         // update eligible peers accordingly
         if keys_updated {
-            // For each peer, union all of the pubkeys from each discovery source
-            // to generate the new eligible peers set.
-            let new_eligible = self.discovered_peers.to_eligible_peers();
-
-            // Swap in the new eligible peers set. Drop the old set after releasing
-            // the write lock.
-            let _old_eligible = {
-                let mut eligible = self.eligible.write();
-                mem::replace(&mut *eligible, new_eligible)
-            };
+            self.update_eligible_peers();
+        }
+    }
+
+    /// Updates the on-chain validators to the set of the given epoch, and prunes
+    /// the validators that left the set. Their addresses and keys learned from
+    /// other peers (or persisted) are dropped too, so that connections to them
+    /// become stale unless they're configured explicitly (e.g., as seeds).
+    fn handle_update_validator_set(&mut self, epoch: u64, validators: PeerSet) {
+        if let Some(current_epoch) = self.validator_set_epoch {
+            if epoch < current_epoch {
+                warn!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Ignoring the validator set of epoch {}, already at epoch {}",
+                    self.network_context,
+                    epoch,
+                    current_epoch
+                );
+                return;
+            }
+        }
+        self.validator_set_epoch = Some(epoch);
+        self.peer_metadata_storage.update_validator_epochs(
+            self.network_context.network_id(),
+            epoch,
+            validators.keys().copied(),
+        );
+
+        let departed_validators: Vec<_> = self
+            .discovered_peers
+            .0
+            .iter()
+            .filter(|(peer_id, peer)| {
+                peer.keys.contains_src(DiscoverySource::OnChainValidatorSet)
+                    && !validators.contains_key(peer_id)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        self.handle_update_discovered_peers(DiscoverySource::OnChainValidatorSet, validators);
+
+        let mut keys_updated = false;
+        for peer_id in departed_validators {
+            info!(
+                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                "{} Pruning peer {}, which left the validator set in epoch {}",
+                self.network_context,
+                peer_id.short_str(),
+                epoch
+            );
+            if let Some(peer) = self.discovered_peers.get_mut(&peer_id) {
+                for src in [DiscoverySource::PeerExchange, DiscoverySource::Persisted] {
+                    keys_updated |= peer.keys.clear_src(src);
+                    peer.addrs.clear_src(src);
+                }
+            }
+            self.discovered_peers.try_remove_empty(&peer_id);
+        }
+        if keys_updated {
+            self.update_eligible_peers();
         }
     }
 
+    fn update_eligible_peers(&mut self) {
+        // For each peer, union all of the pubkeys from each discovery source
+        // to generate the new eligible peers set.
+        let new_eligible = self.discovered_peers.to_eligible_peers();
+
+        // Swap in the new eligible peers set. Drop the old set after releasing
+        // the write lock.
+        let _old_eligible = {
+            let mut eligible = self.eligible.write();
+            mem::replace(&mut *eligible, new_eligible)
+        };
+    }
+
     fn handle_control_notification(&mut self, notif: peer_manager::ConnectionNotification) {
         trace!(
             NetworkSchema::new(&self.network_context),
@@ -787,8 +1046,18 @@ where
                 counters::peer_connected(&self.network_context, &peer_id, 1);
                 self.connected.insert(peer_id, metadata);
 
-                // Cancel possible queued dial to this peer.
-                self.dial_states.remove(&peer_id);
+                // Cancel possible queued dial to this peer. Unless the backoff is
+                // reset on success, it's kept for the next time the peer is dialed.
+                if self.dial_retry_policy.reset_on_success {
+                    self.dial_states.remove(&peer_id);
+                    self.peer_metadata_storage
+                        .remove_dial_backoff(&PeerNetworkId::new(
+                            self.network_context.network_id(),
+                            peer_id,
+                        ));
+                } else if let Some(dial_state) = self.dial_states.get_mut(&peer_id) {
+                    dial_state.attempts = 0;
+                }
                 self.dial_queue.remove(&peer_id);
             },
             peer_manager::ConnectionNotification::LostPeer(metadata, _context, _reason) => {
@@ -826,6 +1095,20 @@ where
     }
 }
 
+/// Adds the pubkeys embedded in the (noise) addresses of each peer to its keys,
+/// so that seed peers configured only with addresses are trusted.
+fn with_address_pubkeys(mut peers: PeerSet) -> PeerSet {
+    for peer in peers.values_mut() {
+        let address_pubkeys: Vec<_> = peer
+            .addresses
+            .iter()
+            .filter_map(NetworkAddress::find_noise_proto)
+            .collect();
+        peer.keys.extend(address_pubkeys);
+    }
+    peers
+}
+
 fn log_dial_result(
     network_context: NetworkContext,
     peer_id: PeerId,
@@ -972,6 +1255,10 @@ impl PublicKeys {
         self.update(src, HashSet::new())
     }
 
+    fn contains_src(&self, src: DiscoverySource) -> bool {
+        !self.0[src.as_usize()].is_empty()
+    }
+
     fn union(&self) -> HashSet<x25519::PublicKey> {
         self.0.iter().flatten().copied().collect()
     }
@@ -1003,6 +1290,7 @@ where
         Self {
             backoff,
             addr_idx: 0,
+            attempts: 0,
         }
     }
 
@@ -1015,8 +1303,9 @@ where
         addrs.get(addr_idx % addrs.len()).unwrap()
     }
 
-    fn next_backoff_delay(&mut self, max_delay: Duration) -> Duration {
-        let jitter = jitter(MAX_CONNECTION_DELAY_JITTER);
+    fn next_backoff_delay(&mut self, max_delay: Duration, max_jitter: Duration) -> Duration {
+        let jitter = jitter(max_jitter);
+        self.attempts = self.attempts.saturating_add(1);
 
         min(max_delay, self.backoff.next().unwrap_or(max_delay)) + jitter
     }
@@ -8,7 +8,10 @@ use crate::{
     transport::ConnectionMetadata,
 };
 use aptos_channels::{aptos_channel, message_queues::QueueStyle};
-use aptos_config::config::{Peer, PeerRole, PeerSet, HANDSHAKE_VERSION};
+use aptos_config::{
+    config::{DialConcurrencyConfig, Peer, PeerRole, PeerSet, RoleType, HANDSHAKE_VERSION},
+    network_id::NetworkId,
+};
 use aptos_crypto::{test_utils::TEST_SEED, x25519, Uniform};
 use aptos_logger::info;
 use aptos_time_service::{MockTimeService, TimeService};
@@ -70,6 +73,7 @@ fn update_peer_with_address(mut peer: Peer, addr_str: &'static str) -> (Peer, Ne
 
 struct TestHarness {
     trusted_peers: Arc<RwLock<PeerSet>>,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
     mock_time: MockTimeService,
     connection_reqs_rx: aptos_channel::Receiver<PeerId, ConnectionRequest>,
     connection_notifs_tx: conn_notifs_channel::Sender,
@@ -78,13 +82,37 @@ struct TestHarness {
 
 impl TestHarness {
     fn new(seeds: PeerSet) -> (Self, ConnectivityManager<FixedInterval>) {
-        let network_context = NetworkContext::mock();
+        Self::new_with_retry_policy(seeds, DialRetryPolicy {
+            max_delay: MAX_CONNECTION_DELAY,
+            ..DialRetryPolicy::default()
+        })
+    }
+
+    fn new_with_retry_policy(
+        seeds: PeerSet,
+        dial_retry_policy: DialRetryPolicy,
+    ) -> (Self, ConnectivityManager<FixedInterval>) {
+        Self::new_with_config(
+            NetworkContext::mock(),
+            seeds,
+            dial_retry_policy,
+            DialConcurrencyConfig::default(),
+        )
+    }
+
+    fn new_with_config(
+        network_context: NetworkContext,
+        seeds: PeerSet,
+        dial_retry_policy: DialRetryPolicy,
+        dial_concurrency: DialConcurrencyConfig,
+    ) -> (Self, ConnectivityManager<FixedInterval>) {
         let time_service = TimeService::mock();
         let (connection_reqs_tx, connection_reqs_rx) =
             aptos_channel::new(QueueStyle::FIFO, 1, None);
         let (connection_notifs_tx, connection_notifs_rx) = conn_notifs_channel::new();
         let (conn_mgr_reqs_tx, conn_mgr_reqs_rx) = aptos_channels::new_test(0);
         let trusted_peers = Arc::new(RwLock::new(HashMap::new()));
+        let peer_metadata_storage = PeerMetadataStorage::new(&[network_context.network_id()]);
 
         let conn_mgr = ConnectivityManager::new(
             network_context,
@@ -96,12 +124,15 @@ impl TestHarness {
             conn_mgr_reqs_rx,
             CONNECTIVITY_CHECK_INTERVAL,
             FixedInterval::new(CONNECTION_DELAY),
-            MAX_CONNECTION_DELAY,
+            dial_retry_policy,
+            peer_metadata_storage.clone(),
+            dial_concurrency,
             Some(MAX_TEST_CONNECTIONS),
             true, /* mutual_authentication */
         );
         let mock = Self {
             trusted_peers,
+            peer_metadata_storage,
             mock_time: time_service.into_mock(),
             connection_reqs_rx,
             connection_notifs_tx,
@@ -297,6 +328,14 @@ impl TestHarness {
         self.wait_until_empty_dial_queue().await;
     }
 
+    async fn send_update_seed_peers(&mut self, seeds: PeerSet) {
+        info!("Sending UpdateSeedPeers");
+        self.conn_mgr_reqs_tx
+            .send(ConnectivityRequest::UpdateSeedPeers(seeds))
+            .await
+            .unwrap();
+    }
+
     async fn send_update_discovered_peers(&mut self, src: DiscoverySource, peers: PeerSet) {
         info!("Sending UpdateDiscoveredPeers");
         self.conn_mgr_reqs_tx
@@ -304,6 +343,14 @@ impl TestHarness {
             .await
             .unwrap();
     }
+
+    async fn send_update_validator_set(&mut self, epoch: u64, validators: PeerSet) {
+        info!("Sending UpdateValidatorSet");
+        self.conn_mgr_reqs_tx
+            .send(ConnectivityRequest::UpdateValidatorSet(epoch, validators))
+            .await
+            .unwrap();
+    }
 }
 
 #[test]
@@ -350,6 +397,97 @@ fn connect_to_seeds_on_startup() {
     block_on(future::join(conn_mgr.start(), test));
 }
 
+#[test]
+fn reload_seed_peers() {
+    let (old_seed_id, old_seed, _, old_seed_addr) = test_peer(AccountAddress::ONE);
+    let (new_seed_id, _, new_seed_pubkey, new_seed_addr) = test_peer(generate_account_address(2));
+    let seeds: PeerSet = hashmap! {old_seed_id => old_seed};
+    let (mut mock, conn_mgr) = TestHarness::new(seeds);
+
+    let test = async move {
+        mock.trigger_connectivity_check().await;
+        mock.trigger_pending_dials().await;
+        mock.expect_one_dial_success(old_seed_id, old_seed_addr.clone())
+            .await;
+
+        // Replace the seeds. The new seed is only configured with an address,
+        // so its pubkey must be taken from the address.
+        let new_seed = Peer::new(
+            vec![new_seed_addr.clone()],
+            HashSet::new(),
+            PeerRole::Validator,
+        );
+        mock.send_update_seed_peers(hashmap! {new_seed_id => new_seed})
+            .await;
+
+        // The old seed is disconnected and the new one dialed, without waiting
+        // for the next connectivity check
+        mock.expect_disconnect_success(old_seed_id, old_seed_addr)
+            .await;
+        mock.trigger_pending_dials().await;
+        mock.expect_one_dial_success(new_seed_id, new_seed_addr)
+            .await;
+
+        let trusted_peers = mock.trusted_peers.read().clone();
+        assert!(!trusted_peers.contains_key(&old_seed_id));
+        assert_eq!(
+            trusted_peers.get(&new_seed_id).unwrap().keys,
+            hashset! {new_seed_pubkey}
+        );
+    };
+    block_on(future::join(conn_mgr.start(), test));
+}
+
+#[test]
+fn prune_departed_validators() {
+    let (staying_id, staying, _, _) = test_peer(AccountAddress::ZERO);
+    let (departing_id, departing, _, departing_addr) = test_peer(AccountAddress::ONE);
+    let (mut mock, conn_mgr) = TestHarness::new(HashMap::new());
+
+    let test = async move {
+        mock.send_update_validator_set(
+            1,
+            hashmap! {staying_id => staying.clone(), departing_id => departing.clone()},
+        )
+        .await;
+        // The departing validator was also learned from another peer
+        mock.send_update_discovered_peers(
+            DiscoverySource::PeerExchange,
+            hashmap! {departing_id => departing.clone()},
+        )
+        .await;
+        mock.trigger_pending_dials().await;
+        mock.expect_num_dials(2).await;
+
+        // Connections to validators that left the set are closed right away,
+        // even though they were also learned from other peers
+        mock.send_update_validator_set(2, hashmap! {staying_id => staying})
+            .await;
+        mock.expect_disconnect_success(departing_id, departing_addr)
+            .await;
+        assert!(!mock.trusted_peers.read().contains_key(&departing_id));
+
+        // The last epoch of each validator is recorded
+        let network_id = NetworkContext::mock().network_id();
+        let storage = &mock.peer_metadata_storage;
+        assert_eq!(
+            storage.get_last_known_epoch(&PeerNetworkId::new(network_id, staying_id)),
+            Some(2)
+        );
+        assert_eq!(
+            storage.get_last_known_epoch(&PeerNetworkId::new(network_id, departing_id)),
+            Some(1)
+        );
+
+        // Validator sets of older epochs are ignored
+        mock.send_update_validator_set(1, hashmap! {departing_id => departing})
+            .await;
+        assert_eq!(mock.get_connected_size().await, 1);
+        assert!(!mock.trusted_peers.read().contains_key(&departing_id));
+    };
+    block_on(future::join(conn_mgr.start(), test));
+}
+
 #[test]
 fn addr_change() {
     let (other_peer_id, other_peer, _, other_addr) = test_peer(AccountAddress::ZERO);
@@ -508,6 +646,56 @@ fn retry_on_failure() {
     block_on(future::join(conn_mgr.start(), test));
 }
 
+#[test]
+fn stop_dialing_after_max_attempts() {
+    let (other_peer_id, peer, _, other_addr) = test_peer(AccountAddress::ZERO);
+    let (mut mock, conn_mgr) =
+        TestHarness::new_with_retry_policy(HashMap::new(), DialRetryPolicy {
+            max_delay: MAX_CONNECTION_DELAY,
+            max_attempts: Some(2),
+            ..DialRetryPolicy::default()
+        });
+    let other_peer = PeerNetworkId::new(NetworkContext::mock().network_id(), other_peer_id);
+
+    let test = async move {
+        let peers = hashmap! {other_peer_id => peer.clone()};
+        mock.send_update_discovered_peers(DiscoverySource::OnChainValidatorSet, peers)
+            .await;
+
+        // Both dial attempts fail
+        for _ in 0..2 {
+            mock.trigger_connectivity_check().await;
+            mock.trigger_pending_dials().await;
+            mock.expect_one_dial_fail(other_peer_id, other_addr.clone())
+                .await;
+        }
+        let dial_backoff = mock
+            .peer_metadata_storage
+            .get_dial_backoff(&other_peer)
+            .unwrap();
+        assert_eq!(dial_backoff.attempts, 2);
+        assert!(dial_backoff.exhausted);
+
+        // The peer isn't dialed again
+        mock.trigger_connectivity_check().await;
+        assert_eq!(mock.get_dial_queue_size().await, 0);
+
+        // Until its address changes, which resets its backoff
+        let (peer, new_addr) = update_peer_with_address(peer, "/ip4/127.0.0.1/tcp/8080");
+        let peers = hashmap! {other_peer_id => peer};
+        mock.send_update_discovered_peers(DiscoverySource::OnChainValidatorSet, peers)
+            .await;
+        mock.trigger_connectivity_check().await;
+        mock.trigger_pending_dials().await;
+        mock.expect_one_dial_success(other_peer_id, new_addr).await;
+        assert_eq!(
+            mock.peer_metadata_storage.get_dial_backoff(&other_peer),
+            None
+        );
+    };
+    block_on(future::join(conn_mgr.start(), test));
+}
+
 // Tests that if we dial an already connected peer or disconnect from an already disconnected
 // peer, connectivity manager does not send any additional dial or disconnect requests.
 #[test]
@@ -806,3 +994,43 @@ fn basic_update_discovered_peers() {
     conn_mgr.handle_update_discovered_peers(DiscoverySource::Config, peers_empty.clone());
     assert_eq!(*trusted_peers.read(), peers_empty);
 }
+
+#[test]
+fn dial_vfns_before_public_peers_within_budgets() {
+    let network_context =
+        NetworkContext::new(RoleType::FullNode, NetworkId::Public, PeerId::random());
+    let (_mock, mut conn_mgr) = TestHarness::new_with_config(
+        network_context,
+        HashMap::new(),
+        DialRetryPolicy::default(),
+        DialConcurrencyConfig {
+            validator_fullnodes: Some(1),
+            public_peers: Some(1),
+            ..DialConcurrencyConfig::default()
+        },
+    );
+
+    let mut peers = PeerSet::new();
+    let mut vfns = HashSet::new();
+    for role in [
+        PeerRole::Upstream,
+        PeerRole::Upstream,
+        PeerRole::ValidatorFullNode,
+        PeerRole::ValidatorFullNode,
+    ] {
+        let (peer_id, mut peer, _, _) = test_peer(AccountAddress::random());
+        peer.role = role;
+        if role == PeerRole::ValidatorFullNode {
+            vfns.insert(peer_id);
+        }
+        peers.insert(peer_id, peer);
+    }
+    conn_mgr.handle_update_discovered_peers(DiscoverySource::Config, peers);
+
+    // A VFN is dialed first, and only one peer of each role is dialed, even
+    // though the outbound connection limit allows more
+    let peers_to_dial = conn_mgr.choose_peers_to_dial();
+    assert_eq!(peers_to_dial.len(), 2);
+    assert!(vfns.contains(&peers_to_dial[0].0));
+    assert_eq!(peers_to_dial[1].1.role, PeerRole::Upstream);
+}
@@ -13,11 +13,18 @@ pub const INBOUND_RPC_TIMEOUT_MS: u64 = 10_000;
 pub const MAX_CONCURRENT_OUTBOUND_RPCS: u32 = 100;
 /// Limit on concurrent Inbound RPC requests before backpressure is applied
 pub const MAX_CONCURRENT_INBOUND_RPCS: u32 = 100;
+/// The interval at which the local addresses are checked for changes
+pub const LOCAL_ADDR_CHECK_INTERVAL_MS: u64 = 5_000;
+/// The time migrating connections have to be re-established before their peers are lost
+pub const CONNECTION_MIGRATION_TIMEOUT_MS: u64 = 30_000;
+/// The time a peer has to be idle before it can be evicted by the longest-idle policy
+pub const MIN_IDLE_TIME_FOR_EVICTION_MS: u64 = 60_000;
 
 // These are only used in tests
 // TODO: Fix this so the tests and the defaults in config are the same
 pub const NETWORK_CHANNEL_SIZE: usize = 1024;
 pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024; /* 4 MiB */
 pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024; /* 64 MiB */
+pub const MAX_REASSEMBLY_SIZE: usize = 128 * 1024 * 1024; /* 128 MiB */
 pub const MAX_CONCURRENT_NETWORK_REQS: usize = 100;
 pub const MAX_CONCURRENT_NETWORK_NOTIFS: usize = 100;
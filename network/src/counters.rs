@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::protocols::wire::handshake::v1::ProtocolId;
-use aptos_config::network_id::NetworkContext;
+use aptos_config::network_id::{NetworkContext, NetworkId};
 use aptos_metrics_core::{
     register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
     Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
@@ -15,6 +15,7 @@ use once_cell::sync::Lazy;
 // some type labels
 pub const REQUEST_LABEL: &str = "request";
 pub const RESPONSE_LABEL: &str = "response";
+pub const CHUNK_LABEL: &str = "chunk";
 
 // some state labels
 pub const CANCELED_LABEL: &str = "canceled";
@@ -23,6 +24,26 @@ pub const RECEIVED_LABEL: &str = "received";
 pub const SENT_LABEL: &str = "sent";
 pub const SUCCEEDED_LABEL: &str = "succeeded";
 pub const FAILED_LABEL: &str = "failed";
+pub const ACCEPTED_LABEL: &str = "accepted";
+pub const REJECTED_LABEL: &str = "rejected";
+pub const INVALID_LABEL: &str = "invalid";
+pub const EXPIRED_LABEL: &str = "expired";
+
+// some direction labels
+pub const INBOUND_LABEL: &str = "inbound";
+pub const OUTBOUND_LABEL: &str = "outbound";
+
+// some replay protection labels
+pub const REPLAYED_LABEL: &str = "replayed";
+pub const UNSEQUENCED_LABEL: &str = "unsequenced";
+
+// some cache labels
+pub const HIT_LABEL: &str = "hit";
+pub const MISS_LABEL: &str = "miss";
+pub const INVALIDATED_LABEL: &str = "invalidated";
+
+// some peer metadata garbage collection labels
+pub const CAPACITY_LABEL: &str = "capacity";
 
 pub static APTOS_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
@@ -63,6 +84,264 @@ pub fn connections_rejected(
     ])
 }
 
+pub static APTOS_CONNECTIONS_EVICTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_connections_evicted",
+        "Number of connections evicted to make room for new ones per interface",
+        &["role_type", "network_id", "peer_id", "direction"]
+    )
+    .unwrap()
+});
+
+pub fn connections_evicted(
+    network_context: &NetworkContext,
+    origin: ConnectionOrigin,
+) -> IntCounter {
+    APTOS_CONNECTIONS_EVICTED.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        origin.as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_ACL_REJECTED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_acl_rejected_messages",
+        "Number of inbound messages rejected by the protocol access control lists",
+        &["role_type", "network_id", "peer_id", "protocol_id"]
+    )
+    .unwrap()
+});
+
+pub fn acl_rejected_messages(
+    network_context: &NetworkContext,
+    protocol_id: ProtocolId,
+) -> IntCounter {
+    APTOS_NETWORK_ACL_REJECTED_MESSAGES.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        protocol_id.as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_OVERSIZED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_oversized_messages",
+        "Number of messages dropped for exceeding the size limit of their protocol",
+        &[
+            "role_type",
+            "network_id",
+            "peer_id",
+            "protocol_id",
+            "direction"
+        ]
+    )
+    .unwrap()
+});
+
+pub fn oversized_messages(
+    network_context: &NetworkContext,
+    protocol_id: ProtocolId,
+    direction: &str,
+) -> IntCounter {
+    APTOS_NETWORK_OVERSIZED_MESSAGES.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        protocol_id.as_str(),
+        direction,
+    ])
+}
+
+pub static APTOS_NETWORK_REPLAYED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_replayed_messages",
+        "Number of inbound messages of replay-protected protocols that were dropped, by reason",
+        &[
+            "role_type",
+            "network_id",
+            "peer_id",
+            "protocol_id",
+            "reason"
+        ]
+    )
+    .unwrap()
+});
+
+pub fn replayed_messages(
+    network_context: &NetworkContext,
+    protocol_id: ProtocolId,
+    reason: &str,
+) -> IntCounter {
+    APTOS_NETWORK_REPLAYED_MESSAGES.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        protocol_id.as_str(),
+        reason,
+    ])
+}
+
+pub static APTOS_NETWORK_INBOUND_RATE_LIMITED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_inbound_rate_limited_messages",
+        "Number of inbound messages dropped for exceeding the per-peer rate limits",
+        &["role_type", "network_id", "peer_id", "protocol_id"]
+    )
+    .unwrap()
+});
+
+pub fn inbound_rate_limited_messages(
+    network_context: &NetworkContext,
+    protocol_id: ProtocolId,
+) -> IntCounter {
+    APTOS_NETWORK_INBOUND_RATE_LIMITED_MESSAGES.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        protocol_id.as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_OUTBOUND_QUEUE_DROPPED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_outbound_queue_dropped_messages",
+        "Number of outbound messages dropped because the peer's outbound queue was full",
+        &["role_type", "network_id", "peer_id", "protocol_id"]
+    )
+    .unwrap()
+});
+
+pub fn outbound_queue_dropped_messages(
+    network_context: &NetworkContext,
+    protocol_id: ProtocolId,
+) -> IntCounter {
+    APTOS_NETWORK_OUTBOUND_QUEUE_DROPPED_MESSAGES.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        protocol_id.as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_RPC_RESPONSE_CACHE_HITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_rpc_response_cache_hits",
+        "Number of inbound rpc requests served from the response cache",
+        &["role_type", "network_id", "peer_id", "protocol_id"]
+    )
+    .unwrap()
+});
+
+pub fn rpc_response_cache_hits(
+    network_context: &NetworkContext,
+    protocol_id: ProtocolId,
+) -> IntCounter {
+    APTOS_NETWORK_RPC_RESPONSE_CACHE_HITS.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        protocol_id.as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_PENALIZED_PEERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_penalized_peers",
+        "Number of times peers were put in the penalty box for repeatedly exceeding the inbound rate limits",
+        &["role_type", "network_id", "peer_id"]
+    )
+    .unwrap()
+});
+
+pub fn penalized_peers(network_context: &NetworkContext) -> IntCounter {
+    APTOS_NETWORK_PENALIZED_PEERS.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_SEED_PEER_RELOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_seed_peer_reloads",
+        "Number of times the seed peers were reloaded at runtime",
+        &["role_type", "network_id", "peer_id"]
+    )
+    .unwrap()
+});
+
+pub fn seed_peer_reloads(network_context: &NetworkContext) -> IntCounter {
+    APTOS_NETWORK_SEED_PEER_RELOADS.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+    ])
+}
+
+pub static APTOS_NETWORK_PEER_EXCHANGE_SUMMARIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_peer_exchange_summaries",
+        "Number of peer summaries received through peer exchange, by admission result",
+        &["role_type", "network_id", "peer_id", "result"]
+    )
+    .unwrap()
+});
+
+pub fn peer_exchange_summaries(network_context: &NetworkContext, result: &str) -> IntCounter {
+    APTOS_NETWORK_PEER_EXCHANGE_SUMMARIES.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        result,
+    ])
+}
+
+pub static APTOS_NETWORK_NAT_HOLE_PUNCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_nat_hole_punches",
+        "Number of hole punches initiated by this node, by result",
+        &["role_type", "network_id", "peer_id", "result"]
+    )
+    .unwrap()
+});
+
+pub fn nat_hole_punches(network_context: &NetworkContext, succeeded: bool) {
+    let result = if succeeded {
+        SUCCEEDED_LABEL
+    } else {
+        FAILED_LABEL
+    };
+    APTOS_NETWORK_NAT_HOLE_PUNCHES
+        .with_label_values(&[
+            network_context.role().as_str(),
+            network_context.network_id().as_str(),
+            network_context.peer_id().short_str().as_str(),
+            result,
+        ])
+        .inc();
+}
+
+pub static APTOS_NETWORK_CONNECTION_MIGRATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_connection_migrations",
+        "Number of connections migrated after a local address change, by result",
+        &["role_type", "network_id", "peer_id", "result"]
+    )
+    .unwrap()
+});
+
+pub fn connection_migrations(network_context: &NetworkContext, result: &str) -> IntCounter {
+    APTOS_NETWORK_CONNECTION_MIGRATIONS.with_label_values(&[
+        network_context.role().as_str(),
+        network_context.network_id().as_str(),
+        network_context.peer_id().short_str().as_str(),
+        result,
+    ])
+}
+
 pub static APTOS_NETWORK_PEER_CONNECTED: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "aptos_network_peer_connected",
@@ -336,6 +615,26 @@ pub static PENDING_HEALTH_CHECKER_NETWORK_EVENTS: Lazy<IntCounterVec> = Lazy::ne
     .unwrap()
 });
 
+/// Counter of pending network events to NAT traversal.
+pub static PENDING_NAT_TRAVERSAL_NETWORK_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_pending_nat_traversal_events",
+        "Number of pending NAT traversal events by state",
+        &["state"]
+    )
+    .unwrap()
+});
+
+/// Counter of pending network events to PeerExchange.
+pub static PENDING_PEER_EXCHANGE_NETWORK_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_pending_peer_exchange_events",
+        "Number of pending peer exchange events by state",
+        &["state"]
+    )
+    .unwrap()
+});
+
 /// Counter of pending network events to Discovery.
 pub static PENDING_DISCOVERY_NETWORK_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
@@ -414,6 +713,15 @@ pub static PENDING_MULTIPLEX_STREAM: Lazy<IntGauge> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Counter of messages pending in the queues of the substreams
+pub static PENDING_SUBSTREAM_MESSAGES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_network_pending_substream_messages",
+        "Number of pending messages to be streamed on substreams"
+    )
+    .unwrap()
+});
+
 /// Counter of pending requests in Direct Send
 pub static PENDING_DIRECT_SEND_REQUESTS: Lazy<IntGauge> = Lazy::new(|| {
     register_int_gauge!(
@@ -556,3 +864,200 @@ pub fn network_application_outbound_traffic(
         ])
         .observe(size as f64);
 }
+
+pub static APTOS_NETWORK_APPLICATION_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_application_messages",
+        "Number of application messages (direct send and rpc) by protocol",
+        &["network_id", "protocol_id", "direction"]
+    )
+    .unwrap()
+});
+
+pub static APTOS_NETWORK_APPLICATION_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_application_bytes",
+        "Number of application bytes (direct send and rpc) by protocol",
+        &["network_id", "protocol_id", "direction"]
+    )
+    .unwrap()
+});
+
+/// Records a single application message of the given size
+pub fn application_traffic(
+    network_id: NetworkId,
+    protocol_id: ProtocolId,
+    direction_label: &'static str,
+    size: u64,
+) {
+    let labels = [network_id.as_str(), protocol_id.as_str(), direction_label];
+    APTOS_NETWORK_APPLICATION_MESSAGES
+        .with_label_values(&labels)
+        .inc();
+    APTOS_NETWORK_APPLICATION_BYTES
+        .with_label_values(&labels)
+        .inc_by(size);
+}
+
+pub static APTOS_NETWORK_APPLICATION_RPCS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_application_rpcs",
+        "Number of completed outbound application rpcs by protocol",
+        &["network_id", "protocol_id", "state"]
+    )
+    .unwrap()
+});
+
+pub static APTOS_NETWORK_APPLICATION_RPC_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aptos_network_application_rpc_latency_seconds",
+        "Latency of successful outbound application rpcs by protocol in seconds",
+        &["network_id", "protocol_id"]
+    )
+    .unwrap()
+});
+
+/// Records the result of an outbound application rpc. The latency is only
+/// recorded for successful rpcs.
+pub fn application_rpc_completed(
+    network_id: NetworkId,
+    protocol_id: ProtocolId,
+    latency: Option<f64>,
+) {
+    let state_label = if latency.is_some() {
+        SUCCEEDED_LABEL
+    } else {
+        FAILED_LABEL
+    };
+    APTOS_NETWORK_APPLICATION_RPCS
+        .with_label_values(&[network_id.as_str(), protocol_id.as_str(), state_label])
+        .inc();
+    if let Some(latency) = latency {
+        APTOS_NETWORK_APPLICATION_RPC_LATENCY
+            .with_label_values(&[network_id.as_str(), protocol_id.as_str()])
+            .observe(latency);
+    }
+}
+
+pub static APTOS_NETWORK_DEDUPLICATED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_deduplicated_messages",
+        "Number of duplicate broadcast messages that were skipped (outbound) or dropped (inbound)",
+        &["network_id", "protocol_id", "direction"]
+    )
+    .unwrap()
+});
+
+pub fn deduplicated_messages(
+    network_id: NetworkId,
+    protocol_id: ProtocolId,
+    direction: &'static str,
+) -> IntCounter {
+    APTOS_NETWORK_DEDUPLICATED_MESSAGES.with_label_values(&[
+        network_id.as_str(),
+        protocol_id.as_str(),
+        direction,
+    ])
+}
+
+pub static APTOS_NETWORK_CACHE_OPERATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_cache_operations",
+        "Number of hits, misses and invalidations of the network caches",
+        &["cache", "operation"]
+    )
+    .unwrap()
+});
+
+pub fn cache_operations(cache: &'static str, operation: &'static str) -> IntCounter {
+    APTOS_NETWORK_CACHE_OPERATIONS.with_label_values(&[cache, operation])
+}
+
+pub static APTOS_NETWORK_CACHE_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "aptos_network_cache_size",
+        "Number of entries in the network caches",
+        &["cache"]
+    )
+    .unwrap()
+});
+
+pub fn cache_size(cache: &'static str) -> IntGauge {
+    APTOS_NETWORK_CACHE_SIZE.with_label_values(&[cache])
+}
+
+pub static APTOS_NETWORK_PEER_METADATA_ENTRIES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "aptos_network_peer_metadata_entries",
+        "Number of peers tracked in each peer metadata map",
+        &["network_id", "map"]
+    )
+    .unwrap()
+});
+
+pub fn peer_metadata_entries(network_id: NetworkId, map: &'static str, entries: usize) {
+    APTOS_NETWORK_PEER_METADATA_ENTRIES
+        .with_label_values(&[network_id.as_str(), map])
+        .set(entries as i64);
+}
+
+pub static APTOS_NETWORK_COLLECTED_PEER_METADATA: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_network_collected_peer_metadata",
+        "Number of disconnected peers whose metadata was garbage collected",
+        &["network_id", "reason"]
+    )
+    .unwrap()
+});
+
+pub fn collected_peer_metadata(network_id: NetworkId, reason: &'static str) -> IntCounter {
+    APTOS_NETWORK_COLLECTED_PEER_METADATA.with_label_values(&[network_id.as_str(), reason])
+}
+
+/// The application metrics of a single protocol on a single network, read
+/// from the in-process counters (i.e., without a Prometheus scrape). All
+/// values are cumulative since the process started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProtocolMetrics {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub rpcs_succeeded: u64,
+    pub rpcs_failed: u64,
+    /// The total latency (in seconds) of all successful rpcs
+    pub rpc_latency_sum: f64,
+}
+
+/// Returns the current application metrics of the given protocol on the
+/// given network
+pub fn protocol_metrics(network_id: NetworkId, protocol_id: ProtocolId) -> ProtocolMetrics {
+    let network_id = network_id.as_str();
+    let protocol_id = protocol_id.as_str();
+    let messages = |direction| {
+        APTOS_NETWORK_APPLICATION_MESSAGES
+            .with_label_values(&[network_id, protocol_id, direction])
+            .get()
+    };
+    let bytes = |direction| {
+        APTOS_NETWORK_APPLICATION_BYTES
+            .with_label_values(&[network_id, protocol_id, direction])
+            .get()
+    };
+    let rpcs = |state| {
+        APTOS_NETWORK_APPLICATION_RPCS
+            .with_label_values(&[network_id, protocol_id, state])
+            .get()
+    };
+    ProtocolMetrics {
+        messages_sent: messages(OUTBOUND_LABEL),
+        messages_received: messages(INBOUND_LABEL),
+        bytes_sent: bytes(OUTBOUND_LABEL),
+        bytes_received: bytes(INBOUND_LABEL),
+        rpcs_succeeded: rpcs(SUCCEEDED_LABEL),
+        rpcs_failed: rpcs(FAILED_LABEL),
+        rpc_latency_sum: APTOS_NETWORK_APPLICATION_RPC_LATENCY
+            .with_label_values(&[network_id, protocol_id])
+            .get_sample_sum(),
+    }
+}
@@ -10,6 +10,13 @@ use thiserror::Error;
 #[error(transparent)]
 pub struct NetworkError(anyhow::Error);
 
+impl NetworkError {
+    /// Returns the kind of the error, if known
+    pub fn kind(&self) -> Option<NetworkErrorKind> {
+        self.0.downcast_ref::<NetworkErrorKind>().copied()
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Error)]
 pub enum NetworkErrorKind {
     #[error("IO error")]
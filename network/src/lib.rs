@@ -20,6 +20,7 @@ pub mod noise;
 pub mod peer;
 pub mod peer_manager;
 pub mod protocols;
+pub mod tls;
 pub mod transport;
 
 #[cfg(feature = "fuzzing")]
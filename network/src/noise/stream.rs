@@ -6,10 +6,17 @@
 //! functions in this module enables encrypting and decrypting messages from a socket.
 //! Note that since noise is length-unaware, we have to prefix every noise message with its length
 //!
+//! Once rekeying is enabled (i.e., both peers negotiated it), each peer rotates the key it
+//! encrypts with after a number of bytes or an amount of time, and signals it to the other
+//! peer with an empty frame (i.e., a zero length prefix), after which the other peer rotates
+//! the key it decrypts with. This keeps long-lived connections forward secret, without
+//! tearing them down.
+//!
 //! [handshake]: crate::noise::handshake
 
 use aptos_crypto::{noise, x25519};
 use aptos_logger::prelude::*;
+use aptos_time_service::{TimeService, TimeServiceTrait};
 use futures::{
     io::{AsyncRead, AsyncWrite},
     ready,
@@ -19,8 +26,59 @@ use std::{
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
+/// The default number of bytes encrypted with a key before it's rotated
+pub const DEFAULT_REKEY_AFTER_BYTES: u64 = 1 << 30; // 1 GiB
+/// The default time after which a key is rotated
+pub const DEFAULT_REKEY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// When a [`NoiseStream`] rotates the key it encrypts with, whichever comes first
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RekeyPolicy {
+    /// The number of (encrypted) bytes written with a key before it's rotated
+    pub max_bytes: u64,
+    /// The time after which a key is rotated (on the next write)
+    pub max_interval: Duration,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_REKEY_AFTER_BYTES,
+            max_interval: DEFAULT_REKEY_INTERVAL,
+        }
+    }
+}
+
+/// The state of the rotation of the write key
+#[derive(Debug)]
+struct Rekeying {
+    policy: RekeyPolicy,
+    time_service: TimeService,
+    /// The number of bytes written with the current key
+    bytes_written: u64,
+    /// The time the current key was set
+    rekeyed_at: Instant,
+}
+
+impl Rekeying {
+    fn is_due(&self) -> bool {
+        self.bytes_written >= self.policy.max_bytes
+            || self
+                .time_service
+                .now()
+                .saturating_duration_since(self.rekeyed_at)
+                >= self.policy.max_interval
+    }
+
+    fn reset(&mut self) {
+        self.bytes_written = 0;
+        self.rekeyed_at = self.time_service.now();
+    }
+}
+
 //
 // NoiseStream
 // ------------
@@ -42,6 +100,10 @@ pub struct NoiseStream<TSocket> {
     read_state: ReadState,
     /// an enum used for progressively writing a noise payload
     write_state: WriteState,
+    /// the rotation of the keys, if both peers support it
+    rekeying: Option<Rekeying>,
+    /// whether the last frame read was a rekey frame, as two in a row are invalid
+    read_rekey_frame: bool,
 }
 
 impl<TSocket> NoiseStream<TSocket> {
@@ -53,9 +115,23 @@ impl<TSocket> NoiseStream<TSocket> {
             buffers: Box::new(NoiseBuffers::new()),
             read_state: ReadState::Init,
             write_state: WriteState::Init,
+            rekeying: None,
+            read_rekey_frame: false,
         }
     }
 
+    /// Rotate the keys as per the given policy, and accept the key rotations of
+    /// the remote. Must only be enabled if the remote supports it.
+    pub fn enable_rekeying(&mut self, policy: RekeyPolicy, time_service: TimeService) {
+        let rekeyed_at = time_service.now();
+        self.rekeying = Some(Rekeying {
+            policy,
+            time_service,
+            bytes_written: 0,
+            rekeyed_at,
+        });
+    }
+
     /// Pull out the static public key of the remote
     pub fn get_remote_static(&self) -> x25519::PublicKey {
         self.session.get_remote_static()
@@ -111,8 +187,16 @@ where
                         Ok(Some(frame_len)) => {
                             // Empty Frame
                             if frame_len == 0 {
-                                // 0-length messages are not expected
-                                self.read_state = ReadState::Eof(Err(()));
+                                if self.rekeying.is_none() || self.read_rekey_frame {
+                                    // 0-length messages are only expected to rekey
+                                    self.read_state = ReadState::Eof(Err(()));
+                                } else if let Err(e) = self.session.rekey_read() {
+                                    error!(error = %e, "Rekey Error: {}", e);
+                                    self.read_state = ReadState::DecryptionError(e);
+                                } else {
+                                    self.read_rekey_frame = true;
+                                    self.read_state = ReadState::Init;
+                                }
                             } else {
                                 self.read_state = ReadState::ReadFrame {
                                     frame_len,
@@ -146,6 +230,7 @@ where
                                 &mut self.buffers.read_buffer[..(frame_len as usize)],
                             ) {
                                 Ok(decrypted) => {
+                                    self.read_rekey_frame = false;
                                     self.read_state = ReadState::CopyDecryptedFrame {
                                         decrypted_len: decrypted.len(),
                                         offset: 0,
@@ -209,6 +294,8 @@ where
 enum WriteState {
     /// Initial State
     Init,
+    /// Write an empty frame, to signal that the write key is rotated
+    WriteRekeyFrame { offset: usize },
     /// Buffer provided data
     BufferData { offset: usize },
     /// Write encrypted frame to the wire
@@ -243,11 +330,47 @@ where
             match self.write_state {
                 WriteState::Init => {
                     if buf.is_some() {
-                        self.write_state = WriteState::BufferData { offset: 0 };
+                        let rekey_due = self.rekeying.as_ref().map_or(false, Rekeying::is_due);
+                        self.write_state = if rekey_due {
+                            WriteState::WriteRekeyFrame { offset: 0 }
+                        } else {
+                            WriteState::BufferData { offset: 0 }
+                        };
                     } else {
                         return Poll::Ready(Ok(None));
                     }
                 },
+                WriteState::WriteRekeyFrame { ref mut offset } => {
+                    match ready!(poll_write_all(
+                        context,
+                        Pin::new(&mut self.socket),
+                        &u16::to_be_bytes(0),
+                        offset
+                    )) {
+                        Ok(()) => {
+                            // the frames after the rekey frame are encrypted with the new key
+                            if let Err(e) = self.session.rekey_write() {
+                                error!(error = %e, "Rekey Error: {}", e);
+                                let err = io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!("EncryptionError: {}", e),
+                                );
+                                self.write_state = WriteState::EncryptionError(e);
+                                return Poll::Ready(Err(err));
+                            }
+                            if let Some(rekeying) = self.rekeying.as_mut() {
+                                rekeying.reset();
+                            }
+                            self.write_state = WriteState::BufferData { offset: 0 };
+                        },
+                        Err(e) => {
+                            if e.kind() == io::ErrorKind::WriteZero {
+                                self.write_state = WriteState::Eof;
+                            }
+                            return Poll::Ready(Err(e));
+                        },
+                    }
+                },
                 WriteState::BufferData { ref mut offset } => {
                     let bytes_buffered = if let Some(buf) = buf {
                         let bytes_to_copy =
@@ -272,6 +395,9 @@ where
                                     .copy_from_slice(&authentication_tag);
                                 // calculate frame length
                                 let frame_len = noise::encrypted_len(*offset);
+                                if let Some(rekeying) = self.rekeying.as_mut() {
+                                    rekeying.bytes_written += frame_len as u64;
+                                }
                                 let frame_len = frame_len
                                     .try_into()
                                     .expect("offset should be able to fit in u16");
@@ -668,6 +794,58 @@ mod test {
         assert_eq!(&buf_receive[..], &buf_send[..]);
     }
 
+    #[test]
+    fn rekeying() {
+        // perform handshake with two testing peers, which rotate their keys
+        let ((client, _client_public), (server, server_public)) = build_peers();
+        let (mut client, mut server) = perform_handshake(client, server_public, server);
+        let time_service = TimeService::mock();
+        let policy = RekeyPolicy {
+            max_bytes: 64,
+            max_interval: Duration::from_secs(60),
+        };
+        client.enable_rekeying(policy, time_service.clone());
+        server.enable_rekeying(policy, time_service.clone());
+
+        // the client rotates its key once it wrote the max bytes
+        block_on(client.write_all(&[1; 100])).unwrap();
+        block_on(client.flush()).unwrap();
+        block_on(client.write_all(b"The Name of the Wind")).unwrap();
+        block_on(client.flush()).unwrap();
+        let client_rekeying = client.rekeying.as_ref().unwrap();
+        assert_eq!(
+            client_rekeying.bytes_written,
+            noise::encrypted_len(20) as u64
+        );
+
+        // the server rotates its key once the max interval elapsed
+        block_on(server.write_all(b"The Wise Man's Fear")).unwrap();
+        block_on(server.flush()).unwrap();
+        time_service.into_mock().advance(Duration::from_secs(60));
+        block_on(server.write_all(b"The Doors of Stone")).unwrap();
+        block_on(server.flush()).unwrap();
+        let server_rekeying = server.rekeying.as_ref().unwrap();
+        assert_eq!(
+            server_rekeying.bytes_written,
+            noise::encrypted_len(18) as u64
+        );
+
+        // the messages written before and after the rotations are read
+        let mut buf = [0; 100];
+        block_on(server.read_exact(&mut buf)).unwrap();
+        assert_eq!(buf, [1; 100]);
+        let mut buf = [0; 20];
+        block_on(server.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"The Name of the Wind");
+
+        let mut buf = [0; 19];
+        block_on(client.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"The Wise Man's Fear");
+        let mut buf = [0; 18];
+        block_on(client.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"The Doors of Stone");
+    }
+
     #[test]
     fn fragmented_stream() {
         // create an in-memory socket for testing
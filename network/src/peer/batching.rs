@@ -0,0 +1,96 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Batching of small outbound messages.
+//!
+//! The writer of a [`Peer`](crate::peer::Peer) flushes every frame to the socket
+//! as soon as it's written, i.e., each message costs (at least) one syscall and
+//! one packet. For chatty protocols with small messages (e.g., health checks and
+//! vote gossip) this overhead dominates. The small messages of the protocols
+//! with batching enabled are instead buffered by the writer, and flushed in a
+//! single write once the batch is full, a non-batched frame is written, or the
+//! protocol's maximum delay elapsed (whichever comes first).
+
+use crate::{protocols::wire::messaging::v1::MultiplexMessage, ProtocolId};
+use std::{collections::HashMap, time::Duration};
+
+/// The maximum payload size of a message that is batched
+pub const MAX_BATCHED_MESSAGE_SIZE: usize = 1024;
+
+/// The maximum total payload size of a batch, after which it's flushed
+pub const MAX_BATCH_SIZE: usize = 64 * 1024;
+
+/// The protocols whose small outbound messages are batched, with the maximum
+/// delay by which each of them may be held back. Shared by the writers of all
+/// the connections of a network.
+#[derive(Clone, Debug, Default)]
+pub struct MessageBatching {
+    max_delays: HashMap<ProtocolId, Duration>,
+}
+
+impl MessageBatching {
+    /// Batches the small messages of the protocol, holding them back by at most
+    /// the given delay (rounded up to the resolution of the timer)
+    pub fn insert(&mut self, protocol_id: ProtocolId, max_delay: Duration) {
+        self.max_delays.insert(protocol_id, max_delay);
+    }
+
+    /// Returns true iff the messages of no protocol are batched
+    pub fn is_empty(&self) -> bool {
+        self.max_delays.is_empty()
+    }
+
+    /// Returns the maximum delay of the message if it can be batched, i.e., it's
+    /// a small message of a protocol with batching enabled
+    pub fn max_delay(&self, message: &MultiplexMessage) -> Option<Duration> {
+        match message {
+            MultiplexMessage::Message(message)
+                if message.data_len() <= MAX_BATCHED_MESSAGE_SIZE =>
+            {
+                message
+                    .protocol_id()
+                    .and_then(|protocol_id| self.max_delays.get(&protocol_id).copied())
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocols::wire::messaging::v1::{DirectSendMsg, NetworkMessage};
+    use bytes::Bytes;
+
+    fn direct_send(protocol_id: ProtocolId, len: usize) -> MultiplexMessage {
+        MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
+            protocol_id,
+            priority: 0,
+            raw_msg: Bytes::from(vec![0; len]),
+        }))
+    }
+
+    #[test]
+    fn only_small_messages_of_batched_protocols_are_batched() {
+        let mut batching = MessageBatching::default();
+        assert!(batching.is_empty());
+        let max_delay = Duration::from_micros(200);
+        batching.insert(ProtocolId::HealthCheckerRpc, max_delay);
+
+        assert_eq!(
+            batching.max_delay(&direct_send(ProtocolId::HealthCheckerRpc, 10)),
+            Some(max_delay)
+        );
+        assert_eq!(
+            batching.max_delay(&direct_send(
+                ProtocolId::HealthCheckerRpc,
+                MAX_BATCHED_MESSAGE_SIZE + 1
+            )),
+            None
+        );
+        assert_eq!(
+            batching.max_delay(&direct_send(ProtocolId::MempoolDirectSend, 10)),
+            None
+        );
+    }
+}
@@ -3,10 +3,15 @@
 
 use crate::{
     constants,
-    peer::Peer,
-    protocols::wire::{
-        handshake::v1::{MessagingProtocolVersion, ProtocolIdSet},
-        messaging::v1::{MultiplexMessage, MultiplexMessageSink},
+    peer::{batching::MessageBatching, BandwidthBuckets, Peer},
+    peer_manager::OutboundQueue,
+    protocols::{
+        direct_send::replay::ReplayProtection,
+        rpc::InboundRpcConcurrencyLimits,
+        wire::{
+            handshake::v1::{MessagingProtocolVersion, ProtocolIdSet},
+            messaging::v1::{MultiplexMessage, MultiplexMessageSink},
+        },
     },
     testutils::fake_socket::ReadOnlyTestSocketVec,
     transport::{Connection, ConnectionId, ConnectionMetadata},
@@ -20,7 +25,7 @@ use aptos_time_service::TimeService;
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use futures::{executor::block_on, future, io::AsyncReadExt, sink::SinkExt, stream::StreamExt};
 use proptest::{arbitrary::any, collection::vec};
-use std::time::Duration;
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
 /// Generate a sequence of `MultiplexMessage`, bcs serialize them, and write them
 /// out to a buffer using our length-prefixed message codec.
@@ -84,6 +89,7 @@ pub fn fuzz(data: &[u8]) {
         MessagingProtocolVersion::V1,
         ProtocolIdSet::all_known(),
         PeerRole::Unknown,
+        None,
     );
     let connection = Connection { socket, metadata };
 
@@ -105,10 +111,19 @@ pub fn fuzz(data: &[u8]) {
         Duration::from_millis(constants::INBOUND_RPC_TIMEOUT_MS),
         constants::MAX_CONCURRENT_INBOUND_RPCS,
         constants::MAX_CONCURRENT_OUTBOUND_RPCS,
+        InboundRpcConcurrencyLimits::default(),
         constants::MAX_FRAME_SIZE,
         constants::MAX_MESSAGE_SIZE,
+        constants::MAX_REASSEMBLY_SIZE,
         None,
         None,
+        BandwidthBuckets::default(),
+        BandwidthBuckets::default(),
+        Arc::new(OutboundQueue::new(constants::NETWORK_CHANNEL_SIZE)),
+        ReplayProtection::default(),
+        MessageBatching::default(),
+        Arc::new(HashSet::new()),
+        Arc::new(OutboundQueue::new(constants::NETWORK_CHANNEL_SIZE)),
     );
     executor.spawn(peer.start());
 
@@ -17,17 +17,39 @@
 use crate::{
     counters::{
         self, network_application_inbound_traffic, network_application_outbound_traffic,
-        RECEIVED_LABEL, SENT_LABEL,
+        INBOUND_LABEL, OUTBOUND_LABEL, RECEIVED_LABEL, SENT_LABEL,
     },
     logging::NetworkSchema,
-    peer_manager::{PeerManagerError, TransportNotification},
+    peer::batching::{MessageBatching, MAX_BATCH_SIZE},
+    peer_manager::{OutboundQueue, PeerManagerError, TransportNotification},
     protocols::{
-        direct_send::Message,
-        rpc::{InboundRpcRequest, InboundRpcs, OutboundRpcRequest, OutboundRpcs},
-        stream::{InboundStreamBuffer, OutboundStream, StreamMessage},
-        wire::messaging::v1::{
-            DirectSendMsg, ErrorCode, MultiplexMessage, MultiplexMessageSink,
-            MultiplexMessageStream, NetworkMessage, Priority, ReadError, WriteError,
+        direct_send::{
+            acks::{AckedMessage, InboundAckedMessage, InboundAcks, OutboundAcks},
+            replay::ReplayProtection,
+            Message,
+        },
+        rpc::{
+            error::RpcError,
+            streaming::{
+                InboundStreamingRpcRequest, InboundStreamingRpcs, OutboundStreamingRpcRequest,
+                OutboundStreamingRpcs,
+            },
+            InboundRpcConcurrencyLimits, InboundRpcRequest, InboundRpcs, OutboundRpcRequest,
+            OutboundRpcs,
+        },
+        stream::{
+            substream_id, InboundStreamBuffer, OutboundStream, StreamMessage, NUM_SUBSTREAMS,
+        },
+        wire::{
+            compression,
+            handshake::v2::Feature,
+            messaging::v1::{
+                new_correlation_id, AckedDirectSendMsg, CorrelatedDirectSendMsg, CorrelationId,
+                DirectSendMsg, ErrorCode, Goodbye, MessagePriority, MultiplexMessage,
+                MultiplexMessageSink, MultiplexMessageStream, NetworkMessage,
+                PrioritizedMessageQueue, Priority, ReadError, RequestId, RpcRequest, RpcResponse,
+                SequencedDirectSendMsg, WriteError,
+            },
         },
     },
     transport::{self, Connection, ConnectionMetadata},
@@ -36,7 +58,7 @@ use crate::{
 use aptos_channels::aptos_channel;
 use aptos_config::network_id::NetworkContext;
 use aptos_logger::prelude::*;
-use aptos_rate_limiter::rate_limit::SharedBucket;
+use aptos_rate_limiter::{async_lib::AsyncRateLimiter, rate_limit::SharedBucket};
 use aptos_short_hex_str::AsShortHexStr;
 use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::PeerId;
@@ -44,21 +66,31 @@ use bytes::Bytes;
 use futures::{
     self,
     channel::oneshot,
+    future::{BoxFuture, Fuse},
     io::{AsyncRead, AsyncWrite},
-    stream::StreamExt,
-    SinkExt,
+    stream::{FusedStream, StreamExt},
+    FutureExt, SinkExt,
 };
-use futures_util::stream::select;
+use futures_util::stream::{select, select_all, select_with_strategy, PollNext};
 use serde::Serialize;
-use std::{fmt, panic, time::Duration};
+use std::{collections::HashSet, fmt, panic, sync::Arc, time::Duration};
 use tokio::runtime::Handle;
 use tokio_util::compat::{
     FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt,
 };
 
+pub mod batching;
 #[cfg(test)]
 mod test;
 
+/// The number of frames of each substream queued for the writer, which bounds
+/// the frames a latency-sensitive stream waits behind
+const SUBSTREAM_WINDOW_SIZE: usize = 16;
+
+/// The relative change of the smoothed RTT after which it's reported to the
+/// PeerManager again
+const RTT_REPORT_THRESHOLD: f64 = 0.1;
+
 #[cfg(any(test, feature = "fuzzing"))]
 pub mod fuzzing;
 
@@ -69,6 +101,37 @@ pub enum PeerRequest {
     SendRpc(OutboundRpcRequest),
     /// Fire-and-forget style message send to peer.
     SendDirectSend(Message),
+    /// Send a streaming RPC request to peer.
+    SendStreamingRpc(OutboundStreamingRpcRequest),
+    /// Message send to peer, whose delivery is acknowledged by the peer.
+    SendAckedDirectSend(AckedMessage),
+    /// Gracefully close the connection (with the given reason), once the
+    /// pending requests completed or the drain timeout elapsed.
+    Drain(String, Duration),
+}
+
+impl PeerRequest {
+    /// Returns the protocol of the message sent by the request, if any
+    pub fn protocol_id(&self) -> Option<ProtocolId> {
+        match self {
+            PeerRequest::SendRpc(request) => Some(request.protocol_id),
+            PeerRequest::SendDirectSend(message) => Some(message.protocol_id),
+            PeerRequest::SendStreamingRpc(request) => Some(request.protocol_id),
+            PeerRequest::SendAckedDirectSend(message) => Some(message.message.protocol_id),
+            PeerRequest::Drain(..) => None,
+        }
+    }
+
+    /// Returns the payload size of the message sent by the request, if any
+    pub fn payload_len(&self) -> Option<usize> {
+        match self {
+            PeerRequest::SendRpc(request) => Some(request.data.len()),
+            PeerRequest::SendDirectSend(message) => Some(message.mdata.len()),
+            PeerRequest::SendStreamingRpc(request) => Some(request.data.len()),
+            PeerRequest::SendAckedDirectSend(message) => Some(message.message.mdata.len()),
+            PeerRequest::Drain(..) => None,
+        }
+    }
 }
 
 /// Notifications that [`Peer`] sends to the [`PeerManager`](crate::peer_manager::PeerManager).
@@ -78,6 +141,22 @@ pub enum PeerNotification {
     RecvRpc(InboundRpcRequest),
     /// A new message has been received from peer.
     RecvMessage(Message),
+    /// A new streaming RPC request has been received from peer.
+    RecvStreamingRpc(InboundStreamingRpcRequest),
+    /// A new message, whose delivery is acknowledged, has been received from peer.
+    RecvAckedMessage(InboundAckedMessage),
+}
+
+impl PeerNotification {
+    /// Returns the payload size of the received message or rpc request
+    pub fn payload_len(&self) -> usize {
+        match self {
+            PeerNotification::RecvRpc(request) => request.data.len(),
+            PeerNotification::RecvMessage(message) => message.mdata.len(),
+            PeerNotification::RecvStreamingRpc(request) => request.data.len(),
+            PeerNotification::RecvAckedMessage(message) => message.message.mdata.len(),
+        }
+    }
 }
 
 /// The reason for closing a connection.
@@ -90,6 +169,8 @@ pub enum PeerNotification {
 pub enum DisconnectReason {
     Requested,
     ConnectionLost,
+    /// The connection was gracefully drained, at the request of either end
+    Drained,
 }
 
 impl fmt::Display for DisconnectReason {
@@ -97,13 +178,37 @@ impl fmt::Display for DisconnectReason {
         let s = match self {
             DisconnectReason::Requested => "Requested",
             DisconnectReason::ConnectionLost => "ConnectionLost",
+            DisconnectReason::Drained => "Drained",
         };
         write!(f, "{}", s)
     }
 }
 
+/// The bandwidth caps of one direction of a connection, in addition to the cap of
+/// its remote IP address. Missing buckets aren't enforced.
+#[derive(Clone, Default)]
+pub struct BandwidthBuckets {
+    /// Caps the traffic of the remote peer, across all of its connections
+    pub peer: Option<SharedBucket>,
+    /// Caps the traffic of all remote peers of the network
+    pub network: Option<SharedBucket>,
+}
+
+impl BandwidthBuckets {
+    /// Wraps the given socket (or socket half) to enforce the caps
+    fn limit<T>(&self, socket: T) -> AsyncRateLimiter<AsyncRateLimiter<T>> {
+        AsyncRateLimiter::new(
+            AsyncRateLimiter::new(socket, self.peer.clone()),
+            self.network.clone(),
+        )
+    }
+}
+
 enum State {
     Connected,
+    /// No new requests are sent over the connection, and it's closed once
+    /// the pending ones completed
+    Draining,
     ShuttingDown(DisconnectReason),
 }
 
@@ -130,18 +235,49 @@ pub struct Peer<TSocket> {
     inbound_rpcs: InboundRpcs,
     /// Outbound rpc request queue for sending requests to remote peer and handling responses.
     outbound_rpcs: OutboundRpcs,
+    /// Inbound streaming rpc queue for streaming responses to the remote peer.
+    inbound_streaming_rpcs: InboundStreamingRpcs,
+    /// Outbound streaming rpc queue for receiving response chunks from the remote peer.
+    outbound_streaming_rpcs: OutboundStreamingRpcs,
+    /// Acknowledgements of inbound acked direct-send messages, pending their delivery.
+    inbound_acks: InboundAcks,
+    /// Outbound acked direct-send messages, pending their acknowledgement.
+    outbound_acks: OutboundAcks,
+    /// The nonces of outbound direct-send messages, and the replay windows of
+    /// inbound ones (shared by all connections of the network).
+    replay_protection: ReplayProtection,
+    /// The protocols whose small outbound messages are batched by the writer
+    message_batching: MessageBatching,
+    /// The protocols whose requests have their own outbound queue, and their own
+    /// lane to the writer, ahead of the (bulk) messages of other protocols
+    isolated_protocols: Arc<HashSet<ProtocolId>>,
+    /// The accounting of the requests of isolated protocols queued in `peer_reqs_rx`
+    isolated_outbound_queue: Arc<OutboundQueue>,
     /// Flag to indicate if the actor is being shut down.
     state: State,
     /// The maximum size of an inbound or outbound request frame
     max_frame_size: usize,
     /// The maximum size of an inbound or outbound request message
     max_message_size: usize,
+    /// The maximum size of a message fragmented for (or reassembled from) a
+    /// peer that supports fragmentation
+    max_reassembly_size: usize,
     /// Optional inbound rate limiter
     inbound_rate_limiter: Option<SharedBucket>,
     /// Optional outbound rate limiter
     outbound_rate_limiter: Option<SharedBucket>,
+    /// Per-peer and per-network inbound bandwidth caps
+    inbound_bandwidth_buckets: BandwidthBuckets,
+    /// Per-peer and per-network outbound bandwidth caps
+    outbound_bandwidth_buckets: BandwidthBuckets,
     /// Inbound stream buffer
     inbound_stream: InboundStreamBuffer,
+    /// The smoothed RTT last reported to the PeerManager
+    reported_rtt: Option<Duration>,
+    /// Completes once the drain timeout elapsed, while the connection is drained
+    drain_timeout: Fuse<BoxFuture<'static, ()>>,
+    /// The accounting of the requests queued in `peer_reqs_rx`
+    outbound_queue: Arc<OutboundQueue>,
 }
 
 impl<TSocket> Peer<TSocket>
@@ -160,16 +296,31 @@ where
         inbound_rpc_timeout: Duration,
         max_concurrent_inbound_rpcs: u32,
         max_concurrent_outbound_rpcs: u32,
+        inbound_rpc_concurrency_limits: InboundRpcConcurrencyLimits,
         max_frame_size: usize,
         max_message_size: usize,
+        max_reassembly_size: usize,
         inbound_rate_limiter: Option<SharedBucket>,
         outbound_rate_limiter: Option<SharedBucket>,
+        inbound_bandwidth_buckets: BandwidthBuckets,
+        outbound_bandwidth_buckets: BandwidthBuckets,
+        outbound_queue: Arc<OutboundQueue>,
+        replay_protection: ReplayProtection,
+        message_batching: MessageBatching,
+        isolated_protocols: Arc<HashSet<ProtocolId>>,
+        isolated_outbound_queue: Arc<OutboundQueue>,
     ) -> Self {
         let Connection {
             metadata: connection_metadata,
             socket,
         } = connection;
         let remote_peer_id = connection_metadata.remote_peer_id;
+        let compressed_protocols = connection_metadata.features.compressed_protocols.clone();
+        let supports_correlation_ids = connection_metadata
+            .features
+            .supports(Feature::CorrelationIds);
+        let supports_rpc_expiry = connection_metadata.features.supports(Feature::RpcExpiry);
+        let supports_qos = connection_metadata.features.supports(Feature::Qos);
         let max_fragments = max_message_size / max_frame_size;
         Self {
             network_context,
@@ -184,21 +335,52 @@ where
                 network_context,
                 time_service.clone(),
                 remote_peer_id,
+                compressed_protocols.clone(),
                 inbound_rpc_timeout,
                 max_concurrent_inbound_rpcs,
+                inbound_rpc_concurrency_limits,
             ),
             outbound_rpcs: OutboundRpcs::new(
                 network_context,
-                time_service,
+                time_service.clone(),
                 remote_peer_id,
+                compressed_protocols,
+                supports_correlation_ids,
+                supports_rpc_expiry,
+                supports_qos,
                 max_concurrent_outbound_rpcs,
             ),
+            inbound_streaming_rpcs: InboundStreamingRpcs::new(
+                network_context,
+                time_service.clone(),
+                remote_peer_id,
+                inbound_rpc_timeout,
+                max_concurrent_inbound_rpcs,
+            ),
+            outbound_streaming_rpcs: OutboundStreamingRpcs::new(
+                network_context,
+                time_service.clone(),
+                remote_peer_id,
+                max_concurrent_outbound_rpcs,
+            ),
+            inbound_acks: InboundAcks::new(),
+            outbound_acks: OutboundAcks::new(time_service),
+            replay_protection,
+            message_batching,
+            isolated_protocols,
+            isolated_outbound_queue,
             state: State::Connected,
             max_frame_size,
             max_message_size,
+            max_reassembly_size,
             inbound_rate_limiter,
             outbound_rate_limiter,
-            inbound_stream: InboundStreamBuffer::new(max_fragments),
+            inbound_bandwidth_buckets,
+            outbound_bandwidth_buckets,
+            inbound_stream: InboundStreamBuffer::new(max_fragments, max_reassembly_size),
+            reported_rtt: None,
+            drain_timeout: Fuse::terminated(),
+            outbound_queue,
         }
     }
 
@@ -206,6 +388,41 @@ where
         self.connection_metadata.remote_peer_id
     }
 
+    /// Folds the latency of a completed outbound rpc into the smoothed RTT of
+    /// the connection, and reports the smoothed RTT to the PeerManager if it
+    /// changed significantly since it was last reported.
+    fn record_rtt_sample(&mut self, rtt: Duration) {
+        self.connection_metadata.record_rtt_sample(rtt);
+        let smoothed_rtt = match self.connection_metadata.smoothed_rtt {
+            Some(smoothed_rtt) => smoothed_rtt,
+            None => return,
+        };
+        let is_significant = self.reported_rtt.map_or(true, |reported_rtt| {
+            let change = smoothed_rtt
+                .checked_sub(reported_rtt)
+                .unwrap_or_else(|| reported_rtt - smoothed_rtt);
+            change.as_secs_f64() >= reported_rtt.as_secs_f64() * RTT_REPORT_THRESHOLD
+        });
+        if !is_significant {
+            return;
+        }
+
+        // Don't block the actor on the PeerManager, the next sample is reported instead
+        let notif = TransportNotification::MetadataUpdated(self.connection_metadata.clone());
+        match self.connection_notifs_tx.try_send(notif) {
+            Ok(()) => self.reported_rtt = Some(smoothed_rtt),
+            Err(err) => debug!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata(&self.connection_metadata),
+                error = %err,
+                "{} Failed to report the smoothed RTT of peer {}: {}",
+                self.network_context,
+                self.remote_peer_id().short_str(),
+                err
+            ),
+        }
+    }
+
     pub async fn start(mut self) {
         let remote_peer_id = self.remote_peer_id();
         trace!(
@@ -220,46 +437,78 @@ where
         let (read_socket, write_socket) =
             tokio::io::split(self.connection.take().unwrap().compat());
 
+        // Enforce the bandwidth caps of the peer and the network. The per-IP rate
+        // limiters are applied by the message stream and sink.
+        let read_socket = self.inbound_bandwidth_buckets.limit(read_socket.compat());
+        let write_socket = self
+            .outbound_bandwidth_buckets
+            .limit(write_socket.compat_write());
+
         let mut reader = MultiplexMessageStream::new(
-            read_socket.compat(),
+            read_socket,
             self.max_frame_size,
             self.inbound_rate_limiter.clone(),
         )
         .fuse();
         let writer = MultiplexMessageSink::new(
-            write_socket.compat_write(),
+            write_socket,
             self.max_frame_size,
             self.outbound_rate_limiter.clone(),
         );
 
-        // Start writer "process" as a separate task. We receive two handles to
+        // Start writer "process" as a separate task. We receive three handles to
         // communicate with the task:
         //   1. `write_reqs_tx`: Queue of pending NetworkMessages to write.
-        //   2. `close_tx`: Handle to close the task and underlying connection.
-        let (mut write_reqs_tx, writer_close_tx) = Self::start_writer_task(
-            &self.executor,
-            self.time_service.clone(),
-            self.connection_metadata.clone(),
-            self.network_context,
-            writer,
-            self.max_frame_size,
-            self.max_message_size,
-        );
+        //   2. `isolated_write_reqs_tx`: Queue of pending NetworkMessages of the
+        //      isolated protocols to write, ahead of the other messages.
+        //   3. `close_tx`: Handle to close the task and underlying connection.
+        let (mut write_reqs_tx, mut isolated_write_reqs_tx, writer_close_tx) =
+            Self::start_writer_task(
+                &self.executor,
+                self.time_service.clone(),
+                self.connection_metadata.clone(),
+                self.network_context,
+                writer,
+                self.max_frame_size,
+                self.max_message_size,
+                self.max_reassembly_size,
+                self.message_batching.clone(),
+            );
 
         // Start main Peer event loop.
         let reason = loop {
-            if let State::ShuttingDown(reason) = self.state {
-                break reason;
+            match self.state {
+                State::ShuttingDown(reason) => break reason,
+                State::Draining if self.is_drained() => break DisconnectReason::Drained,
+                _ => {},
             }
 
             futures::select! {
                 // Handle a new outbound request from the PeerManager.
                 maybe_request = self.peer_reqs_rx.next() => {
                     match maybe_request {
-                        Some(request) => self.handle_outbound_request(request, &mut write_reqs_tx).await,
+                        Some(request) => {
+                            let write_reqs_tx = if self.is_isolated(&request) {
+                                self.isolated_outbound_queue.dequeue(&request);
+                                // Large messages are streamed along with the bulk messages
+                                let max_frame_size = self.max_frame_size;
+                                if request.payload_len().map_or(true, |len| len <= max_frame_size) {
+                                    &mut isolated_write_reqs_tx
+                                } else {
+                                    &mut write_reqs_tx
+                                }
+                            } else {
+                                self.outbound_queue.dequeue(&request);
+                                &mut write_reqs_tx
+                            };
+                            self.handle_outbound_request(request, write_reqs_tx).await
+                        },
                         // The PeerManager is requesting this connection to close
-                        // by dropping the corresponding peer_reqs_tx handle.
-                        None => self.shutdown(DisconnectReason::Requested),
+                        // by dropping the corresponding peer_reqs_tx handle. Drained
+                        // connections are closed once the pending requests completed.
+                        None => if !matches!(self.state, State::Draining) {
+                            self.shutdown(DisconnectReason::Requested)
+                        },
                     }
                 },
                 // Handle a new inbound MultiplexMessage that we've just read off
@@ -280,25 +529,51 @@ where
                             }
                         },
                         // The socket was gracefully closed by the remote peer.
-                        None => self.shutdown(DisconnectReason::ConnectionLost),
+                        None => if matches!(self.state, State::Draining) {
+                            self.shutdown(DisconnectReason::Drained)
+                        } else {
+                            self.shutdown(DisconnectReason::ConnectionLost)
+                        },
                     }
                 },
                 // Drive the queue of pending inbound rpcs. When one is fulfilled
                 // by an upstream protocol, send the response to the remote peer.
-                maybe_response = self.inbound_rpcs.next_completed_response() => {
-                    if let Err(err) = self.inbound_rpcs.send_outbound_response(&mut write_reqs_tx, maybe_response).await {
+                (request_id, protocol_id, maybe_response) = self.inbound_rpcs.next_completed_response() => {
+                    self.handle_completed_inbound_rpc(request_id, protocol_id, maybe_response, &mut write_reqs_tx).await;
+                },
+                // Poll the queue of pending outbound rpc tasks for the next
+                // successfully or unsuccessfully completed request.
+                (request_id, maybe_completed_request) = self.outbound_rpcs.next_completed_request() => {
+                    let maybe_latency = maybe_completed_request.as_ref().ok().map(|(latency, _)| *latency);
+                    self.outbound_rpcs.handle_completed_request(request_id, maybe_completed_request);
+                    if let Some(latency) = maybe_latency {
+                        self.record_rtt_sample(Duration::from_secs_f64(latency));
+                    }
+                },
+                // Poll the streaming rpc tasks for the next completed stream
+                // (in either direction).
+                (request_id, result) = self.inbound_streaming_rpcs.next_completed_stream() => {
+                    self.inbound_streaming_rpcs.handle_completed_stream(request_id, result);
+                },
+                (request_id, result) = self.outbound_streaming_rpcs.next_completed_stream() => {
+                    self.outbound_streaming_rpcs.handle_completed_stream(request_id, result);
+                },
+                // Acknowledge the inbound acked direct-send messages, once they're
+                // handed to (or dropped before reaching) the application.
+                ack = self.inbound_acks.next_ack() => {
+                    if let Err(err) = write_reqs_tx.send(NetworkMessage::DirectSendAck(ack)).await {
                         warn!(
                             NetworkSchema::new(&self.network_context).connection_metadata(&self.connection_metadata),
                             error = %err,
-                            "{} Error in handling inbound rpc request, error: {}", self.network_context, err,
+                            "{} Error in sending direct send ack, error: {}", self.network_context, err,
                         );
                     }
                 },
-                // Poll the queue of pending outbound rpc tasks for the next
-                // successfully or unsuccessfully completed request.
-                (request_id, maybe_completed_request) = self.outbound_rpcs.next_completed_request() => {
-                    self.outbound_rpcs.handle_completed_request(request_id, maybe_completed_request);
-                }
+                request_id = self.outbound_acks.next_timeout() => {
+                    self.outbound_acks.handle_timeout(request_id);
+                },
+                // Close the drained connection, even if requests are still pending.
+                _ = &mut self.drain_timeout => self.shutdown(DisconnectReason::Drained),
             }
         };
 
@@ -308,12 +583,16 @@ where
     }
 
     // Start a new task on the given executor which is responsible for writing outbound messages on
-    // the wire. The function returns two channels which can be used to send instructions to the
+    // the wire. The function returns three channels which can be used to send instructions to the
     // task:
     // 1. The first channel is used to send outbound NetworkMessages to the task
-    // 2. The second channel is used to instruct the task to close the connection and terminate.
+    // 2. The second channel is used to send the outbound NetworkMessages of isolated protocols,
+    //    which are written before the messages (and stream fragments) of the first channel. The
+    //    messages must fit in a single frame.
+    // 3. The third channel is used to instruct the task to close the connection and terminate.
     // If outbound messages are queued when the task receives a close instruction, it discards
     // them and immediately closes the connection.
+    #[allow(clippy::too_many_arguments)]
     fn start_writer_task(
         executor: &Handle,
         time_service: TimeService,
@@ -322,23 +601,132 @@ where
         mut writer: MultiplexMessageSink<impl AsyncWrite + Unpin + Send + 'static>,
         max_frame_size: usize,
         max_message_size: usize,
-    ) -> (aptos_channels::Sender<NetworkMessage>, oneshot::Sender<()>) {
+        max_reassembly_size: usize,
+        message_batching: MessageBatching,
+    ) -> (
+        aptos_channels::Sender<NetworkMessage>,
+        aptos_channels::Sender<NetworkMessage>,
+        oneshot::Sender<bool>,
+    ) {
         let remote_peer_id = connection_metadata.remote_peer_id;
+        let supports_fragmentation = connection_metadata
+            .features
+            .supports(Feature::Fragmentation);
+        let supports_substreams = connection_metadata.features.supports(Feature::Substreams);
         let (write_reqs_tx, mut write_reqs_rx): (aptos_channels::Sender<NetworkMessage>, _) =
             aptos_channels::new(1024, &counters::PENDING_WIRE_MESSAGES);
+        let (isolated_write_reqs_tx, isolated_write_reqs_rx) =
+            aptos_channels::new(1024, &counters::PENDING_WIRE_MESSAGES);
         let (close_tx, mut close_rx) = oneshot::channel();
 
         let (mut msg_tx, msg_rx) = aptos_channels::new(1024, &counters::PENDING_MULTIPLEX_MESSAGE);
         let (stream_msg_tx, stream_msg_rx) =
             aptos_channels::new(1024, &counters::PENDING_MULTIPLEX_STREAM);
 
-        // this task ends when the multiplex task ends (by dropping the senders)
+        // If the peer supports substreams, each priority class streams its
+        // messages on its own substream, with its own queue and window of
+        // frames, so that a bulk transfer doesn't hold back the streams of
+        // latency-sensitive messages. The writer interleaves the frames of
+        // the substreams round-robin.
+        let mut substream_txs = vec![];
+        let mut substream_frame_rxs = vec![];
+        if supports_substreams {
+            for substream_id in 0..NUM_SUBSTREAMS as u8 {
+                let (substream_tx, mut substream_rx) =
+                    aptos_channels::new(1024, &counters::PENDING_SUBSTREAM_MESSAGES);
+                let (frame_tx, frame_rx) =
+                    aptos_channels::new(SUBSTREAM_WINDOW_SIZE, &counters::PENDING_MULTIPLEX_STREAM);
+                let mut outbound_substream =
+                    OutboundStream::new(max_frame_size, max_message_size, frame_tx)
+                        .with_substream(substream_id);
+                if supports_fragmentation {
+                    outbound_substream = outbound_substream.with_fragmentation(max_reassembly_size);
+                }
+                // this task ends when the multiplex task ends (by dropping the sender)
+                executor.spawn(async move {
+                    while let Some(message) = substream_rx.next().await {
+                        if let Err(err) = outbound_substream.stream_message(message).await {
+                            warn!(
+                                error = %err,
+                                "{} Error in streaming message to peer: {} on substream {}",
+                                network_context,
+                                remote_peer_id.short_str(),
+                                substream_id,
+                            );
+                        }
+                    }
+                });
+                substream_txs.push(substream_tx);
+                substream_frame_rxs.push(frame_rx);
+            }
+        }
+
+        // this task ends when the multiplex task ends (by dropping the senders),
+        // and the actor drops the sender of the isolated messages
         let writer_task = async move {
-            let mut stream = select(msg_rx, stream_msg_rx);
+            // The messages of isolated protocols are always written first, so
+            // that they never wait behind the bulk messages of other protocols
+            let mut stream = select_with_strategy(
+                isolated_write_reqs_rx.map(MultiplexMessage::Message),
+                select(
+                    select(msg_rx, stream_msg_rx),
+                    select_all(substream_frame_rxs),
+                ),
+                |_: &mut ()| PollNext::Left,
+            )
+            .fuse();
             let log_context =
                 NetworkSchema::new(&network_context).connection_metadata(&connection_metadata);
-            while let Some(message) = stream.next().await {
-                if let Err(err) = writer.send(&message).await {
+
+            // The small messages of batched protocols are written without being
+            // flushed, until the batch is full, a non-batched message is written
+            // (and flushed), or the earliest maximum delay of the batch elapsed.
+            let mut batch_size = 0;
+            let mut batch_deadline = None;
+            let mut batch_timeout: Fuse<BoxFuture<'static, ()>> = Fuse::terminated();
+            loop {
+                let message = futures::select! {
+                    message = stream.next() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = batch_timeout => {
+                        batch_size = 0;
+                        batch_deadline = None;
+                        if let Err(err) = writer.flush().await {
+                            warn!(
+                                log_context,
+                                error = %err,
+                                "{} Error in flushing messages to peer: {}",
+                                network_context,
+                                remote_peer_id.short_str(),
+                            );
+                        }
+                        continue;
+                    },
+                };
+                let message_size = match &message {
+                    MultiplexMessage::Message(message) => message.data_len(),
+                    MultiplexMessage::Stream(_) => 0,
+                };
+                let result = match message_batching.max_delay(&message) {
+                    Some(max_delay) if batch_size + message_size <= MAX_BATCH_SIZE => {
+                        let deadline = time_service.now() + max_delay;
+                        if batch_deadline.map_or(true, |batch_deadline| deadline < batch_deadline) {
+                            batch_deadline = Some(deadline);
+                            batch_timeout = time_service.sleep_until(deadline).boxed().fuse();
+                        }
+                        batch_size += message_size;
+                        writer.feed(&message).await
+                    },
+                    _ => {
+                        batch_size = 0;
+                        batch_deadline = None;
+                        batch_timeout = Fuse::terminated();
+                        writer.send(&message).await
+                    },
+                };
+                if let Err(err) = result {
                     warn!(
                         log_context,
                         error = %err,
@@ -394,41 +782,141 @@ where
         let multiplex_task = async move {
             let mut outbound_stream =
                 OutboundStream::new(max_frame_size, max_message_size, stream_msg_tx);
+            if supports_fragmentation {
+                outbound_stream = outbound_stream.with_fragmentation(max_reassembly_size);
+            }
+            let mut pending_messages = PrioritizedMessageQueue::new();
+            let mut closing = false;
             loop {
                 futures::select! {
-                    message = write_reqs_rx.select_next_some() => {
-                        // either channel full would block the other one
-                        let result = if outbound_stream.should_stream(&message) {
-                            outbound_stream.stream_message(message).await
+                    message = write_reqs_rx.select_next_some() => pending_messages.push(message),
+                    flush = close_rx => {
+                        // The queued messages of drained connections are flushed
+                        // before closing, otherwise they're dropped.
+                        if !matches!(flush, Ok(true)) {
+                            break;
+                        }
+                        closing = true;
+                    }
+                }
+
+                // Drain the queue highest priority first. Messages that are
+                // queued while we're blocked on a send are picked up before
+                // the next message is chosen.
+                loop {
+                    while let Some(Some(message)) = write_reqs_rx.next().now_or_never() {
+                        pending_messages.push(message);
+                    }
+                    let message = match pending_messages.pop() {
+                        Some(message) => message,
+                        None => break,
+                    };
+
+                    // either channel full would block the other one
+                    let result = if outbound_stream.should_stream(&message) {
+                        if supports_substreams {
+                            let substream_id = substream_id(message.priority()) as usize;
+                            substream_txs[substream_id]
+                                .send(message)
+                                .await
+                                .map_err(|_| anyhow::anyhow!("Substream task ended"))
                         } else {
-                            msg_tx.send(MultiplexMessage::Message(message)).await.map_err(|_| anyhow::anyhow!("Writer task ended"))
-                        };
-                        if let Err(err) = result {
-                            warn!(
-                                error = %err,
-                                "{} Error in sending message to peer: {}",
-                                network_context,
-                                remote_peer_id.short_str(),
-                            );
+                            outbound_stream.stream_message(message).await
                         }
-                    },
-                    _ = close_rx => {
-                        break;
+                    } else {
+                        msg_tx
+                            .send(MultiplexMessage::Message(message))
+                            .await
+                            .map_err(|_| anyhow::anyhow!("Writer task ended"))
+                    };
+                    if let Err(err) = result {
+                        warn!(
+                            error = %err,
+                            "{} Error in sending message to peer: {}",
+                            network_context,
+                            remote_peer_id.short_str(),
+                        );
                     }
                 }
+                if closing {
+                    break;
+                }
             }
         };
         executor.spawn(writer_task);
         executor.spawn(multiplex_task);
-        (write_reqs_tx, close_tx)
+        (write_reqs_tx, isolated_write_reqs_tx, close_tx)
     }
 
     async fn handle_inbound_network_message(
         &mut self,
         message: NetworkMessage,
+        write_reqs_tx: &mut aptos_channels::Sender<NetworkMessage>,
     ) -> Result<(), PeerManagerError> {
         match message {
-            NetworkMessage::DirectSendMsg(message) => self.handle_inbound_direct_send(message),
+            // Messages from peers that don't send correlation ids get a local one,
+            // so that they can still be traced through our own handlers.
+            NetworkMessage::DirectSendMsg(message) => {
+                if self.is_unsequenced_message_allowed(message.protocol_id) {
+                    self.handle_inbound_direct_send(message, new_correlation_id(), None)
+                }
+            },
+            NetworkMessage::CorrelatedDirectSendMsg(message) => {
+                let (message, correlation_id) = message.into_parts();
+                if self.is_unsequenced_message_allowed(message.protocol_id) {
+                    self.handle_inbound_direct_send(message, correlation_id, None)
+                }
+            },
+            NetworkMessage::AckedDirectSendMsg(message) => {
+                let (message, request_id) = message.into_parts();
+                // Dropping the delivery channel acknowledges the message as not delivered
+                let delivered_tx = self.inbound_acks.insert(request_id);
+                if self.is_unsequenced_message_allowed(message.protocol_id) {
+                    self.handle_inbound_direct_send(
+                        message,
+                        new_correlation_id(),
+                        Some(delivered_tx),
+                    )
+                }
+            },
+            NetworkMessage::SequencedDirectSendMsg(message) => {
+                let (message, nonce, correlation_id) = message.into_parts();
+                let protocol_id = message.protocol_id;
+                if self
+                    .replay_protection
+                    .accept(self.remote_peer_id(), protocol_id, nonce)
+                {
+                    self.handle_inbound_direct_send(message, correlation_id, None)
+                } else {
+                    counters::replayed_messages(
+                        &self.network_context,
+                        protocol_id,
+                        counters::REPLAYED_LABEL,
+                    )
+                    .inc();
+                    sample!(
+                        SampleRate::Duration(Duration::from_secs(10)),
+                        warn!(
+                            NetworkSchema::new(&self.network_context)
+                                .connection_metadata(&self.connection_metadata),
+                            correlation_id = correlation_id,
+                            "{} Dropping replayed message for protocol {} from peer {} (nonce {})",
+                            self.network_context,
+                            protocol_id,
+                            self.remote_peer_id().short_str(),
+                            nonce
+                        )
+                    );
+                }
+            },
+            NetworkMessage::DirectSendAck(ack) => self.outbound_acks.handle_inbound_ack(ack),
+            NetworkMessage::Goodbye(goodbye) => self.handle_goodbye(goodbye).await,
+            NetworkMessage::Error(ErrorCode::Busy(busy)) => {
+                self.outbound_rpcs.handle_inbound_busy(busy.request_id)
+            },
+            NetworkMessage::Error(ErrorCode::Expired(expired)) => self
+                .outbound_rpcs
+                .handle_inbound_expired(expired.request_id),
             NetworkMessage::Error(error_msg) => {
                 warn!(
                     NetworkSchema::new(&self.network_context)
@@ -441,30 +929,204 @@ where
                 );
             },
             NetworkMessage::RpcRequest(request) => {
-                if let Err(err) = self
-                    .inbound_rpcs
-                    .handle_inbound_request(&mut self.peer_notifs_tx, request)
-                {
+                self.handle_inbound_rpc_request(request, None, new_correlation_id(), write_reqs_tx)
+                    .await
+            },
+            NetworkMessage::RpcResponse(response) => {
+                self.outbound_rpcs.handle_inbound_response(response)
+            },
+            NetworkMessage::StreamingRpcRequest(request) => {
+                if let Err(err) = self.inbound_streaming_rpcs.handle_inbound_request(
+                    &mut self.peer_notifs_tx,
+                    write_reqs_tx,
+                    request,
+                ) {
                     warn!(
                         NetworkSchema::new(&self.network_context)
                             .connection_metadata(&self.connection_metadata),
                         error = %err,
-                        "{} Error handling inbound rpc request: {}",
+                        "{} Error handling inbound streaming rpc request: {}",
                         self.network_context,
                         err
                     );
                 }
             },
-            NetworkMessage::RpcResponse(response) => {
-                self.outbound_rpcs.handle_inbound_response(response)
+            NetworkMessage::RpcResponseChunk(chunk) => {
+                self.outbound_streaming_rpcs.handle_inbound_chunk(chunk)
+            },
+            NetworkMessage::RpcChunkAck(ack) => self.inbound_streaming_rpcs.handle_inbound_ack(ack),
+            NetworkMessage::RpcRequestWithDeadline(request) => {
+                let (request, time_budget) = request.into_parts();
+                self.handle_inbound_rpc_request(
+                    request,
+                    Some(time_budget),
+                    new_correlation_id(),
+                    write_reqs_tx,
+                )
+                .await
+            },
+            NetworkMessage::CorrelatedRpcRequest(request) => {
+                let (request, correlation_id) = request.into_parts();
+                let (request, time_budget) = request.into_parts();
+                self.handle_inbound_rpc_request(
+                    request,
+                    Some(time_budget),
+                    correlation_id,
+                    write_reqs_tx,
+                )
+                .await
             },
         };
         Ok(())
     }
 
+    /// Handle the remote peer's announcement that it's draining the connection:
+    /// the PeerManager stops routing new requests to the peer, and the
+    /// connection is closed once the pending ones completed.
+    async fn handle_goodbye(&mut self, goodbye: Goodbye) {
+        info!(
+            NetworkSchema::new(&self.network_context)
+                .connection_metadata(&self.connection_metadata),
+            "{} Peer {} is draining the connection: {}",
+            self.network_context,
+            self.remote_peer_id().short_str(),
+            goodbye.reason
+        );
+        if !self.start_draining(Duration::from_millis(goodbye.drain_timeout_ms)) {
+            return;
+        }
+        if let Err(err) = self
+            .connection_notifs_tx
+            .send(TransportNotification::Draining(
+                self.connection_metadata.clone(),
+            ))
+            .await
+        {
+            warn!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata(&self.connection_metadata),
+                error = %err,
+                "{} Failed to notify upstream about draining of peer: {}; error: {}",
+                self.network_context,
+                self.remote_peer_id().short_str(),
+                err
+            );
+        }
+    }
+
+    /// Starts draining the connection, unless it's already drained or shutting
+    /// down. Returns false if it was.
+    fn start_draining(&mut self, drain_timeout: Duration) -> bool {
+        if !matches!(self.state, State::Connected) {
+            return false;
+        }
+        self.state = State::Draining;
+        self.drain_timeout = self.time_service.sleep(drain_timeout).boxed().fuse();
+        true
+    }
+
+    /// Returns true once a drained connection can be closed: the PeerManager
+    /// no longer routes requests to the peer, and no request (in either
+    /// direction) is pending.
+    fn is_drained(&self) -> bool {
+        self.peer_reqs_rx.is_terminated()
+            && self.inbound_rpcs.is_empty()
+            && self.outbound_rpcs.is_empty()
+            && self.inbound_streaming_rpcs.is_empty()
+            && self.outbound_streaming_rpcs.is_empty()
+            && self.inbound_acks.is_empty()
+            && self.outbound_acks.is_empty()
+    }
+
+    /// Handle an inbound rpc request from the remote peer. Requests shed because
+    /// their protocol is overloaded are rejected with a busy error, if the peer
+    /// understands it.
+    async fn handle_inbound_rpc_request(
+        &mut self,
+        request: RpcRequest,
+        time_budget: Option<Duration>,
+        correlation_id: CorrelationId,
+        write_reqs_tx: &mut aptos_channels::Sender<NetworkMessage>,
+    ) {
+        let request_id = request.request_id;
+        let protocol_id = request.protocol_id;
+        if let Err(err) = self.inbound_rpcs.handle_inbound_request(
+            &mut self.peer_notifs_tx,
+            request,
+            time_budget,
+            correlation_id,
+        ) {
+            // Shed requests are expected under load, and only counted
+            if let RpcError::Busy = err {
+                if self
+                    .connection_metadata
+                    .features
+                    .supports(Feature::LoadShedding)
+                {
+                    let busy = NetworkMessage::Error(ErrorCode::busy(request_id, protocol_id));
+                    // The connection may be shutting down
+                    let _ = write_reqs_tx.send(busy).await;
+                }
+                return;
+            }
+            warn!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata(&self.connection_metadata),
+                error = %err,
+                correlation_id = correlation_id,
+                "{} Error handling inbound rpc request: {}",
+                self.network_context,
+                err
+            );
+        }
+    }
+
+    /// Sends the response of a completed inbound rpc request. Requests that
+    /// expired before they were handled are rejected with an expired error, if
+    /// the peer understands it.
+    async fn handle_completed_inbound_rpc(
+        &mut self,
+        request_id: RequestId,
+        protocol_id: ProtocolId,
+        maybe_response: Result<RpcResponse, RpcError>,
+        write_reqs_tx: &mut aptos_channels::Sender<NetworkMessage>,
+    ) {
+        match self
+            .inbound_rpcs
+            .send_outbound_response(write_reqs_tx, maybe_response)
+            .await
+        {
+            Ok(()) => (),
+            // Expired requests are expected under load, and only counted
+            Err(RpcError::Expired) => {
+                if self
+                    .connection_metadata
+                    .features
+                    .supports(Feature::RpcExpiry)
+                {
+                    let expired =
+                        NetworkMessage::Error(ErrorCode::expired(request_id, protocol_id));
+                    // The connection may be shutting down
+                    let _ = write_reqs_tx.send(expired).await;
+                }
+            },
+            Err(err) => {
+                warn!(
+                    NetworkSchema::new(&self.network_context)
+                        .connection_metadata(&self.connection_metadata),
+                    error = %err,
+                    "{} Error in handling inbound rpc request, error: {}",
+                    self.network_context,
+                    err,
+                );
+            },
+        }
+    }
+
     async fn handle_inbound_stream_message(
         &mut self,
         message: StreamMessage,
+        write_reqs_tx: &mut aptos_channels::Sender<NetworkMessage>,
     ) -> Result<(), PeerManagerError> {
         match message {
             StreamMessage::Header(header) => {
@@ -472,13 +1134,47 @@ where
             },
             StreamMessage::Fragment(fragment) => {
                 if let Some(message) = self.inbound_stream.append_fragment(fragment)? {
-                    self.handle_inbound_network_message(message).await?;
+                    self.handle_inbound_network_message(message, write_reqs_tx)
+                        .await?;
+                }
+            },
+            StreamMessage::SequencedHeader(header) => {
+                self.ensure_stream_feature(Feature::Fragmentation)?;
+                self.inbound_stream.new_sequenced_stream(header)?;
+            },
+            StreamMessage::SequencedFragment(fragment) => {
+                if let Some(message) = self.inbound_stream.append_sequenced_fragment(fragment)? {
+                    self.handle_inbound_network_message(message, write_reqs_tx)
+                        .await?;
+                }
+            },
+            StreamMessage::SubstreamHeader(header) => {
+                self.ensure_stream_feature(Feature::Substreams)?;
+                self.inbound_stream.new_substream(header)?;
+            },
+            StreamMessage::SubstreamFragment(fragment) => {
+                if let Some(message) = self.inbound_stream.append_substream_fragment(fragment)? {
+                    self.handle_inbound_network_message(message, write_reqs_tx)
+                        .await?;
                 }
             },
         }
         Ok(())
     }
 
+    /// Fails unless the peer negotiated the feature of its stream, so that peers can't reassemble
+    /// larger messages than they agreed to
+    fn ensure_stream_feature(&self, feature: Feature) -> Result<(), PeerManagerError> {
+        if self.connection_metadata.features.supports(feature) {
+            Ok(())
+        } else {
+            Err(PeerManagerError::Error(anyhow::anyhow!(
+                "Stream of peer that didn't negotiate {:?}",
+                feature
+            )))
+        }
+    }
+
     async fn handle_inbound_message(
         &mut self,
         message: Result<MultiplexMessage, ReadError>,
@@ -517,37 +1213,126 @@ where
 
         match message {
             MultiplexMessage::Message(message) => {
-                self.handle_inbound_network_message(message).await
+                self.handle_inbound_network_message(message, write_reqs_tx)
+                    .await
+            },
+            MultiplexMessage::Stream(message) => {
+                self.handle_inbound_stream_message(message, write_reqs_tx)
+                    .await
             },
-            MultiplexMessage::Stream(message) => self.handle_inbound_stream_message(message).await,
         }
     }
 
+    /// Returns true iff the request is of an isolated protocol
+    fn is_isolated(&self, request: &PeerRequest) -> bool {
+        request.protocol_id().map_or(false, |protocol_id| {
+            self.isolated_protocols.contains(&protocol_id)
+        })
+    }
+
+    /// Returns true iff an inbound direct-send message of the protocol without a
+    /// nonce can be delivered. Replay-protected protocols require nonces from
+    /// the peers that can send them.
+    fn is_unsequenced_message_allowed(&self, protocol_id: ProtocolId) -> bool {
+        if !self.replay_protection.is_protected(protocol_id)
+            || !self
+                .connection_metadata
+                .features
+                .supports(Feature::ReplayProtection)
+        {
+            return true;
+        }
+        counters::replayed_messages(
+            &self.network_context,
+            protocol_id,
+            counters::UNSEQUENCED_LABEL,
+        )
+        .inc();
+        sample!(
+            SampleRate::Duration(Duration::from_secs(10)),
+            warn!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata(&self.connection_metadata),
+                "{} Dropping message without a nonce for replay-protected protocol {} from peer {}",
+                self.network_context,
+                protocol_id,
+                self.remote_peer_id().short_str(),
+            )
+        );
+        false
+    }
+
     /// Handle an inbound DirectSendMsg from the remote peer. There's not much to
     /// do here other than bump some counters and forward the message up to the
-    /// PeerManager.
-    fn handle_inbound_direct_send(&mut self, message: DirectSendMsg) {
+    /// PeerManager. If the message is acked, its delivery (or loss) is reported
+    /// over `delivered_tx`.
+    fn handle_inbound_direct_send(
+        &mut self,
+        message: DirectSendMsg,
+        correlation_id: CorrelationId,
+        delivered_tx: Option<oneshot::Sender<bool>>,
+    ) {
         let peer_id = self.remote_peer_id();
         let protocol_id = message.protocol_id;
         let data = message.raw_msg;
+        let data = if self
+            .connection_metadata
+            .features
+            .is_compression_enabled(protocol_id)
+        {
+            match compression::decode_payload(&data) {
+                Ok(data) => Bytes::from(data),
+                Err(err) => {
+                    warn!(
+                        NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                        error = %err,
+                        correlation_id = correlation_id,
+                        "{} Failed to decode inbound DirectSend message for protocol {}. Error: {}",
+                        self.network_context,
+                        protocol_id,
+                        err
+                    );
+                    return;
+                },
+            }
+        } else {
+            data
+        };
 
         trace!(
             NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
             protocol_id = protocol_id,
-            "{} DirectSend: Received inbound message from peer {} for protocol {:?}",
+            correlation_id = correlation_id,
+            "{} DirectSend: Received inbound message from peer {} for protocol {:?} with correlation_id {}",
             self.network_context,
             peer_id.short_str(),
-            protocol_id
+            protocol_id,
+            correlation_id
         );
         let data_len = data.len() as u64;
         counters::direct_send_messages(&self.network_context, RECEIVED_LABEL).inc();
         counters::direct_send_bytes(&self.network_context, RECEIVED_LABEL).inc_by(data_len);
         network_application_inbound_traffic(self.network_context, message.protocol_id, data_len);
+        counters::application_traffic(
+            self.network_context.network_id(),
+            protocol_id,
+            INBOUND_LABEL,
+            data_len,
+        );
 
-        let notif = PeerNotification::RecvMessage(Message {
+        let message = Message {
             protocol_id,
-            mdata: Bytes::from(data),
-        });
+            mdata: data,
+            priority: message.priority.into(),
+            correlation_id,
+        };
+        let notif = match delivered_tx {
+            Some(delivered_tx) => PeerNotification::RecvAckedMessage(InboundAckedMessage {
+                message,
+                delivered_tx,
+            }),
+            None => PeerNotification::RecvMessage(message),
+        };
 
         if let Err(err) = self.peer_notifs_tx.push(protocol_id, notif) {
             warn!(
@@ -560,6 +1345,46 @@ where
         }
     }
 
+    /// The priority an outbound message is queued and sent with. Peers that didn't
+    /// negotiate [`Feature::Qos`] don't know the priority classes, so all of their
+    /// messages are sent with the default priority, in order.
+    fn wire_priority(&self, priority: MessagePriority) -> Priority {
+        if self.connection_metadata.features.supports(Feature::Qos) {
+            priority.into()
+        } else {
+            MessagePriority::default().into()
+        }
+    }
+
+    /// Compresses the payload of an outbound direct-send message, if compression
+    /// was negotiated for the protocol. Returns `None` if compression failed.
+    fn encode_direct_send_payload(&self, protocol_id: ProtocolId, mdata: Bytes) -> Option<Bytes> {
+        if !self
+            .connection_metadata
+            .features
+            .is_compression_enabled(protocol_id)
+        {
+            // The payload is shared with the application (and with the
+            // messages to other peers), rather than copied
+            return Some(mdata);
+        }
+        match compression::encode_payload(mdata.as_ref()) {
+            Ok(raw_msg) => Some(Bytes::from(raw_msg)),
+            Err(err) => {
+                warn!(
+                    NetworkSchema::new(&self.network_context)
+                        .connection_metadata(&self.connection_metadata),
+                    error = %err,
+                    "Failed to encode direct send message for protocol {} to peer: {}. Error: {}",
+                    protocol_id,
+                    self.remote_peer_id().short_str(),
+                    err,
+                );
+                None
+            },
+        }
+    }
+
     async fn handle_outbound_request(
         &mut self,
         request: PeerRequest,
@@ -576,28 +1401,55 @@ where
             PeerRequest::SendDirectSend(message) => {
                 let message_len = message.mdata.len();
                 let protocol_id = message.protocol_id;
+                let correlation_id = message.correlation_id;
                 network_application_outbound_traffic(
                     self.network_context,
                     protocol_id,
                     message_len as u64,
                 );
-                let message = NetworkMessage::DirectSendMsg(DirectSendMsg {
+                let raw_msg = match self.encode_direct_send_payload(protocol_id, message.mdata) {
+                    Some(raw_msg) => raw_msg,
+                    None => return,
+                };
+                let message = DirectSendMsg {
                     protocol_id,
-                    priority: Priority::default(),
-                    raw_msg: Vec::from(message.mdata.as_ref()),
-                });
+                    priority: self.wire_priority(message.priority),
+                    raw_msg,
+                };
+                let features = &self.connection_metadata.features;
+                let message = if features.supports(Feature::ReplayProtection) {
+                    NetworkMessage::SequencedDirectSendMsg(SequencedDirectSendMsg::new(
+                        message,
+                        self.replay_protection.next_nonce(),
+                        correlation_id,
+                    ))
+                } else if features.supports(Feature::CorrelationIds) {
+                    NetworkMessage::CorrelatedDirectSendMsg(CorrelatedDirectSendMsg::new(
+                        message,
+                        correlation_id,
+                    ))
+                } else {
+                    NetworkMessage::DirectSendMsg(message)
+                };
 
                 match write_reqs_tx.send(message).await {
                     Ok(_) => {
                         counters::direct_send_messages(&self.network_context, SENT_LABEL).inc();
                         counters::direct_send_bytes(&self.network_context, SENT_LABEL)
                             .inc_by(message_len as u64);
+                        counters::application_traffic(
+                            self.network_context.network_id(),
+                            protocol_id,
+                            OUTBOUND_LABEL,
+                            message_len as u64,
+                        );
                     },
                     Err(e) => {
                         warn!(
                             NetworkSchema::new(&self.network_context)
                                 .connection_metadata(&self.connection_metadata),
                             error = ?e,
+                            correlation_id = correlation_id,
                             "Failed to send direct send message for protocol {} to peer: {}. Error: {:?}",
                             protocol_id,
                             self.remote_peer_id().short_str(),
@@ -606,6 +1458,78 @@ where
                     },
                 }
             },
+            // Acked messages are only sent to peers that can acknowledge them,
+            // and tracked until they're acknowledged (or time out).
+            PeerRequest::SendAckedDirectSend(AckedMessage {
+                message,
+                timeout,
+                ack_tx,
+            }) => {
+                let message_len = message.mdata.len();
+                let protocol_id = message.protocol_id;
+                if !self
+                    .connection_metadata
+                    .features
+                    .supports(Feature::DirectSendAcks)
+                {
+                    let _ = ack_tx.send(Err(RpcError::Error(anyhow::anyhow!(
+                        "Peer {} doesn't support direct send acks",
+                        self.remote_peer_id().short_str()
+                    ))));
+                    return;
+                }
+                network_application_outbound_traffic(
+                    self.network_context,
+                    protocol_id,
+                    message_len as u64,
+                );
+                let raw_msg = match self.encode_direct_send_payload(protocol_id, message.mdata) {
+                    Some(raw_msg) => raw_msg,
+                    None => {
+                        let _ = ack_tx.send(Err(RpcError::Error(anyhow::anyhow!(
+                            "Failed to encode direct send message"
+                        ))));
+                        return;
+                    },
+                };
+                let request_id = self.outbound_acks.next_request_id();
+                let message = AckedDirectSendMsg {
+                    protocol_id,
+                    request_id,
+                    priority: self.wire_priority(message.priority),
+                    raw_msg,
+                };
+
+                match write_reqs_tx
+                    .send(NetworkMessage::AckedDirectSendMsg(message))
+                    .await
+                {
+                    Ok(_) => {
+                        self.outbound_acks.insert(request_id, timeout, ack_tx);
+                        counters::direct_send_messages(&self.network_context, SENT_LABEL).inc();
+                        counters::direct_send_bytes(&self.network_context, SENT_LABEL)
+                            .inc_by(message_len as u64);
+                        counters::application_traffic(
+                            self.network_context.network_id(),
+                            protocol_id,
+                            OUTBOUND_LABEL,
+                            message_len as u64,
+                        );
+                    },
+                    Err(e) => {
+                        warn!(
+                            NetworkSchema::new(&self.network_context)
+                                .connection_metadata(&self.connection_metadata),
+                            error = ?e,
+                            "Failed to send acked direct send message for protocol {} to peer: {}. Error: {:?}",
+                            protocol_id,
+                            self.remote_peer_id().short_str(),
+                            e,
+                        );
+                        let _ = ack_tx.send(Err(e.into()));
+                    },
+                }
+            },
             PeerRequest::SendRpc(request) => {
                 let protocol_id = request.protocol_id;
                 network_application_outbound_traffic(
@@ -629,6 +1553,68 @@ where
                     );
                 }
             },
+            // Streaming rpcs are only sent to peers that can respond with chunks
+            PeerRequest::SendStreamingRpc(mut request) => {
+                let protocol_id = request.protocol_id;
+                if !self
+                    .connection_metadata
+                    .features
+                    .supports(Feature::StreamingRpc)
+                {
+                    let _ = request.res_tx.try_send(Err(RpcError::Error(anyhow::anyhow!(
+                        "Peer {} doesn't support streaming rpcs",
+                        self.remote_peer_id().short_str()
+                    ))));
+                    return;
+                }
+                network_application_outbound_traffic(
+                    self.network_context,
+                    protocol_id,
+                    request.data.len() as u64,
+                );
+                if let Err(e) = self
+                    .outbound_streaming_rpcs
+                    .handle_outbound_request(request, write_reqs_tx)
+                    .await
+                {
+                    warn!(
+                        NetworkSchema::new(&self.network_context)
+                            .connection_metadata(&self.connection_metadata),
+                        error = %e,
+                        "Failed to send outbound streaming rpc request for protocol {} to peer: {}. Error: {}",
+                        protocol_id,
+                        self.remote_peer_id().short_str(),
+                        e,
+                    );
+                }
+            },
+            PeerRequest::Drain(reason, drain_timeout) => {
+                if !self.start_draining(drain_timeout) {
+                    return;
+                }
+                // Peers that don't understand goodbyes only see the connection close
+                if self
+                    .connection_metadata
+                    .features
+                    .supports(Feature::GracefulDrain)
+                {
+                    let goodbye = NetworkMessage::Goodbye(Goodbye {
+                        reason,
+                        drain_timeout_ms: drain_timeout.as_millis() as u64,
+                    });
+                    if let Err(err) = write_reqs_tx.send(goodbye).await {
+                        warn!(
+                            NetworkSchema::new(&self.network_context)
+                                .connection_metadata(&self.connection_metadata),
+                            error = %err,
+                            "{} Failed to send goodbye to peer: {}. Error: {}",
+                            self.network_context,
+                            self.remote_peer_id().short_str(),
+                            err,
+                        );
+                    }
+                }
+            },
         }
     }
 
@@ -638,8 +1624,15 @@ where
         self.state = State::ShuttingDown(reason);
     }
 
-    async fn do_shutdown(mut self, writer_close_tx: oneshot::Sender<()>, reason: DisconnectReason) {
+    async fn do_shutdown(
+        mut self,
+        writer_close_tx: oneshot::Sender<bool>,
+        reason: DisconnectReason,
+    ) {
         let remote_peer_id = self.remote_peer_id();
+        // Applications waiting for the outbound queues to drain are woken up
+        self.outbound_queue.close();
+        self.isolated_outbound_queue.close();
 
         // Send a PeerDisconnected event to PeerManager.
         if let Err(e) = self
@@ -662,9 +1655,10 @@ where
         }
 
         // Send a close instruction to the writer task. On receipt of this
-        // instruction, the writer task drops all pending outbound messages and
-        // closes the connection.
-        if let Err(e) = writer_close_tx.send(()) {
+        // instruction, the writer task drops all pending outbound messages
+        // (unless the connection was drained, in which case it flushes them)
+        // and closes the connection.
+        if let Err(e) = writer_close_tx.send(reason == DisconnectReason::Drained) {
             info!(
                 NetworkSchema::new(&self.network_context)
                     .connection_metadata(&self.connection_metadata),
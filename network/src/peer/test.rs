@@ -4,18 +4,34 @@
 use crate::{
     constants::{
         INBOUND_RPC_TIMEOUT_MS, MAX_CONCURRENT_INBOUND_RPCS, MAX_CONCURRENT_OUTBOUND_RPCS,
-        MAX_FRAME_SIZE, MAX_MESSAGE_SIZE, NETWORK_CHANNEL_SIZE,
+        MAX_FRAME_SIZE, MAX_MESSAGE_SIZE, MAX_REASSEMBLY_SIZE, NETWORK_CHANNEL_SIZE,
     },
-    peer::{DisconnectReason, Peer, PeerNotification, PeerRequest},
-    peer_manager::TransportNotification,
+    peer::{
+        batching::{MessageBatching, MAX_BATCHED_MESSAGE_SIZE},
+        BandwidthBuckets, DisconnectReason, Peer, PeerNotification, PeerRequest,
+    },
+    peer_manager::{OutboundQueue, TransportNotification},
     protocols::{
-        direct_send::Message,
-        rpc::{error::RpcError, InboundRpcRequest, OutboundRpcRequest},
+        direct_send::{acks::AckedMessage, replay::ReplayProtection, Message},
+        rpc::{
+            error::RpcError,
+            streaming::{InboundStreamingRpcRequest, OutboundStreamingRpcRequest},
+            InboundRpcConcurrencyLimits, InboundRpcRequest, InboundRpcs, OutboundRpcRequest,
+        },
+        stream::{
+            SequencedStreamFragment, SequencedStreamHeader, StreamMessage, SubstreamFragment,
+            SubstreamHeader,
+        },
         wire::{
-            handshake::v1::{MessagingProtocolVersion, ProtocolIdSet},
+            handshake::{
+                v1::{MessagingProtocolVersion, ProtocolIdSet},
+                v2::{Feature, FeatureSet},
+            },
             messaging::v1::{
-                DirectSendMsg, MultiplexMessage, MultiplexMessageSink, MultiplexMessageStream,
-                NetworkMessage, RpcRequest, RpcResponse,
+                CorrelatedDirectSendMsg, DirectSendMsg, MessagePriority, MultiplexMessage,
+                MultiplexMessageSink, MultiplexMessageStream, NetworkMessage, RpcChunkAck,
+                RpcRequest, RpcRequestWithDeadline, RpcResponse, RpcResponseChunk,
+                SequencedDirectSendMsg, StreamingRpcRequest,
             },
         },
     },
@@ -30,13 +46,13 @@ use aptos_time_service::{MockTimeService, TimeService};
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use bytes::Bytes;
 use futures::{
-    channel::oneshot,
+    channel::{mpsc, oneshot},
     future::{self, FutureExt},
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     stream::{StreamExt, TryStreamExt},
     SinkExt,
 };
-use std::{collections::HashSet, str::FromStr, time::Duration};
+use std::{collections::HashSet, str::FromStr, sync::Arc, time::Duration};
 use tokio::runtime::{Handle, Runtime};
 use tokio_util::compat::{
     FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt,
@@ -54,10 +70,25 @@ fn build_test_peer(
     MemorySocket,
     aptos_channels::Receiver<TransportNotification<MemorySocket>>,
     aptos_channel::Receiver<ProtocolId, PeerNotification>,
+) {
+    build_test_peer_with_features(executor, time_service, origin, FeatureSet::empty())
+}
+
+fn build_test_peer_with_features(
+    executor: Handle,
+    time_service: TimeService,
+    origin: ConnectionOrigin,
+    features: FeatureSet,
+) -> (
+    Peer<MemorySocket>,
+    PeerHandle,
+    MemorySocket,
+    aptos_channels::Receiver<TransportNotification<MemorySocket>>,
+    aptos_channel::Receiver<ProtocolId, PeerNotification>,
 ) {
     let (a, b) = MemorySocket::new_pair();
     let peer_id = PeerId::random();
-    let connection = Connection {
+    let mut connection = Connection {
         metadata: ConnectionMetadata::new(
             peer_id,
             ConnectionId::default(),
@@ -66,9 +97,11 @@ fn build_test_peer(
             MessagingProtocolVersion::V1,
             ProtocolIdSet::empty(),
             PeerRole::Unknown,
+            None,
         ),
         socket: a,
     };
+    connection.metadata.features.features = features;
 
     let (connection_notifs_tx, connection_notifs_rx) = aptos_channels::new_test(1);
     let (peer_reqs_tx, peer_reqs_rx) =
@@ -87,10 +120,19 @@ fn build_test_peer(
         Duration::from_millis(INBOUND_RPC_TIMEOUT_MS),
         MAX_CONCURRENT_INBOUND_RPCS,
         MAX_CONCURRENT_OUTBOUND_RPCS,
+        InboundRpcConcurrencyLimits::default(),
         MAX_FRAME_SIZE,
         MAX_MESSAGE_SIZE,
+        MAX_REASSEMBLY_SIZE,
         None,
         None,
+        BandwidthBuckets::default(),
+        BandwidthBuckets::default(),
+        Arc::new(OutboundQueue::new(NETWORK_CHANNEL_SIZE)),
+        ReplayProtection::default(),
+        MessageBatching::default(),
+        Arc::new(HashSet::new()),
+        Arc::new(OutboundQueue::new(NETWORK_CHANNEL_SIZE)),
     );
     let peer_handle = PeerHandle(peer_reqs_tx);
 
@@ -177,6 +219,22 @@ impl PeerHandle {
             .unwrap()
     }
 
+    fn send_acked_direct_send(&self, message: Message) -> oneshot::Receiver<Result<(), RpcError>> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let message = AckedMessage {
+            message,
+            timeout: Duration::from_secs(10),
+            ack_tx,
+        };
+        self.0
+            .push(
+                message.message.protocol_id,
+                PeerRequest::SendAckedDirectSend(message),
+            )
+            .unwrap();
+        ack_rx
+    }
+
     async fn send_rpc_request(
         &mut self,
         protocol_id: ProtocolId,
@@ -189,11 +247,81 @@ impl PeerHandle {
             data,
             res_tx,
             timeout,
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         };
         self.0.push(protocol_id, PeerRequest::SendRpc(request))?;
         let response_data = res_rx.await??;
         Ok(response_data)
     }
+
+    fn send_streaming_rpc(
+        &self,
+        data: Bytes,
+        window: u32,
+    ) -> mpsc::Receiver<Result<Bytes, RpcError>> {
+        let (res_tx, res_rx) = mpsc::channel(1);
+        let request = OutboundStreamingRpcRequest {
+            protocol_id: PROTOCOL,
+            data,
+            res_tx,
+            chunk_timeout: Duration::from_secs(10),
+            window,
+        };
+        self.0
+            .push(PROTOCOL, PeerRequest::SendStreamingRpc(request))
+            .unwrap();
+        res_rx
+    }
+
+    fn drain(&self, reason: &str, drain_timeout: Duration) {
+        self.0
+            .push(
+                ProtocolId::DiscoveryDirectSend,
+                PeerRequest::Drain(reason.to_string(), drain_timeout),
+            )
+            .unwrap()
+    }
+}
+
+// Outbound messages should only carry their priority if the peer negotiated QoS.
+#[test]
+fn peer_send_message_priority() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    for (features, priority) in [
+        (FeatureSet::empty(), 0),
+        (FeatureSet::from_iter([Feature::Qos]), 2),
+    ] {
+        let (peer, mut peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+            build_test_peer_with_features(
+                rt.handle().clone(),
+                TimeService::mock(),
+                ConnectionOrigin::Inbound,
+                features,
+            );
+        let (_client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+        let send_msg = Message {
+            protocol_id: PROTOCOL,
+            mdata: Bytes::from("hello world"),
+            priority: MessagePriority::High,
+            correlation_id: 0,
+        };
+        let recv_msg = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
+            protocol_id: PROTOCOL,
+            priority,
+            raw_msg: Bytes::from_static(b"hello world"),
+        }));
+
+        let client = async move {
+            peer_handle.send_direct_send(send_msg);
+            let msg = client_stream.next().await.unwrap().unwrap();
+            assert_eq!(msg, recv_msg);
+            drop(peer_handle);
+        };
+        rt.block_on(future::join(peer.start(), client));
+    }
 }
 
 // Sending an outbound DirectSend should write it to the wire.
@@ -212,11 +340,13 @@ fn peer_send_message() {
     let send_msg = Message {
         protocol_id: PROTOCOL,
         mdata: Bytes::from("hello world"),
+        priority: MessagePriority::Normal,
+        correlation_id: 0,
     };
     let recv_msg = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
         protocol_id: PROTOCOL,
         priority: 0,
-        raw_msg: Vec::from("hello world"),
+        raw_msg: Bytes::from_static(b"hello world"),
     }));
 
     let client = async {
@@ -254,11 +384,13 @@ fn peer_recv_message() {
     let send_msg = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
         protocol_id: PROTOCOL,
         priority: 0,
-        raw_msg: Vec::from("hello world"),
+        raw_msg: Bytes::from_static(b"hello world"),
     }));
     let recv_msg = PeerNotification::RecvMessage(Message {
         protocol_id: PROTOCOL,
         mdata: Bytes::from("hello world"),
+        priority: MessagePriority::Normal,
+        correlation_id: 0,
     });
 
     let client = async move {
@@ -281,6 +413,376 @@ fn peer_recv_message() {
     rt.block_on(future::join3(peer.start(), server, client));
 }
 
+// Sequenced streams and substreams are only reassembled for the peers that
+// negotiated them.
+#[test]
+fn peer_rejects_unnegotiated_streams() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (peer, _peer_handle, connection, _connection_notifs_rx, mut peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+
+    let direct_send_msg = |raw_msg: &'static [u8]| {
+        NetworkMessage::DirectSendMsg(DirectSendMsg {
+            protocol_id: PROTOCOL,
+            priority: 0,
+            raw_msg: Bytes::from_static(raw_msg),
+        })
+    };
+    let sequenced_header = SequencedStreamHeader {
+        request_id: 1,
+        num_fragments: 1,
+        payload_len: 10,
+        message: direct_send_msg(b"hello"),
+    };
+    let sequenced_fragment = SequencedStreamFragment {
+        request_id: 1,
+        fragment_id: 1,
+        raw_data: Bytes::from_static(b"world"),
+    };
+    let stream_msgs = vec![
+        StreamMessage::SequencedHeader(sequenced_header.clone()),
+        StreamMessage::SequencedFragment(sequenced_fragment.clone()),
+        StreamMessage::SubstreamHeader(SubstreamHeader {
+            substream_id: 0,
+            header: sequenced_header,
+        }),
+        StreamMessage::SubstreamFragment(SubstreamFragment {
+            substream_id: 0,
+            fragment: sequenced_fragment,
+        }),
+    ];
+    let recv_msg = PeerNotification::RecvMessage(Message {
+        protocol_id: PROTOCOL,
+        mdata: Bytes::from("hello world"),
+        priority: MessagePriority::Normal,
+        correlation_id: 0,
+    });
+
+    let client = async move {
+        let mut connection = MultiplexMessageSink::new(connection, MAX_FRAME_SIZE, None);
+        for stream_msg in stream_msgs {
+            connection
+                .send(&MultiplexMessage::Stream(stream_msg))
+                .await
+                .unwrap();
+        }
+        connection
+            .send(&MultiplexMessage::Message(direct_send_msg(b"hello world")))
+            .await
+            .unwrap();
+        connection.close().await.unwrap();
+    };
+
+    let server = async move {
+        // Only the plain message is delivered
+        assert_eq!(peer_notifs_rx.next().await.unwrap(), recv_msg);
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// Peers that negotiated correlation ids should carry them on the wire, in both
+// directions.
+#[test]
+fn peer_correlation_ids() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (mut peer, mut peer_handle, mut connection, _connection_notifs_rx, mut peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    peer.connection_metadata
+        .features
+        .features
+        .insert(Feature::CorrelationIds);
+    let (mut client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+    let client = async {
+        // The client sends a message with a correlation id
+        let send_msg = MultiplexMessage::Message(NetworkMessage::CorrelatedDirectSendMsg(
+            CorrelatedDirectSendMsg {
+                correlation_id: 42,
+                protocol_id: PROTOCOL,
+                priority: 0,
+                raw_msg: Bytes::from_static(b"hello world"),
+            },
+        ));
+        client_sink.send(&send_msg).await.unwrap();
+
+        // The server's reply carries the server's correlation id
+        let msg = client_stream.next().await.unwrap().unwrap();
+        match msg {
+            MultiplexMessage::Message(NetworkMessage::CorrelatedDirectSendMsg(msg)) => {
+                assert_eq!(msg.correlation_id, 43);
+                assert_eq!(msg.raw_msg, Vec::from("goodbye world"));
+            },
+            msg => panic!("Expected CorrelatedDirectSendMsg; unexpected: {:?}", msg),
+        }
+        client_sink.close().await.unwrap();
+    };
+
+    let server = async {
+        match peer_notifs_rx.next().await.unwrap() {
+            PeerNotification::RecvMessage(msg) => {
+                assert_eq!(msg.correlation_id, 42);
+                assert_eq!(msg.mdata, Bytes::from("hello world"));
+            },
+            notif => panic!("Unexpected PeerNotification: {:?}", notif),
+        }
+        peer_handle.send_direct_send(Message {
+            protocol_id: PROTOCOL,
+            mdata: Bytes::from("goodbye world"),
+            priority: MessagePriority::Normal,
+            correlation_id: 43,
+        });
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// Small messages of batched protocols should be written along with the next
+// non-batched message, in order.
+#[test]
+fn peer_batches_small_messages() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (mut peer, mut peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    peer.message_batching
+        .insert(PROTOCOL, Duration::from_secs(10));
+    let (mut client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+    let large_message = Bytes::from(vec![0; MAX_BATCHED_MESSAGE_SIZE + 1]);
+    let messages = [
+        Bytes::from("batched 1"),
+        Bytes::from("batched 2"),
+        large_message,
+    ];
+
+    let client = async {
+        for expected in messages.clone() {
+            match client_stream.next().await.unwrap().unwrap() {
+                MultiplexMessage::Message(NetworkMessage::DirectSendMsg(msg)) => {
+                    assert_eq!(msg.raw_msg, expected);
+                },
+                msg => panic!("Expected DirectSendMsg; unexpected: {:?}", msg),
+            }
+        }
+        client_sink.close().await.unwrap();
+    };
+
+    let server = async {
+        // The batch is held back (for up to 10s) until the large message
+        for mdata in messages.clone() {
+            peer_handle.send_direct_send(Message {
+                protocol_id: PROTOCOL,
+                mdata,
+                priority: MessagePriority::Normal,
+                correlation_id: 0,
+            });
+        }
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// The messages of isolated protocols should be written on their own lane, along
+// with (and in order among themselves) the messages of other protocols.
+#[test]
+fn peer_sends_isolated_messages() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (mut peer, mut peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    const ISOLATED_PROTOCOL: ProtocolId = ProtocolId::ConsensusDirectSendBcs;
+    peer.isolated_protocols = Arc::new([ISOLATED_PROTOCOL].into_iter().collect());
+    let (mut client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+    let isolated_messages = [Bytes::from("isolated 1"), Bytes::from("isolated 2")];
+    let bulk_message = Bytes::from("bulk");
+
+    let client = async {
+        let mut isolated = vec![];
+        let mut bulk = vec![];
+        for _ in 0..3 {
+            match client_stream.next().await.unwrap().unwrap() {
+                MultiplexMessage::Message(NetworkMessage::DirectSendMsg(msg))
+                    if msg.protocol_id == ISOLATED_PROTOCOL =>
+                {
+                    isolated.push(msg.raw_msg)
+                },
+                MultiplexMessage::Message(NetworkMessage::DirectSendMsg(msg)) => {
+                    bulk.push(msg.raw_msg)
+                },
+                msg => panic!("Expected DirectSendMsg; unexpected: {:?}", msg),
+            }
+        }
+        assert_eq!(isolated, isolated_messages.to_vec());
+        assert_eq!(bulk, vec![bulk_message.clone()]);
+        client_sink.close().await.unwrap();
+    };
+
+    let server = async {
+        peer_handle.send_direct_send(Message {
+            protocol_id: PROTOCOL,
+            mdata: bulk_message.clone(),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        });
+        for mdata in isolated_messages.clone() {
+            peer_handle.send_direct_send(Message {
+                protocol_id: ISOLATED_PROTOCOL,
+                mdata,
+                priority: MessagePriority::Normal,
+                correlation_id: 0,
+            });
+        }
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// Peers that negotiated replay protection should carry nonces on the wire, and
+// drop the replayed (or unsequenced) messages of protected protocols.
+#[test]
+fn peer_replay_protection() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (mut peer, mut peer_handle, mut connection, _connection_notifs_rx, mut peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    peer.connection_metadata
+        .features
+        .features
+        .insert(Feature::ReplayProtection);
+    peer.replay_protection
+        .insert(PROTOCOL, Duration::from_secs(10));
+    let (mut client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+    let client = async {
+        let sequenced_msg = |nonce, data: &'static str| {
+            MultiplexMessage::Message(NetworkMessage::SequencedDirectSendMsg(
+                SequencedDirectSendMsg {
+                    nonce,
+                    correlation_id: nonce,
+                    protocol_id: PROTOCOL,
+                    priority: 0,
+                    raw_msg: Bytes::from_static(data.as_bytes()),
+                },
+            ))
+        };
+        let unsequenced_msg =
+            MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
+                protocol_id: PROTOCOL,
+                priority: 0,
+                raw_msg: Bytes::from_static(b"unsequenced"),
+            }));
+
+        // The client replays a message, and sends one without a nonce
+        for msg in [
+            sequenced_msg(1, "hello"),
+            sequenced_msg(1, "replayed"),
+            unsequenced_msg,
+            sequenced_msg(2, "world"),
+        ] {
+            client_sink.send(&msg).await.unwrap();
+        }
+
+        // The server's reply carries the server's nonce
+        let msg = client_stream.next().await.unwrap().unwrap();
+        match msg {
+            MultiplexMessage::Message(NetworkMessage::SequencedDirectSendMsg(msg)) => {
+                assert!(msg.nonce > 0);
+                assert_eq!(msg.raw_msg, Vec::from("goodbye world"));
+            },
+            msg => panic!("Expected SequencedDirectSendMsg; unexpected: {:?}", msg),
+        }
+        client_sink.close().await.unwrap();
+    };
+
+    let server = async {
+        // Only the first message and the next sequenced one are delivered
+        for expected in ["hello", "world"] {
+            match peer_notifs_rx.next().await.unwrap() {
+                PeerNotification::RecvMessage(msg) => {
+                    assert_eq!(msg.mdata, Bytes::from(expected));
+                },
+                notif => panic!("Unexpected PeerNotification: {:?}", notif),
+            }
+        }
+        peer_handle.send_direct_send(Message {
+            protocol_id: PROTOCOL,
+            mdata: Bytes::from("goodbye world"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        });
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// Acked messages should be acknowledged once the receiving peer reports their
+// delivery, and fail if they're dropped.
+#[test]
+fn peers_send_acked_message() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (
+        (mut peer_a, peer_handle_a, _connection_notifs_rx_a, _peer_notifs_rx_a),
+        (mut peer_b, _peer_handle_b, _connection_notifs_rx_b, mut peer_notifs_rx_b),
+    ) = build_test_connected_peers(rt.handle().clone(), TimeService::mock());
+    for peer in [&mut peer_a, &mut peer_b] {
+        peer.connection_metadata
+            .features
+            .features
+            .insert(Feature::DirectSendAcks);
+    }
+
+    let test = async move {
+        let msg = Message {
+            protocol_id: PROTOCOL,
+            mdata: Bytes::from("hello world"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        };
+
+        // The message is acknowledged once it's delivered
+        let ack_rx = peer_handle_a.send_acked_direct_send(msg.clone());
+        match peer_notifs_rx_b.next().await.unwrap() {
+            PeerNotification::RecvAckedMessage(inbound) => {
+                assert_eq!(inbound.message, msg);
+                inbound.delivered_tx.send(true).unwrap();
+            },
+            notif => panic!("Unexpected PeerNotification: {:?}", notif),
+        }
+        assert!(ack_rx.await.unwrap().is_ok());
+
+        // Dropped messages fail
+        let ack_rx = peer_handle_a.send_acked_direct_send(msg.clone());
+        match peer_notifs_rx_b.next().await.unwrap() {
+            PeerNotification::RecvAckedMessage(inbound) => drop(inbound),
+            notif => panic!("Unexpected PeerNotification: {:?}", notif),
+        }
+        assert!(ack_rx.await.unwrap().is_err());
+        drop(peer_handle_a);
+    };
+
+    rt.block_on(future::join3(peer_a.start(), peer_b.start(), test));
+}
+
 // Two connected Peer actors should be able to send/recv a DirectSend from each
 // other and then shutdown gracefully.
 #[test]
@@ -299,10 +801,14 @@ fn peers_send_message_concurrent() {
         let msg_a = Message {
             protocol_id: PROTOCOL,
             mdata: Bytes::from("hello world"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         };
         let msg_b = Message {
             protocol_id: PROTOCOL,
             mdata: Bytes::from("namaste"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         };
 
         // Peer A -> msg_a -> Peer B
@@ -353,17 +859,19 @@ fn peer_recv_rpc() {
         request_id: 123,
         protocol_id: PROTOCOL,
         priority: 0,
-        raw_request: Vec::from("hello world"),
+        raw_request: Bytes::from_static(b"hello world"),
     }));
     let recv_msg = PeerNotification::RecvRpc(InboundRpcRequest {
         protocol_id: PROTOCOL,
         data: Bytes::from("hello world"),
         res_tx: oneshot::channel().0,
+        deadline: None,
+        correlation_id: 0,
     });
     let resp_msg = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
         request_id: 123,
         priority: 0,
-        raw_response: Vec::from("goodbye world"),
+        raw_response: Bytes::from_static(b"goodbye world"),
     }));
 
     let client = async move {
@@ -396,6 +904,294 @@ fn peer_recv_rpc() {
     rt.block_on(future::join3(peer.start(), server, client));
 }
 
+#[test]
+fn peer_recv_rpc_with_deadline() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let time_service = TimeService::mock();
+    let (peer, _peer_handle, mut connection, _connection_notifs_rx, mut peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            time_service.clone(),
+            ConnectionOrigin::Inbound,
+        );
+    let (mut client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+    // The first request has already expired, so it should never reach the
+    // upper layer.
+    let expired_msg = MultiplexMessage::Message(NetworkMessage::RpcRequestWithDeadline(
+        RpcRequestWithDeadline {
+            request_id: 122,
+            protocol_id: PROTOCOL,
+            priority: 0,
+            timeout_ms: 0,
+            raw_request: Bytes::from_static(b"too late"),
+        },
+    ));
+    let send_msg = MultiplexMessage::Message(NetworkMessage::RpcRequestWithDeadline(
+        RpcRequestWithDeadline {
+            request_id: 123,
+            protocol_id: PROTOCOL,
+            priority: 0,
+            timeout_ms: 1000,
+            raw_request: Bytes::from_static(b"hello world"),
+        },
+    ));
+    let resp_msg = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
+        request_id: 123,
+        priority: 0,
+        raw_response: Bytes::from_static(b"goodbye world"),
+    }));
+
+    let client = async move {
+        client_sink.send(&expired_msg).await.unwrap();
+        client_sink.send(&send_msg).await.unwrap();
+        let received = client_stream.next().await.unwrap().unwrap();
+        assert_eq!(received, resp_msg);
+        client_sink.close().await.unwrap();
+    };
+    let server = async move {
+        let received = peer_notifs_rx.next().await.unwrap();
+        match received {
+            PeerNotification::RecvRpc(req) => {
+                assert_eq!(req.data, Bytes::from("hello world"));
+                assert_eq!(
+                    req.deadline,
+                    Some(time_service.now() + Duration::from_millis(1000))
+                );
+                req.res_tx.send(Ok(Bytes::from("goodbye world"))).unwrap()
+            },
+            _ => panic!("Unexpected PeerNotification: {:?}", received),
+        }
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+#[test]
+fn peer_recv_streaming_rpc() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (peer, _peer_handle, mut connection, _connection_notifs_rx, mut peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    let (mut client_sink, mut client_stream) = build_network_sink_stream(&mut connection);
+
+    let send_msg =
+        MultiplexMessage::Message(NetworkMessage::StreamingRpcRequest(StreamingRpcRequest {
+            request_id: 123,
+            protocol_id: PROTOCOL,
+            priority: 0,
+            window: 2,
+            raw_request: Bytes::from_static(b"hello world"),
+        }));
+    let recv_msg = PeerNotification::RecvStreamingRpc(InboundStreamingRpcRequest {
+        protocol_id: PROTOCOL,
+        data: Bytes::from("hello world"),
+        res_tx: futures::channel::mpsc::channel(1).0,
+    });
+    let chunk_msg = |chunk_id: u32, end_of_stream: bool, raw_chunk: &str| {
+        MultiplexMessage::Message(NetworkMessage::RpcResponseChunk(RpcResponseChunk {
+            request_id: 123,
+            chunk_id,
+            end_of_stream,
+            failed: false,
+            raw_chunk: Bytes::copy_from_slice(raw_chunk.as_bytes()),
+        }))
+    };
+    let ack_msg = |chunk_id: u32| {
+        MultiplexMessage::Message(NetworkMessage::RpcChunkAck(RpcChunkAck {
+            request_id: 123,
+            chunk_id,
+        }))
+    };
+
+    let client = async move {
+        client_sink.send(&send_msg).await.unwrap();
+
+        // The first two chunks fill the window.
+        let received = client_stream.next().await.unwrap().unwrap();
+        assert_eq!(received, chunk_msg(0, false, "chunk 0"));
+        let received = client_stream.next().await.unwrap().unwrap();
+        assert_eq!(received, chunk_msg(1, false, "chunk 1"));
+
+        // Every ack allows one more chunk through.
+        client_sink.send(&ack_msg(0)).await.unwrap();
+        let received = client_stream.next().await.unwrap().unwrap();
+        assert_eq!(received, chunk_msg(2, false, "chunk 2"));
+        client_sink.send(&ack_msg(1)).await.unwrap();
+        let received = client_stream.next().await.unwrap().unwrap();
+        assert_eq!(received, chunk_msg(3, true, ""));
+
+        // Client then closes connection.
+        client_sink.close().await.unwrap();
+    };
+    let server = async move {
+        // Wait to receive StreamingRpcRequest from Peer.
+        let received = peer_notifs_rx.next().await.unwrap();
+        assert_eq!(recv_msg, received);
+
+        // Send the response chunks, then end the stream by dropping the sender.
+        match received {
+            PeerNotification::RecvStreamingRpc(mut req) => {
+                for i in 0..3 {
+                    let chunk = Ok(Bytes::from(format!("chunk {}", i)));
+                    req.res_tx.send(chunk).await.unwrap();
+                }
+            },
+            _ => panic!("Unexpected PeerNotification: {:?}", received),
+        }
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// A stream the responder failed to complete should fail, instead of ending
+// with the chunks received so far.
+#[test]
+fn peer_send_streaming_rpc_failed() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (mut peer, peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    peer.connection_metadata
+        .features
+        .features
+        .insert(Feature::StreamingRpc);
+    let (mut server_sink, mut server_stream) = build_network_sink_stream(&mut connection);
+
+    let client = async move {
+        let mut res_rx = peer_handle.send_streaming_rpc(Bytes::from("hello world"), 2);
+        assert_eq!(
+            res_rx.next().await.unwrap().unwrap(),
+            Bytes::from("chunk 0")
+        );
+        assert!(res_rx.next().await.unwrap().is_err());
+        assert!(res_rx.next().await.is_none());
+    };
+    let server = async move {
+        let request_id = match server_stream.next().await.unwrap().unwrap() {
+            MultiplexMessage::Message(NetworkMessage::StreamingRpcRequest(request)) => {
+                assert_eq!(request.raw_request, Bytes::from("hello world"));
+                request.request_id
+            },
+            received => panic!("Expected StreamingRpcRequest; unexpected: {:?}", received),
+        };
+        let chunk_msg =
+            |chunk_id: u32, end_of_stream: bool, failed: bool, raw_chunk: &'static [u8]| {
+                MultiplexMessage::Message(NetworkMessage::RpcResponseChunk(RpcResponseChunk {
+                    request_id,
+                    chunk_id,
+                    end_of_stream,
+                    failed,
+                    raw_chunk: Bytes::from_static(raw_chunk),
+                }))
+            };
+        server_sink
+            .send(&chunk_msg(0, false, false, b"chunk 0"))
+            .await
+            .unwrap();
+        server_sink
+            .send(&chunk_msg(1, true, true, b""))
+            .await
+            .unwrap();
+        // The first chunk is still acked
+        let received = server_stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            received,
+            MultiplexMessage::Message(NetworkMessage::RpcChunkAck(RpcChunkAck {
+                request_id,
+                chunk_id: 0,
+            }))
+        );
+        server_sink.close().await.unwrap();
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// A responder that sends more chunks than the flow control window allows should
+// fail the stream.
+#[test]
+fn peer_send_streaming_rpc_window_exceeded() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (mut peer, peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    peer.connection_metadata
+        .features
+        .features
+        .insert(Feature::StreamingRpc);
+    let (mut server_sink, mut server_stream) = build_network_sink_stream(&mut connection);
+    let (sent_tx, sent_rx) = oneshot::channel();
+
+    let client = async move {
+        let res_rx = peer_handle.send_streaming_rpc(Bytes::from("hello world"), 1);
+
+        // Only start consuming once the responder has overrun the window
+        sent_rx.await.unwrap();
+        let chunks: Vec<_> = res_rx.collect().await;
+        assert!(chunks.len() < 10);
+        assert!(chunks.last().unwrap().is_err());
+    };
+    let server = async move {
+        let request_id = match server_stream.next().await.unwrap().unwrap() {
+            MultiplexMessage::Message(NetworkMessage::StreamingRpcRequest(request)) => {
+                assert_eq!(request.window, 1);
+                request.request_id
+            },
+            received => panic!("Expected StreamingRpcRequest; unexpected: {:?}", received),
+        };
+        for chunk_id in 0..10 {
+            let chunk =
+                MultiplexMessage::Message(NetworkMessage::RpcResponseChunk(RpcResponseChunk {
+                    request_id,
+                    chunk_id,
+                    end_of_stream: chunk_id == 9,
+                    failed: false,
+                    raw_chunk: Bytes::from(format!("chunk {}", chunk_id)),
+                }));
+            server_sink.send(&chunk).await.unwrap();
+        }
+        sent_tx.send(()).unwrap();
+        // Drain the acks of the chunks that were delivered
+        while let Some(Ok(_)) = server_stream.next().await {}
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+// Streaming rpcs should fail locally, without being sent, if the remote peer
+// doesn't support them.
+#[test]
+fn peer_send_streaming_rpc_unsupported() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (peer, peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+        build_test_peer(
+            rt.handle().clone(),
+            TimeService::mock(),
+            ConnectionOrigin::Inbound,
+        );
+    let (_server_sink, mut server_stream) = build_network_sink_stream(&mut connection);
+
+    let client = async move {
+        let mut res_rx = peer_handle.send_streaming_rpc(Bytes::from("hello world"), 2);
+        assert!(res_rx.next().await.unwrap().is_err());
+        drop(peer_handle);
+        // Nothing was written before the connection closes
+        assert!(server_stream.next().await.is_none());
+    };
+    rt.block_on(future::join(peer.start(), client));
+}
+
 #[test]
 fn peer_recv_rpc_concurrent() {
     ::aptos_logger::Logger::init_for_testing();
@@ -412,17 +1208,19 @@ fn peer_recv_rpc_concurrent() {
         request_id: 123,
         protocol_id: PROTOCOL,
         priority: 0,
-        raw_request: Vec::from("hello world"),
+        raw_request: Bytes::from_static(b"hello world"),
     }));
     let recv_msg = PeerNotification::RecvRpc(InboundRpcRequest {
         protocol_id: PROTOCOL,
         data: Bytes::from("hello world"),
         res_tx: oneshot::channel().0,
+        deadline: None,
+        correlation_id: 0,
     });
     let resp_msg = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
         request_id: 123,
         priority: 0,
-        raw_response: Vec::from("goodbye world"),
+        raw_response: Bytes::from_static(b"goodbye world"),
     }));
 
     let client = async move {
@@ -479,12 +1277,14 @@ fn peer_recv_rpc_timeout() {
         request_id: 123,
         protocol_id: PROTOCOL,
         priority: 0,
-        raw_request: Vec::from("hello world"),
+        raw_request: Bytes::from_static(b"hello world"),
     }));
     let recv_msg = PeerNotification::RecvRpc(InboundRpcRequest {
         protocol_id: PROTOCOL,
         data: Bytes::from("hello world"),
         res_tx: oneshot::channel().0,
+        deadline: None,
+        correlation_id: 0,
     });
 
     let test = async move {
@@ -537,12 +1337,14 @@ fn peer_recv_rpc_cancel() {
         request_id: 123,
         protocol_id: PROTOCOL,
         priority: 0,
-        raw_request: Vec::from("hello world"),
+        raw_request: Bytes::from_static(b"hello world"),
     }));
     let recv_msg = PeerNotification::RecvRpc(InboundRpcRequest {
         protocol_id: PROTOCOL,
         data: Bytes::from("hello world"),
         res_tx: oneshot::channel().0,
+        deadline: None,
+        correlation_id: 0,
     });
 
     let test = async move {
@@ -575,11 +1377,198 @@ fn peer_recv_rpc_cancel() {
     rt.block_on(future::join(peer.start(), test));
 }
 
+// Inbound rpcs beyond the concurrency limit of their protocol should be shed,
+// and fail right away for the requester.
+#[test]
+fn peers_shed_rpcs_beyond_concurrency_limit() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (
+        (mut peer_a, mut peer_handle_a, _connection_notifs_rx_a, _peer_notifs_rx_a),
+        (mut peer_b, _peer_handle_b, _connection_notifs_rx_b, mut peer_notifs_rx_b),
+    ) = build_test_connected_peers(rt.handle().clone(), TimeService::mock());
+    for peer in [&mut peer_a, &mut peer_b] {
+        peer.connection_metadata
+            .features
+            .features
+            .insert(Feature::LoadShedding);
+    }
+    let mut concurrency_limits = InboundRpcConcurrencyLimits::default();
+    concurrency_limits.insert(PROTOCOL, 1);
+    peer_b.inbound_rpcs = InboundRpcs::new(
+        NetworkContext::mock(),
+        TimeService::mock(),
+        peer_b.remote_peer_id(),
+        ProtocolIdSet::empty(),
+        Duration::from_millis(INBOUND_RPC_TIMEOUT_MS),
+        MAX_CONCURRENT_INBOUND_RPCS,
+        concurrency_limits,
+    );
+
+    let test = async move {
+        let timeout = Duration::from_secs(10);
+        let mut first_handle = peer_handle_a.clone();
+        let first_rpc = async move {
+            first_handle
+                .send_rpc_request(PROTOCOL, Bytes::from("hello world"), timeout)
+                .await
+        };
+        let second_rpc = async {
+            let request = match peer_notifs_rx_b.next().await.unwrap() {
+                PeerNotification::RecvRpc(request) => request,
+                notif => panic!("Unexpected PeerNotification: {:?}", notif),
+            };
+
+            // The second request is shed while the first one is processed
+            let result = peer_handle_a
+                .send_rpc_request(PROTOCOL, Bytes::from("hello again"), timeout)
+                .await;
+            assert!(matches!(result, Err(RpcError::Busy)));
+            request
+                .res_tx
+                .send(Ok(Bytes::from("goodbye world")))
+                .unwrap();
+        };
+        let (result, ()) = future::join(first_rpc, second_rpc).await;
+        assert_eq!(result.unwrap(), Bytes::from("goodbye world"));
+        drop(peer_handle_a);
+    };
+
+    rt.block_on(future::join3(peer_a.start(), peer_b.start(), test));
+}
+
+// Inbound rpcs that expired before they were handled should be dropped, and
+// fail right away for the requester.
+#[test]
+fn peers_reject_expired_rpcs() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (
+        (mut peer_a, mut peer_handle_a, _connection_notifs_rx_a, _peer_notifs_rx_a),
+        (mut peer_b, _peer_handle_b, _connection_notifs_rx_b, mut peer_notifs_rx_b),
+    ) = build_test_connected_peers(rt.handle().clone(), TimeService::mock());
+    for peer in [&mut peer_a, &mut peer_b] {
+        peer.connection_metadata
+            .features
+            .features
+            .insert(Feature::RpcExpiry);
+    }
+
+    let test = async move {
+        let mut rpc_handle = peer_handle_a.clone();
+        let rpc = async move {
+            rpc_handle
+                .send_rpc_request(
+                    PROTOCOL,
+                    Bytes::from("hello world"),
+                    Duration::from_secs(10),
+                )
+                .await
+        };
+        let expire = async {
+            let request = match peer_notifs_rx_b.next().await.unwrap() {
+                PeerNotification::RecvRpc(request) => request,
+                notif => panic!("Unexpected PeerNotification: {:?}", notif),
+            };
+
+            // The request expires before it reaches its handler (the mock time
+            // never advances, so the requester would otherwise wait forever)
+            request.res_tx.send(Err(RpcError::Expired)).unwrap();
+        };
+        let (result, ()) = future::join(rpc, expire).await;
+        assert!(matches!(result, Err(RpcError::Expired)));
+        drop(peer_handle_a);
+    };
+
+    rt.block_on(future::join3(peer_a.start(), peer_b.start(), test));
+}
+
+// Draining a connection announces it to the remote peer, completes the pending
+// rpcs and then closes the connection at both ends.
+#[test]
+fn peers_drain_gracefully() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (
+        (mut peer_a, peer_handle_a, mut connection_notifs_rx_a, _peer_notifs_rx_a),
+        (mut peer_b, peer_handle_b, mut connection_notifs_rx_b, mut peer_notifs_rx_b),
+    ) = build_test_connected_peers(rt.handle().clone(), TimeService::mock());
+    for peer in [&mut peer_a, &mut peer_b] {
+        peer.connection_metadata
+            .features
+            .features
+            .insert(Feature::GracefulDrain);
+    }
+    let peer_id_a = peer_a.remote_peer_id();
+    let peer_id_b = peer_b.remote_peer_id();
+
+    let test = async move {
+        let mut rpc_handle = peer_handle_a.clone();
+        let rpc = async move {
+            rpc_handle
+                .send_rpc_request(
+                    PROTOCOL,
+                    Bytes::from("hello world"),
+                    Duration::from_secs(10),
+                )
+                .await
+        };
+        let drain = async {
+            let request = match peer_notifs_rx_b.next().await.unwrap() {
+                PeerNotification::RecvRpc(request) => request,
+                notif => panic!("Unexpected PeerNotification: {:?}", notif),
+            };
+
+            // Drain the connection while the rpc is still pending
+            peer_handle_a.drain("restarting", Duration::from_secs(10));
+            drop(peer_handle_a);
+            match connection_notifs_rx_b.next().await {
+                Some(TransportNotification::Draining(metadata)) => {
+                    assert_eq!(metadata.remote_peer_id, peer_id_b);
+                },
+                event => panic!("Expected a Draining, received: {:?}", event),
+            }
+            drop(peer_handle_b);
+
+            // The pending rpc still completes
+            request
+                .res_tx
+                .send(Ok(Bytes::from("goodbye world")))
+                .unwrap();
+        };
+        let (result, ()) = future::join(rpc, drain).await;
+        assert_eq!(result.unwrap(), Bytes::from("goodbye world"));
+
+        // Both ends then close the connection
+        loop {
+            match connection_notifs_rx_a.next().await {
+                Some(TransportNotification::MetadataUpdated(_)) => continue,
+                Some(TransportNotification::Disconnected(metadata, reason)) => {
+                    assert_eq!(metadata.remote_peer_id, peer_id_a);
+                    assert_eq!(reason, DisconnectReason::Drained);
+                    break;
+                },
+                event => panic!("Expected a Disconnected, received: {:?}", event),
+            }
+        }
+        assert_disconnected_event(
+            peer_id_b,
+            DisconnectReason::Drained,
+            &mut connection_notifs_rx_b,
+        )
+        .await;
+    };
+
+    rt.block_on(future::join3(peer_a.start(), peer_b.start(), test));
+}
+
+// Peers that didn't negotiate rpc expiry should still receive plain rpc
+// requests.
 #[test]
 fn peer_send_rpc() {
     ::aptos_logger::Logger::init_for_testing();
     let rt = Runtime::new().unwrap();
-    let (peer, mut peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+    let (peer, mut peer_handle, mut connection, mut connection_notifs_rx, _peer_notifs_rx) =
         build_test_peer(
             rt.handle().clone(),
             TimeService::mock(),
@@ -591,14 +1580,25 @@ fn peer_send_rpc() {
     let mut request_ids = HashSet::new();
 
     let client = async move {
-        for _ in 0..30 {
+        for i in 0..30 {
             // Send RpcRequest to server and await response data.
             let response = peer_handle
                 .send_rpc_request(PROTOCOL, Bytes::from(&b"hello world"[..]), timeout)
                 .await
                 .unwrap();
             assert_eq!(response, Bytes::from(&b"goodbye world"[..]));
+
+            // The first rpc latency is reported as the smoothed RTT
+            if i == 0 {
+                match connection_notifs_rx.next().await {
+                    Some(TransportNotification::MetadataUpdated(metadata)) => {
+                        assert!(metadata.smoothed_rtt.is_some());
+                    },
+                    event => panic!("Expected a MetadataUpdated, received: {:?}", event),
+                }
+            }
         }
+        drop(connection_notifs_rx);
         // Client then closes connection.
     };
     let server = async move {
@@ -612,7 +1612,7 @@ fn peer_send_rpc() {
 
             assert_eq!(received.protocol_id, PROTOCOL);
             assert_eq!(received.priority, 0);
-            assert_eq!(received.raw_request, b"hello world");
+            assert_eq!(received.raw_request, &b"hello world"[..]);
 
             assert!(
                 request_ids.insert(received.request_id),
@@ -623,7 +1623,7 @@ fn peer_send_rpc() {
             let response = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
                 request_id: received.request_id,
                 priority: 0,
-                raw_response: Vec::from(&b"goodbye world"[..]),
+                raw_response: Bytes::from_static(b"goodbye world"),
             }));
 
             // Server should send the rpc request.
@@ -634,19 +1634,65 @@ fn peer_send_rpc() {
     rt.block_on(future::join3(peer.start(), server, client));
 }
 
+// Peers that negotiated rpc expiry should receive the timeout of our requests.
 #[test]
-fn peer_send_rpc_concurrent() {
+fn peer_send_rpc_with_deadline() {
     ::aptos_logger::Logger::init_for_testing();
     let rt = Runtime::new().unwrap();
-    let (peer, peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
-        build_test_peer(
+    let (peer, mut peer_handle, mut connection, _connection_notifs_rx, _peer_notifs_rx) =
+        build_test_peer_with_features(
             rt.handle().clone(),
             TimeService::mock(),
             ConnectionOrigin::Inbound,
+            FeatureSet::from_iter([Feature::RpcExpiry]),
         );
     let (mut server_sink, mut server_stream) = build_network_sink_stream(&mut connection);
     let timeout = Duration::from_millis(10_000);
 
+    let client = async move {
+        let response = peer_handle
+            .send_rpc_request(PROTOCOL, Bytes::from(&b"hello world"[..]), timeout)
+            .await
+            .unwrap();
+        assert_eq!(response, Bytes::from(&b"goodbye world"[..]));
+    };
+    let server = async move {
+        let received = server_stream.next().await.unwrap().unwrap();
+        let (received, received_timeout) = match received {
+            MultiplexMessage::Message(NetworkMessage::RpcRequestWithDeadline(request)) => {
+                request.into_parts()
+            },
+            _ => panic!(
+                "Expected RpcRequestWithDeadline; unexpected: {:?}",
+                received
+            ),
+        };
+        assert_eq!(received.raw_request, &b"hello world"[..]);
+        assert_eq!(received_timeout, timeout);
+
+        let response = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
+            request_id: received.request_id,
+            priority: 0,
+            raw_response: Bytes::from_static(b"goodbye world"),
+        }));
+        server_sink.send(&response).await.unwrap();
+        assert!(matches!(server_stream.next().await, None));
+    };
+    rt.block_on(future::join3(peer.start(), server, client));
+}
+
+#[test]
+fn peer_send_rpc_concurrent() {
+    ::aptos_logger::Logger::init_for_testing();
+    let rt = Runtime::new().unwrap();
+    let (peer, peer_handle, mut connection, _, _peer_notifs_rx) = build_test_peer(
+        rt.handle().clone(),
+        TimeService::mock(),
+        ConnectionOrigin::Inbound,
+    );
+    let (mut server_sink, mut server_stream) = build_network_sink_stream(&mut connection);
+    let timeout = Duration::from_millis(10_000);
+
     let mut request_ids = HashSet::new();
 
     let client = async move {
@@ -681,7 +1727,7 @@ fn peer_send_rpc_concurrent() {
 
             assert_eq!(received.protocol_id, PROTOCOL);
             assert_eq!(received.priority, 0);
-            assert_eq!(received.raw_request, b"hello world");
+            assert_eq!(received.raw_request, &b"hello world"[..]);
 
             assert!(
                 request_ids.insert(received.request_id),
@@ -692,7 +1738,7 @@ fn peer_send_rpc_concurrent() {
             let response = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
                 request_id: received.request_id,
                 priority: 0,
-                raw_response: Vec::from(&b"goodbye world"[..]),
+                raw_response: Bytes::from_static(b"goodbye world"),
             }));
 
             // Server should send the rpc request.
@@ -724,6 +1770,8 @@ fn peer_send_rpc_cancel() {
             data: Bytes::from(&b"hello world"[..]),
             res_tx: response_tx,
             timeout,
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         });
         peer_handle.0.push(PROTOCOL, request).unwrap();
 
@@ -736,7 +1784,7 @@ fn peer_send_rpc_cancel() {
 
         assert_eq!(received.protocol_id, PROTOCOL);
         assert_eq!(received.priority, 0);
-        assert_eq!(received.raw_request, b"hello world");
+        assert_eq!(received.raw_request, &b"hello world"[..]);
 
         // Request should still be live. Ok(_) means the sender is not dropped.
         // Ok(None) means there is no response yet.
@@ -749,7 +1797,7 @@ fn peer_send_rpc_cancel() {
         let response = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
             request_id: received.request_id,
             priority: 0,
-            raw_response: Vec::from(&b"goodbye world"[..]),
+            raw_response: Bytes::from_static(b"goodbye world"),
         }));
         server_sink.send(&response).await.unwrap();
 
@@ -785,6 +1833,8 @@ fn peer_send_rpc_timeout() {
             data: Bytes::from(&b"hello world"[..]),
             res_tx: response_tx,
             timeout,
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         });
         peer_handle.0.push(PROTOCOL, request).unwrap();
 
@@ -797,7 +1847,7 @@ fn peer_send_rpc_timeout() {
 
         assert_eq!(received.protocol_id, PROTOCOL);
         assert_eq!(received.priority, 0);
-        assert_eq!(received.raw_request, b"hello world");
+        assert_eq!(received.raw_request, &b"hello world"[..]);
 
         // Request should still be live. Ok(_) means the sender is not dropped.
         // Ok(None) means there is no response yet.
@@ -813,7 +1863,7 @@ fn peer_send_rpc_timeout() {
         let response = MultiplexMessage::Message(NetworkMessage::RpcResponse(RpcResponse {
             request_id: received.request_id,
             priority: 0,
-            raw_response: Vec::from(&b"goodbye world"[..]),
+            raw_response: Bytes::from_static(b"goodbye world"),
         }));
         server_sink.send(&response).await.unwrap();
 
@@ -911,10 +1961,14 @@ fn peers_send_multiplex() {
         let msg_a = Message {
             protocol_id: PROTOCOL,
             mdata: Bytes::from(vec![0; MAX_MESSAGE_SIZE]), // stream message
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         };
         let msg_b = Message {
             protocol_id: PROTOCOL,
             mdata: Bytes::from(vec![1; 1024]), // normal message
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
         };
 
         // Peer A -> msg_a -> Peer B
@@ -0,0 +1,211 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-protocol access control lists.
+//!
+//! Applications can register allow and deny rules for each of their protocols, to
+//! restrict which peers may send them messages and RPCs. The rules are checked by
+//! the `PeerManager` before inbound messages are delivered to the upstream handlers
+//! (and hence to `NetworkEvents`), so e.g. consensus can reject messages from
+//! non-validator peers at the network layer. Rejected RPCs are dropped, which
+//! fails them for the remote peer.
+//!
+//! The same [`ProtocolAcls`] handle can be shared by several networks and updated
+//! at runtime; updates apply to all subsequent inbound messages.
+
+use crate::ProtocolId;
+use aptos_config::{config::PeerRole, network_id::NetworkId};
+use aptos_infallible::RwLock;
+use aptos_types::PeerId;
+use std::{collections::HashMap, sync::Arc};
+
+/// Matches the remote peers that an [`AclRule`] applies to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AclMatcher {
+    /// Matches every peer
+    Any,
+    /// Matches the peer with the given id
+    Peer(PeerId),
+    /// Matches peers with the given role
+    Role(PeerRole),
+    /// Matches peers on the given network
+    Network(NetworkId),
+}
+
+impl AclMatcher {
+    fn matches(&self, peer_id: PeerId, role: PeerRole, network_id: NetworkId) -> bool {
+        match self {
+            AclMatcher::Any => true,
+            AclMatcher::Peer(matched_peer_id) => *matched_peer_id == peer_id,
+            AclMatcher::Role(matched_role) => *matched_role == role,
+            AclMatcher::Network(matched_network_id) => *matched_network_id == network_id,
+        }
+    }
+}
+
+/// A single rule of a protocol's access control list
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AclRule {
+    /// Allows messages from the matching peers
+    Allow(AclMatcher),
+    /// Rejects messages from the matching peers (takes precedence over allow rules)
+    Deny(AclMatcher),
+}
+
+/// The access control lists of all protocols. Protocols without any rules accept
+/// messages from every peer. Otherwise, a message is accepted iff no deny rule
+/// matches the sender and, if the protocol has allow rules, one of them does.
+#[derive(Clone, Debug, Default)]
+pub struct ProtocolAcls {
+    rules: Arc<RwLock<HashMap<ProtocolId, Vec<AclRule>>>>,
+}
+
+impl ProtocolAcls {
+    /// Adds the given rule to the access control list of the protocol
+    pub fn add_rule(&self, protocol_id: ProtocolId, rule: AclRule) {
+        self.rules
+            .write()
+            .entry(protocol_id)
+            .or_insert_with(Vec::new)
+            .push(rule);
+    }
+
+    /// Only accepts messages for the protocol from peers with one of the given roles
+    pub fn allow_roles(&self, protocol_id: ProtocolId, roles: &[PeerRole]) {
+        for role in roles {
+            self.add_rule(protocol_id, AclRule::Allow(AclMatcher::Role(*role)));
+        }
+    }
+
+    /// Removes all rules of the protocol, so that it accepts messages from every peer
+    pub fn clear_rules(&self, protocol_id: ProtocolId) {
+        self.rules.write().remove(&protocol_id);
+    }
+
+    /// Returns the rules of the protocol, in the order they were added
+    pub fn rules(&self, protocol_id: ProtocolId) -> Vec<AclRule> {
+        self.rules
+            .read()
+            .get(&protocol_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns true iff a message for the protocol from the given peer is accepted
+    pub fn is_allowed(
+        &self,
+        protocol_id: ProtocolId,
+        peer_id: PeerId,
+        role: PeerRole,
+        network_id: NetworkId,
+    ) -> bool {
+        let rules = self.rules.read();
+        let rules = match rules.get(&protocol_id) {
+            Some(rules) => rules,
+            None => return true,
+        };
+
+        let mut has_allow_rules = false;
+        let mut allowed = false;
+        for rule in rules {
+            match rule {
+                AclRule::Deny(matcher) => {
+                    if matcher.matches(peer_id, role, network_id) {
+                        return false;
+                    }
+                },
+                AclRule::Allow(matcher) => {
+                    has_allow_rules = true;
+                    allowed |= matcher.matches(peer_id, role, network_id);
+                },
+            }
+        }
+        allowed || !has_allow_rules
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn protocols_without_rules_allow_all() {
+        let acls = ProtocolAcls::default();
+        assert!(acls.is_allowed(
+            ProtocolId::ConsensusRpcBcs,
+            PeerId::random(),
+            PeerRole::Unknown,
+            NetworkId::Public
+        ));
+    }
+
+    #[test]
+    fn allow_rules_restrict_senders() {
+        let acls = ProtocolAcls::default();
+        acls.allow_roles(ProtocolId::ConsensusRpcBcs, &[PeerRole::Validator]);
+
+        let peer_id = PeerId::random();
+        assert!(acls.is_allowed(
+            ProtocolId::ConsensusRpcBcs,
+            peer_id,
+            PeerRole::Validator,
+            NetworkId::Validator
+        ));
+        assert!(!acls.is_allowed(
+            ProtocolId::ConsensusRpcBcs,
+            peer_id,
+            PeerRole::ValidatorFullNode,
+            NetworkId::Validator
+        ));
+
+        // Other protocols are unaffected
+        assert!(acls.is_allowed(
+            ProtocolId::MempoolDirectSend,
+            peer_id,
+            PeerRole::ValidatorFullNode,
+            NetworkId::Validator
+        ));
+
+        // Clearing the rules allows all peers again
+        acls.clear_rules(ProtocolId::ConsensusRpcBcs);
+        assert!(acls.is_allowed(
+            ProtocolId::ConsensusRpcBcs,
+            peer_id,
+            PeerRole::ValidatorFullNode,
+            NetworkId::Validator
+        ));
+    }
+
+    #[test]
+    fn deny_rules_take_precedence() {
+        let acls = ProtocolAcls::default();
+        let banned_peer = PeerId::random();
+        acls.add_rule(
+            ProtocolId::MempoolDirectSend,
+            AclRule::Allow(AclMatcher::Network(NetworkId::Public)),
+        );
+        acls.add_rule(
+            ProtocolId::MempoolDirectSend,
+            AclRule::Deny(AclMatcher::Peer(banned_peer)),
+        );
+
+        assert!(acls.is_allowed(
+            ProtocolId::MempoolDirectSend,
+            PeerId::random(),
+            PeerRole::Unknown,
+            NetworkId::Public
+        ));
+        assert!(!acls.is_allowed(
+            ProtocolId::MempoolDirectSend,
+            banned_peer,
+            PeerRole::Unknown,
+            NetworkId::Public
+        ));
+        assert!(!acls.is_allowed(
+            ProtocolId::MempoolDirectSend,
+            PeerId::random(),
+            PeerRole::Unknown,
+            NetworkId::Vfn
+        ));
+    }
+}
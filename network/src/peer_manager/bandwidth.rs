@@ -0,0 +1,167 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-peer and per-network bandwidth caps.
+//!
+//! The caps are token buckets enforced by the [`Peer`] actors on their sockets, on top
+//! of the per-IP rate limits. The buckets of a remote peer are shared by all of its
+//! connections, and the buckets of the network by all of its peers, so that a single
+//! peer (e.g., a public fullnode performing a bulk state sync) can't saturate the NIC.
+//!
+//! [`Peer`]: crate::peer::Peer
+
+use crate::{counters::NETWORK_RATE_LIMIT_METRICS, peer::BandwidthBuckets};
+use aptos_config::{
+    config::{BandwidthLimitConfig, ByteRateLimit},
+    network_id::NetworkContext,
+};
+use aptos_infallible::Mutex;
+use aptos_rate_limiter::rate_limit::{Bucket, SharedBucket, TokenBucketRateLimiter};
+use aptos_types::PeerId;
+use std::sync::Arc;
+
+pub type PeerIdTokenBucketLimiter = TokenBucketRateLimiter<PeerId>;
+
+/// The bandwidth caps of a network. Caps that aren't configured aren't enforced.
+#[derive(Default)]
+pub struct BandwidthLimiters {
+    peer_upload: Option<PeerIdTokenBucketLimiter>,
+    peer_download: Option<PeerIdTokenBucketLimiter>,
+    network_upload: Option<SharedBucket>,
+    network_download: Option<SharedBucket>,
+}
+
+impl BandwidthLimiters {
+    pub fn new(network_context: &NetworkContext, config: BandwidthLimitConfig) -> Self {
+        Self {
+            peer_upload: config
+                .peer_upload
+                .map(|limit| peer_rate_limiter(network_context, "peer_upload", limit)),
+            peer_download: config
+                .peer_download
+                .map(|limit| peer_rate_limiter(network_context, "peer_download", limit)),
+            network_upload: config
+                .network_upload
+                .map(|limit| network_bucket(network_context, "network_upload", limit)),
+            network_download: config
+                .network_download
+                .map(|limit| network_bucket(network_context, "network_download", limit)),
+        }
+    }
+
+    /// Returns the inbound (download) and outbound (upload) buckets of a connection
+    /// to the given peer
+    pub fn buckets(&self, peer_id: PeerId) -> (BandwidthBuckets, BandwidthBuckets) {
+        let inbound = BandwidthBuckets {
+            peer: self
+                .peer_download
+                .as_ref()
+                .map(|limiter| limiter.bucket(peer_id)),
+            network: self.network_download.clone(),
+        };
+        let outbound = BandwidthBuckets {
+            peer: self
+                .peer_upload
+                .as_ref()
+                .map(|limiter| limiter.bucket(peer_id)),
+            network: self.network_upload.clone(),
+        };
+        (inbound, outbound)
+    }
+
+    /// Garbage collects the buckets of the given peer, if they're no longer used
+    pub fn try_garbage_collect_peer(&self, peer_id: &PeerId) {
+        for limiter in [&self.peer_upload, &self.peer_download]
+            .into_iter()
+            .flatten()
+        {
+            limiter.try_garbage_collect_key(peer_id);
+        }
+    }
+}
+
+fn peer_rate_limiter(
+    network_context: &NetworkContext,
+    label: &'static str,
+    limit: ByteRateLimit,
+) -> PeerIdTokenBucketLimiter {
+    TokenBucketRateLimiter::new(
+        label,
+        network_context.to_string(),
+        100,
+        limit.byte_bucket_size,
+        limit.byte_bucket_rate,
+        Some(NETWORK_RATE_LIMIT_METRICS.clone()),
+    )
+}
+
+fn network_bucket(
+    network_context: &NetworkContext,
+    label: &'static str,
+    limit: ByteRateLimit,
+) -> SharedBucket {
+    Arc::new(Mutex::new(Bucket::new(
+        label.to_string(),
+        network_context.to_string(),
+        network_context.network_id().to_string(),
+        limit.byte_bucket_size,
+        limit.byte_bucket_size,
+        limit.byte_bucket_rate,
+        Some(NETWORK_RATE_LIMIT_METRICS.clone()),
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bandwidth_buckets() {
+        let limit = ByteRateLimit {
+            byte_bucket_rate: 1024,
+            byte_bucket_size: 1024,
+        };
+        let config = BandwidthLimitConfig {
+            peer_upload: Some(limit),
+            network_download: Some(limit),
+            ..Default::default()
+        };
+        let limiters = BandwidthLimiters::new(&NetworkContext::mock(), config);
+        let (peer_a, peer_b) = (PeerId::random(), PeerId::random());
+
+        // Only the configured caps are enforced
+        let (inbound_a, outbound_a) = limiters.buckets(peer_a);
+        assert!(inbound_a.peer.is_none() && outbound_a.network.is_none());
+
+        // Connections to the same peer share its buckets
+        let (_, outbound_a_2) = limiters.buckets(peer_a);
+        assert!(Arc::ptr_eq(
+            outbound_a.peer.as_ref().unwrap(),
+            outbound_a_2.peer.as_ref().unwrap()
+        ));
+
+        // All peers share the network buckets, but not the peer buckets
+        let (inbound_b, outbound_b) = limiters.buckets(peer_b);
+        assert!(Arc::ptr_eq(
+            inbound_a.network.as_ref().unwrap(),
+            inbound_b.network.as_ref().unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            outbound_a.peer.as_ref().unwrap(),
+            outbound_b.peer.as_ref().unwrap()
+        ));
+
+        // Buckets are only garbage collected once unused
+        let outbound_b_bucket = Arc::downgrade(outbound_b.peer.as_ref().unwrap());
+        drop((inbound_b, outbound_b));
+        limiters.try_garbage_collect_peer(&peer_b);
+        assert!(outbound_b_bucket.upgrade().is_none());
+        drop(outbound_a_2);
+        limiters.try_garbage_collect_peer(&peer_a);
+        let (_, outbound_a_3) = limiters.buckets(peer_a);
+        assert!(Arc::ptr_eq(
+            outbound_a.peer.as_ref().unwrap(),
+            outbound_a_3.peer.as_ref().unwrap()
+        ));
+    }
+}
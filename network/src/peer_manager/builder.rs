@@ -2,24 +2,34 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    application::storage::PeerMetadataStorage,
+    application::{
+        dedup::MessageDeduplicator, rate_limit::InboundRateLimiter,
+        response_cache::RpcResponseCache, storage::PeerMetadataStorage,
+    },
     counters,
     counters::NETWORK_RATE_LIMIT_METRICS,
-    noise::{stream::NoiseStream, HandshakeAuthMode},
+    noise::HandshakeAuthMode,
+    peer::batching::MessageBatching,
     peer_manager::{
-        conn_notifs_channel, ConnectionRequest, ConnectionRequestSender, PeerManager,
-        PeerManagerNotification, PeerManagerRequest, PeerManagerRequestSender,
+        conn_notifs_channel, BandwidthLimiters, ConnectionRequest, ConnectionRequestSender,
+        PeerManager, PeerManagerNotification, PeerManagerRequest, PeerManagerRequestSender,
+        ProtocolAcls, ProtocolMessageSizeLimits,
     },
     protocols::{
+        direct_send::replay::ReplayProtection,
         network::{NetworkClientConfig, NetworkServiceConfig},
+        rpc::InboundRpcConcurrencyLimits,
         wire::handshake::v1::ProtocolIdSet,
     },
-    transport::{self, AptosNetTransport, Connection, APTOS_TCP_TRANSPORT},
+    transport::{self, AptosNetTransport, Connection, SecureStream, APTOS_TCP_TRANSPORT},
     ProtocolId,
 };
 use aptos_channels::{self, aptos_channel, message_queues::QueueStyle};
 use aptos_config::{
-    config::{PeerSet, RateLimitConfig, HANDSHAKE_VERSION},
+    config::{
+        BandwidthLimitConfig, EvictionPolicy, PeerSet, RateLimitConfig, TransportSecurity,
+        HANDSHAKE_VERSION,
+    },
     network_id::NetworkContext,
 };
 use aptos_crypto::x25519;
@@ -28,17 +38,25 @@ use aptos_logger::prelude::*;
 #[cfg(any(test, feature = "testing", feature = "fuzzing"))]
 use aptos_netcore::transport::memory::MemoryTransport;
 use aptos_netcore::transport::{
-    tcp::{TCPBufferCfg, TcpSocket, TcpTransport},
+    socks5::Socks5Proxy,
+    tcp::{TCPBufferCfg, TcpKeepaliveCfg, TcpSocket, TcpTransport},
+    websocket::{WsSocket, WsTransport},
     Transport,
 };
 use aptos_rate_limiter::rate_limit::TokenBucketRateLimiter;
 use aptos_time_service::TimeService;
 use aptos_types::{chain_id::ChainId, network_address::NetworkAddress, PeerId};
-use std::{clone::Clone, collections::HashMap, fmt::Debug, net::IpAddr, sync::Arc};
+use std::{
+    clone::Clone,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    net::IpAddr,
+    sync::Arc,
+};
 use tokio::runtime::Handle;
 
-/// Inbound and Outbound connections are always secured with NoiseIK.  The dialer
-/// will always verify the listener.
+/// Inbound and Outbound connections are always secured with NoiseIK (or TLS, per the
+/// `TransportSecurity`).  The dialer will always verify the listener.
 #[derive(Debug)]
 pub enum AuthenticationMode {
     /// Inbound connections will first be checked against the known peers set, and
@@ -57,6 +75,9 @@ struct TransportContext {
     authentication_mode: AuthenticationMode,
     trusted_peers: Arc<RwLock<PeerSet>>,
     enable_proxy_protocol: bool,
+    transport_security: TransportSecurity,
+    socks5_proxy: Option<Socks5Proxy>,
+    tcp_keepalive: Option<TcpKeepaliveCfg>,
 }
 
 impl TransportContext {
@@ -77,15 +98,28 @@ struct PeerManagerContext {
     trusted_peers: Arc<RwLock<PeerSet>>,
     upstream_handlers:
         HashMap<ProtocolId, aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>>,
+    protocol_acls: ProtocolAcls,
+    protocol_message_size_limits: ProtocolMessageSizeLimits,
+    inbound_deduplicators: HashMap<ProtocolId, Arc<MessageDeduplicator>>,
+    inbound_protocol_rate_limiters: HashMap<ProtocolId, Arc<InboundRateLimiter>>,
+    rpc_response_caches: HashMap<ProtocolId, Arc<RpcResponseCache>>,
+    inbound_rpc_concurrency_limits: InboundRpcConcurrencyLimits,
+    replay_protection: ReplayProtection,
+    message_batching: MessageBatching,
+    isolated_protocols: HashSet<ProtocolId>,
     connection_event_handlers: Vec<conn_notifs_channel::Sender>,
 
     max_concurrent_network_reqs: usize,
     channel_size: usize,
     max_frame_size: usize,
     max_message_size: usize,
+    max_reassembly_size: usize,
     inbound_connection_limit: usize,
+    outbound_connection_limit: Option<usize>,
+    eviction_policy: EvictionPolicy,
     inbound_rate_limit_config: Option<RateLimitConfig>,
     outbound_rate_limit_config: Option<RateLimitConfig>,
+    bandwidth_limit_config: BandwidthLimitConfig,
     tcp_buffer_cfg: TCPBufferCfg,
 }
 
@@ -103,15 +137,28 @@ impl PeerManagerContext {
             ProtocolId,
             aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>,
         >,
+        protocol_acls: ProtocolAcls,
+        protocol_message_size_limits: ProtocolMessageSizeLimits,
+        inbound_deduplicators: HashMap<ProtocolId, Arc<MessageDeduplicator>>,
+        inbound_protocol_rate_limiters: HashMap<ProtocolId, Arc<InboundRateLimiter>>,
+        rpc_response_caches: HashMap<ProtocolId, Arc<RpcResponseCache>>,
+        inbound_rpc_concurrency_limits: InboundRpcConcurrencyLimits,
+        replay_protection: ReplayProtection,
+        message_batching: MessageBatching,
+        isolated_protocols: HashSet<ProtocolId>,
         connection_event_handlers: Vec<conn_notifs_channel::Sender>,
 
         max_concurrent_network_reqs: usize,
         channel_size: usize,
         max_frame_size: usize,
         max_message_size: usize,
+        max_reassembly_size: usize,
         inbound_connection_limit: usize,
+        outbound_connection_limit: Option<usize>,
+        eviction_policy: EvictionPolicy,
         inbound_rate_limit_config: Option<RateLimitConfig>,
         outbound_rate_limit_config: Option<RateLimitConfig>,
+        bandwidth_limit_config: BandwidthLimitConfig,
         tcp_buffer_cfg: TCPBufferCfg,
     ) -> Self {
         Self {
@@ -123,15 +170,28 @@ impl PeerManagerContext {
             peer_metadata_storage,
             trusted_peers,
             upstream_handlers,
+            protocol_acls,
+            protocol_message_size_limits,
+            inbound_deduplicators,
+            inbound_protocol_rate_limiters,
+            rpc_response_caches,
+            inbound_rpc_concurrency_limits,
+            replay_protection,
+            message_batching,
+            isolated_protocols,
             connection_event_handlers,
 
             max_concurrent_network_reqs,
             channel_size,
             max_frame_size,
             max_message_size,
+            max_reassembly_size,
             inbound_connection_limit,
+            outbound_connection_limit,
+            eviction_policy,
             inbound_rate_limit_config,
             outbound_rate_limit_config,
+            bandwidth_limit_config,
             tcp_buffer_cfg,
         }
     }
@@ -154,13 +214,15 @@ impl PeerManagerContext {
 
 #[cfg(any(test, feature = "testing", feature = "fuzzing"))]
 type MemoryPeerManager =
-    PeerManager<AptosNetTransport<MemoryTransport>, NoiseStream<aptos_memsocket::MemorySocket>>;
-type TcpPeerManager = PeerManager<AptosNetTransport<TcpTransport>, NoiseStream<TcpSocket>>;
+    PeerManager<AptosNetTransport<MemoryTransport>, SecureStream<aptos_memsocket::MemorySocket>>;
+type TcpPeerManager = PeerManager<AptosNetTransport<TcpTransport>, SecureStream<TcpSocket>>;
+type WsPeerManager = PeerManager<AptosNetTransport<WsTransport>, SecureStream<WsSocket>>;
 
 enum TransportPeerManager {
     #[cfg(any(test, feature = "testing", feature = "fuzzing"))]
     Memory(MemoryPeerManager),
     Tcp(TcpPeerManager),
+    Ws(WsPeerManager),
 }
 
 pub struct PeerManagerBuilder {
@@ -189,10 +251,15 @@ impl PeerManagerBuilder {
         max_concurrent_network_reqs: usize,
         max_frame_size: usize,
         max_message_size: usize,
+        max_reassembly_size: usize,
         enable_proxy_protocol: bool,
+        transport_security: TransportSecurity,
         inbound_connection_limit: usize,
+        outbound_connection_limit: Option<usize>,
+        eviction_policy: EvictionPolicy,
         inbound_rate_limit_config: Option<RateLimitConfig>,
         outbound_rate_limit_config: Option<RateLimitConfig>,
+        bandwidth_limit_config: BandwidthLimitConfig,
         tcp_buffer_cfg: TCPBufferCfg,
     ) -> Self {
         // Setup channel to send requests to peer manager.
@@ -214,6 +281,9 @@ impl PeerManagerBuilder {
                 authentication_mode,
                 trusted_peers: trusted_peers.clone(),
                 enable_proxy_protocol,
+                transport_security,
+                socks5_proxy: None,
+                tcp_keepalive: None,
             }),
             peer_manager_context: Some(PeerManagerContext::new(
                 pm_reqs_tx,
@@ -223,14 +293,27 @@ impl PeerManagerBuilder {
                 peer_metadata_storage,
                 trusted_peers,
                 HashMap::new(),
+                ProtocolAcls::default(),
+                ProtocolMessageSizeLimits::default(),
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                InboundRpcConcurrencyLimits::default(),
+                ReplayProtection::default(),
+                MessageBatching::default(),
+                HashSet::new(),
                 Vec::new(),
                 max_concurrent_network_reqs,
                 channel_size,
                 max_frame_size,
                 max_message_size,
+                max_reassembly_size,
                 inbound_connection_limit,
+                outbound_connection_limit,
+                eviction_policy,
                 inbound_rate_limit_config,
                 outbound_rate_limit_config,
+                bandwidth_limit_config,
                 tcp_buffer_cfg,
             )),
             peer_manager: None,
@@ -275,6 +358,7 @@ impl PeerManagerBuilder {
         let protos = transport_context.supported_protocols;
         let chain_id = transport_context.chain_id;
         let enable_proxy_protocol = transport_context.enable_proxy_protocol;
+        let transport_security = transport_context.transport_security;
 
         let (key, auth_mode) = match transport_context.authentication_mode {
             AuthenticationMode::MaybeMutual(key) => (
@@ -290,6 +374,8 @@ impl PeerManagerBuilder {
         let mut aptos_tcp_transport = APTOS_TCP_TRANSPORT.clone();
         let tcp_cfg = self.get_tcp_buffers_cfg();
         aptos_tcp_transport.set_tcp_buffers(&tcp_cfg);
+        aptos_tcp_transport.set_keepalive(transport_context.tcp_keepalive);
+        aptos_tcp_transport.set_socks5_proxy(transport_context.socks5_proxy);
 
         self.peer_manager = match self.listen_address.as_slice() {
             [Ip4(_), Tcp(_)] | [Ip6(_), Tcp(_)] => {
@@ -300,6 +386,24 @@ impl PeerManagerBuilder {
                         self.time_service.clone(),
                         key,
                         auth_mode,
+                        transport_security,
+                        HANDSHAKE_VERSION,
+                        chain_id,
+                        protos,
+                        enable_proxy_protocol,
+                    ),
+                    executor,
+                )))
+            },
+            [Ip4(_), Tcp(_), Ws] | [Ip6(_), Tcp(_), Ws] => {
+                Some(TransportPeerManager::Ws(self.build_with_transport(
+                    AptosNetTransport::new(
+                        WsTransport::new(aptos_tcp_transport),
+                        self.network_context,
+                        self.time_service.clone(),
+                        key,
+                        auth_mode,
+                        transport_security,
                         HANDSHAKE_VERSION,
                         chain_id,
                         protos,
@@ -316,6 +420,7 @@ impl PeerManagerBuilder {
                     self.time_service.clone(),
                     key,
                     auth_mode,
+                    transport_security,
                     HANDSHAKE_VERSION,
                     chain_id,
                     protos,
@@ -325,7 +430,8 @@ impl PeerManagerBuilder {
             ))),
             _ => panic!(
                 "{} Unsupported listen_address: '{}', expected '/memory/<port>', \
-                 '/ip4/<addr>/tcp/<port>', or '/ip6/<addr>/tcp/<port>'.",
+                 '/ip4/<addr>/tcp/<port>', '/ip6/<addr>/tcp/<port>', \
+                 '/ip4/<addr>/tcp/<port>/ws', or '/ip6/<addr>/tcp/<port>/ws'.",
                 self.network_context, self.listen_address
             ),
         };
@@ -358,6 +464,8 @@ impl PeerManagerBuilder {
             "outbound",
             pm_context.outbound_rate_limit_config,
         );
+        let bandwidth_limiters =
+            BandwidthLimiters::new(&self.network_context, pm_context.bandwidth_limit_config);
         let peer_mgr = PeerManager::new(
             executor.clone(),
             self.time_service.clone(),
@@ -371,14 +479,27 @@ impl PeerManagerBuilder {
             pm_context.pm_reqs_rx,
             pm_context.connection_reqs_rx,
             pm_context.upstream_handlers,
+            pm_context.protocol_acls,
+            pm_context.protocol_message_size_limits,
+            pm_context.inbound_deduplicators,
+            pm_context.inbound_protocol_rate_limiters,
+            pm_context.rpc_response_caches,
+            pm_context.inbound_rpc_concurrency_limits,
+            pm_context.replay_protection,
+            pm_context.message_batching,
+            Arc::new(pm_context.isolated_protocols),
             pm_context.connection_event_handlers,
             pm_context.channel_size,
             pm_context.max_concurrent_network_reqs,
             pm_context.max_frame_size,
             pm_context.max_message_size,
+            pm_context.max_reassembly_size,
             pm_context.inbound_connection_limit,
+            pm_context.outbound_connection_limit,
+            pm_context.eviction_policy,
             inbound_rate_limiters,
             outbound_rate_limiters,
+            bandwidth_limiters,
         );
 
         // PeerManager constructor appends a public key to the listen_address.
@@ -409,6 +530,7 @@ impl PeerManagerBuilder {
             #[cfg(any(test, feature = "testing", feature = "fuzzing"))]
             TransportPeerManager::Memory(pm) => self.start_peer_manager(pm, executor),
             TransportPeerManager::Tcp(pm) => self.start_peer_manager(pm, executor),
+            TransportPeerManager::Ws(pm) => self.start_peer_manager(pm, executor),
         }
     }
 
@@ -419,6 +541,51 @@ impl PeerManagerBuilder {
             .add_connection_event_listener()
     }
 
+    /// Returns a handle to the protocol access control lists of this network.
+    /// Rules can be added through the handle at any time, including after the
+    /// PeerManager has been started.
+    pub fn protocol_acls(&self) -> ProtocolAcls {
+        self.peer_manager_context
+            .as_ref()
+            .expect("Cannot access protocol ACLs once PeerManager has been built")
+            .protocol_acls
+            .clone()
+    }
+
+    /// Replaces the protocol access control lists of this network, e.g., to
+    /// share a single set of ACLs across networks.
+    pub fn set_protocol_acls(&mut self, protocol_acls: ProtocolAcls) {
+        self.peer_manager_context().protocol_acls = protocol_acls;
+    }
+
+    /// Returns a handle to the per-protocol message size limits of this network.
+    /// Limits can be updated through the handle at any time, including after the
+    /// PeerManager has been started.
+    pub fn protocol_message_size_limits(&self) -> ProtocolMessageSizeLimits {
+        self.peer_manager_context
+            .as_ref()
+            .expect("Cannot access protocol message size limits once PeerManager has been built")
+            .protocol_message_size_limits
+            .clone()
+    }
+
+    /// Replaces the per-protocol message size limits of this network, e.g., to
+    /// share a single set of limits across networks.
+    pub fn set_protocol_message_size_limits(&mut self, limits: ProtocolMessageSizeLimits) {
+        self.peer_manager_context().protocol_message_size_limits = limits;
+    }
+
+    /// Establishes the outbound connections of this network through the given
+    /// SOCKS5 proxy
+    pub fn set_socks5_proxy(&mut self, socks5_proxy: Socks5Proxy) {
+        self.transport_context().socks5_proxy = Some(socks5_proxy);
+    }
+
+    /// Sends TCP keepalive probes on the connections of this network
+    pub fn set_tcp_keepalive(&mut self, tcp_keepalive: TcpKeepaliveCfg) {
+        self.transport_context().tcp_keepalive = Some(tcp_keepalive);
+    }
+
     pub fn get_tcp_buffers_cfg(&self) -> TCPBufferCfg {
         self.peer_manager_context
             .as_ref()
@@ -440,6 +607,28 @@ impl PeerManagerBuilder {
 
         // Create the context and return the request senders
         let pm_context = self.peer_manager_context();
+
+        // Batch the small outbound messages of the client's protocols, if enabled
+        if let Some(max_delay) = config.message_batching_max_delay {
+            for protocol in config
+                .direct_send_protocols_and_preferences
+                .iter()
+                .chain(&config.rpc_protocols_and_preferences)
+            {
+                pm_context.message_batching.insert(*protocol, max_delay);
+            }
+        }
+
+        // Queue (and write) the outbound messages of the client's protocols apart
+        // from the bulk messages of other protocols, if isolated
+        if config.isolated {
+            pm_context.isolated_protocols.extend(
+                config
+                    .direct_send_protocols_and_preferences
+                    .iter()
+                    .chain(&config.rpc_protocols_and_preferences),
+            );
+        }
         (
             PeerManagerRequestSender::new(pm_context.pm_reqs_tx.clone()),
             ConnectionRequestSender::new(pm_context.connection_reqs_tx.clone()),
@@ -460,8 +649,13 @@ impl PeerManagerBuilder {
         self.transport_context()
             .add_protocols(&config.rpc_protocols_and_preferences);
 
-        // Create the context and register the protocols
-        let (network_notifs_tx, network_notifs_rx) = config.inbound_queue_config.build();
+        // Create the context and register the protocols. If fair queuing is
+        // enabled, the inbound queue is shared fairly among the peers (rather
+        // than among each peer's protocols).
+        let (network_notifs_tx, network_notifs_rx) = config
+            .inbound_queue_config
+            .build_grouped(|(peer_id, _): &(PeerId, ProtocolId)| *peer_id);
+        let time_service = self.time_service.clone();
         let pm_context = self.peer_manager_context();
         for protocol in config
             .direct_send_protocols_and_preferences
@@ -470,6 +664,61 @@ impl PeerManagerBuilder {
         {
             pm_context.add_upstream_handler(*protocol, network_notifs_tx.clone());
         }
+
+        // Deduplicate the inbound direct-send messages of the service, if enabled
+        if let Some(window) = config.inbound_dedup_window {
+            let deduplicator = Arc::new(MessageDeduplicator::new(window, time_service.clone()));
+            for protocol in &config.direct_send_protocols_and_preferences {
+                pm_context
+                    .inbound_deduplicators
+                    .insert(*protocol, deduplicator.clone());
+            }
+        }
+
+        // Drop the replayed direct-send messages of the service, if enabled
+        if let Some(window) = config.replay_protection_window {
+            for protocol in &config.direct_send_protocols_and_preferences {
+                pm_context.replay_protection.insert(*protocol, window);
+            }
+        }
+
+        // Rate limit the inbound messages of each of the service's protocols, if enabled
+        if let Some(rate_limit) = config.inbound_rate_limit {
+            for protocol in config
+                .direct_send_protocols_and_preferences
+                .iter()
+                .chain(&config.rpc_protocols_and_preferences)
+            {
+                pm_context.inbound_protocol_rate_limiters.insert(
+                    *protocol,
+                    Arc::new(InboundRateLimiter::new(
+                        *protocol,
+                        rate_limit,
+                        time_service.clone(),
+                    )),
+                );
+            }
+        }
+
+        // Cache the responses of each of the service's rpc protocols, if enabled
+        if let Some(response_cache) = config.rpc_response_cache {
+            for protocol in &config.rpc_protocols_and_preferences {
+                pm_context.rpc_response_caches.insert(
+                    *protocol,
+                    Arc::new(RpcResponseCache::new(response_cache, time_service.clone())),
+                );
+            }
+        }
+
+        // Shed the inbound rpcs of each of the service's rpc protocols beyond the
+        // concurrency limit, if enabled
+        if let Some(max_concurrent_rpcs) = config.max_concurrent_inbound_rpcs {
+            for protocol in &config.rpc_protocols_and_preferences {
+                pm_context
+                    .inbound_rpc_concurrency_limits
+                    .insert(*protocol, max_concurrent_rpcs);
+            }
+        }
         let connection_notifs_rx = pm_context.add_connection_event_listener();
 
         (network_notifs_rx, connection_notifs_rx)
@@ -22,9 +22,15 @@ pub enum PeerManagerError {
     #[error("Shutting down Peer")]
     ShuttingDownPeer,
 
+    #[error("Draining all connections")]
+    Draining,
+
     #[error("Not connected with Peer {0}")]
     NotConnected(PeerId),
 
+    #[error("Peer {0} is banned")]
+    Banned(PeerId),
+
     #[error("Already connected at {0}")]
     AlreadyConnected(NetworkAddress),
 
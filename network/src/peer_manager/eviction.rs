@@ -0,0 +1,117 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Connection eviction once a connection limit is reached.
+//!
+//! Instead of rejecting every new connection from a public peer once the network is
+//! full, the `PeerManager` can make room for it by evicting one of the connected
+//! public peers, as selected by the configured [`EvictionPolicy`]. This keeps popular
+//! public fullnodes from being held by stale or misbehaving peers.
+
+use crate::constants::MIN_IDLE_TIME_FOR_EVICTION_MS;
+use aptos_config::config::EvictionPolicy;
+use aptos_types::PeerId;
+use std::{cmp::Ordering, time::Duration};
+
+/// A connected public peer that may be evicted to make room for a new one
+#[derive(Clone, Debug)]
+pub struct EvictionCandidate {
+    pub peer_id: PeerId,
+    pub score: f64,
+    /// The time since the last message sent to or received from the peer
+    pub idle_time: Duration,
+}
+
+/// Selects the peer to evict to make room for a new peer with the given score,
+/// or `None` if the policy doesn't allow evicting any of the candidates.
+pub fn select_eviction(
+    policy: EvictionPolicy,
+    new_peer_score: f64,
+    candidates: impl IntoIterator<Item = EvictionCandidate>,
+) -> Option<PeerId> {
+    let candidates = candidates.into_iter();
+    let selected = match policy {
+        EvictionPolicy::Reject => None,
+        EvictionPolicy::LowestScore => candidates
+            .filter(|candidate| candidate.score < new_peer_score)
+            .min_by(|a, b| {
+                a.score
+                    .partial_cmp(&b.score)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| b.idle_time.cmp(&a.idle_time))
+            }),
+        EvictionPolicy::LongestIdle => candidates
+            .filter(|candidate| {
+                candidate.idle_time >= Duration::from_millis(MIN_IDLE_TIME_FOR_EVICTION_MS)
+            })
+            .max_by_key(|candidate| candidate.idle_time),
+    };
+    selected.map(|candidate| candidate.peer_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(score: f64, idle_secs: u64) -> EvictionCandidate {
+        EvictionCandidate {
+            peer_id: PeerId::random(),
+            score,
+            idle_time: Duration::from_secs(idle_secs),
+        }
+    }
+
+    #[test]
+    fn reject_never_evicts() {
+        let candidates = vec![candidate(0.0, 3600)];
+        assert_eq!(
+            select_eviction(EvictionPolicy::Reject, 100.0, candidates),
+            None
+        );
+    }
+
+    #[test]
+    fn lowest_score_evicts_worse_peers_only() {
+        let low = candidate(10.0, 0);
+        let low_idle = candidate(10.0, 10);
+        let high = candidate(90.0, 3600);
+        let candidates = vec![high.clone(), low, low_idle.clone()];
+
+        // Ties are broken by idle time
+        assert_eq!(
+            select_eviction(EvictionPolicy::LowestScore, 50.0, candidates.clone()),
+            Some(low_idle.peer_id)
+        );
+
+        // Peers aren't evicted for a peer with a lower score
+        assert_eq!(
+            select_eviction(EvictionPolicy::LowestScore, 10.0, candidates),
+            None
+        );
+        assert_eq!(
+            select_eviction(EvictionPolicy::LowestScore, 100.0, vec![high.clone()]),
+            Some(high.peer_id)
+        );
+    }
+
+    #[test]
+    fn longest_idle_evicts_idle_peers_only() {
+        let min_idle_secs = MIN_IDLE_TIME_FOR_EVICTION_MS / 1000;
+        let active = candidate(0.0, min_idle_secs - 1);
+        let idle = candidate(100.0, min_idle_secs);
+        let idlest = candidate(100.0, min_idle_secs + 1);
+
+        assert_eq!(
+            select_eviction(EvictionPolicy::LongestIdle, 0.0, vec![
+                active.clone(),
+                idlest.clone(),
+                idle
+            ]),
+            Some(idlest.peer_id)
+        );
+        assert_eq!(
+            select_eviction(EvictionPolicy::LongestIdle, 0.0, vec![active]),
+            None
+        );
+    }
+}
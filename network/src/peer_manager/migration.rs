@@ -0,0 +1,162 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Connection migration on local address changes.
+//!
+//! When the local IP address of a node changes (e.g., after a cloud failover or when a
+//! mobile node switches networks), its existing connections are bound to an address that
+//! is no longer usable. Instead of waiting for them to time out, the `PeerManager`
+//! migrates them: it closes the connections, re-dials the peers it dialed (peers that
+//! dialed us are expected to reconnect) and queues the outbound messages to migrating
+//! peers until their new connections are established. Applications don't see migrating
+//! peers as lost; the peers are marked as [`PeerState::Migrating`] instead, and a
+//! [`PeerEvent::ConnectionMigrated`] event is sent once the new connection is up.
+//!
+//! [`PeerState::Migrating`]: crate::application::types::PeerState::Migrating
+//! [`PeerEvent::ConnectionMigrated`]: crate::application::types::PeerEvent::ConnectionMigrated
+
+use crate::{peer::PeerRequest, transport::ConnectionMetadata, ProtocolId};
+use aptos_types::network_address::{NetworkAddress, Protocol};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+/// A documentation-only address (RFC 5737), used to find the local IPv4 address
+const IPV4_PROBE_ADDR: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+/// A documentation-only address (RFC 3849), used to find the local IPv6 address
+const IPV6_PROBE_ADDR: Ipv6Addr = Ipv6Addr::new(0x2001, 0xDB8, 0, 0, 0, 0, 0, 1);
+/// The (discard) port of the probed addresses
+const PROBE_PORT: u16 = 9;
+
+/// The local addresses used to reach remote peers, one per IP family
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LocalAddrs {
+    pub ipv4: Option<IpAddr>,
+    pub ipv6: Option<IpAddr>,
+}
+
+impl LocalAddrs {
+    /// Finds the current local addresses using the routing table of the host
+    pub fn probe() -> Self {
+        Self {
+            ipv4: probe_local_addr((IPV4_PROBE_ADDR, PROBE_PORT).into()),
+            ipv6: probe_local_addr((IPV6_PROBE_ADDR, PROBE_PORT).into()),
+        }
+    }
+
+    /// Updates the local addresses with the probed ones, returning the replaced
+    /// addresses. Addresses that couldn't be probed (e.g., while the interface is
+    /// down) are kept, and the first probed address of a family isn't a change.
+    pub fn update(&mut self, probed: LocalAddrs) -> Vec<IpAddr> {
+        let mut replaced = vec![];
+        for (current, probed) in [(&mut self.ipv4, probed.ipv4), (&mut self.ipv6, probed.ipv6)] {
+            if let Some(probed) = probed {
+                if let Some(previous) = current.replace(probed) {
+                    if previous != probed {
+                        replaced.push(previous);
+                    }
+                }
+            }
+        }
+        replaced
+    }
+
+    /// Returns the local address of the same family as the given address
+    pub fn of_family(&self, addr: &IpAddr) -> Option<IpAddr> {
+        match addr {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        }
+    }
+}
+
+/// Returns the local address the host would use to reach the given address.
+/// Connecting a UDP socket only selects a route, no packets are sent.
+fn probe_local_addr(remote_addr: SocketAddr) -> Option<IpAddr> {
+    let bind_addr: SocketAddr = match remote_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).ok()?;
+    socket.connect(remote_addr).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Returns the given address with its IP replaced, if it starts with one
+pub fn replace_ip(addr: &NetworkAddress, ip: IpAddr) -> Option<NetworkAddress> {
+    let mut protos = addr.as_slice().to_vec();
+    protos[0] = match (protos.first()?, ip) {
+        (Protocol::Ip4(_), IpAddr::V4(ip)) => Protocol::Ip4(ip),
+        (Protocol::Ip6(_), IpAddr::V6(ip)) => Protocol::Ip6(ip),
+        _ => return None,
+    };
+    NetworkAddress::from_protocols(protos).ok()
+}
+
+/// A peer whose connection is being migrated
+pub struct Migration {
+    /// The connection being replaced
+    pub connection: ConnectionMetadata,
+    /// Outbound requests to deliver once the new connection is established
+    pub queued_requests: Vec<(ProtocolId, PeerRequest)>,
+    /// The time after which the peer is considered lost
+    pub deadline: Instant,
+}
+
+impl Migration {
+    pub fn new(connection: ConnectionMetadata, deadline: Instant) -> Self {
+        Self {
+            connection,
+            queued_requests: vec![],
+            deadline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_addrs_update() {
+        let ip_a: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip_b: IpAddr = "10.0.0.2".parse().unwrap();
+        let ip_c: IpAddr = "fd00::1".parse().unwrap();
+        let mut local_addrs = LocalAddrs::default();
+
+        // The first probed addresses aren't changes
+        let probed = LocalAddrs {
+            ipv4: Some(ip_a),
+            ipv6: Some(ip_c),
+        };
+        assert!(local_addrs.update(probed).is_empty());
+        assert!(local_addrs.update(probed).is_empty());
+
+        // Addresses that can't be probed are kept
+        assert!(local_addrs.update(LocalAddrs::default()).is_empty());
+        assert_eq!(local_addrs, probed);
+
+        // Changed addresses are replaced
+        let probed = LocalAddrs {
+            ipv4: Some(ip_b),
+            ipv6: Some(ip_c),
+        };
+        assert_eq!(local_addrs.update(probed), vec![ip_a]);
+        assert_eq!(local_addrs.of_family(&ip_a), Some(ip_b));
+    }
+
+    #[test]
+    fn replace_ip_in_addr() {
+        let addr: NetworkAddress = "/ip4/10.0.0.1/tcp/6180".parse().unwrap();
+        assert_eq!(
+            replace_ip(&addr, "10.0.0.2".parse().unwrap()),
+            Some("/ip4/10.0.0.2/tcp/6180".parse().unwrap())
+        );
+        assert_eq!(replace_ip(&addr, "::1".parse().unwrap()), None);
+        assert_eq!(
+            replace_ip(&NetworkAddress::mock(), "10.0.0.2".parse().unwrap()),
+            None
+        );
+    }
+}
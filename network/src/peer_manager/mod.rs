@@ -12,17 +12,21 @@
 //!  * An actor responsible for dialing and listening for new connections.
 use crate::{
     constants,
-    counters::{self},
+    counters::{self, FAILED_LABEL, SUCCEEDED_LABEL},
     logging::*,
-    peer::{Peer, PeerNotification, PeerRequest},
+    peer::{batching::MessageBatching, DisconnectReason, Peer, PeerNotification, PeerRequest},
     transport::{
         Connection, ConnectionId, ConnectionMetadata, TSocket as TransportTSocket,
         TRANSPORT_TIMEOUT,
     },
     ProtocolId,
 };
-use aptos_channels::{self, aptos_channel, message_queues::QueueStyle};
-use aptos_config::network_id::NetworkContext;
+use aptos_channels::{
+    self,
+    aptos_channel::{self, ElementStatus},
+    message_queues::QueueStyle,
+};
+use aptos_config::network_id::{NetworkContext, PeerNetworkId};
 use aptos_logger::prelude::*;
 use aptos_netcore::transport::{ConnectionOrigin, Transport};
 use aptos_rate_limiter::rate_limit::TokenBucketRateLimiter;
@@ -31,41 +35,73 @@ use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use futures::{
     channel::oneshot,
+    future::join_all,
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     sink::SinkExt,
     stream::StreamExt,
 };
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     marker::PhantomData,
     net::{IpAddr, Ipv4Addr},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, sync::broadcast::error::RecvError};
 
+pub mod acl;
+mod bandwidth;
 pub mod builder;
 pub mod conn_notifs_channel;
 mod error;
+mod eviction;
+mod migration;
+mod outbound_queue;
 mod senders;
+mod size_limits;
 #[cfg(test)]
 mod tests;
 mod transport;
 mod types;
 
-pub use self::error::PeerManagerError;
+pub use self::{
+    acl::ProtocolAcls,
+    bandwidth::BandwidthLimiters,
+    error::PeerManagerError,
+    outbound_queue::{OutboundQueue, OutboundQueueDepth},
+    size_limits::ProtocolMessageSizeLimits,
+};
 use crate::{
-    application::storage::PeerMetadataStorage,
-    peer_manager::transport::{TransportHandler, TransportRequest},
-    protocols::network::SerializedRequest,
+    application::{
+        dedup::MessageDeduplicator,
+        rate_limit::{InboundRateLimitDecision, InboundRateLimiter},
+        response_cache::RpcResponseCache,
+        storage::PeerMetadataStorage,
+        types::{PeerEvent, PeerState},
+    },
+    peer_manager::{
+        eviction::{select_eviction, EvictionCandidate},
+        migration::{replace_ip, LocalAddrs, Migration},
+        transport::{TransportHandler, TransportRequest},
+    },
+    protocols::{
+        direct_send::{acks::InboundAckedMessage, replay::ReplayProtection},
+        network::SerializedRequest,
+        rpc::InboundRpcConcurrencyLimits,
+    },
 };
-use aptos_config::config::{PeerRole, PeerSet};
-use aptos_infallible::RwLock;
+use aptos_config::config::{EvictionPolicy, PeerRole, PeerSet};
+use aptos_infallible::{Mutex, RwLock};
 pub use senders::*;
 pub use types::*;
 
 pub type IpAddrTokenBucketLimiter = TokenBucketRateLimiter<IpAddr>;
 
+/// The key under which drain requests are queued to the peer actors. The
+/// protocol is unused, so the requests are never dropped behind the
+/// application requests.
+const DRAIN_REQUEST_KEY: ProtocolId = ProtocolId::DiscoveryDirectSend;
+
 /// Responsible for handling and maintaining connections to other Peers
 pub struct PeerManager<TTransport, TSocket>
 where
@@ -99,6 +135,36 @@ where
     /// of messages across (PeerId, ProtocolId).
     upstream_handlers:
         HashMap<ProtocolId, aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>>,
+    /// Access control lists checked before delivering messages to the upstream handlers.
+    protocol_acls: ProtocolAcls,
+    /// Maximum message sizes of the protocols, checked for both outbound and
+    /// inbound messages.
+    protocol_message_size_limits: ProtocolMessageSizeLimits,
+    /// Deduplicators of the inbound direct-send messages, for the protocols that
+    /// drop duplicate messages before delivering them to the upstream handlers.
+    inbound_deduplicators: HashMap<ProtocolId, Arc<MessageDeduplicator>>,
+    /// Per-peer rate limiters of the inbound messages, for the protocols that
+    /// limit the messages received from each peer
+    inbound_protocol_rate_limiters: HashMap<ProtocolId, Arc<InboundRateLimiter>>,
+    /// Caches of the responses to the inbound rpc requests, for the protocols
+    /// that serve identical requests without re-invoking the upstream handlers
+    rpc_response_caches: HashMap<ProtocolId, Arc<RpcResponseCache>>,
+    /// Limits on the inbound rpcs processed concurrently, for the protocols that
+    /// shed the requests beyond them. Shared by the peers' actors.
+    inbound_rpc_concurrency_limits: InboundRpcConcurrencyLimits,
+    /// The nonces of the outbound direct-send messages, and the replay windows
+    /// of the protocols that drop replayed messages. Shared by the peers' actors.
+    replay_protection: ReplayProtection,
+    /// The protocols whose small outbound messages are batched by the writers
+    /// of the peers' connections
+    message_batching: MessageBatching,
+    /// The protocols whose outbound messages are queued, and written, apart
+    /// from the (bulk) messages of other protocols
+    isolated_protocols: Arc<HashSet<ProtocolId>>,
+    /// Channel used to put the peers that repeatedly exceeded the inbound rate
+    /// limits in the penalty box (until the given time), and to disconnect them
+    penalized_peers_tx: aptos_channel::Sender<PeerId, (PeerId, Instant)>,
+    penalized_peers_rx: aptos_channel::Receiver<PeerId, (PeerId, Instant)>,
     /// Channels to send NewPeer/LostPeer notifications to.
     connection_event_handlers: Vec<conn_notifs_channel::Sender>,
     /// Channel used to send Dial requests to the ConnectionHandler actor
@@ -112,6 +178,9 @@ where
     /// A map of outstanding disconnect requests.
     outstanding_disconnect_requests:
         HashMap<ConnectionId, oneshot::Sender<Result<(), PeerManagerError>>>,
+    /// Whether all connections are being drained, in which case new
+    /// connections are rejected (and new dials refused)
+    draining: bool,
     /// Pin the transport type corresponding to this PeerManager instance
     phantom_transport: PhantomData<TTransport>,
     /// Maximum concurrent network requests to any peer.
@@ -122,12 +191,32 @@ where
     max_frame_size: usize,
     /// Max network message size
     max_message_size: usize,
+    /// Max size of messages fragmented for peers that support fragmentation,
+    /// which also caps the memory to reassemble the messages of each peer
+    max_reassembly_size: usize,
     /// Inbound connection limit separate of outbound connections
     inbound_connection_limit: usize,
+    /// Outbound connection limit, if any
+    outbound_connection_limit: Option<usize>,
+    /// How to make room for new public peers once a connection limit is reached
+    eviction_policy: EvictionPolicy,
+    /// The time of the last message sent to or received from each connected peer
+    last_activity: HashMap<PeerId, Arc<Mutex<Instant>>>,
+    /// The outbound queue of the active connection with each peer
+    outbound_queues: HashMap<PeerId, Arc<OutboundQueue>>,
+    /// The outbound queue of the isolated protocols of the active connection
+    /// with each peer
+    isolated_outbound_queues: HashMap<PeerId, Arc<OutboundQueue>>,
     /// Keyed storage of all inbound rate limiters
     inbound_rate_limiters: IpAddrTokenBucketLimiter,
     /// Keyed storage of all outbound rate limiters
     outbound_rate_limiters: IpAddrTokenBucketLimiter,
+    /// Per-peer and per-network bandwidth caps
+    bandwidth_limiters: BandwidthLimiters,
+    /// Peers whose connections are being migrated after a local address change
+    migrating_peers: HashMap<PeerId, Migration>,
+    /// The last known local addresses, used to detect address changes
+    local_addrs: LocalAddrs,
 }
 
 impl<TTransport, TSocket> PeerManager<TTransport, TSocket>
@@ -151,14 +240,27 @@ where
             ProtocolId,
             aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>,
         >,
+        protocol_acls: ProtocolAcls,
+        protocol_message_size_limits: ProtocolMessageSizeLimits,
+        inbound_deduplicators: HashMap<ProtocolId, Arc<MessageDeduplicator>>,
+        inbound_protocol_rate_limiters: HashMap<ProtocolId, Arc<InboundRateLimiter>>,
+        rpc_response_caches: HashMap<ProtocolId, Arc<RpcResponseCache>>,
+        inbound_rpc_concurrency_limits: InboundRpcConcurrencyLimits,
+        replay_protection: ReplayProtection,
+        message_batching: MessageBatching,
+        isolated_protocols: Arc<HashSet<ProtocolId>>,
         connection_event_handlers: Vec<conn_notifs_channel::Sender>,
         channel_size: usize,
         max_concurrent_network_reqs: usize,
         max_frame_size: usize,
         max_message_size: usize,
+        max_reassembly_size: usize,
         inbound_connection_limit: usize,
+        outbound_connection_limit: Option<usize>,
+        eviction_policy: EvictionPolicy,
         inbound_rate_limiters: IpAddrTokenBucketLimiter,
         outbound_rate_limiters: IpAddrTokenBucketLimiter,
+        bandwidth_limiters: BandwidthLimiters,
     ) -> Self {
         let (transport_notifs_tx, transport_notifs_rx) = aptos_channels::new(
             channel_size,
@@ -178,6 +280,8 @@ where
             transport_reqs_rx,
             transport_notifs_tx_clone,
         );
+        let (penalized_peers_tx, penalized_peers_rx) =
+            aptos_channel::new(QueueStyle::KLAST, 1, None);
 
         Self {
             network_context,
@@ -194,16 +298,37 @@ where
             transport_notifs_tx,
             transport_notifs_rx,
             outstanding_disconnect_requests: HashMap::new(),
+            draining: false,
             phantom_transport: PhantomData,
             upstream_handlers,
+            protocol_acls,
+            protocol_message_size_limits,
+            inbound_deduplicators,
+            inbound_protocol_rate_limiters,
+            rpc_response_caches,
+            inbound_rpc_concurrency_limits,
+            replay_protection,
+            message_batching,
+            isolated_protocols,
+            penalized_peers_tx,
+            penalized_peers_rx,
             connection_event_handlers,
             max_concurrent_network_reqs,
             channel_size,
             max_frame_size,
             max_message_size,
+            max_reassembly_size,
             inbound_connection_limit,
+            outbound_connection_limit,
+            eviction_policy,
+            last_activity: HashMap::new(),
+            outbound_queues: HashMap::new(),
+            isolated_outbound_queues: HashMap::new(),
             inbound_rate_limiters,
             outbound_rate_limiters,
+            bandwidth_limiters,
+            migrating_peers: HashMap::new(),
+            local_addrs: LocalAddrs::default(),
         }
     }
 
@@ -256,6 +381,22 @@ where
             "Start listening for incoming connections on {}", self.listen_addr
         );
         self.start_connection_listener();
+        let local_addr_ticker = self.time_service.interval(Duration::from_millis(
+            constants::LOCAL_ADDR_CHECK_INTERVAL_MS,
+        ));
+        tokio::pin!(local_addr_ticker);
+        // The storage events, used to disconnect the peers banned by applications
+        let peer_events = futures::stream::unfold(
+            self.peer_metadata_storage.subscribe(),
+            |mut peer_events| async move {
+                match peer_events.recv().await {
+                    Err(RecvError::Closed) => None,
+                    result => Some((result, peer_events)),
+                }
+            },
+        )
+        .fuse();
+        tokio::pin!(peer_events);
         loop {
             ::futures::select! {
                 connection_event = self.transport_notifs_rx.select_next_some() => {
@@ -267,6 +408,15 @@ where
                 request = self.requests_rx.select_next_some() => {
                     self.handle_outbound_request(request).await;
                 }
+                _ = local_addr_ticker.select_next_some() => {
+                    self.check_local_addrs().await;
+                }
+                (peer_id, until) = self.penalized_peers_rx.select_next_some() => {
+                    self.penalize_peer(peer_id, until);
+                }
+                peer_event = peer_events.select_next_some() => {
+                    self.handle_peer_event(peer_event);
+                }
                 complete => {
                     break;
                 }
@@ -290,6 +440,56 @@ where
         self.sample_connected_peers();
         match event {
             TransportNotification::NewConnection(mut conn) => {
+                // Reject the connections (in both directions) of the peers in
+                // the penalty box, until their penalty expires
+                let peer_network_id = PeerNetworkId::new(
+                    self.network_context.network_id(),
+                    conn.metadata.remote_peer_id,
+                );
+                if self
+                    .peer_metadata_storage
+                    .is_peer_penalized(&peer_network_id, self.time_service.now())
+                {
+                    info!(
+                        NetworkSchema::new(&self.network_context)
+                            .connection_metadata_with_address(&conn.metadata),
+                        "{} Connection rejected, as the peer is in the penalty box: {}",
+                        self.network_context,
+                        conn.metadata
+                    );
+                    counters::connections_rejected(&self.network_context, conn.metadata.origin)
+                        .inc();
+                    self.disconnect(conn);
+                    return;
+                }
+                if let Some(ban) = self.peer_metadata_storage.get_peer_ban(&peer_network_id) {
+                    info!(
+                        NetworkSchema::new(&self.network_context)
+                            .connection_metadata_with_address(&conn.metadata),
+                        "{} Connection rejected, as the peer is banned ({}): {}",
+                        self.network_context,
+                        ban.reason,
+                        conn.metadata
+                    );
+                    counters::connections_rejected(&self.network_context, conn.metadata.origin)
+                        .inc();
+                    self.disconnect(conn);
+                    return;
+                }
+                if self.draining {
+                    info!(
+                        NetworkSchema::new(&self.network_context)
+                            .connection_metadata_with_address(&conn.metadata),
+                        "{} Connection rejected, as all connections are being drained: {}",
+                        self.network_context,
+                        conn.metadata
+                    );
+                    counters::connections_rejected(&self.network_context, conn.metadata.origin)
+                        .inc();
+                    self.disconnect(conn);
+                    return;
+                }
+
                 match conn.metadata.origin {
                     ConnectionOrigin::Outbound => {
                         // TODO: This is right now a hack around having to feed trusted peers deeper in the outbound path.  Inbound ones are assigned at Noise handshake time.
@@ -308,6 +508,25 @@ where
                                 conn.metadata
                             )
                         }
+
+                        // Outbound connections were requested, so they're never rejected,
+                        // but they may make room for themselves by evicting public peers.
+                        if let Some(limit) = self.outbound_connection_limit {
+                            let outbound_conns = self
+                                .active_peers
+                                .values()
+                                .filter(|(metadata, _)| {
+                                    metadata.origin == ConnectionOrigin::Outbound
+                                })
+                                .count();
+                            if !self
+                                .active_peers
+                                .contains_key(&conn.metadata.remote_peer_id)
+                                && outbound_conns + 1 > limit
+                            {
+                                self.evict_for(&conn.metadata);
+                            }
+                        }
                     },
                     ConnectionOrigin::Inbound => {
                         // Everything below here is meant for unknown peers only, role comes from
@@ -328,13 +547,15 @@ where
                                 })
                                 .count();
 
-                            // Reject excessive inbound connections made by unknown peers
+                            // Reject excessive inbound connections made by unknown peers, unless
+                            // the eviction policy makes room for them.
                             // We control outbound connections with Connectivity manager before we even send them
                             // and we must allow connections that already exist to pass through tie breaking.
                             if !self
                                 .active_peers
                                 .contains_key(&conn.metadata.remote_peer_id)
                                 && unknown_inbound_conns + 1 > self.inbound_connection_limit
+                                && !self.evict_for(&conn.metadata)
                             {
                                 info!(
                                     NetworkSchema::new(&self.network_context)
@@ -389,6 +610,11 @@ where
                         )
                     }
                 }
+                if !self.active_peers.contains_key(&peer_id) {
+                    self.last_activity.remove(&peer_id);
+                    self.outbound_queues.remove(&peer_id);
+                    self.isolated_outbound_queues.remove(&peer_id);
+                }
                 self.update_connected_peers_metrics();
 
                 // If the connection was explicitly closed by an upstream client, send an ACK.
@@ -415,8 +641,10 @@ where
                     .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
 
                 // Notify upstream if there's still no active connection. This might be redundant,
-                // but does not affect correctness.
-                if !self.active_peers.contains_key(&peer_id) {
+                // but does not affect correctness. Migrating peers aren't lost (yet).
+                if !self.active_peers.contains_key(&peer_id)
+                    && !self.migrating_peers.contains_key(&peer_id)
+                {
                     let notif = ConnectionNotification::LostPeer(
                         lost_conn_metadata,
                         self.network_context,
@@ -429,6 +657,35 @@ where
                 self.inbound_rate_limiters.try_garbage_collect_key(&ip_addr);
                 self.outbound_rate_limiters
                     .try_garbage_collect_key(&ip_addr);
+                self.bandwidth_limiters.try_garbage_collect_peer(&peer_id);
+            },
+            TransportNotification::Draining(conn_metadata) => {
+                // The remote peer is draining the connection, so no new requests
+                // are routed to it. Its actor closes the connection once the
+                // queued and pending requests completed.
+                let peer_id = conn_metadata.remote_peer_id;
+                if let Entry::Occupied(entry) = self.active_peers.entry(peer_id) {
+                    if entry.get().0.connection_id == conn_metadata.connection_id {
+                        entry.remove();
+                        self.peer_metadata_storage
+                            .remove_connection(self.network_context.network_id(), &conn_metadata);
+                        self.update_connected_peers_metrics();
+                    }
+                }
+            },
+            TransportNotification::MetadataUpdated(conn_metadata) => {
+                // Only the metadata of the active connection is kept up to date
+                if let Some((active_metadata, _)) =
+                    self.active_peers.get_mut(&conn_metadata.remote_peer_id)
+                {
+                    if active_metadata.connection_id == conn_metadata.connection_id {
+                        *active_metadata = conn_metadata.clone();
+                        self.peer_metadata_storage.update_connection_metadata(
+                            self.network_context.network_id(),
+                            conn_metadata,
+                        );
+                    }
+                }
             },
         }
     }
@@ -445,7 +702,18 @@ where
         match request {
             ConnectionRequest::DialPeer(requested_peer_id, addr, response_tx) => {
                 // Only dial peers which we aren't already connected with
-                if let Some((curr_connection, _)) = self.active_peers.get(&requested_peer_id) {
+                if self.draining {
+                    let _ = response_tx.send(Err(PeerManagerError::Draining));
+                } else if self
+                    .peer_metadata_storage
+                    .is_peer_banned(&PeerNetworkId::new(
+                        self.network_context.network_id(),
+                        requested_peer_id,
+                    ))
+                {
+                    let _ = response_tx.send(Err(PeerManagerError::Banned(requested_peer_id)));
+                } else if let Some((curr_connection, _)) = self.active_peers.get(&requested_peer_id)
+                {
                     let error = PeerManagerError::AlreadyConnected(curr_connection.addr.clone());
                     debug!(
                         NetworkSchema::new(&self.network_context)
@@ -484,6 +752,20 @@ where
                     // Add to outstanding disconnect requests.
                     self.outstanding_disconnect_requests
                         .insert(connection_id, resp_tx);
+                } else if let Some(migration) = self.migrating_peers.remove(&peer_id) {
+                    // The old connection is already closed, so the migration is
+                    // abandoned and the peer is lost right away
+                    self.peer_metadata_storage.remove_connection(
+                        self.network_context.network_id(),
+                        &migration.connection,
+                    );
+                    let notif = ConnectionNotification::LostPeer(
+                        migration.connection,
+                        self.network_context,
+                        DisconnectReason::Requested,
+                    );
+                    self.send_conn_notification(peer_id, notif);
+                    let _ = resp_tx.send(Ok(()));
                 } else {
                     info!(
                         NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
@@ -503,9 +785,75 @@ where
                     }
                 }
             },
+            ConnectionRequest::DrainPeer(peer_id, reason, drain_timeout, resp_tx) => {
+                match self.drain_peer(peer_id, reason, drain_timeout) {
+                    // The request is answered once the connection is closed
+                    Some(connection_id) => {
+                        self.outstanding_disconnect_requests
+                            .insert(connection_id, resp_tx);
+                    },
+                    None => {
+                        let _ = resp_tx.send(Err(PeerManagerError::NotConnected(peer_id)));
+                    },
+                }
+            },
+            ConnectionRequest::DrainAll(reason, drain_timeout, resp_tx) => {
+                info!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Draining all connections: {}", self.network_context, reason
+                );
+                self.draining = true;
+                let peer_ids: Vec<_> = self.active_peers.keys().copied().collect();
+                let mut closed_rxs = vec![];
+                for peer_id in peer_ids {
+                    if let Some(connection_id) =
+                        self.drain_peer(peer_id, reason.clone(), drain_timeout)
+                    {
+                        let (closed_tx, closed_rx) = oneshot::channel();
+                        self.outstanding_disconnect_requests
+                            .insert(connection_id, closed_tx);
+                        closed_rxs.push(closed_rx);
+                    }
+                }
+                // The request is answered once all connections are closed
+                self.executor.spawn(async move {
+                    join_all(closed_rxs).await;
+                    let _ = resp_tx.send(Ok(()));
+                });
+            },
         }
     }
 
+    /// Starts draining the active connection with the peer, and returns its id.
+    /// No new requests are routed to the peer, and its actor closes the
+    /// connection once the queued and pending requests completed (or the drain
+    /// timeout elapsed).
+    fn drain_peer(
+        &mut self,
+        peer_id: PeerId,
+        reason: String,
+        drain_timeout: Duration,
+    ) -> Option<ConnectionId> {
+        let (conn_metadata, sender) = self.active_peers.remove(&peer_id)?;
+        self.peer_metadata_storage
+            .remove_connection(self.network_context.network_id(), &conn_metadata);
+        if let Err(err) = sender.push(DRAIN_REQUEST_KEY, PeerRequest::Drain(reason, drain_timeout))
+        {
+            warn!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata(&conn_metadata),
+                error = %err,
+                "{} Failed to drain the connection to peer {}, closing it: {}",
+                self.network_context,
+                peer_id.short_str(),
+                err
+            );
+        }
+        // Dropping the sender closes the connection, once the queued requests
+        // (including the drain request) are handled
+        Some(conn_metadata.connection_id)
+    }
+
     /// Sends an outbound request for `RPC` or `DirectSend` to the peer
     async fn handle_outbound_request(&mut self, request: PeerManagerRequest) {
         trace!(
@@ -523,9 +871,82 @@ where
             PeerManagerRequest::SendRpc(peer_id, req) => {
                 (peer_id, req.protocol_id(), PeerRequest::SendRpc(req))
             },
+            PeerManagerRequest::SendStreamingRpc(peer_id, req) => (
+                peer_id,
+                req.protocol_id(),
+                PeerRequest::SendStreamingRpc(req),
+            ),
+            PeerManagerRequest::SendAckedDirectSend(peer_id, msg) => (
+                peer_id,
+                msg.message.protocol_id(),
+                PeerRequest::SendAckedDirectSend(msg),
+            ),
         };
 
+        // Oversized messages would be dropped by the remote peer anyway. Dropping
+        // an rpc request also drops its response channel, which fails the rpc.
+        let message_size = peer_request.payload_len().unwrap_or_default();
+        if !self
+            .protocol_message_size_limits
+            .is_allowed(protocol_id, message_size)
+        {
+            counters::oversized_messages(
+                &self.network_context,
+                protocol_id,
+                counters::OUTBOUND_LABEL,
+            )
+            .inc();
+            sample!(
+                SampleRate::Duration(Duration::from_secs(10)),
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                    protocol_id = %protocol_id,
+                    "{} Dropping message of {} bytes to peer {}, it exceeds the size limit of protocol {}",
+                    self.network_context,
+                    message_size,
+                    peer_id.short_str(),
+                    protocol_id,
+                )
+            );
+            return;
+        }
+
         if let Some((conn_metadata, sender)) = self.active_peers.get_mut(&peer_id) {
+            if let Some(last_activity) = self.last_activity.get(&peer_id) {
+                *last_activity.lock() = self.time_service.now();
+            }
+            // Messages to peers that can't keep up are dropped explicitly, before
+            // the peer's channel silently drops them. The messages of isolated
+            // protocols are accounted for (and dropped) separately, so that they
+            // aren't dropped because of a backlog of bulk messages.
+            let outbound_queues = if self.isolated_protocols.contains(&protocol_id) {
+                &self.isolated_outbound_queues
+            } else {
+                &self.outbound_queues
+            };
+            if let Some(outbound_queue) = outbound_queues.get(&peer_id) {
+                if outbound_queue.is_full() {
+                    counters::outbound_queue_dropped_messages(&self.network_context, protocol_id)
+                        .inc();
+                    sample!(
+                        SampleRate::Duration(Duration::from_secs(10)),
+                        warn!(
+                            NetworkSchema::new(&self.network_context)
+                                .connection_metadata(conn_metadata),
+                            protocol_id = %protocol_id,
+                            "{} Dropping message to peer {}, its outbound queue is full",
+                            self.network_context,
+                            peer_id.short_str()
+                        )
+                    );
+                    return;
+                }
+                outbound_queue.enqueue(&peer_request);
+            }
+            self.peer_metadata_storage.record_outbound_message(
+                &PeerNetworkId::new(self.network_context.network_id(), peer_id),
+                message_size,
+            );
             if let Err(err) = sender.push(protocol_id, peer_request) {
                 info!(
                     NetworkSchema::new(&self.network_context).connection_metadata(conn_metadata),
@@ -535,6 +956,19 @@ where
                     self.network_context, err
                 );
             }
+        } else if let Some(migration) = self.migrating_peers.get_mut(&peer_id) {
+            // Deliver the message once the new connection is established
+            if migration.queued_requests.len() < self.channel_size {
+                migration.queued_requests.push((protocol_id, peer_request));
+            } else {
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                    protocol_id = %protocol_id,
+                    "{} Dropping message to migrating peer {}, too many messages are queued",
+                    self.network_context,
+                    peer_id.short_str()
+                );
+            }
         } else {
             warn!(
                 NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
@@ -546,6 +980,122 @@ where
         }
     }
 
+    /// Checks whether the local addresses changed, in which case the listener
+    /// is rebound (if needed) and all connections are migrated
+    async fn check_local_addrs(&mut self) {
+        self.expire_migrations();
+
+        // Networks without IP addresses (e.g., in-memory ones) aren't affected
+        let listen_ip = match self.listen_addr.find_ip_addr() {
+            Some(listen_ip) => listen_ip,
+            None => return,
+        };
+        let replaced_addrs = self.local_addrs.update(LocalAddrs::probe());
+        if replaced_addrs.is_empty() {
+            return;
+        }
+        info!(
+            NetworkSchema::new(&self.network_context),
+            "{} Local addresses changed from {:?} to {:?}",
+            self.network_context,
+            replaced_addrs,
+            self.local_addrs
+        );
+
+        // A listener bound to a replaced address can't accept connections anymore
+        if replaced_addrs.contains(&listen_ip) {
+            if let Some(listen_addr) = self
+                .local_addrs
+                .of_family(&listen_ip)
+                .and_then(|local_ip| replace_ip(&self.listen_addr, local_ip))
+            {
+                let request = TransportRequest::RebindListener(listen_addr.clone());
+                self.transport_reqs_tx.send(request).await.unwrap();
+                self.listen_addr = listen_addr;
+            }
+        }
+
+        self.migrate_connections().await;
+    }
+
+    /// Closes all connections and re-establishes them, without notifying
+    /// upstream handlers that the peers are lost. Peers that dialed us are
+    /// expected to dial us again.
+    async fn migrate_connections(&mut self) {
+        let deadline = self.time_service.now()
+            + Duration::from_millis(constants::CONNECTION_MIGRATION_TIMEOUT_MS);
+        let active_peers: Vec<_> = self.active_peers.drain().collect();
+        for (peer_id, (conn_metadata, sender)) in active_peers {
+            info!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata_with_address(&conn_metadata),
+                "{} Migrating connection {}", self.network_context, conn_metadata
+            );
+            // This triggers a disconnect.
+            drop(sender);
+            let peer_network_id = PeerNetworkId::new(self.network_context.network_id(), peer_id);
+            let _ = self
+                .peer_metadata_storage
+                .update_peer_state(peer_network_id, PeerState::Migrating);
+
+            if conn_metadata.origin == ConnectionOrigin::Outbound {
+                let (response_tx, response_rx) = oneshot::channel();
+                let request =
+                    TransportRequest::DialPeer(peer_id, conn_metadata.addr.clone(), response_tx);
+                self.transport_reqs_tx.send(request).await.unwrap();
+
+                let network_context = self.network_context;
+                self.executor.spawn(async move {
+                    if let Ok(Err(err)) = response_rx.await {
+                        info!(
+                            NetworkSchema::new(&network_context).remote_peer(&peer_id),
+                            error = %err,
+                            "{} Failed to re-dial migrating peer {}: {}",
+                            network_context,
+                            peer_id.short_str(),
+                            err
+                        );
+                    }
+                });
+            }
+            self.migrating_peers
+                .insert(peer_id, Migration::new(conn_metadata, deadline));
+        }
+        self.update_connected_peers_metrics();
+    }
+
+    /// Gives up on the migrations that didn't complete in time, and notifies
+    /// upstream handlers that the peers are lost
+    fn expire_migrations(&mut self) {
+        let now = self.time_service.now();
+        let expired_peers: Vec<_> = self
+            .migrating_peers
+            .iter()
+            .filter(|(_, migration)| migration.deadline <= now)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in expired_peers {
+            if let Some(migration) = self.migrating_peers.remove(&peer_id) {
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                    "{} Connection migration for peer {} timed out, dropping {} queued messages",
+                    self.network_context,
+                    peer_id.short_str(),
+                    migration.queued_requests.len()
+                );
+                counters::connection_migrations(&self.network_context, FAILED_LABEL).inc();
+                self.peer_metadata_storage
+                    .remove_connection(self.network_context.network_id(), &migration.connection);
+                let notif = ConnectionNotification::LostPeer(
+                    migration.connection,
+                    self.network_context,
+                    DisconnectReason::ConnectionLost,
+                );
+                self.send_conn_notification(peer_id, notif);
+            }
+        }
+    }
+
     fn start_connection_listener(&mut self) {
         let transport_handler = self
             .transport_handler
@@ -578,6 +1128,131 @@ where
         }
     }
 
+    /// Tries to make room for the given new connection by evicting a public peer
+    /// connected with the same origin, as selected by the eviction policy. Returns
+    /// true iff a peer was evicted.
+    fn evict_for(&mut self, new_conn_metadata: &ConnectionMetadata) -> bool {
+        let network_id = self.network_context.network_id();
+        let now = self.time_service.now();
+        let candidates: Vec<_> = {
+            let trusted_peers = self.trusted_peers.read();
+            self.active_peers
+                .iter()
+                .filter(|(peer_id, (metadata, _))| {
+                    metadata.origin == new_conn_metadata.origin
+                        && trusted_peers
+                            .get(peer_id)
+                            .map_or(true, |peer| peer.role == PeerRole::Unknown)
+                })
+                .map(|(peer_id, _)| EvictionCandidate {
+                    peer_id: *peer_id,
+                    score: self
+                        .peer_metadata_storage
+                        .get_peer_score(&PeerNetworkId::new(network_id, *peer_id)),
+                    idle_time: self
+                        .last_activity
+                        .get(peer_id)
+                        .map_or(Duration::ZERO, |last_activity| {
+                            now.saturating_duration_since(*last_activity.lock())
+                        }),
+                })
+                .collect()
+        };
+        let new_peer_score = self
+            .peer_metadata_storage
+            .get_peer_score(&PeerNetworkId::new(
+                network_id,
+                new_conn_metadata.remote_peer_id,
+            ));
+        let evicted_peer_id =
+            match select_eviction(self.eviction_policy, new_peer_score, candidates) {
+                Some(peer_id) => peer_id,
+                None => return false,
+            };
+
+        if let Some((conn_metadata, sender)) = self.active_peers.remove(&evicted_peer_id) {
+            info!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata_with_address(&conn_metadata),
+                "{} Evicting peer {} ({:?} policy) to make room for: {}",
+                self.network_context,
+                evicted_peer_id.short_str(),
+                self.eviction_policy,
+                new_conn_metadata
+            );
+            counters::connections_evicted(&self.network_context, conn_metadata.origin).inc();
+            self.peer_metadata_storage
+                .remove_connection(network_id, &conn_metadata);
+            // This triggers a disconnect.
+            drop(sender);
+        }
+        true
+    }
+
+    /// Puts a peer that repeatedly exceeded the inbound rate limits in the
+    /// penalty box until the given time, and disconnects it
+    fn penalize_peer(&mut self, peer_id: PeerId, until: Instant) {
+        let network_id = self.network_context.network_id();
+        self.peer_metadata_storage.penalize_peer(
+            PeerNetworkId::new(network_id, peer_id),
+            until,
+            self.time_service.now(),
+        );
+        counters::penalized_peers(&self.network_context).inc();
+
+        if let Some((conn_metadata, sender)) = self.active_peers.remove(&peer_id) {
+            warn!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata_with_address(&conn_metadata),
+                "{} Disconnecting peer {} that repeatedly exceeded the inbound rate limits",
+                self.network_context,
+                peer_id.short_str()
+            );
+            self.peer_metadata_storage
+                .remove_connection(network_id, &conn_metadata);
+            // This triggers a disconnect.
+            drop(sender);
+        }
+    }
+
+    /// Disconnects the peers of this network that were banned by applications.
+    /// If events were missed, all connected peers are checked.
+    fn handle_peer_event(&mut self, peer_event: Result<PeerEvent, RecvError>) {
+        let network_id = self.network_context.network_id();
+        let banned_peers: Vec<_> = match peer_event {
+            Ok(PeerEvent::PeerBanned(peer_network_id, _))
+                if peer_network_id.network_id() == network_id =>
+            {
+                vec![peer_network_id.peer_id()]
+            },
+            Err(RecvError::Lagged(_)) => self.active_peers.keys().copied().collect(),
+            _ => return,
+        };
+        for peer_id in banned_peers {
+            let ban = match self
+                .peer_metadata_storage
+                .get_peer_ban(&PeerNetworkId::new(network_id, peer_id))
+            {
+                Some(ban) => ban,
+                None => continue,
+            };
+            if let Some((conn_metadata, sender)) = self.active_peers.remove(&peer_id) {
+                warn!(
+                    NetworkSchema::new(&self.network_context)
+                        .connection_metadata_with_address(&conn_metadata),
+                    "{} Disconnecting banned peer {}: {}",
+                    self.network_context,
+                    peer_id.short_str(),
+                    ban.reason
+                );
+                self.peer_metadata_storage
+                    .remove_connection(network_id, &conn_metadata);
+                // This triggers a disconnect.
+                drop(sender);
+            }
+        }
+    }
+
     fn disconnect(&mut self, connection: Connection<TSocket>) {
         let network_context = self.network_context;
         let time_service = self.time_service.clone();
@@ -661,6 +1336,8 @@ where
             .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
         let inbound_rate_limiter = self.inbound_rate_limiters.bucket(ip_addr);
         let outbound_rate_limiter = self.outbound_rate_limiters.bucket(ip_addr);
+        let (inbound_bandwidth_buckets, outbound_bandwidth_buckets) =
+            self.bandwidth_limiters.buckets(peer_id);
 
         // TODO: Add label for peer.
         let (peer_reqs_tx, peer_reqs_rx) = aptos_channel::new(
@@ -674,6 +1351,10 @@ where
             self.channel_size,
             Some(&counters::PENDING_NETWORK_NOTIFICATIONS),
         );
+        // The queue is bounded by the capacity of each protocol's queue, so
+        // that the channel itself never drops messages.
+        let outbound_queue = Arc::new(OutboundQueue::new(self.channel_size));
+        let isolated_outbound_queue = Arc::new(OutboundQueue::new(self.channel_size));
 
         // Initialize a new Peer actor for this connection.
         let peer = Peer::new(
@@ -687,19 +1368,85 @@ where
             Duration::from_millis(constants::INBOUND_RPC_TIMEOUT_MS),
             constants::MAX_CONCURRENT_INBOUND_RPCS,
             constants::MAX_CONCURRENT_OUTBOUND_RPCS,
+            self.inbound_rpc_concurrency_limits.clone(),
             self.max_frame_size,
             self.max_message_size,
+            self.max_reassembly_size,
             Some(inbound_rate_limiter),
             Some(outbound_rate_limiter),
+            inbound_bandwidth_buckets,
+            outbound_bandwidth_buckets,
+            outbound_queue.clone(),
+            self.replay_protection.clone(),
+            self.message_batching.clone(),
+            self.isolated_protocols.clone(),
+            isolated_outbound_queue.clone(),
         );
         self.executor.spawn(peer.start());
 
         // Start background task to handle events (RPCs and DirectSend messages) received from
         // peer.
-        self.spawn_peer_network_events_handler(peer_id, peer_notifs_rx);
+        let last_activity = Arc::new(Mutex::new(self.time_service.now()));
+        self.last_activity.insert(peer_id, last_activity.clone());
+        self.spawn_peer_network_events_handler(
+            peer_id,
+            conn_meta.role,
+            peer_notifs_rx,
+            last_activity,
+        );
+
+        // If the connection replaces a migrating one, deliver the queued messages
+        // and don't notify connection event handlers, as the peer was never lost.
+        if let Some(migration) = self.migrating_peers.remove(&peer_id) {
+            for (protocol_id, peer_request) in migration.queued_requests {
+                if self.isolated_protocols.contains(&protocol_id) {
+                    isolated_outbound_queue.enqueue(&peer_request);
+                } else {
+                    outbound_queue.enqueue(&peer_request);
+                }
+                if let Err(err) = peer_reqs_tx.push(protocol_id, peer_request) {
+                    info!(
+                        NetworkSchema::new(&self.network_context).connection_metadata(&conn_meta),
+                        protocol_id = %protocol_id,
+                        error = ?err,
+                        "{} Failed to forward queued message to downstream actor. Error: {:?}",
+                        self.network_context, err
+                    );
+                }
+            }
+            info!(
+                NetworkSchema::new(&self.network_context)
+                    .connection_metadata_with_address(&conn_meta),
+                "{} Migrated connection {} to {}",
+                self.network_context,
+                migration.connection,
+                conn_meta
+            );
+            counters::connection_migrations(&self.network_context, SUCCEEDED_LABEL).inc();
+            self.active_peers
+                .insert(peer_id, (conn_meta.clone(), peer_reqs_tx));
+            self.outbound_queues.insert(peer_id, outbound_queue.clone());
+            self.isolated_outbound_queues
+                .insert(peer_id, isolated_outbound_queue.clone());
+            self.peer_metadata_storage.insert_outbound_queue(
+                PeerNetworkId::new(self.network_context.network_id(), peer_id),
+                outbound_queue,
+            );
+            self.peer_metadata_storage
+                .migrate_connection(self.network_context.network_id(), conn_meta);
+            return;
+        }
+
         // Save PeerRequest sender to `active_peers`.
         self.active_peers
             .insert(peer_id, (conn_meta.clone(), peer_reqs_tx));
+        self.outbound_queues.insert(peer_id, outbound_queue.clone());
+        self.isolated_outbound_queues
+            .insert(peer_id, isolated_outbound_queue);
+        self.peer_metadata_storage.insert_outbound_queue(
+            PeerNetworkId::new(self.network_context.network_id(), peer_id),
+            outbound_queue,
+        );
         self.peer_metadata_storage
             .insert_connection(self.network_context.network_id(), conn_meta.clone());
         // Send NewPeer notification to connection event handlers.
@@ -731,49 +1478,225 @@ where
     fn spawn_peer_network_events_handler(
         &self,
         peer_id: PeerId,
+        peer_role: PeerRole,
         network_events: aptos_channel::Receiver<ProtocolId, PeerNotification>,
+        last_activity: Arc<Mutex<Instant>>,
     ) {
         let mut upstream_handlers = self.upstream_handlers.clone();
+        let protocol_acls = self.protocol_acls.clone();
+        let protocol_message_size_limits = self.protocol_message_size_limits.clone();
+        let inbound_deduplicators = self.inbound_deduplicators.clone();
+        let inbound_protocol_rate_limiters = self.inbound_protocol_rate_limiters.clone();
+        let rpc_response_caches = self.rpc_response_caches.clone();
+        let penalized_peers_tx = self.penalized_peers_tx.clone();
+        let peer_metadata_storage = self.peer_metadata_storage.clone();
         let network_context = self.network_context;
+        let time_service = self.time_service.clone();
+        let peer = PeerNetworkId::new(network_context.network_id(), peer_id);
         self.executor.spawn(network_events.for_each_concurrent(
             self.max_concurrent_network_reqs,
             move |inbound_event| {
-                handle_inbound_request(
+                *last_activity.lock() = time_service.now();
+                peer_metadata_storage.record_inbound_message(&peer, inbound_event.payload_len());
+                if let Some(until) = handle_inbound_request(
                     network_context,
                     inbound_event,
                     peer_id,
+                    peer_role,
+                    &protocol_acls,
+                    &protocol_message_size_limits,
+                    &inbound_deduplicators,
+                    &inbound_protocol_rate_limiters,
+                    &rpc_response_caches,
                     &mut upstream_handlers,
-                );
+                ) {
+                    // The peer manager may have stopped, in which case the peer
+                    // is about to be disconnected anyway
+                    let _ = penalized_peers_tx.push(peer_id, (peer_id, until));
+                }
                 futures::future::ready(())
             },
         ));
     }
 }
 
-/// A task for consuming inbound network messages
+/// A task for consuming inbound network messages. Returns the time until which
+/// the peer should be put in the penalty box, if it repeatedly exceeded the
+/// inbound rate limits.
+#[allow(clippy::too_many_arguments)]
 fn handle_inbound_request(
     network_context: NetworkContext,
     inbound_event: PeerNotification,
     peer_id: PeerId,
+    peer_role: PeerRole,
+    protocol_acls: &ProtocolAcls,
+    protocol_message_size_limits: &ProtocolMessageSizeLimits,
+    inbound_deduplicators: &HashMap<ProtocolId, Arc<MessageDeduplicator>>,
+    inbound_protocol_rate_limiters: &HashMap<ProtocolId, Arc<InboundRateLimiter>>,
+    rpc_response_caches: &HashMap<ProtocolId, Arc<RpcResponseCache>>,
     upstream_handlers: &mut HashMap<
         ProtocolId,
         aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>,
     >,
-) {
-    let (protocol_id, notification) = match inbound_event {
-        PeerNotification::RecvMessage(msg) => (
-            msg.protocol_id(),
-            PeerManagerNotification::RecvMessage(peer_id, msg),
-        ),
+) -> Option<Instant> {
+    let mut digest = None;
+    // Reports the delivery of acked messages. Dropping it, e.g., when the message
+    // is rejected, reports the message as not delivered.
+    let mut delivered_tx = None;
+    let (protocol_id, num_bytes, notification) = match inbound_event {
+        PeerNotification::RecvMessage(msg) => {
+            if inbound_deduplicators.contains_key(&msg.protocol_id()) {
+                digest = Some(MessageDeduplicator::digest(&msg.mdata));
+            }
+            (
+                msg.protocol_id(),
+                msg.mdata.len(),
+                PeerManagerNotification::RecvMessage(peer_id, msg),
+            )
+        },
         PeerNotification::RecvRpc(req) => (
             req.protocol_id(),
+            req.data.len(),
             PeerManagerNotification::RecvRpc(peer_id, req),
         ),
+        PeerNotification::RecvStreamingRpc(req) => (
+            req.protocol_id(),
+            req.data.len(),
+            PeerManagerNotification::RecvStreamingRpc(peer_id, req),
+        ),
+        // Acked messages are handed to the application like any other message
+        PeerNotification::RecvAckedMessage(InboundAckedMessage {
+            message,
+            delivered_tx: tx,
+        }) => {
+            if inbound_deduplicators.contains_key(&message.protocol_id()) {
+                digest = Some(MessageDeduplicator::digest(&message.mdata));
+            }
+            delivered_tx = Some(tx);
+            (
+                message.protocol_id(),
+                message.mdata.len(),
+                PeerManagerNotification::RecvMessage(peer_id, message),
+            )
+        },
+    };
+
+    // Drop messages rejected by the protocol's ACL. Dropping an rpc request also
+    // drops its response channel, which fails the rpc for the remote peer.
+    if !protocol_acls.is_allowed(
+        protocol_id,
+        peer_id,
+        peer_role,
+        network_context.network_id(),
+    ) {
+        counters::acl_rejected_messages(&network_context, protocol_id).inc();
+        sample!(
+            SampleRate::Duration(Duration::from_secs(10)),
+            warn!(
+                NetworkSchema::new(&network_context).remote_peer(&peer_id),
+                protocol_id = protocol_id,
+                "{} Rejected message for protocol {} from peer {} (role: {}) by ACL",
+                network_context,
+                protocol_id,
+                peer_id.short_str(),
+                peer_role.as_str(),
+            )
+        );
+        return None;
+    }
+
+    // Drop messages exceeding the protocol's size limit
+    if !protocol_message_size_limits.is_allowed(protocol_id, num_bytes) {
+        counters::oversized_messages(&network_context, protocol_id, counters::INBOUND_LABEL).inc();
+        sample!(
+            SampleRate::Duration(Duration::from_secs(10)),
+            warn!(
+                NetworkSchema::new(&network_context).remote_peer(&peer_id),
+                protocol_id = protocol_id,
+                "{} Dropping message of {} bytes from peer {}, it exceeds the size limit of protocol {}",
+                network_context,
+                num_bytes,
+                peer_id.short_str(),
+                protocol_id,
+            )
+        );
+        return None;
+    }
+
+    // Drop messages beyond the peer's inbound rate limits, and put the peers
+    // that exceed them too often in the penalty box
+    let peer = PeerNetworkId::new(network_context.network_id(), peer_id);
+    if let Some(rate_limiter) = inbound_protocol_rate_limiters.get(&protocol_id) {
+        match rate_limiter.check(&peer, num_bytes) {
+            InboundRateLimitDecision::Allow => {},
+            InboundRateLimitDecision::Throttle => {
+                counters::inbound_rate_limited_messages(&network_context, protocol_id).inc();
+                return None;
+            },
+            InboundRateLimitDecision::Penalize(until) => {
+                counters::inbound_rate_limited_messages(&network_context, protocol_id).inc();
+                warn!(
+                    NetworkSchema::new(&network_context).remote_peer(&peer_id),
+                    protocol_id = protocol_id,
+                    "{} Peer {} repeatedly exceeded the inbound rate limits for protocol {}",
+                    network_context,
+                    peer_id.short_str(),
+                    protocol_id,
+                );
+                return Some(until);
+            },
+        }
+    }
+
+    // Drop direct-send messages identical to one recently received from the peer
+    if let (Some(deduplicator), Some(digest)) = (inbound_deduplicators.get(&protocol_id), digest) {
+        if deduplicator.check_and_record(peer, digest) {
+            counters::deduplicated_messages(
+                network_context.network_id(),
+                protocol_id,
+                counters::INBOUND_LABEL,
+            )
+            .inc();
+            // An identical message was already delivered to the application
+            if let Some(delivered_tx) = delivered_tx {
+                let _ = delivered_tx.send(true);
+            }
+            return None;
+        }
+    }
+
+    // Serve rpc requests identical to one recently answered from the cache
+    let notification = match (notification, rpc_response_caches.get(&protocol_id)) {
+        (PeerManagerNotification::RecvRpc(peer_id, request), Some(response_cache)) => {
+            match response_cache.handle_request(request) {
+                Some(request) => PeerManagerNotification::RecvRpc(peer_id, request),
+                None => {
+                    counters::rpc_response_cache_hits(&network_context, protocol_id).inc();
+                    return None;
+                },
+            }
+        },
+        (notification, _) => notification,
     };
 
     if let Some(handler) = upstream_handlers.get_mut(&protocol_id) {
-        // Send over aptos channel for fairness.
-        if let Err(err) = handler.push((peer_id, protocol_id), notification) {
+        // Send over aptos channel for fairness. Acked messages are only reported
+        // as delivered if they weren't dropped by the (full) channel.
+        let result = match delivered_tx {
+            Some(delivered_tx) => {
+                let (status_tx, mut status_rx) = oneshot::channel();
+                let result = handler.push_with_feedback(
+                    (peer_id, protocol_id),
+                    notification,
+                    Some(status_tx),
+                );
+                let dropped = matches!(status_rx.try_recv(), Ok(Some(ElementStatus::Dropped(_))));
+                let _ = delivered_tx.send(result.is_ok() && !dropped);
+                result
+            },
+            None => handler.push((peer_id, protocol_id), notification),
+        };
+        if let Err(err) = result {
             warn!(
                 NetworkSchema::new(&network_context),
                 error = ?err,
@@ -792,4 +1715,5 @@ fn handle_inbound_request(
             notification,
         );
     }
+    None
 }
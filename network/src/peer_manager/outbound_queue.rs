@@ -0,0 +1,178 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-connection accounting of the outbound requests queued for a peer.
+//!
+//! The [`PeerManager`] forwards the outbound requests of the applications to
+//! the [`Peer`] actor of each connection. When a peer (or its link) is slow,
+//! these requests pile up in the actor's queue. The [`OutboundQueue`] of a
+//! connection counts the messages (and payload bytes) that were handed to the
+//! actor, but not yet dequeued by it. It's exposed via the
+//! [`PeerMetadataStorage`], so that applications can apply backpressure (e.g.,
+//! by broadcasting less) instead of having their messages dropped.
+//!
+//! [`PeerManager`]: crate::peer_manager::PeerManager
+//! [`Peer`]: crate::peer::Peer
+//! [`PeerMetadataStorage`]: crate::application::storage::PeerMetadataStorage
+
+use crate::peer::PeerRequest;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use tokio::sync::Notify;
+
+/// A snapshot of the depth of an outbound queue
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct OutboundQueueDepth {
+    /// The number of queued messages
+    pub messages: usize,
+    /// The total payload size of the queued messages
+    pub bytes: usize,
+}
+
+/// The outbound requests queued for the [`Peer`](crate::peer::Peer) actor of a
+/// connection. Requests are dropped (rather than queued) once the queue is full.
+pub struct OutboundQueue {
+    capacity: usize,
+    messages: AtomicUsize,
+    bytes: AtomicUsize,
+    /// Set once the connection is closed, and no more messages are dequeued
+    closed: AtomicBool,
+    /// Notified whenever a message is dequeued from a full queue, or the
+    /// connection is closed
+    space_available: Notify,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            messages: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            space_available: Notify::new(),
+        }
+    }
+
+    /// Returns the current depth of the queue
+    pub fn depth(&self) -> OutboundQueueDepth {
+        OutboundQueueDepth {
+            messages: self.messages.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the maximum number of queued messages
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.messages.load(Ordering::Relaxed) >= self.capacity
+    }
+
+    /// Returns true once the connection is closed
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Waits until the queue has space for more messages, or the connection is
+    /// closed. Space isn't reserved for the caller, so concurrent senders may
+    /// still fill the queue.
+    pub async fn wait_for_capacity(&self) {
+        loop {
+            // Register for notifications before checking, to not miss a dequeue
+            let space_available = self.space_available.notified();
+            if !self.is_full() || self.is_closed() {
+                return;
+            }
+            space_available.await;
+        }
+    }
+
+    /// Accounts for a request handed to the peer actor. Only requests that send
+    /// messages are accounted for.
+    pub(crate) fn enqueue(&self, request: &PeerRequest) {
+        if let Some(bytes) = request.payload_len() {
+            self.messages.fetch_add(1, Ordering::Relaxed);
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Accounts for a request dequeued by the peer actor
+    pub(crate) fn dequeue(&self, request: &PeerRequest) {
+        if let Some(bytes) = request.payload_len() {
+            self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+            let queued_messages = self.messages.fetch_sub(1, Ordering::Relaxed);
+            if queued_messages >= self.capacity {
+                self.space_available.notify_waiters();
+            }
+        }
+    }
+
+    /// Marks the connection as closed, and wakes up all waiters
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.space_available.notify_waiters();
+    }
+}
+
+impl fmt::Debug for OutboundQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutboundQueue")
+            .field("capacity", &self.capacity)
+            .field("depth", &self.depth())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        protocols::{direct_send::Message, wire::messaging::v1::MessagePriority},
+        ProtocolId,
+    };
+    use futures::FutureExt;
+
+    fn send_direct_send(len: usize) -> PeerRequest {
+        PeerRequest::SendDirectSend(Message {
+            protocol_id: ProtocolId::MempoolDirectSend,
+            mdata: vec![0; len].into(),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn waiters_are_notified_once_the_queue_has_space() {
+        let queue = OutboundQueue::new(2);
+        queue.enqueue(&send_direct_send(10));
+        queue.enqueue(&send_direct_send(20));
+        queue.enqueue(&PeerRequest::Drain("restart".into(), Default::default()));
+        assert_eq!(queue.depth(), OutboundQueueDepth {
+            messages: 2,
+            bytes: 30
+        });
+        assert!(queue.is_full());
+
+        let mut wait_for_capacity = queue.wait_for_capacity().boxed();
+        assert!((&mut wait_for_capacity).now_or_never().is_none());
+        queue.dequeue(&send_direct_send(10));
+        wait_for_capacity.await;
+        assert_eq!(queue.depth(), OutboundQueueDepth {
+            messages: 1,
+            bytes: 20
+        });
+
+        // Waiters are also woken up once the connection is closed
+        queue.enqueue(&send_direct_send(10));
+        let mut wait_for_capacity = queue.wait_for_capacity().boxed();
+        assert!((&mut wait_for_capacity).now_or_never().is_none());
+        queue.close();
+        wait_for_capacity.await;
+        assert!(queue.is_closed());
+    }
+}
@@ -4,15 +4,22 @@
 use crate::{
     peer_manager::{types::PeerManagerRequest, ConnectionRequest, PeerManagerError},
     protocols::{
-        direct_send::Message,
-        rpc::{error::RpcError, OutboundRpcRequest},
+        direct_send::{acks::AckedMessage, Message},
+        rpc::{
+            error::RpcError,
+            streaming::{OutboundStreamingRpcRequest, DEFAULT_STREAMING_RPC_WINDOW},
+            OutboundRpcRequest,
+        },
+        wire::messaging::v1::{new_correlation_id, MessagePriority},
     },
     ProtocolId,
 };
 use aptos_channels::{self, aptos_channel};
+use aptos_logger::prelude::*;
+use aptos_short_hex_str::AsShortHexStr;
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use bytes::Bytes;
-use futures::channel::oneshot;
+use futures::channel::{mpsc, oneshot};
 use std::time::Duration;
 
 /// Convenience wrapper which makes it easy to issue communication requests and await the responses
@@ -46,13 +53,78 @@ impl PeerManagerRequestSender {
         protocol_id: ProtocolId,
         mdata: Bytes,
     ) -> Result<(), PeerManagerError> {
+        self.send_to_with_priority(peer_id, protocol_id, mdata, protocol_id.default_priority())
+    }
+
+    /// Send a fire-and-forget direct-send message to remote peer with the given priority.
+    /// See [`PeerManagerRequestSender::send_to`].
+    pub fn send_to_with_priority(
+        &self,
+        peer_id: PeerId,
+        protocol_id: ProtocolId,
+        mdata: Bytes,
+        priority: MessagePriority,
+    ) -> Result<(), PeerManagerError> {
+        let correlation_id = new_correlation_id();
+        trace!(
+            correlation_id = correlation_id,
+            "Enqueuing direct send message for protocol {} to peer {} with correlation_id {}",
+            protocol_id,
+            peer_id.short_str(),
+            correlation_id
+        );
         self.inner.push(
             (peer_id, protocol_id),
-            PeerManagerRequest::SendDirectSend(peer_id, Message { protocol_id, mdata }),
+            PeerManagerRequest::SendDirectSend(peer_id, Message {
+                protocol_id,
+                mdata,
+                priority,
+                correlation_id,
+            }),
         )?;
         Ok(())
     }
 
+    /// Send a direct-send message to remote peer, and wait for the peer to
+    /// acknowledge it.
+    ///
+    /// The function returns once the message was enqueued for the remote
+    /// application. An error is returned if the message was dropped by the
+    /// remote peer, wasn't acknowledged within the timeout, or the peer doesn't
+    /// support direct-send acks.
+    pub async fn send_to_with_ack(
+        &self,
+        peer_id: PeerId,
+        protocol_id: ProtocolId,
+        mdata: Bytes,
+        timeout: Duration,
+    ) -> Result<(), RpcError> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let correlation_id = new_correlation_id();
+        trace!(
+            correlation_id = correlation_id,
+            "Enqueuing acked direct send message for protocol {} to peer {} with correlation_id {}",
+            protocol_id,
+            peer_id.short_str(),
+            correlation_id
+        );
+        let message = AckedMessage {
+            message: Message {
+                protocol_id,
+                mdata,
+                priority: protocol_id.default_priority(),
+                correlation_id,
+            },
+            timeout,
+            ack_tx,
+        };
+        self.inner.push(
+            (peer_id, protocol_id),
+            PeerManagerRequest::SendAckedDirectSend(peer_id, message),
+        )?;
+        ack_rx.await?
+    }
+
     /// Send the _same_ message to many recipients using the direct-send protocol.
     ///
     /// This method is an optimization so that we can avoid serializing and
@@ -70,15 +142,31 @@ impl PeerManagerRequestSender {
         protocol_id: ProtocolId,
         mdata: Bytes,
     ) -> Result<(), PeerManagerError> {
-        let msg = Message { protocol_id, mdata };
+        let priority = protocol_id.default_priority();
         for recipient in recipients {
+            // Each copy of the message gets its own correlation id, so that it
+            // can be traced to a single recipient.
+            let correlation_id = new_correlation_id();
+            trace!(
+                correlation_id = correlation_id,
+                "Enqueuing direct send message for protocol {} to peer {} with correlation_id {}",
+                protocol_id,
+                recipient.short_str(),
+                correlation_id
+            );
+            let msg = Message {
+                protocol_id,
+                mdata: mdata.clone(),
+                priority,
+                correlation_id,
+            };
             // We return `Err` early here if the send fails. Since sending will
             // only fail if the queue is unexpectedly shutdown (i.e., receiver
             // dropped early), we know that we can't make further progress if
             // this send fails.
             self.inner.push(
                 (recipient, protocol_id),
-                PeerManagerRequest::SendDirectSend(recipient, msg.clone()),
+                PeerManagerRequest::SendDirectSend(recipient, msg),
             )?;
         }
         Ok(())
@@ -91,13 +179,43 @@ impl PeerManagerRequestSender {
         protocol_id: ProtocolId,
         req: Bytes,
         timeout: Duration,
+    ) -> Result<Bytes, RpcError> {
+        self.send_rpc_with_priority(
+            peer_id,
+            protocol_id,
+            req,
+            timeout,
+            protocol_id.default_priority(),
+        )
+        .await
+    }
+
+    /// Sends a unary RPC to a remote peer with the given priority and waits to either
+    /// receive a response or times out.
+    pub async fn send_rpc_with_priority(
+        &self,
+        peer_id: PeerId,
+        protocol_id: ProtocolId,
+        req: Bytes,
+        timeout: Duration,
+        priority: MessagePriority,
     ) -> Result<Bytes, RpcError> {
         let (res_tx, res_rx) = oneshot::channel();
+        let correlation_id = new_correlation_id();
+        trace!(
+            correlation_id = correlation_id,
+            "Enqueuing rpc request for protocol {} to peer {} with correlation_id {}",
+            protocol_id,
+            peer_id.short_str(),
+            correlation_id
+        );
         let request = OutboundRpcRequest {
             protocol_id,
             data: req,
             res_tx,
             timeout,
+            priority,
+            correlation_id,
         };
         self.inner.push(
             (peer_id, protocol_id),
@@ -105,6 +223,32 @@ impl PeerManagerRequestSender {
         )?;
         res_rx.await?
     }
+
+    /// Sends a streaming RPC to a remote peer. The returned receiver yields the
+    /// response chunks as they arrive, and is closed once the stream ends. An
+    /// error is yielded (and the stream ends) if no chunk arrives within
+    /// `chunk_timeout`.
+    pub fn send_streaming_rpc(
+        &self,
+        peer_id: PeerId,
+        protocol_id: ProtocolId,
+        req: Bytes,
+        chunk_timeout: Duration,
+    ) -> Result<mpsc::Receiver<Result<Bytes, RpcError>>, RpcError> {
+        let (res_tx, res_rx) = mpsc::channel(DEFAULT_STREAMING_RPC_WINDOW as usize);
+        let request = OutboundStreamingRpcRequest {
+            protocol_id,
+            data: req,
+            res_tx,
+            chunk_timeout,
+            window: DEFAULT_STREAMING_RPC_WINDOW,
+        };
+        self.inner.push(
+            (peer_id, protocol_id),
+            PeerManagerRequest::SendStreamingRpc(peer_id, request),
+        )?;
+        Ok(res_rx)
+    }
 }
 
 impl ConnectionRequestSender {
@@ -130,4 +274,37 @@ impl ConnectionRequestSender {
             .push(peer, ConnectionRequest::DisconnectPeer(peer, oneshot_tx))?;
         oneshot_rx.await?
     }
+
+    /// Gracefully disconnects from the peer: no new requests are sent to it,
+    /// and the connection is closed once the pending requests completed (or
+    /// the drain timeout elapsed). Returns once the connection is closed.
+    pub async fn disconnect_peer_gracefully(
+        &self,
+        peer: PeerId,
+        reason: String,
+        drain_timeout: Duration,
+    ) -> Result<(), PeerManagerError> {
+        let (oneshot_tx, oneshot_rx) = oneshot::channel();
+        self.inner.push(
+            peer,
+            ConnectionRequest::DrainPeer(peer, reason, drain_timeout, oneshot_tx),
+        )?;
+        oneshot_rx.await?
+    }
+
+    /// Gracefully disconnects from all peers, and rejects new connections.
+    /// Returns once all connections are closed.
+    pub async fn drain_all(
+        &self,
+        reason: String,
+        drain_timeout: Duration,
+    ) -> Result<(), PeerManagerError> {
+        let (oneshot_tx, oneshot_rx) = oneshot::channel();
+        // The request isn't specific to a peer, so it's queued under the zero id
+        self.inner.push(
+            PeerId::ZERO,
+            ConnectionRequest::DrainAll(reason, drain_timeout, oneshot_tx),
+        )?;
+        oneshot_rx.await?
+    }
 }
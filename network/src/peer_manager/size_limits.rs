@@ -0,0 +1,110 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-protocol message size limits.
+//!
+//! The `max_message_size` of a network bounds the messages of all protocols, and
+//! is enforced by the wire. Protocols can be limited further, e.g., so that a
+//! peer can't flood mempool with blocks-sized transactions. The limits are
+//! checked by the `PeerManager` for both outbound and inbound messages: oversized
+//! messages are dropped, which fails oversized RPCs.
+//!
+//! Unlike the wire limit, the limits of [`ProtocolMessageSizeLimits`] can be
+//! updated at runtime (e.g., on a node config reload, or by an application that
+//! follows an on-chain config), and updates apply to all subsequent messages.
+//! The same handle can be shared by several networks.
+
+use crate::ProtocolId;
+use aptos_infallible::RwLock;
+use aptos_logger::prelude::*;
+use std::{collections::HashMap, sync::Arc};
+
+/// The maximum message sizes of the protocols. Protocols without a limit are
+/// only bounded by the network's `max_message_size`.
+#[derive(Clone, Debug, Default)]
+pub struct ProtocolMessageSizeLimits {
+    limits: Arc<RwLock<HashMap<ProtocolId, usize>>>,
+}
+
+impl ProtocolMessageSizeLimits {
+    /// Returns the limits of the protocols, keyed by protocol name (as in the
+    /// node config), ignoring (and logging) the unknown protocols.
+    pub fn from_config(config_limits: &HashMap<String, usize>) -> Self {
+        let limits = Self::default();
+        limits.reload(config_limits);
+        limits
+    }
+
+    /// Sets the maximum message size of the protocol
+    pub fn set_limit(&self, protocol_id: ProtocolId, max_message_size: usize) {
+        self.limits.write().insert(protocol_id, max_message_size);
+    }
+
+    /// Removes the limit of the protocol, so that it's only bounded by the
+    /// network's `max_message_size`
+    pub fn clear_limit(&self, protocol_id: ProtocolId) {
+        self.limits.write().remove(&protocol_id);
+    }
+
+    /// Replaces the limits of all protocols with the given limits, keyed by
+    /// protocol name (as in the node config). Unknown protocols are ignored.
+    pub fn reload(&self, config_limits: &HashMap<String, usize>) {
+        let mut limits = HashMap::new();
+        for (protocol_name, max_message_size) in config_limits {
+            match ProtocolId::all()
+                .iter()
+                .find(|protocol_id| protocol_id.as_str() == protocol_name)
+            {
+                Some(protocol_id) => {
+                    limits.insert(*protocol_id, *max_message_size);
+                },
+                None => warn!(
+                    "Ignoring the message size limit of unknown protocol: {}",
+                    protocol_name
+                ),
+            }
+        }
+        *self.limits.write() = limits;
+    }
+
+    /// Returns the maximum message size of the protocol, if it's limited
+    pub fn limit(&self, protocol_id: ProtocolId) -> Option<usize> {
+        self.limits.read().get(&protocol_id).copied()
+    }
+
+    /// Returns true iff a message of the given size is within the protocol's limit
+    pub fn is_allowed(&self, protocol_id: ProtocolId, message_size: usize) -> bool {
+        self.limit(protocol_id)
+            .map_or(true, |max_message_size| message_size <= max_message_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits_can_be_updated_and_reloaded() {
+        let limits = ProtocolMessageSizeLimits::default();
+        assert!(limits.is_allowed(ProtocolId::ConsensusRpcBcs, usize::MAX));
+
+        limits.set_limit(ProtocolId::ConsensusRpcBcs, 1024);
+        assert!(limits.is_allowed(ProtocolId::ConsensusRpcBcs, 1024));
+        assert!(!limits.is_allowed(ProtocolId::ConsensusRpcBcs, 1025));
+        assert!(limits.is_allowed(ProtocolId::MempoolDirectSend, 1025));
+
+        // Reloading replaces all limits, ignoring unknown protocols
+        let config_limits = [
+            ("MempoolDirectSend".to_string(), 512),
+            ("NotAProtocol".to_string(), 1),
+        ]
+        .into_iter()
+        .collect();
+        limits.reload(&config_limits);
+        assert_eq!(limits.limit(ProtocolId::ConsensusRpcBcs), None);
+        assert_eq!(limits.limit(ProtocolId::MempoolDirectSend), Some(512));
+
+        limits.clear_limit(ProtocolId::MempoolDirectSend);
+        assert!(limits.is_allowed(ProtocolId::MempoolDirectSend, usize::MAX));
+    }
+}
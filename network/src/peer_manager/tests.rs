@@ -2,18 +2,32 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    application::storage::PeerMetadataStorage,
+    application::{
+        dedup::MessageDeduplicator,
+        rate_limit::{InboundRateLimitConfig, InboundRateLimiter, RateLimit},
+        response_cache::{RpcResponseCache, RpcResponseCacheConfig},
+        storage::PeerMetadataStorage,
+        types::{PeerEvent, PeerState},
+    },
     constants,
-    peer::DisconnectReason,
+    peer::{batching::MessageBatching, DisconnectReason, PeerNotification},
     peer_manager::{
-        conn_notifs_channel, error::PeerManagerError, ConnectionNotification, ConnectionRequest,
-        PeerManager, PeerManagerNotification, PeerManagerRequest, TransportNotification,
+        acl::{AclMatcher, AclRule},
+        conn_notifs_channel,
+        error::PeerManagerError,
+        handle_inbound_request, BandwidthLimiters, ConnectionNotification, ConnectionRequest,
+        PeerManager, PeerManagerNotification, PeerManagerRequest, ProtocolAcls,
+        ProtocolMessageSizeLimits, TransportNotification,
     },
-    protocols::wire::{
-        handshake::v1::{MessagingProtocolVersion, ProtocolIdSet},
-        messaging::v1::{
-            ErrorCode, MultiplexMessage, MultiplexMessageSink, MultiplexMessageStream,
-            NetworkMessage,
+    protocols::{
+        direct_send::{replay::ReplayProtection, Message},
+        rpc::{InboundRpcConcurrencyLimits, InboundRpcRequest},
+        wire::{
+            handshake::v1::{MessagingProtocolVersion, ProtocolIdSet},
+            messaging::v1::{
+                ErrorCode, MessagePriority, MultiplexMessage, MultiplexMessageSink,
+                MultiplexMessageStream, NetworkMessage,
+            },
         },
     },
     transport,
@@ -23,8 +37,8 @@ use crate::{
 use anyhow::anyhow;
 use aptos_channels::{aptos_channel, message_queues::QueueStyle};
 use aptos_config::{
-    config::{PeerRole, MAX_INBOUND_CONNECTIONS},
-    network_id::NetworkContext,
+    config::{EvictionPolicy, PeerRole, MAX_INBOUND_CONNECTIONS},
+    network_id::{NetworkContext, PeerNetworkId},
 };
 use aptos_infallible::RwLock;
 use aptos_memsocket::MemorySocket;
@@ -32,11 +46,15 @@ use aptos_netcore::transport::{
     boxed::BoxedTransport, memory::MemoryTransport, ConnectionOrigin, TransportExt,
 };
 use aptos_rate_limiter::rate_limit::TokenBucketRateLimiter;
-use aptos_time_service::TimeService;
+use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use bytes::Bytes;
-use futures::{channel::oneshot, io::AsyncWriteExt, stream::StreamExt};
-use std::{collections::HashMap, sync::Arc};
+use futures::{channel::oneshot, future::FutureExt, io::AsyncWriteExt, stream::StreamExt};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::runtime::Handle;
 use tokio_util::compat::{
     FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt,
@@ -61,6 +79,7 @@ pub fn build_test_transport(
                     MessagingProtocolVersion::V1,
                     ProtocolIdSet::mock(),
                     PeerRole::Unknown,
+                    None,
                 ),
             })
         })
@@ -110,14 +129,27 @@ fn build_test_peer_manager(
         peer_manager_request_rx,
         connection_reqs_rx,
         [(ProtocolId::mock(), hello_tx)].iter().cloned().collect(),
+        ProtocolAcls::default(),
+        ProtocolMessageSizeLimits::default(),
+        HashMap::new(),
+        HashMap::new(),
+        HashMap::new(),
+        InboundRpcConcurrencyLimits::default(),
+        ReplayProtection::default(),
+        MessageBatching::default(),
+        Arc::new(HashSet::new()),
         vec![conn_status_tx],
         constants::NETWORK_CHANNEL_SIZE,
         constants::MAX_CONCURRENT_NETWORK_REQS,
         constants::MAX_FRAME_SIZE,
         constants::MAX_MESSAGE_SIZE,
+        constants::MAX_REASSEMBLY_SIZE,
         MAX_INBOUND_CONNECTIONS,
+        None,
+        EvictionPolicy::Reject,
         TokenBucketRateLimiter::open("inbound"),
         TokenBucketRateLimiter::open("outbound"),
+        BandwidthLimiters::default(),
     );
 
     (
@@ -245,6 +277,7 @@ fn create_connection<TSocket: transport::TSocket>(
             MessagingProtocolVersion::V1,
             ProtocolIdSet::mock(),
             PeerRole::Unknown,
+            None,
         ),
     }
 }
@@ -570,6 +603,7 @@ fn peer_manager_simultaneous_dial_disconnect_event() {
                 MessagingProtocolVersion::V1,
                 ProtocolIdSet::mock(),
                 PeerRole::Unknown,
+                None,
             ),
             DisconnectReason::ConnectionLost,
         );
@@ -625,6 +659,7 @@ fn test_dial_disconnect() {
                 MessagingProtocolVersion::V1,
                 ProtocolIdSet::mock(),
                 PeerRole::Unknown,
+                None,
             ),
             DisconnectReason::Requested,
         );
@@ -643,3 +678,706 @@ fn test_dial_disconnect() {
 
     runtime.block_on(test);
 }
+
+#[test]
+fn test_connection_migration() {
+    ::aptos_logger::Logger::init_for_testing();
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+
+    let ids = ordered_peer_ids(2);
+    let (mut peer_manager, _request_tx, _connection_reqs_tx, _hello_rx, mut conn_status_rx) =
+        build_test_peer_manager(runtime.handle().clone(), ids[1]);
+
+    let test = async move {
+        let peer_network_id = PeerNetworkId::new(peer_manager.network_context.network_id(), ids[0]);
+        let (outbound, _inbound) = build_test_connection();
+        peer_manager.add_peer(create_connection(
+            outbound,
+            ids[0],
+            NetworkAddress::mock(),
+            ConnectionOrigin::Outbound,
+            ConnectionId::from(0),
+        ));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+
+        // Migrating the connection closes it, but the peer isn't lost
+        peer_manager.migrate_connections().await;
+        assert!(!peer_manager.active_peers.contains_key(&ids[0]));
+        assert_eq!(
+            peer_manager
+                .peer_metadata_storage
+                .read(peer_network_id)
+                .unwrap()
+                .status,
+            PeerState::Migrating
+        );
+        assert_peer_disconnected_event(
+            ids[0],
+            ConnectionOrigin::Outbound,
+            DisconnectReason::Requested,
+            &mut peer_manager,
+        )
+        .await;
+        assert!(conn_status_rx.next().now_or_never().is_none());
+
+        // Messages sent while migrating are queued
+        let mut events = peer_manager.peer_metadata_storage.subscribe();
+        let message = Message {
+            protocol_id: ProtocolId::mock(),
+            mdata: Bytes::from_static(b"migrated"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        };
+        peer_manager
+            .handle_outbound_request(PeerManagerRequest::SendDirectSend(ids[0], message))
+            .await;
+
+        // And delivered over the new connection
+        let (outbound, inbound) = build_test_connection();
+        peer_manager.add_peer(create_connection(
+            outbound,
+            ids[0],
+            NetworkAddress::mock(),
+            ConnectionOrigin::Outbound,
+            ConnectionId::from(1),
+        ));
+        let (read_half, _write_half) = tokio::io::split(inbound.compat());
+        let mut msg_rx =
+            MultiplexMessageStream::new(read_half.compat(), constants::MAX_FRAME_SIZE, None);
+        match msg_rx.next().await.unwrap().unwrap() {
+            MultiplexMessage::Message(NetworkMessage::DirectSendMsg(message)) => {
+                assert_eq!(message.raw_msg, b"migrated".to_vec());
+            },
+            message => panic!("Unexpected message: {:?}", message),
+        }
+
+        // Applications are notified of the migration instead of a new peer
+        assert!(conn_status_rx.next().now_or_never().is_none());
+        match events.recv().await.unwrap() {
+            PeerEvent::ConnectionMigrated(migrated_peer, connection) => {
+                assert_eq!(migrated_peer, peer_network_id);
+                assert_eq!(connection.connection_id, ConnectionId::from(1));
+            },
+            event => panic!("Unexpected peer event: {:?}", event),
+        }
+        assert!(peer_manager
+            .peer_metadata_storage
+            .read(peer_network_id)
+            .unwrap()
+            .is_connected());
+    };
+
+    runtime.block_on(test);
+}
+
+#[test]
+fn test_connection_migration_timeout() {
+    ::aptos_logger::Logger::init_for_testing();
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+
+    let ids = ordered_peer_ids(2);
+    let (mut peer_manager, _request_tx, _connection_reqs_tx, _hello_rx, mut conn_status_rx) =
+        build_test_peer_manager(runtime.handle().clone(), ids[1]);
+
+    let test = async move {
+        let peer_network_id = PeerNetworkId::new(peer_manager.network_context.network_id(), ids[0]);
+        let (outbound, _inbound) = build_test_connection();
+        peer_manager.add_peer(create_connection(
+            outbound,
+            ids[0],
+            NetworkAddress::mock(),
+            ConnectionOrigin::Inbound,
+            ConnectionId::from(0),
+        ));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+        peer_manager.migrate_connections().await;
+
+        // The peer isn't lost before the migration times out
+        peer_manager.expire_migrations();
+        assert!(conn_status_rx.next().now_or_never().is_none());
+
+        // But is once it does
+        peer_manager
+            .time_service
+            .clone()
+            .into_mock()
+            .advance(Duration::from_millis(
+                constants::CONNECTION_MIGRATION_TIMEOUT_MS,
+            ));
+        peer_manager.expire_migrations();
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(
+            conn_notif,
+            ConnectionNotification::LostPeer(_, _, DisconnectReason::ConnectionLost)
+        ));
+        assert!(peer_manager
+            .peer_metadata_storage
+            .read(peer_network_id)
+            .is_none());
+        assert!(peer_manager.migrating_peers.is_empty());
+    };
+
+    runtime.block_on(test);
+}
+
+#[test]
+fn test_inbound_connection_limit() {
+    ::aptos_logger::Logger::init_for_testing();
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+
+    let ids = ordered_peer_ids(3);
+    let (mut peer_manager, _request_tx, _connection_reqs_tx, _hello_rx, mut conn_status_rx) =
+        build_test_peer_manager(runtime.handle().clone(), ids[2]);
+    peer_manager.inbound_connection_limit = 1;
+
+    let test = async move {
+        let (inbound_a, _outbound_a) = build_test_connection();
+        peer_manager.handle_connection_event(TransportNotification::NewConnection(
+            create_connection(
+                inbound_a,
+                ids[0],
+                NetworkAddress::mock(),
+                ConnectionOrigin::Inbound,
+                ConnectionId::from(0),
+            ),
+        ));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+
+        // Without an eviction policy, excess connections are rejected
+        let (inbound_b, _outbound_b) = build_test_connection();
+        peer_manager.handle_connection_event(TransportNotification::NewConnection(
+            create_connection(
+                inbound_b,
+                ids[1],
+                NetworkAddress::mock(),
+                ConnectionOrigin::Inbound,
+                ConnectionId::from(1),
+            ),
+        ));
+        assert!(conn_status_rx.next().now_or_never().is_none());
+        assert!(peer_manager.active_peers.contains_key(&ids[0]));
+        assert!(!peer_manager.active_peers.contains_key(&ids[1]));
+    };
+
+    runtime.block_on(test);
+}
+
+#[test]
+fn test_inbound_connection_eviction() {
+    ::aptos_logger::Logger::init_for_testing();
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+
+    let ids = ordered_peer_ids(3);
+    let (mut peer_manager, _request_tx, _connection_reqs_tx, _hello_rx, mut conn_status_rx) =
+        build_test_peer_manager(runtime.handle().clone(), ids[2]);
+    peer_manager.inbound_connection_limit = 1;
+    peer_manager.eviction_policy = EvictionPolicy::LongestIdle;
+
+    let test = async move {
+        let (inbound_a, _outbound_a) = build_test_connection();
+        peer_manager.handle_connection_event(TransportNotification::NewConnection(
+            create_connection(
+                inbound_a,
+                ids[0],
+                NetworkAddress::mock(),
+                ConnectionOrigin::Inbound,
+                ConnectionId::from(0),
+            ),
+        ));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+
+        // Peers that have been idle long enough are evicted to make room for new ones
+        peer_manager
+            .time_service
+            .clone()
+            .into_mock()
+            .advance(Duration::from_millis(
+                constants::MIN_IDLE_TIME_FOR_EVICTION_MS,
+            ));
+        let (inbound_b, _outbound_b) = build_test_connection();
+        peer_manager.handle_connection_event(TransportNotification::NewConnection(
+            create_connection(
+                inbound_b,
+                ids[1],
+                NetworkAddress::mock(),
+                ConnectionOrigin::Inbound,
+                ConnectionId::from(1),
+            ),
+        ));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(
+            matches!(conn_notif, ConnectionNotification::NewPeer(metadata, _) if metadata.remote_peer_id == ids[1])
+        );
+        assert_peer_disconnected_event(
+            ids[0],
+            ConnectionOrigin::Inbound,
+            DisconnectReason::Requested,
+            &mut peer_manager,
+        )
+        .await;
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(
+            matches!(conn_notif, ConnectionNotification::LostPeer(metadata, _, _) if metadata.remote_peer_id == ids[0])
+        );
+        assert!(!peer_manager.active_peers.contains_key(&ids[0]));
+        assert!(peer_manager.active_peers.contains_key(&ids[1]));
+    };
+
+    runtime.block_on(test);
+}
+
+#[test]
+fn test_penalized_peer_disconnected_and_rejected() {
+    ::aptos_logger::Logger::init_for_testing();
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+
+    let ids = ordered_peer_ids(2);
+    let (mut peer_manager, _request_tx, _connection_reqs_tx, _hello_rx, mut conn_status_rx) =
+        build_test_peer_manager(runtime.handle().clone(), ids[1]);
+
+    let test = async move {
+        let new_connection = |socket, connection_id| {
+            TransportNotification::NewConnection(create_connection(
+                socket,
+                ids[0],
+                NetworkAddress::mock(),
+                ConnectionOrigin::Inbound,
+                ConnectionId::from(connection_id),
+            ))
+        };
+        let (inbound_a, _outbound_a) = build_test_connection();
+        peer_manager.handle_connection_event(new_connection(inbound_a, 0));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+
+        // Penalized peers are disconnected
+        let mock_time = peer_manager.time_service.clone().into_mock();
+        peer_manager.penalize_peer(ids[0], mock_time.now() + Duration::from_secs(60));
+        assert_peer_disconnected_event(
+            ids[0],
+            ConnectionOrigin::Inbound,
+            DisconnectReason::Requested,
+            &mut peer_manager,
+        )
+        .await;
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(
+            conn_notif,
+            ConnectionNotification::LostPeer(_, _, _)
+        ));
+
+        // And their connections are rejected until the penalty expires
+        let (inbound_b, _outbound_b) = build_test_connection();
+        peer_manager.handle_connection_event(new_connection(inbound_b, 1));
+        assert!(conn_status_rx.next().now_or_never().is_none());
+        assert!(!peer_manager.active_peers.contains_key(&ids[0]));
+
+        mock_time.advance(Duration::from_secs(60));
+        let (inbound_c, _outbound_c) = build_test_connection();
+        peer_manager.handle_connection_event(new_connection(inbound_c, 2));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+    };
+
+    runtime.block_on(test);
+}
+
+#[test]
+fn test_banned_peer_disconnected_and_rejected() {
+    ::aptos_logger::Logger::init_for_testing();
+    let runtime = ::tokio::runtime::Runtime::new().unwrap();
+
+    let ids = ordered_peer_ids(2);
+    let (mut peer_manager, _request_tx, _connection_reqs_tx, _hello_rx, mut conn_status_rx) =
+        build_test_peer_manager(runtime.handle().clone(), ids[1]);
+
+    let test = async move {
+        let new_connection = |socket, connection_id| {
+            TransportNotification::NewConnection(create_connection(
+                socket,
+                ids[0],
+                NetworkAddress::mock(),
+                ConnectionOrigin::Inbound,
+                ConnectionId::from(connection_id),
+            ))
+        };
+        let (inbound_a, _outbound_a) = build_test_connection();
+        peer_manager.handle_connection_event(new_connection(inbound_a, 0));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+
+        // Peers banned by applications are disconnected
+        let peer_network_id = PeerNetworkId::new(peer_manager.network_context.network_id(), ids[0]);
+        let mut peer_events = peer_manager.peer_metadata_storage.subscribe();
+        peer_manager.peer_metadata_storage.ban_peer(
+            peer_network_id,
+            Duration::from_secs(60),
+            "invalid consensus messages".into(),
+        );
+        let peer_event = peer_events.recv().await;
+        assert!(matches!(peer_event, Ok(PeerEvent::PeerBanned(_, _))));
+        peer_manager.handle_peer_event(peer_event);
+        assert_peer_disconnected_event(
+            ids[0],
+            ConnectionOrigin::Inbound,
+            DisconnectReason::Requested,
+            &mut peer_manager,
+        )
+        .await;
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(
+            conn_notif,
+            ConnectionNotification::LostPeer(_, _, _)
+        ));
+
+        // And their connections are rejected, and dials refused, while banned
+        let (inbound_b, _outbound_b) = build_test_connection();
+        peer_manager.handle_connection_event(new_connection(inbound_b, 1));
+        assert!(conn_status_rx.next().now_or_never().is_none());
+        assert!(!peer_manager.active_peers.contains_key(&ids[0]));
+
+        let (dial_tx, dial_rx) = oneshot::channel();
+        peer_manager
+            .handle_outbound_connection_request(ConnectionRequest::DialPeer(
+                ids[0],
+                NetworkAddress::mock(),
+                dial_tx,
+            ))
+            .await;
+        assert!(matches!(
+            dial_rx.await.unwrap(),
+            Err(PeerManagerError::Banned(_))
+        ));
+
+        // Until the ban is lifted
+        peer_manager
+            .peer_metadata_storage
+            .unban_peer(&peer_network_id);
+        let (inbound_c, _outbound_c) = build_test_connection();
+        peer_manager.handle_connection_event(new_connection(inbound_c, 2));
+        let conn_notif = conn_status_rx.next().await.unwrap();
+        assert!(matches!(conn_notif, ConnectionNotification::NewPeer(_, _)));
+    };
+
+    runtime.block_on(test);
+}
+
+#[test]
+fn test_inbound_messages_rejected_by_acl() {
+    let network_context = NetworkContext::mock();
+    let (upstream_tx, mut upstream_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
+    let mut upstream_handlers: HashMap<_, _> = [(ProtocolId::mock(), upstream_tx)]
+        .iter()
+        .cloned()
+        .collect();
+    let protocol_acls = ProtocolAcls::default();
+    protocol_acls.allow_roles(ProtocolId::mock(), &[PeerRole::Validator]);
+    let banned_validator = PeerId::random();
+    protocol_acls.add_rule(
+        ProtocolId::mock(),
+        AclRule::Deny(AclMatcher::Peer(banned_validator)),
+    );
+
+    let notification = || {
+        PeerNotification::RecvMessage(Message {
+            protocol_id: ProtocolId::mock(),
+            mdata: Bytes::from_static(b"hello"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        })
+    };
+
+    // Messages from peers that aren't allowed (or are denied) are dropped
+    for (peer_id, role) in [
+        (PeerId::random(), PeerRole::Unknown),
+        (banned_validator, PeerRole::Validator),
+    ] {
+        handle_inbound_request(
+            network_context,
+            notification(),
+            peer_id,
+            role,
+            &protocol_acls,
+            &ProtocolMessageSizeLimits::default(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &mut upstream_handlers,
+        );
+        assert!(upstream_rx.select_next_some().now_or_never().is_none());
+    }
+
+    // Messages from allowed peers are delivered
+    let validator = PeerId::random();
+    handle_inbound_request(
+        network_context,
+        notification(),
+        validator,
+        PeerRole::Validator,
+        &protocol_acls,
+        &ProtocolMessageSizeLimits::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+        &HashMap::new(),
+        &mut upstream_handlers,
+    );
+    match upstream_rx.select_next_some().now_or_never() {
+        Some(PeerManagerNotification::RecvMessage(peer_id, _)) => assert_eq!(peer_id, validator),
+        notification => panic!("Unexpected notification: {:?}", notification),
+    }
+}
+
+#[test]
+fn test_oversized_inbound_messages_dropped() {
+    let network_context = NetworkContext::mock();
+    let (upstream_tx, mut upstream_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
+    let mut upstream_handlers: HashMap<_, _> = [(ProtocolId::mock(), upstream_tx)]
+        .iter()
+        .cloned()
+        .collect();
+    let size_limits = ProtocolMessageSizeLimits::default();
+    size_limits.set_limit(ProtocolId::mock(), 4);
+
+    let notification = || {
+        PeerNotification::RecvMessage(Message {
+            protocol_id: ProtocolId::mock(),
+            mdata: Bytes::from_static(b"hello"),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        })
+    };
+    let mut handle_notification = |size_limits: &ProtocolMessageSizeLimits| {
+        handle_inbound_request(
+            network_context,
+            notification(),
+            PeerId::random(),
+            PeerRole::Unknown,
+            &ProtocolAcls::default(),
+            size_limits,
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &mut upstream_handlers,
+        );
+    };
+
+    // Messages exceeding the protocol's limit are dropped
+    handle_notification(&size_limits);
+    assert!(upstream_rx.select_next_some().now_or_never().is_none());
+
+    // Until the limit is raised
+    size_limits.set_limit(ProtocolId::mock(), 5);
+    handle_notification(&size_limits);
+    assert!(matches!(
+        upstream_rx.select_next_some().now_or_never(),
+        Some(PeerManagerNotification::RecvMessage(_, _))
+    ));
+}
+
+#[test]
+fn test_duplicate_inbound_messages_dropped() {
+    let network_context = NetworkContext::mock();
+    let (upstream_tx, mut upstream_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
+    let mut upstream_handlers: HashMap<_, _> = [(ProtocolId::mock(), upstream_tx)]
+        .iter()
+        .cloned()
+        .collect();
+    let inbound_deduplicators: HashMap<_, _> = [(
+        ProtocolId::mock(),
+        Arc::new(MessageDeduplicator::new(
+            Duration::from_secs(10),
+            TimeService::mock(),
+        )),
+    )]
+    .iter()
+    .cloned()
+    .collect();
+
+    let notification = |mdata: &'static [u8]| {
+        PeerNotification::RecvMessage(Message {
+            protocol_id: ProtocolId::mock(),
+            mdata: Bytes::from_static(mdata),
+            priority: MessagePriority::Normal,
+            correlation_id: 0,
+        })
+    };
+    let mut deliver = |peer_id: PeerId, mdata: &'static [u8]| {
+        handle_inbound_request(
+            network_context,
+            notification(mdata),
+            peer_id,
+            PeerRole::Unknown,
+            &ProtocolAcls::default(),
+            &ProtocolMessageSizeLimits::default(),
+            &inbound_deduplicators,
+            &HashMap::new(),
+            &HashMap::new(),
+            &mut upstream_handlers,
+        );
+    };
+
+    // Only the first of the identical messages from a peer is delivered
+    let peer_id = PeerId::random();
+    let other_peer_id = PeerId::random();
+    deliver(peer_id, b"hello");
+    deliver(peer_id, b"hello");
+    deliver(peer_id, b"world");
+    deliver(other_peer_id, b"hello");
+    let mut delivered = vec![];
+    while let Some(notification) = upstream_rx.select_next_some().now_or_never() {
+        match notification {
+            PeerManagerNotification::RecvMessage(peer_id, msg) => {
+                delivered.push((peer_id, msg.mdata))
+            },
+            notification => panic!("Unexpected notification: {:?}", notification),
+        }
+    }
+    assert_eq!(delivered, vec![
+        (peer_id, Bytes::from_static(b"hello")),
+        (peer_id, Bytes::from_static(b"world")),
+        (other_peer_id, Bytes::from_static(b"hello")),
+    ]);
+}
+
+#[test]
+fn test_inbound_messages_rate_limited() {
+    let network_context = NetworkContext::mock();
+    let (upstream_tx, mut upstream_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
+    let mut upstream_handlers: HashMap<_, _> = [(ProtocolId::mock(), upstream_tx)]
+        .iter()
+        .cloned()
+        .collect();
+    let time_service = TimeService::mock();
+    let inbound_protocol_rate_limiters: HashMap<_, _> = [(
+        ProtocolId::mock(),
+        Arc::new(InboundRateLimiter::new(
+            ProtocolId::mock(),
+            InboundRateLimitConfig {
+                messages: Some(RateLimit {
+                    bucket_size: 1,
+                    fill_rate: 1,
+                }),
+                bytes: None,
+                max_violations: 2,
+                violation_window: Duration::from_secs(10),
+                penalty_duration: Duration::from_secs(60),
+            },
+            time_service.clone(),
+        )),
+    )]
+    .iter()
+    .cloned()
+    .collect();
+
+    let mut deliver = |peer_id: PeerId| {
+        handle_inbound_request(
+            network_context,
+            PeerNotification::RecvMessage(Message {
+                protocol_id: ProtocolId::mock(),
+                mdata: Bytes::from_static(b"hello"),
+                priority: MessagePriority::Normal,
+                correlation_id: 0,
+            }),
+            peer_id,
+            PeerRole::Unknown,
+            &ProtocolAcls::default(),
+            &ProtocolMessageSizeLimits::default(),
+            &HashMap::new(),
+            &inbound_protocol_rate_limiters,
+            &HashMap::new(),
+            &mut upstream_handlers,
+        )
+    };
+
+    // Messages beyond the limits are dropped, and the peers that exceed the
+    // limits too often are penalized
+    let peer_id = PeerId::random();
+    assert_eq!(deliver(peer_id), None);
+    assert_eq!(deliver(peer_id), None);
+    assert_eq!(
+        deliver(peer_id),
+        Some(time_service.now() + Duration::from_secs(60))
+    );
+
+    // Other peers are unaffected
+    let other_peer_id = PeerId::random();
+    assert_eq!(deliver(other_peer_id), None);
+
+    let mut delivered = vec![];
+    while let Some(notification) = upstream_rx.select_next_some().now_or_never() {
+        match notification {
+            PeerManagerNotification::RecvMessage(peer_id, _) => delivered.push(peer_id),
+            notification => panic!("Unexpected notification: {:?}", notification),
+        }
+    }
+    assert_eq!(delivered, vec![peer_id, other_peer_id]);
+}
+
+#[tokio::test]
+async fn test_inbound_rpc_responses_cached() {
+    let network_context = NetworkContext::mock();
+    let (upstream_tx, mut upstream_rx) = aptos_channel::new(QueueStyle::FIFO, 8, None);
+    let mut upstream_handlers: HashMap<_, _> = [(ProtocolId::mock(), upstream_tx)]
+        .iter()
+        .cloned()
+        .collect();
+    let rpc_response_caches: HashMap<_, _> = [(
+        ProtocolId::mock(),
+        Arc::new(RpcResponseCache::new(
+            RpcResponseCacheConfig::default(),
+            TimeService::mock(),
+        )),
+    )]
+    .iter()
+    .cloned()
+    .collect();
+
+    let mut deliver = |peer_id: PeerId| {
+        let (res_tx, res_rx) = oneshot::channel();
+        handle_inbound_request(
+            network_context,
+            PeerNotification::RecvRpc(InboundRpcRequest {
+                protocol_id: ProtocolId::mock(),
+                data: Bytes::from_static(b"request"),
+                res_tx,
+                deadline: None,
+                correlation_id: 0,
+            }),
+            peer_id,
+            PeerRole::Unknown,
+            &ProtocolAcls::default(),
+            &ProtocolMessageSizeLimits::default(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &rpc_response_caches,
+            &mut upstream_handlers,
+        );
+        res_rx
+    };
+
+    // The first request is handed to the upstream handler
+    let res_rx = deliver(PeerId::random());
+    match upstream_rx.select_next_some().now_or_never() {
+        Some(PeerManagerNotification::RecvRpc(_, request)) => request
+            .res_tx
+            .send(Ok(Bytes::from_static(b"response")))
+            .unwrap(),
+        notification => panic!("Unexpected notification: {:?}", notification),
+    }
+    assert_eq!(
+        res_rx.await.unwrap().unwrap(),
+        Bytes::from_static(b"response")
+    );
+
+    // Identical requests (from any peer) are served from the cache
+    let res_rx = deliver(PeerId::random());
+    assert_eq!(
+        res_rx.await.unwrap().unwrap(),
+        Bytes::from_static(b"response")
+    );
+    assert!(upstream_rx.select_next_some().now_or_never().is_none());
+}
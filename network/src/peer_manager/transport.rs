@@ -30,6 +30,9 @@ pub enum TransportRequest {
         NetworkAddress,
         oneshot::Sender<Result<(), PeerManagerError>>,
     ),
+    /// Replaces the listener with one listening on the given address (e.g.,
+    /// after the local address changed)
+    RebindListener(NetworkAddress),
 }
 
 /// Responsible for listening for new incoming connections and making outbound connections
@@ -98,9 +101,16 @@ where
 
         loop {
             futures::select! {
-                dial_request = self.transport_reqs_rx.select_next_some() => {
-                    if let Some(fut) = self.dial_peer(dial_request) {
-                        pending_outbound_connections.push(fut);
+                transport_request = self.transport_reqs_rx.select_next_some() => {
+                    match transport_request {
+                        TransportRequest::DialPeer(peer_id, addr, response_tx) => {
+                            if let Some(fut) = self.dial_peer(peer_id, addr, response_tx) {
+                                pending_outbound_connections.push(fut);
+                            }
+                        },
+                        TransportRequest::RebindListener(listen_addr) => {
+                            self.rebind_listener(listen_addr);
+                        },
                     }
                 },
                 inbound_connection = self.listener.select_next_some() => {
@@ -167,10 +177,40 @@ where
         }
     }
 
+    /// Replaces the current listener with one listening on the given address.
+    /// The current listener is kept if the new one can't be created.
+    fn rebind_listener(&mut self, listen_addr: NetworkAddress) {
+        match self.transport.listen_on(listen_addr.clone()) {
+            Ok((listener, listen_addr)) => {
+                info!(
+                    NetworkSchema::new(&self.network_context),
+                    listen_address = listen_addr,
+                    "{} Rebound listener to '{}'",
+                    self.network_context,
+                    listen_addr
+                );
+                self.listener = listener.fuse();
+            },
+            Err(err) => {
+                warn!(
+                    NetworkSchema::new(&self.network_context),
+                    listen_address = listen_addr,
+                    error = %err,
+                    "{} Failed to rebind listener to '{}': {}",
+                    self.network_context,
+                    listen_addr,
+                    err
+                );
+            },
+        }
+    }
+
     /// Make an outbound request upgrade future e.g. Noise handshakes
     fn dial_peer(
         &self,
-        dial_peer_request: TransportRequest,
+        peer_id: PeerId,
+        addr: NetworkAddress,
+        response_tx: oneshot::Sender<Result<(), PeerManagerError>>,
     ) -> Option<
         BoxFuture<
             'static,
@@ -183,38 +223,34 @@ where
             ),
         >,
     > {
-        match dial_peer_request {
-            TransportRequest::DialPeer(peer_id, addr, response_tx) => {
-                match self.transport.dial(peer_id, addr.clone()) {
-                    Ok(upgrade) => {
-                        counters::pending_connection_upgrades(
-                            &self.network_context,
-                            ConnectionOrigin::Outbound,
-                        )
-                        .inc();
+        match self.transport.dial(peer_id, addr.clone()) {
+            Ok(upgrade) => {
+                counters::pending_connection_upgrades(
+                    &self.network_context,
+                    ConnectionOrigin::Outbound,
+                )
+                .inc();
 
-                        let start_time = self.time_service.now();
-                        Some(
-                            upgrade
-                                .map(move |out| (out, addr, peer_id, start_time, response_tx))
-                                .boxed(),
-                        )
-                    },
-                    Err(error) => {
-                        if let Err(send_err) =
-                            response_tx.send(Err(PeerManagerError::from_transport_error(error)))
-                        {
-                            info!(
-                                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
-                                "{} Failed to notify clients of TransportError for Peer {}: {:?}",
-                                self.network_context,
-                                peer_id.short_str(),
-                                send_err
-                            );
-                        }
-                        None
-                    },
+                let start_time = self.time_service.now();
+                Some(
+                    upgrade
+                        .map(move |out| (out, addr, peer_id, start_time, response_tx))
+                        .boxed(),
+                )
+            },
+            Err(error) => {
+                if let Err(send_err) =
+                    response_tx.send(Err(PeerManagerError::from_transport_error(error)))
+                {
+                    info!(
+                        NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                        "{} Failed to notify clients of TransportError for Peer {}: {:?}",
+                        self.network_context,
+                        peer_id.short_str(),
+                        send_err
+                    );
                 }
+                None
             },
         }
     }
@@ -4,8 +4,11 @@ use crate::{
     peer::DisconnectReason,
     peer_manager::PeerManagerError,
     protocols::{
-        direct_send::Message,
-        rpc::{InboundRpcRequest, OutboundRpcRequest},
+        direct_send::{acks::AckedMessage, Message},
+        rpc::{
+            streaming::{InboundStreamingRpcRequest, OutboundStreamingRpcRequest},
+            InboundRpcRequest, OutboundRpcRequest,
+        },
     },
     transport::{Connection, ConnectionMetadata},
 };
@@ -13,7 +16,7 @@ use aptos_config::network_id::NetworkContext;
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use futures::channel::oneshot;
 use serde::Serialize;
-use std::fmt;
+use std::{fmt, time::Duration};
 
 /// Request received by PeerManager from upstream actors.
 #[derive(Debug, Serialize)]
@@ -22,6 +25,10 @@ pub enum PeerManagerRequest {
     SendRpc(PeerId, #[serde(skip)] OutboundRpcRequest),
     /// Fire-and-forget style message send to a remote peer.
     SendDirectSend(PeerId, #[serde(skip)] Message),
+    /// Send a streaming RPC request to a remote peer.
+    SendStreamingRpc(PeerId, #[serde(skip)] OutboundStreamingRpcRequest),
+    /// Message send to a remote peer, whose delivery is acknowledged by the peer.
+    SendAckedDirectSend(PeerId, #[serde(skip)] AckedMessage),
 }
 
 /// Notifications sent by PeerManager to upstream actors.
@@ -31,6 +38,8 @@ pub enum PeerManagerNotification {
     RecvRpc(PeerId, InboundRpcRequest),
     /// A new message has been received from a remote peer.
     RecvMessage(PeerId, Message),
+    /// A new streaming RPC request has been received from a remote peer.
+    RecvStreamingRpc(PeerId, InboundStreamingRpcRequest),
 }
 
 #[derive(Debug, Serialize)]
@@ -44,6 +53,20 @@ pub enum ConnectionRequest {
         PeerId,
         #[serde(skip)] oneshot::Sender<Result<(), PeerManagerError>>,
     ),
+    /// Gracefully disconnect from the peer (with the given reason), once its
+    /// pending requests completed or the drain timeout elapsed
+    DrainPeer(
+        PeerId,
+        String,
+        Duration,
+        #[serde(skip)] oneshot::Sender<Result<(), PeerManagerError>>,
+    ),
+    /// Gracefully disconnect from all peers, and reject new connections
+    DrainAll(
+        String,
+        Duration,
+        #[serde(skip)] oneshot::Sender<Result<(), PeerManagerError>>,
+    ),
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize)]
@@ -77,4 +100,10 @@ impl fmt::Display for ConnectionNotification {
 pub enum TransportNotification<TSocket> {
     NewConnection(#[serde(skip)] Connection<TSocket>),
     Disconnected(ConnectionMetadata, DisconnectReason),
+    /// The remote peer announced that it's draining the connection, so no new
+    /// requests should be routed to it
+    Draining(ConnectionMetadata),
+    /// The metadata of a connection was updated by its peer actor (e.g., its
+    /// smoothed RTT)
+    MetadataUpdated(ConnectionMetadata),
 }
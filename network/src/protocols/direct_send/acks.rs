@@ -0,0 +1,267 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Delivery acknowledgements of direct-send messages.
+//!
+//! Direct-send messages are fire-and-forget: the sender learns nothing about
+//! whether the message reached the remote application, or was dropped on the
+//! way (e.g., because the remote application's queue was full). Applications
+//! that want delivery feedback (e.g., mempool broadcasts) can opt-in to acked
+//! direct-sends, which are sent as [`AckedDirectSendMsg`]s to peers that
+//! negotiated [`Feature::DirectSendAcks`]. The receiver answers each of them
+//! with a lightweight [`DirectSendAck`], once the message was enqueued for (or
+//! dropped before reaching) its application.
+//!
+//! [`AckedDirectSendMsg`]: crate::protocols::wire::messaging::v1::AckedDirectSendMsg
+//! [`Feature::DirectSendAcks`]: crate::protocols::wire::handshake::v2::Feature::DirectSendAcks
+
+use crate::protocols::{
+    direct_send::Message,
+    rpc::error::RpcError,
+    wire::messaging::v1::{DirectSendAck, RequestId},
+};
+use aptos_id_generator::{IdGenerator, U32IdGenerator};
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, FusedFuture, Future, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use std::{collections::HashMap, fmt, time::Duration};
+
+/// An outbound direct-send message whose delivery is acknowledged by the
+/// remote peer
+pub struct AckedMessage {
+    pub message: Message,
+    /// The time after which the message is considered lost, if it wasn't
+    /// acknowledged
+    pub timeout: Duration,
+    /// Resolves once the message was acknowledged, with an error if it was
+    /// dropped by the remote peer, or wasn't acknowledged in time
+    pub ack_tx: oneshot::Sender<Result<(), RpcError>>,
+}
+
+impl fmt::Debug for AckedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AckedMessage {{ message: {:?}, timeout: {:?} }}",
+            self.message, self.timeout
+        )
+    }
+}
+
+/// An inbound direct-send message, whose sender waits for an acknowledgement
+pub struct InboundAckedMessage {
+    pub message: Message,
+    /// Notified with whether the message was enqueued for the application. If
+    /// dropped, the message is acknowledged as not delivered.
+    pub delivered_tx: oneshot::Sender<bool>,
+}
+
+/// The ack channel is ignored in comparisons
+impl PartialEq for InboundAckedMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+}
+
+impl fmt::Debug for InboundAckedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InboundAckedMessage {{ message: {:?} }}", self.message)
+    }
+}
+
+/// Tracks the acked direct-send messages sent to a single peer, until they're
+/// acknowledged or time out.
+///
+/// There is one `OutboundAcks` handler per [`Peer`](crate::peer::Peer).
+pub struct OutboundAcks {
+    time_service: TimeService,
+    request_id_gen: U32IdGenerator,
+    /// The senders waiting for an acknowledgement, by `RequestId`
+    pending_acks: HashMap<RequestId, oneshot::Sender<Result<(), RpcError>>>,
+    /// Resolves to the `RequestId` of each message, once its timeout elapsed
+    ack_timeouts: FuturesUnordered<BoxFuture<'static, RequestId>>,
+}
+
+impl OutboundAcks {
+    pub fn new(time_service: TimeService) -> Self {
+        Self {
+            time_service,
+            request_id_gen: U32IdGenerator::new(),
+            pending_acks: HashMap::new(),
+            ack_timeouts: FuturesUnordered::new(),
+        }
+    }
+
+    /// Returns the `RequestId` of a new outbound message
+    pub fn next_request_id(&mut self) -> RequestId {
+        self.request_id_gen.next()
+    }
+
+    /// Waits for the acknowledgement of the (sent) message with the given id
+    pub fn insert(
+        &mut self,
+        request_id: RequestId,
+        timeout: Duration,
+        ack_tx: oneshot::Sender<Result<(), RpcError>>,
+    ) {
+        self.pending_acks.insert(request_id, ack_tx);
+        self.ack_timeouts.push(
+            self.time_service
+                .sleep(timeout)
+                .map(move |_| request_id)
+                .boxed(),
+        );
+    }
+
+    /// Returns the number of messages waiting for an acknowledgement
+    pub fn len(&self) -> usize {
+        self.pending_acks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending_acks.is_empty()
+    }
+
+    /// Notifies the sender of the acknowledged message. Acknowledgements of
+    /// messages that already timed out are ignored.
+    pub fn handle_inbound_ack(&mut self, ack: DirectSendAck) {
+        if let Some(ack_tx) = self.pending_acks.remove(&ack.request_id) {
+            let result = if ack.delivered {
+                Ok(())
+            } else {
+                Err(RpcError::Error(anyhow::anyhow!(
+                    "The message was dropped by the remote peer"
+                )))
+            };
+            // The sender may no longer be waiting for the acknowledgement
+            let _ = ack_tx.send(result);
+        }
+    }
+
+    /// Resolves to the `RequestId` of the next message whose timeout elapsed.
+    /// The returned `Future` is a `FusedFuture` so it works correctly in a
+    /// `futures::select!`.
+    pub fn next_timeout(&mut self) -> impl Future<Output = RequestId> + FusedFuture + '_ {
+        self.ack_timeouts.select_next_some()
+    }
+
+    /// Fails the message with the given id, if it wasn't acknowledged yet
+    pub fn handle_timeout(&mut self, request_id: RequestId) {
+        if let Some(ack_tx) = self.pending_acks.remove(&request_id) {
+            let _ = ack_tx.send(Err(RpcError::TimedOut));
+        }
+    }
+}
+
+/// Tracks the acked direct-send messages received from a single peer, until
+/// they're handed to (or dropped before reaching) the application.
+///
+/// There is one `InboundAcks` handler per [`Peer`](crate::peer::Peer).
+#[derive(Default)]
+pub struct InboundAcks {
+    pending_acks: FuturesUnordered<BoxFuture<'static, DirectSendAck>>,
+}
+
+impl InboundAcks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if no inbound message is pending its delivery
+    pub fn is_empty(&self) -> bool {
+        self.pending_acks.is_empty()
+    }
+
+    /// Returns the channel over which the delivery of the message with the
+    /// given id is reported
+    pub fn insert(&mut self, request_id: RequestId) -> oneshot::Sender<bool> {
+        let (delivered_tx, delivered_rx) = oneshot::channel();
+        self.pending_acks.push(
+            delivered_rx
+                .map(move |delivered| DirectSendAck {
+                    request_id,
+                    delivered: delivered.unwrap_or(false),
+                })
+                .boxed(),
+        );
+        delivered_tx
+    }
+
+    /// Resolves to the next acknowledgement to send to the remote peer. The
+    /// returned `Future` is a `FusedFuture` so it works correctly in a
+    /// `futures::select!`.
+    pub fn next_ack(&mut self) -> impl Future<Output = DirectSendAck> + FusedFuture + '_ {
+        self.pending_acks.select_next_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn outbound_acks_resolve_or_time_out() {
+        let time_service = TimeService::mock();
+        let mock_time = time_service.clone().into_mock();
+        let mut outbound_acks = OutboundAcks::new(time_service);
+
+        // Delivered and dropped messages are reported to the sender
+        let (delivered_tx, delivered_rx) = oneshot::channel();
+        let (dropped_tx, dropped_rx) = oneshot::channel();
+        let (lost_tx, lost_rx) = oneshot::channel();
+        let delivered_id = outbound_acks.next_request_id();
+        let dropped_id = outbound_acks.next_request_id();
+        let lost_id = outbound_acks.next_request_id();
+        for (request_id, ack_tx) in [
+            (delivered_id, delivered_tx),
+            (dropped_id, dropped_tx),
+            (lost_id, lost_tx),
+        ] {
+            outbound_acks.insert(request_id, Duration::from_secs(1), ack_tx);
+        }
+        outbound_acks.handle_inbound_ack(DirectSendAck {
+            request_id: delivered_id,
+            delivered: true,
+        });
+        outbound_acks.handle_inbound_ack(DirectSendAck {
+            request_id: dropped_id,
+            delivered: false,
+        });
+        assert!(delivered_rx.await.unwrap().is_ok());
+        assert!(dropped_rx.await.unwrap().is_err());
+        assert_eq!(outbound_acks.len(), 1);
+
+        // Unacknowledged messages time out
+        mock_time.advance_secs(1);
+        for _ in 0..3 {
+            let request_id = outbound_acks.next_timeout().await;
+            outbound_acks.handle_timeout(request_id);
+        }
+        assert!(matches!(lost_rx.await.unwrap(), Err(RpcError::TimedOut)));
+        assert!(outbound_acks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn inbound_acks_report_delivery() {
+        let mut inbound_acks = InboundAcks::new();
+        let delivered_tx = inbound_acks.insert(1);
+        let dropped_tx = inbound_acks.insert(2);
+
+        delivered_tx.send(true).unwrap();
+        assert_eq!(inbound_acks.next_ack().await, DirectSendAck {
+            request_id: 1,
+            delivered: true,
+        });
+
+        // Dropping the channel (e.g., when the message is rejected) reports
+        // the message as not delivered
+        drop(dropped_tx);
+        assert_eq!(inbound_acks.next_ack().await, DirectSendAck {
+            request_id: 2,
+            delivered: false,
+        });
+    }
+}
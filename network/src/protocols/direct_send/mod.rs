@@ -1,12 +1,21 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{protocols::network::SerializedRequest, ProtocolId};
+use crate::{
+    protocols::{
+        network::SerializedRequest,
+        wire::messaging::v1::{CorrelationId, MessagePriority},
+    },
+    ProtocolId,
+};
 use bytes::Bytes;
 use serde::Serialize;
 use std::fmt::Debug;
 
-#[derive(Clone, Eq, PartialEq, Serialize)]
+pub mod acks;
+pub mod replay;
+
+#[derive(Clone, Eq, Serialize)]
 pub struct Message {
     /// The [`ProtocolId`] for which of our upstream application modules should
     /// handle (i.e., deserialize and then respond to) this inbound rpc request.
@@ -19,6 +28,22 @@ pub struct Message {
     /// deserialized later in the handling application module.
     #[serde(skip)]
     pub mdata: Bytes,
+    /// The priority with which the message is sent (or was sent, for inbound
+    /// messages).
+    pub priority: MessagePriority,
+    /// Identifies the message in the logs of both the sender and the receiver.
+    /// Inbound messages from peers that don't send correlation ids are
+    /// assigned a new one on receipt.
+    pub correlation_id: CorrelationId,
+}
+
+/// The correlation id is only used for tracing, so it's ignored in comparisons
+impl PartialEq for Message {
+    fn eq(&self, other: &Self) -> bool {
+        self.protocol_id == other.protocol_id
+            && self.mdata == other.mdata
+            && self.priority == other.priority
+    }
 }
 
 impl Debug for Message {
@@ -30,8 +55,8 @@ impl Debug for Message {
         };
         write!(
             f,
-            "Message {{ protocol: {:?}, mdata: {} }}",
-            self.protocol_id, mdata_str
+            "Message {{ protocol: {:?}, mdata: {}, priority: {:?}, correlation_id: {} }}",
+            self.protocol_id, mdata_str, self.priority, self.correlation_id
         )
     }
 }
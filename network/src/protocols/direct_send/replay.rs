@@ -0,0 +1,159 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replay protection of direct-send messages.
+//!
+//! Some applications (e.g., vote relays) require at-most-once delivery of their
+//! direct-send messages. Rather than having each of them deduplicate messages,
+//! the sender includes a nonce in the [`SequencedDirectSendMsg`] frames sent to
+//! peers that negotiated [`Feature::ReplayProtection`]. The nonce increases with
+//! every message sent over the network, and starts at the sender's clock (in
+//! microseconds), so that it keeps increasing across restarts.
+//!
+//! The receiver keeps a sliding window of the nonces recently seen from each
+//! peer with each protected protocol, and drops the messages whose nonce was
+//! already seen, or is older than the window. Messages without a nonce from
+//! peers that negotiated the feature are dropped too, as they can't be checked.
+//! Messages from older peers are still accepted.
+//!
+//! [`SequencedDirectSendMsg`]: crate::protocols::wire::messaging::v1::SequencedDirectSendMsg
+//! [`Feature::ReplayProtection`]: crate::protocols::wire::handshake::v2::Feature::ReplayProtection
+
+use crate::ProtocolId;
+use aptos_infallible::{duration_since_epoch, Mutex};
+use aptos_types::PeerId;
+use std::{
+    cmp::max,
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The nonces recently seen from a peer with a protocol
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    /// The highest nonce seen
+    highest: u64,
+    /// The nonces seen within the window below (and including) the highest one
+    seen: BTreeSet<u64>,
+}
+
+impl ReplayWindow {
+    /// Records the nonce, and returns true iff it's within the window and wasn't
+    /// seen before
+    fn accept(&mut self, nonce: u64, window: u64) -> bool {
+        if nonce.saturating_add(window) <= self.highest || !self.seen.insert(nonce) {
+            return false;
+        }
+        if nonce > self.highest {
+            self.highest = nonce;
+            self.seen = self.seen.split_off(&nonce.saturating_sub(window));
+        }
+        true
+    }
+}
+
+/// The nonces of the outbound direct-send messages of a network, and the replay
+/// windows of its protected protocols. The same handle is shared by the `Peer`
+/// actors of all connections, so that windows outlive reconnections.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayProtection {
+    /// The window of each protected protocol
+    windows: HashMap<ProtocolId, Duration>,
+    /// The nonce of the last outbound message
+    last_nonce: Arc<AtomicU64>,
+    /// The nonces seen from each peer with each protected protocol
+    seen: Arc<Mutex<HashMap<(PeerId, ProtocolId), ReplayWindow>>>,
+}
+
+impl ReplayProtection {
+    /// Protects the inbound direct-send messages of the protocol against
+    /// replays. As nonces follow the sender's clock, the window is the maximum
+    /// delay by which a message can be reordered (w.r.t. later messages of the
+    /// same peer) without being dropped.
+    pub fn insert(&mut self, protocol_id: ProtocolId, window: Duration) {
+        self.windows.insert(protocol_id, window);
+    }
+
+    /// Returns true iff the inbound messages of the protocol are protected
+    pub fn is_protected(&self, protocol_id: ProtocolId) -> bool {
+        self.windows.contains_key(&protocol_id)
+    }
+
+    /// Returns the nonce of a new outbound message, which is greater than the
+    /// nonces of all previous messages
+    pub fn next_nonce(&self) -> u64 {
+        let now = duration_since_epoch().as_micros() as u64;
+        let last_nonce = self
+            .last_nonce
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last_nonce| {
+                Some(max(now, last_nonce + 1))
+            })
+            .expect("The nonce is always updated");
+        max(now, last_nonce + 1)
+    }
+
+    /// Records the nonce of an inbound message of the protocol from the peer,
+    /// and returns true iff the message should be delivered, i.e., the protocol
+    /// isn't protected, or the nonce is within the window and wasn't seen before
+    pub fn accept(&self, peer_id: PeerId, protocol_id: ProtocolId, nonce: u64) -> bool {
+        match self.windows.get(&protocol_id) {
+            Some(window) => self
+                .seen
+                .lock()
+                .entry((peer_id, protocol_id))
+                .or_default()
+                .accept(nonce, window.as_micros() as u64),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replayed_and_stale_nonces_are_rejected() {
+        let mut replay_protection = ReplayProtection::default();
+        replay_protection.insert(
+            ProtocolId::ConsensusDirectSendBcs,
+            Duration::from_micros(10),
+        );
+        let peer_id = PeerId::random();
+        let accept =
+            |nonce| replay_protection.accept(peer_id, ProtocolId::ConsensusDirectSendBcs, nonce);
+
+        // Nonces are accepted once, even if reordered within the window
+        assert!(accept(100));
+        assert!(accept(105));
+        assert!(accept(103));
+        assert!(!accept(103));
+        assert!(!accept(105));
+
+        // Nonces older than the window are rejected
+        assert!(accept(120));
+        assert!(!accept(110));
+        assert!(accept(111));
+
+        // Unprotected protocols and other peers aren't affected
+        assert!(replay_protection.accept(peer_id, ProtocolId::MempoolDirectSend, 100));
+        assert!(replay_protection.accept(
+            PeerId::random(),
+            ProtocolId::ConsensusDirectSendBcs,
+            100
+        ));
+    }
+
+    #[test]
+    fn nonces_increase() {
+        let replay_protection = ReplayProtection::default();
+        let first_nonce = replay_protection.next_nonce();
+        let second_nonce = replay_protection.next_nonce();
+        assert!(second_nonce > first_nonce);
+        assert!(first_nonce >= duration_since_epoch().as_micros() as u64 - 1_000_000);
+    }
+}
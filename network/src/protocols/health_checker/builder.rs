@@ -24,12 +24,15 @@ pub struct HealthCheckerBuilder {
 }
 
 impl HealthCheckerBuilder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         network_context: NetworkContext,
         time_service: TimeService,
         ping_interval_ms: u64,
+        max_ping_interval_ms: Option<u64>,
         ping_timeout_ms: u64,
         ping_failures_tolerated: u64,
+        dead_peer_missed_pings: Option<u64>,
         network_sender: NetworkSender<HealthCheckerMsg>,
         network_rx: HealthCheckerNetworkEvents,
         peer_metadata_storage: Arc<PeerMetadataStorage>,
@@ -46,8 +49,10 @@ impl HealthCheckerBuilder {
             time_service,
             HealthCheckNetworkInterface::new(network_client, network_rx),
             Duration::from_millis(ping_interval_ms),
+            max_ping_interval_ms.map(Duration::from_millis),
             Duration::from_millis(ping_timeout_ms),
             ping_failures_tolerated,
+            dead_peer_missed_pings,
         );
         Self {
             service: Some(service),
@@ -20,17 +20,31 @@ use std::{
     collections::HashMap,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 #[derive(Clone, Copy, Default, Debug, Eq, PartialEq)]
 pub struct HealthCheckData {
     pub round: u64,
     pub failures: u64,
+    /// Whether the peer was marked as `Disconnecting` because it missed pings
+    pub unresponsive: bool,
+    /// The number of rounds between the pings to the peer, which grows while
+    /// the peer is stable
+    pub ping_interval_rounds: u64,
+    /// The round in which the peer is pinged next
+    pub next_ping_round: u64,
 }
 
 impl HealthCheckData {
     pub fn new(round: u64) -> Self {
-        HealthCheckData { round, failures: 0 }
+        HealthCheckData {
+            round,
+            failures: 0,
+            unresponsive: false,
+            ping_interval_rounds: 1,
+            next_ping_round: round,
+        }
     }
 }
 
@@ -54,9 +68,41 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg>>
 
     // TODO: migrate this over to the network client once we
     // deduplicate the work.
-    /// Returns all connected peers
-    pub fn connected_peers(&self) -> Vec<PeerId> {
-        self.health_check_data.read().keys().cloned().collect()
+    /// Returns the connected peers that are due to be pinged in the given round,
+    /// and schedules their next ping
+    pub fn peers_to_ping(&mut self, round: u64) -> Vec<PeerId> {
+        self.health_check_data
+            .write()
+            .iter_mut()
+            .filter(|(_, health_check_data)| health_check_data.next_ping_round <= round)
+            .map(|(peer_id, health_check_data)| {
+                health_check_data.next_ping_round = round + health_check_data.ping_interval_rounds;
+                *peer_id
+            })
+            .collect()
+    }
+
+    /// Adapts the ping interval of the peer to the outcome of its ping in the
+    /// given round: the interval is doubled (up to the given maximum) after a
+    /// successful ping, and the peer is pinged in every round after a failure.
+    /// If the peer is not found, nothing is done.
+    pub fn adapt_ping_interval(
+        &mut self,
+        peer_id: PeerId,
+        round: u64,
+        success: bool,
+        max_ping_interval_rounds: u64,
+    ) {
+        if let Some(health_check_data) = self.health_check_data.write().get_mut(&peer_id) {
+            if success {
+                health_check_data.ping_interval_rounds =
+                    (health_check_data.ping_interval_rounds * 2).min(max_ping_interval_rounds);
+            } else {
+                health_check_data.ping_interval_rounds = 1;
+                health_check_data.next_ping_round =
+                    health_check_data.next_ping_round.min(round + 1);
+            }
+        }
     }
 
     /// Disconnect a peer, and keep track of the associated state
@@ -138,6 +184,43 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg>>
             .map(|health_check_data| health_check_data.failures)
     }
 
+    /// Marks the peer as `Disconnecting` (i.e., no longer selected by applications)
+    /// because it missed pings. If the peer is not found, nothing is done.
+    pub fn mark_peer_unresponsive(&mut self, peer_network_id: PeerNetworkId) {
+        if let Some(health_check_data) = self
+            .health_check_data
+            .write()
+            .get_mut(&peer_network_id.peer_id())
+        {
+            if !health_check_data.unresponsive {
+                health_check_data.unresponsive = true;
+                let _ = self.update_peer_state(peer_network_id, PeerState::Disconnecting);
+            }
+        }
+    }
+
+    /// Marks the peer as `Connected` again if it was marked as unresponsive
+    pub fn mark_peer_responsive(&mut self, peer_network_id: PeerNetworkId) {
+        if let Some(health_check_data) = self
+            .health_check_data
+            .write()
+            .get_mut(&peer_network_id.peer_id())
+        {
+            if health_check_data.unresponsive {
+                health_check_data.unresponsive = false;
+                let _ = self.update_peer_state(peer_network_id, PeerState::Connected);
+            }
+        }
+    }
+
+    /// Records the RTT of a ping to the peer (or `None` if it failed) in the
+    /// peer metadata, so that it's visible to peer selection and operators
+    pub fn record_ping_result(&self, peer_network_id: PeerNetworkId, rtt: Option<Duration>) {
+        self.network_client
+            .get_peer_metadata_storage()
+            .record_ping_result(peer_network_id, rtt);
+    }
+
     // TODO: we shouldn't need to expose this
     pub fn network_client(&self) -> NetworkClient {
         self.network_client.clone()
@@ -11,6 +11,12 @@
 //! disconnect from the peer. It relies on ConnectivityManager or the remote peer to re-establish
 //! the connection.
 //!
+//! If a maximum ping interval is configured, the ping interval adapts to the stability of each
+//! peer: it's doubled after every successful probe (up to the maximum), and reset to the base
+//! interval after a failed one. Stable peers are thus probed rarely, while new and flapping peers
+//! are probed frequently. The outcomes of the probes are also recorded in the peer metadata, as an
+//! estimate of the peer's availability.
+//!
 //! Future Work
 //! -----------
 //! We can make a few other improvements to the health checker. These are:
@@ -100,12 +106,19 @@ pub struct HealthChecker<NetworkClient> {
     rng: SmallRng,
     /// Time we wait between each set of pings.
     ping_interval: Duration,
+    /// The maximum number of rounds between the pings to a (stable) peer
+    max_ping_interval_rounds: u64,
     /// Ping timeout duration.
     ping_timeout: Duration,
     /// Number of successive ping failures we tolerate before declaring a node as unhealthy and
     /// disconnecting from it. In the future, this can be replaced with a more general failure
     /// detection policy.
     ping_failures_tolerated: u64,
+    /// Number of successive ping failures after which a node is marked as `Disconnecting` in
+    /// the peer metadata, so that applications stop selecting it without waiting for the
+    /// disconnect (or the OS-level TCP timeout). The node is marked as `Connected` again if it
+    /// becomes healthy before it's disconnected.
+    dead_peer_missed_pings: Option<u64>,
     /// Counter incremented in each round of health checks
     round: u64,
 }
@@ -117,17 +130,24 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
         time_service: TimeService,
         network_interface: HealthCheckNetworkInterface<NetworkClient>,
         ping_interval: Duration,
+        max_ping_interval: Option<Duration>,
         ping_timeout: Duration,
         ping_failures_tolerated: u64,
+        dead_peer_missed_pings: Option<u64>,
     ) -> Self {
+        let max_ping_interval_rounds = max_ping_interval.map_or(1, |max_ping_interval| {
+            (max_ping_interval.as_millis() / ping_interval.as_millis().max(1)).max(1) as u64
+        });
         HealthChecker {
             network_context,
             time_service,
             network_interface,
             rng: SmallRng::from_entropy(),
             ping_interval,
+            max_ping_interval_rounds,
             ping_timeout,
             ping_failures_tolerated,
+            dead_peer_missed_pings,
             round: 0,
         }
     }
@@ -163,7 +183,7 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                                 &metadata.remote_peer_id
                             );
                         }
-                        Event::RpcRequest(peer_id, msg, protocol, res_tx) => {
+                        Event::RpcRequest(peer_id, msg, protocol, res_tx, _) => {
                             match msg {
                                 HealthCheckerMsg::Ping(ping) => self.handle_ping_request(peer_id, ping, protocol, res_tx),
                                 _ => {
@@ -179,7 +199,7 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                                 }
                             };
                         }
-                        Event::Message(peer_id, msg) => {
+                        Event::Message(peer_id, msg, _) => {
                             error!(
                                 SecurityEvent::InvalidNetworkEventHC,
                                 NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
@@ -190,12 +210,23 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                             );
                             debug_assert!(false, "Unexpected network event");
                         }
+                        Event::StreamingRpcRequest(peer_id, msg, _, _) => {
+                            error!(
+                                SecurityEvent::InvalidNetworkEventHC,
+                                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                                "{} Unexpected streaming rpc from {} msg {:?}",
+                                self.network_context,
+                                peer_id,
+                                msg,
+                            );
+                            debug_assert!(false, "Unexpected network event");
+                        }
                     }
                 }
                 _ = ticker.select_next_some() => {
                     self.round += 1;
-                    let connected = self.network_interface.connected_peers();
-                    if connected.is_empty() {
+                    let peers_to_ping = self.network_interface.peers_to_ping(self.round);
+                    if peers_to_ping.is_empty() {
                         trace!(
                             NetworkSchema::new(&self.network_context),
                             round = self.round,
@@ -206,7 +237,7 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                         continue
                     }
 
-                    for peer_id in connected {
+                    for peer_id in peers_to_ping {
                         let nonce = self.rng.gen::<u32>();
                         trace!(
                             NetworkSchema::new(&self.network_context),
@@ -220,6 +251,7 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
 
                         tick_handlers.push(Self::ping_peer(
                             self.network_context,
+                            self.time_service.clone(),
                             self.network_interface.network_client(),
                             peer_id,
                             self.round,
@@ -229,8 +261,8 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                     }
                 }
                 res = tick_handlers.select_next_some() => {
-                    let (peer_id, round, nonce, ping_result) = res;
-                    self.handle_ping_response(peer_id, round, nonce, ping_result).await;
+                    let (peer_id, round, nonce, rtt, ping_result) = res;
+                    self.handle_ping_response(peer_id, round, nonce, rtt, ping_result).await;
                 }
             }
         }
@@ -267,6 +299,8 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
         );
         // Record Ingress HC here and reset failures.
         self.network_interface.reset_peer_failures(peer_id);
+        let peer_network_id = PeerNetworkId::new(self.network_context.network_id(), peer_id);
+        self.network_interface.mark_peer_responsive(peer_network_id);
 
         let _ = res_tx.send(Ok(message.into()));
     }
@@ -276,8 +310,10 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
         peer_id: PeerId,
         round: u64,
         req_nonce: u32,
+        rtt: Duration,
         ping_result: Result<Pong, RpcError>,
     ) {
+        let peer_network_id = PeerNetworkId::new(self.network_context.network_id(), peer_id);
         match ping_result {
             Ok(pong) => {
                 if pong.0 == req_nonce {
@@ -293,6 +329,15 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                     // If it's not in storage, don't bother updating it
                     self.network_interface
                         .reset_peer_round_state(peer_id, round);
+                    self.network_interface
+                        .record_ping_result(peer_network_id, Some(rtt));
+                    self.network_interface.adapt_ping_interval(
+                        peer_id,
+                        round,
+                        true,
+                        self.max_ping_interval_rounds,
+                    );
+                    self.network_interface.mark_peer_responsive(peer_network_id);
                 } else {
                     warn!(
                         SecurityEvent::InvalidHealthCheckerMsg,
@@ -320,6 +365,14 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                 );
                 self.network_interface
                     .increment_peer_round_failure(peer_id, round);
+                self.network_interface
+                    .record_ping_result(peer_network_id, None);
+                self.network_interface.adapt_ping_interval(
+                    peer_id,
+                    round,
+                    false,
+                    self.max_ping_interval_rounds,
+                );
 
                 // If the ping failures are now more than
                 // `self.ping_failures_tolerated`, we disconnect from the node.
@@ -336,8 +389,6 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                         self.network_context,
                         peer_id.short_str()
                     );
-                    let peer_network_id =
-                        PeerNetworkId::new(self.network_context.network_id(), peer_id);
                     if let Err(err) = self
                         .network_interface
                         .disconnect_peer(peer_network_id)
@@ -353,6 +404,12 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                             err
                         );
                     }
+                } else if self
+                    .dead_peer_missed_pings
+                    .map_or(false, |missed_pings| failures >= missed_pings)
+                {
+                    self.network_interface
+                        .mark_peer_unresponsive(peer_network_id);
                 }
             },
         }
@@ -360,12 +417,13 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
 
     async fn ping_peer(
         network_context: NetworkContext,
+        time_service: TimeService,
         network_client: NetworkClient, // TODO: we shouldn't need to pass the client directly
         peer_id: PeerId,
         round: u64,
         nonce: u32,
         ping_timeout: Duration,
-    ) -> (PeerId, u64, u32, Result<Pong, RpcError>) {
+    ) -> (PeerId, u64, u32, Duration, Result<Pong, RpcError>) {
         trace!(
             NetworkSchema::new(&network_context).remote_peer(&peer_id),
             round = round,
@@ -376,6 +434,7 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
             nonce
         );
         let peer_network_id = PeerNetworkId::new(network_context.network_id(), peer_id);
+        let start = time_service.now();
         let res_pong_msg = network_client
             .send_to_peer_rpc(
                 HealthCheckerMsg::Ping(Ping(nonce)),
@@ -388,6 +447,7 @@ impl<NetworkClient: NetworkClientInterface<HealthCheckerMsg> + Unpin> HealthChec
                 HealthCheckerMsg::Pong(res) => Ok(res),
                 _ => Err(RpcError::InvalidRpcResponse),
             });
-        (peer_id, round, nonce, res_pong_msg)
+        let rtt = time_service.now().duration_since(start);
+        (peer_id, round, nonce, rtt, res_pong_msg)
     }
 }
@@ -3,7 +3,11 @@
 
 use super::*;
 use crate::{
-    application::{interface::NetworkClient, storage::PeerMetadataStorage, types::PeerInfo},
+    application::{
+        interface::NetworkClient,
+        storage::PeerMetadataStorage,
+        types::{PeerInfo, PeerState},
+    },
     peer_manager::{
         self, conn_notifs_channel, ConnectionRequest, ConnectionRequestSender,
         PeerManagerNotification, PeerManagerRequest, PeerManagerRequestSender,
@@ -37,6 +41,21 @@ struct TestHarness {
 impl TestHarness {
     fn new_permissive(
         ping_failures_tolerated: u64,
+    ) -> (Self, HealthChecker<NetworkClient<HealthCheckerMsg>>) {
+        Self::new_with_dead_peer_detection(ping_failures_tolerated, None)
+    }
+
+    fn new_with_dead_peer_detection(
+        ping_failures_tolerated: u64,
+        dead_peer_missed_pings: Option<u64>,
+    ) -> (Self, HealthChecker<NetworkClient<HealthCheckerMsg>>) {
+        Self::new(ping_failures_tolerated, None, dead_peer_missed_pings)
+    }
+
+    fn new(
+        ping_failures_tolerated: u64,
+        max_ping_interval: Option<Duration>,
+        dead_peer_missed_pings: Option<u64>,
     ) -> (Self, HealthChecker<NetworkClient<HealthCheckerMsg>>) {
         ::aptos_logger::Logger::init_for_testing();
         let mock_time = TimeService::mock();
@@ -69,8 +88,10 @@ impl TestHarness {
             mock_time.clone(),
             HealthCheckNetworkInterface::new(network_client, hc_network_rx),
             PING_INTERVAL,
+            max_ping_interval,
             PING_TIMEOUT,
             ping_failures_tolerated,
+            dead_peer_missed_pings,
         );
 
         (
@@ -139,6 +160,8 @@ impl TestHarness {
             protocol_id,
             data,
             res_tx,
+            deadline: None,
+            correlation_id: 0,
         };
         let key = (peer_id, ProtocolId::HealthCheckerRpc);
         let (delivered_tx, delivered_rx) = oneshot::channel();
@@ -330,3 +353,112 @@ fn outbound_failure_strict() {
     };
     block_on(future::join(health_checker.start(), test));
 }
+
+#[test]
+fn ping_stats_recorded() {
+    let (mut harness, health_checker) = TestHarness::new_permissive(10);
+
+    let test = async move {
+        let peer_id = PeerId::new([0x42; PeerId::LENGTH]);
+        let peer_network_id = PeerNetworkId::new(NetworkContext::mock().network_id(), peer_id);
+        harness.send_new_peer_notification(peer_id).await;
+
+        // A failed ping is counted
+        harness.trigger_ping().await;
+        harness.expect_ping_send_not_ok().await;
+
+        // And a successful ping records its RTT
+        harness.trigger_ping().await;
+        let (ping, res_tx) = harness.expect_ping().await;
+        harness.mock_time.advance(Duration::from_millis(30));
+        let res_data = bcs::to_bytes(&HealthCheckerMsg::Pong(Pong(ping.0))).unwrap();
+        res_tx.send(Ok(res_data.into())).unwrap();
+
+        let ping_stats = loop {
+            let ping_stats = harness
+                .peer_metadata_storage
+                .get_peer_ping_stats(&peer_network_id)
+                .unwrap();
+            if ping_stats.num_successes() > 0 {
+                break ping_stats;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(ping_stats.total_failures, 1);
+        assert_eq!(ping_stats.consecutive_failures, 0);
+        assert_eq!(ping_stats.average_rtt, Some(Duration::from_millis(30)));
+        assert_eq!(
+            harness
+                .peer_metadata_storage
+                .get_peer_latency(&peer_network_id),
+            Some(Duration::from_millis(30))
+        );
+    };
+    block_on(future::join(health_checker.start(), test));
+}
+
+#[test]
+fn unresponsive_peer_marked_disconnecting() {
+    let (mut harness, health_checker) = TestHarness::new_with_dead_peer_detection(10, Some(2));
+
+    let test = async move {
+        let peer_id = PeerId::new([0x42; PeerId::LENGTH]);
+        let peer_network_id = PeerNetworkId::new(NetworkContext::mock().network_id(), peer_id);
+        harness.send_new_peer_notification(peer_id).await;
+
+        // The peer is marked as disconnecting once it missed enough pings, but
+        // isn't disconnected yet
+        for _ in 0..2 {
+            harness.trigger_ping().await;
+            harness.expect_ping_send_not_ok().await;
+        }
+        wait_for_peer_state(&harness, peer_network_id, PeerState::Disconnecting).await;
+
+        // And marked as connected again once it responds
+        harness.trigger_ping().await;
+        harness.expect_ping_send_ok().await;
+        wait_for_peer_state(&harness, peer_network_id, PeerState::Connected).await;
+    };
+    block_on(future::join(health_checker.start(), test));
+}
+
+async fn wait_for_peer_state(
+    harness: &TestHarness,
+    peer_network_id: PeerNetworkId,
+    expected_state: PeerState,
+) {
+    while harness
+        .peer_metadata_storage
+        .read(peer_network_id)
+        .unwrap()
+        .status
+        != expected_state
+    {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[test]
+fn ping_interval_adapts_to_stability() {
+    let (_harness, mut health_checker) = TestHarness::new(10, Some(4 * PING_INTERVAL), None);
+    let max_ping_interval_rounds = health_checker.max_ping_interval_rounds;
+    assert_eq!(max_ping_interval_rounds, 4);
+    let network_interface = &mut health_checker.network_interface;
+    let peer_id = PeerId::new([0x42; PeerId::LENGTH]);
+    network_interface.create_peer_and_health_data(peer_id, 0);
+
+    // The interval grows (up to the maximum) while the pings succeed
+    let mut pinged_rounds = vec![];
+    for round in 1..=12 {
+        if !network_interface.peers_to_ping(round).is_empty() {
+            pinged_rounds.push(round);
+            network_interface.adapt_ping_interval(peer_id, round, true, max_ping_interval_rounds);
+        }
+    }
+    assert_eq!(pinged_rounds, vec![1, 2, 4, 8, 12]);
+
+    // And the peer is pinged in every round again after a failure
+    network_interface.adapt_ping_interval(peer_id, 12, false, max_ping_interval_rounds);
+    assert_eq!(network_interface.peers_to_ping(13), vec![peer_id]);
+    assert_eq!(network_interface.peers_to_ping(14), vec![peer_id]);
+}
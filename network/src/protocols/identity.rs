@@ -3,10 +3,11 @@
 
 //! Protocol used to exchange supported protocol information with a remote.
 
-use crate::protocols::wire::handshake::v1::HandshakeMsg;
+use crate::protocols::wire::handshake::{v1::HandshakeMsg, v2::HandshakeMsgV2};
 use aptos_netcore::framing::{read_u16frame, write_u16frame};
 use bytes::BytesMut;
 use futures::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use serde::{de::DeserializeOwned, Serialize};
 use std::io;
 
 /// The Handshake exchange protocol.
@@ -16,9 +17,30 @@ pub async fn exchange_handshake<T>(
 ) -> io::Result<HandshakeMsg>
 where
     T: AsyncRead + AsyncWrite + Unpin,
+{
+    exchange_msg(own_handshake, socket).await
+}
+
+/// The v2 Handshake exchange protocol. Must only be run after the v1 exchange, if
+/// both peers advertised support for it.
+pub async fn exchange_handshake_v2<T>(
+    own_handshake: &HandshakeMsgV2,
+    socket: &mut T,
+) -> io::Result<HandshakeMsgV2>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    exchange_msg(own_handshake, socket).await
+}
+
+/// Sends our message and reads the remote's, both serialized and length-prefixed.
+async fn exchange_msg<M, T>(own_msg: &M, socket: &mut T) -> io::Result<M>
+where
+    M: Serialize + DeserializeOwned,
+    T: AsyncRead + AsyncWrite + Unpin,
 {
     // Send serialized handshake message to remote peer.
-    let msg = bcs::to_bytes(own_handshake).map_err(|e| {
+    let msg = bcs::to_bytes(own_msg).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Failed to serialize identity msg: {}", e),
@@ -43,8 +65,11 @@ where
 mod tests {
     use crate::{
         protocols::{
-            identity::exchange_handshake,
-            wire::handshake::v1::{HandshakeMsg, MessagingProtocolVersion, ProtocolIdSet},
+            identity::{exchange_handshake, exchange_handshake_v2},
+            wire::handshake::{
+                v1::{HandshakeMsg, MessagingProtocolVersion, ProtocolIdSet},
+                v2::{Feature, FeatureSet, HandshakeMsgV2},
+            },
         },
         ProtocolId,
     };
@@ -185,4 +210,32 @@ mod tests {
 
         block_on(join(server, client));
     }
+
+    #[test]
+    fn handshake_v2() {
+        let (mut outbound, mut inbound) = MemorySocket::new_pair();
+        let server_handshake =
+            HandshakeMsgV2::new(FeatureSet::all_known(), &ProtocolIdSet::all_known());
+        let client_handshake = HandshakeMsgV2::new(
+            FeatureSet::from_iter([Feature::Qos]),
+            &ProtocolIdSet::from_iter([ProtocolId::HealthCheckerRpc]),
+        );
+
+        let server_handshake_clone = server_handshake.clone();
+        let client_handshake_clone = client_handshake.clone();
+        let server = async move {
+            let handshake = exchange_handshake_v2(&server_handshake, &mut inbound)
+                .await
+                .unwrap();
+            assert_eq!(handshake, client_handshake_clone);
+        };
+        let client = async move {
+            let handshake = exchange_handshake_v2(&client_handshake, &mut outbound)
+                .await
+                .unwrap();
+            assert_eq!(handshake, server_handshake_clone);
+        };
+
+        block_on(join(server, client));
+    }
 }
@@ -7,7 +7,9 @@
 pub mod direct_send;
 pub mod health_checker;
 pub mod identity;
+pub mod nat_traversal;
 pub mod network;
+pub mod peer_exchange;
 pub mod rpc;
 pub mod stream;
 pub mod wire;
@@ -0,0 +1,75 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    application::{interface::NetworkClient, storage::PeerMetadataStorage},
+    protocols::{
+        nat_traversal::{
+            NatTraversal, NatTraversalClient, NatTraversalMsg, NatTraversalNetworkEvents,
+        },
+        network::NetworkSender,
+        wire::handshake::v1::ProtocolId::NatTraversalRpc,
+    },
+};
+use aptos_config::network_id::NetworkContext;
+use aptos_crypto::x25519;
+use aptos_logger::prelude::*;
+use aptos_time_service::TimeService;
+use maplit::hashmap;
+use std::{sync::Arc, time::Duration};
+use tokio::runtime::Handle;
+
+pub struct NatTraversalBuilder {
+    service: Option<NatTraversal<NetworkClient<NatTraversalMsg>>>,
+    client: NatTraversalClient<NetworkClient<NatTraversalMsg>>,
+}
+
+impl NatTraversalBuilder {
+    pub fn new(
+        network_context: NetworkContext,
+        time_service: TimeService,
+        identity_pubkey: x25519::PublicKey,
+        observation_interval_ms: u64,
+        rpc_timeout_ms: u64,
+        network_sender: NetworkSender<NatTraversalMsg>,
+        network_rx: NatTraversalNetworkEvents,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+    ) -> Self {
+        let network_senders = hashmap! {network_context.network_id() => network_sender.clone()};
+        let network_client = NetworkClient::new(
+            vec![],
+            vec![NatTraversalRpc],
+            network_senders,
+            peer_metadata_storage,
+        );
+        let client = NatTraversalClient::new(
+            network_context,
+            network_client,
+            network_sender,
+            Duration::from_millis(rpc_timeout_ms),
+        );
+        let service = NatTraversal::new(
+            network_context,
+            time_service,
+            network_rx,
+            client.clone(),
+            identity_pubkey,
+            Duration::from_millis(observation_interval_ms),
+        );
+        Self {
+            service: Some(service),
+            client,
+        }
+    }
+
+    /// Returns a handle to query reflexive addresses and request hole punches
+    pub fn client(&self) -> NatTraversalClient<NetworkClient<NatTraversalMsg>> {
+        self.client.clone()
+    }
+
+    pub fn start(&mut self, executor: &Handle) {
+        if let Some(service) = self.service.take() {
+            spawn_named!("[Network] NAT", executor, service.start());
+        }
+    }
+}
@@ -0,0 +1,590 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Protocol used to traverse NATs
+//!
+//! Nodes behind a NAT (e.g., fullnodes run from home) can't accept inbound
+//! connections, as their listen address isn't reachable from the outside. The
+//! NatTraversal actor works around this as follows:
+//!
+//! 1. Reflexive address discovery: the node periodically asks a few connected peers for
+//! the address they observe its connection coming from. Addresses confirmed by at least
+//! [`MIN_CONFIRMING_PEERS`] peers become the node's reflexive (i.e., public) addresses.
+//! 2. Advertisement: the reflexive addresses are shared with peers when coordinating hole
+//! punches, and are exposed by [`NatTraversalClient::reflexive_addrs`].
+//! 3. Hole punching: to connect to a peer behind a NAT, the node asks a relay (i.e., a peer
+//! connected to both of them) to coordinate. The relay forwards the request to the target,
+//! which replies with its own reflexive addresses and immediately dials the initiator.
+//! The initiator dials the target as soon as the reply arrives, so that the simultaneous
+//! dials open the NAT mappings on both sides. If the initiator is publicly reachable, the
+//! dial of the target succeeds regardless (i.e., the relay falls back to a connection
+//! reversal).
+//!
+//! Note: the relay only coordinates the hole punch, application traffic is never relayed.
+//! Outbound connections don't reuse the listen port, so hole punching only succeeds
+//! behind NATs that keep the same mapping for all destinations (e.g., full cone NATs).
+use crate::{
+    application::{error::Error, interface::NetworkClientInterface, selection::RandomPeerSelector},
+    constants::NETWORK_CHANNEL_SIZE,
+    counters,
+    logging::NetworkSchema,
+    protocols::{
+        network::{
+            Event, NetworkApplicationConfig, NetworkClientConfig, NetworkEvents, NetworkSender,
+            NetworkServiceConfig,
+        },
+        rpc::error::RpcError,
+    },
+    transport::ConnectionOrigin,
+    ProtocolId,
+};
+use anyhow::anyhow;
+use aptos_channels::{aptos_channel, message_queues::QueueStyle};
+use aptos_config::{
+    config::HANDSHAKE_VERSION,
+    network_id::{NetworkContext, PeerNetworkId},
+};
+use aptos_crypto::x25519;
+use aptos_infallible::RwLock;
+use aptos_logger::prelude::*;
+use aptos_short_hex_str::AsShortHexStr;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use aptos_types::{
+    network_address::{parse_ip_tcp, NetworkAddress},
+    PeerId,
+};
+use bytes::Bytes;
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+pub mod builder;
+#[cfg(test)]
+mod test;
+
+/// The number of peers asked for our observed address in each round
+pub const MAX_OBSERVING_PEERS: usize = 4;
+/// The number of peers that must observe the same address before we consider
+/// it one of our reflexive addresses
+pub const MIN_CONFIRMING_PEERS: usize = 2;
+
+/// The interface from Network to NatTraversal layer.
+pub type NatTraversalNetworkEvents = NetworkEvents<NatTraversalMsg>;
+
+/// Returns a network application config for the NAT traversal client and service
+pub fn nat_traversal_network_config() -> NetworkApplicationConfig {
+    let direct_send_protocols = vec![]; // NAT traversal doesn't use direct send
+    let rpc_protocols = vec![ProtocolId::NatTraversalRpc];
+
+    let network_client_config =
+        NetworkClientConfig::new(direct_send_protocols.clone(), rpc_protocols.clone());
+    let network_service_config = NetworkServiceConfig::new(
+        direct_send_protocols,
+        rpc_protocols,
+        aptos_channel::Config::new(NETWORK_CHANNEL_SIZE)
+            .queue_style(QueueStyle::FIFO)
+            .counters(&counters::PENDING_NAT_TRAVERSAL_NETWORK_EVENTS),
+    );
+    NetworkApplicationConfig::new(network_client_config, network_service_config)
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum NatTraversalMsg {
+    /// Asks the peer for the address it observes our connection coming from
+    ObservedAddressRequest,
+    /// The address our connection is observed from, or `None` if the
+    /// connection was dialed by the responder
+    ObservedAddressResponse(Option<NetworkAddress>),
+    /// Sent by the initiator of a hole punch to the relay
+    HolePunchRequest(HolePunchRequest),
+    /// Forwarded by the relay to the target of a hole punch
+    HolePunchSync(HolePunchSync),
+    /// Sent by the target of a hole punch to the relay, and forwarded by the
+    /// relay to the initiator
+    HolePunchResponse(HolePunchResponse),
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HolePunchRequest {
+    /// The peer to connect to
+    pub target: PeerId,
+    /// The reflexive addresses of the initiator
+    pub addrs: Vec<NetworkAddress>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HolePunchSync {
+    /// The peer that requested the hole punch
+    pub initiator: PeerId,
+    /// The reflexive addresses of the initiator
+    pub addrs: Vec<NetworkAddress>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HolePunchResponse {
+    /// The reflexive addresses of the target
+    pub addrs: Vec<NetworkAddress>,
+}
+
+/// A handle used by other components to query our reflexive addresses and
+/// to request hole punches
+#[derive(Clone)]
+pub struct NatTraversalClient<NetworkClient> {
+    network_context: NetworkContext,
+    network_client: NetworkClient,
+    /// Used to dial the target of a hole punch
+    network_sender: NetworkSender<NatTraversalMsg>,
+    /// Our dialable reflexive addresses, updated by the [`NatTraversal`] actor
+    reflexive_addrs: Arc<RwLock<Vec<NetworkAddress>>>,
+    rpc_timeout: Duration,
+}
+
+impl<NetworkClient: NetworkClientInterface<NatTraversalMsg>> NatTraversalClient<NetworkClient> {
+    pub fn new(
+        network_context: NetworkContext,
+        network_client: NetworkClient,
+        network_sender: NetworkSender<NatTraversalMsg>,
+        rpc_timeout: Duration,
+    ) -> Self {
+        Self {
+            network_context,
+            network_client,
+            network_sender,
+            reflexive_addrs: Arc::new(RwLock::new(vec![])),
+            rpc_timeout,
+        }
+    }
+
+    /// Returns our reflexive addresses (including the noise and handshake
+    /// protocols), or an empty list if they aren't known yet
+    pub fn reflexive_addrs(&self) -> Vec<NetworkAddress> {
+        self.reflexive_addrs.read().clone()
+    }
+
+    /// Connects to the target peer by hole punching through the NATs of both
+    /// peers, using the given (connected) peer to coordinate.
+    pub async fn hole_punch(&self, relay: PeerId, target: PeerId) -> Result<(), Error> {
+        let request = NatTraversalMsg::HolePunchRequest(HolePunchRequest {
+            target,
+            addrs: self.reflexive_addrs(),
+        });
+        let relay = PeerNetworkId::new(self.network_context.network_id(), relay);
+        let result = match self
+            .network_client
+            .send_to_peer_rpc(request, self.rpc_timeout, relay)
+            .await
+        {
+            Ok(NatTraversalMsg::HolePunchResponse(response)) => {
+                dial_any(
+                    self.network_context,
+                    &self.network_client,
+                    &self.network_sender,
+                    target,
+                    response.addrs,
+                )
+                .await
+            },
+            Ok(msg) => Err(Error::UnexpectedError(format!(
+                "Unexpected hole punch response: {:?}",
+                msg
+            ))),
+            Err(error) => Err(error),
+        };
+        counters::nat_hole_punches(&self.network_context, result.is_ok());
+        result
+    }
+}
+
+/// Dials the given addresses of the peer in order, until a connection is
+/// established. Succeeds if the peer dialed us in the meantime.
+async fn dial_any<NetworkClient: NetworkClientInterface<NatTraversalMsg>>(
+    network_context: NetworkContext,
+    network_client: &NetworkClient,
+    network_sender: &NetworkSender<NatTraversalMsg>,
+    peer_id: PeerId,
+    addrs: Vec<NetworkAddress>,
+) -> Result<(), Error> {
+    let peer_network_id = PeerNetworkId::new(network_context.network_id(), peer_id);
+    let mut last_error = Error::NetworkError(format!("No addresses to dial for {}", peer_id));
+    for addr in addrs {
+        match network_sender.dial_peer(peer_id, addr.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                debug!(
+                    NetworkSchema::new(&network_context)
+                        .remote_peer(&peer_id)
+                        .network_address(&addr),
+                    error = ?error,
+                    "{} Failed to dial {} at {}: {}",
+                    network_context,
+                    peer_id.short_str(),
+                    addr,
+                    error
+                );
+                last_error = error.into();
+            },
+        }
+    }
+
+    // The simultaneous dial of the peer may have won the race
+    if network_client
+        .get_peer_metadata_storage()
+        .read(peer_network_id)
+        .is_some()
+    {
+        return Ok(());
+    }
+    Err(last_error)
+}
+
+/// The actor discovering our reflexive addresses and coordinating hole punches
+pub struct NatTraversal<NetworkClient> {
+    network_context: NetworkContext,
+    /// A handle to a time service for easily mocking time-related operations.
+    time_service: TimeService,
+    network_events: NatTraversalNetworkEvents,
+    client: NatTraversalClient<NetworkClient>,
+    /// Our public key, appended to the reflexive addresses so that peers can dial them
+    identity_pubkey: x25519::PublicKey,
+    /// The (ip, tcp) address each peer last observed our connection coming from
+    observations: HashMap<PeerId, NetworkAddress>,
+    /// Time we wait between each round of address observations.
+    observation_interval: Duration,
+}
+
+impl<NetworkClient: NetworkClientInterface<NatTraversalMsg> + Unpin + 'static>
+    NatTraversal<NetworkClient>
+{
+    /// Create new instance of the [`NatTraversal`] actor.
+    pub fn new(
+        network_context: NetworkContext,
+        time_service: TimeService,
+        network_events: NatTraversalNetworkEvents,
+        client: NatTraversalClient<NetworkClient>,
+        identity_pubkey: x25519::PublicKey,
+        observation_interval: Duration,
+    ) -> Self {
+        Self {
+            network_context,
+            time_service,
+            network_events,
+            client,
+            identity_pubkey,
+            observations: HashMap::new(),
+            observation_interval,
+        }
+    }
+
+    pub async fn start(mut self) {
+        let mut pending_observations = FuturesUnordered::new();
+        let mut pending_tasks: FuturesUnordered<BoxFuture<'static, ()>> = FuturesUnordered::new();
+        info!(
+            NetworkSchema::new(&self.network_context),
+            "{} NAT traversal actor started", self.network_context
+        );
+
+        let ticker = self.time_service.interval(self.observation_interval);
+        tokio::pin!(ticker);
+
+        loop {
+            futures::select! {
+                maybe_event = self.network_events.next() => {
+                    // Shutdown the actor when this network instance shuts
+                    // down. This happens when the `PeerManager` drops.
+                    let event = match maybe_event {
+                        Some(event) => event,
+                        None => break,
+                    };
+
+                    match event {
+                        Event::NewPeer(_) => {},
+                        Event::LostPeer(metadata) => {
+                            if self.observations.remove(&metadata.remote_peer_id).is_some() {
+                                self.update_reflexive_addrs();
+                            }
+                        }
+                        Event::RpcRequest(peer_id, msg, protocol, res_tx, _) => {
+                            if let Some(task) = self.handle_rpc_request(peer_id, msg, protocol, res_tx) {
+                                pending_tasks.push(task);
+                            }
+                        }
+                        Event::Message(peer_id, msg, _) => {
+                            warn!(
+                                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                                "{} Unexpected direct send from {} msg {:?}",
+                                self.network_context,
+                                peer_id,
+                                msg,
+                            );
+                        }
+                        Event::StreamingRpcRequest(peer_id, msg, _, _) => {
+                            warn!(
+                                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                                "{} Unexpected streaming rpc from {} msg {:?}",
+                                self.network_context,
+                                peer_id,
+                                msg,
+                            );
+                        }
+                    }
+                }
+                _ = ticker.select_next_some() => {
+                    for peer_id in self.observing_peers() {
+                        pending_observations.push(Self::request_observed_address(
+                            self.network_context,
+                            self.client.network_client.clone(),
+                            peer_id,
+                            self.client.rpc_timeout,
+                        ));
+                    }
+                }
+                (peer_id, result) = pending_observations.select_next_some() => {
+                    self.handle_observed_address(peer_id, result);
+                }
+                _ = pending_tasks.select_next_some() => {}
+            }
+        }
+        warn!(
+            NetworkSchema::new(&self.network_context),
+            "{} NAT traversal actor terminated", self.network_context
+        );
+    }
+
+    /// Returns the connected peers to ask for our observed address
+    fn observing_peers(&self) -> Vec<PeerId> {
+        let network_id = self.network_context.network_id();
+        self.client
+            .network_client
+            .get_available_peers_ranked(&RandomPeerSelector)
+            .into_iter()
+            .filter(|peer| peer.network_id() == network_id)
+            .map(|peer| peer.peer_id())
+            .take(MAX_OBSERVING_PEERS)
+            .collect()
+    }
+
+    async fn request_observed_address(
+        network_context: NetworkContext,
+        network_client: NetworkClient,
+        peer_id: PeerId,
+        rpc_timeout: Duration,
+    ) -> (PeerId, Result<Option<NetworkAddress>, Error>) {
+        let peer_network_id = PeerNetworkId::new(network_context.network_id(), peer_id);
+        let result = network_client
+            .send_to_peer_rpc(
+                NatTraversalMsg::ObservedAddressRequest,
+                rpc_timeout,
+                peer_network_id,
+            )
+            .await
+            .and_then(|msg| match msg {
+                NatTraversalMsg::ObservedAddressResponse(addr) => Ok(addr),
+                msg => Err(Error::UnexpectedError(format!(
+                    "Unexpected observed address response: {:?}",
+                    msg
+                ))),
+            });
+        (peer_id, result)
+    }
+
+    fn handle_observed_address(
+        &mut self,
+        peer_id: PeerId,
+        result: Result<Option<NetworkAddress>, Error>,
+    ) {
+        match result {
+            Ok(Some(addr)) => {
+                self.observations.insert(peer_id, addr);
+            },
+            Ok(None) => {
+                self.observations.remove(&peer_id);
+            },
+            Err(error) => {
+                debug!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                    error = ?error,
+                    "{} Failed to get observed address from {}: {}",
+                    self.network_context,
+                    peer_id.short_str(),
+                    error
+                );
+                return;
+            },
+        }
+        self.update_reflexive_addrs();
+    }
+
+    /// Recomputes our reflexive addresses from the current observations
+    fn update_reflexive_addrs(&self) {
+        let mut confirmations: HashMap<&NetworkAddress, usize> = HashMap::new();
+        for addr in self.observations.values() {
+            *confirmations.entry(addr).or_default() += 1;
+        }
+        let mut confirmed: Vec<_> = confirmations
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_CONFIRMING_PEERS)
+            .collect();
+        // Prefer the addresses observed by the most peers
+        confirmed.sort_by(|(addr_a, count_a), (addr_b, count_b)| {
+            count_b
+                .cmp(count_a)
+                .then_with(|| addr_a.to_string().cmp(&addr_b.to_string()))
+        });
+        let reflexive_addrs: Vec<_> = confirmed
+            .into_iter()
+            .map(|(addr, _)| {
+                addr.clone()
+                    .append_prod_protos(self.identity_pubkey, HANDSHAKE_VERSION)
+            })
+            .collect();
+
+        let mut current_addrs = self.client.reflexive_addrs.write();
+        if *current_addrs != reflexive_addrs {
+            info!(
+                NetworkSchema::new(&self.network_context),
+                "{} Reflexive addresses changed to {:?}", self.network_context, reflexive_addrs
+            );
+            *current_addrs = reflexive_addrs;
+        }
+    }
+
+    /// Returns the (ip, tcp) address the peer's connection comes from, if the
+    /// peer dialed us
+    fn observed_address(&self, peer_id: PeerId) -> Option<NetworkAddress> {
+        let peer_network_id = PeerNetworkId::new(self.network_context.network_id(), peer_id);
+        let connection = self
+            .client
+            .network_client
+            .get_peer_metadata_storage()
+            .read(peer_network_id)?
+            .active_connection;
+        if connection.origin != ConnectionOrigin::Inbound {
+            return None;
+        }
+        parse_ip_tcp(connection.addr.as_slice())
+            .map(|((ip, port), _)| NetworkAddress::from(SocketAddr::new(ip, port)))
+    }
+
+    /// Handles the given rpc request, returning a task to run for the
+    /// requests that can't be answered right away
+    fn handle_rpc_request(
+        &self,
+        peer_id: PeerId,
+        msg: NatTraversalMsg,
+        protocol: ProtocolId,
+        res_tx: oneshot::Sender<Result<Bytes, RpcError>>,
+    ) -> Option<BoxFuture<'static, ()>> {
+        match msg {
+            NatTraversalMsg::ObservedAddressRequest => {
+                let response =
+                    NatTraversalMsg::ObservedAddressResponse(self.observed_address(peer_id));
+                send_response(&self.network_context, protocol, res_tx, Ok(response));
+                None
+            },
+            NatTraversalMsg::HolePunchRequest(request) => {
+                Some(self.relay_hole_punch(peer_id, request, protocol, res_tx))
+            },
+            NatTraversalMsg::HolePunchSync(sync) => {
+                let response = NatTraversalMsg::HolePunchResponse(HolePunchResponse {
+                    addrs: self.client.reflexive_addrs(),
+                });
+                send_response(&self.network_context, protocol, res_tx, Ok(response));
+
+                // Dial the initiator right away, so that our NAT lets its dials through
+                let network_context = self.network_context;
+                let client = self.client.clone();
+                Some(
+                    async move {
+                        let result = dial_any(
+                            network_context,
+                            &client.network_client,
+                            &client.network_sender,
+                            sync.initiator,
+                            sync.addrs,
+                        )
+                        .await;
+                        if let Err(error) = result {
+                            debug!(
+                                NetworkSchema::new(&network_context).remote_peer(&sync.initiator),
+                                error = ?error,
+                                "{} Failed to dial hole punch initiator {}: {}",
+                                network_context,
+                                sync.initiator.short_str(),
+                                error
+                            );
+                        }
+                    }
+                    .boxed(),
+                )
+            },
+            msg => {
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                    rpc_message = msg,
+                    "{} Unexpected RPC message from {}",
+                    self.network_context,
+                    peer_id
+                );
+                None
+            },
+        }
+    }
+
+    /// Forwards the hole punch request to the target, and the target's
+    /// response back to the initiator
+    fn relay_hole_punch(
+        &self,
+        initiator: PeerId,
+        request: HolePunchRequest,
+        protocol: ProtocolId,
+        res_tx: oneshot::Sender<Result<Bytes, RpcError>>,
+    ) -> BoxFuture<'static, ()> {
+        let network_context = self.network_context;
+        let network_client = self.client.network_client.clone();
+        let rpc_timeout = self.client.rpc_timeout;
+        async move {
+            let target = PeerNetworkId::new(network_context.network_id(), request.target);
+            let sync = NatTraversalMsg::HolePunchSync(HolePunchSync {
+                initiator,
+                addrs: request.addrs,
+            });
+            let response = match network_client
+                .send_to_peer_rpc(sync, rpc_timeout, target)
+                .await
+            {
+                Ok(response @ NatTraversalMsg::HolePunchResponse(_)) => Ok(response),
+                Ok(_) => Err(RpcError::InvalidRpcResponse),
+                Err(error) => Err(RpcError::Error(anyhow!(
+                    "Failed to relay hole punch to {}: {}",
+                    request.target,
+                    error
+                ))),
+            };
+            send_response(&network_context, protocol, res_tx, response);
+        }
+        .boxed()
+    }
+}
+
+fn send_response(
+    network_context: &NetworkContext,
+    protocol: ProtocolId,
+    res_tx: oneshot::Sender<Result<Bytes, RpcError>>,
+    response: Result<NatTraversalMsg, RpcError>,
+) {
+    let response = response.and_then(|msg| {
+        protocol.to_bytes(&msg).map(Bytes::from).map_err(|e| {
+            warn!(
+                NetworkSchema::new(network_context),
+                error = ?e,
+                "{} Unable to serialize NAT traversal response: {}", network_context, e
+            );
+            RpcError::Error(e)
+        })
+    });
+    let _ = res_tx.send(response);
+}
@@ -0,0 +1,342 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{
+    application::{interface::NetworkClient, storage::PeerMetadataStorage, types::PeerInfo},
+    peer_manager::{
+        conn_notifs_channel, ConnectionRequest, ConnectionRequestSender, PeerManagerNotification,
+        PeerManagerRequest, PeerManagerRequestSender,
+    },
+    protocols::{
+        network::{NewNetworkEvents, NewNetworkSender},
+        rpc::InboundRpcRequest,
+        wire::handshake::v1::{ProtocolId::NatTraversalRpc, ProtocolIdSet},
+    },
+    transport::ConnectionMetadata,
+};
+use aptos_channels::aptos_channel;
+use aptos_config::config::PeerRole;
+use aptos_crypto::{test_utils::TEST_SEED, Uniform};
+use aptos_time_service::MockTimeService;
+use futures::future;
+use maplit::hashmap;
+use rand::{rngs::StdRng, SeedableRng};
+use std::iter::FromIterator;
+
+const OBSERVATION_INTERVAL: Duration = Duration::from_secs(1);
+const RPC_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct TestHarness {
+    mock_time: MockTimeService,
+    peer_mgr_reqs_rx: aptos_channel::Receiver<(PeerId, ProtocolId), PeerManagerRequest>,
+    peer_mgr_notifs_tx: aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>,
+    connection_reqs_rx: aptos_channel::Receiver<PeerId, ConnectionRequest>,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    client: NatTraversalClient<NetworkClient<NatTraversalMsg>>,
+    identity_pubkey: x25519::PublicKey,
+    // Keeps the connection notifications channel open
+    _connection_notifs_tx: conn_notifs_channel::Sender,
+}
+
+impl TestHarness {
+    fn new() -> (Self, NatTraversal<NetworkClient<NatTraversalMsg>>) {
+        ::aptos_logger::Logger::init_for_testing();
+        let mock_time = TimeService::mock();
+
+        let (peer_mgr_reqs_tx, peer_mgr_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+        let (connection_reqs_tx, connection_reqs_rx) =
+            aptos_channel::new(QueueStyle::FIFO, 1, None);
+        let (peer_mgr_notifs_tx, peer_mgr_notifs_rx) =
+            aptos_channel::new(QueueStyle::FIFO, 1, None);
+        let (connection_notifs_tx, connection_notifs_rx) = conn_notifs_channel::new();
+
+        let network_sender = NetworkSender::new(
+            PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+            ConnectionRequestSender::new(connection_reqs_tx),
+        );
+        let network_events =
+            NatTraversalNetworkEvents::new(peer_mgr_notifs_rx, connection_notifs_rx);
+
+        let network_context = NetworkContext::mock();
+        let peer_metadata_storage = PeerMetadataStorage::test();
+        let network_client = NetworkClient::new(
+            vec![],
+            vec![NatTraversalRpc],
+            hashmap! {network_context.network_id() => network_sender.clone()},
+            peer_metadata_storage.clone(),
+        );
+        let client =
+            NatTraversalClient::new(network_context, network_client, network_sender, RPC_TIMEOUT);
+
+        let mut rng = StdRng::from_seed(TEST_SEED);
+        let identity_pubkey = x25519::PrivateKey::generate(&mut rng).public_key();
+        let nat_traversal = NatTraversal::new(
+            network_context,
+            mock_time.clone(),
+            network_events,
+            client.clone(),
+            identity_pubkey,
+            OBSERVATION_INTERVAL,
+        );
+
+        (
+            Self {
+                mock_time: mock_time.into_mock(),
+                peer_mgr_reqs_rx,
+                peer_mgr_notifs_tx,
+                connection_reqs_rx,
+                peer_metadata_storage,
+                client,
+                identity_pubkey,
+                _connection_notifs_tx: connection_notifs_tx,
+            },
+            nat_traversal,
+        )
+    }
+
+    /// Adds a connected peer, where the connection has the given origin and address
+    fn add_peer(&self, peer_id: PeerId, origin: ConnectionOrigin, addr: &str) {
+        let mut connection_metadata =
+            ConnectionMetadata::mock_with_role_and_origin(peer_id, PeerRole::Unknown, origin);
+        connection_metadata.addr = addr.parse().unwrap();
+        connection_metadata.application_protocols = ProtocolIdSet::from_iter(vec![NatTraversalRpc]);
+        self.peer_metadata_storage.insert(
+            PeerNetworkId::new(NetworkContext::mock().network_id(), peer_id),
+            PeerInfo::new(connection_metadata),
+        );
+    }
+
+    async fn trigger_observation(&self) {
+        self.mock_time.advance_async(OBSERVATION_INTERVAL).await;
+    }
+
+    /// Expects an outbound rpc, and returns its destination and message
+    async fn expect_rpc(
+        &mut self,
+    ) -> (
+        PeerId,
+        NatTraversalMsg,
+        oneshot::Sender<Result<Bytes, RpcError>>,
+    ) {
+        let req = self.peer_mgr_reqs_rx.next().await.unwrap();
+        let (peer_id, rpc_req) = match req {
+            PeerManagerRequest::SendRpc(peer_id, rpc_req) => (peer_id, rpc_req),
+            _ => panic!("Unexpected PeerManagerRequest: {:?}", req),
+        };
+        assert_eq!(rpc_req.protocol_id, NatTraversalRpc);
+        let msg = bcs::from_bytes(&rpc_req.data).unwrap();
+        (peer_id, msg, rpc_req.res_tx)
+    }
+
+    async fn send_inbound_rpc(
+        &mut self,
+        peer_id: PeerId,
+        msg: NatTraversalMsg,
+    ) -> oneshot::Receiver<Result<Bytes, RpcError>> {
+        let data = bcs::to_bytes(&msg).unwrap().into();
+        let (res_tx, res_rx) = oneshot::channel();
+        let inbound_rpc_req = InboundRpcRequest {
+            protocol_id: NatTraversalRpc,
+            data,
+            res_tx,
+            deadline: None,
+            correlation_id: 0,
+        };
+        let (delivered_tx, delivered_rx) = oneshot::channel();
+        self.peer_mgr_notifs_tx
+            .push_with_feedback(
+                (peer_id, NatTraversalRpc),
+                PeerManagerNotification::RecvRpc(peer_id, inbound_rpc_req),
+                Some(delivered_tx),
+            )
+            .unwrap();
+        delivered_rx.await.unwrap();
+        res_rx
+    }
+
+    async fn expect_dial(&mut self, expected_peer_id: PeerId, expected_addr: &NetworkAddress) {
+        let req = self.connection_reqs_rx.next().await.unwrap();
+        let (peer_id, addr, res_tx) = match req {
+            ConnectionRequest::DialPeer(peer_id, addr, res_tx) => (peer_id, addr, res_tx),
+            _ => panic!("Unexpected ConnectionRequest: {:?}", req),
+        };
+        assert_eq!(peer_id, expected_peer_id);
+        assert_eq!(&addr, expected_addr);
+        res_tx.send(Ok(())).unwrap();
+    }
+}
+
+async fn expect_response(res_rx: oneshot::Receiver<Result<Bytes, RpcError>>) -> NatTraversalMsg {
+    let res_data = res_rx.await.unwrap().unwrap();
+    bcs::from_bytes(&res_data).unwrap()
+}
+
+fn respond(res_tx: oneshot::Sender<Result<Bytes, RpcError>>, msg: NatTraversalMsg) {
+    res_tx
+        .send(Ok(bcs::to_bytes(&msg).unwrap().into()))
+        .unwrap();
+}
+
+#[tokio::test]
+async fn observed_address() {
+    let (mut harness, nat_traversal) = TestHarness::new();
+
+    let test = async move {
+        // Peers that dialed us get the address their connection comes from
+        let inbound_peer = PeerId::random();
+        harness.add_peer(
+            inbound_peer,
+            ConnectionOrigin::Inbound,
+            "/ip4/1.2.3.4/tcp/5678/noise-ik/080e287879c918794170e258bfaddd75acac5b3e350419044655e4983a487120/handshake/0",
+        );
+        let res_rx = harness
+            .send_inbound_rpc(inbound_peer, NatTraversalMsg::ObservedAddressRequest)
+            .await;
+        assert_eq!(
+            expect_response(res_rx).await,
+            NatTraversalMsg::ObservedAddressResponse(Some(
+                "/ip4/1.2.3.4/tcp/5678".parse().unwrap()
+            ))
+        );
+
+        // Peers that we dialed don't get an address
+        let outbound_peer = PeerId::random();
+        harness.add_peer(
+            outbound_peer,
+            ConnectionOrigin::Outbound,
+            "/ip4/1.2.3.5/tcp/6180",
+        );
+        let res_rx = harness
+            .send_inbound_rpc(outbound_peer, NatTraversalMsg::ObservedAddressRequest)
+            .await;
+        assert_eq!(
+            expect_response(res_rx).await,
+            NatTraversalMsg::ObservedAddressResponse(None)
+        );
+    };
+    future::join(nat_traversal.start(), test).await;
+}
+
+#[tokio::test]
+async fn reflexive_address_confirmation() {
+    let (mut harness, nat_traversal) = TestHarness::new();
+
+    let test = async move {
+        let observed_addr: NetworkAddress = "/ip4/1.2.3.4/tcp/5678".parse().unwrap();
+        for _ in 0..MIN_CONFIRMING_PEERS {
+            harness.add_peer(
+                PeerId::random(),
+                ConnectionOrigin::Outbound,
+                "/ip4/1.2.3.5/tcp/6180",
+            );
+        }
+
+        // A single observation isn't enough to confirm the address
+        harness.trigger_observation().await;
+        let (_, msg, res_tx) = harness.expect_rpc().await;
+        assert_eq!(msg, NatTraversalMsg::ObservedAddressRequest);
+        respond(
+            res_tx,
+            NatTraversalMsg::ObservedAddressResponse(Some(observed_addr.clone())),
+        );
+        let (_, msg, res_tx) = harness.expect_rpc().await;
+        assert_eq!(msg, NatTraversalMsg::ObservedAddressRequest);
+        respond(res_tx, NatTraversalMsg::ObservedAddressResponse(None));
+
+        // Once enough peers observe the same address, it becomes dialable
+        harness.trigger_observation().await;
+        for _ in 0..MIN_CONFIRMING_PEERS {
+            let (_, msg, res_tx) = harness.expect_rpc().await;
+            assert_eq!(msg, NatTraversalMsg::ObservedAddressRequest);
+            respond(
+                res_tx,
+                NatTraversalMsg::ObservedAddressResponse(Some(observed_addr.clone())),
+            );
+        }
+        let expected_addr =
+            observed_addr.append_prod_protos(harness.identity_pubkey, HANDSHAKE_VERSION);
+        while harness.client.reflexive_addrs().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(harness.client.reflexive_addrs(), vec![expected_addr]);
+    };
+    future::join(nat_traversal.start(), test).await;
+}
+
+#[tokio::test]
+async fn relay_hole_punch() {
+    let (mut harness, nat_traversal) = TestHarness::new();
+
+    let test = async move {
+        let initiator = PeerId::random();
+        let target = PeerId::random();
+        harness.add_peer(
+            initiator,
+            ConnectionOrigin::Inbound,
+            "/ip4/1.2.3.4/tcp/5678",
+        );
+        harness.add_peer(target, ConnectionOrigin::Inbound, "/ip4/1.2.3.5/tcp/5678");
+        let initiator_addrs = vec!["/ip4/1.2.3.4/tcp/5678".parse().unwrap()];
+        let target_addrs = vec!["/ip4/1.2.3.5/tcp/5678".parse().unwrap()];
+
+        // The request is forwarded to the target
+        let res_rx = harness
+            .send_inbound_rpc(
+                initiator,
+                NatTraversalMsg::HolePunchRequest(HolePunchRequest {
+                    target,
+                    addrs: initiator_addrs.clone(),
+                }),
+            )
+            .await;
+        let (peer_id, msg, res_tx) = harness.expect_rpc().await;
+        assert_eq!(peer_id, target);
+        assert_eq!(
+            msg,
+            NatTraversalMsg::HolePunchSync(HolePunchSync {
+                initiator,
+                addrs: initiator_addrs,
+            })
+        );
+
+        // And the target's response is forwarded to the initiator
+        let response = NatTraversalMsg::HolePunchResponse(HolePunchResponse {
+            addrs: target_addrs,
+        });
+        respond(res_tx, response.clone());
+        assert_eq!(expect_response(res_rx).await, response);
+    };
+    future::join(nat_traversal.start(), test).await;
+}
+
+#[tokio::test]
+async fn hole_punch_target_dials_initiator() {
+    let (mut harness, nat_traversal) = TestHarness::new();
+
+    let test = async move {
+        let relay = PeerId::random();
+        let initiator = PeerId::random();
+        harness.add_peer(relay, ConnectionOrigin::Outbound, "/ip4/1.2.3.4/tcp/6180");
+        let initiator_addr: NetworkAddress = "/ip4/1.2.3.5/tcp/5678".parse().unwrap();
+
+        // The target responds with its (unknown) reflexive addresses
+        let res_rx = harness
+            .send_inbound_rpc(
+                relay,
+                NatTraversalMsg::HolePunchSync(HolePunchSync {
+                    initiator,
+                    addrs: vec![initiator_addr.clone()],
+                }),
+            )
+            .await;
+        assert_eq!(
+            expect_response(res_rx).await,
+            NatTraversalMsg::HolePunchResponse(HolePunchResponse { addrs: vec![] })
+        );
+
+        // And dials the initiator
+        harness.expect_dial(initiator, &initiator_addr).await;
+    };
+    future::join(nat_traversal.start(), test).await;
+}
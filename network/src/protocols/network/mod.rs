@@ -3,13 +3,15 @@
 
 //! Convenience Network API for Aptos
 
-pub use crate::protocols::rpc::error::RpcError;
+pub use crate::protocols::{rpc::error::RpcError, wire::messaging::v1::CorrelationId};
 use crate::{
+    application::{rate_limit::InboundRateLimitConfig, response_cache::RpcResponseCacheConfig},
     error::NetworkError,
     peer_manager::{
         ConnectionNotification, ConnectionRequestSender, PeerManagerNotification,
         PeerManagerRequestSender,
     },
+    protocols::wire::messaging::v1::MessagePriority,
     transport::ConnectionMetadata,
     ProtocolId,
 };
@@ -19,14 +21,22 @@ use aptos_short_hex_str::AsShortHexStr;
 use aptos_types::{network_address::NetworkAddress, PeerId};
 use bytes::Bytes;
 use futures::{
-    channel::oneshot,
+    channel::{mpsc, oneshot},
     future,
     stream::{FilterMap, FusedStream, Map, Select, Stream, StreamExt},
     task::{Context, Poll},
 };
 use pin_project::pin_project;
 use serde::{de::DeserializeOwned, Serialize};
-use std::{cmp::min, fmt::Debug, marker::PhantomData, pin::Pin, time::Duration};
+use std::{
+    cmp::min,
+    fmt::Debug,
+    marker::PhantomData,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+pub mod versioned;
 
 pub trait Message: DeserializeOwned + Serialize {}
 impl<T: DeserializeOwned + Serialize> Message for T {}
@@ -42,8 +52,10 @@ impl<T: DeserializeOwned + Serialize> Message for T {}
 /// [`PeerNotification`]: crate::peer::PeerNotification
 #[derive(Debug)]
 pub enum Event<TMessage> {
-    /// New inbound direct-send message from peer.
-    Message(PeerId, TMessage),
+    /// New inbound direct-send message from peer. The last field is the
+    /// message's correlation id, which identifies it in the network logs of
+    /// both peers.
+    Message(PeerId, TMessage, CorrelationId),
     /// New inbound rpc request. The request is fulfilled by sending the
     /// serialized response `Bytes` over the `oneshot::Sender`, where the network
     /// layer will handle sending the response over-the-wire.
@@ -52,6 +64,16 @@ pub enum Event<TMessage> {
         TMessage,
         ProtocolId,
         oneshot::Sender<Result<Bytes, RpcError>>,
+        RpcMetadata,
+    ),
+    /// New inbound streaming rpc request. The request is fulfilled by sending
+    /// each serialized response chunk over the `mpsc::Sender`, and the stream
+    /// is ended by dropping it.
+    StreamingRpcRequest(
+        PeerId,
+        TMessage,
+        ProtocolId,
+        mpsc::Sender<Result<Bytes, RpcError>>,
     ),
     /// Peer which we have a newly established connection with.
     NewPeer(ConnectionMetadata),
@@ -59,16 +81,40 @@ pub enum Event<TMessage> {
     LostPeer(ConnectionMetadata),
 }
 
+/// The metadata of an inbound rpc request
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RpcMetadata {
+    /// The time after which the sender no longer waits for the response (if the
+    /// sender propagated its deadline)
+    pub deadline: Option<Instant>,
+    /// The correlation id of the request, which identifies it in the network
+    /// logs of both peers
+    pub correlation_id: CorrelationId,
+}
+
+impl RpcMetadata {
+    /// Returns if the sender no longer waits for the response as of `now`
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.deadline.map_or(false, |deadline| deadline <= now)
+    }
+}
+
 /// impl PartialEq for simpler testing
 impl<TMessage: PartialEq> PartialEq for Event<TMessage> {
     fn eq(&self, other: &Event<TMessage>) -> bool {
         use Event::*;
         match (self, other) {
-            (Message(pid1, msg1), Message(pid2, msg2)) => pid1 == pid2 && msg1 == msg2,
+            // ignore correlation ids in comparison
+            (Message(pid1, msg1, _), Message(pid2, msg2, _)) => pid1 == pid2 && msg1 == msg2,
             // ignore oneshot::Sender in comparison
-            (RpcRequest(pid1, msg1, proto1, _), RpcRequest(pid2, msg2, proto2, _)) => {
+            (RpcRequest(pid1, msg1, proto1, _, _), RpcRequest(pid2, msg2, proto2, _, _)) => {
                 pid1 == pid2 && msg1 == msg2 && proto1 == proto2
             },
+            // ignore mpsc::Sender in comparison
+            (
+                StreamingRpcRequest(pid1, msg1, proto1, _),
+                StreamingRpcRequest(pid2, msg2, proto2, _),
+            ) => pid1 == pid2 && msg1 == msg2 && proto1 == proto2,
             (NewPeer(metadata1), NewPeer(metadata2)) => metadata1 == metadata2,
             (LostPeer(metadata1), LostPeer(metadata2)) => metadata1 == metadata2,
             _ => false,
@@ -83,6 +129,15 @@ pub struct NetworkClientConfig {
     pub direct_send_protocols_and_preferences: Vec<ProtocolId>,
     /// RPC protocols for the application (sorted by preference, highest to lowest)
     pub rpc_protocols_and_preferences: Vec<ProtocolId>,
+    /// The window within which identical broadcasts to the same peer are
+    /// skipped (if any)
+    pub broadcast_dedup_window: Option<Duration>,
+    /// The maximum delay by which small outbound messages are held back to be
+    /// batched with others (if batching is enabled)
+    pub message_batching_max_delay: Option<Duration>,
+    /// Whether the outbound messages are queued (and written) apart from the
+    /// messages of other (non-isolated) protocols
+    pub isolated: bool,
 }
 
 impl NetworkClientConfig {
@@ -93,8 +148,36 @@ impl NetworkClientConfig {
         Self {
             direct_send_protocols_and_preferences,
             rpc_protocols_and_preferences,
+            broadcast_dedup_window: None,
+            message_batching_max_delay: None,
+            isolated: false,
         }
     }
+
+    /// Skips re-sending identical broadcasts to the same peer within the window
+    pub fn broadcast_dedup(mut self, window: Duration) -> Self {
+        self.broadcast_dedup_window = Some(window);
+        self
+    }
+
+    /// Coalesces the small outbound messages of the client's protocols to the
+    /// same peer into a single write, holding them back by at most the given
+    /// delay (e.g., a few hundred microseconds). For chatty protocols with small
+    /// messages, e.g., health checks and vote gossip.
+    pub fn batch_small_messages(mut self, max_delay: Duration) -> Self {
+        self.message_batching_max_delay = Some(max_delay);
+        self
+    }
+
+    /// Isolates the outbound messages of the client's protocols from the bulk
+    /// traffic of other protocols (e.g., state sync chunks): they have their own
+    /// outbound queue per peer, and are written to the wire ahead of the bulk
+    /// messages. For latency-critical protocols, e.g., consensus. Messages that
+    /// don't fit in a single frame are still streamed along with the bulk ones.
+    pub fn isolated(mut self) -> Self {
+        self.isolated = true;
+        self
+    }
 }
 
 /// Configuration needed for the service side of AptosNet applications
@@ -106,6 +189,19 @@ pub struct NetworkServiceConfig {
     pub rpc_protocols_and_preferences: Vec<ProtocolId>,
     /// The inbound queue config (from the network to the application)
     pub inbound_queue_config: aptos_channel::Config,
+    /// The window within which identical direct-send messages from the same
+    /// peer are dropped (if any)
+    pub inbound_dedup_window: Option<Duration>,
+    /// The window of the nonces within which replayed direct-send messages are
+    /// dropped (if replay protection is enabled)
+    pub replay_protection_window: Option<Duration>,
+    /// The per-peer rate limits on the inbound messages of each protocol (if any)
+    pub inbound_rate_limit: Option<InboundRateLimitConfig>,
+    /// The cache of the responses to each rpc protocol (if any)
+    pub rpc_response_cache: Option<RpcResponseCacheConfig>,
+    /// The maximum number of inbound rpcs of each protocol that are processed
+    /// concurrently (if limited)
+    pub max_concurrent_inbound_rpcs: Option<usize>,
 }
 
 impl NetworkServiceConfig {
@@ -118,8 +214,54 @@ impl NetworkServiceConfig {
             direct_send_protocols_and_preferences,
             rpc_protocols_and_preferences,
             inbound_queue_config,
+            inbound_dedup_window: None,
+            replay_protection_window: None,
+            inbound_rate_limit: None,
+            rpc_response_cache: None,
+            max_concurrent_inbound_rpcs: None,
         }
     }
+
+    /// Drops identical direct-send messages from the same peer within the
+    /// window, before they are queued for the application
+    pub fn inbound_dedup(mut self, window: Duration) -> Self {
+        self.inbound_dedup_window = Some(window);
+        self
+    }
+
+    /// Drops the direct-send messages that were already received from the same
+    /// peer (by nonce), for at-most-once delivery. Messages reordered by more
+    /// than the window are dropped too. Acked direct-sends don't carry nonces,
+    /// so they're rejected by peers that support replay protection.
+    pub fn replay_protection(mut self, window: Duration) -> Self {
+        self.replay_protection_window = Some(window);
+        self
+    }
+
+    /// Limits the messages received from each peer with each of the service's
+    /// protocols, and penalizes the peers that repeatedly exceed the limits
+    pub fn inbound_rate_limit(mut self, config: InboundRateLimitConfig) -> Self {
+        self.inbound_rate_limit = Some(config);
+        self
+    }
+
+    /// Serves identical rpc requests (with any of the service's rpc protocols)
+    /// from a cache of the recent responses, without handing them to the
+    /// application. Only for protocols whose responses don't depend on the
+    /// requesting peer.
+    pub fn rpc_response_cache(mut self, config: RpcResponseCacheConfig) -> Self {
+        self.rpc_response_cache = Some(config);
+        self
+    }
+
+    /// Limits the number of inbound rpcs of each of the service's protocols
+    /// that are processed concurrently (across all peers). Requests beyond the
+    /// limit are rejected right away with a busy error, rather than queued
+    /// past their deadlines.
+    pub fn max_concurrent_inbound_rpcs(mut self, max_concurrent_rpcs: usize) -> Self {
+        self.max_concurrent_inbound_rpcs = Some(max_concurrent_rpcs);
+        self
+    }
 }
 
 /// Configuration needed for AptosNet applications to register with the network
@@ -211,11 +353,28 @@ fn peer_mgr_notif_to_event<TMessage: Message>(
 ) -> future::Ready<Option<Event<TMessage>>> {
     let maybe_event = match notif {
         PeerManagerNotification::RecvRpc(peer_id, rpc_req) => {
-            request_to_network_event(peer_id, &rpc_req)
-                .map(|msg| Event::RpcRequest(peer_id, msg, rpc_req.protocol_id, rpc_req.res_tx))
+            // Don't hand requests that expired while queued to the handler, as
+            // the sender no longer waits for their response
+            let metadata = RpcMetadata {
+                deadline: rpc_req.deadline,
+                correlation_id: rpc_req.correlation_id,
+            };
+            if metadata.is_expired(Instant::now()) {
+                let _ = rpc_req.res_tx.send(Err(RpcError::Expired));
+                return future::ready(None);
+            }
+            request_to_network_event(peer_id, &rpc_req).map(|msg| {
+                Event::RpcRequest(peer_id, msg, rpc_req.protocol_id, rpc_req.res_tx, metadata)
+            })
         },
         PeerManagerNotification::RecvMessage(peer_id, request) => {
-            request_to_network_event(peer_id, &request).map(|msg| Event::Message(peer_id, msg))
+            request_to_network_event(peer_id, &request)
+                .map(|msg| Event::Message(peer_id, msg, request.correlation_id))
+        },
+        PeerManagerNotification::RecvStreamingRpc(peer_id, rpc_req) => {
+            request_to_network_event(peer_id, &rpc_req).map(|msg| {
+                Event::StreamingRpcRequest(peer_id, msg, rpc_req.protocol_id, rpc_req.res_tx)
+            })
         },
     };
     future::ready(maybe_event)
@@ -310,6 +469,61 @@ impl<TMessage> NetworkSender<TMessage> {
         self.connection_reqs_tx.disconnect_peer(peer).await?;
         Ok(())
     }
+
+    /// Request that a given Peer be gracefully disconnected, once the pending
+    /// requests completed (or the drain timeout elapsed), and synchronously
+    /// wait for the request to be performed.
+    pub async fn disconnect_peer_gracefully(
+        &self,
+        peer: PeerId,
+        reason: String,
+        drain_timeout: Duration,
+    ) -> Result<(), NetworkError> {
+        self.connection_reqs_tx
+            .disconnect_peer_gracefully(peer, reason, drain_timeout)
+            .await?;
+        Ok(())
+    }
+
+    /// Request that all Peers be gracefully disconnected, and that no new
+    /// connections be accepted, and wait for all connections to be closed.
+    pub async fn drain_all(
+        &self,
+        reason: String,
+        drain_timeout: Duration,
+    ) -> Result<(), NetworkError> {
+        self.connection_reqs_tx
+            .drain_all(reason, drain_timeout)
+            .await?;
+        Ok(())
+    }
+
+    /// Send a pre-serialized message to a single recipient. The data must have
+    /// been serialized with [`ProtocolId::to_bytes`] for the given protocol,
+    /// and is shared (rather than copied) all the way down to the socket, so
+    /// large messages can be serialized once and sent many times.
+    pub fn send_bytes_to(
+        &self,
+        recipient: PeerId,
+        protocol: ProtocolId,
+        mdata: Bytes,
+    ) -> Result<(), NetworkError> {
+        self.peer_mgr_reqs_tx.send_to(recipient, protocol, mdata)?;
+        Ok(())
+    }
+
+    /// Send a pre-serialized message to many recipients. See
+    /// [`NetworkSender::send_bytes_to`].
+    pub fn send_bytes_to_many(
+        &self,
+        recipients: impl Iterator<Item = PeerId>,
+        protocol: ProtocolId,
+        mdata: Bytes,
+    ) -> Result<(), NetworkError> {
+        self.peer_mgr_reqs_tx
+            .send_to_many(recipients, protocol, mdata)?;
+        Ok(())
+    }
 }
 
 impl<TMessage: Message> NetworkSender<TMessage> {
@@ -326,6 +540,38 @@ impl<TMessage: Message> NetworkSender<TMessage> {
         Ok(())
     }
 
+    /// Send a protobuf message to a single recipient, and wait for the recipient
+    /// to acknowledge it. Provides a wrapper over
+    /// `[peer_manager::PeerManagerRequestSender::send_to_with_ack]`.
+    pub async fn send_to_with_ack(
+        &self,
+        recipient: PeerId,
+        protocol: ProtocolId,
+        message: TMessage,
+        timeout: Duration,
+    ) -> Result<(), RpcError> {
+        let mdata = protocol.to_bytes(&message)?.into();
+        self.peer_mgr_reqs_tx
+            .send_to_with_ack(recipient, protocol, mdata, timeout)
+            .await
+    }
+
+    /// Send a protobuf message to a single recipient with the given priority.
+    /// Provides a wrapper over
+    /// `[peer_manager::PeerManagerRequestSender::send_to_with_priority]`.
+    pub fn send_to_with_priority(
+        &self,
+        recipient: PeerId,
+        protocol: ProtocolId,
+        message: TMessage,
+        priority: MessagePriority,
+    ) -> Result<(), NetworkError> {
+        let mdata = protocol.to_bytes(&message)?.into();
+        self.peer_mgr_reqs_tx
+            .send_to_with_priority(recipient, protocol, mdata, priority)?;
+        Ok(())
+    }
+
     /// Send a protobuf message to a many recipients. Provides a wrapper over
     /// `[peer_manager::PeerManagerRequestSender::send_to_many]`.
     pub fn send_to_many(
@@ -360,6 +606,50 @@ impl<TMessage: Message> NetworkSender<TMessage> {
         let res_msg: TMessage = protocol.from_bytes(&res_data)?;
         Ok(res_msg)
     }
+
+    /// Send a protobuf rpc request to a single recipient with the given
+    /// priority. See [`NetworkSender::send_rpc`].
+    pub async fn send_rpc_with_priority(
+        &self,
+        recipient: PeerId,
+        protocol: ProtocolId,
+        req_msg: TMessage,
+        timeout: Duration,
+        priority: MessagePriority,
+    ) -> Result<TMessage, RpcError> {
+        // serialize request
+        let req_data = protocol.to_bytes(&req_msg)?.into();
+        let res_data = self
+            .peer_mgr_reqs_tx
+            .send_rpc_with_priority(recipient, protocol, req_data, timeout, priority)
+            .await?;
+        let res_msg: TMessage = protocol.from_bytes(&res_data)?;
+        Ok(res_msg)
+    }
+
+    /// Send a streaming rpc request to a single recipient. The returned stream
+    /// yields each deserialized response chunk as it arrives. Assumes that the
+    /// request and the response chunks all have the same message type.
+    pub fn send_streaming_rpc(
+        &self,
+        recipient: PeerId,
+        protocol: ProtocolId,
+        req_msg: TMessage,
+        chunk_timeout: Duration,
+    ) -> Result<impl Stream<Item = Result<TMessage, RpcError>>, RpcError> {
+        // serialize request
+        let req_data = protocol.to_bytes(&req_msg)?.into();
+        let res_stream = self.peer_mgr_reqs_tx.send_streaming_rpc(
+            recipient,
+            protocol,
+            req_data,
+            chunk_timeout,
+        )?;
+        Ok(res_stream.map(move |res_data| {
+            let res_msg: TMessage = protocol.from_bytes(&res_data?)?;
+            Ok(res_msg)
+        }))
+    }
 }
 
 /// Generalized functionality for any request across `DirectSend` and `Rpc`.
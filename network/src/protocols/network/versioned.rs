@@ -0,0 +1,245 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Backward-compatible versioning of application messages.
+//!
+//! BCS isn't self-describing: adding a field to a message changes its encoding,
+//! so nodes running different releases can't decode each other's messages. An
+//! application can instead send its message `M` as a [`Versioned<M>`], which
+//! prefixes the encoded message with the version of its schema. Receivers
+//! decode messages of older versions by converting them to the current schema.
+//!
+//! The schema of a [`VersionedMessage`] evolves according to these rules:
+//! 1. The existing fields of a schema are never changed. A new schema (e.g.,
+//!    one with an additional field) bumps [`VersionedMessage::VERSION`].
+//! 2. The previous schema is kept as a separate type (e.g., `MessageV1`), with
+//!    conversions from and to the current schema: added fields are defaulted
+//!    when converting from the older schema, and dropped when converting to it.
+//!    These conversions back [`VersionedMessage::decode_older_version`] and
+//!    [`VersionedMessage::encode_older_version`].
+//! 3. New schemas are rolled out in two steps. First, all nodes are upgraded to
+//!    understand the new schema, while still sending the previous one (see
+//!    [`Versioned::with_version`]). Then, they start sending the new schema.
+//!    Messages of versions newer than the receiver's are rejected.
+
+use anyhow::{bail, ensure};
+use serde::{de, de::DeserializeOwned, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The version of the schema of a message
+pub type MessageVersion = u8;
+
+/// A message whose schema is versioned, so that it can evolve without breaking
+/// fleets running different releases
+pub trait VersionedMessage: DeserializeOwned + Serialize {
+    /// The version of the current schema of the message
+    const VERSION: MessageVersion;
+
+    /// Decodes the (BCS-encoded) payload of an older schema version, and
+    /// converts it to the current schema. No older version is supported by
+    /// default.
+    fn decode_older_version(version: MessageVersion, _payload: &[u8]) -> anyhow::Result<Self> {
+        bail!("Unsupported message version: {}", version)
+    }
+
+    /// Converts the message to an older schema version, and returns its BCS
+    /// encoding (e.g., to send it to nodes that don't understand the current
+    /// schema yet). No older version is supported by default.
+    fn encode_older_version(&self, version: MessageVersion) -> anyhow::Result<Vec<u8>> {
+        bail!("Unsupported message version: {}", version)
+    }
+}
+
+/// The versioned encoding of a message
+#[derive(Deserialize, Serialize)]
+struct Envelope {
+    version: MessageVersion,
+    #[serde(with = "serde_bytes")]
+    payload: Vec<u8>,
+}
+
+/// A message sent (or received) with the given version of its schema
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Versioned<M> {
+    version: MessageVersion,
+    message: M,
+}
+
+impl<M: VersionedMessage> Versioned<M> {
+    /// Wraps the message, to be sent with the current version of its schema
+    pub fn new(message: M) -> Self {
+        Self {
+            version: M::VERSION,
+            message,
+        }
+    }
+
+    /// Wraps the message, to be sent with the given (older) version of its
+    /// schema, e.g., while not all peers understand the current one
+    pub fn with_version(message: M, version: MessageVersion) -> Self {
+        Self { version, message }
+    }
+
+    /// Returns the schema version the message is (or was) sent with
+    pub fn version(&self) -> MessageVersion {
+        self.version
+    }
+
+    pub fn message(&self) -> &M {
+        &self.message
+    }
+
+    pub fn into_message(self) -> M {
+        self.message
+    }
+
+    fn encode(&self) -> anyhow::Result<Envelope> {
+        ensure!(
+            self.version <= M::VERSION,
+            "Can't encode message version {} newer than the current version {}",
+            self.version,
+            M::VERSION
+        );
+        let payload = if self.version == M::VERSION {
+            bcs::to_bytes(&self.message)?
+        } else {
+            self.message.encode_older_version(self.version)?
+        };
+        Ok(Envelope {
+            version: self.version,
+            payload,
+        })
+    }
+
+    fn decode(envelope: Envelope) -> anyhow::Result<Self> {
+        let Envelope { version, payload } = envelope;
+        ensure!(
+            version <= M::VERSION,
+            "Received message version {} newer than the current version {}",
+            version,
+            M::VERSION
+        );
+        let message = if version == M::VERSION {
+            bcs::from_bytes(&payload)?
+        } else {
+            M::decode_older_version(version, &payload)?
+        };
+        Ok(Self { version, message })
+    }
+}
+
+impl<M: VersionedMessage> Serialize for Versioned<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.encode()
+            .map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de, M: VersionedMessage> Deserialize<'de> for Versioned<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::decode(Envelope::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The first schema of the message
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    struct DummyMessageV1 {
+        value: u64,
+    }
+
+    impl VersionedMessage for DummyMessageV1 {
+        const VERSION: MessageVersion = 1;
+    }
+
+    /// The current schema of the message, with an additional field
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    struct DummyMessage {
+        value: u64,
+        label: Option<String>,
+    }
+
+    impl From<DummyMessageV1> for DummyMessage {
+        fn from(message: DummyMessageV1) -> Self {
+            Self {
+                value: message.value,
+                label: None,
+            }
+        }
+    }
+
+    impl From<&DummyMessage> for DummyMessageV1 {
+        fn from(message: &DummyMessage) -> Self {
+            Self {
+                value: message.value,
+            }
+        }
+    }
+
+    impl VersionedMessage for DummyMessage {
+        const VERSION: MessageVersion = 2;
+
+        fn decode_older_version(version: MessageVersion, payload: &[u8]) -> anyhow::Result<Self> {
+            ensure!(version == 1, "Unsupported message version: {}", version);
+            Ok(bcs::from_bytes::<DummyMessageV1>(payload)?.into())
+        }
+
+        fn encode_older_version(&self, version: MessageVersion) -> anyhow::Result<Vec<u8>> {
+            ensure!(version == 1, "Unsupported message version: {}", version);
+            Ok(bcs::to_bytes(&DummyMessageV1::from(self))?)
+        }
+    }
+
+    fn message() -> DummyMessage {
+        DummyMessage {
+            value: 7,
+            label: Some("seven".into()),
+        }
+    }
+
+    #[test]
+    fn messages_roundtrip_with_the_current_version() {
+        let bytes = bcs::to_bytes(&Versioned::new(message())).unwrap();
+        assert_eq!(bytes[0], DummyMessage::VERSION);
+        let versioned: Versioned<DummyMessage> = bcs::from_bytes(&bytes).unwrap();
+        assert_eq!(versioned.version(), DummyMessage::VERSION);
+        assert_eq!(versioned.into_message(), message());
+
+        // The envelope is independent of the encoding of the protocol
+        let json = serde_json::to_string(&Versioned::new(message())).unwrap();
+        let versioned: Versioned<DummyMessage> = serde_json::from_str(&json).unwrap();
+        assert_eq!(versioned.into_message(), message());
+    }
+
+    #[test]
+    fn older_versions_are_upgraded() {
+        let old_message = DummyMessageV1 { value: 7 };
+        let bytes = bcs::to_bytes(&Versioned::new(old_message)).unwrap();
+        let versioned: Versioned<DummyMessage> = bcs::from_bytes(&bytes).unwrap();
+        assert_eq!(versioned.version(), 1);
+        assert_eq!(versioned.message(), &DummyMessage {
+            value: 7,
+            label: None,
+        });
+    }
+
+    #[test]
+    fn messages_can_be_sent_with_older_versions() {
+        let bytes = bcs::to_bytes(&Versioned::with_version(message(), 1)).unwrap();
+        let versioned: Versioned<DummyMessageV1> = bcs::from_bytes(&bytes).unwrap();
+        assert_eq!(versioned.into_message(), DummyMessageV1 { value: 7 });
+
+        // Unsupported older versions can't be sent
+        assert!(bcs::to_bytes(&Versioned::with_version(message(), 0)).is_err());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let bytes = bcs::to_bytes(&Versioned::new(message())).unwrap();
+        assert!(bcs::from_bytes::<Versioned<DummyMessageV1>>(&bytes).is_err());
+        assert!(bcs::to_bytes(&Versioned::with_version(DummyMessageV1 { value: 7 }, 2)).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    application::{interface::NetworkClient, storage::PeerMetadataStorage},
+    connectivity_manager::ConnectivityRequest,
+    protocols::{
+        network::NetworkSender,
+        peer_exchange::{PeerExchange, PeerExchangeMsg, PeerExchangeNetworkEvents},
+        wire::handshake::v1::ProtocolId::PeerExchangeRpc,
+    },
+};
+use aptos_config::network_id::NetworkContext;
+use aptos_logger::prelude::*;
+use aptos_time_service::TimeService;
+use maplit::hashmap;
+use std::{sync::Arc, time::Duration};
+use tokio::runtime::Handle;
+
+pub struct PeerExchangeBuilder {
+    service: Option<PeerExchange<NetworkClient<PeerExchangeMsg>>>,
+}
+
+impl PeerExchangeBuilder {
+    pub fn new(
+        network_context: NetworkContext,
+        time_service: TimeService,
+        exchange_interval_ms: u64,
+        rpc_timeout_ms: u64,
+        network_sender: NetworkSender<PeerExchangeMsg>,
+        network_rx: PeerExchangeNetworkEvents,
+        peer_metadata_storage: Arc<PeerMetadataStorage>,
+        conn_mgr_reqs_tx: aptos_channels::Sender<ConnectivityRequest>,
+    ) -> Self {
+        let network_senders = hashmap! {network_context.network_id() => network_sender};
+        let network_client = NetworkClient::new(
+            vec![],
+            vec![PeerExchangeRpc],
+            network_senders,
+            peer_metadata_storage,
+        );
+        let service = PeerExchange::new(
+            network_context,
+            time_service,
+            network_rx,
+            network_client,
+            conn_mgr_reqs_tx,
+            Duration::from_millis(exchange_interval_ms),
+            Duration::from_millis(rpc_timeout_ms),
+        );
+        Self {
+            service: Some(service),
+        }
+    }
+
+    pub fn start(&mut self, executor: &Handle) {
+        if let Some(service) = self.service.take() {
+            spawn_named!("[Network] PEX", executor, service.start());
+        }
+    }
+}
@@ -0,0 +1,431 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Peer exchange (PEX) protocol
+//!
+//! To reduce the reliance on the (centrally configured) seed peers, connected peers
+//! periodically exchange summaries of the other public peers they know about. Every
+//! [`PeerExchange`] round, the actor asks a few connected peers for their summaries,
+//! and answers the same requests from its peers with the public peers it successfully
+//! dialed (i.e., whose addresses are known to be reachable).
+//!
+//! Summaries are self-certifying: each advertised address must carry the noise key
+//! of the summarized peer, and on the public network the peer id must be derived
+//! from that key. As dials authenticate the remote noise key, a malicious peer can't
+//! redirect connections for another peer to an address it controls.
+//!
+//! Received candidates are admitted based on scores: summaries are only accepted from
+//! peers (and about peers) with a score of at least [`MIN_ADMISSION_SCORE`], and the
+//! senders of invalid summaries are penalized. Admitted candidates are handed to the
+//! connectivity manager as [`DiscoverySource::PeerExchange`] peers, which have a lower
+//! priority than the configured seeds.
+use crate::{
+    application::{
+        error::Error, interface::NetworkClientInterface, scoring::PeerScoreEvent,
+        selection::RandomPeerSelector,
+    },
+    connectivity_manager::{ConnectivityRequest, DiscoverySource},
+    constants::NETWORK_CHANNEL_SIZE,
+    counters,
+    logging::NetworkSchema,
+    protocols::{
+        network::{
+            Event, NetworkApplicationConfig, NetworkClientConfig, NetworkEvents,
+            NetworkServiceConfig,
+        },
+        rpc::error::RpcError,
+        wire::handshake::v1::ProtocolIdSet,
+    },
+    transport::ConnectionOrigin,
+    ProtocolId,
+};
+use aptos_channels::{aptos_channel, message_queues::QueueStyle};
+use aptos_config::{
+    config::{Peer, PeerRole, PeerSet},
+    network_id::{NetworkContext, NetworkId, PeerNetworkId},
+};
+use aptos_logger::prelude::*;
+use aptos_short_hex_str::AsShortHexStr;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use aptos_types::{account_address, network_address::NetworkAddress, PeerId};
+use bytes::Bytes;
+use futures::{
+    channel::oneshot,
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::HashSet, time::Duration};
+
+pub mod builder;
+#[cfg(test)]
+mod test;
+
+/// The number of peers asked for their summaries in each round
+pub const MAX_EXCHANGE_PEERS_PER_ROUND: usize = 2;
+/// The maximum number of summaries sent (and accepted) in a single response
+pub const MAX_EXCHANGED_PEERS: usize = 32;
+/// The maximum number of candidates handed to the connectivity manager
+pub const MAX_CANDIDATES: usize = 256;
+/// The minimum score of a peer for its summaries (or summaries about it) to be admitted
+pub const MIN_ADMISSION_SCORE: f64 = 40.0;
+
+/// The interface from Network to PeerExchange layer.
+pub type PeerExchangeNetworkEvents = NetworkEvents<PeerExchangeMsg>;
+
+/// Returns a network application config for the peer exchange client and service
+pub fn peer_exchange_network_config() -> NetworkApplicationConfig {
+    let direct_send_protocols = vec![]; // Peer exchange doesn't use direct send
+    let rpc_protocols = vec![ProtocolId::PeerExchangeRpc];
+
+    let network_client_config =
+        NetworkClientConfig::new(direct_send_protocols.clone(), rpc_protocols.clone());
+    let network_service_config = NetworkServiceConfig::new(
+        direct_send_protocols,
+        rpc_protocols,
+        aptos_channel::Config::new(NETWORK_CHANNEL_SIZE)
+            .queue_style(QueueStyle::LIFO)
+            .counters(&counters::PENDING_PEER_EXCHANGE_NETWORK_EVENTS),
+    );
+    NetworkApplicationConfig::new(network_client_config, network_service_config)
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum PeerExchangeMsg {
+    /// Asks the peer for the public peers it knows about
+    PeerSummariesRequest,
+    /// The public peers known by the responder
+    PeerSummariesResponse(Vec<PeerSummary>),
+}
+
+/// A summary of a public peer, as advertised by one of its neighbours
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PeerSummary {
+    pub peer_id: PeerId,
+    /// The addresses the peer was reached on (including its noise key)
+    pub addresses: Vec<NetworkAddress>,
+    pub role: PeerRole,
+    /// The application protocols the peer supports
+    pub protocols: ProtocolIdSet,
+}
+
+impl PeerSummary {
+    /// Returns true iff every address of the summary carries the noise key
+    /// of the summarized peer (see the module documentation)
+    pub fn is_self_certifying(&self, network_id: NetworkId) -> bool {
+        if self.addresses.is_empty() {
+            return false;
+        }
+        let mut keys = HashSet::new();
+        for addr in &self.addresses {
+            match addr.find_noise_proto() {
+                Some(key) => {
+                    keys.insert(key);
+                },
+                None => return false,
+            }
+        }
+        // Peers on the public network use ids derived from their keys
+        network_id != NetworkId::Public
+            || keys
+                .iter()
+                .all(|key| account_address::from_identity_public_key(*key) == self.peer_id)
+    }
+}
+
+/// The actor exchanging peer summaries with the connected peers
+pub struct PeerExchange<NetworkClient> {
+    network_context: NetworkContext,
+    /// A handle to a time service for easily mocking time-related operations.
+    time_service: TimeService,
+    network_events: PeerExchangeNetworkEvents,
+    network_client: NetworkClient,
+    conn_mgr_reqs_tx: aptos_channels::Sender<ConnectivityRequest>,
+    /// The admitted candidates, handed to the connectivity manager
+    candidates: PeerSet,
+    /// Time we wait between each round of exchanges.
+    exchange_interval: Duration,
+    rpc_timeout: Duration,
+}
+
+impl<NetworkClient: NetworkClientInterface<PeerExchangeMsg> + Unpin + 'static>
+    PeerExchange<NetworkClient>
+{
+    /// Create new instance of the [`PeerExchange`] actor.
+    pub fn new(
+        network_context: NetworkContext,
+        time_service: TimeService,
+        network_events: PeerExchangeNetworkEvents,
+        network_client: NetworkClient,
+        conn_mgr_reqs_tx: aptos_channels::Sender<ConnectivityRequest>,
+        exchange_interval: Duration,
+        rpc_timeout: Duration,
+    ) -> Self {
+        Self {
+            network_context,
+            time_service,
+            network_events,
+            network_client,
+            conn_mgr_reqs_tx,
+            candidates: PeerSet::new(),
+            exchange_interval,
+            rpc_timeout,
+        }
+    }
+
+    pub async fn start(mut self) {
+        let mut pending_exchanges = FuturesUnordered::new();
+        info!(
+            NetworkSchema::new(&self.network_context),
+            "{} Peer exchange actor started", self.network_context
+        );
+
+        let ticker = self.time_service.interval(self.exchange_interval);
+        tokio::pin!(ticker);
+
+        loop {
+            futures::select! {
+                maybe_event = self.network_events.next() => {
+                    // Shutdown the actor when this network instance shuts
+                    // down. This happens when the `PeerManager` drops.
+                    let event = match maybe_event {
+                        Some(event) => event,
+                        None => break,
+                    };
+
+                    match event {
+                        Event::NewPeer(_) | Event::LostPeer(_) => {},
+                        Event::RpcRequest(peer_id, msg, protocol, res_tx, _) => {
+                            self.handle_rpc_request(peer_id, msg, protocol, res_tx);
+                        }
+                        Event::Message(peer_id, msg, _) => {
+                            warn!(
+                                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                                "{} Unexpected direct send from {} msg {:?}",
+                                self.network_context,
+                                peer_id,
+                                msg,
+                            );
+                        }
+                        Event::StreamingRpcRequest(peer_id, msg, _, _) => {
+                            warn!(
+                                NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                                "{} Unexpected streaming rpc from {} msg {:?}",
+                                self.network_context,
+                                peer_id,
+                                msg,
+                            );
+                        }
+                    }
+                }
+                _ = ticker.select_next_some() => {
+                    for peer_id in self.exchange_peers() {
+                        pending_exchanges.push(Self::request_summaries(
+                            self.network_context,
+                            self.network_client.clone(),
+                            peer_id,
+                            self.rpc_timeout,
+                        ));
+                    }
+                }
+                (peer_id, result) = pending_exchanges.select_next_some() => {
+                    self.handle_summaries(peer_id, result);
+                }
+            }
+        }
+        warn!(
+            NetworkSchema::new(&self.network_context),
+            "{} Peer exchange actor terminated", self.network_context
+        );
+    }
+
+    /// Returns the connected peers to exchange summaries with in this round
+    fn exchange_peers(&self) -> Vec<PeerId> {
+        let network_id = self.network_context.network_id();
+        self.network_client
+            .get_available_peers_ranked(&RandomPeerSelector)
+            .into_iter()
+            .filter(|peer| peer.network_id() == network_id)
+            .map(|peer| peer.peer_id())
+            .take(MAX_EXCHANGE_PEERS_PER_ROUND)
+            .collect()
+    }
+
+    async fn request_summaries(
+        network_context: NetworkContext,
+        network_client: NetworkClient,
+        peer_id: PeerId,
+        rpc_timeout: Duration,
+    ) -> (PeerId, Result<Vec<PeerSummary>, Error>) {
+        let peer_network_id = PeerNetworkId::new(network_context.network_id(), peer_id);
+        let result = network_client
+            .send_to_peer_rpc(
+                PeerExchangeMsg::PeerSummariesRequest,
+                rpc_timeout,
+                peer_network_id,
+            )
+            .await
+            .and_then(|msg| match msg {
+                PeerExchangeMsg::PeerSummariesResponse(summaries) => Ok(summaries),
+                msg => Err(Error::UnexpectedError(format!(
+                    "Unexpected peer summaries response: {:?}",
+                    msg
+                ))),
+            });
+        (peer_id, result)
+    }
+
+    fn peer_score(&self, peer_id: PeerId) -> f64 {
+        let peer_network_id = PeerNetworkId::new(self.network_context.network_id(), peer_id);
+        self.network_client
+            .get_peer_metadata_storage()
+            .get_peer_score(&peer_network_id)
+    }
+
+    /// Admits the candidates of the given summaries, and forwards them to the
+    /// connectivity manager if they changed
+    fn handle_summaries(&mut self, sender: PeerId, result: Result<Vec<PeerSummary>, Error>) {
+        let summaries = match result {
+            Ok(summaries) => summaries,
+            Err(error) => {
+                debug!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&sender),
+                    error = ?error,
+                    "{} Failed to get peer summaries from {}: {}",
+                    self.network_context,
+                    sender.short_str(),
+                    error
+                );
+                return;
+            },
+        };
+
+        // Only admit candidates from peers that behaved well so far
+        if self.peer_score(sender) < MIN_ADMISSION_SCORE {
+            counters::peer_exchange_summaries(&self.network_context, counters::REJECTED_LABEL)
+                .inc_by(summaries.len() as u64);
+            return;
+        }
+
+        let network_id = self.network_context.network_id();
+        let mut num_invalid: u64 = 0;
+        let mut changed = false;
+        for summary in summaries.into_iter().take(MAX_EXCHANGED_PEERS) {
+            if !summary.is_self_certifying(network_id) {
+                num_invalid += 1;
+                continue;
+            }
+            if summary.peer_id == self.network_context.peer_id()
+                || summary.role == PeerRole::Validator
+                || self.peer_score(summary.peer_id) < MIN_ADMISSION_SCORE
+                || (self.candidates.len() >= MAX_CANDIDATES
+                    && !self.candidates.contains_key(&summary.peer_id))
+            {
+                counters::peer_exchange_summaries(&self.network_context, counters::REJECTED_LABEL)
+                    .inc();
+                continue;
+            }
+
+            counters::peer_exchange_summaries(&self.network_context, counters::ACCEPTED_LABEL)
+                .inc();
+            let peer = Peer::new(summary.addresses, HashSet::new(), summary.role);
+            if self.candidates.get(&summary.peer_id) != Some(&peer) {
+                self.candidates.insert(summary.peer_id, peer);
+                changed = true;
+            }
+        }
+
+        if num_invalid > 0 {
+            counters::peer_exchange_summaries(&self.network_context, counters::INVALID_LABEL)
+                .inc_by(num_invalid);
+            self.network_client
+                .get_peer_metadata_storage()
+                .update_peer_score(
+                    PeerNetworkId::new(network_id, sender),
+                    PeerScoreEvent::InvalidMessage,
+                );
+        }
+
+        if changed {
+            let request = ConnectivityRequest::UpdateDiscoveredPeers(
+                DiscoverySource::PeerExchange,
+                self.candidates.clone(),
+            );
+            if let Err(error) = self.conn_mgr_reqs_tx.try_send(request) {
+                warn!(
+                    NetworkSchema::new(&self.network_context),
+                    "{} Failed to send peer exchange candidates {:?}", self.network_context, error
+                );
+            }
+        }
+    }
+
+    /// Returns the summaries of the public peers we dialed (excluding the
+    /// requester), best scored first
+    fn local_summaries(&self, requester: PeerId) -> Vec<PeerSummary> {
+        let peer_metadata_storage = self.network_client.get_peer_metadata_storage();
+        let mut peers: Vec<_> = peer_metadata_storage
+            .read_all(self.network_context.network_id())
+            .into_iter()
+            .filter(|(peer_network_id, peer_info)| {
+                let connection = &peer_info.active_connection;
+                peer_info.is_connected()
+                    && peer_network_id.peer_id() != requester
+                    && connection.origin == ConnectionOrigin::Outbound
+                    && connection.role != PeerRole::Validator
+                    && connection.addr.find_noise_proto().is_some()
+            })
+            .map(|(peer_network_id, peer_info)| {
+                (
+                    peer_metadata_storage.get_peer_score(&peer_network_id),
+                    peer_info.active_connection,
+                )
+            })
+            .collect();
+        peers.sort_by(|(score_a, _), (score_b, _)| {
+            score_b.partial_cmp(score_a).unwrap_or(Ordering::Equal)
+        });
+        peers
+            .into_iter()
+            .take(MAX_EXCHANGED_PEERS)
+            .map(|(_, connection)| PeerSummary {
+                peer_id: connection.remote_peer_id,
+                addresses: vec![connection.addr],
+                role: connection.role,
+                protocols: connection.application_protocols,
+            })
+            .collect()
+    }
+
+    fn handle_rpc_request(
+        &self,
+        peer_id: PeerId,
+        msg: PeerExchangeMsg,
+        protocol: ProtocolId,
+        res_tx: oneshot::Sender<Result<Bytes, RpcError>>,
+    ) {
+        match msg {
+            PeerExchangeMsg::PeerSummariesRequest => {
+                let response =
+                    PeerExchangeMsg::PeerSummariesResponse(self.local_summaries(peer_id));
+                let response = protocol.to_bytes(&response).map(Bytes::from).map_err(|e| {
+                    warn!(
+                        NetworkSchema::new(&self.network_context),
+                        error = ?e,
+                        "{} Unable to serialize peer summaries: {}", self.network_context, e
+                    );
+                    RpcError::Error(e)
+                });
+                let _ = res_tx.send(response);
+            },
+            msg => {
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&peer_id),
+                    rpc_message = msg,
+                    "{} Unexpected RPC message from {}",
+                    self.network_context,
+                    peer_id
+                );
+            },
+        }
+    }
+}
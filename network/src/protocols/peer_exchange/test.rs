@@ -0,0 +1,291 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{
+    application::{
+        interface::NetworkClient, scoring::STARTING_SCORE, storage::PeerMetadataStorage,
+        types::PeerInfo,
+    },
+    peer_manager::{
+        conn_notifs_channel, ConnectionRequestSender, PeerManagerNotification, PeerManagerRequest,
+        PeerManagerRequestSender,
+    },
+    protocols::{
+        network::{NetworkSender, NewNetworkEvents, NewNetworkSender},
+        rpc::InboundRpcRequest,
+        wire::handshake::v1::ProtocolId::PeerExchangeRpc,
+    },
+    transport::ConnectionMetadata,
+};
+use aptos_config::config::{RoleType, HANDSHAKE_VERSION};
+use aptos_crypto::{test_utils::TEST_SEED, x25519, Uniform};
+use aptos_time_service::MockTimeService;
+use futures::future;
+use maplit::hashmap;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{iter::FromIterator, str::FromStr, sync::Arc};
+
+const EXCHANGE_INTERVAL: Duration = Duration::from_secs(1);
+const RPC_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct TestHarness {
+    network_context: NetworkContext,
+    mock_time: MockTimeService,
+    rng: StdRng,
+    peer_mgr_reqs_rx: aptos_channel::Receiver<(PeerId, ProtocolId), PeerManagerRequest>,
+    peer_mgr_notifs_tx: aptos_channel::Sender<(PeerId, ProtocolId), PeerManagerNotification>,
+    conn_mgr_reqs_rx: aptos_channels::Receiver<ConnectivityRequest>,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+    // Keeps the connection notifications channel open
+    _connection_notifs_tx: conn_notifs_channel::Sender,
+}
+
+impl TestHarness {
+    fn new() -> (Self, PeerExchange<NetworkClient<PeerExchangeMsg>>) {
+        ::aptos_logger::Logger::init_for_testing();
+        let mock_time = TimeService::mock();
+
+        let (peer_mgr_reqs_tx, peer_mgr_reqs_rx) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+        let (connection_reqs_tx, _) = aptos_channel::new(QueueStyle::FIFO, 1, None);
+        let (peer_mgr_notifs_tx, peer_mgr_notifs_rx) =
+            aptos_channel::new(QueueStyle::FIFO, 1, None);
+        let (connection_notifs_tx, connection_notifs_rx) = conn_notifs_channel::new();
+        let (conn_mgr_reqs_tx, conn_mgr_reqs_rx) =
+            aptos_channels::new(1, &counters::PENDING_CONNECTIVITY_MANAGER_REQUESTS);
+
+        let network_sender = NetworkSender::new(
+            PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+            ConnectionRequestSender::new(connection_reqs_tx),
+        );
+        let network_events =
+            PeerExchangeNetworkEvents::new(peer_mgr_notifs_rx, connection_notifs_rx);
+
+        let network_context =
+            NetworkContext::new(RoleType::FullNode, NetworkId::Public, PeerId::random());
+        let peer_metadata_storage = PeerMetadataStorage::new(&[NetworkId::Public]);
+        let network_client = NetworkClient::new(
+            vec![],
+            vec![PeerExchangeRpc],
+            hashmap! {network_context.network_id() => network_sender},
+            peer_metadata_storage.clone(),
+        );
+        let peer_exchange = PeerExchange::new(
+            network_context,
+            mock_time.clone(),
+            network_events,
+            network_client,
+            conn_mgr_reqs_tx,
+            EXCHANGE_INTERVAL,
+            RPC_TIMEOUT,
+        );
+
+        (
+            Self {
+                network_context,
+                mock_time: mock_time.into_mock(),
+                rng: StdRng::from_seed(TEST_SEED),
+                peer_mgr_reqs_rx,
+                peer_mgr_notifs_tx,
+                conn_mgr_reqs_rx,
+                peer_metadata_storage,
+                _connection_notifs_tx: connection_notifs_tx,
+            },
+            peer_exchange,
+        )
+    }
+
+    /// Returns a summary of a new public peer, with a valid noise address
+    fn new_summary(&mut self) -> PeerSummary {
+        let pubkey = x25519::PrivateKey::generate(&mut self.rng).public_key();
+        let addr = NetworkAddress::from_str("/ip4/1.2.3.4/tcp/6180")
+            .unwrap()
+            .append_prod_protos(pubkey, HANDSHAKE_VERSION);
+        PeerSummary {
+            peer_id: account_address::from_identity_public_key(pubkey),
+            addresses: vec![addr],
+            role: PeerRole::Unknown,
+            protocols: ProtocolIdSet::from_iter(vec![PeerExchangeRpc]),
+        }
+    }
+
+    /// Adds a connected peer, described by the summary
+    fn add_peer(&self, summary: &PeerSummary, origin: ConnectionOrigin) {
+        let mut connection_metadata =
+            ConnectionMetadata::mock_with_role_and_origin(summary.peer_id, summary.role, origin);
+        connection_metadata.addr = summary.addresses[0].clone();
+        connection_metadata.application_protocols = summary.protocols.clone();
+        self.peer_metadata_storage.insert(
+            self.peer_network_id(summary.peer_id),
+            PeerInfo::new(connection_metadata),
+        );
+    }
+
+    fn peer_network_id(&self, peer_id: PeerId) -> PeerNetworkId {
+        PeerNetworkId::new(self.network_context.network_id(), peer_id)
+    }
+
+    async fn trigger_exchange(&self) {
+        self.mock_time.advance_async(EXCHANGE_INTERVAL).await;
+    }
+
+    /// Expects a summaries request to the given peer, and responds with the summaries
+    async fn respond_to_request(&mut self, expected_peer_id: PeerId, summaries: Vec<PeerSummary>) {
+        let req = self.peer_mgr_reqs_rx.next().await.unwrap();
+        let (peer_id, rpc_req) = match req {
+            PeerManagerRequest::SendRpc(peer_id, rpc_req) => (peer_id, rpc_req),
+            _ => panic!("Unexpected PeerManagerRequest: {:?}", req),
+        };
+        assert_eq!(peer_id, expected_peer_id);
+        assert_eq!(rpc_req.protocol_id, PeerExchangeRpc);
+        let msg: PeerExchangeMsg = bcs::from_bytes(&rpc_req.data).unwrap();
+        assert_eq!(msg, PeerExchangeMsg::PeerSummariesRequest);
+        let response = PeerExchangeMsg::PeerSummariesResponse(summaries);
+        rpc_req
+            .res_tx
+            .send(Ok(bcs::to_bytes(&response).unwrap().into()))
+            .unwrap();
+    }
+
+    async fn request_summaries(&mut self, peer_id: PeerId) -> Vec<PeerSummary> {
+        let data = bcs::to_bytes(&PeerExchangeMsg::PeerSummariesRequest)
+            .unwrap()
+            .into();
+        let (res_tx, res_rx) = oneshot::channel();
+        let inbound_rpc_req = InboundRpcRequest {
+            protocol_id: PeerExchangeRpc,
+            data,
+            res_tx,
+            deadline: None,
+            correlation_id: 0,
+        };
+        self.peer_mgr_notifs_tx
+            .push(
+                (peer_id, PeerExchangeRpc),
+                PeerManagerNotification::RecvRpc(peer_id, inbound_rpc_req),
+            )
+            .unwrap();
+        let res_data = res_rx.await.unwrap().unwrap();
+        match bcs::from_bytes(&res_data).unwrap() {
+            PeerExchangeMsg::PeerSummariesResponse(summaries) => summaries,
+            msg => panic!("Unexpected response: {:?}", msg),
+        }
+    }
+
+    async fn expect_candidates(&mut self) -> PeerSet {
+        match self.conn_mgr_reqs_rx.next().await.unwrap() {
+            ConnectivityRequest::UpdateDiscoveredPeers(DiscoverySource::PeerExchange, peers) => {
+                peers
+            },
+            req => panic!("Unexpected ConnectivityRequest: {:?}", req),
+        }
+    }
+}
+
+#[tokio::test]
+async fn responds_with_dialed_public_peers() {
+    let (mut harness, peer_exchange) = TestHarness::new();
+
+    let test = async move {
+        let requester = harness.new_summary();
+        harness.add_peer(&requester, ConnectionOrigin::Outbound);
+        let dialed = harness.new_summary();
+        harness.add_peer(&dialed, ConnectionOrigin::Outbound);
+        let inbound = harness.new_summary();
+        harness.add_peer(&inbound, ConnectionOrigin::Inbound);
+        let mut validator = harness.new_summary();
+        validator.role = PeerRole::Validator;
+        harness.add_peer(&validator, ConnectionOrigin::Outbound);
+
+        // Only the dialed (non-validator) peers are shared, excluding the requester
+        let summaries = harness.request_summaries(requester.peer_id).await;
+        assert_eq!(summaries, vec![dialed]);
+    };
+    future::join(peer_exchange.start(), test).await;
+}
+
+#[tokio::test]
+async fn admits_valid_candidates() {
+    let (mut harness, peer_exchange) = TestHarness::new();
+
+    let test = async move {
+        let neighbour = harness.new_summary();
+        harness.add_peer(&neighbour, ConnectionOrigin::Outbound);
+
+        let candidate = harness.new_summary();
+        // Summaries with addresses of other peers aren't self-certifying
+        let mut forged = harness.new_summary();
+        forged.addresses = candidate.addresses.clone();
+        let mut validator = harness.new_summary();
+        validator.role = PeerRole::Validator;
+
+        harness.trigger_exchange().await;
+        harness
+            .respond_to_request(neighbour.peer_id, vec![
+                candidate.clone(),
+                forged.clone(),
+                validator,
+            ])
+            .await;
+
+        let candidates = harness.expect_candidates().await;
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(
+            candidates.get(&candidate.peer_id).unwrap().addresses,
+            candidate.addresses
+        );
+
+        // The neighbour was penalized for the forged summary
+        assert!(
+            harness
+                .peer_metadata_storage
+                .get_peer_score(&harness.peer_network_id(neighbour.peer_id))
+                < STARTING_SCORE
+        );
+    };
+    future::join(peer_exchange.start(), test).await;
+}
+
+#[tokio::test]
+async fn ignores_low_scored_senders() {
+    let (mut harness, peer_exchange) = TestHarness::new();
+
+    let test = async move {
+        let neighbour = harness.new_summary();
+        harness.add_peer(&neighbour, ConnectionOrigin::Outbound);
+        for _ in 0..2 {
+            harness.peer_metadata_storage.update_peer_score(
+                harness.peer_network_id(neighbour.peer_id),
+                PeerScoreEvent::InvalidMessage,
+            );
+        }
+
+        harness.trigger_exchange().await;
+        let candidate = harness.new_summary();
+        harness
+            .respond_to_request(neighbour.peer_id, vec![candidate])
+            .await;
+
+        // Once the sender is well behaved again, its candidates are admitted
+        let candidate = harness.new_summary();
+        while harness
+            .peer_metadata_storage
+            .get_peer_score(&harness.peer_network_id(neighbour.peer_id))
+            < MIN_ADMISSION_SCORE
+        {
+            harness.peer_metadata_storage.update_peer_score(
+                harness.peer_network_id(neighbour.peer_id),
+                PeerScoreEvent::RpcSuccess(Duration::from_millis(1)),
+            );
+        }
+        harness.trigger_exchange().await;
+        harness
+            .respond_to_request(neighbour.peer_id, vec![candidate.clone()])
+            .await;
+        let candidates = harness.expect_candidates().await;
+        assert_eq!(candidates.keys().collect::<Vec<_>>(), vec![
+            &candidate.peer_id
+        ]);
+    };
+    future::join(peer_exchange.start(), test).await;
+}
@@ -41,6 +41,12 @@ pub enum RpcError {
 
     #[error("Rpc timed out")]
     TimedOut,
+
+    #[error("Remote peer is too busy to handle the rpc request")]
+    Busy,
+
+    #[error("Rpc request expired before it was handled")]
+    Expired,
 }
 
 impl From<PeerManagerError> for RpcError {
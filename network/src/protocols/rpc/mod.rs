@@ -38,7 +38,12 @@
 //! ## Limits:
 //!
 //! We limit the number of pending inbound and outbound RPC tasks to ensure that
-//! resource usage is bounded.
+//! resource usage is bounded. Applications can additionally limit the number of
+//! inbound requests of a protocol that are processed concurrently (across all
+//! peers), see [`InboundRpcConcurrencyLimits`]. Requests beyond that limit are
+//! shed right away, and rejected with a busy error rather than left to time out.
+//! Similarly, requests whose deadline passed while they were queued are dropped
+//! before reaching their handler, and rejected with an expired error.
 //!
 //! [AptosNet wire protocol v1]: https://github.com/aptos-labs/aptos-core/blob/main/specifications/network/messaging-v1.md
 //! [`Peer`]: crate::peer::Peer
@@ -46,14 +51,21 @@
 use crate::{
     counters::{
         self, network_application_inbound_traffic, network_application_outbound_traffic,
-        CANCELED_LABEL, DECLINED_LABEL, FAILED_LABEL, RECEIVED_LABEL, REQUEST_LABEL,
-        RESPONSE_LABEL, SENT_LABEL,
+        CANCELED_LABEL, DECLINED_LABEL, EXPIRED_LABEL, FAILED_LABEL, INBOUND_LABEL, OUTBOUND_LABEL,
+        RECEIVED_LABEL, REQUEST_LABEL, RESPONSE_LABEL, SENT_LABEL,
     },
     logging::NetworkSchema,
     peer::PeerNotification,
     protocols::{
         network::SerializedRequest,
-        wire::messaging::v1::{NetworkMessage, Priority, RequestId, RpcRequest, RpcResponse},
+        wire::{
+            compression,
+            handshake::v1::ProtocolIdSet,
+            messaging::v1::{
+                CorrelatedRpcRequest, CorrelationId, MessagePriority, NetworkMessage, RequestId,
+                RpcRequest, RpcRequestWithDeadline, RpcResponse,
+            },
+        },
     },
     ProtocolId,
 };
@@ -74,9 +86,17 @@ use futures::{
     stream::{FuturesUnordered, StreamExt},
 };
 use serde::Serialize;
-use std::{cmp::PartialEq, collections::HashMap, fmt::Debug, time::Duration};
+use std::{
+    cmp::{min, PartialEq},
+    collections::HashMap,
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 pub mod error;
+pub mod streaming;
 
 /// A wrapper struct for an inbound rpc request and its associated context.
 #[derive(Debug)]
@@ -104,6 +124,14 @@ pub struct InboundRpcRequest {
     /// when trying to send their response, as the rpc call might have timed out
     /// while handling the request.
     pub res_tx: oneshot::Sender<Result<Bytes, RpcError>>,
+    /// The time after which the sender no longer waits for the response, if
+    /// the sender propagated its deadline. Handlers can use this to skip work
+    /// for requests that have already expired.
+    pub deadline: Option<Instant>,
+    /// Identifies the request in the logs of both the sender and the receiver.
+    /// Requests from peers that don't send correlation ids are assigned a new
+    /// one on receipt.
+    pub correlation_id: CorrelationId,
 }
 
 impl SerializedRequest for InboundRpcRequest {
@@ -141,6 +169,11 @@ pub struct OutboundRpcRequest {
     /// rpc layer will send an [`RpcError::TimedOut`] error over the
     /// `res_tx` channel to the upper client layer.
     pub timeout: Duration,
+    /// The priority with which the request is sent. The remote peer sends
+    /// its response with the same priority.
+    pub priority: MessagePriority,
+    /// Identifies the request in the logs of both the sender and the receiver.
+    pub correlation_id: CorrelationId,
 }
 
 impl SerializedRequest for OutboundRpcRequest {
@@ -159,6 +192,39 @@ impl PartialEq for InboundRpcRequest {
     }
 }
 
+/// The maximum number of inbound rpcs of each (limited) protocol that are
+/// processed concurrently, across all the peers of a network.
+#[derive(Clone, Debug, Default)]
+pub struct InboundRpcConcurrencyLimits {
+    /// The permits of the requests being processed, by protocol
+    permits: HashMap<ProtocolId, Arc<Semaphore>>,
+}
+
+impl InboundRpcConcurrencyLimits {
+    /// Limits the number of concurrently processed requests of the protocol
+    pub fn insert(&mut self, protocol_id: ProtocolId, max_concurrent_rpcs: usize) {
+        self.permits
+            .insert(protocol_id, Arc::new(Semaphore::new(max_concurrent_rpcs)));
+    }
+
+    /// Returns the permit to process a new request of the protocol, which is
+    /// held until the request completes, or a `Busy` error if too many requests
+    /// are already being processed. No permits are needed for unlimited protocols.
+    pub fn try_acquire(
+        &self,
+        protocol_id: ProtocolId,
+    ) -> Result<Option<OwnedSemaphorePermit>, RpcError> {
+        match self.permits.get(&protocol_id) {
+            Some(permits) => permits
+                .clone()
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| RpcError::Busy),
+            None => Ok(None),
+        }
+    }
+}
+
 /// `InboundRpcs` handles new inbound rpc requests off the wire, notifies the
 /// `PeerManager` of the new request, and stores the pending response on a queue.
 /// If the response eventually completes, `InboundRpc` records some metrics and
@@ -172,9 +238,14 @@ pub struct InboundRpcs {
     time_service: TimeService,
     /// The PeerId of this connection's remote peer. Used for logging.
     remote_peer_id: PeerId,
+    /// The protocols whose payloads are transparently compressed on this
+    /// connection.
+    compressed_protocols: ProtocolIdSet,
     /// The core async queue of pending inbound rpc tasks. The tasks are driven
     /// to completion by the `InboundRpcs::next_completed_response()` method.
-    inbound_rpc_tasks: FuturesUnordered<BoxFuture<'static, Result<RpcResponse, RpcError>>>,
+    inbound_rpc_tasks: FuturesUnordered<
+        BoxFuture<'static, (RequestId, ProtocolId, Result<RpcResponse, RpcError>)>,
+    >,
     /// A blanket timeout on all inbound rpc requests. If the application handler
     /// doesn't respond to the request before this timeout, the request will be
     /// dropped.
@@ -182,6 +253,9 @@ pub struct InboundRpcs {
     /// Only allow this many concurrent inbound rpcs at one time from this remote
     /// peer.  New inbound requests exceeding this limit will be dropped.
     max_concurrent_inbound_rpcs: u32,
+    /// The per-protocol limits on the inbound rpcs processed concurrently,
+    /// shared with the other peers of the network.
+    concurrency_limits: InboundRpcConcurrencyLimits,
 }
 
 impl InboundRpcs {
@@ -189,24 +263,37 @@ impl InboundRpcs {
         network_context: NetworkContext,
         time_service: TimeService,
         remote_peer_id: PeerId,
+        compressed_protocols: ProtocolIdSet,
         inbound_rpc_timeout: Duration,
         max_concurrent_inbound_rpcs: u32,
+        concurrency_limits: InboundRpcConcurrencyLimits,
     ) -> Self {
         Self {
             network_context,
             time_service,
             remote_peer_id,
+            compressed_protocols,
             inbound_rpc_tasks: FuturesUnordered::new(),
             inbound_rpc_timeout,
             max_concurrent_inbound_rpcs,
+            concurrency_limits,
         }
     }
 
-    /// Handle a new inbound `RpcRequest` message off the wire.
+    /// Returns true if no inbound rpc is pending its response
+    pub fn is_empty(&self) -> bool {
+        self.inbound_rpc_tasks.is_empty()
+    }
+
+    /// Handle a new inbound `RpcRequest` message off the wire. If the sender
+    /// propagated its deadline, `time_budget` holds the time remaining until
+    /// the deadline (as of the time the request was sent).
     pub fn handle_inbound_request(
         &mut self,
         peer_notifs_tx: &mut aptos_channel::Sender<ProtocolId, PeerNotification>,
         request: RpcRequest,
+        time_budget: Option<Duration>,
+        correlation_id: CorrelationId,
     ) -> Result<(), RpcError> {
         let network_context = &self.network_context;
 
@@ -217,33 +304,73 @@ impl InboundRpcs {
             return Err(RpcError::TooManyPending(self.max_concurrent_inbound_rpcs));
         }
 
+        // Drop requests the sender has already given up on.
+        if time_budget == Some(Duration::ZERO) {
+            counters::rpc_messages(network_context, REQUEST_LABEL, DECLINED_LABEL).inc();
+            return Err(RpcError::TimedOut);
+        }
+
         let protocol_id = request.protocol_id;
         let request_id = request.request_id;
         let priority = request.priority;
         let req_len = request.raw_request.len() as u64;
 
+        // Shed requests of protocols that already process too many requests,
+        // rather than queueing them past their deadlines.
+        let permit = self
+            .concurrency_limits
+            .try_acquire(protocol_id)
+            .map_err(|err| {
+                counters::rpc_messages(network_context, REQUEST_LABEL, DECLINED_LABEL).inc();
+                err
+            })?;
+
         trace!(
             NetworkSchema::new(network_context).remote_peer(&self.remote_peer_id),
-            "{} Received inbound rpc request from peer {} with request_id {} and protocol_id {}",
+            correlation_id = correlation_id,
+            "{} Received inbound rpc request from peer {} with request_id {}, protocol_id {} and correlation_id {}",
             network_context,
             self.remote_peer_id.short_str(),
             request_id,
             protocol_id,
+            correlation_id,
         );
 
         // Collect counters for received request.
         counters::rpc_messages(network_context, REQUEST_LABEL, RECEIVED_LABEL).inc();
         counters::rpc_bytes(network_context, REQUEST_LABEL, RECEIVED_LABEL).inc_by(req_len);
         network_application_inbound_traffic(self.network_context, protocol_id, req_len);
+        counters::application_traffic(
+            network_context.network_id(),
+            protocol_id,
+            INBOUND_LABEL,
+            req_len,
+        );
         let timer =
             counters::inbound_rpc_handler_latency(network_context, protocol_id).start_timer();
 
+        // Decompress the request if compression was negotiated for the protocol.
+        let is_compressed = self.compressed_protocols.contains(protocol_id);
+        let raw_request = if is_compressed {
+            compression::decode_payload(&request.raw_request)
+                .map(Bytes::from)
+                .map_err(|err| {
+                    counters::rpc_messages(network_context, REQUEST_LABEL, FAILED_LABEL).inc();
+                    RpcError::Error(err)
+                })?
+        } else {
+            request.raw_request
+        };
+
         // Foward request to PeerManager for handling.
         let (response_tx, response_rx) = oneshot::channel();
+        let deadline = time_budget.map(|time_budget| self.time_service.now() + time_budget);
         let notif = PeerNotification::RecvRpc(InboundRpcRequest {
             protocol_id,
-            data: Bytes::from(request.raw_request),
+            data: raw_request,
             res_tx: response_tx,
+            deadline,
+            correlation_id,
         });
         if let Err(err) = peer_notifs_tx.push(protocol_id, notif) {
             counters::rpc_messages(network_context, RESPONSE_LABEL, FAILED_LABEL).inc();
@@ -251,17 +378,39 @@ impl InboundRpcs {
         }
 
         // Create a new task that waits for a response from the upper layer with a timeout.
+        // There's no point in waiting past the sender's deadline.
+        let inbound_rpc_timeout = time_budget.map_or(self.inbound_rpc_timeout, |time_budget| {
+            min(time_budget, self.inbound_rpc_timeout)
+        });
+        let network_id = network_context.network_id();
         let inbound_rpc_task = self
             .time_service
-            .timeout(self.inbound_rpc_timeout, response_rx)
+            .timeout(inbound_rpc_timeout, response_rx)
             .map(move |result| {
+                // Release the protocol's permit once the request completes
+                drop(permit);
                 // Flatten the errors
                 let maybe_response = match result {
-                    Ok(Ok(Ok(response_bytes))) => Ok(RpcResponse {
-                        request_id,
-                        priority,
-                        raw_response: Vec::from(response_bytes.as_ref()),
-                    }),
+                    Ok(Ok(Ok(response_bytes))) => {
+                        counters::application_traffic(
+                            network_id,
+                            protocol_id,
+                            OUTBOUND_LABEL,
+                            response_bytes.len() as u64,
+                        );
+                        let raw_response = if is_compressed {
+                            compression::encode_payload(response_bytes.as_ref()).map(Bytes::from)
+                        } else {
+                            Ok(response_bytes)
+                        };
+                        raw_response
+                            .map(|raw_response| RpcResponse {
+                                request_id,
+                                priority,
+                                raw_response,
+                            })
+                            .map_err(RpcError::Error)
+                    },
                     Ok(Ok(Err(err))) => Err(err),
                     Ok(Err(oneshot::Canceled)) => Err(RpcError::UnexpectedResponseChannelCancel),
                     Err(timeout::Elapsed) => Err(RpcError::TimedOut),
@@ -271,7 +420,7 @@ impl InboundRpcs {
                     Ok(_) => timer.stop_and_record(),
                     Err(_) => timer.stop_and_discard(),
                 };
-                maybe_response
+                (request_id, protocol_id, maybe_response)
             })
             .boxed();
 
@@ -284,10 +433,12 @@ impl InboundRpcs {
 
     /// Method for `Peer` actor to drive the pending inbound rpc tasks forward.
     /// The returned `Future` is a `FusedFuture` so it works correctly in a
-    /// `futures::select!`.
+    /// `futures::select!`. Each completed request is returned with its request
+    /// and protocol ids, so that failed requests can be reported to the peer.
     pub fn next_completed_response(
         &mut self,
-    ) -> impl Future<Output = Result<RpcResponse, RpcError>> + FusedFuture + '_ {
+    ) -> impl Future<Output = (RequestId, ProtocolId, Result<RpcResponse, RpcError>)> + FusedFuture + '_
+    {
         self.inbound_rpc_tasks.select_next_some()
     }
 
@@ -302,6 +453,10 @@ impl InboundRpcs {
         let network_context = &self.network_context;
         let response = match maybe_response {
             Ok(response) => response,
+            Err(RpcError::Expired) => {
+                counters::rpc_messages(network_context, REQUEST_LABEL, EXPIRED_LABEL).inc();
+                return Err(RpcError::Expired);
+            },
             Err(err) => {
                 counters::rpc_messages(network_context, RESPONSE_LABEL, FAILED_LABEL).inc();
                 return Err(err);
@@ -337,6 +492,19 @@ pub struct OutboundRpcs {
     time_service: TimeService,
     /// The PeerId of this connection's remote peer. Used for logging.
     remote_peer_id: PeerId,
+    /// The protocols whose payloads are transparently compressed on this
+    /// connection.
+    compressed_protocols: ProtocolIdSet,
+    /// Whether the remote peer negotiated correlation ids, i.e., whether the
+    /// requests' correlation ids are sent on the wire.
+    supports_correlation_ids: bool,
+    /// Whether the remote peer negotiated rpc expiry, i.e., whether the
+    /// requests' timeouts are sent on the wire. Other peers only understand
+    /// plain `RpcRequest`s.
+    supports_rpc_expiry: bool,
+    /// Whether the remote peer negotiated QoS, i.e., whether the requests are
+    /// sent with their priority. Other peers get the default priority.
+    supports_qos: bool,
     /// Generates the next RequestId to use for the next outbound RPC. Note that
     /// request ids are local to each connection.
     request_id_gen: U32IdGenerator,
@@ -351,7 +519,8 @@ pub struct OutboundRpcs {
     /// Maps a `RequestId` into a handle to a task in the `outbound_rpc_tasks`
     /// completion queue. When a new `RpcResponse` message comes in, we will use
     /// this map to notify the corresponding task that its response has arrived.
-    pending_outbound_rpcs: HashMap<RequestId, (ProtocolId, oneshot::Sender<RpcResponse>)>,
+    pending_outbound_rpcs:
+        HashMap<RequestId, (ProtocolId, oneshot::Sender<Result<RpcResponse, RpcError>>)>,
     /// Only allow this many concurrent outbound rpcs at one time from this remote
     /// peer. New outbound requests exceeding this limit will be dropped.
     max_concurrent_outbound_rpcs: u32,
@@ -362,12 +531,20 @@ impl OutboundRpcs {
         network_context: NetworkContext,
         time_service: TimeService,
         remote_peer_id: PeerId,
+        compressed_protocols: ProtocolIdSet,
+        supports_correlation_ids: bool,
+        supports_rpc_expiry: bool,
+        supports_qos: bool,
         max_concurrent_outbound_rpcs: u32,
     ) -> Self {
         Self {
             network_context,
             time_service,
             remote_peer_id,
+            compressed_protocols,
+            supports_correlation_ids,
+            supports_rpc_expiry,
+            supports_qos,
             request_id_gen: U32IdGenerator::new(),
             outbound_rpc_tasks: FuturesUnordered::new(),
             pending_outbound_rpcs: HashMap::new(),
@@ -375,6 +552,11 @@ impl OutboundRpcs {
         }
     }
 
+    /// Returns true if no outbound rpc is pending its response
+    pub fn is_empty(&self) -> bool {
+        self.outbound_rpc_tasks.is_empty()
+    }
+
     /// Handle a new outbound rpc request from the application layer.
     pub async fn handle_outbound_request(
         &mut self,
@@ -390,6 +572,8 @@ impl OutboundRpcs {
             data: request_data,
             timeout,
             res_tx: mut application_response_tx,
+            priority,
+            correlation_id,
         } = request;
         let req_len = request_data.len() as u64;
 
@@ -412,10 +596,12 @@ impl OutboundRpcs {
 
         trace!(
             NetworkSchema::new(network_context).remote_peer(peer_id),
-            "{} Sending outbound rpc request with request_id {} and protocol_id {} to {}",
+            correlation_id = correlation_id,
+            "{} Sending outbound rpc request with request_id {}, protocol_id {} and correlation_id {} to {}",
             network_context,
             request_id,
             protocol_id,
+            correlation_id,
             peer_id.short_str(),
         );
 
@@ -423,22 +609,61 @@ impl OutboundRpcs {
         let timer =
             counters::outbound_rpc_request_latency(network_context, protocol_id).start_timer();
 
-        // Enqueue rpc request message onto outbound write queue.
-        let message = NetworkMessage::RpcRequest(RpcRequest {
+        // Compress the request if compression was negotiated for the protocol.
+        let is_compressed = self.compressed_protocols.contains(protocol_id);
+        let raw_request = if is_compressed {
+            match compression::encode_payload(request_data.as_ref()) {
+                Ok(raw_request) => Bytes::from(raw_request),
+                Err(err) => {
+                    counters::rpc_messages(network_context, REQUEST_LABEL, FAILED_LABEL).inc();
+                    let _ = application_response_tx.send(Err(RpcError::Error(anyhow!(
+                        "Failed to compress the rpc request: {}",
+                        err
+                    ))));
+                    return Err(RpcError::Error(err));
+                },
+            }
+        } else {
+            request_data
+        };
+
+        // Enqueue rpc request message onto outbound write queue. If the remote
+        // peer understands it, the timeout is propagated so that it can skip the
+        // request once it expires.
+        let priority = if self.supports_qos {
+            priority
+        } else {
+            MessagePriority::default()
+        };
+        let request = RpcRequestWithDeadline {
             protocol_id,
             request_id,
-            priority: Priority::default(),
-            raw_request: Vec::from(request_data.as_ref()),
-        });
+            priority: priority.into(),
+            timeout_ms: timeout.as_millis() as u64,
+            raw_request,
+        };
+        let message = if self.supports_correlation_ids {
+            NetworkMessage::CorrelatedRpcRequest(CorrelatedRpcRequest::new(request, correlation_id))
+        } else if self.supports_rpc_expiry {
+            NetworkMessage::RpcRequestWithDeadline(request)
+        } else {
+            NetworkMessage::RpcRequest(request.into_parts().0)
+        };
         write_reqs_tx.send(message).await?;
 
         // Collect counters for requests sent.
         counters::rpc_messages(network_context, REQUEST_LABEL, SENT_LABEL).inc();
         counters::rpc_bytes(network_context, REQUEST_LABEL, SENT_LABEL).inc_by(req_len);
         network_application_outbound_traffic(self.network_context, protocol_id, req_len);
+        counters::application_traffic(
+            network_context.network_id(),
+            protocol_id,
+            OUTBOUND_LABEL,
+            req_len,
+        );
 
         // Create channel over which response is delivered to outbound_rpc_task.
-        let (response_tx, response_rx) = oneshot::channel::<RpcResponse>();
+        let (response_tx, response_rx) = oneshot::channel::<Result<RpcResponse, RpcError>>();
 
         // Store send-side in the pending map so we can notify outbound_rpc_task
         // when the rpc response has arrived.
@@ -448,17 +673,25 @@ impl OutboundRpcs {
         // A future that waits for the rpc response with a timeout. We create the
         // timeout out here to start the timer as soon as we push onto the queue
         // (as opposed to whenever it first gets polled on the queue).
-        let wait_for_response = self
-            .time_service
-            .timeout(timeout, response_rx)
-            .map(|result| {
-                // Flatten errors.
-                match result {
-                    Ok(Ok(response)) => Ok(Bytes::from(response.raw_response)),
-                    Ok(Err(oneshot::Canceled)) => Err(RpcError::UnexpectedResponseChannelCancel),
-                    Err(timeout::Elapsed) => Err(RpcError::TimedOut),
-                }
-            });
+        let wait_for_response =
+            self.time_service
+                .timeout(timeout, response_rx)
+                .map(move |result| {
+                    // Flatten errors.
+                    match result {
+                        Ok(Ok(Ok(response))) if is_compressed => {
+                            compression::decode_payload(&response.raw_response)
+                                .map(Bytes::from)
+                                .map_err(RpcError::Error)
+                        },
+                        Ok(Ok(Ok(response))) => Ok(response.raw_response),
+                        Ok(Ok(Err(err))) => Err(err),
+                        Ok(Err(oneshot::Canceled)) => {
+                            Err(RpcError::UnexpectedResponseChannelCancel)
+                        },
+                        Err(timeout::Elapsed) => Err(RpcError::TimedOut),
+                    }
+                });
 
         // A future that waits for the response and sends it to the application.
         let notify_application = async move {
@@ -586,7 +819,13 @@ impl OutboundRpcs {
                 protocol_id,
                 response.raw_response.len() as u64,
             );
-            response_tx.send(response).is_err()
+            counters::application_traffic(
+                network_context.network_id(),
+                protocol_id,
+                INBOUND_LABEL,
+                response.raw_response.len() as u64,
+            );
+            response_tx.send(Ok(response)).is_err()
         } else {
             true
         };
@@ -611,4 +850,43 @@ impl OutboundRpcs {
             );
         }
     }
+
+    /// Handle a new inbound expired error, i.e., the remote peer dropped our
+    /// request because it expired before it could be handled. Fails the pending
+    /// request with the matching request id right away, rather than letting it
+    /// time out.
+    pub fn handle_inbound_expired(&mut self, request_id: RequestId) {
+        if let Some((protocol_id, response_tx)) = self.pending_outbound_rpcs.remove(&request_id) {
+            trace!(
+                NetworkSchema::new(&self.network_context).remote_peer(&self.remote_peer_id),
+                request_id = request_id,
+                "{} Peer {} dropped the expired request_id {} for protocol {}",
+                self.network_context,
+                self.remote_peer_id.short_str(),
+                request_id,
+                protocol_id,
+            );
+            // The request may have already been canceled
+            let _ = response_tx.send(Err(RpcError::Expired));
+        }
+    }
+
+    /// Handle a new inbound busy error, i.e., the remote peer rejected our
+    /// request because it's overloaded. Fails the pending request with the
+    /// matching request id right away, rather than letting it time out.
+    pub fn handle_inbound_busy(&mut self, request_id: RequestId) {
+        if let Some((protocol_id, response_tx)) = self.pending_outbound_rpcs.remove(&request_id) {
+            trace!(
+                NetworkSchema::new(&self.network_context).remote_peer(&self.remote_peer_id),
+                request_id = request_id,
+                "{} Peer {} is too busy to handle the request_id {} for protocol {}",
+                self.network_context,
+                self.remote_peer_id.short_str(),
+                request_id,
+                protocol_id,
+            );
+            // The request may have already been canceled
+            let _ = response_tx.send(Err(RpcError::Busy));
+        }
+    }
 }
@@ -0,0 +1,550 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implementation of the streaming RPC protocol.
+//!
+//! ## Design:
+//!
+//! A streaming RPC is a single request that yields a stream of response
+//! chunks, instead of a single response. The requester sends a
+//! [`StreamingRpcRequest`] carrying a flow control `window`; the responder then
+//! sends [`RpcResponseChunk`]s, the last one of which is flagged as
+//! `end_of_stream`. If the responder fails to complete the stream, the last
+//! chunk is also flagged as `failed`, and the requester surfaces an error to
+//! the application instead of a truncated stream.
+//!
+//! ## Flow control:
+//!
+//! The responder may have at most `window` unacknowledged chunks in flight.
+//! The requester acknowledges every chunk with an [`RpcChunkAck`] once it has
+//! been handed to the application, which allows the responder to send one more
+//! chunk. A slow consumer therefore applies backpressure all the way to the
+//! responding application, and neither side ever buffers more than `window`
+//! chunks of a stream: a responder that overruns the window fails the stream.
+//!
+//! ## Timeouts:
+//!
+//! Instead of a timeout for the entire call, each chunk has a timeout: the
+//! requester gives up if no chunk arrives in time, and the responder gives up
+//! if the application does not produce a chunk (or the requester does not
+//! acknowledge one) in time.
+
+use crate::{
+    counters::{self, CHUNK_LABEL, DECLINED_LABEL, FAILED_LABEL, RECEIVED_LABEL, SENT_LABEL},
+    logging::NetworkSchema,
+    peer::PeerNotification,
+    protocols::{
+        network::SerializedRequest,
+        rpc::error::RpcError,
+        wire::messaging::v1::{
+            NetworkMessage, Priority, RequestId, RpcChunkAck, RpcResponseChunk, StreamingRpcRequest,
+        },
+    },
+    ProtocolId,
+};
+use aptos_channels::aptos_channel;
+use aptos_config::network_id::NetworkContext;
+use aptos_id_generator::{IdGenerator, U32IdGenerator};
+use aptos_logger::prelude::*;
+use aptos_short_hex_str::AsShortHexStr;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use aptos_types::PeerId;
+use bytes::Bytes;
+use futures::{
+    channel::mpsc,
+    future::{BoxFuture, FusedFuture, Future, FutureExt},
+    sink::SinkExt,
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde::Serialize;
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+/// The default number of unacknowledged response chunks per stream
+pub const DEFAULT_STREAMING_RPC_WINDOW: u32 = 8;
+
+/// A wrapper struct for an inbound streaming rpc request and its associated context.
+#[derive(Debug)]
+pub struct InboundStreamingRpcRequest {
+    /// The [`ProtocolId`] for which of our upstream application modules should
+    /// handle this inbound streaming rpc request.
+    pub protocol_id: ProtocolId,
+    /// The serialized request data received from the sender.
+    pub data: Bytes,
+    /// Channel over which the response chunks are sent from the upper
+    /// application layer to the network rpc layer. The application ends the
+    /// stream by dropping the sender. The channel is bounded by the flow
+    /// control window, so sending blocks while the requester is behind.
+    pub res_tx: mpsc::Sender<Result<Bytes, RpcError>>,
+}
+
+impl SerializedRequest for InboundStreamingRpcRequest {
+    fn protocol_id(&self) -> ProtocolId {
+        self.protocol_id
+    }
+
+    fn data(&self) -> &Bytes {
+        &self.data
+    }
+}
+
+impl PartialEq for InboundStreamingRpcRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.protocol_id == other.protocol_id && self.data == other.data
+    }
+}
+
+/// A wrapper struct for an outbound streaming rpc request and its associated context.
+#[derive(Debug, Serialize)]
+pub struct OutboundStreamingRpcRequest {
+    /// The remote peer's application module that should handle our request.
+    pub protocol_id: ProtocolId,
+    /// The serialized request data to be sent to the receiver.
+    #[serde(skip)]
+    pub data: Bytes,
+    /// Channel over which the response chunks are sent from the rpc layer to
+    /// the upper client layer. The channel is closed when the stream ends.
+    #[serde(skip)]
+    pub res_tx: mpsc::Sender<Result<Bytes, RpcError>>,
+    /// The maximum time to wait for each response chunk.
+    pub chunk_timeout: Duration,
+    /// The number of chunks the responder may send ahead of our acknowledgements.
+    pub window: u32,
+}
+
+impl SerializedRequest for OutboundStreamingRpcRequest {
+    fn protocol_id(&self) -> ProtocolId {
+        self.protocol_id
+    }
+
+    fn data(&self) -> &Bytes {
+        &self.data
+    }
+}
+
+/// `InboundStreamingRpcs` handles new inbound streaming rpc requests off the
+/// wire, forwards them to the application and writes the response chunks
+/// produced by the application onto the outbound write queue, honoring the
+/// requester's flow control window.
+///
+/// There is one `InboundStreamingRpcs` handler per [`Peer`](crate::peer::Peer).
+pub struct InboundStreamingRpcs {
+    /// The network instance this Peer actor is running under.
+    network_context: NetworkContext,
+    /// A handle to a time service for easily mocking time-related operations.
+    time_service: TimeService,
+    /// The PeerId of this connection's remote peer. Used for logging.
+    remote_peer_id: PeerId,
+    /// The remaining send credits of each active stream, replenished by acks.
+    stream_credits: HashMap<RequestId, Arc<Semaphore>>,
+    /// The tasks driving each active stream. Each task yields the number of
+    /// chunks it sent.
+    stream_tasks: FuturesUnordered<BoxFuture<'static, (RequestId, Result<u32, RpcError>)>>,
+    /// The maximum time to wait for the application to produce a chunk, or for
+    /// the requester to acknowledge one.
+    chunk_timeout: Duration,
+    /// Only allow this many concurrent inbound streams from this remote peer.
+    max_concurrent_inbound_streams: u32,
+}
+
+impl InboundStreamingRpcs {
+    pub fn new(
+        network_context: NetworkContext,
+        time_service: TimeService,
+        remote_peer_id: PeerId,
+        chunk_timeout: Duration,
+        max_concurrent_inbound_streams: u32,
+    ) -> Self {
+        Self {
+            network_context,
+            time_service,
+            remote_peer_id,
+            stream_credits: HashMap::new(),
+            stream_tasks: FuturesUnordered::new(),
+            chunk_timeout,
+            max_concurrent_inbound_streams,
+        }
+    }
+
+    /// Returns true if no inbound stream is active
+    pub fn is_empty(&self) -> bool {
+        self.stream_tasks.is_empty()
+    }
+
+    /// Handle a new inbound `StreamingRpcRequest` message off the wire.
+    pub fn handle_inbound_request(
+        &mut self,
+        peer_notifs_tx: &mut aptos_channel::Sender<ProtocolId, PeerNotification>,
+        write_reqs_tx: &aptos_channels::Sender<NetworkMessage>,
+        request: StreamingRpcRequest,
+    ) -> Result<(), RpcError> {
+        let network_context = &self.network_context;
+
+        // Drop new inbound streams if we're at capacity.
+        if self.stream_tasks.len() as u32 >= self.max_concurrent_inbound_streams {
+            counters::rpc_messages(network_context, CHUNK_LABEL, DECLINED_LABEL).inc();
+            return Err(RpcError::TooManyPending(
+                self.max_concurrent_inbound_streams,
+            ));
+        }
+
+        let StreamingRpcRequest {
+            protocol_id,
+            request_id,
+            window,
+            raw_request,
+            ..
+        } = request;
+        if window == 0 {
+            return Err(RpcError::Error(anyhow::anyhow!(
+                "Streaming rpc request {} has an empty flow control window",
+                request_id
+            )));
+        }
+
+        trace!(
+            NetworkSchema::new(network_context).remote_peer(&self.remote_peer_id),
+            "{} Received inbound streaming rpc request from peer {} with request_id {} and protocol_id {}",
+            network_context,
+            self.remote_peer_id.short_str(),
+            request_id,
+            protocol_id,
+        );
+
+        // Forward the request to the application.
+        let (response_tx, mut response_rx) = mpsc::channel(window as usize);
+        let notif = PeerNotification::RecvStreamingRpc(InboundStreamingRpcRequest {
+            protocol_id,
+            data: raw_request,
+            res_tx: response_tx,
+        });
+        peer_notifs_tx.push(protocol_id, notif)?;
+
+        // Create a task that forwards the application's chunks onto the wire,
+        // sending at most `window` chunks ahead of the requester's acks.
+        let credits = Arc::new(Semaphore::new(window as usize));
+        self.stream_credits.insert(request_id, credits.clone());
+        let time_service = self.time_service.clone();
+        let chunk_timeout = self.chunk_timeout;
+        let mut write_reqs_tx = write_reqs_tx.clone();
+        let stream_task = async move {
+            let mut chunk_id = 0;
+            let result = loop {
+                // Wait for a send credit
+                match time_service.timeout(chunk_timeout, credits.acquire()).await {
+                    Ok(Ok(permit)) => permit.forget(),
+                    Ok(Err(_)) => break Err(RpcError::UnexpectedResponseChannelCancel),
+                    Err(_) => break Err(RpcError::TimedOut),
+                }
+
+                // Wait for the next chunk from the application
+                let (raw_chunk, end_of_stream) = match time_service
+                    .timeout(chunk_timeout, response_rx.next())
+                    .await
+                {
+                    Ok(Some(Ok(chunk))) => (chunk, false),
+                    Ok(Some(Err(err))) => break Err(err),
+                    Ok(None) => (Bytes::new(), true),
+                    Err(_) => break Err(RpcError::TimedOut),
+                };
+                let message = NetworkMessage::RpcResponseChunk(RpcResponseChunk {
+                    request_id,
+                    chunk_id,
+                    end_of_stream,
+                    failed: false,
+                    raw_chunk,
+                });
+                if let Err(err) = write_reqs_tx.send(message).await {
+                    return (request_id, Err(err.into()));
+                }
+                chunk_id += 1;
+                if end_of_stream {
+                    break Ok(chunk_id);
+                }
+            };
+
+            // Always terminate the stream so the requester doesn't wait for the
+            // chunk timeout to fire, and can tell it apart from a complete one.
+            if result.is_err() {
+                let message = NetworkMessage::RpcResponseChunk(RpcResponseChunk {
+                    request_id,
+                    chunk_id,
+                    end_of_stream: true,
+                    failed: true,
+                    raw_chunk: Bytes::new(),
+                });
+                let _ = write_reqs_tx.send(message).await;
+            }
+            (request_id, result)
+        };
+        self.stream_tasks.push(stream_task.boxed());
+
+        Ok(())
+    }
+
+    /// Handle an inbound `RpcChunkAck`, granting the stream another send credit.
+    pub fn handle_inbound_ack(&mut self, ack: RpcChunkAck) {
+        if let Some(credits) = self.stream_credits.get(&ack.request_id) {
+            credits.add_permits(1);
+        }
+    }
+
+    /// Method for `Peer` actor to drive the pending inbound streams forward.
+    /// The returned `Future` is a `FusedFuture` so it works correctly in a
+    /// `futures::select!`.
+    pub fn next_completed_stream(
+        &mut self,
+    ) -> impl Future<Output = (RequestId, Result<u32, RpcError>)> + FusedFuture + '_ {
+        self.stream_tasks.select_next_some()
+    }
+
+    /// Handle a completed stream task and clean up its state.
+    pub fn handle_completed_stream(
+        &mut self,
+        request_id: RequestId,
+        result: Result<u32, RpcError>,
+    ) {
+        let _ = self.stream_credits.remove(&request_id);
+
+        let network_context = &self.network_context;
+        match result {
+            Ok(num_chunks) => {
+                counters::rpc_messages(network_context, CHUNK_LABEL, SENT_LABEL)
+                    .inc_by(num_chunks as u64);
+            },
+            Err(err) => {
+                counters::rpc_messages(network_context, CHUNK_LABEL, FAILED_LABEL).inc();
+                warn!(
+                    NetworkSchema::new(network_context).remote_peer(&self.remote_peer_id),
+                    "{} Error streaming rpc response to peer {} (request_id {}). Error: {}",
+                    network_context,
+                    self.remote_peer_id.short_str(),
+                    request_id,
+                    err
+                );
+            },
+        }
+    }
+}
+
+/// `OutboundStreamingRpcs` handles new outbound streaming rpc requests made
+/// from the application layer, and delivers the response chunks to the
+/// application as they arrive.
+///
+/// There is one `OutboundStreamingRpcs` handler per [`Peer`](crate::peer::Peer).
+pub struct OutboundStreamingRpcs {
+    /// The network instance this Peer actor is running under.
+    network_context: NetworkContext,
+    /// A handle to a time service for easily mocking time-related operations.
+    time_service: TimeService,
+    /// The PeerId of this connection's remote peer. Used for logging.
+    remote_peer_id: PeerId,
+    /// Generates the next RequestId to use for the next outbound stream.
+    request_id_gen: U32IdGenerator,
+    /// Maps a `RequestId` to the queue of chunks of the corresponding stream
+    /// task. Each queue is bounded by the flow control window of its stream.
+    pending_outbound_streams: HashMap<RequestId, mpsc::Sender<RpcResponseChunk>>,
+    /// The tasks delivering chunks of each active stream to the application.
+    /// Each task yields the number of chunks it received.
+    stream_tasks: FuturesUnordered<BoxFuture<'static, (RequestId, Result<u32, RpcError>)>>,
+    /// Only allow this many concurrent outbound streams to this remote peer.
+    max_concurrent_outbound_streams: u32,
+}
+
+impl OutboundStreamingRpcs {
+    pub fn new(
+        network_context: NetworkContext,
+        time_service: TimeService,
+        remote_peer_id: PeerId,
+        max_concurrent_outbound_streams: u32,
+    ) -> Self {
+        Self {
+            network_context,
+            time_service,
+            remote_peer_id,
+            request_id_gen: U32IdGenerator::new(),
+            pending_outbound_streams: HashMap::new(),
+            stream_tasks: FuturesUnordered::new(),
+            max_concurrent_outbound_streams,
+        }
+    }
+
+    /// Returns true if no outbound stream is active
+    pub fn is_empty(&self) -> bool {
+        self.stream_tasks.is_empty()
+    }
+
+    /// Handle a new outbound streaming rpc request from the application layer.
+    pub async fn handle_outbound_request(
+        &mut self,
+        request: OutboundStreamingRpcRequest,
+        write_reqs_tx: &mut aptos_channels::Sender<NetworkMessage>,
+    ) -> Result<(), RpcError> {
+        let network_context = &self.network_context;
+        let OutboundStreamingRpcRequest {
+            protocol_id,
+            data,
+            res_tx: mut application_response_tx,
+            chunk_timeout,
+            window,
+        } = request;
+
+        // Drop new outbound streams if we're at capacity.
+        if self.stream_tasks.len() as u32 >= self.max_concurrent_outbound_streams {
+            counters::rpc_messages(network_context, CHUNK_LABEL, DECLINED_LABEL).inc();
+            let err = Err(RpcError::TooManyPending(
+                self.max_concurrent_outbound_streams,
+            ));
+            let _ = application_response_tx.try_send(err);
+            return Err(RpcError::TooManyPending(
+                self.max_concurrent_outbound_streams,
+            ));
+        }
+
+        let request_id = self.request_id_gen.next();
+        let message = NetworkMessage::StreamingRpcRequest(StreamingRpcRequest {
+            protocol_id,
+            request_id,
+            priority: Priority::default(),
+            window,
+            raw_request: data,
+        });
+        write_reqs_tx.send(message).await?;
+
+        // Create a task that delivers the chunks to the application, and acks
+        // each chunk once the application has accepted it. The responder may
+        // never have more than `window` chunks in flight (note that the
+        // channel has an extra slot per sender).
+        let (chunk_tx, mut chunk_rx) = mpsc::channel(window.saturating_sub(1) as usize);
+        self.pending_outbound_streams.insert(request_id, chunk_tx);
+        let time_service = self.time_service.clone();
+        let mut write_reqs_tx = write_reqs_tx.clone();
+        let stream_task = async move {
+            let mut expected_chunk_id = 0;
+            let result = loop {
+                let chunk: RpcResponseChunk =
+                    match time_service.timeout(chunk_timeout, chunk_rx.next()).await {
+                        Ok(Some(chunk)) => chunk,
+                        // The queue is only closed when the responder overruns it
+                        Ok(None) => {
+                            break Err(RpcError::Error(anyhow::anyhow!(
+                                "Responder exceeded the flow control window of {} chunks",
+                                window
+                            )))
+                        },
+                        Err(_) => break Err(RpcError::TimedOut),
+                    };
+                if chunk.chunk_id != expected_chunk_id {
+                    break Err(RpcError::InvalidRpcResponse);
+                }
+                expected_chunk_id += 1;
+                if chunk.failed {
+                    break Err(RpcError::ApplicationError(anyhow::anyhow!(
+                        "Responder failed to complete the stream after {} chunks",
+                        chunk.chunk_id
+                    )));
+                }
+
+                if !chunk.raw_chunk.is_empty()
+                    && application_response_tx
+                        .send(Ok(chunk.raw_chunk))
+                        .await
+                        .is_err()
+                {
+                    break Err(RpcError::UnexpectedResponseChannelCancel);
+                }
+                if chunk.end_of_stream {
+                    break Ok(expected_chunk_id);
+                }
+
+                let ack = NetworkMessage::RpcChunkAck(RpcChunkAck {
+                    request_id,
+                    chunk_id: chunk.chunk_id,
+                });
+                if let Err(err) = write_reqs_tx.send(ack).await {
+                    break Err(err.into());
+                }
+            };
+
+            // Notify the application of any failure (it may have gone away already)
+            if let Err(err) = &result {
+                let err = RpcError::Error(anyhow::anyhow!(err.to_string()));
+                let _ = application_response_tx.send(Err(err)).await;
+            }
+            (request_id, result)
+        };
+        self.stream_tasks.push(stream_task.boxed());
+
+        Ok(())
+    }
+
+    /// Handle a new inbound `RpcResponseChunk` message, forwarding it to the
+    /// task of the corresponding stream. A chunk beyond the flow control window
+    /// fails the stream.
+    pub fn handle_inbound_chunk(&mut self, chunk: RpcResponseChunk) {
+        let request_id = chunk.request_id;
+        let result = self
+            .pending_outbound_streams
+            .get_mut(&request_id)
+            .map(|chunk_tx| chunk_tx.try_send(chunk));
+        match result {
+            Some(Ok(())) => {
+                counters::rpc_messages(&self.network_context, CHUNK_LABEL, RECEIVED_LABEL).inc();
+            },
+            Some(Err(err)) if err.is_full() => {
+                // Closing the queue fails the stream once the task has drained it
+                let _ = self.pending_outbound_streams.remove(&request_id);
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&self.remote_peer_id),
+                    request_id = request_id,
+                    "{} Peer {} exceeded the flow control window of stream request_id {}.",
+                    self.network_context,
+                    self.remote_peer_id.short_str(),
+                    request_id,
+                );
+            },
+            _ => {
+                debug!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&self.remote_peer_id),
+                    request_id = request_id,
+                    "{} Received chunk for expired stream request_id {} from {}. Discarding.",
+                    self.network_context,
+                    request_id,
+                    self.remote_peer_id.short_str(),
+                );
+            },
+        }
+    }
+
+    /// Method for `Peer` actor to drive the pending outbound streams forward.
+    /// The returned `Future` is a `FusedFuture` so it works correctly in a
+    /// `futures::select!`.
+    pub fn next_completed_stream(
+        &mut self,
+    ) -> impl Future<Output = (RequestId, Result<u32, RpcError>)> + FusedFuture + '_ {
+        self.stream_tasks.select_next_some()
+    }
+
+    /// Handle a completed stream task and clean up its state.
+    pub fn handle_completed_stream(
+        &mut self,
+        request_id: RequestId,
+        result: Result<u32, RpcError>,
+    ) {
+        let _ = self.pending_outbound_streams.remove(&request_id);
+
+        if let Err(err) = result {
+            if !matches!(err, RpcError::UnexpectedResponseChannelCancel) {
+                counters::rpc_messages(&self.network_context, CHUNK_LABEL, FAILED_LABEL).inc();
+                warn!(
+                    NetworkSchema::new(&self.network_context).remote_peer(&self.remote_peer_id),
+                    "{} Error receiving streaming rpc response from {} (request_id {}). Error: {}",
+                    self.network_context,
+                    self.remote_peer_id.short_str(),
+                    request_id,
+                    err
+                );
+            }
+        }
+    }
+}
@@ -1,21 +1,56 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::protocols::wire::messaging::v1::{MultiplexMessage, NetworkMessage};
+#[cfg(any(test, feature = "fuzzing"))]
+use crate::protocols::wire::messaging::v1::arb_payload;
+use crate::protocols::wire::messaging::v1::{
+    serde_payload, MessagePriority, MultiplexMessage, NetworkMessage,
+};
 use anyhow::{bail, ensure};
 use aptos_channels::Sender;
 use aptos_id_generator::{IdGenerator, U32IdGenerator};
+use bytes::{Bytes, BytesMut};
 use futures_util::SinkExt;
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug};
+
+/// The number of substreams of a connection, one per [`MessagePriority`]
+pub const NUM_SUBSTREAMS: usize = 4;
+
+/// Returns the substream of the messages of the given priority, so that
+/// latency-sensitive messages are never streamed behind bulk transfers
+pub fn substream_id(priority: MessagePriority) -> u8 {
+    match priority {
+        MessagePriority::Low => 0,
+        MessagePriority::Normal => 1,
+        MessagePriority::High => 2,
+        MessagePriority::Critical => 3,
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub enum StreamMessage {
     Header(StreamHeader),
     Fragment(StreamFragment),
+    /// Only sent to peers that negotiated [`Feature::Fragmentation`]
+    ///
+    /// [`Feature::Fragmentation`]: crate::protocols::wire::handshake::v2::Feature::Fragmentation
+    SequencedHeader(SequencedStreamHeader),
+    /// Only sent to peers that negotiated [`Feature::Fragmentation`]
+    ///
+    /// [`Feature::Fragmentation`]: crate::protocols::wire::handshake::v2::Feature::Fragmentation
+    SequencedFragment(SequencedStreamFragment),
+    /// Only sent to peers that negotiated [`Feature::Substreams`]
+    ///
+    /// [`Feature::Substreams`]: crate::protocols::wire::handshake::v2::Feature::Substreams
+    SubstreamHeader(SubstreamHeader),
+    /// Only sent to peers that negotiated [`Feature::Substreams`]
+    ///
+    /// [`Feature::Substreams`]: crate::protocols::wire::handshake::v2::Feature::Substreams
+    SubstreamFragment(SubstreamFragment),
 }
 
 #[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -32,8 +67,50 @@ pub struct StreamHeader {
 pub struct StreamFragment {
     pub request_id: u32,
     pub fragment_id: u8,
-    #[serde(with = "serde_bytes")]
-    pub raw_data: Vec<u8>,
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_data: Bytes,
+}
+
+/// A `StreamHeader` for messages that may exceed the max message size. The
+/// number of fragments isn't limited to a `u8`, and the length of the whole
+/// payload is announced upfront, so that the receiver can check it against its
+/// reassembly limit before buffering any fragment.
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct SequencedStreamHeader {
+    pub request_id: u32,
+    pub num_fragments: u32,
+    /// The length of the reassembled payload
+    pub payload_len: u64,
+    /// original message with chunked raw data
+    pub message: NetworkMessage,
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct SequencedStreamFragment {
+    pub request_id: u32,
+    pub fragment_id: u32,
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_data: Bytes,
+}
+
+/// A `SequencedStreamHeader` of one of the substreams of a connection. The
+/// fragments of the streams of different substreams may be interleaved.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct SubstreamHeader {
+    pub substream_id: u8,
+    pub header: SequencedStreamHeader,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct SubstreamFragment {
+    pub substream_id: u8,
+    pub fragment: SequencedStreamFragment,
 }
 
 impl Debug for StreamHeader {
@@ -58,92 +135,343 @@ impl Debug for StreamFragment {
     }
 }
 
+impl Debug for SequencedStreamHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SequencedStreamHeader {{ request_id: {}, num_fragments: {}, payload_len: {}, message: {:?} }}",
+            self.request_id, self.num_fragments, self.payload_len, self.message
+        )
+    }
+}
+
+impl Debug for SequencedStreamFragment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SequencedStreamFragment {{ request_id: {}, fragment_id: {}, size: {} }}",
+            self.request_id,
+            self.fragment_id,
+            self.raw_data.len()
+        )
+    }
+}
+
+/// Reassembles the inbound streams of a peer. Streams are sent one at a time
+/// over a connection, so there's at most a single stream to reassemble. Peers
+/// that negotiated substreams may additionally interleave a stream per
+/// substream. The bytes buffered by all the streams of the peer are capped by
+/// the max reassembly size, and a stream is dropped once one of its fragments
+/// is rejected.
 pub struct InboundStreamBuffer {
     stream: Option<InboundStream>,
+    substreams: HashMap<u8, InboundStream>,
     max_fragments: usize,
+    max_reassembly_size: usize,
 }
 
 impl InboundStreamBuffer {
-    pub fn new(max_fragments: usize) -> Self {
+    pub fn new(max_fragments: usize, max_reassembly_size: usize) -> Self {
         Self {
             stream: None,
+            substreams: HashMap::new(),
             max_fragments,
+            max_reassembly_size,
         }
     }
 
     pub fn new_stream(&mut self, header: StreamHeader) -> anyhow::Result<()> {
-        if let Some(old) = self
-            .stream
-            .replace(InboundStream::new(header, self.max_fragments)?)
-        {
+        ensure!(
+            header.num_fragments as usize <= self.max_fragments,
+            "Stream header exceeds max fragments limit"
+        );
+        let stream = InboundStream::new(
+            header.request_id,
+            header.num_fragments as u32,
+            None,
+            self.max_reassembly_size,
+            header.message,
+        )?;
+        self.replace_stream(stream)
+    }
+
+    pub fn new_sequenced_stream(&mut self, header: SequencedStreamHeader) -> anyhow::Result<()> {
+        ensure!(
+            header.payload_len <= self.max_reassembly_size as u64,
+            "Stream payload length {} exceeds the reassembly limit {}",
+            header.payload_len,
+            self.max_reassembly_size
+        );
+        let stream = InboundStream::new(
+            header.request_id,
+            header.num_fragments,
+            Some(header.payload_len as usize),
+            self.max_reassembly_size,
+            header.message,
+        )?;
+        self.replace_stream(stream)
+    }
+
+    pub fn new_substream(&mut self, header: SubstreamHeader) -> anyhow::Result<()> {
+        let SubstreamHeader {
+            substream_id,
+            header,
+        } = header;
+        ensure!(
+            (substream_id as usize) < NUM_SUBSTREAMS,
+            "Unknown substream {}",
+            substream_id
+        );
+        ensure!(
+            header.payload_len <= self.max_reassembly_size as u64,
+            "Stream payload length {} exceeds the reassembly limit {}",
+            header.payload_len,
+            self.max_reassembly_size
+        );
+        let stream = InboundStream::new(
+            header.request_id,
+            header.num_fragments,
+            Some(header.payload_len as usize),
+            self.max_reassembly_size,
+            header.message,
+        )?;
+        let old = self.substreams.remove(&substream_id);
+        self.ensure_reassembly_budget(stream.buffered_len)?;
+        self.substreams.insert(substream_id, stream);
+        if let Some(old) = old {
+            bail!(
+                "Discard existing stream {} of substream {}",
+                old.request_id,
+                substream_id
+            )
+        } else {
+            Ok(())
+        }
+    }
+
+    fn replace_stream(&mut self, stream: InboundStream) -> anyhow::Result<()> {
+        let old = self.stream.take();
+        self.ensure_reassembly_budget(stream.buffered_len)?;
+        self.stream = Some(stream);
+        if let Some(old) = old {
             bail!("Discard existing stream {}", old.request_id)
         } else {
             Ok(())
         }
     }
 
+    /// Fails if buffering `len` more bytes would exceed the max reassembly
+    /// size, which is shared by all the streams of the peer
+    fn ensure_reassembly_budget(&self, len: usize) -> anyhow::Result<()> {
+        let buffered_len: usize = self
+            .stream
+            .iter()
+            .chain(self.substreams.values())
+            .map(|stream| stream.buffered_len)
+            .sum();
+        ensure!(
+            buffered_len + len <= self.max_reassembly_size,
+            "Streams exceed the reassembly limit {}",
+            self.max_reassembly_size
+        );
+        Ok(())
+    }
+
     pub fn append_fragment(
         &mut self,
         fragment: StreamFragment,
     ) -> anyhow::Result<Option<NetworkMessage>> {
-        let stream = self
-            .stream
-            .as_mut()
-            .ok_or_else(|| anyhow::anyhow!("No stream exist"))?;
-        let stream_end = stream.append_fragment(fragment)?;
-        if stream_end {
-            Ok(Some(self.stream.take().unwrap().message))
-        } else {
-            Ok(None)
+        self.append(
+            false,
+            fragment.request_id,
+            fragment.fragment_id as u32,
+            fragment.raw_data,
+        )
+    }
+
+    pub fn append_sequenced_fragment(
+        &mut self,
+        fragment: SequencedStreamFragment,
+    ) -> anyhow::Result<Option<NetworkMessage>> {
+        self.append(
+            true,
+            fragment.request_id,
+            fragment.fragment_id,
+            fragment.raw_data,
+        )
+    }
+
+    pub fn append_substream_fragment(
+        &mut self,
+        fragment: SubstreamFragment,
+    ) -> anyhow::Result<Option<NetworkMessage>> {
+        let SubstreamFragment {
+            substream_id,
+            fragment,
+        } = fragment;
+        ensure!(
+            self.substreams.contains_key(&substream_id),
+            "No stream exist for substream {}",
+            substream_id
+        );
+        let stream_end = self
+            .ensure_reassembly_budget(fragment.raw_data.len())
+            .and_then(|_| {
+                self.substreams
+                    .get_mut(&substream_id)
+                    .expect("The substream exists")
+                    .append_fragment(fragment.request_id, fragment.fragment_id, fragment.raw_data)
+            });
+        match stream_end {
+            Ok(true) => Ok(self
+                .substreams
+                .remove(&substream_id)
+                .map(|stream| stream.message)),
+            Ok(false) => Ok(None),
+            Err(err) => {
+                self.substreams.remove(&substream_id);
+                Err(err)
+            },
+        }
+    }
+
+    fn append(
+        &mut self,
+        sequenced: bool,
+        request_id: u32,
+        fragment_id: u32,
+        raw_data: Bytes,
+    ) -> anyhow::Result<Option<NetworkMessage>> {
+        ensure!(self.stream.is_some(), "No stream exist");
+        let stream_end = self.ensure_reassembly_budget(raw_data.len()).and_then(|_| {
+            let stream = self.stream.as_mut().expect("The stream exists");
+            ensure!(
+                stream.payload_len.is_some() == sequenced,
+                "Stream fragment of a different kind than the stream header"
+            );
+            stream.append_fragment(request_id, fragment_id, raw_data)
+        });
+        match stream_end {
+            Ok(true) => Ok(self.stream.take().map(|stream| stream.message)),
+            Ok(false) => Ok(None),
+            Err(err) => {
+                self.stream = None;
+                Err(err)
+            },
         }
     }
 }
 
 pub struct InboundStream {
     request_id: u32,
-    num_fragments: u8,
-    current_fragment_id: u8,
+    num_fragments: u32,
+    current_fragment_id: u32,
+    /// The announced length of the reassembled payload (of sequenced streams)
+    payload_len: Option<usize>,
+    /// The maximum number of bytes buffered to reassemble the stream
+    max_buffered_len: usize,
+    /// The number of bytes buffered so far (including the header payload)
+    buffered_len: usize,
     message: NetworkMessage,
+    /// The fragments received so far, concatenated to the message payload
+    /// once the stream is complete
+    fragments: Vec<Bytes>,
 }
 
 impl InboundStream {
-    fn new(header: StreamHeader, max_fragments: usize) -> anyhow::Result<Self> {
+    fn new(
+        request_id: u32,
+        num_fragments: u32,
+        payload_len: Option<usize>,
+        max_reassembly_size: usize,
+        message: NetworkMessage,
+    ) -> anyhow::Result<Self> {
         ensure!(
-            !matches!(header.message, NetworkMessage::Error(_)),
+            !matches!(message, NetworkMessage::Error(_)),
             "Error message is not expected for stream"
         );
         ensure!(
-            header.num_fragments as usize <= max_fragments,
-            "Stream header exceeds max fragments limit"
+            !matches!(message, NetworkMessage::RpcChunkAck(_)),
+            "RpcChunkAck message is not expected for stream"
         );
+        let max_buffered_len = payload_len.unwrap_or(max_reassembly_size);
+        let buffered_len = message.data_len();
+        ensure!(
+            buffered_len <= max_buffered_len,
+            "Stream header payload exceeds the reassembly limit"
+        );
+        // Fragments are never empty, so there can't be more of them than bytes to come
+        if let Some(payload_len) = payload_len {
+            ensure!(
+                num_fragments as usize <= payload_len - buffered_len,
+                "Stream announces {} fragments for {} bytes",
+                num_fragments,
+                payload_len - buffered_len
+            );
+        }
         Ok(Self {
-            request_id: header.request_id,
-            num_fragments: header.num_fragments,
+            request_id,
+            num_fragments,
             current_fragment_id: 0,
-            message: header.message,
+            payload_len,
+            max_buffered_len,
+            buffered_len,
+            message,
+            // The fragment count is bounded by the reassembly limit only once
+            // the fragments arrive, so it's not trusted for the allocation
+            fragments: Vec::new(),
         })
     }
 
-    fn append_fragment(&mut self, mut fragment: StreamFragment) -> anyhow::Result<bool> {
+    fn append_fragment(
+        &mut self,
+        request_id: u32,
+        fragment_id: u32,
+        raw_data: Bytes,
+    ) -> anyhow::Result<bool> {
         ensure!(
-            self.request_id == fragment.request_id,
+            self.request_id == request_id,
             "Stream fragment from a different request"
         );
         ensure!(
-            self.current_fragment_id + 1 == fragment.fragment_id,
+            self.current_fragment_id + 1 == fragment_id,
             "Unexpected fragment id, expected {}, got {}",
             self.current_fragment_id + 1,
-            fragment.fragment_id
+            fragment_id
+        );
+        ensure!(!raw_data.is_empty(), "Empty stream fragment");
+        ensure!(
+            self.buffered_len + raw_data.len() <= self.max_buffered_len,
+            "Stream exceeds the reassembly limit {}",
+            self.max_buffered_len
         );
         self.current_fragment_id += 1;
-        let raw_data = &mut fragment.raw_data;
-        match &mut self.message {
-            NetworkMessage::Error(_) => panic!("StreamHeader with Error should be rejected"),
-            NetworkMessage::RpcRequest(request) => request.raw_request.append(raw_data),
-            NetworkMessage::RpcResponse(response) => response.raw_response.append(raw_data),
-            NetworkMessage::DirectSendMsg(message) => message.raw_msg.append(raw_data),
+        self.buffered_len += raw_data.len();
+        self.fragments.push(raw_data);
+        if self.current_fragment_id < self.num_fragments {
+            return Ok(false);
         }
-        Ok(self.current_fragment_id == self.num_fragments)
+        if let Some(payload_len) = self.payload_len {
+            ensure!(
+                self.buffered_len == payload_len,
+                "Stream payload length {} doesn't match the announced length {}",
+                self.buffered_len,
+                payload_len
+            );
+        }
+
+        // Reassemble the payload with a single copy
+        let payload = self
+            .message
+            .payload_mut()
+            .expect("StreamHeader without payload should be rejected");
+        let mut reassembled = BytesMut::with_capacity(self.buffered_len);
+        reassembled.extend_from_slice(payload);
+        for fragment in self.fragments.drain(..) {
+            reassembled.extend_from_slice(&fragment);
+        }
+        *payload = reassembled.freeze();
+        Ok(true)
     }
 }
 
@@ -151,6 +479,12 @@ pub struct OutboundStream {
     request_id_gen: U32IdGenerator,
     max_frame_size: usize,
     max_message_size: usize,
+    /// The max size of the messages fragmented into sequenced streams, if the
+    /// peer supports fragmentation
+    max_fragmented_message_size: Option<usize>,
+    /// The substream the messages are streamed on, if the peer supports
+    /// substreams
+    substream_id: Option<u8>,
     stream_tx: Sender<MultiplexMessage>,
 }
 
@@ -172,61 +506,109 @@ impl OutboundStream {
             request_id_gen: U32IdGenerator::new(),
             max_frame_size,
             max_message_size,
+            max_fragmented_message_size: None,
+            substream_id: None,
             stream_tx,
         }
     }
 
+    /// Streams messages as sequenced fragments, which may exceed the max
+    /// message size up to the given max reassembly size of the peer
+    pub fn with_fragmentation(mut self, max_reassembly_size: usize) -> Self {
+        self.max_fragmented_message_size = Some(max_reassembly_size.max(self.max_message_size));
+        self
+    }
+
+    /// Streams messages as sequenced fragments of the given substream, which
+    /// may be interleaved with the fragments of the other substreams
+    pub fn with_substream(mut self, substream_id: u8) -> Self {
+        self.substream_id = Some(substream_id);
+        self
+    }
+
     pub fn should_stream(&self, message: &NetworkMessage) -> bool {
         message.data_len() > self.max_frame_size
     }
 
     pub async fn stream_message(&mut self, mut message: NetworkMessage) -> anyhow::Result<()> {
+        let payload_len = message.data_len();
+        let max_message_size = self
+            .max_fragmented_message_size
+            .unwrap_or(self.max_message_size);
         ensure!(
-            message.data_len() <= self.max_message_size,
+            payload_len <= max_message_size,
             "Message length {} exceed size limit {}",
-            message.data_len(),
-            self.max_message_size,
+            payload_len,
+            max_message_size,
         );
         ensure!(
-            message.data_len() >= self.max_frame_size,
+            payload_len >= self.max_frame_size,
             "Message length {} is smaller than frame size {}, should not go through stream",
-            message.data_len(),
+            payload_len,
             self.max_frame_size,
         );
+        let sequenced = self.max_fragmented_message_size.is_some() || self.substream_id.is_some();
         let request_id = self.request_id_gen.next();
-        let rest = match &mut message {
-            NetworkMessage::Error(_) => {
-                unreachable!("NetworkMessage::Error should always fit in a single frame")
-            },
-            NetworkMessage::RpcRequest(request) => {
-                request.raw_request.split_off(self.max_frame_size)
-            },
-            NetworkMessage::RpcResponse(response) => {
-                response.raw_response.split_off(self.max_frame_size)
-            },
-            NetworkMessage::DirectSendMsg(message) => {
-                message.raw_msg.split_off(self.max_frame_size)
-            },
+        // Splitting the payload only slices it, without copying
+        let mut rest = message
+            .payload_mut()
+            .expect("Messages without payload should always fit in a single frame")
+            .split_off(self.max_frame_size);
+        let num_fragments = (rest.len() + self.max_frame_size - 1) / self.max_frame_size;
+        let header = if sequenced {
+            ensure!(
+                num_fragments <= u32::MAX as usize,
+                "Number of fragments overflowed"
+            );
+            let header = SequencedStreamHeader {
+                request_id,
+                num_fragments: num_fragments as u32,
+                payload_len: payload_len as u64,
+                message,
+            };
+            match self.substream_id {
+                Some(substream_id) => StreamMessage::SubstreamHeader(SubstreamHeader {
+                    substream_id,
+                    header,
+                }),
+                None => StreamMessage::SequencedHeader(header),
+            }
+        } else {
+            ensure!(
+                num_fragments <= u8::MAX as usize,
+                "Number of fragments overflowed"
+            );
+            StreamMessage::Header(StreamHeader {
+                request_id,
+                num_fragments: num_fragments as u8,
+                message,
+            })
         };
-        let chunks = rest.chunks(self.max_frame_size);
-        ensure!(
-            chunks.len() <= u8::MAX as usize,
-            "Number of fragments overflowed"
-        );
-        let header = StreamMessage::Header(StreamHeader {
-            request_id,
-            num_fragments: chunks.len() as u8,
-            message,
-        });
         self.stream_tx
             .send(MultiplexMessage::Stream(header))
             .await?;
-        for (index, chunk) in chunks.enumerate() {
-            let message = StreamMessage::Fragment(StreamFragment {
-                request_id,
-                fragment_id: index as u8 + 1,
-                raw_data: Vec::from(chunk),
-            });
+        for index in 0..num_fragments {
+            let raw_data = rest.split_to(self.max_frame_size.min(rest.len()));
+            let message = if sequenced {
+                let fragment = SequencedStreamFragment {
+                    request_id,
+                    fragment_id: index as u32 + 1,
+                    raw_data,
+                };
+                match self.substream_id {
+                    Some(substream_id) => StreamMessage::SubstreamFragment(SubstreamFragment {
+                        substream_id,
+                        fragment,
+                    }),
+                    None => StreamMessage::SequencedFragment(fragment),
+                }
+            } else {
+                StreamMessage::Fragment(StreamFragment {
+                    request_id,
+                    fragment_id: index as u8 + 1,
+                    raw_data,
+                })
+            };
             self.stream_tx
                 .send(MultiplexMessage::Stream(message))
                 .await?;
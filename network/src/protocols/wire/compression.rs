@@ -0,0 +1,95 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent compression of application payloads.
+//!
+//! Peers advertise the protocols for which they support transparent compression
+//! in their [`HandshakeMsgV2`] (see [`HandshakeMsgV2::compressed_protocols`]). If
+//! both peers support it for a protocol, every direct send and unary rpc payload of that protocol is prefixed
+//! with a single byte describing its encoding, and payloads larger than
+//! [`COMPRESSION_THRESHOLD_BYTES`] are compressed. This lets applications without
+//! a dedicated "Compressed" protocol save bandwidth, without any changes on their
+//! side.
+//!
+//! [`HandshakeMsgV2`]: crate::protocols::wire::handshake::v2::HandshakeMsgV2
+//! [`HandshakeMsgV2::compressed_protocols`]: crate::protocols::wire::handshake::v2::HandshakeMsgV2::compressed_protocols
+
+use anyhow::{anyhow, bail};
+use aptos_compression::metrics::CompressionClient;
+use aptos_config::config::MAX_APPLICATION_MESSAGE_SIZE;
+
+/// Payloads up to this size are sent uncompressed, as compressing them
+/// isn't worth the CPU cost.
+pub const COMPRESSION_THRESHOLD_BYTES: usize = 1024;
+
+/// The encoding of a payload, sent as the first byte of the payload
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PayloadEncoding {
+    Raw = 0,
+    Lz4 = 1,
+}
+
+/// Encodes the given payload, compressing it if it's above the threshold
+pub fn encode_payload(payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if payload.len() <= COMPRESSION_THRESHOLD_BYTES {
+        let mut encoded = Vec::with_capacity(payload.len() + 1);
+        encoded.push(PayloadEncoding::Raw as u8);
+        encoded.extend_from_slice(payload);
+        return Ok(encoded);
+    }
+
+    let compressed = aptos_compression::compress(
+        payload.to_vec(),
+        CompressionClient::Network,
+        MAX_APPLICATION_MESSAGE_SIZE,
+    )
+    .map_err(|e| anyhow!("{:?}", e))?;
+    let mut encoded = Vec::with_capacity(compressed.len() + 1);
+    encoded.push(PayloadEncoding::Lz4 as u8);
+    encoded.extend(compressed);
+    Ok(encoded)
+}
+
+/// Decodes a payload produced by [`encode_payload`]
+pub fn decode_payload(encoded: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (encoding, payload) = match encoded.split_first() {
+        Some(split) => split,
+        None => bail!("Missing payload encoding"),
+    };
+    match *encoding {
+        encoding if encoding == PayloadEncoding::Raw as u8 => Ok(payload.to_vec()),
+        encoding if encoding == PayloadEncoding::Lz4 as u8 => aptos_compression::decompress(
+            &payload.to_vec(),
+            CompressionClient::Network,
+            MAX_APPLICATION_MESSAGE_SIZE,
+        )
+        .map_err(|e| anyhow!("{:?}", e)),
+        encoding => bail!("Unknown payload encoding: {}", encoding),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn payload_roundtrip() {
+        // Small payloads are sent as is
+        let small_payload = vec![7u8; COMPRESSION_THRESHOLD_BYTES];
+        let encoded = encode_payload(&small_payload).unwrap();
+        assert_eq!(encoded.len(), small_payload.len() + 1);
+        assert_eq!(decode_payload(&encoded).unwrap(), small_payload);
+
+        // Large payloads are compressed
+        let large_payload = vec![7u8; 10 * COMPRESSION_THRESHOLD_BYTES];
+        let encoded = encode_payload(&large_payload).unwrap();
+        assert!(encoded.len() < large_payload.len());
+        assert_eq!(decode_payload(&encoded).unwrap(), large_payload);
+
+        // Invalid payloads are rejected
+        assert!(decode_payload(&[]).is_err());
+        assert!(decode_payload(&[PayloadEncoding::Lz4 as u8, 1, 2, 3]).is_err());
+        assert!(decode_payload(&[42]).is_err());
+    }
+}
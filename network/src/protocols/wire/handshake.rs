@@ -3,3 +3,5 @@
 
 // v1 of the AptosNet handshake protocol.
 pub mod v1;
+// v2 of the AptosNet handshake protocol, negotiated on top of v1.
+pub mod v2;
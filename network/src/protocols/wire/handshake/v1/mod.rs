@@ -12,9 +12,13 @@
 //!
 //! [AptosNet Handshake v1 Specification]: https://github.com/aptos-labs/aptos-core/blob/main/specifications/network/handshake-v1.md
 
+use crate::protocols::wire::messaging::v1::MessagePriority;
 use anyhow::anyhow;
 use aptos_compression::metrics::CompressionClient;
-use aptos_config::{config::MAX_APPLICATION_MESSAGE_SIZE, network_id::NetworkId};
+use aptos_config::{
+    config::{ProtocolPreferenceProfile, MAX_APPLICATION_MESSAGE_SIZE},
+    network_id::NetworkId,
+};
 use aptos_types::chain_id::ChainId;
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest_derive::Arbitrary;
@@ -38,8 +42,11 @@ pub const USER_INPUT_RECURSION_LIMIT: usize = 32;
 pub const RECURSION_LIMIT: usize = 64;
 
 /// Unique identifier associated with each application protocol.
+///
+/// Id 127 is reserved: the corresponding bit of a [`ProtocolIdSet`] advertises
+/// support for the v2 handshake, so it must never be assigned to a protocol.
 #[repr(u8)]
-#[derive(Clone, Copy, Hash, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub enum ProtocolId {
     ConsensusRpcBcs = 0,
@@ -55,8 +62,30 @@ pub enum ProtocolId {
     PeerMonitoringServiceRpc = 10,
     ConsensusRpcCompressed = 11,
     ConsensusDirectSendCompressed = 12,
+    NatTraversalRpc = 13,
+    PeerExchangeRpc = 14,
+    // 127 is reserved (see `HANDSHAKE_V2_BIT`)
 }
 
+/// All known protocols, in the order of their ids
+const ALL_PROTOCOL_IDS: &[ProtocolId] = &[
+    ProtocolId::ConsensusRpcBcs,
+    ProtocolId::ConsensusDirectSendBcs,
+    ProtocolId::MempoolDirectSend,
+    ProtocolId::StateSyncDirectSend,
+    ProtocolId::DiscoveryDirectSend,
+    ProtocolId::HealthCheckerRpc,
+    ProtocolId::ConsensusDirectSendJson,
+    ProtocolId::ConsensusRpcJson,
+    ProtocolId::StorageServiceRpc,
+    ProtocolId::MempoolRpc,
+    ProtocolId::PeerMonitoringServiceRpc,
+    ProtocolId::ConsensusRpcCompressed,
+    ProtocolId::ConsensusDirectSendCompressed,
+    ProtocolId::NatTraversalRpc,
+    ProtocolId::PeerExchangeRpc,
+];
+
 /// The encoding types for Protocols
 enum Encoding {
     Bcs(usize),
@@ -81,25 +110,35 @@ impl ProtocolId {
             PeerMonitoringServiceRpc => "PeerMonitoringServiceRpc",
             ConsensusRpcCompressed => "ConsensusRpcCompressed",
             ConsensusDirectSendCompressed => "ConsensusDirectSendCompressed",
+            NatTraversalRpc => "NatTraversalRpc",
+            PeerExchangeRpc => "PeerExchangeRpc",
         }
     }
 
     pub fn all() -> &'static [ProtocolId] {
-        &[
-            ProtocolId::ConsensusRpcBcs,
-            ProtocolId::ConsensusDirectSendBcs,
-            ProtocolId::MempoolDirectSend,
-            ProtocolId::StateSyncDirectSend,
-            ProtocolId::DiscoveryDirectSend,
-            ProtocolId::HealthCheckerRpc,
-            ProtocolId::ConsensusDirectSendJson,
-            ProtocolId::ConsensusRpcJson,
-            ProtocolId::StorageServiceRpc,
-            ProtocolId::MempoolRpc,
-            ProtocolId::PeerMonitoringServiceRpc,
-            ProtocolId::ConsensusRpcCompressed,
-            ProtocolId::ConsensusDirectSendCompressed,
-        ]
+        ALL_PROTOCOL_IDS
+    }
+
+    /// The priority of messages sent with this protocol, unless the sender
+    /// explicitly specifies one
+    pub fn default_priority(self) -> MessagePriority {
+        use ProtocolId::*;
+        match self {
+            ConsensusRpcBcs
+            | ConsensusDirectSendBcs
+            | ConsensusDirectSendJson
+            | ConsensusRpcJson
+            | ConsensusRpcCompressed
+            | ConsensusDirectSendCompressed => MessagePriority::High,
+            StateSyncDirectSend | StorageServiceRpc => MessagePriority::Low,
+            MempoolDirectSend
+            | DiscoveryDirectSend
+            | HealthCheckerRpc
+            | MempoolRpc
+            | PeerMonitoringServiceRpc
+            | NatTraversalRpc
+            | PeerExchangeRpc => MessagePriority::Normal,
+        }
     }
 
     /// How to encode messages for a given `ProtocolId`
@@ -115,6 +154,27 @@ impl ProtocolId {
         }
     }
 
+    /// The rank of this protocol under the given preference profile (lower
+    /// ranks are preferred). Protocols of the same rank keep the order of the
+    /// application.
+    pub fn preference_rank(self, profile: ProtocolPreferenceProfile) -> u8 {
+        match (profile, self.encoding()) {
+            (ProtocolPreferenceProfile::Application, _) => 0,
+            (ProtocolPreferenceProfile::CompressedFirst, Encoding::CompressedBcs(_)) => 0,
+            (ProtocolPreferenceProfile::CompressedFirst, Encoding::Bcs(_)) => 1,
+            (ProtocolPreferenceProfile::BcsFirst, Encoding::Bcs(_)) => 0,
+            (ProtocolPreferenceProfile::BcsFirst, Encoding::CompressedBcs(_)) => 1,
+            (_, Encoding::Json) => 2,
+        }
+    }
+
+    /// Whether payloads of this protocol can be compressed transparently by
+    /// the peer layer (see [`crate::protocols::wire::compression`]). This is
+    /// the case for all protocols that don't already compress their messages.
+    pub fn supports_transparent_compression(self) -> bool {
+        !matches!(self.encoding(), Encoding::CompressedBcs(_))
+    }
+
     /// Returns the compression client label based on the current protocol id
     fn get_compression_client(self) -> CompressionClient {
         match self {
@@ -199,10 +259,27 @@ impl fmt::Display for ProtocolId {
 /// These sets are sent over-the-wire in the initial [`HandshakeMsg`] to other
 /// AptosNet peers in order to negotiate the set of common supported protocols for
 /// use on a new AptosNet connection.
+///
+/// Bit `HANDSHAKE_V2_BIT` advertises support for the
+/// [v2 handshake](crate::protocols::wire::handshake::v2). Older nodes ignore it,
+/// so the v2 handshake is only run if both peers set it.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub struct ProtocolIdSet(aptos_bitvec::BitVec);
 
+/// The bit of a `ProtocolIdSet` advertising support for the v2 handshake. This
+/// id is reserved in [`ProtocolId`].
+const HANDSHAKE_V2_BIT: u16 = 127;
+
+// No protocol may use the id of the v2 handshake bit (or of the bits above it)
+const _: () = {
+    let mut i = 0;
+    while i < ALL_PROTOCOL_IDS.len() {
+        assert!((ALL_PROTOCOL_IDS[i] as u16) < HANDSHAKE_V2_BIT);
+        i += 1;
+    }
+};
+
 impl ProtocolIdSet {
     pub fn empty() -> Self {
         Self::default()
@@ -248,6 +325,28 @@ impl ProtocolIdSet {
     pub fn insert(&mut self, protocol: ProtocolId) {
         self.0.set(protocol as u16)
     }
+
+    /// Advertises support for the v2 handshake.
+    pub fn enable_handshake_v2(&mut self) {
+        self.0.set(HANDSHAKE_V2_BIT)
+    }
+
+    /// Returns if the v2 handshake is supported. On a negotiated set, this means
+    /// that both peers support it.
+    pub fn supports_handshake_v2(&self) -> bool {
+        self.0.is_set(HANDSHAKE_V2_BIT)
+    }
+
+    /// Returns a copy of the set without the v2 handshake advertisement.
+    pub fn without_handshake_v2(&self) -> ProtocolIdSet {
+        ProtocolIdSet(
+            self.0
+                .iter_ones()
+                .filter(|idx| *idx != HANDSHAKE_V2_BIT as usize)
+                .map(|idx| idx as u8)
+                .collect(),
+        )
+    }
 }
 
 impl FromIterator<ProtocolId> for ProtocolIdSet {
@@ -369,7 +468,9 @@ impl HandshakeMsg {
             if let Some(their_protocols) = other.supported_protocols.get(our_handshake_version) {
                 let common_protocols = our_protocols.intersect(their_protocols);
 
-                if !common_protocols.is_empty() {
+                // ignore the bits that don't represent protocols (e.g., the v2
+                // handshake advertisement)
+                if common_protocols.iter().next().is_some() {
                     return Ok((*our_handshake_version, common_protocols));
                 }
             }
@@ -182,3 +182,37 @@ fn ignore_unknown_protocols() {
         ProtocolIdSet::empty(),
     );
 }
+
+#[test]
+fn handshake_v2_negotiation() {
+    let protos = ProtocolIdSet::from_iter([ProtocolId::MempoolDirectSend]);
+    let mut v2_protos = protos.clone();
+    v2_protos.enable_handshake_v2();
+    assert!(v2_protos.supports_handshake_v2());
+    assert!(!protos.supports_handshake_v2());
+
+    // The v2 handshake advertisement doesn't show up as a protocol
+    assert_eq!(v2_protos.iter().collect::<Vec<_>>(), vec![
+        ProtocolId::MempoolDirectSend
+    ]);
+    assert_eq!(v2_protos.without_handshake_v2(), protos);
+
+    // The v2 handshake is only negotiated if both peers support it
+    let v2_hs = HandshakeMsg::from_supported(v2_protos.clone());
+    let (_, common_protos) = v2_hs
+        .perform_handshake(&HandshakeMsg::from_supported(protos))
+        .unwrap();
+    assert!(!common_protos.supports_handshake_v2());
+    let (_, common_protos) = v2_hs.perform_handshake(&v2_hs).unwrap();
+    assert!(common_protos.supports_handshake_v2());
+
+    // The advertisement alone isn't a common protocol
+    let mut no_protos = ProtocolIdSet::empty();
+    no_protos.enable_handshake_v2();
+    assert_eq!(
+        v2_hs
+            .perform_handshake(&HandshakeMsg::from_supported(no_protos))
+            .unwrap_err(),
+        HandshakeError::NoCommonProtocols,
+    );
+}
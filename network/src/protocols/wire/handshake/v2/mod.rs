@@ -0,0 +1,274 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module defines the structs transported during the network handshake protocol v2.
+//!
+//! The v2 handshake extends the v1 handshake, rather than replacing it, so that it can be
+//! rolled out without a flag-day across the fleet: nodes advertise support for it in their
+//! v1 [`ProtocolIdSet`]s (see [`ProtocolIdSet::enable_handshake_v2`]), and only if both
+//! end-points of a connection do, they then send a serialized and length-prefixed
+//! [`HandshakeMsgV2`] to each other.
+//!
+//! The v2 handshake message contains the set of optional wire features the node supports
+//! and the version of each application protocol it speaks. On receipt, both ends keep the
+//! features supported by both, and the lowest common version of each protocol, so that new
+//! wire capabilities (and new versions of a protocol) are only used once both peers
+//! understand them.
+//!
+//! [`ProtocolIdSet::enable_handshake_v2`]: crate::protocols::wire::handshake::v1::ProtocolIdSet::enable_handshake_v2
+
+use crate::protocols::wire::handshake::v1::{ProtocolId, ProtocolIdSet};
+#[cfg(any(test, feature = "fuzzing"))]
+use proptest_derive::Arbitrary;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    iter::{FromIterator, Iterator},
+    ops::BitAnd,
+};
+
+#[cfg(test)]
+mod test;
+
+//
+// Feature
+//
+
+/// Optional wire capabilities, negotiated per connection.
+#[repr(u8)]
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub enum Feature {
+    /// Transparent compression of the payloads of the protocols that both peers
+    /// can compress (see [`HandshakeMsgV2::compressed_protocols`])
+    Compression = 0,
+    /// Streaming RPCs, with chunked and flow-controlled responses
+    StreamingRpc = 1,
+    /// Priority classes of outbound messages (otherwise all messages are sent
+    /// with the default priority)
+    Qos = 2,
+    /// Fragmentation of large messages into streams of frames
+    Fragmentation = 3,
+    /// End-to-end correlation ids on direct-send messages and rpc requests
+    CorrelationIds = 4,
+    /// Independent substreams per priority class, whose fragments are interleaved
+    Substreams = 5,
+    /// Rotation of the noise session keys, without reconnecting
+    NoiseRekey = 6,
+    /// Delivery acknowledgements of (opted-in) direct-send messages
+    DirectSendAcks = 7,
+    /// Busy errors for the inbound rpcs shed by overloaded peers
+    LoadShedding = 8,
+    /// Goodbye frames announcing that a connection is gracefully drained
+    GracefulDrain = 9,
+    /// Nonces on direct-send messages, for the receiver's replay protection
+    ReplayProtection = 10,
+    /// Expired errors for the inbound rpcs that expired before being handled
+    RpcExpiry = 11,
+}
+
+impl Feature {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::Compression => "Compression",
+            Feature::StreamingRpc => "StreamingRpc",
+            Feature::Qos => "Qos",
+            Feature::Fragmentation => "Fragmentation",
+            Feature::CorrelationIds => "CorrelationIds",
+            Feature::Substreams => "Substreams",
+            Feature::NoiseRekey => "NoiseRekey",
+            Feature::DirectSendAcks => "DirectSendAcks",
+            Feature::LoadShedding => "LoadShedding",
+            Feature::GracefulDrain => "GracefulDrain",
+            Feature::ReplayProtection => "ReplayProtection",
+            Feature::RpcExpiry => "RpcExpiry",
+        }
+    }
+
+    pub fn all() -> &'static [Feature] {
+        &[
+            Feature::Compression,
+            Feature::StreamingRpc,
+            Feature::Qos,
+            Feature::Fragmentation,
+            Feature::CorrelationIds,
+            Feature::Substreams,
+            Feature::NoiseRekey,
+            Feature::DirectSendAcks,
+            Feature::LoadShedding,
+            Feature::GracefulDrain,
+            Feature::ReplayProtection,
+            Feature::RpcExpiry,
+        ]
+    }
+}
+
+impl fmt::Debug for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+//
+// FeatureSet
+//
+
+/// A compact representation for a set of [`Feature`]s. Like the [`ProtocolIdSet`],
+/// this is a bitvec, so that features unknown to older nodes are simply ignored.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct FeatureSet(aptos_bitvec::BitVec);
+
+impl FeatureSet {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    pub fn all_known() -> Self {
+        Self::from_iter(Feature::all())
+    }
+
+    /// Iterate over all `Feature`s, ignoring any that our node version doesn't
+    /// understand.
+    pub fn iter(&self) -> impl Iterator<Item = Feature> + '_ {
+        self.0
+            .iter_ones()
+            .filter_map(|idx| bcs::from_bytes(&[idx as u8]).ok())
+    }
+
+    /// Find the intersection between two sets of features.
+    pub fn intersect(&self, other: &FeatureSet) -> FeatureSet {
+        FeatureSet(self.0.bitand(&other.0))
+    }
+
+    /// Returns if the feature is set.
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0.is_set(feature as u16)
+    }
+
+    /// Insert a new feature into the set.
+    pub fn insert(&mut self, feature: Feature) {
+        self.0.set(feature as u16)
+    }
+}
+
+impl FromIterator<Feature> for FeatureSet {
+    fn from_iter<T: IntoIterator<Item = Feature>>(iter: T) -> Self {
+        Self(iter.into_iter().map(|feature| feature as u8).collect())
+    }
+}
+
+impl<'a> FromIterator<&'a Feature> for FeatureSet {
+    fn from_iter<T: IntoIterator<Item = &'a Feature>>(iter: T) -> Self {
+        iter.into_iter().copied().collect()
+    }
+}
+
+//
+// Protocol versions
+//
+
+/// The latest version of the given protocol supported by this node. Bump the version
+/// of a protocol when changing its messages, and only send the new messages to peers
+/// that negotiated the new version.
+pub fn latest_protocol_version(_protocol: ProtocolId) -> u8 {
+    // All protocols are still at their initial version
+    0
+}
+
+//
+// HandshakeMsgV2
+//
+
+/// The HandshakeMsgV2 contains the optional wire features supported by the node,
+/// the version of each application protocol it supports, and the protocols whose
+/// payloads it can compress transparently.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HandshakeMsgV2 {
+    pub features: FeatureSet,
+    pub protocol_versions: BTreeMap<ProtocolId, u8>,
+    pub compressed_protocols: ProtocolIdSet,
+}
+
+impl HandshakeMsgV2 {
+    /// Creates the handshake message of a node supporting the given features and
+    /// protocols (at their latest version).
+    pub fn new(features: FeatureSet, protocols: &ProtocolIdSet) -> Self {
+        Self {
+            features,
+            protocol_versions: protocols
+                .iter()
+                .map(|protocol| (protocol, latest_protocol_version(protocol)))
+                .collect(),
+            compressed_protocols: protocols
+                .iter()
+                .filter(|protocol| protocol.supports_transparent_compression())
+                .collect(),
+        }
+    }
+
+    /// Finds the features supported by both peers, the lowest common version of
+    /// the protocols supported by both peers and, if both peers support
+    /// [`Feature::Compression`], the protocols both peers can compress.
+    pub fn negotiate(&self, other: &HandshakeMsgV2) -> NegotiatedFeatures {
+        let protocol_versions = self
+            .protocol_versions
+            .iter()
+            .filter_map(|(protocol, our_version)| {
+                other
+                    .protocol_versions
+                    .get(protocol)
+                    .map(|their_version| (*protocol, *our_version.min(their_version)))
+            })
+            .collect();
+        let features = self.features.intersect(&other.features);
+        let compressed_protocols = if features.contains(Feature::Compression) {
+            self.compressed_protocols
+                .intersect(&other.compressed_protocols)
+        } else {
+            ProtocolIdSet::empty()
+        };
+        NegotiatedFeatures {
+            features,
+            protocol_versions,
+            compressed_protocols,
+        }
+    }
+}
+
+/// The wire features and protocol versions negotiated over a connection. For
+/// connections to peers that don't support the v2 handshake, no features are
+/// negotiated, all protocols are at version 0 and no protocol is compressed.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct NegotiatedFeatures {
+    pub features: FeatureSet,
+    pub protocol_versions: BTreeMap<ProtocolId, u8>,
+    pub compressed_protocols: ProtocolIdSet,
+}
+
+impl NegotiatedFeatures {
+    /// Returns if both peers support the feature
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// Returns the version of the protocol to use with the peer
+    pub fn protocol_version(&self, protocol: ProtocolId) -> u8 {
+        self.protocol_versions
+            .get(&protocol)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns if the payloads of the protocol are transparently compressed
+    pub fn is_compression_enabled(&self, protocol: ProtocolId) -> bool {
+        self.compressed_protocols.contains(protocol)
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use std::iter::FromIterator;
+
+#[test]
+fn test_as_u8_serde_equiv() {
+    for feature in Feature::all() {
+        let feature_as_u8_repr = *feature as u8;
+        let feature_bcs_repr = bcs::to_bytes(feature).unwrap();
+        assert_eq!(feature_bcs_repr, vec![feature_as_u8_repr]);
+        assert_eq!(
+            bcs::from_bytes::<Feature>(&[feature_as_u8_repr]).unwrap(),
+            *feature,
+        );
+    }
+}
+
+#[test]
+fn ignore_unknown_features() {
+    let some_unknown_features = FeatureSet(aptos_bitvec::BitVec::from_iter([
+        Feature::Qos as u8,
+        66,
+        234,
+    ]));
+    assert_eq!(some_unknown_features.iter().collect::<Vec<_>>(), vec![
+        Feature::Qos
+    ]);
+    assert_eq!(
+        FeatureSet::all_known().intersect(&some_unknown_features),
+        FeatureSet::from_iter([Feature::Qos])
+    );
+}
+
+#[test]
+fn negotiate() {
+    let ours = HandshakeMsgV2 {
+        features: FeatureSet::from_iter([Feature::Compression, Feature::StreamingRpc]),
+        protocol_versions: BTreeMap::from_iter([
+            (ProtocolId::ConsensusRpcBcs, 2),
+            (ProtocolId::MempoolDirectSend, 0),
+        ]),
+        compressed_protocols: ProtocolIdSet::from_iter([ProtocolId::ConsensusRpcBcs]),
+    };
+    let theirs = HandshakeMsgV2 {
+        features: FeatureSet::from_iter([Feature::StreamingRpc, Feature::Fragmentation]),
+        protocol_versions: BTreeMap::from_iter([
+            (ProtocolId::ConsensusRpcBcs, 1),
+            (ProtocolId::StorageServiceRpc, 3),
+        ]),
+        compressed_protocols: ProtocolIdSet::from_iter([ProtocolId::ConsensusRpcBcs]),
+    };
+
+    // Negotiation is symmetric
+    let negotiated = ours.negotiate(&theirs);
+    assert_eq!(negotiated, theirs.negotiate(&ours));
+
+    // Only common features are negotiated
+    assert!(negotiated.supports(Feature::StreamingRpc));
+    assert!(!negotiated.supports(Feature::Compression));
+    assert!(!negotiated.supports(Feature::Fragmentation));
+
+    // The lowest version of each common protocol is negotiated
+    assert_eq!(negotiated.protocol_version(ProtocolId::ConsensusRpcBcs), 1);
+    assert_eq!(
+        negotiated.protocol_version(ProtocolId::StorageServiceRpc),
+        0
+    );
+    assert_eq!(
+        negotiated.protocol_versions,
+        BTreeMap::from_iter([(ProtocolId::ConsensusRpcBcs, 1)])
+    );
+
+    // Nothing is compressed unless both peers support compression
+    assert!(!negotiated.is_compression_enabled(ProtocolId::ConsensusRpcBcs));
+}
+
+#[test]
+fn negotiate_compression() {
+    let ours = HandshakeMsgV2 {
+        features: FeatureSet::from_iter([Feature::Compression]),
+        protocol_versions: BTreeMap::new(),
+        compressed_protocols: ProtocolIdSet::from_iter([
+            ProtocolId::MempoolRpc,
+            ProtocolId::StorageServiceRpc,
+        ]),
+    };
+    let theirs = HandshakeMsgV2 {
+        compressed_protocols: ProtocolIdSet::from_iter([ProtocolId::StorageServiceRpc]),
+        ..ours.clone()
+    };
+
+    // Only the protocols both peers can compress are compressed
+    let negotiated = ours.negotiate(&theirs);
+    assert_eq!(negotiated, theirs.negotiate(&ours));
+    assert!(negotiated.is_compression_enabled(ProtocolId::StorageServiceRpc));
+    assert!(!negotiated.is_compression_enabled(ProtocolId::MempoolRpc));
+}
+
+#[test]
+fn new_from_protocols() {
+    let mut protocols =
+        ProtocolIdSet::from_iter([ProtocolId::ConsensusRpcBcs, ProtocolId::HealthCheckerRpc]);
+    protocols.insert(ProtocolId::ConsensusRpcCompressed);
+    protocols.enable_handshake_v2();
+
+    // Only actual protocols are versioned
+    let msg = HandshakeMsgV2::new(FeatureSet::all_known(), &protocols);
+    assert_eq!(
+        msg.protocol_versions.keys().copied().collect::<Vec<_>>(),
+        vec![
+            ProtocolId::ConsensusRpcBcs,
+            ProtocolId::HealthCheckerRpc,
+            ProtocolId::ConsensusRpcCompressed
+        ]
+    );
+
+    // Protocols that already compress their messages aren't compressed again
+    assert_eq!(
+        msg.compressed_protocols,
+        ProtocolIdSet::from_iter([ProtocolId::ConsensusRpcBcs, ProtocolId::HealthCheckerRpc])
+    );
+}
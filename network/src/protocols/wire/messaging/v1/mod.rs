@@ -11,7 +11,7 @@
 
 use crate::protocols::{stream::StreamMessage, wire::handshake::v1::ProtocolId};
 use aptos_rate_limiter::{async_lib::AsyncRateLimiter, rate_limit::SharedBucket};
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::{
     io::{AsyncRead, AsyncWrite},
     sink::Sink,
@@ -22,13 +22,15 @@ use pin_project::pin_project;
 use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, VecDeque},
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use thiserror::Error;
 use tokio_util::{
-    codec::{FramedRead, FramedWrite, LengthDelimitedCodec},
+    codec::{Encoder, FramedRead, FramedWrite, LengthDelimitedCodec},
     compat::{Compat, FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt},
 };
 
@@ -43,6 +45,16 @@ pub enum NetworkMessage {
     RpcRequest(RpcRequest),
     RpcResponse(RpcResponse),
     DirectSendMsg(DirectSendMsg),
+    StreamingRpcRequest(StreamingRpcRequest),
+    RpcResponseChunk(RpcResponseChunk),
+    RpcChunkAck(RpcChunkAck),
+    RpcRequestWithDeadline(RpcRequestWithDeadline),
+    CorrelatedDirectSendMsg(CorrelatedDirectSendMsg),
+    CorrelatedRpcRequest(CorrelatedRpcRequest),
+    AckedDirectSendMsg(AckedDirectSendMsg),
+    DirectSendAck(DirectSendAck),
+    Goodbye(Goodbye),
+    SequencedDirectSendMsg(SequencedDirectSendMsg),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -55,11 +67,86 @@ pub enum MultiplexMessage {
 impl NetworkMessage {
     /// The size of the raw data excluding the headers
     pub fn data_len(&self) -> usize {
+        self.payload().map_or(0, Bytes::len)
+    }
+
+    /// The raw data of the message, if it carries any
+    pub fn payload(&self) -> Option<&Bytes> {
         match self {
-            NetworkMessage::Error(_) => 0,
-            NetworkMessage::RpcRequest(request) => request.raw_request.len(),
-            NetworkMessage::RpcResponse(response) => response.raw_response.len(),
-            NetworkMessage::DirectSendMsg(message) => message.raw_msg.len(),
+            NetworkMessage::Error(_)
+            | NetworkMessage::RpcChunkAck(_)
+            | NetworkMessage::DirectSendAck(_)
+            | NetworkMessage::Goodbye(_) => None,
+            NetworkMessage::RpcRequest(request) => Some(&request.raw_request),
+            NetworkMessage::RpcResponse(response) => Some(&response.raw_response),
+            NetworkMessage::DirectSendMsg(message) => Some(&message.raw_msg),
+            NetworkMessage::StreamingRpcRequest(request) => Some(&request.raw_request),
+            NetworkMessage::RpcResponseChunk(chunk) => Some(&chunk.raw_chunk),
+            NetworkMessage::RpcRequestWithDeadline(request) => Some(&request.raw_request),
+            NetworkMessage::CorrelatedDirectSendMsg(message) => Some(&message.raw_msg),
+            NetworkMessage::CorrelatedRpcRequest(request) => Some(&request.raw_request),
+            NetworkMessage::AckedDirectSendMsg(message) => Some(&message.raw_msg),
+            NetworkMessage::SequencedDirectSendMsg(message) => Some(&message.raw_msg),
+        }
+    }
+
+    /// The mutable raw data of the message, if it carries any
+    pub fn payload_mut(&mut self) -> Option<&mut Bytes> {
+        match self {
+            NetworkMessage::Error(_)
+            | NetworkMessage::RpcChunkAck(_)
+            | NetworkMessage::DirectSendAck(_)
+            | NetworkMessage::Goodbye(_) => None,
+            NetworkMessage::RpcRequest(request) => Some(&mut request.raw_request),
+            NetworkMessage::RpcResponse(response) => Some(&mut response.raw_response),
+            NetworkMessage::DirectSendMsg(message) => Some(&mut message.raw_msg),
+            NetworkMessage::StreamingRpcRequest(request) => Some(&mut request.raw_request),
+            NetworkMessage::RpcResponseChunk(chunk) => Some(&mut chunk.raw_chunk),
+            NetworkMessage::RpcRequestWithDeadline(request) => Some(&mut request.raw_request),
+            NetworkMessage::CorrelatedDirectSendMsg(message) => Some(&mut message.raw_msg),
+            NetworkMessage::CorrelatedRpcRequest(request) => Some(&mut request.raw_request),
+            NetworkMessage::AckedDirectSendMsg(message) => Some(&mut message.raw_msg),
+            NetworkMessage::SequencedDirectSendMsg(message) => Some(&mut message.raw_msg),
+        }
+    }
+
+    /// The protocol of the message, if it's a request or a direct-send message
+    pub fn protocol_id(&self) -> Option<ProtocolId> {
+        match self {
+            NetworkMessage::RpcRequest(request) => Some(request.protocol_id),
+            NetworkMessage::DirectSendMsg(message) => Some(message.protocol_id),
+            NetworkMessage::StreamingRpcRequest(request) => Some(request.protocol_id),
+            NetworkMessage::RpcRequestWithDeadline(request) => Some(request.protocol_id),
+            NetworkMessage::CorrelatedDirectSendMsg(message) => Some(message.protocol_id),
+            NetworkMessage::CorrelatedRpcRequest(request) => Some(request.protocol_id),
+            NetworkMessage::AckedDirectSendMsg(message) => Some(message.protocol_id),
+            NetworkMessage::SequencedDirectSendMsg(message) => Some(message.protocol_id),
+            NetworkMessage::Error(_)
+            | NetworkMessage::RpcResponse(_)
+            | NetworkMessage::RpcResponseChunk(_)
+            | NetworkMessage::RpcChunkAck(_)
+            | NetworkMessage::DirectSendAck(_)
+            | NetworkMessage::Goodbye(_) => None,
+        }
+    }
+
+    /// The priority with which the message should be written to the wire
+    pub fn priority(&self) -> MessagePriority {
+        match self {
+            NetworkMessage::RpcRequest(request) => request.priority.into(),
+            NetworkMessage::RpcResponse(response) => response.priority.into(),
+            NetworkMessage::DirectSendMsg(message) => message.priority.into(),
+            NetworkMessage::StreamingRpcRequest(request) => request.priority.into(),
+            NetworkMessage::RpcRequestWithDeadline(request) => request.priority.into(),
+            NetworkMessage::CorrelatedDirectSendMsg(message) => message.priority.into(),
+            NetworkMessage::CorrelatedRpcRequest(request) => request.priority.into(),
+            NetworkMessage::AckedDirectSendMsg(message) => message.priority.into(),
+            NetworkMessage::SequencedDirectSendMsg(message) => message.priority.into(),
+            NetworkMessage::Error(_)
+            | NetworkMessage::RpcResponseChunk(_)
+            | NetworkMessage::RpcChunkAck(_)
+            | NetworkMessage::DirectSendAck(_)
+            | NetworkMessage::Goodbye(_) => MessagePriority::Normal,
         }
     }
 }
@@ -71,12 +158,38 @@ pub enum ErrorCode {
     ParsingError(ParsingErrorType),
     /// A message was received for a protocol that is not supported over this connection.
     NotSupported(NotSupportedType),
+    /// An rpc request was rejected because too many requests of its protocol are
+    /// already being processed. Only sent to peers that negotiated
+    /// [`Feature::LoadShedding`].
+    ///
+    /// [`Feature::LoadShedding`]: crate::protocols::wire::handshake::v2::Feature::LoadShedding
+    Busy(BusyType),
+    /// An rpc request expired (i.e., its deadline passed) while it was queued,
+    /// and was dropped without being handled. Only sent to peers that negotiated
+    /// [`Feature::RpcExpiry`].
+    ///
+    /// [`Feature::RpcExpiry`]: crate::protocols::wire::handshake::v2::Feature::RpcExpiry
+    Expired(ExpiredType),
 }
 
 impl ErrorCode {
     pub fn parsing_error(message: u8, protocol: u8) -> Self {
         ErrorCode::ParsingError(ParsingErrorType { message, protocol })
     }
+
+    pub fn busy(request_id: RequestId, protocol_id: ProtocolId) -> Self {
+        ErrorCode::Busy(BusyType {
+            request_id,
+            protocol_id,
+        })
+    }
+
+    pub fn expired(request_id: RequestId, protocol_id: ProtocolId) -> Self {
+        ErrorCode::Expired(ExpiredType {
+            request_id,
+            protocol_id,
+        })
+    }
 }
 
 /// Flags an invalid network message with as much header information as possible. This is a message
@@ -97,12 +210,115 @@ pub enum NotSupportedType {
     DirectSendMsg(ProtocolId),
 }
 
+/// Identifies the rejected rpc request of a [`ErrorCode::Busy`] error, so that
+/// the requester can fail it right away rather than waiting for it to time out.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct BusyType {
+    /// RequestId of the rejected rpc request.
+    pub request_id: RequestId,
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+}
+
+/// Identifies the dropped rpc request of a [`ErrorCode::Expired`] error, so that
+/// the requester can fail it right away rather than waiting for it to time out.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct ExpiredType {
+    /// RequestId of the dropped rpc request.
+    pub request_id: RequestId,
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+}
+
 /// Create alias RequestId for `u32`.
 pub type RequestId = u32;
 
 /// Create alias Priority for u8.
 pub type Priority = u8;
 
+/// Identifies an outbound direct-send message or rpc request end-to-end, i.e.,
+/// in the logs of the sender's queues, on the wire and in the receiver's
+/// handlers. Unlike a `RequestId`, it is random rather than local to a
+/// connection, so it can be searched for across the logs of many nodes.
+pub type CorrelationId = u64;
+
+/// Generates a new random `CorrelationId`.
+pub fn new_correlation_id() -> CorrelationId {
+    rand::random()
+}
+
+/// The QoS class of an outbound message. Each peer's outbound queue writes
+/// higher priority messages first, so that, e.g., consensus messages are not
+/// stuck behind bulk state sync data on a congested connection.
+///
+/// On the wire, `Normal` is encoded as the default priority (0), so that
+/// messages from peers unaware of priorities are treated as `Normal`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum MessagePriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Critical,
+}
+
+impl From<Priority> for MessagePriority {
+    fn from(priority: Priority) -> Self {
+        match priority {
+            0 => MessagePriority::Normal,
+            1 => MessagePriority::Low,
+            2 => MessagePriority::High,
+            _ => MessagePriority::Critical,
+        }
+    }
+}
+
+impl From<MessagePriority> for Priority {
+    fn from(priority: MessagePriority) -> Self {
+        match priority {
+            MessagePriority::Normal => 0,
+            MessagePriority::Low => 1,
+            MessagePriority::High => 2,
+            MessagePriority::Critical => 3,
+        }
+    }
+}
+
+/// A queue of outbound messages that pops the highest priority message first.
+/// Messages of the same priority are popped in FIFO order.
+#[derive(Debug, Default)]
+pub struct PrioritizedMessageQueue {
+    queues: BTreeMap<MessagePriority, VecDeque<NetworkMessage>>,
+}
+
+impl PrioritizedMessageQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, message: NetworkMessage) {
+        self.queues
+            .entry(message.priority())
+            .or_default()
+            .push_back(message);
+    }
+
+    pub fn pop(&mut self) -> Option<NetworkMessage> {
+        let mut queue = self.queues.last_entry()?;
+        let message = queue.get_mut().pop_front();
+        if queue.get().is_empty() {
+            queue.remove();
+        }
+        message
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub struct RpcRequest {
@@ -113,8 +329,9 @@ pub struct RpcRequest {
     /// Request priority in the range 0..=255.
     pub priority: Priority,
     /// Request payload. This will be parsed by the application-level handler.
-    #[serde(with = "serde_bytes")]
-    pub raw_request: Vec<u8>,
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_request: Bytes,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -126,8 +343,9 @@ pub struct RpcResponse {
     /// corresponding request.
     pub priority: Priority,
     /// Response payload.
-    #[serde(with = "serde_bytes")]
-    pub raw_response: Vec<u8>,
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_response: Bytes,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -138,8 +356,318 @@ pub struct DirectSendMsg {
     /// Message priority in the range 0..=255.
     pub priority: Priority,
     /// Message payload.
-    #[serde(with = "serde_bytes")]
-    pub raw_msg: Vec<u8>,
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_msg: Bytes,
+}
+
+/// An `RpcRequest` that also carries the time the sender is still willing to
+/// wait for the response. The budget is relative (rather than an absolute
+/// deadline) so that it doesn't depend on the peers' clocks being in sync.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct RpcRequestWithDeadline {
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+    /// RequestId for the RPC Request.
+    pub request_id: RequestId,
+    /// Request priority in the range 0..=255.
+    pub priority: Priority,
+    /// The remaining time budget (in milliseconds) for the request, as of
+    /// the time it was sent.
+    pub timeout_ms: u64,
+    /// Request payload. This will be parsed by the application-level handler.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_request: Bytes,
+}
+
+impl RpcRequestWithDeadline {
+    /// Splits the request into a plain `RpcRequest` and its time budget
+    pub fn into_parts(self) -> (RpcRequest, Duration) {
+        let request = RpcRequest {
+            protocol_id: self.protocol_id,
+            request_id: self.request_id,
+            priority: self.priority,
+            raw_request: self.raw_request,
+        };
+        (request, Duration::from_millis(self.timeout_ms))
+    }
+}
+
+/// A `DirectSendMsg` that also carries the sender's `CorrelationId`. Only sent
+/// to peers that negotiated [`Feature::CorrelationIds`].
+///
+/// [`Feature::CorrelationIds`]: crate::protocols::wire::handshake::v2::Feature::CorrelationIds
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct CorrelatedDirectSendMsg {
+    /// The sender's id of the message.
+    pub correlation_id: CorrelationId,
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+    /// Message priority in the range 0..=255.
+    pub priority: Priority,
+    /// Message payload.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_msg: Bytes,
+}
+
+impl CorrelatedDirectSendMsg {
+    pub fn new(message: DirectSendMsg, correlation_id: CorrelationId) -> Self {
+        Self {
+            correlation_id,
+            protocol_id: message.protocol_id,
+            priority: message.priority,
+            raw_msg: message.raw_msg,
+        }
+    }
+
+    /// Splits the message into a plain `DirectSendMsg` and its `CorrelationId`
+    pub fn into_parts(self) -> (DirectSendMsg, CorrelationId) {
+        let message = DirectSendMsg {
+            protocol_id: self.protocol_id,
+            priority: self.priority,
+            raw_msg: self.raw_msg,
+        };
+        (message, self.correlation_id)
+    }
+}
+
+/// An `RpcRequestWithDeadline` that also carries the sender's `CorrelationId`.
+/// Only sent to peers that negotiated [`Feature::CorrelationIds`].
+///
+/// [`Feature::CorrelationIds`]: crate::protocols::wire::handshake::v2::Feature::CorrelationIds
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct CorrelatedRpcRequest {
+    /// The sender's id of the request.
+    pub correlation_id: CorrelationId,
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+    /// RequestId for the RPC Request.
+    pub request_id: RequestId,
+    /// Request priority in the range 0..=255.
+    pub priority: Priority,
+    /// The remaining time budget (in milliseconds) for the request, as of
+    /// the time it was sent.
+    pub timeout_ms: u64,
+    /// Request payload. This will be parsed by the application-level handler.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_request: Bytes,
+}
+
+impl CorrelatedRpcRequest {
+    pub fn new(request: RpcRequestWithDeadline, correlation_id: CorrelationId) -> Self {
+        Self {
+            correlation_id,
+            protocol_id: request.protocol_id,
+            request_id: request.request_id,
+            priority: request.priority,
+            timeout_ms: request.timeout_ms,
+            raw_request: request.raw_request,
+        }
+    }
+
+    /// Splits the request into a plain `RpcRequestWithDeadline` and its
+    /// `CorrelationId`
+    pub fn into_parts(self) -> (RpcRequestWithDeadline, CorrelationId) {
+        let request = RpcRequestWithDeadline {
+            protocol_id: self.protocol_id,
+            request_id: self.request_id,
+            priority: self.priority,
+            timeout_ms: self.timeout_ms,
+            raw_request: self.raw_request,
+        };
+        (request, self.correlation_id)
+    }
+}
+
+/// A `DirectSendMsg` whose delivery to the receiver's application is
+/// acknowledged with a [`DirectSendAck`] carrying the same `RequestId`. Only
+/// sent to peers that negotiated [`Feature::DirectSendAcks`].
+///
+/// [`Feature::DirectSendAcks`]: crate::protocols::wire::handshake::v2::Feature::DirectSendAcks
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct AckedDirectSendMsg {
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+    /// RequestId of the message, echoed back in its acknowledgement.
+    pub request_id: RequestId,
+    /// Message priority in the range 0..=255.
+    pub priority: Priority,
+    /// Message payload.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_msg: Bytes,
+}
+
+impl AckedDirectSendMsg {
+    pub fn new(message: DirectSendMsg, request_id: RequestId) -> Self {
+        Self {
+            protocol_id: message.protocol_id,
+            request_id,
+            priority: message.priority,
+            raw_msg: message.raw_msg,
+        }
+    }
+
+    /// Splits the message into a plain `DirectSendMsg` and its `RequestId`
+    pub fn into_parts(self) -> (DirectSendMsg, RequestId) {
+        let message = DirectSendMsg {
+            protocol_id: self.protocol_id,
+            priority: self.priority,
+            raw_msg: self.raw_msg,
+        };
+        (message, self.request_id)
+    }
+}
+
+/// A `CorrelatedDirectSendMsg` that also carries the sender's nonce, which
+/// increases with every message sent over the network. Receivers drop the
+/// messages of replay-protected protocols whose nonce was already seen (or is
+/// older than their window). Only sent to peers that negotiated
+/// [`Feature::ReplayProtection`].
+///
+/// [`Feature::ReplayProtection`]: crate::protocols::wire::handshake::v2::Feature::ReplayProtection
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct SequencedDirectSendMsg {
+    /// The sender's nonce of the message.
+    pub nonce: u64,
+    /// The sender's id of the message.
+    pub correlation_id: CorrelationId,
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+    /// Message priority in the range 0..=255.
+    pub priority: Priority,
+    /// Message payload.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_msg: Bytes,
+}
+
+impl SequencedDirectSendMsg {
+    pub fn new(message: DirectSendMsg, nonce: u64, correlation_id: CorrelationId) -> Self {
+        Self {
+            nonce,
+            correlation_id,
+            protocol_id: message.protocol_id,
+            priority: message.priority,
+            raw_msg: message.raw_msg,
+        }
+    }
+
+    /// Splits the message into a plain `DirectSendMsg`, its nonce and its
+    /// `CorrelationId`
+    pub fn into_parts(self) -> (DirectSendMsg, u64, CorrelationId) {
+        let message = DirectSendMsg {
+            protocol_id: self.protocol_id,
+            priority: self.priority,
+            raw_msg: self.raw_msg,
+        };
+        (message, self.nonce, self.correlation_id)
+    }
+}
+
+/// Acknowledges the receipt of the [`AckedDirectSendMsg`] with the given id.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct DirectSendAck {
+    /// RequestId of the acknowledged message.
+    pub request_id: RequestId,
+    /// Whether the message was enqueued for the receiver's application, rather
+    /// than dropped (e.g., because the application's queue was full).
+    pub delivered: bool,
+}
+
+/// Announces that the sender is gracefully closing the connection: the
+/// receiver should stop sending new requests, and the connection is closed
+/// once the pending ones completed (or the drain timeout elapsed). Only sent
+/// to peers that negotiated [`Feature::GracefulDrain`].
+///
+/// [`Feature::GracefulDrain`]: crate::protocols::wire::handshake::v2::Feature::GracefulDrain
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct Goodbye {
+    /// Why the connection is closed (e.g., "restart"), for the receiver's logs.
+    pub reason: String,
+    /// The time after which the connection is closed, even if requests are
+    /// still pending.
+    pub drain_timeout_ms: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct StreamingRpcRequest {
+    /// `protocol_id` is a variant of the ProtocolId enum.
+    pub protocol_id: ProtocolId,
+    /// RequestId for the streaming RPC Request.
+    pub request_id: RequestId,
+    /// Request priority in the range 0..=255.
+    pub priority: Priority,
+    /// The number of response chunks the responder may send before it has to
+    /// wait for an acknowledgement from the requester.
+    pub window: u32,
+    /// Request payload. This will be parsed by the application-level handler.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_request: Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct RpcResponseChunk {
+    /// RequestId for corresponding streaming request.
+    pub request_id: RequestId,
+    /// The index of this chunk in the response stream, starting at 0.
+    pub chunk_id: u32,
+    /// Set on the last chunk of the stream. No chunks follow this one.
+    pub end_of_stream: bool,
+    /// Set (along with `end_of_stream`) if the responder failed to complete
+    /// the stream, in which case the chunks received so far are incomplete.
+    pub failed: bool,
+    /// Chunk payload. Empty for an end-of-stream marker without data.
+    #[serde(with = "serde_payload")]
+    #[cfg_attr(any(test, feature = "fuzzing"), proptest(strategy = "arb_payload()"))]
+    pub raw_chunk: Bytes,
+}
+
+/// Acknowledges that the requester has consumed the response chunk with the
+/// given id, which allows the responder to send one more chunk.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub struct RpcChunkAck {
+    /// RequestId for corresponding streaming request.
+    pub request_id: RequestId,
+    /// The id of the consumed chunk.
+    pub chunk_id: u32,
+}
+
+/// (De)serializes `Bytes` payloads exactly like `serde_bytes` does `Vec<u8>`s,
+/// so that the payloads can be shared with the application (and between the
+/// messages to different peers) without changing the wire format.
+pub(crate) mod serde_payload {
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(payload: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_bytes::serialize(payload.as_ref(), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        serde_bytes::ByteBuf::deserialize(deserializer).map(|payload| payload.into_vec().into())
+    }
+}
+
+/// Generates arbitrary message payloads
+#[cfg(any(test, feature = "fuzzing"))]
+pub(crate) fn arb_payload() -> impl proptest::strategy::Strategy<Value = Bytes> {
+    use proptest::{arbitrary::any, strategy::Strategy};
+    any::<Vec<u8>>().prop_map(Bytes::from)
 }
 
 /// Errors from reading and deserializing network messages off the wire.
@@ -162,12 +690,15 @@ pub enum WriteError {
     IoError(#[from] io::Error),
 }
 
+/// The length (in bytes) of the length prefix of each frame
+const FRAME_LENGTH_FIELD_LEN: usize = 4;
+
 /// Returns a fully configured length-delimited codec for writing/reading
 /// serialized [`NetworkMessage`] frames to/from a socket.
 pub fn network_message_frame_codec(max_frame_size: usize) -> LengthDelimitedCodec {
     LengthDelimitedCodec::builder()
         .max_frame_length(max_frame_size)
-        .length_field_length(4)
+        .length_field_length(FRAME_LENGTH_FIELD_LEN)
         .big_endian()
         .new_codec()
 }
@@ -218,20 +749,75 @@ impl<TReadSocket: AsyncRead + Unpin> Stream for MultiplexMessageStream<TReadSock
     }
 }
 
+/// Encodes outbound `MultiplexMessage`s into the same length-delimited frames
+/// as [`network_message_frame_codec`]. Messages are serialized straight into
+/// the write buffer (rather than into an intermediate frame), so that their
+/// payloads are only copied once on their way to the socket.
+pub struct MultiplexMessageEncoder {
+    max_frame_size: usize,
+}
+
+impl MultiplexMessageEncoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    /// Reserves room for a frame of the given length, and writes its length prefix
+    fn put_frame_header(&self, frame_len: usize, dst: &mut BytesMut) -> Result<(), WriteError> {
+        if frame_len > self.max_frame_size {
+            return Err(WriteError::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame size too big: {}, max frame size: {}",
+                    frame_len, self.max_frame_size
+                ),
+            )));
+        }
+        dst.reserve(FRAME_LENGTH_FIELD_LEN + frame_len);
+        dst.put_u32(frame_len as u32);
+        Ok(())
+    }
+}
+
+impl<'a> Encoder<&'a MultiplexMessage> for MultiplexMessageEncoder {
+    type Error = WriteError;
+
+    fn encode(
+        &mut self,
+        message: &'a MultiplexMessage,
+        dst: &mut BytesMut,
+    ) -> Result<(), WriteError> {
+        let frame_len = bcs::serialized_size(message).map_err(WriteError::SerializeError)?;
+        self.put_frame_header(frame_len, dst)?;
+        bcs::serialize_into(&mut dst.writer(), message).map_err(WriteError::SerializeError)
+    }
+}
+
+/// Encodes pre-serialized frames
+impl Encoder<Bytes> for MultiplexMessageEncoder {
+    type Error = WriteError;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> Result<(), WriteError> {
+        self.put_frame_header(frame.len(), dst)?;
+        dst.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
 /// A `Sink` of outbound `NetworkMessage`s that will be serialized and sent over
 /// an underlying socket.
 #[pin_project]
 pub struct MultiplexMessageSink<TWriteSocket: AsyncWrite> {
     #[pin]
-    framed_write: FramedWrite<Compat<AsyncRateLimiter<TWriteSocket>>, LengthDelimitedCodec>,
+    framed_write: FramedWrite<Compat<AsyncRateLimiter<TWriteSocket>>, MultiplexMessageEncoder>,
 }
 
 impl<TWriteSocket: AsyncWrite> MultiplexMessageSink<TWriteSocket> {
     pub fn new(socket: TWriteSocket, max_frame_size: usize, bucket: Option<SharedBucket>) -> Self {
-        let frame_codec = network_message_frame_codec(max_frame_size);
+        let encoder = MultiplexMessageEncoder::new(max_frame_size);
         let rate_limited_socket = AsyncRateLimiter::new(socket, bucket);
         let compat_socket = rate_limited_socket.compat_write();
-        let framed_write = FramedWrite::new(compat_socket, frame_codec);
+        let framed_write = FramedWrite::new(compat_socket, encoder);
         Self { framed_write }
     }
 }
@@ -240,10 +826,7 @@ impl<TWriteSocket: AsyncWrite> MultiplexMessageSink<TWriteSocket> {
 impl<TWriteSocket: AsyncWrite + Unpin> MultiplexMessageSink<TWriteSocket> {
     pub async fn send_raw_frame(&mut self, frame: Bytes) -> Result<(), WriteError> {
         use futures::sink::SinkExt;
-        self.framed_write
-            .send(frame)
-            .await
-            .map_err(WriteError::IoError)
+        self.framed_write.send(frame).await
     }
 }
 
@@ -251,33 +834,18 @@ impl<TWriteSocket: AsyncWrite> Sink<&MultiplexMessage> for MultiplexMessageSink<
     type Error = WriteError;
 
     fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project()
-            .framed_write
-            .poll_ready(cx)
-            .map_err(WriteError::IoError)
+        Sink::<&MultiplexMessage>::poll_ready(self.project().framed_write, cx)
     }
 
     fn start_send(self: Pin<&mut Self>, message: &MultiplexMessage) -> Result<(), Self::Error> {
-        let frame = bcs::to_bytes(message).map_err(WriteError::SerializeError)?;
-        let frame = Bytes::from(frame);
-
-        self.project()
-            .framed_write
-            .start_send(frame)
-            .map_err(WriteError::IoError)
+        self.project().framed_write.start_send(message)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project()
-            .framed_write
-            .poll_flush(cx)
-            .map_err(WriteError::IoError)
+        Sink::<&MultiplexMessage>::poll_flush(self.project().framed_write, cx)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project()
-            .framed_write
-            .poll_close(cx)
-            .map_err(WriteError::IoError)
+        Sink::<&MultiplexMessage>::poll_close(self.project().framed_write, cx)
     }
 }
@@ -3,12 +3,15 @@
 
 use super::*;
 use crate::{
-    protocols::stream::{InboundStreamBuffer, OutboundStream, StreamFragment, StreamHeader},
+    protocols::stream::{
+        InboundStreamBuffer, OutboundStream, SequencedStreamFragment, SequencedStreamHeader,
+        StreamFragment, StreamHeader, SubstreamFragment, SubstreamHeader,
+    },
     testutils::fake_socket::{ReadOnlyTestSocket, ReadWriteTestSocket},
 };
 use aptos_memsocket::MemorySocket;
 use bcs::test_helpers::assert_canonical_encode_decode;
-use futures::{executor::block_on, future, sink::SinkExt, stream::StreamExt};
+use futures::{executor::block_on, future, sink::SinkExt, stream::StreamExt, FutureExt};
 use futures_util::stream::select;
 use proptest::{collection::vec, prelude::*};
 
@@ -36,7 +39,7 @@ fn rpc_request() -> bcs::Result<()> {
         request_id: 25,
         protocol_id: ProtocolId::ConsensusRpcBcs,
         priority: 0,
-        raw_request: [0, 1, 2, 3].to_vec(),
+        raw_request: Bytes::from_static(&[0, 1, 2, 3]),
     };
     assert_eq!(
         bcs::to_bytes(&rpc_request)?,
@@ -50,12 +53,45 @@ fn rpc_request() -> bcs::Result<()> {
     Ok(())
 }
 
+#[test]
+fn prioritized_message_queue() {
+    let direct_send = |priority: MessagePriority, raw_msg: u8| {
+        NetworkMessage::DirectSendMsg(DirectSendMsg {
+            protocol_id: ProtocolId::ConsensusDirectSendBcs,
+            priority: priority.into(),
+            raw_msg: Bytes::from(vec![raw_msg]),
+        })
+    };
+
+    let mut queue = PrioritizedMessageQueue::new();
+    queue.push(direct_send(MessagePriority::Low, 0));
+    queue.push(direct_send(MessagePriority::Normal, 1));
+    queue.push(direct_send(MessagePriority::Critical, 2));
+    queue.push(direct_send(MessagePriority::Normal, 3));
+    queue.push(direct_send(MessagePriority::High, 4));
+
+    // Higher priorities are popped first, equal priorities in FIFO order
+    assert_eq!(queue.pop(), Some(direct_send(MessagePriority::Critical, 2)));
+    assert_eq!(queue.pop(), Some(direct_send(MessagePriority::High, 4)));
+    assert_eq!(queue.pop(), Some(direct_send(MessagePriority::Normal, 1)));
+    assert_eq!(queue.pop(), Some(direct_send(MessagePriority::Normal, 3)));
+    assert_eq!(queue.pop(), Some(direct_send(MessagePriority::Low, 0)));
+    assert_eq!(queue.pop(), None);
+    assert!(queue.is_empty());
+
+    // The default wire priority is normal
+    assert_eq!(
+        MessagePriority::from(Priority::default()),
+        MessagePriority::Normal
+    );
+}
+
 #[test]
 fn stream_message() {
     let message = NetworkMessage::DirectSendMsg(DirectSendMsg {
         protocol_id: ProtocolId::MempoolDirectSend,
         priority: 0,
-        raw_msg: Vec::from("hello world"),
+        raw_msg: Bytes::from_static(b"hello world"),
     });
     let stream_header = StreamHeader {
         request_id: 42,
@@ -68,19 +104,306 @@ fn stream_message() {
     let stream_fragment = StreamFragment {
         request_id: 42,
         fragment_id: 254,
-        raw_data: vec![11, 22, 33],
+        raw_data: Bytes::from(vec![11, 22, 33]),
     };
     assert_eq!(bcs::to_bytes(&stream_fragment).unwrap(), vec![
         42, 0, 0, 0, 254, 3, 11, 22, 33
     ],);
 }
 
+#[test]
+fn sequenced_stream_message() {
+    let message = NetworkMessage::DirectSendMsg(DirectSendMsg {
+        protocol_id: ProtocolId::MempoolDirectSend,
+        priority: 0,
+        raw_msg: Bytes::from_static(b"hello world"),
+    });
+    let stream_header = StreamMessage::SequencedHeader(SequencedStreamHeader {
+        request_id: 42,
+        num_fragments: 300,
+        payload_len: 1000,
+        message,
+    });
+    assert_eq!(bcs::to_bytes(&stream_header).unwrap(), vec![
+        2, 42, 0, 0, 0, 44, 1, 0, 0, 232, 3, 0, 0, 0, 0, 0, 0, 3, 2, 0, 11, 104, 101, 108, 108,
+        111, 32, 119, 111, 114, 108, 100
+    ],);
+    let stream_fragment = StreamMessage::SequencedFragment(SequencedStreamFragment {
+        request_id: 42,
+        fragment_id: 299,
+        raw_data: Bytes::from(vec![11, 22, 33]),
+    });
+    assert_eq!(bcs::to_bytes(&stream_fragment).unwrap(), vec![
+        3, 42, 0, 0, 0, 43, 1, 0, 0, 3, 11, 22, 33
+    ],);
+}
+
+/// Streams the message, and reassembles it with the given reassembly limit
+fn stream_and_reassemble(
+    outbound_stream: &mut OutboundStream,
+    stream_rx: &mut aptos_channels::Receiver<MultiplexMessage>,
+    message: NetworkMessage,
+    max_reassembly_size: usize,
+) -> anyhow::Result<Option<NetworkMessage>> {
+    block_on(outbound_stream.stream_message(message))?;
+    let mut inbound_stream = InboundStreamBuffer::new(255, max_reassembly_size);
+    let mut reassembled = None;
+    while let Some(Some(MultiplexMessage::Stream(message))) = stream_rx.next().now_or_never() {
+        reassembled = match message {
+            StreamMessage::Header(header) => inbound_stream.new_stream(header).map(|_| None)?,
+            StreamMessage::Fragment(fragment) => inbound_stream.append_fragment(fragment)?,
+            StreamMessage::SequencedHeader(header) => {
+                inbound_stream.new_sequenced_stream(header).map(|_| None)?
+            },
+            StreamMessage::SequencedFragment(fragment) => {
+                inbound_stream.append_sequenced_fragment(fragment)?
+            },
+            StreamMessage::SubstreamHeader(header) => {
+                inbound_stream.new_substream(header).map(|_| None)?
+            },
+            StreamMessage::SubstreamFragment(fragment) => {
+                inbound_stream.append_substream_fragment(fragment)?
+            },
+        };
+    }
+    Ok(reassembled)
+}
+
+#[test]
+fn fragmentation_exceeds_max_message_size() {
+    let (stream_tx, mut stream_rx) = aptos_channels::new_test(1024);
+    let mut outbound_stream = OutboundStream::new(128, 64 * 10, stream_tx);
+    let message = NetworkMessage::DirectSendMsg(DirectSendMsg {
+        protocol_id: ProtocolId::ConsensusDirectSendBcs,
+        priority: 0,
+        raw_msg: Bytes::from(vec![7; 64 * 400]),
+    });
+
+    // Without fragmentation, the message is too large to be streamed
+    block_on(outbound_stream.stream_message(message.clone())).unwrap_err();
+
+    // With fragmentation, it's reassembled as long as it fits the reassembly limit
+    let mut outbound_stream = outbound_stream.with_fragmentation(64 * 1000);
+    let reassembled = stream_and_reassemble(
+        &mut outbound_stream,
+        &mut stream_rx,
+        message.clone(),
+        64 * 400,
+    )
+    .unwrap();
+    assert_eq!(reassembled, Some(message.clone()));
+    stream_and_reassemble(&mut outbound_stream, &mut stream_rx, message, 64 * 400 - 1).unwrap_err();
+}
+
+#[test]
+fn sequenced_stream_is_capped() {
+    let message = NetworkMessage::DirectSendMsg(DirectSendMsg {
+        protocol_id: ProtocolId::ConsensusDirectSendBcs,
+        priority: 0,
+        raw_msg: Bytes::from(vec![7; 10]),
+    });
+    let header = |payload_len| SequencedStreamHeader {
+        request_id: 1,
+        num_fragments: 2,
+        payload_len,
+        message: message.clone(),
+    };
+    let fragment = |fragment_id, len| SequencedStreamFragment {
+        request_id: 1,
+        fragment_id,
+        raw_data: Bytes::from(vec![7; len]),
+    };
+
+    // Headers announcing payloads above the limit are rejected upfront
+    let mut inbound_stream = InboundStreamBuffer::new(255, 100);
+    inbound_stream
+        .new_sequenced_stream(header(101))
+        .unwrap_err();
+
+    // Fragments beyond the announced length are rejected
+    inbound_stream.new_sequenced_stream(header(30)).unwrap();
+    assert_eq!(
+        inbound_stream
+            .append_sequenced_fragment(fragment(1, 10))
+            .unwrap(),
+        None
+    );
+    inbound_stream
+        .append_sequenced_fragment(fragment(2, 11))
+        .unwrap_err();
+
+    // Streams shorter than announced are rejected
+    let mut inbound_stream = InboundStreamBuffer::new(255, 100);
+    inbound_stream.new_sequenced_stream(header(30)).unwrap();
+    inbound_stream
+        .append_sequenced_fragment(fragment(1, 10))
+        .unwrap();
+    inbound_stream
+        .append_sequenced_fragment(fragment(2, 5))
+        .unwrap_err();
+}
+
+#[test]
+fn empty_fragments_are_rejected() {
+    let message = NetworkMessage::DirectSendMsg(DirectSendMsg {
+        protocol_id: ProtocolId::ConsensusDirectSendBcs,
+        priority: 0,
+        raw_msg: Bytes::from(vec![7; 10]),
+    });
+    let header = |num_fragments| SequencedStreamHeader {
+        request_id: 1,
+        num_fragments,
+        payload_len: 30,
+        message: message.clone(),
+    };
+    let empty_fragment = |fragment_id| SequencedStreamFragment {
+        request_id: 1,
+        fragment_id,
+        raw_data: Bytes::new(),
+    };
+
+    // Headers announcing more fragments than bytes to come are rejected upfront
+    let mut inbound_stream = InboundStreamBuffer::new(255, 100);
+    inbound_stream
+        .new_sequenced_stream(header(u32::MAX))
+        .unwrap_err();
+    inbound_stream.new_sequenced_stream(header(21)).unwrap_err();
+
+    // A stream of empty fragments is rejected at its first fragment
+    inbound_stream.new_sequenced_stream(header(20)).unwrap();
+    inbound_stream
+        .append_sequenced_fragment(empty_fragment(1))
+        .unwrap_err();
+    inbound_stream
+        .append_sequenced_fragment(empty_fragment(2))
+        .unwrap_err();
+}
+
+#[test]
+fn substreams_share_the_reassembly_limit() {
+    let header = |substream_id| SubstreamHeader {
+        substream_id,
+        header: SequencedStreamHeader {
+            request_id: 1,
+            num_fragments: 2,
+            payload_len: 60,
+            message: NetworkMessage::DirectSendMsg(DirectSendMsg {
+                protocol_id: ProtocolId::ConsensusDirectSendBcs,
+                priority: 0,
+                raw_msg: Bytes::from(vec![7; 10]),
+            }),
+        },
+    };
+    let fragment = |substream_id, fragment_id, len| SubstreamFragment {
+        substream_id,
+        fragment: SequencedStreamFragment {
+            request_id: 1,
+            fragment_id,
+            raw_data: Bytes::from(vec![7; len]),
+        },
+    };
+
+    let mut inbound_stream = InboundStreamBuffer::new(255, 100);
+    inbound_stream.new_substream(header(0)).unwrap();
+    inbound_stream
+        .append_substream_fragment(fragment(0, 1, 40))
+        .unwrap();
+
+    // Each substream is below the limit, but not both together, so the
+    // substream of the rejected fragment is dropped
+    inbound_stream.new_substream(header(1)).unwrap();
+    inbound_stream
+        .append_substream_fragment(fragment(1, 1, 45))
+        .unwrap_err();
+    inbound_stream
+        .append_substream_fragment(fragment(1, 2, 5))
+        .unwrap_err();
+
+    // Which frees its bytes for the other substream
+    assert!(inbound_stream
+        .append_substream_fragment(fragment(0, 2, 10))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn substreams_are_interleaved() {
+    let new_message = |protocol_id, len| {
+        NetworkMessage::DirectSendMsg(DirectSendMsg {
+            protocol_id,
+            priority: 0,
+            raw_msg: Bytes::from(vec![7; len]),
+        })
+    };
+    let bulk_message = new_message(ProtocolId::StateSyncDirectSend, 64 * 20);
+    let consensus_message = new_message(ProtocolId::ConsensusDirectSendBcs, 64 * 5);
+
+    // Stream both messages on their own substream
+    let (bulk_tx, mut bulk_rx) = aptos_channels::new_test(1024);
+    let (consensus_tx, mut consensus_rx) = aptos_channels::new_test(1024);
+    block_on(
+        OutboundStream::new(128, 64 * 255, bulk_tx)
+            .with_substream(0)
+            .stream_message(bulk_message.clone()),
+    )
+    .unwrap();
+    block_on(
+        OutboundStream::new(128, 64 * 255, consensus_tx)
+            .with_substream(3)
+            .stream_message(consensus_message.clone()),
+    )
+    .unwrap();
+
+    // Interleave their frames, starting with the bulk transfer
+    let mut frames = vec![];
+    loop {
+        let bulk_frame = bulk_rx.next().now_or_never().flatten();
+        let consensus_frame = consensus_rx.next().now_or_never().flatten();
+        if bulk_frame.is_none() && consensus_frame.is_none() {
+            break;
+        }
+        frames.extend(bulk_frame);
+        frames.extend(consensus_frame);
+    }
+
+    // The consensus message is reassembled first, although its stream
+    // started after the bulk one
+    let mut inbound_stream = InboundStreamBuffer::new(255, 64 * 255);
+    let mut reassembled = vec![];
+    for frame in frames {
+        let message = match frame {
+            MultiplexMessage::Stream(StreamMessage::SubstreamHeader(header)) => {
+                inbound_stream.new_substream(header).map(|_| None)
+            },
+            MultiplexMessage::Stream(StreamMessage::SubstreamFragment(fragment)) => {
+                inbound_stream.append_substream_fragment(fragment)
+            },
+            frame => panic!("Unexpected frame {:?}", frame),
+        };
+        reassembled.extend(message.unwrap());
+    }
+    assert_eq!(reassembled, vec![consensus_message.clone(), bulk_message]);
+
+    // Unknown substreams are rejected
+    inbound_stream
+        .new_substream(SubstreamHeader {
+            substream_id: 4,
+            header: SequencedStreamHeader {
+                request_id: 1,
+                num_fragments: 1,
+                payload_len: 10,
+                message: consensus_message,
+            },
+        })
+        .unwrap_err();
+}
+
 #[test]
 fn aptosnet_wire_test_vectors() {
     let message = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
         protocol_id: ProtocolId::MempoolDirectSend,
         priority: 0,
-        raw_msg: Vec::from("hello world"),
+        raw_msg: Bytes::from_static(b"hello world"),
     }));
     let message_bytes = [
         // [0, 0, 0, 16] -> frame length
@@ -127,11 +450,38 @@ fn send_fails_when_larger_than_frame_limit() {
     let message = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
         protocol_id: ProtocolId::ConsensusRpcBcs,
         priority: 0,
-        raw_msg: vec![0; 123],
+        raw_msg: Bytes::from(vec![0; 123]),
     }));
     block_on(message_tx.send(&message)).unwrap_err();
 }
 
+#[test]
+fn encoder_matches_frame_codec() {
+    let message = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
+        protocol_id: ProtocolId::ConsensusRpcBcs,
+        priority: 0,
+        raw_msg: Bytes::from(vec![7; 1024]),
+    }));
+    let frame = Bytes::from(bcs::to_bytes(&message).unwrap());
+    let mut expected = BytesMut::new();
+    network_message_frame_codec(2048)
+        .encode(frame.clone(), &mut expected)
+        .unwrap();
+
+    // Both messages and pre-serialized frames are framed like the codec does
+    let mut encoder = MultiplexMessageEncoder::new(2048);
+    let mut encoded = BytesMut::new();
+    encoder.encode(&message, &mut encoded).unwrap();
+    assert_eq!(encoded, expected);
+    let mut encoded = BytesMut::new();
+    encoder.encode(frame, &mut encoded).unwrap();
+    assert_eq!(encoded, expected);
+
+    // Frames larger than the max frame size are rejected
+    let mut encoder = MultiplexMessageEncoder::new(1024);
+    encoder.encode(&message, &mut BytesMut::new()).unwrap_err();
+}
+
 #[test]
 fn recv_fails_when_larger_than_frame_limit() {
     let (memsocket_tx, memsocket_rx) = MemorySocket::new_pair();
@@ -143,7 +493,7 @@ fn recv_fails_when_larger_than_frame_limit() {
     let message = MultiplexMessage::Message(NetworkMessage::DirectSendMsg(DirectSendMsg {
         protocol_id: ProtocolId::ConsensusRpcBcs,
         priority: 0,
-        raw_msg: vec![0; 80],
+        raw_msg: Bytes::from(vec![0; 80]),
     }));
     let f_send = message_tx.send(&message);
     let f_recv = message_rx.next();
@@ -157,7 +507,7 @@ fn arb_rpc_request(max_frame_size: usize) -> impl Strategy<Value = RpcRequest> {
         any::<ProtocolId>(),
         any::<RequestId>(),
         any::<Priority>(),
-        (0..max_frame_size).prop_map(|size| vec![0u8; size]),
+        (0..max_frame_size).prop_map(|size| Bytes::from(vec![0u8; size])),
     )
         .prop_map(
             |(protocol_id, request_id, priority, raw_request)| RpcRequest {
@@ -173,7 +523,7 @@ fn arb_rpc_response(max_frame_size: usize) -> impl Strategy<Value = RpcResponse>
     (
         any::<RequestId>(),
         any::<Priority>(),
-        (0..max_frame_size).prop_map(|size| vec![0u8; size]),
+        (0..max_frame_size).prop_map(|size| Bytes::from(vec![0u8; size])),
     )
         .prop_map(|(request_id, priority, raw_response)| RpcResponse {
             request_id,
@@ -186,7 +536,7 @@ fn arb_direct_send_msg(max_frame_size: usize) -> impl Strategy<Value = DirectSen
     let args = (
         any::<ProtocolId>(),
         any::<Priority>(),
-        (0..max_frame_size).prop_map(|size| vec![0u8; size]),
+        (0..max_frame_size).prop_map(|size| Bytes::from(vec![0u8; size])),
     );
     args.prop_map(|(protocol_id, priority, raw_msg)| DirectSendMsg {
         protocol_id,
@@ -222,6 +572,7 @@ proptest! {
         messages in vec(arb_network_message(64 * 255), 1..20),
         fragmented_read in any::<bool>(),
         fragmented_write in any::<bool>(),
+        fragmentation in any::<bool>(),
     ) {
         let (mut socket_tx, mut socket_rx) = ReadWriteTestSocket::new_pair();
 
@@ -237,7 +588,10 @@ proptest! {
         let (stream_tx, stream_rx) = aptos_channels::new_test(1024);
         let (mut msg_tx, msg_rx) = aptos_channels::new_test(1024);
         let mut outbound_stream = OutboundStream::new(128, 64 * 255, stream_tx);
-        let mut inbound_stream = InboundStreamBuffer::new(255);
+        if fragmentation {
+            outbound_stream = outbound_stream.with_fragmentation(64 * 255);
+        }
+        let mut inbound_stream = InboundStreamBuffer::new(255, 64 * 255);
 
         let messages_clone = messages.clone();
         let f_stream_all = async move {
@@ -276,6 +630,22 @@ proptest! {
                                 recv.push(network_msg);
                             }
                         }
+                        StreamMessage::SequencedHeader(header) => {
+                            inbound_stream.new_sequenced_stream(header).unwrap()
+                        }
+                        StreamMessage::SequencedFragment(fragment) => {
+                            if let Some(network_msg) = inbound_stream.append_sequenced_fragment(fragment).unwrap() {
+                                recv.push(network_msg);
+                            }
+                        }
+                        StreamMessage::SubstreamHeader(header) => {
+                            inbound_stream.new_substream(header).unwrap()
+                        }
+                        StreamMessage::SubstreamFragment(fragment) => {
+                            if let Some(network_msg) = inbound_stream.append_substream_fragment(fragment).unwrap() {
+                                recv.push(network_msg);
+                            }
+                        }
                     }
                 }
             }
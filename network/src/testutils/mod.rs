@@ -3,5 +3,6 @@
 
 pub mod builder;
 pub mod fake_socket;
+pub mod simulation;
 pub mod test_framework;
 pub mod test_node;
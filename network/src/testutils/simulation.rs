@@ -0,0 +1,614 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! A deterministic, in-process simulation of a network.
+//!
+//! The [`SimulatedNetwork`] connects the [`NetworkClient`] and [`NetworkEvents`]
+//! of several nodes without any sockets: the requests of each node are routed
+//! to the other nodes with a configurable latency, jitter and loss rate, and
+//! the network can be partitioned. All delays are driven by the given
+//! [`TimeService`] (i.e., a mock time service makes the simulation controllable
+//! by advancing time), and all random decisions are made by RNGs derived from
+//! the given seed, so that a simulation is reproducible.
+
+use crate::{
+    application::{interface::NetworkClient, storage::PeerMetadataStorage},
+    peer::DisconnectReason,
+    peer_manager::{
+        conn_notifs_channel, ConnectionNotification, ConnectionRequest, ConnectionRequestSender,
+        PeerManagerError, PeerManagerNotification, PeerManagerRequest, PeerManagerRequestSender,
+    },
+    protocols::{
+        network::{
+            Message, NetworkApplicationConfig, NetworkEvents, NetworkSender, NewNetworkEvents,
+            NewNetworkSender,
+        },
+        rpc::{error::RpcError, streaming::InboundStreamingRpcRequest, InboundRpcRequest},
+    },
+    testutils::test_node::{mock_conn_metadata, InboundMessageSender},
+    ProtocolId,
+};
+use aptos_channels::{aptos_channel, message_queues::QueueStyle};
+use aptos_config::{
+    config::{PeerRole, RoleType, NETWORK_CHANNEL_SIZE},
+    network_id::{NetworkContext, NetworkId, PeerNetworkId},
+};
+use aptos_infallible::Mutex;
+use aptos_netcore::transport::ConnectionOrigin;
+use aptos_time_service::{TimeService, TimeServiceTrait};
+use aptos_types::PeerId;
+use futures::{channel::oneshot, future, StreamExt};
+use maplit::hashmap;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// The properties of the links between the simulated nodes
+#[derive(Clone, Debug, Default)]
+pub struct LinkConfig {
+    /// The minimum time it takes to deliver a message
+    pub latency: Duration,
+    /// The maximum (uniformly distributed) delay added to the latency
+    pub jitter: Duration,
+    /// The probability that a message (or an RPC response) is lost
+    pub loss_rate: f64,
+}
+
+/// A node of the simulated network, as seen by the application
+pub struct SimulatedNode<TMessage> {
+    pub peer_network_id: PeerNetworkId,
+    pub network_client: NetworkClient<TMessage>,
+    pub network_events: NetworkEvents<TMessage>,
+}
+
+/// The simulated network's view of a node
+struct SimulatedPeer {
+    peer_network_id: PeerNetworkId,
+    role: PeerRole,
+    protocols: Vec<ProtocolId>,
+    inbound_message_sender: InboundMessageSender,
+    connection_update_sender: conn_notifs_channel::Sender,
+    peer_metadata_storage: Arc<PeerMetadataStorage>,
+}
+
+impl SimulatedPeer {
+    fn network_context(&self) -> NetworkContext {
+        let role = match self.role {
+            PeerRole::Validator => RoleType::Validator,
+            _ => RoleType::FullNode,
+        };
+        NetworkContext::new(
+            role,
+            self.peer_network_id.network_id(),
+            self.peer_network_id.peer_id(),
+        )
+    }
+
+    /// Records the connection to the given peer, and notifies the application
+    fn connect(&self, remote_peer: &SimulatedPeer, origin: ConnectionOrigin) {
+        let connection_metadata = mock_conn_metadata(
+            remote_peer.peer_network_id,
+            remote_peer.role,
+            origin,
+            &remote_peer.protocols,
+        );
+        self.peer_metadata_storage.insert_connection(
+            self.peer_network_id.network_id(),
+            connection_metadata.clone(),
+        );
+        let _ = self.connection_update_sender.push(
+            connection_metadata.remote_peer_id,
+            ConnectionNotification::NewPeer(connection_metadata, self.network_context()),
+        );
+    }
+
+    /// Removes the connection to the given peer (if any), and notifies the application
+    fn disconnect(&self, remote_peer: &SimulatedPeer) {
+        let remote_peer_network_id = remote_peer.peer_network_id;
+        if let Some(peer_info) = self.peer_metadata_storage.read(remote_peer_network_id) {
+            let connection_metadata = peer_info.active_connection;
+            self.peer_metadata_storage
+                .remove_connection(remote_peer_network_id.network_id(), &connection_metadata);
+            let _ = self.connection_update_sender.push(
+                connection_metadata.remote_peer_id,
+                ConnectionNotification::LostPeer(
+                    connection_metadata,
+                    self.network_context(),
+                    DisconnectReason::ConnectionLost,
+                ),
+            );
+        }
+    }
+}
+
+struct SimulationState {
+    peers: HashMap<PeerId, SimulatedPeer>,
+    link_config: LinkConfig,
+    /// The partition of each partitioned peer. Peers can only communicate with
+    /// the peers of the same partition (unpartitioned peers form their own).
+    partitions: HashMap<PeerId, usize>,
+}
+
+impl SimulationState {
+    /// Returns the delay after which a message from the sender reaches the
+    /// receiver, and the receiver's inbound queue, or `None` if the message is
+    /// lost (or the receiver is unknown or partitioned away)
+    fn schedule_delivery(
+        &self,
+        sender: PeerId,
+        receiver: PeerId,
+        rng: &mut StdRng,
+    ) -> Option<(Duration, InboundMessageSender)> {
+        // Draw the random values first, so that they don't depend on the outcome
+        let is_lost = rng.gen_bool(self.link_config.loss_rate.clamp(0.0, 1.0));
+        let jitter = self.link_config.jitter.mul_f64(rng.gen::<f64>());
+
+        let receiver_peer = self.peers.get(&receiver)?;
+        if is_lost || self.partitions.get(&sender) != self.partitions.get(&receiver) {
+            return None;
+        }
+        Some((
+            self.link_config.latency + jitter,
+            receiver_peer.inbound_message_sender.clone(),
+        ))
+    }
+}
+
+/// A simulated network of nodes on a single [`NetworkId`]
+#[derive(Clone)]
+pub struct SimulatedNetwork {
+    network_id: NetworkId,
+    seed: u64,
+    time_service: TimeService,
+    state: Arc<Mutex<SimulationState>>,
+}
+
+impl SimulatedNetwork {
+    pub fn new(
+        network_id: NetworkId,
+        link_config: LinkConfig,
+        time_service: TimeService,
+        seed: u64,
+    ) -> Self {
+        Self {
+            network_id,
+            seed,
+            time_service,
+            state: Arc::new(Mutex::new(SimulationState {
+                peers: HashMap::new(),
+                link_config,
+                partitions: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Adds a node with the given application config to the network, and starts
+    /// routing its requests. Must be called from within a tokio runtime.
+    pub fn add_node<TMessage: Message + Clone>(
+        &self,
+        peer_id: PeerId,
+        role: PeerRole,
+        config: &NetworkApplicationConfig,
+    ) -> SimulatedNode<TMessage> {
+        let peer_network_id = PeerNetworkId::new(self.network_id, peer_id);
+        let peer_metadata_storage = PeerMetadataStorage::new(&[self.network_id]);
+
+        // Create the channels normally connecting the application to the peer manager
+        let (peer_mgr_reqs_tx, peer_mgr_reqs_rx) =
+            aptos_channel::new(QueueStyle::FIFO, NETWORK_CHANNEL_SIZE, None);
+        let (connection_reqs_tx, connection_reqs_rx) =
+            aptos_channel::new(QueueStyle::FIFO, NETWORK_CHANNEL_SIZE, None);
+        let (inbound_message_sender, peer_mgr_notifs_rx) =
+            config.network_service_config.inbound_queue_config.build();
+        let (connection_update_sender, connection_notifs_rx) = conn_notifs_channel::new();
+
+        let network_sender = NetworkSender::new(
+            PeerManagerRequestSender::new(peer_mgr_reqs_tx),
+            ConnectionRequestSender::new(connection_reqs_tx),
+        );
+        let client_config = &config.network_client_config;
+        let network_client = NetworkClient::new(
+            client_config.direct_send_protocols_and_preferences.clone(),
+            client_config.rpc_protocols_and_preferences.clone(),
+            hashmap! {self.network_id => network_sender},
+            peer_metadata_storage.clone(),
+        )
+        .with_time_service(self.time_service.clone());
+
+        let protocols = client_config
+            .direct_send_protocols_and_preferences
+            .iter()
+            .chain(&client_config.rpc_protocols_and_preferences)
+            .copied()
+            .collect();
+        self.state.lock().peers.insert(peer_id, SimulatedPeer {
+            peer_network_id,
+            role,
+            protocols,
+            inbound_message_sender,
+            connection_update_sender,
+            peer_metadata_storage,
+        });
+
+        tokio::spawn(self.clone().route_requests(peer_id, peer_mgr_reqs_rx));
+        tokio::spawn(
+            self.clone()
+                .handle_connection_requests(peer_id, connection_reqs_rx),
+        );
+
+        SimulatedNode {
+            peer_network_id,
+            network_client,
+            network_events: NetworkEvents::new(peer_mgr_notifs_rx, connection_notifs_rx),
+        }
+    }
+
+    /// Connects the given nodes (the first one dialing the second one)
+    pub fn connect(&self, dialer: PeerId, listener: PeerId) {
+        let state = self.state.lock();
+        let (dialer, listener) = (&state.peers[&dialer], &state.peers[&listener]);
+        dialer.connect(listener, ConnectionOrigin::Outbound);
+        listener.connect(dialer, ConnectionOrigin::Inbound);
+    }
+
+    /// Disconnects the given nodes (if they are connected)
+    pub fn disconnect(&self, peer_a: PeerId, peer_b: PeerId) {
+        let state = self.state.lock();
+        if let (Some(peer_a), Some(peer_b)) = (state.peers.get(&peer_a), state.peers.get(&peer_b)) {
+            peer_a.disconnect(peer_b);
+            peer_b.disconnect(peer_a);
+        }
+    }
+
+    /// Replaces the properties of all links. Messages already in flight keep
+    /// their delay.
+    pub fn set_link_config(&self, link_config: LinkConfig) {
+        self.state.lock().link_config = link_config;
+    }
+
+    /// Partitions the network into the given groups of nodes. Messages between
+    /// nodes of different groups (or between a grouped and an ungrouped node)
+    /// are dropped, although the nodes remain connected.
+    pub fn partition(&self, groups: &[&[PeerId]]) {
+        let mut state = self.state.lock();
+        state.partitions.clear();
+        for (partition, group) in groups.iter().enumerate() {
+            for peer_id in group.iter() {
+                state.partitions.insert(*peer_id, partition);
+            }
+        }
+    }
+
+    /// Removes all partitions
+    pub fn heal(&self) {
+        self.state.lock().partitions.clear();
+    }
+
+    /// Returns the RNG used to route the requests of the given node
+    fn new_rng(&self, peer_id: PeerId) -> StdRng {
+        let mut rng_seed = peer_id.into_bytes();
+        for (byte, seed_byte) in rng_seed.iter_mut().zip(self.seed.to_le_bytes()) {
+            *byte ^= seed_byte;
+        }
+        StdRng::from_seed(rng_seed)
+    }
+
+    /// Routes the requests of the given node to the other nodes, until the node
+    /// is dropped. Requests are handled in order, so that the random decisions
+    /// (and thus the simulation) are reproducible.
+    async fn route_requests(
+        self,
+        sender: PeerId,
+        mut peer_mgr_reqs_rx: aptos_channel::Receiver<(PeerId, ProtocolId), PeerManagerRequest>,
+    ) {
+        let mut rng = self.new_rng(sender);
+        while let Some(request) = peer_mgr_reqs_rx.next().await {
+            match request {
+                PeerManagerRequest::SendDirectSend(receiver, message) => {
+                    let delivery = self
+                        .state
+                        .lock()
+                        .schedule_delivery(sender, receiver, &mut rng);
+                    let notification = PeerManagerNotification::RecvMessage(sender, message);
+                    self.deliver(sender, delivery, notification);
+                },
+                PeerManagerRequest::SendRpc(receiver, request) => {
+                    let (request_delivery, response_delivery) = {
+                        let state = self.state.lock();
+                        (
+                            state.schedule_delivery(sender, receiver, &mut rng),
+                            state.schedule_delivery(receiver, sender, &mut rng),
+                        )
+                    };
+                    let is_request_lost = request_delivery.is_none();
+                    let (inbound_res_tx, inbound_res_rx) = oneshot::channel();
+                    let notification =
+                        PeerManagerNotification::RecvRpc(sender, InboundRpcRequest {
+                            protocol_id: request.protocol_id,
+                            data: request.data,
+                            res_tx: inbound_res_tx,
+                            deadline: None,
+                            correlation_id: request.correlation_id,
+                        });
+                    self.deliver(sender, request_delivery, notification);
+
+                    // Delay the response, and time out the request if it (or
+                    // its response) is lost
+                    let time_service = self.time_service.clone();
+                    tokio::spawn(async move {
+                        let response = async {
+                            if is_request_lost {
+                                return future::pending().await;
+                            }
+                            let response = inbound_res_rx
+                                .await
+                                .unwrap_or(Err(RpcError::UnexpectedResponseChannelCancel));
+                            match response_delivery {
+                                Some((delay, _)) => sleep(&time_service, delay).await,
+                                None => future::pending().await,
+                            }
+                            response
+                        };
+                        let response = time_service
+                            .timeout(request.timeout, response)
+                            .await
+                            .unwrap_or(Err(RpcError::TimedOut));
+                        let _ = request.res_tx.send(response);
+                    });
+                },
+                // The response chunks are delivered straight to the requester
+                PeerManagerRequest::SendStreamingRpc(receiver, request) => {
+                    let delivery = self
+                        .state
+                        .lock()
+                        .schedule_delivery(sender, receiver, &mut rng);
+                    let notification = PeerManagerNotification::RecvStreamingRpc(
+                        sender,
+                        InboundStreamingRpcRequest {
+                            protocol_id: request.protocol_id,
+                            data: request.data,
+                            res_tx: request.res_tx,
+                        },
+                    );
+                    self.deliver(sender, delivery, notification);
+                },
+                // Delivered messages are acknowledged right away, lost ones fail as timed out
+                PeerManagerRequest::SendAckedDirectSend(receiver, message) => {
+                    let delivery = self
+                        .state
+                        .lock()
+                        .schedule_delivery(sender, receiver, &mut rng);
+                    let ack = if delivery.is_some() {
+                        Ok(())
+                    } else {
+                        Err(RpcError::TimedOut)
+                    };
+                    let _ = message.ack_tx.send(ack);
+                    let notification =
+                        PeerManagerNotification::RecvMessage(sender, message.message);
+                    self.deliver(sender, delivery, notification);
+                },
+            }
+        }
+    }
+
+    /// Delivers the given notification to the receiver after the scheduled
+    /// delay (or drops it, if it's lost)
+    fn deliver(
+        &self,
+        sender: PeerId,
+        delivery: Option<(Duration, InboundMessageSender)>,
+        notification: PeerManagerNotification,
+    ) {
+        if let Some((delay, inbound_message_sender)) = delivery {
+            let protocol_id = match &notification {
+                PeerManagerNotification::RecvMessage(_, message) => message.protocol_id,
+                PeerManagerNotification::RecvRpc(_, request) => request.protocol_id,
+                PeerManagerNotification::RecvStreamingRpc(_, request) => request.protocol_id,
+            };
+            let time_service = self.time_service.clone();
+            tokio::spawn(async move {
+                sleep(&time_service, delay).await;
+                let _ = inbound_message_sender.push((sender, protocol_id), notification);
+            });
+        }
+    }
+
+    /// Handles the disconnection requests of the given node. Dialing isn't
+    /// supported, nodes are connected with [`SimulatedNetwork::connect`].
+    async fn handle_connection_requests(
+        self,
+        peer_id: PeerId,
+        mut connection_reqs_rx: aptos_channel::Receiver<PeerId, ConnectionRequest>,
+    ) {
+        while let Some(request) = connection_reqs_rx.next().await {
+            match request {
+                ConnectionRequest::DialPeer(remote_peer_id, _, response_tx) => {
+                    let _ = response_tx.send(Err(PeerManagerError::NotConnected(remote_peer_id)));
+                },
+                ConnectionRequest::DisconnectPeer(remote_peer_id, response_tx) => {
+                    self.disconnect(peer_id, remote_peer_id);
+                    let _ = response_tx.send(Ok(()));
+                },
+                ConnectionRequest::DrainPeer(remote_peer_id, _, _, response_tx) => {
+                    self.disconnect(peer_id, remote_peer_id);
+                    let _ = response_tx.send(Ok(()));
+                },
+                ConnectionRequest::DrainAll(_, _, response_tx) => {
+                    let remote_peer_ids: Vec<_> = self
+                        .state
+                        .lock()
+                        .peers
+                        .keys()
+                        .filter(|remote_peer_id| **remote_peer_id != peer_id)
+                        .copied()
+                        .collect();
+                    for remote_peer_id in remote_peer_ids {
+                        self.disconnect(peer_id, remote_peer_id);
+                    }
+                    let _ = response_tx.send(Ok(()));
+                },
+            }
+        }
+    }
+}
+
+/// Sleeps for the given delay. Unlike the mock time service's sleeps, a zero
+/// delay completes without waiting for time to be advanced.
+async fn sleep(time_service: &TimeService, delay: Duration) {
+    if !delay.is_zero() {
+        time_service.sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::{error::Error, interface::NetworkClientInterface},
+        protocols::network::{Event, NetworkClientConfig, NetworkServiceConfig},
+    };
+    use futures::FutureExt;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    struct TestMessage(u64);
+
+    fn application_config() -> NetworkApplicationConfig {
+        let direct_send_protocols = vec![ProtocolId::ConsensusDirectSendBcs];
+        let rpc_protocols = vec![ProtocolId::ConsensusRpcBcs];
+        NetworkApplicationConfig::new(
+            NetworkClientConfig::new(direct_send_protocols.clone(), rpc_protocols.clone()),
+            NetworkServiceConfig::new(
+                direct_send_protocols,
+                rpc_protocols,
+                aptos_channel::Config::new(NETWORK_CHANNEL_SIZE),
+            ),
+        )
+    }
+
+    /// Advances the mock time by the given duration, once a message is in flight
+    async fn advance_in_flight(time_service: &TimeService, duration: Duration) {
+        let mock_time = time_service.clone().into_mock();
+        while mock_time.num_waiters() == 0 {
+            tokio::task::yield_now().await;
+        }
+        mock_time.advance_async(duration).await;
+    }
+
+    #[tokio::test]
+    async fn messages_are_delayed_and_partitioned() {
+        let time_service = TimeService::mock();
+        let network = SimulatedNetwork::new(
+            NetworkId::Validator,
+            LinkConfig {
+                latency: Duration::from_millis(100),
+                ..LinkConfig::default()
+            },
+            time_service.clone(),
+            0,
+        );
+        let (peer_a, peer_b) = (PeerId::new([1; 32]), PeerId::new([2; 32]));
+        let config = application_config();
+        let node_a = network.add_node::<TestMessage>(peer_a, PeerRole::Validator, &config);
+        let mut node_b = network.add_node::<TestMessage>(peer_b, PeerRole::Validator, &config);
+        network.connect(peer_a, peer_b);
+        assert!(matches!(
+            node_b.network_events.next().await,
+            Some(Event::NewPeer(metadata)) if metadata.remote_peer_id == peer_a
+        ));
+
+        // Messages are delivered once the latency elapsed
+        node_a
+            .network_client
+            .send_to_peer(TestMessage(1), node_b.peer_network_id)
+            .unwrap();
+        advance_in_flight(&time_service, Duration::from_millis(99)).await;
+        assert!(node_b.network_events.next().now_or_never().is_none());
+        time_service
+            .clone()
+            .into_mock()
+            .advance_async(Duration::from_millis(1))
+            .await;
+        assert!(matches!(
+            node_b.network_events.next().await,
+            Some(Event::Message(peer_id, TestMessage(1), _)) if peer_id == peer_a
+        ));
+
+        // RPCs across partitions time out
+        network.partition(&[&[peer_a], &[peer_b]]);
+        let network_client = node_a.network_client.clone();
+        let rpc = tokio::spawn(async move {
+            network_client
+                .send_to_peer_rpc(
+                    TestMessage(2),
+                    Duration::from_secs(1),
+                    node_b.peer_network_id,
+                )
+                .await
+        });
+        advance_in_flight(&time_service, Duration::from_secs(1)).await;
+        assert!(matches!(rpc.await.unwrap(), Err(Error::Timeout(_))));
+        assert!(node_b.network_events.next().now_or_never().is_none());
+
+        // And succeed once the partition is healed
+        network.heal();
+        network.set_link_config(LinkConfig::default());
+        let network_client = node_a.network_client.clone();
+        let rpc = tokio::spawn(async move {
+            network_client
+                .send_to_peer_rpc(
+                    TestMessage(3),
+                    Duration::from_secs(1),
+                    node_b.peer_network_id,
+                )
+                .await
+        });
+        match node_b.network_events.next().await {
+            Some(Event::RpcRequest(_, TestMessage(3), _, res_tx, _)) => {
+                res_tx
+                    .send(Ok(bcs::to_bytes(&TestMessage(4)).unwrap().into()))
+                    .unwrap();
+            },
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert_eq!(rpc.await.unwrap().unwrap(), TestMessage(4));
+    }
+
+    #[tokio::test]
+    async fn simulations_are_reproducible() {
+        let link_config = LinkConfig {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(50),
+            loss_rate: 0.3,
+        };
+        let (peer_a, peer_b) = (PeerId::new([1; 32]), PeerId::new([2; 32]));
+        let schedule = |seed| {
+            let network = SimulatedNetwork::new(
+                NetworkId::Validator,
+                link_config.clone(),
+                TimeService::mock(),
+                seed,
+            );
+            let _node_b =
+                network.add_node::<TestMessage>(peer_b, PeerRole::Validator, &application_config());
+            let mut rng = network.new_rng(peer_a);
+            let state = network.state.lock();
+            (0..100)
+                .map(|_| {
+                    state
+                        .schedule_delivery(peer_a, peer_b, &mut rng)
+                        .map(|(delay, _)| delay)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let delays = schedule(7);
+        assert_eq!(delays, schedule(7));
+        assert_ne!(delays, schedule(8));
+        assert!(delays.iter().any(Option::is_none));
+        assert!(delays
+            .iter()
+            .flatten()
+            .all(|delay| *delay >= link_config.latency && *delay <= link_config.latency * 2));
+    }
+}
@@ -4,9 +4,9 @@
 use crate::{
     application::{storage::PeerMetadataStorage, types::PeerState},
     peer_manager::{ConnectionNotification, PeerManagerNotification, PeerManagerRequest},
-    protocols::{
-        direct_send::Message,
-        rpc::{InboundRpcRequest, OutboundRpcRequest},
+    protocols::rpc::{
+        streaming::{InboundStreamingRpcRequest, OutboundStreamingRpcRequest},
+        InboundRpcRequest, OutboundRpcRequest,
     },
     transport::ConnectionMetadata,
     DisconnectReason, ProtocolId,
@@ -331,6 +331,24 @@ pub trait TestNode: ApplicationNode + Sync {
             PeerManagerRequest::SendDirectSend(peer_id, message) => {
                 (peer_id, message.protocol_id, message.mdata)
             },
+            PeerManagerRequest::SendAckedDirectSend(peer_id, message) => {
+                // Acknowledge the message, otherwise the sender waits until it times out.
+                let _ = message.ack_tx.send(Ok(()));
+                (peer_id, message.message.protocol_id, message.message.mdata)
+            },
+            PeerManagerRequest::SendStreamingRpc(
+                peer_id,
+                OutboundStreamingRpcRequest {
+                    protocol_id,
+                    res_tx,
+                    data,
+                    ..
+                },
+            ) => {
+                // Close the response stream, otherwise listening task will hang forever.
+                drop(res_tx);
+                (peer_id, protocol_id, data)
+            },
         }
     }
 
@@ -338,36 +356,51 @@ pub trait TestNode: ApplicationNode + Sync {
     async fn send_next_network_msg(&mut self, network_id: NetworkId) {
         let request = self.get_next_network_msg(network_id).await;
 
-        let (remote_peer_id, protocol_id, data, maybe_rpc_info) = match request {
+        let sender_peer_id = self.peer_network_id(network_id).peer_id();
+
+        // TODO: Add timeout functionality
+        let (remote_peer_id, protocol_id, peer_manager_notif) = match request {
             PeerManagerRequest::SendRpc(peer_id, msg) => (
                 peer_id,
                 msg.protocol_id,
-                msg.data,
-                Some((msg.timeout, msg.res_tx)),
+                PeerManagerNotification::RecvRpc(sender_peer_id, InboundRpcRequest {
+                    protocol_id: msg.protocol_id,
+                    data: msg.data,
+                    res_tx: msg.res_tx,
+                    deadline: None,
+                    correlation_id: msg.correlation_id,
+                }),
+            ),
+            PeerManagerRequest::SendDirectSend(peer_id, msg) => (
+                peer_id,
+                msg.protocol_id,
+                PeerManagerNotification::RecvMessage(sender_peer_id, msg),
             ),
-            PeerManagerRequest::SendDirectSend(peer_id, msg) => {
-                (peer_id, msg.protocol_id, msg.mdata, None)
+            PeerManagerRequest::SendAckedDirectSend(peer_id, msg) => {
+                let _ = msg.ack_tx.send(Ok(()));
+                (
+                    peer_id,
+                    msg.message.protocol_id,
+                    PeerManagerNotification::RecvMessage(sender_peer_id, msg.message),
+                )
             },
+            // The response chunks are delivered straight to the requester
+            PeerManagerRequest::SendStreamingRpc(peer_id, msg) => (
+                peer_id,
+                msg.protocol_id,
+                PeerManagerNotification::RecvStreamingRpc(
+                    sender_peer_id,
+                    InboundStreamingRpcRequest {
+                        protocol_id: msg.protocol_id,
+                        data: msg.data,
+                        res_tx: msg.res_tx,
+                    },
+                ),
+            ),
         };
 
-        let sender_peer_network_id = self.peer_network_id(network_id);
         let receiver_peer_network_id = PeerNetworkId::new(network_id, remote_peer_id);
         let receiver_handle = self.get_inbound_handle_for_peer(receiver_peer_network_id);
-        let sender_peer_id = sender_peer_network_id.peer_id();
-
-        // TODO: Add timeout functionality
-        let peer_manager_notif = if let Some((_timeout, res_tx)) = maybe_rpc_info {
-            PeerManagerNotification::RecvRpc(sender_peer_id, InboundRpcRequest {
-                protocol_id,
-                data,
-                res_tx,
-            })
-        } else {
-            PeerManagerNotification::RecvMessage(sender_peer_id, Message {
-                protocol_id,
-                mdata: data,
-            })
-        };
         receiver_handle
             .inbound_message_sender
             .push((sender_peer_id, protocol_id), peer_manager_notif)
@@ -0,0 +1,160 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Self-signed certificates carrying the x25519 network key of a peer.
+//!
+//! The certificates aren't issued by any authority: peers are authenticated by
+//! their network keys, exactly like with Noise. The certificate verifiers below
+//! only check that the certificate carries the expected (or any valid) network
+//! key. Ownership of the network key is proven after the TLS handshake (see
+//! [`TlsUpgrader`](crate::tls::TlsUpgrader)).
+
+use aptos_crypto::x25519;
+use rcgen::{CertificateParams, CustomExtension, RcgenError, PKCS_ED25519};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    server::{ClientCertVerified, ClientCertVerifier},
+    Certificate, DistinguishedNames, PrivateKey, ServerName,
+};
+use std::{convert::TryFrom, time::SystemTime};
+use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+/// The (private) OID of the certificate extension carrying the x25519 network key
+pub const NETWORK_KEY_EXTENSION_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 57104, 1, 1];
+
+/// The server name dialers expect. It isn't verified, as servers are identified
+/// by their network keys.
+pub const SERVER_NAME: &str = "aptos";
+
+/// Generates a self-signed certificate (and its private key) carrying the given
+/// network key
+pub fn generate_certificate(
+    network_key: x25519::PublicKey,
+) -> Result<(Certificate, PrivateKey), RcgenError> {
+    let mut params = CertificateParams::new(vec![SERVER_NAME.to_string()]);
+    params.alg = &PKCS_ED25519;
+    params
+        .custom_extensions
+        .push(CustomExtension::from_oid_content(
+            NETWORK_KEY_EXTENSION_OID,
+            network_key.as_slice().to_vec(),
+        ));
+    let certificate = rcgen::Certificate::from_params(params)?;
+    Ok((
+        Certificate(certificate.serialize_der()?),
+        PrivateKey(certificate.serialize_private_key_der()),
+    ))
+}
+
+/// Extracts the network key carried by the given certificate
+pub fn network_key(certificate: &Certificate) -> Result<x25519::PublicKey, String> {
+    let (_, certificate) =
+        X509Certificate::from_der(&certificate.0).map_err(|err| err.to_string())?;
+    let extension = certificate
+        .extensions()
+        .iter()
+        .find(|extension| {
+            extension.oid.iter().map_or(false, |arcs| {
+                arcs.eq(NETWORK_KEY_EXTENSION_OID.iter().copied())
+            })
+        })
+        .ok_or_else(|| "missing network key extension".to_string())?;
+    x25519::PublicKey::try_from(extension.value).map_err(|err| err.to_string())
+}
+
+fn invalid_certificate(error: String) -> rustls::Error {
+    rustls::Error::InvalidCertificateData(error)
+}
+
+/// Accepts server certificates carrying the network key we dialed
+pub struct ServerNetworkKeyVerifier {
+    expected_key: x25519::PublicKey,
+}
+
+impl ServerNetworkKeyVerifier {
+    pub fn new(expected_key: x25519::PublicKey) -> Self {
+        Self { expected_key }
+    }
+}
+
+impl ServerCertVerifier for ServerNetworkKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let key = network_key(end_entity).map_err(invalid_certificate)?;
+        if key != self.expected_key {
+            return Err(invalid_certificate(format!(
+                "unexpected network key: {}, expected: {}",
+                key, self.expected_key
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Requires client certificates, and accepts those carrying a network key. Whether
+/// the key is trusted is decided after the handshake, like with Noise.
+pub struct ClientNetworkKeyVerifier;
+
+impl ClientCertVerifier for ClientNetworkKeyVerifier {
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        Some(DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        network_key(end_entity).map_err(invalid_certificate)?;
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aptos_crypto::{test_utils::TEST_SEED, Uniform};
+    use rand::SeedableRng;
+
+    #[test]
+    fn certificate_carries_network_key() {
+        let mut rng = rand::rngs::StdRng::from_seed(TEST_SEED);
+        let key = x25519::PrivateKey::generate(&mut rng).public_key();
+        let other_key = x25519::PrivateKey::generate(&mut rng).public_key();
+        let (certificate, _) = generate_certificate(key).unwrap();
+        assert_eq!(network_key(&certificate).unwrap(), key);
+
+        let verify = |expected_key| {
+            ServerNetworkKeyVerifier::new(expected_key).verify_server_cert(
+                &certificate,
+                &[],
+                &ServerName::try_from(SERVER_NAME).unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+        };
+        assert!(verify(key).is_ok());
+        assert!(verify(other_key).is_err());
+
+        // Certificates without a network key are rejected
+        let certificate = Certificate(
+            rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+                .unwrap()
+                .serialize_der()
+                .unwrap(),
+        );
+        assert!(network_key(&certificate).is_err());
+        assert!(ClientNetworkKeyVerifier
+            .verify_client_cert(&certificate, &[], SystemTime::now())
+            .is_err());
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_short_hex_str::ShortHexStr;
+use aptos_types::PeerId;
+use std::io;
+use thiserror::Error;
+
+/// Different errors than can be raised when negotiating a TLS handshake.
+#[derive(Debug, Error)]
+pub enum TlsHandshakeError {
+    #[error("tls client: MUST_FIX: error building the client config: {0}")]
+    BuildClientConfigFailed(rustls::Error),
+
+    #[error(
+        "tls client: error performing the tls handshake, server probably rejected \
+         our certificate or presented an unexpected one: {0}"
+    )]
+    ClientHandshakeFailed(io::Error),
+
+    #[error("tls client: error sending the key binding message: {0}")]
+    ClientWriteFailed(io::Error),
+
+    #[error("tls client: error flushing socket after writing: {0}")]
+    ClientFlushFailed(io::Error),
+
+    #[error(
+        "tls client: error reading the server key binding message, server \
+         probably rejected our binding message: {0}"
+    )]
+    ClientReadFailed(io::Error),
+
+    #[error("tls client: server failed to prove ownership of its network key")]
+    InvalidServerBinding,
+
+    #[error("tls: MUST_FIX: error exporting keying material: {0}")]
+    ExportKeyingMaterialFailed(rustls::Error),
+
+    #[error("tls: remote certificate doesn't carry a valid network key: {0}")]
+    InvalidRemoteCertificate(String),
+
+    #[error("tls: remote network key is a low-order point: {0}")]
+    LowOrderRemotePubkey(String),
+
+    #[error("tls server: error performing the tls handshake: {0}")]
+    ServerHandshakeFailed(io::Error),
+
+    #[error("tls server: error reading the client key binding message: {0}")]
+    ServerReadFailed(io::Error),
+
+    #[error("tls server: client peer id is malformed: {0}")]
+    InvalidClientPeerId(String),
+
+    #[error("tls server: detected self-dial: we're trying to connect to ourselves")]
+    SelfDialDetected,
+
+    #[error(
+        "tls server: client {0}: known client peer id connecting to us with \
+         unauthenticated public key: {1}"
+    )]
+    UnauthenticatedClientPubkey(ShortHexStr, String),
+
+    #[error("tls server: client {0}: client connecting with unauthenticated peer id: {1}")]
+    UnauthenticatedClient(ShortHexStr, PeerId),
+
+    #[error(
+        "tls server: client {0}: client's self-reported peer id and pubkey-derived peer \
+         id don't match: self-reported: {1}, derived: {2}"
+    )]
+    ClientPeerIdMismatch(ShortHexStr, PeerId, PeerId),
+
+    #[error("tls server: client {0}: client failed to prove ownership of its network key")]
+    InvalidClientBinding(ShortHexStr),
+
+    #[error("tls server: client {0}: error sending the key binding message: {1}")]
+    ServerWriteFailed(ShortHexStr, io::Error),
+}
+
+impl TlsHandshakeError {
+    /// Errors that are either clear bugs or indicate some security issue. Should
+    /// immediately alert an engineer if we hit one of these errors.
+    pub fn should_security_log(&self) -> bool {
+        use TlsHandshakeError::*;
+        matches!(self, InvalidServerBinding | InvalidClientBinding(_))
+    }
+}
@@ -0,0 +1,526 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! The TLS equivalent of the [`NoiseUpgrader`](crate::noise::NoiseUpgrader).
+//!
+//! After the TLS 1.3 handshake, which authenticates the certificates (and thus the
+//! network keys they carry) but not the ownership of the network keys, both peers
+//! prove the ownership of their network key by exchanging a key binding message:
+//!
+//! ```text
+//! client -> server: client_peer_id | HMAC(DH(client, server), "client" | exporter)
+//! server -> client: HMAC(DH(server, client), "server" | exporter)
+//! ```
+//!
+//! where `exporter` is keying material exported from the TLS session. As the binding
+//! messages are specific to the TLS session, they can't be replayed.
+
+use crate::{
+    noise::HandshakeAuthMode,
+    tls::{
+        certificate::{
+            generate_certificate, network_key, ClientNetworkKeyVerifier, ServerNetworkKeyVerifier,
+            SERVER_NAME,
+        },
+        error::TlsHandshakeError,
+        stream::TlsStream,
+    },
+};
+use aptos_config::{
+    config::{Peer, PeerRole},
+    network_id::NetworkContext,
+};
+use aptos_crypto::x25519;
+use aptos_logger::trace;
+use aptos_short_hex_str::{AsShortHexStr, ShortHexStr};
+use aptos_types::PeerId;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use ring::hmac;
+use rustls::{version::TLS13, Certificate, ClientConfig, PrivateKey, ServerConfig, ServerName};
+use std::{convert::TryFrom as _, fmt::Debug, sync::Arc};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// The configuration to be used to perform a TLS upgrade on an underlying socket.
+pub struct TlsUpgrader {
+    /// The validator's network context
+    pub network_context: NetworkContext,
+    /// Our static network key
+    key: x25519::PrivateKey,
+    /// Our certificate, carrying our static network public key
+    certificate: Certificate,
+    /// The private key of our certificate
+    certificate_key: PrivateKey,
+    /// Accepts inbound TLS sessions
+    acceptor: TlsAcceptor,
+    /// Handshake authentication can be either mutual or server-only authentication.
+    auth_mode: HandshakeAuthMode,
+}
+
+impl TlsUpgrader {
+    /// The client message is the client's peer_id and binding tag.
+    const CLIENT_MESSAGE_SIZE: usize = PeerId::LENGTH + Self::TAG_SIZE;
+    const CLIENT_ROLE: &'static [u8] = b"client";
+    /// The label of the keying material exported from the TLS session
+    const EXPORTER_LABEL: &'static [u8] = b"EXPORTER-aptos-network-key-binding";
+    const EXPORTER_SIZE: usize = 32;
+    const SERVER_ROLE: &'static [u8] = b"server";
+    /// The size of an HMAC-SHA256 binding tag
+    const TAG_SIZE: usize = 32;
+
+    /// Create a new TlsUpgrader with the provided key and authentication mode.
+    pub fn new(
+        network_context: NetworkContext,
+        key: x25519::PrivateKey,
+        auth_mode: HandshakeAuthMode,
+    ) -> anyhow::Result<Self> {
+        let (certificate, certificate_key) = generate_certificate(key.public_key())?;
+        let server_config = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&TLS13])?
+            .with_client_cert_verifier(Arc::new(ClientNetworkKeyVerifier))
+            .with_single_cert(vec![certificate.clone()], certificate_key.clone())?;
+        Ok(Self {
+            network_context,
+            key,
+            certificate,
+            certificate_key,
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            auth_mode,
+        })
+    }
+
+    /// Our static network public key
+    pub fn public_key(&self) -> x25519::PublicKey {
+        self.key.public_key()
+    }
+
+    /// Perform an outbound protocol upgrade on this connection.
+    ///
+    /// This runs the client side of the TLS handshake, only accepting a server
+    /// certificate carrying `remote_public_key`, and then exchanges the key binding
+    /// messages.
+    pub async fn upgrade_outbound<TSocket>(
+        &self,
+        socket: TSocket,
+        remote_public_key: x25519::PublicKey,
+    ) -> Result<TlsStream<TSocket>, TlsHandshakeError>
+    where
+        TSocket: AsyncRead + AsyncWrite + Debug + Unpin,
+    {
+        let binding_key = self.binding_key(&remote_public_key)?;
+
+        // the verifier depends on the remote, so the config can't be shared
+        let client_config = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&TLS13])
+            .map_err(TlsHandshakeError::BuildClientConfigFailed)?
+            .with_custom_certificate_verifier(Arc::new(ServerNetworkKeyVerifier::new(
+                remote_public_key,
+            )))
+            .with_single_cert(vec![self.certificate.clone()], self.certificate_key.clone())
+            .map_err(TlsHandshakeError::BuildClientConfigFailed)?;
+        let server_name =
+            ServerName::try_from(SERVER_NAME).expect("SERVER_NAME is a valid server name");
+
+        // perform the tls handshake
+        trace!(
+            "{} tls client: handshake: remote_public_key: {}",
+            self.network_context,
+            remote_public_key,
+        );
+        let stream = TlsConnector::from(Arc::new(client_config))
+            .connect(server_name, TlsStream::compat_socket(socket))
+            .await
+            .map_err(TlsHandshakeError::ClientHandshakeFailed)?;
+        let mut exporter = [0; Self::EXPORTER_SIZE];
+        stream
+            .get_ref()
+            .1
+            .export_keying_material(&mut exporter, Self::EXPORTER_LABEL, None)
+            .map_err(TlsHandshakeError::ExportKeyingMaterialFailed)?;
+        let mut stream = TlsStream::new(stream.into(), remote_public_key);
+
+        // send our peer id and binding tag
+        trace!(
+            "{} tls client: binding write: remote_public_key: {}",
+            self.network_context,
+            remote_public_key,
+        );
+        let mut client_message = [0; Self::CLIENT_MESSAGE_SIZE];
+        client_message[..PeerId::LENGTH].copy_from_slice(self.network_context.peer_id().as_ref());
+        client_message[PeerId::LENGTH..].copy_from_slice(
+            Self::binding_tag(&binding_key, Self::CLIENT_ROLE, &exporter).as_ref(),
+        );
+        stream
+            .write_all(&client_message)
+            .await
+            .map_err(TlsHandshakeError::ClientWriteFailed)?;
+        stream
+            .flush()
+            .await
+            .map_err(TlsHandshakeError::ClientFlushFailed)?;
+
+        // receive and verify the server's binding tag
+        trace!(
+            "{} tls client: binding read: remote_public_key: {}",
+            self.network_context,
+            remote_public_key,
+        );
+        let mut server_message = [0; Self::TAG_SIZE];
+        stream
+            .read_exact(&mut server_message)
+            .await
+            .map_err(TlsHandshakeError::ClientReadFailed)?;
+        Self::verify_binding_tag(&binding_key, Self::SERVER_ROLE, &exporter, &server_message)
+            .map_err(|_| TlsHandshakeError::InvalidServerBinding)?;
+
+        Ok(stream)
+    }
+
+    /// Perform an inbound protocol upgrade on this connection.
+    ///
+    /// This runs the server side of the TLS handshake and then exchanges the key
+    /// binding messages. If the configuration requires mutual authentication, we
+    /// will only allow connections from peers with a network key in our
+    /// `trusted_peers` set.
+    pub async fn upgrade_inbound<TSocket>(
+        &self,
+        socket: TSocket,
+    ) -> Result<(TlsStream<TSocket>, PeerId, PeerRole), TlsHandshakeError>
+    where
+        TSocket: AsyncRead + AsyncWrite + Debug + Unpin,
+    {
+        // perform the tls handshake
+        trace!("{} tls server: handshake", self.network_context);
+        let stream = self
+            .acceptor
+            .accept(TlsStream::compat_socket(socket))
+            .await
+            .map_err(TlsHandshakeError::ServerHandshakeFailed)?;
+        let (_, connection) = stream.get_ref();
+        let remote_public_key = connection
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .ok_or_else(|| {
+                TlsHandshakeError::InvalidRemoteCertificate("missing certificate".into())
+            })
+            .and_then(|certificate| {
+                network_key(certificate).map_err(TlsHandshakeError::InvalidRemoteCertificate)
+            })?;
+        let mut exporter = [0; Self::EXPORTER_SIZE];
+        connection
+            .export_keying_material(&mut exporter, Self::EXPORTER_LABEL, None)
+            .map_err(TlsHandshakeError::ExportKeyingMaterialFailed)?;
+        let mut stream = TlsStream::new(stream.into(), remote_public_key);
+
+        // receive the client's peer id and binding tag
+        trace!("{} tls server: binding read", self.network_context);
+        let mut client_message = [0; Self::CLIENT_MESSAGE_SIZE];
+        stream
+            .read_exact(&mut client_message)
+            .await
+            .map_err(TlsHandshakeError::ServerReadFailed)?;
+        let (remote_peer_id, client_tag) = client_message.split_at(PeerId::LENGTH);
+
+        // parse the client's peer id
+        let remote_peer_id = PeerId::try_from(remote_peer_id)
+            .map_err(|_| TlsHandshakeError::InvalidClientPeerId(hex::encode(remote_peer_id)))?;
+        let remote_peer_short = remote_peer_id.short_str();
+
+        // reject accidental self-dials
+        if remote_peer_id == self.network_context.peer_id() {
+            return Err(TlsHandshakeError::SelfDialDetected);
+        }
+
+        // if mutual auth mode, verify the remote pubkey is in our set of trusted peers
+        let peer_role = match &self.auth_mode {
+            HandshakeAuthMode::Mutual { trusted_peers, .. } => {
+                match trusted_peers.read().get(&remote_peer_id) {
+                    Some(peer) => {
+                        Self::authenticate_inbound(remote_peer_short, peer, &remote_public_key)
+                    },
+                    None => Err(TlsHandshakeError::UnauthenticatedClient(
+                        remote_peer_short,
+                        remote_peer_id,
+                    )),
+                }
+            },
+            HandshakeAuthMode::MaybeMutual(trusted_peers) => {
+                match trusted_peers.read().get(&remote_peer_id) {
+                    Some(peer) => {
+                        Self::authenticate_inbound(remote_peer_short, peer, &remote_public_key)
+                    },
+                    None => {
+                        // if not, verify that their peerid is constructed correctly from their public key
+                        let derived_remote_peer_id =
+                            aptos_types::account_address::from_identity_public_key(
+                                remote_public_key,
+                            );
+                        if derived_remote_peer_id != remote_peer_id {
+                            Err(TlsHandshakeError::ClientPeerIdMismatch(
+                                remote_peer_short,
+                                remote_peer_id,
+                                derived_remote_peer_id,
+                            ))
+                        } else {
+                            Ok(PeerRole::Unknown)
+                        }
+                    },
+                }
+            },
+        }?;
+
+        // verify that the client owns the network key of its certificate
+        let binding_key = self.binding_key(&remote_public_key)?;
+        Self::verify_binding_tag(&binding_key, Self::CLIENT_ROLE, &exporter, client_tag)
+            .map_err(|_| TlsHandshakeError::InvalidClientBinding(remote_peer_short))?;
+
+        // prove that we own ours
+        trace!(
+            "{} tls server: binding write: remote_peer_id: {}",
+            self.network_context,
+            remote_peer_short,
+        );
+        let server_tag = Self::binding_tag(&binding_key, Self::SERVER_ROLE, &exporter);
+        stream
+            .write_all(server_tag.as_ref())
+            .await
+            .map_err(|err| TlsHandshakeError::ServerWriteFailed(remote_peer_short, err))?;
+        stream
+            .flush()
+            .await
+            .map_err(|err| TlsHandshakeError::ServerWriteFailed(remote_peer_short, err))?;
+
+        Ok((stream, remote_peer_id, peer_role))
+    }
+
+    fn authenticate_inbound(
+        remote_peer_short: ShortHexStr,
+        peer: &Peer,
+        remote_public_key: &x25519::PublicKey,
+    ) -> Result<PeerRole, TlsHandshakeError> {
+        if !peer.keys.contains(remote_public_key) {
+            return Err(TlsHandshakeError::UnauthenticatedClientPubkey(
+                remote_peer_short,
+                hex::encode(remote_public_key.as_slice()),
+            ));
+        }
+        Ok(peer.role)
+    }
+
+    /// The key of the binding tags, only known to the owners of the two network keys
+    fn binding_key(
+        &self,
+        remote_public_key: &x25519::PublicKey,
+    ) -> Result<hmac::Key, TlsHandshakeError> {
+        let shared_secret = self.key.diffie_hellman(remote_public_key);
+        // a low-order remote key would make the shared secret public
+        if shared_secret.iter().all(|byte| *byte == 0) {
+            return Err(TlsHandshakeError::LowOrderRemotePubkey(hex::encode(
+                remote_public_key.as_slice(),
+            )));
+        }
+        Ok(hmac::Key::new(hmac::HMAC_SHA256, &shared_secret))
+    }
+
+    fn binding_tag(key: &hmac::Key, role: &[u8], exporter: &[u8]) -> hmac::Tag {
+        let mut context = hmac::Context::with_key(key);
+        context.update(role);
+        context.update(exporter);
+        context.sign()
+    }
+
+    fn verify_binding_tag(
+        key: &hmac::Key,
+        role: &[u8],
+        exporter: &[u8],
+        tag: &[u8],
+    ) -> Result<(), ring::error::Unspecified> {
+        hmac::verify(key, &[role, exporter].concat(), tag)
+    }
+}
+
+//
+// Tests
+// -----
+//
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aptos_config::config::{Peer, PeerRole};
+    use aptos_crypto::{test_utils::TEST_SEED, traits::Uniform as _};
+    use aptos_infallible::RwLock;
+    use aptos_memsocket::MemorySocket;
+    use futures::{executor::block_on, future::join};
+    use rand::SeedableRng as _;
+
+    /// helper to setup two testing peers
+    fn build_peers(
+        is_mutual_auth: bool,
+    ) -> (
+        (TlsUpgrader, x25519::PublicKey),
+        (TlsUpgrader, x25519::PublicKey),
+    ) {
+        let mut rng = ::rand::rngs::StdRng::from_seed(TEST_SEED);
+
+        let client_private_key = x25519::PrivateKey::generate(&mut rng);
+        let client_public_key = client_private_key.public_key();
+
+        let server_private_key = x25519::PrivateKey::generate(&mut rng);
+        let server_public_key = server_private_key.public_key();
+
+        let (client_auth, server_auth, client_peer_id, server_peer_id) = if is_mutual_auth {
+            let client_peer_id = PeerId::random();
+            let client_pubkey_set = [client_public_key].iter().copied().collect();
+            let server_peer_id = PeerId::random();
+            let server_pubkey_set = [server_public_key].iter().copied().collect();
+            let trusted_peers = Arc::new(RwLock::new(
+                vec![
+                    (
+                        client_peer_id,
+                        Peer::new(Vec::new(), client_pubkey_set, PeerRole::Validator),
+                    ),
+                    (
+                        server_peer_id,
+                        Peer::new(Vec::new(), server_pubkey_set, PeerRole::Validator),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ));
+            let client_auth = HandshakeAuthMode::mutual(trusted_peers.clone());
+            let server_auth = HandshakeAuthMode::mutual(trusted_peers);
+            (client_auth, server_auth, client_peer_id, server_peer_id)
+        } else {
+            let client_peer_id =
+                aptos_types::account_address::from_identity_public_key(client_public_key);
+            let server_peer_id =
+                aptos_types::account_address::from_identity_public_key(server_public_key);
+            (
+                HandshakeAuthMode::server_only(),
+                HandshakeAuthMode::server_only(),
+                client_peer_id,
+                server_peer_id,
+            )
+        };
+
+        let client = TlsUpgrader::new(
+            NetworkContext::mock_with_peer_id(client_peer_id),
+            client_private_key,
+            client_auth,
+        )
+        .unwrap();
+        let server = TlsUpgrader::new(
+            NetworkContext::mock_with_peer_id(server_peer_id),
+            server_private_key,
+            server_auth,
+        )
+        .unwrap();
+
+        ((client, client_public_key), (server, server_public_key))
+    }
+
+    /// helper to perform a tls handshake with two peers
+    fn perform_handshake(
+        client: &TlsUpgrader,
+        server: &TlsUpgrader,
+        server_public_key: x25519::PublicKey,
+    ) -> (
+        Result<TlsStream<MemorySocket>, TlsHandshakeError>,
+        Result<(TlsStream<MemorySocket>, PeerId, PeerRole), TlsHandshakeError>,
+    ) {
+        // create an in-memory socket for testing
+        let (dialer_socket, listener_socket) = MemorySocket::new_pair();
+
+        // perform the handshake
+        block_on(join(
+            client.upgrade_outbound(dialer_socket, server_public_key),
+            server.upgrade_inbound(listener_socket),
+        ))
+    }
+
+    fn test_handshake_success(is_mutual_auth: bool) {
+        // perform handshake with two testing peers
+        let ((client, client_public_key), (server, server_public_key)) =
+            build_peers(is_mutual_auth);
+        let (client_res, server_res) = perform_handshake(&client, &server, server_public_key);
+        let mut client_stream = client_res.unwrap();
+        let (mut server_stream, remote_peer_id, peer_role) = server_res.unwrap();
+
+        assert_eq!(client_stream.get_remote_static(), server_public_key);
+        assert_eq!(server_stream.get_remote_static(), client_public_key);
+        assert_eq!(remote_peer_id, client.network_context.peer_id());
+        let expected_role = if is_mutual_auth {
+            PeerRole::Validator
+        } else {
+            PeerRole::Unknown
+        };
+        assert_eq!(peer_role, expected_role);
+
+        // the session works in both directions
+        block_on(async {
+            client_stream.write_all(b"client hello").await.unwrap();
+            client_stream.flush().await.unwrap();
+            let mut buf = [0; 12];
+            server_stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"client hello");
+
+            server_stream.write_all(b"server hello").await.unwrap();
+            server_stream.flush().await.unwrap();
+            client_stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"server hello");
+        });
+    }
+
+    #[test]
+    fn test_handshake_server_only_auth() {
+        test_handshake_success(false /* is_mutual_auth */);
+    }
+
+    #[test]
+    fn test_handshake_mutual_auth() {
+        test_handshake_success(true /* is_mutual_auth */);
+    }
+
+    #[test]
+    fn test_handshake_unexpected_server_key() {
+        let ((client, client_public_key), (server, _)) = build_peers(false);
+        let (client_res, server_res) = perform_handshake(&client, &server, client_public_key);
+
+        assert!(matches!(
+            client_res,
+            Err(TlsHandshakeError::ClientHandshakeFailed(_))
+        ));
+        assert!(server_res.is_err());
+    }
+
+    #[test]
+    fn test_handshake_untrusted_client() {
+        let ((client, _), (server, server_public_key)) = build_peers(true);
+        if let HandshakeAuthMode::Mutual { trusted_peers, .. } = &server.auth_mode {
+            trusted_peers.write().clear();
+        }
+        let (client_res, server_res) = perform_handshake(&client, &server, server_public_key);
+
+        assert!(client_res.is_err());
+        assert!(matches!(
+            server_res,
+            Err(TlsHandshakeError::UnauthenticatedClient(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_handshake_self_dial() {
+        let (_, (server, server_public_key)) = build_peers(false);
+        let (client_res, server_res) = perform_handshake(&server, &server, server_public_key);
+
+        assert!(client_res.is_err());
+        assert!(matches!(
+            server_res,
+            Err(TlsHandshakeError::SelfDialDetected)
+        ));
+    }
+}
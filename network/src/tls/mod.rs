@@ -0,0 +1,22 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements a TLS 1.3 alternative to [Noise](crate::noise), for
+//! deployments whose compliance requirements mandate TLS. It's selected per
+//! network with the `transport_security` of the network config.
+//!
+//! Peers are still identified by their x25519 network keys: each peer presents a
+//! self-signed certificate carrying its network key (see [`certificate`]), and
+//! proves its ownership of the key once the TLS session is established (see
+//! [`handshake`]). The authentication modes are the same as with Noise, except
+//! that no anti-replay timestamps are needed, as the ownership proofs are bound
+//! to the TLS session.
+
+pub mod certificate;
+pub mod error;
+pub mod handshake;
+pub mod stream;
+
+pub use error::TlsHandshakeError;
+pub use handshake::TlsUpgrader;
+pub use stream::TlsStream;
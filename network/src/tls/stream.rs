@@ -0,0 +1,86 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! A TLS-secured socket, as returned by the [`TlsUpgrader`](crate::tls::TlsUpgrader).
+
+use aptos_crypto::x25519;
+use futures::io::{AsyncRead, AsyncWrite};
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+
+/// A TLS session on top of a socket, authenticated to the network key of the remote.
+pub struct TlsStream<TSocket> {
+    stream: Compat<tokio_rustls::TlsStream<Compat<TSocket>>>,
+    remote_static: x25519::PublicKey,
+}
+
+impl<TSocket> TlsStream<TSocket>
+where
+    TSocket: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(
+        stream: tokio_rustls::TlsStream<Compat<TSocket>>,
+        remote_static: x25519::PublicKey,
+    ) -> Self {
+        Self {
+            stream: TokioAsyncReadCompatExt::compat(stream),
+            remote_static,
+        }
+    }
+
+    /// Wraps a socket so that it can be used by `tokio_rustls`
+    pub(crate) fn compat_socket(socket: TSocket) -> Compat<TSocket> {
+        FuturesAsyncReadCompatExt::compat(socket)
+    }
+
+    /// Get the static public key of the remote
+    pub fn get_remote_static(&self) -> x25519::PublicKey {
+        self.remote_static
+    }
+}
+
+impl<TSocket> fmt::Debug for TlsStream<TSocket> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsStream")
+            .field("remote_static", &self.remote_static)
+            .finish()
+    }
+}
+
+impl<TSocket> AsyncRead for TlsStream<TSocket>
+where
+    TSocket: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_read(context, buf)
+    }
+}
+
+impl<TSocket> AsyncWrite for TlsStream<TSocket>
+where
+    TSocket: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(context, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(context)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(context)
+    }
+}
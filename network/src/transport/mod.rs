@@ -3,14 +3,21 @@
 
 use crate::{
     logging::NetworkSchema,
-    noise::{stream::NoiseStream, AntiReplayTimestamps, HandshakeAuthMode, NoiseUpgrader},
+    noise::{
+        stream::{NoiseStream, RekeyPolicy},
+        AntiReplayTimestamps, HandshakeAuthMode, NoiseUpgrader,
+    },
     protocols::{
-        identity::exchange_handshake,
-        wire::handshake::v1::{HandshakeMsg, MessagingProtocolVersion, ProtocolIdSet},
+        identity::{exchange_handshake, exchange_handshake_v2},
+        wire::handshake::{
+            v1::{HandshakeMsg, MessagingProtocolVersion, ProtocolIdSet},
+            v2::{Feature, FeatureSet, HandshakeMsgV2, NegotiatedFeatures},
+        },
     },
+    tls::{TlsStream, TlsUpgrader},
 };
 use aptos_config::{
-    config::{PeerRole, HANDSHAKE_VERSION},
+    config::{PeerRole, TransportSecurity, HANDSHAKE_VERSION},
     network_id::{NetworkContext, NetworkId},
 };
 use aptos_crypto::x25519;
@@ -18,12 +25,12 @@ use aptos_id_generator::{IdGenerator, U32IdGenerator};
 use aptos_logger::prelude::*;
 // Re-exposed for aptos-network-checker
 pub use aptos_netcore::transport::tcp::{resolve_and_connect, TCPBufferCfg, TcpSocket};
-use aptos_netcore::transport::{proxy_protocol, tcp, ConnectionOrigin, Transport};
+use aptos_netcore::transport::{proxy_protocol, tcp, ConnectionOrigin, PeerAddr, Transport};
 use aptos_short_hex_str::AsShortHexStr;
 use aptos_time_service::{timeout, TimeService, TimeServiceTrait};
 use aptos_types::{
     chain_id::ChainId,
-    network_address::{parse_dns_tcp, parse_ip_tcp, parse_memory, NetworkAddress},
+    network_address::{parse_dns_tcp, parse_ip_tcp, parse_memory, parse_ws, NetworkAddress},
     PeerId,
 };
 use futures::{
@@ -32,7 +39,16 @@ use futures::{
     stream::{Stream, StreamExt, TryStreamExt},
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, convert::TryFrom, fmt, io, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt, io,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 #[cfg(test)]
 mod test;
@@ -44,6 +60,10 @@ pub const TRANSPORT_TIMEOUT: Duration = Duration::from_secs(30);
 /// TODO: Add ability to support more than one messaging protocol.
 pub const SUPPORTED_MESSAGING_PROTOCOL: MessagingProtocolVersion = MessagingProtocolVersion::V1;
 
+/// The weight of the latest sample in the smoothed RTT of a connection (as
+/// for the smoothed RTT of TCP, see RFC 6298)
+const RTT_SMOOTHING_FACTOR: f64 = 0.125;
+
 /// Global connection-id generator.
 static CONNECTION_ID_GENERATOR: ConnectionIdGenerator = ConnectionIdGenerator::new();
 
@@ -55,6 +75,10 @@ pub const APTOS_TCP_TRANSPORT: tcp::TcpTransport = tcp::TcpTransport {
     nodelay: Some(true),
     // Use default TCP setting, overridden by Network config
     tcp_buff_cfg: tcp::TCPBufferCfg::new(),
+    // Use default keepalive, overridden by Network config
+    keepalive: None,
+    // No SOCKS5 proxy, overridden by Network config
+    socks5_proxy: None,
 };
 
 /// A trait alias for "socket-like" things.
@@ -89,6 +113,31 @@ impl ConnectionIdGenerator {
     }
 }
 
+/// The IP family of a connection
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl From<IpAddr> for IpFamily {
+    fn from(ip_addr: IpAddr) -> Self {
+        match ip_addr {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpFamily::V4 => f.write_str("ipv4"),
+            IpFamily::V6 => f.write_str("ipv6"),
+        }
+    }
+}
+
 /// Metadata associated with an established and fully upgraded connection.
 #[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
 pub struct ConnectionMetadata {
@@ -99,6 +148,17 @@ pub struct ConnectionMetadata {
     pub messaging_protocol: MessagingProtocolVersion,
     pub application_protocols: ProtocolIdSet,
     pub role: PeerRole,
+    /// The IP family the connection was established over, or `None` if it
+    /// isn't known (e.g., for in-memory connections). For outbound connections
+    /// to DNS addresses, this is the family that won the Happy Eyeballs race.
+    pub ip_family: Option<IpFamily>,
+    /// The wire features and protocol versions negotiated by the v2 handshake
+    pub features: NegotiatedFeatures,
+    /// The smoothed round-trip time of the connection, estimated by the peer
+    /// actor from the latencies of its outbound rpcs, or `None` until the
+    /// first rpc completes
+    #[serde(default)]
+    pub smoothed_rtt: Option<Duration>,
 }
 
 impl ConnectionMetadata {
@@ -110,6 +170,7 @@ impl ConnectionMetadata {
         messaging_protocol: MessagingProtocolVersion,
         application_protocols: ProtocolIdSet,
         role: PeerRole,
+        ip_family: Option<IpFamily>,
     ) -> ConnectionMetadata {
         ConnectionMetadata {
             remote_peer_id,
@@ -119,9 +180,22 @@ impl ConnectionMetadata {
             messaging_protocol,
             application_protocols,
             role,
+            ip_family,
+            features: NegotiatedFeatures::default(),
+            smoothed_rtt: None,
         }
     }
 
+    /// Folds the given RTT sample into the smoothed RTT of the connection
+    pub fn record_rtt_sample(&mut self, rtt: Duration) {
+        self.smoothed_rtt = Some(match self.smoothed_rtt {
+            Some(smoothed_rtt) => {
+                smoothed_rtt.mul_f64(1.0 - RTT_SMOOTHING_FACTOR) + rtt.mul_f64(RTT_SMOOTHING_FACTOR)
+            },
+            None => rtt,
+        });
+    }
+
     #[cfg(any(test, feature = "fuzzing"))]
     pub fn mock(remote_peer_id: PeerId) -> ConnectionMetadata {
         Self::mock_with_role_and_origin(
@@ -145,6 +219,9 @@ impl ConnectionMetadata {
             addr: NetworkAddress::mock(),
             messaging_protocol: MessagingProtocolVersion::V1,
             application_protocols: ProtocolIdSet::empty(),
+            ip_family: None,
+            features: NegotiatedFeatures::default(),
+            smoothed_rtt: None,
         }
     }
 }
@@ -159,13 +236,14 @@ impl fmt::Display for ConnectionMetadata {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "[{},{},{},{},{:?},{:?}]",
+            "[{},{},{},{},{:?},{:?},{:?}]",
             self.remote_peer_id,
             self.addr,
             self.origin,
             self.messaging_protocol,
             self.application_protocols,
-            self.role
+            self.role,
+            self.ip_family
         )
     }
 }
@@ -178,6 +256,80 @@ pub struct Connection<TSocket> {
     pub metadata: ConnectionMetadata,
 }
 
+/// A socket secured by either of the supported security protocols.
+#[derive(Debug)]
+pub enum SecureStream<TSocket> {
+    Noise(NoiseStream<TSocket>),
+    Tls(TlsStream<TSocket>),
+}
+
+impl<TSocket> SecureStream<TSocket>
+where
+    TSocket: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Get the static public key of the remote
+    pub fn get_remote_static(&self) -> x25519::PublicKey {
+        match self {
+            SecureStream::Noise(stream) => stream.get_remote_static(),
+            SecureStream::Tls(stream) => stream.get_remote_static(),
+        }
+    }
+
+    /// Rotate the session keys of noise streams as per the given policy. TLS
+    /// streams are left as is, as TLS 1.3 updates its keys on its own.
+    pub fn enable_rekeying(&mut self, policy: RekeyPolicy, time_service: TimeService) {
+        if let SecureStream::Noise(stream) = self {
+            stream.enable_rekeying(policy, time_service);
+        }
+    }
+}
+
+impl<TSocket> AsyncRead for SecureStream<TSocket>
+where
+    TSocket: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SecureStream::Noise(stream) => Pin::new(stream).poll_read(context, buf),
+            SecureStream::Tls(stream) => Pin::new(stream).poll_read(context, buf),
+        }
+    }
+}
+
+impl<TSocket> AsyncWrite for SecureStream<TSocket>
+where
+    TSocket: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        context: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SecureStream::Noise(stream) => Pin::new(stream).poll_write(context, buf),
+            SecureStream::Tls(stream) => Pin::new(stream).poll_write(context, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SecureStream::Noise(stream) => Pin::new(stream).poll_flush(context),
+            SecureStream::Tls(stream) => Pin::new(stream).poll_flush(context),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, context: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SecureStream::Noise(stream) => Pin::new(stream).poll_close(context),
+            SecureStream::Tls(stream) => Pin::new(stream).poll_close(context),
+        }
+    }
+}
+
 /// Convenience function for adding a timeout to a Future that returns an `io::Result`.
 async fn timeout_io<F, T>(time_service: TimeService, duration: Duration, fut: F) -> io::Result<T>
 where
@@ -190,29 +342,67 @@ where
     }
 }
 
+/// Secures and authenticates connections with one of the supported protocols.
+pub enum SecurityUpgrader {
+    Noise(NoiseUpgrader),
+    Tls(TlsUpgrader),
+}
+
+impl SecurityUpgrader {
+    fn network_context(&self) -> &NetworkContext {
+        match self {
+            SecurityUpgrader::Noise(noise) => &noise.network_context,
+            SecurityUpgrader::Tls(tls) => &tls.network_context,
+        }
+    }
+
+    /// The security event of failed handshakes
+    fn security_event(&self) -> SecurityEvent {
+        match self {
+            SecurityUpgrader::Noise(_) => SecurityEvent::NoiseHandshake,
+            SecurityUpgrader::Tls(_) => SecurityEvent::TlsHandshake,
+        }
+    }
+}
+
+impl From<NoiseUpgrader> for SecurityUpgrader {
+    fn from(noise: NoiseUpgrader) -> Self {
+        SecurityUpgrader::Noise(noise)
+    }
+}
+
+impl From<TlsUpgrader> for SecurityUpgrader {
+    fn from(tls: TlsUpgrader) -> Self {
+        SecurityUpgrader::Tls(tls)
+    }
+}
+
 /// Common context for performing both inbound and outbound connection upgrades.
 pub struct UpgradeContext {
-    noise: NoiseUpgrader,
+    security: SecurityUpgrader,
     handshake_version: u8,
     supported_protocols: BTreeMap<MessagingProtocolVersion, ProtocolIdSet>,
     chain_id: ChainId,
     network_id: NetworkId,
+    time_service: TimeService,
 }
 
 impl UpgradeContext {
     pub fn new(
-        noise: NoiseUpgrader,
+        security: impl Into<SecurityUpgrader>,
         handshake_version: u8,
         supported_protocols: BTreeMap<MessagingProtocolVersion, ProtocolIdSet>,
         chain_id: ChainId,
         network_id: NetworkId,
+        time_service: TimeService,
     ) -> Self {
         UpgradeContext {
-            noise,
+            security: security.into(),
             handshake_version,
             supported_protocols,
             chain_id,
             network_id,
+            time_service,
         }
     }
 }
@@ -229,9 +419,54 @@ fn add_pp_addr(proxy_protocol_enabled: bool, error: io::Error, addr: &NetworkAdd
     }
 }
 
-/// Upgrade an inbound connection. This means we run a Noise IK handshake for
-/// authentication and then negotiate common supported protocols. If
-/// `ctxt.noise.auth_mode` is `HandshakeAuthMode::Mutual( anti_replay_timestamps , trusted_peers )`,
+/// Converts a failed security handshake into an `io::Error`, logging it as a security
+/// event if it's either a clear bug or indicates some security issue.
+fn handshake_error<E>(
+    ctxt: &UpgradeContext,
+    should_security_log: bool,
+    err: E,
+    addr: &NetworkAddress,
+    origin: ConnectionOrigin,
+) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    if should_security_log {
+        sample!(
+            SampleRate::Duration(Duration::from_secs(15)),
+            error!(
+                ctxt.security.security_event(),
+                NetworkSchema::new(ctxt.security.network_context())
+                    .network_address(addr)
+                    .connection_origin(&origin),
+                error = %err,
+            )
+        );
+    }
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// Runs the v2 handshake, if both peers advertised support for it in the v1
+/// handshake, and returns the negotiated features and the application protocols
+/// (without the v2 handshake advertisement).
+async fn negotiate_features<T: TSocket>(
+    socket: &mut T,
+    application_protocols: ProtocolIdSet,
+) -> io::Result<(NegotiatedFeatures, ProtocolIdSet)> {
+    let application_protocols_v1 = application_protocols.without_handshake_v2();
+    if !application_protocols.supports_handshake_v2() {
+        return Ok((NegotiatedFeatures::default(), application_protocols_v1));
+    }
+
+    let handshake_msg = HandshakeMsgV2::new(FeatureSet::all_known(), &application_protocols_v1);
+    let remote_handshake = exchange_handshake_v2(&handshake_msg, socket).await?;
+    let features = handshake_msg.negotiate(&remote_handshake);
+    Ok((features, application_protocols_v1))
+}
+
+/// Upgrade an inbound connection. This means we run a Noise IK (or TLS) handshake
+/// for authentication and then negotiate common supported protocols. If the
+/// `auth_mode` is `HandshakeAuthMode::Mutual( anti_replay_timestamps , trusted_peers )`,
 /// then we will only allow connections from peers with a pubkey in the `trusted_peers`
 /// set. Otherwise, we will allow inbound connections from any pubkey.
 async fn upgrade_inbound<T: TSocket>(
@@ -239,7 +474,7 @@ async fn upgrade_inbound<T: TSocket>(
     fut_socket: impl Future<Output = io::Result<T>>,
     addr: NetworkAddress,
     proxy_protocol_enabled: bool,
-) -> io::Result<Connection<NoiseStream<T>>> {
+) -> io::Result<Connection<SecureStream<T>>> {
     let origin = ConnectionOrigin::Inbound;
     let mut socket = fut_socket.await?;
 
@@ -261,25 +496,23 @@ async fn upgrade_inbound<T: TSocket>(
         addr
     };
 
-    // try authenticating via noise handshake
-    let (mut socket, remote_peer_id, peer_role) =
-        ctxt.noise.upgrade_inbound(socket).await.map_err(|err| {
-            if err.should_security_log() {
-                sample!(
-                    SampleRate::Duration(Duration::from_secs(15)),
-                    error!(
-                        SecurityEvent::NoiseHandshake,
-                        NetworkSchema::new(&ctxt.noise.network_context)
-                            .network_address(&addr)
-                            .connection_origin(&origin),
-                        error = %err,
-                    )
-                );
-            }
-            let err = io::Error::new(io::ErrorKind::Other, err);
-            add_pp_addr(proxy_protocol_enabled, err, &addr)
-        })?;
+    // try authenticating via noise (or tls) handshake
+    let (mut socket, remote_peer_id, peer_role) = match &ctxt.security {
+        SecurityUpgrader::Noise(noise) => noise
+            .upgrade_inbound(socket)
+            .await
+            .map(|(socket, peer_id, role)| (SecureStream::Noise(socket), peer_id, role))
+            .map_err(|err| handshake_error(&ctxt, err.should_security_log(), err, &addr, origin)),
+        SecurityUpgrader::Tls(tls) => tls
+            .upgrade_inbound(socket)
+            .await
+            .map(|(socket, peer_id, role)| (SecureStream::Tls(socket), peer_id, role))
+            .map_err(|err| handshake_error(&ctxt, err.should_security_log(), err, &addr, origin)),
+    }
+    .map_err(|err| add_pp_addr(proxy_protocol_enabled, err, &addr))?;
     let remote_pubkey = socket.get_remote_static();
+    // use the dialer's address (rather than the socket), as it accounts for the proxy protocol
+    let ip_family = addr.find_ip_addr().map(IpFamily::from);
     let addr = addr.append_prod_protos(remote_pubkey, HANDSHAKE_VERSION);
 
     // exchange HandshakeMsg
@@ -308,55 +541,60 @@ async fn upgrade_inbound<T: TSocket>(
             )
         })?;
 
+    // negotiate the optional wire features, if both peers support it
+    let (features, application_protocols) = negotiate_features(&mut socket, application_protocols)
+        .await
+        .map_err(|err| add_pp_addr(proxy_protocol_enabled, err, &addr))?;
+    if features.supports(Feature::NoiseRekey) {
+        socket.enable_rekeying(RekeyPolicy::default(), ctxt.time_service.clone());
+    }
+
     // return successful connection
-    Ok(Connection {
-        socket,
-        metadata: ConnectionMetadata::new(
-            remote_peer_id,
-            CONNECTION_ID_GENERATOR.next(),
-            addr,
-            origin,
-            messaging_protocol,
-            application_protocols,
-            peer_role,
-        ),
-    })
+    let mut metadata = ConnectionMetadata::new(
+        remote_peer_id,
+        CONNECTION_ID_GENERATOR.next(),
+        addr,
+        origin,
+        messaging_protocol,
+        application_protocols,
+        peer_role,
+        ip_family,
+    );
+    metadata.features = features;
+    Ok(Connection { socket, metadata })
 }
 
-/// Upgrade an outbound connection. This means we run a Noise IK handshake for
-/// authentication and then negotiate common supported protocols.
-pub async fn upgrade_outbound<T: TSocket>(
+/// Upgrade an outbound connection. This means we run a Noise IK (or TLS) handshake
+/// for authentication and then negotiate common supported protocols.
+pub async fn upgrade_outbound<T: TSocket + PeerAddr>(
     ctxt: Arc<UpgradeContext>,
     fut_socket: impl Future<Output = io::Result<T>>,
     addr: NetworkAddress,
     remote_peer_id: PeerId,
     remote_pubkey: x25519::PublicKey,
-) -> io::Result<Connection<NoiseStream<T>>> {
+) -> io::Result<Connection<SecureStream<T>>> {
     let origin = ConnectionOrigin::Outbound;
     let socket = fut_socket.await?;
+    let ip_family = socket
+        .peer_addr()
+        .map(|socket_addr| IpFamily::from(socket_addr.ip()));
+
+    // noise (or tls) handshake
+    let mut socket = match &ctxt.security {
+        SecurityUpgrader::Noise(noise) => noise
+            .upgrade_outbound(socket, remote_pubkey, AntiReplayTimestamps::now)
+            .await
+            .map(SecureStream::Noise)
+            .map_err(|err| handshake_error(&ctxt, err.should_security_log(), err, &addr, origin)),
+        SecurityUpgrader::Tls(tls) => tls
+            .upgrade_outbound(socket, remote_pubkey)
+            .await
+            .map(SecureStream::Tls)
+            .map_err(|err| handshake_error(&ctxt, err.should_security_log(), err, &addr, origin)),
+    }?;
 
-    // noise handshake
-    let mut socket = ctxt
-        .noise
-        .upgrade_outbound(socket, remote_pubkey, AntiReplayTimestamps::now)
-        .await
-        .map_err(|err| {
-            if err.should_security_log() {
-                sample!(
-                    SampleRate::Duration(Duration::from_secs(15)),
-                    error!(
-                        SecurityEvent::NoiseHandshake,
-                        NetworkSchema::new(&ctxt.noise.network_context)
-                            .network_address(&addr)
-                            .connection_origin(&origin),
-                        error = %err,
-                    )
-                );
-            }
-            io::Error::new(io::ErrorKind::Other, err)
-        })?;
-
-    // sanity check: Noise IK should always guarantee this is true
+    // sanity check: Noise IK and the TLS certificate verification should always
+    // guarantee this is true
     debug_assert_eq!(remote_pubkey, socket.get_remote_static());
 
     // exchange HandshakeMsg
@@ -378,30 +616,37 @@ pub async fn upgrade_outbound<T: TSocket>(
             io::Error::new(io::ErrorKind::Other, e)
         })?;
 
+    // negotiate the optional wire features, if both peers support it
+    let (features, application_protocols) =
+        negotiate_features(&mut socket, application_protocols).await?;
+    if features.supports(Feature::NoiseRekey) {
+        socket.enable_rekeying(RekeyPolicy::default(), ctxt.time_service.clone());
+    }
+
     // return successful connection
-    Ok(Connection {
-        socket,
-        metadata: ConnectionMetadata::new(
-            remote_peer_id,
-            CONNECTION_ID_GENERATOR.next(),
-            addr,
-            origin,
-            messaging_protocol,
-            application_protocols,
-            PeerRole::Unknown,
-        ),
-    })
+    let mut metadata = ConnectionMetadata::new(
+        remote_peer_id,
+        CONNECTION_ID_GENERATOR.next(),
+        addr,
+        origin,
+        messaging_protocol,
+        application_protocols,
+        PeerRole::Unknown,
+        ip_family,
+    );
+    metadata.features = features;
+    Ok(Connection { socket, metadata })
 }
 
 /// The common AptosNet Transport.
 ///
 /// The base transport layer is pluggable, so long as it provides a reliable,
 /// ordered, connection-oriented, byte-stream abstraction (e.g., TCP). We currently
-/// use either `MemoryTransport` or `TcpTransport` as this base layer.
+/// use either `MemoryTransport`, `TcpTransport` or `WsTransport` as this base layer.
 ///
 /// Inbound and outbound connections are first established with the `base_transport`
-/// and then negotiate a secure, authenticated transport layer (currently Noise
-/// protocol). Finally, we negotiate common supported application protocols with
+/// and then negotiate a secure, authenticated transport layer (either the Noise
+/// protocol or TLS 1.3, per the `TransportSecurity`). Finally, we negotiate common supported application protocols with
 /// the `Handshake` protocol.
 // TODO(philiphayes): rework Transport trait, possibly include Upgrade trait.
 // ideas in this PR thread: https://github.com/aptos-labs/aptos-core/pull/3478#issuecomment-617385633
@@ -416,7 +661,7 @@ pub struct AptosNetTransport<TTransport> {
 impl<TTransport> AptosNetTransport<TTransport>
 where
     TTransport: Transport<Error = io::Error>,
-    TTransport::Output: TSocket,
+    TTransport::Output: TSocket + PeerAddr,
     TTransport::Outbound: Send + 'static,
     TTransport::Inbound: Send + 'static,
     TTransport::Listener: Send + 'static,
@@ -427,23 +672,34 @@ where
         time_service: TimeService,
         identity_key: x25519::PrivateKey,
         auth_mode: HandshakeAuthMode,
+        transport_security: TransportSecurity,
         handshake_version: u8,
         chain_id: ChainId,
-        application_protocols: ProtocolIdSet,
+        mut application_protocols: ProtocolIdSet,
         enable_proxy_protocol: bool,
     ) -> Self {
         // build supported protocols
+        application_protocols.enable_handshake_v2();
         let mut supported_protocols = BTreeMap::new();
         supported_protocols.insert(SUPPORTED_MESSAGING_PROTOCOL, application_protocols);
 
         let identity_pubkey = identity_key.public_key();
 
+        let security: SecurityUpgrader = match transport_security {
+            TransportSecurity::Noise => {
+                NoiseUpgrader::new(network_context, identity_key, auth_mode).into()
+            },
+            TransportSecurity::Tls => TlsUpgrader::new(network_context, identity_key, auth_mode)
+                .expect("Failed to build the TLS configuration")
+                .into(),
+        };
         let upgrade_context = UpgradeContext::new(
-            NoiseUpgrader::new(network_context, identity_key, auth_mode),
+            security,
             handshake_version,
             supported_protocols,
             chain_id,
             network_context.network_id(),
+            time_service.clone(),
         );
 
         Self {
@@ -467,15 +723,19 @@ where
         // TODO(philiphayes): protos[..X] is kinda hacky. `Transport` trait
         // should handle this.
         let (base_transport_protos, base_transport_suffix) = parse_ip_tcp(protos)
-            .map(|x| (&protos[..2], x.1))
-            .or_else(|| parse_dns_tcp(protos).map(|x| (&protos[..2], x.1)))
+            .map(|x| x.1)
+            .or_else(|| parse_dns_tcp(protos).map(|x| x.1))
+            .map(|suffix| match parse_ws(suffix) {
+                Some(ws_suffix) => (&protos[..3], ws_suffix),
+                None => (&protos[..2], suffix),
+            })
             .or_else(|| parse_memory(protos).map(|x| (&protos[..1], x.1)))
             .ok_or_else(|| {
                 io::Error::new(
                     io::ErrorKind::InvalidInput,
                     format!(
                         "Unexpected dialing network address: '{}', expected: \
-                         memory, ip+tcp, or dns+tcp",
+                         memory, ip+tcp, dns+tcp, ip+tcp+ws, or dns+tcp+ws",
                         addr
                     ),
                 )
@@ -521,12 +781,15 @@ where
     /// `/dns/<ipaddr>/tcp/<port>` or
     /// `/dns4/<ipaddr>/tcp/<port>` or
     /// `/dns6/<ipaddr>/tcp/<port>`
+    ///
+    /// If the base transport is `WsTransport`, then `/<base_transport>` is any of
+    /// the `TcpTransport` formats followed by `/ws`.
     pub fn dial(
         &self,
         peer_id: PeerId,
         addr: NetworkAddress,
     ) -> io::Result<
-        impl Future<Output = io::Result<Connection<NoiseStream<TTransport::Output>>>> + Send + 'static,
+        impl Future<Output = io::Result<Connection<SecureStream<TTransport::Output>>>> + Send + 'static,
     > {
         // parse aptosnet protocols
         // TODO(philiphayes): `Transport` trait should include parsing in `dial`?
@@ -567,13 +830,18 @@ where
     ///
     /// `/ip4/<ipaddr>/tcp/<port>` or
     /// `/ip6/<ipaddr>/tcp/<port>`
+    ///
+    /// If the base transport is `WsTransport`, then we expect:
+    ///
+    /// `/ip4/<ipaddr>/tcp/<port>/ws` or
+    /// `/ip6/<ipaddr>/tcp/<port>/ws`
     pub fn listen_on(
         &self,
         addr: NetworkAddress,
     ) -> io::Result<(
         impl Stream<
                 Item = io::Result<(
-                    impl Future<Output = io::Result<Connection<NoiseStream<TTransport::Output>>>>
+                    impl Future<Output = io::Result<Connection<SecureStream<TTransport::Output>>>>
                         + Send
                         + 'static,
                     NetworkAddress,
@@ -623,7 +891,7 @@ where
 impl<TTransport: Transport> Transport for AptosNetTransport<TTransport>
 where
     TTransport: Transport<Error = io::Error> + Send + 'static,
-    TTransport::Output: TSocket,
+    TTransport::Output: TSocket + PeerAddr,
     TTransport::Outbound: Send + 'static,
     TTransport::Inbound: Send + 'static,
     TTransport::Listener: Send + 'static,
@@ -633,7 +901,7 @@ where
     type Listener =
         Pin<Box<dyn Stream<Item = io::Result<(Self::Inbound, NetworkAddress)>> + Send + 'static>>;
     type Outbound = Pin<Box<dyn Future<Output = io::Result<Self::Output>> + Send + 'static>>;
-    type Output = Connection<NoiseStream<TTransport::Output>>;
+    type Output = Connection<SecureStream<TTransport::Output>>;
 
     fn dial(&self, peer_id: PeerId, addr: NetworkAddress) -> io::Result<Self::Outbound> {
         self.dial(peer_id, addr)
@@ -6,14 +6,14 @@ use crate::{
     transport::*,
 };
 use aptos_config::{
-    config::{Peer, PeerRole, PeerSet, HANDSHAKE_VERSION},
+    config::{Peer, PeerRole, PeerSet, TransportSecurity, HANDSHAKE_VERSION},
     network_id::NetworkContext,
 };
 use aptos_crypto::{test_utils::TEST_SEED, traits::Uniform, x25519};
 use aptos_infallible::RwLock;
 use aptos_netcore::{
     framing::{read_u16frame, write_u16frame},
-    transport::{memory, ConnectionOrigin, Transport},
+    transport::{memory, websocket::WsTransport, ConnectionOrigin, PeerAddr, Transport},
 };
 use aptos_time_service::MockTimeService;
 use aptos_types::{
@@ -56,6 +56,7 @@ enum Auth {
 
 fn setup<TTransport>(
     base_transport: TTransport,
+    transport_security: TransportSecurity,
     auth: Auth,
 ) -> (
     Runtime,
@@ -67,7 +68,7 @@ fn setup<TTransport>(
 )
 where
     TTransport: Transport<Error = io::Error> + Clone,
-    TTransport::Output: TSocket,
+    TTransport::Output: TSocket + PeerAddr,
     TTransport::Outbound: Send + 'static,
     TTransport::Inbound: Send + 'static,
     TTransport::Listener: Send + 'static,
@@ -152,6 +153,7 @@ where
         time_service.clone(),
         listener_key,
         listener_auth_mode,
+        transport_security,
         HANDSHAKE_VERSION,
         chain_id,
         supported_protocols.clone(),
@@ -164,6 +166,7 @@ where
         time_service.clone(),
         dialer_key,
         dialer_auth_mode,
+        transport_security,
         HANDSHAKE_VERSION,
         chain_id,
         supported_protocols.clone(),
@@ -209,14 +212,31 @@ fn expect_ip4_tcp_noise_addr(addr: &NetworkAddress) {
     );
 }
 
+/// Check that the network address matches the format
+/// `"/ip4/<ipaddr>/tcp/<port>/ws/noise-ik/<pubkey>/handshake/<version>"`
+fn expect_ip4_tcp_ws_noise_addr(addr: &NetworkAddress) {
+    assert!(
+        matches!(addr.as_slice(), [
+            Ip4(_),
+            Tcp(_),
+            Ws,
+            NoiseIK(_),
+            Handshake(_)
+        ]),
+        "addr: '{}'",
+        addr
+    );
+}
+
 fn test_transport_success<TTransport>(
     base_transport: TTransport,
+    transport_security: TransportSecurity,
     auth: Auth,
     listen_addr: &str,
     expect_formatted_addr: fn(&NetworkAddress),
 ) where
     TTransport: Transport<Error = io::Error> + Clone,
-    TTransport::Output: TSocket,
+    TTransport::Output: TSocket + PeerAddr,
     TTransport::Outbound: Send + 'static,
     TTransport::Inbound: Send + 'static,
     TTransport::Listener: Send + 'static,
@@ -228,7 +248,7 @@ fn test_transport_success<TTransport>(
         (dialer_peer_id, dialer_transport),
         _trusted_peers,
         supported_protocols,
-    ) = setup(base_transport, auth);
+    ) = setup(base_transport, transport_security, auth);
 
     let _guard = rt.enter();
     let (mut inbounds, listener_addr) = listener_transport
@@ -284,6 +304,13 @@ fn test_transport_success<TTransport>(
         );
         assert_eq!(conn.metadata.application_protocols, supported_protocols);
 
+        // both peers support the v2 handshake
+        assert_eq!(conn.metadata.features.features, FeatureSet::all_known());
+        assert_eq!(
+            conn.metadata.features.protocol_versions.len(),
+            supported_protocols.iter().count()
+        );
+
         // test the socket works
         let msg = write_read_msg(&mut conn.socket, b"barbaz").await;
         assert_eq!(&msg, b"foobar".as_ref());
@@ -295,11 +322,12 @@ fn test_transport_success<TTransport>(
 
 fn test_transport_rejects_unauthed_dialer<TTransport>(
     base_transport: TTransport,
+    transport_security: TransportSecurity,
     listen_addr: &str,
     expect_formatted_addr: fn(&NetworkAddress),
 ) where
     TTransport: Transport<Error = io::Error> + Clone,
-    TTransport::Output: TSocket,
+    TTransport::Output: TSocket + PeerAddr,
     TTransport::Outbound: Send + 'static,
     TTransport::Inbound: Send + 'static,
     TTransport::Listener: Send + 'static,
@@ -311,7 +339,7 @@ fn test_transport_rejects_unauthed_dialer<TTransport>(
         (dialer_peer_id, dialer_transport),
         trusted_peers,
         _supported_protocols,
-    ) = setup(base_transport, Auth::Mutual);
+    ) = setup(base_transport, transport_security, Auth::Mutual);
 
     // remove dialer from trusted_peers set
     trusted_peers.write().remove(&dialer_peer_id).unwrap();
@@ -349,11 +377,12 @@ fn test_transport_rejects_unauthed_dialer<TTransport>(
 
 fn test_transport_maybe_mutual<TTransport>(
     base_transport: TTransport,
+    transport_security: TransportSecurity,
     listen_addr: &str,
     expect_formatted_addr: fn(&NetworkAddress),
 ) where
     TTransport: Transport<Error = io::Error> + Clone,
-    TTransport::Output: TSocket,
+    TTransport::Output: TSocket + PeerAddr,
     TTransport::Outbound: Send + 'static,
     TTransport::Inbound: Send + 'static,
     TTransport::Listener: Send + 'static,
@@ -365,7 +394,7 @@ fn test_transport_maybe_mutual<TTransport>(
         (dialer_peer_id, dialer_transport),
         trusted_peers,
         supported_protocols,
-    ) = setup(base_transport, Auth::MaybeMutual);
+    ) = setup(base_transport, transport_security, Auth::MaybeMutual);
 
     let _guard = rt.enter();
     let (mut inbounds, listener_addr) = listener_transport
@@ -497,6 +526,7 @@ fn test_transport_maybe_mutual<TTransport>(
 fn test_memory_transport_mutual_auth() {
     test_transport_success(
         memory::MemoryTransport,
+        TransportSecurity::Noise,
         Auth::Mutual,
         "/memory/0",
         expect_memory_noise_addr,
@@ -507,6 +537,7 @@ fn test_memory_transport_mutual_auth() {
 fn test_memory_transport_server_only_auth() {
     test_transport_success(
         memory::MemoryTransport,
+        TransportSecurity::Noise,
         Auth::ServerOnly,
         "/memory/0",
         expect_memory_noise_addr,
@@ -517,6 +548,7 @@ fn test_memory_transport_server_only_auth() {
 fn test_memory_transport_rejects_unauthed_dialer() {
     test_transport_rejects_unauthed_dialer(
         memory::MemoryTransport,
+        TransportSecurity::Noise,
         "/memory/0",
         expect_memory_noise_addr,
     );
@@ -526,6 +558,7 @@ fn test_memory_transport_rejects_unauthed_dialer() {
 fn test_memory_transport_maybe_mutual() {
     test_transport_maybe_mutual(
         memory::MemoryTransport,
+        TransportSecurity::Noise,
         "/memory/0",
         expect_memory_noise_addr,
     );
@@ -539,6 +572,7 @@ fn test_memory_transport_maybe_mutual() {
 fn test_tcp_transport_mutual_auth() {
     test_transport_success(
         APTOS_TCP_TRANSPORT.clone(),
+        TransportSecurity::Noise,
         Auth::Mutual,
         "/ip4/127.0.0.1/tcp/0",
         expect_ip4_tcp_noise_addr,
@@ -549,6 +583,7 @@ fn test_tcp_transport_mutual_auth() {
 fn test_tcp_transport_server_only_auth() {
     test_transport_success(
         APTOS_TCP_TRANSPORT.clone(),
+        TransportSecurity::Noise,
         Auth::ServerOnly,
         "/ip4/127.0.0.1/tcp/0",
         expect_ip4_tcp_noise_addr,
@@ -559,6 +594,78 @@ fn test_tcp_transport_server_only_auth() {
 fn test_tcp_transport_rejects_unauthed_dialer() {
     test_transport_rejects_unauthed_dialer(
         APTOS_TCP_TRANSPORT.clone(),
+        TransportSecurity::Noise,
+        "/ip4/127.0.0.1/tcp/0",
+        expect_ip4_tcp_noise_addr,
+    );
+}
+
+////////////////////////////////////
+// AptosNetTransport<WsTransport> //
+////////////////////////////////////
+
+#[test]
+fn test_ws_transport_mutual_auth() {
+    test_transport_success(
+        WsTransport::new(APTOS_TCP_TRANSPORT.clone()),
+        TransportSecurity::Noise,
+        Auth::Mutual,
+        "/ip4/127.0.0.1/tcp/0/ws",
+        expect_ip4_tcp_ws_noise_addr,
+    );
+}
+
+#[test]
+fn test_ws_transport_rejects_unauthed_dialer() {
+    test_transport_rejects_unauthed_dialer(
+        WsTransport::new(APTOS_TCP_TRANSPORT.clone()),
+        TransportSecurity::Noise,
+        "/ip4/127.0.0.1/tcp/0/ws",
+        expect_ip4_tcp_ws_noise_addr,
+    );
+}
+
+///////////////////////////////////////
+// AptosNetTransport with TLS security //
+///////////////////////////////////////
+
+#[test]
+fn test_tls_memory_transport_mutual_auth() {
+    test_transport_success(
+        memory::MemoryTransport,
+        TransportSecurity::Tls,
+        Auth::Mutual,
+        "/memory/0",
+        expect_memory_noise_addr,
+    );
+}
+
+#[test]
+fn test_tls_memory_transport_rejects_unauthed_dialer() {
+    test_transport_rejects_unauthed_dialer(
+        memory::MemoryTransport,
+        TransportSecurity::Tls,
+        "/memory/0",
+        expect_memory_noise_addr,
+    );
+}
+
+#[test]
+fn test_tls_memory_transport_maybe_mutual() {
+    test_transport_maybe_mutual(
+        memory::MemoryTransport,
+        TransportSecurity::Tls,
+        "/memory/0",
+        expect_memory_noise_addr,
+    );
+}
+
+#[test]
+fn test_tls_tcp_transport_server_only_auth() {
+    test_transport_success(
+        APTOS_TCP_TRANSPORT.clone(),
+        TransportSecurity::Tls,
+        Auth::ServerOnly,
         "/ip4/127.0.0.1/tcp/0",
         expect_ip4_tcp_noise_addr,
     );
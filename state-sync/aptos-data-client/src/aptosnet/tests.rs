@@ -14,7 +14,7 @@ use aptos_network::{
     application::{interface::NetworkClient, storage::PeerMetadataStorage, types::PeerState},
     peer_manager::{ConnectionRequestSender, PeerManagerRequest, PeerManagerRequestSender},
     protocols::{
-        network::{NetworkSender, NewNetworkSender},
+        network::{NetworkSender, NewNetworkSender, RpcMetadata},
         wire::handshake::v1::ProtocolId,
     },
     transport::ConnectionMetadata,
@@ -209,6 +209,7 @@ impl MockNetwork {
                     protocol_id,
                     storage_service_request,
                     response_sender,
+                    rpc_metadata: RpcMetadata::default(),
                 })
             },
             Some(PeerManagerRequest::SendDirectSend(_, _)) => panic!("Unexpected direct send msg"),
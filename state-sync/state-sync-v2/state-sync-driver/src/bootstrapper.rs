@@ -22,7 +22,6 @@ use aptos_data_streaming_service::{
 use aptos_logger::{prelude::*, sample::SampleRate};
 use aptos_storage_interface::DbReader;
 use aptos_types::{
-    epoch_change::Verifier,
     epoch_state::EpochState,
     ledger_info::LedgerInfoWithSignatures,
     state_store::state_value::StateValueChunkWithProof,
@@ -94,28 +93,24 @@ impl VerifiedEpochStates {
         waypoint: &Waypoint,
     ) -> Result<(), Error> {
         // Verify the ledger info against the latest epoch state
-        self.latest_epoch_state
-            .verify(epoch_ending_ledger_info)
+        let next_epoch_state = self
+            .latest_epoch_state
+            .verifier
+            .verify_epoch_change(self.latest_epoch_state.epoch, epoch_ending_ledger_info)
             .map_err(|error| {
                 Error::VerificationError(format!("Ledger info failed verification: {:?}", error))
             })?;
 
         // Update the latest epoch state with the next epoch
-        if let Some(next_epoch_state) = epoch_ending_ledger_info.ledger_info().next_epoch_state() {
-            self.highest_fetched_epoch_ending_version =
-                epoch_ending_ledger_info.ledger_info().version();
-            self.latest_epoch_state = next_epoch_state.clone();
-            self.insert_new_epoch_ending_ledger_info(epoch_ending_ledger_info.clone())?;
-
-            trace!(LogSchema::new(LogEntry::Bootstrapper).message(&format!(
-                "Updated the latest epoch state to epoch: {:?}",
-                self.latest_epoch_state.epoch
-            )));
-        } else {
-            return Err(Error::VerificationError(
-                "The ledger info was not epoch ending!".into(),
-            ));
-        }
+        self.highest_fetched_epoch_ending_version =
+            epoch_ending_ledger_info.ledger_info().version();
+        self.latest_epoch_state = next_epoch_state.clone();
+        self.insert_new_epoch_ending_ledger_info(epoch_ending_ledger_info.clone())?;
+
+        trace!(LogSchema::new(LogEntry::Bootstrapper).message(&format!(
+            "Updated the latest epoch state to epoch: {:?}",
+            self.latest_epoch_state.epoch
+        )));
 
         // Check if the ledger info corresponds to the trusted waypoint
         self.verify_waypoint(epoch_ending_ledger_info, waypoint)
@@ -404,8 +404,22 @@ impl<T: StorageReaderInterface> StorageServiceServer<T> {
             let data_subscriptions = self.data_subscriptions.clone();
             let lru_storage_cache = self.lru_storage_cache.clone();
             let time_service = self.time_service.clone();
+            let rpc_metadata = network_request.rpc_metadata;
             self.bounded_executor
                 .spawn_blocking(move || {
+                    // Skip the request if the sender stopped waiting for the
+                    // response while it was queued
+                    if rpc_metadata.is_expired(time_service.now()) {
+                        trace!(LogSchema::new(LogEntry::ReceivedStorageRequest)
+                            .request(&storage_service_request)
+                            .message(&format!(
+                                "Dropping expired storage request. Peer: {:?}, protocol: {:?}.",
+                                peer_network_id, protocol_id,
+                            )));
+                        network_request.response_sender.send_expired();
+                        return;
+                    }
+
                     Handler::new(
                         cached_storage_server_summary,
                         data_subscriptions,
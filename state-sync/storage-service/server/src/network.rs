@@ -4,7 +4,7 @@
 use aptos_config::network_id::{NetworkId, PeerNetworkId};
 use aptos_network::{
     application::interface::NetworkServiceEvents,
-    protocols::network::{Event, RpcError},
+    protocols::network::{Event, RpcError, RpcMetadata},
     ProtocolId,
 };
 use aptos_storage_service_types::{
@@ -15,7 +15,7 @@ use bytes::Bytes;
 use futures::{
     channel::oneshot,
     future,
-    stream::{select_all, BoxStream, Stream, StreamExt},
+    stream::{BoxStream, Stream, StreamExt},
 };
 use std::{
     pin::Pin,
@@ -28,6 +28,7 @@ pub struct NetworkRequest {
     pub protocol_id: ProtocolId,
     pub storage_service_request: StorageServiceRequest,
     pub response_sender: ResponseSender,
+    pub rpc_metadata: RpcMetadata,
 }
 
 /// A stream of requests from network. Each request also comes with a callback to
@@ -39,12 +40,7 @@ pub struct StorageServiceNetworkEvents {
 impl StorageServiceNetworkEvents {
     pub fn new(network_service_events: NetworkServiceEvents<StorageServiceMessage>) -> Self {
         // Transform the event streams to also include the network ID
-        let network_events: Vec<_> = network_service_events
-            .into_network_and_events()
-            .into_iter()
-            .map(|(network_id, events)| events.map(move |event| (network_id, event)))
-            .collect();
-        let network_events = select_all(network_events).fuse();
+        let network_events = network_service_events.into_unified_stream();
 
         // Transform each event to a network request
         let network_request_stream = network_events
@@ -69,6 +65,7 @@ impl StorageServiceNetworkEvents {
                 StorageServiceMessage::Request(storage_service_request),
                 protocol_id,
                 response_tx,
+                rpc_metadata,
             ) => {
                 let response_sender = ResponseSender::new(response_tx);
                 let peer_network_id = PeerNetworkId::new(network_id, peer_id);
@@ -77,6 +74,7 @@ impl StorageServiceNetworkEvents {
                     protocol_id,
                     storage_service_request,
                     response_sender,
+                    rpc_metadata,
                 })
             },
             _ => None, // We don't use direct send and don't care about connection events
@@ -110,4 +108,9 @@ impl ResponseSender {
             .map_err(RpcError::BcsError);
         let _ = self.response_tx.send(result);
     }
+
+    /// Notifies the sender that the request expired before it was handled
+    pub fn send_expired(self) {
+        let _ = self.response_tx.send(Err(RpcError::Expired));
+    }
 }
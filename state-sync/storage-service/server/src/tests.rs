@@ -17,7 +17,7 @@ use aptos_network::{
     application::interface::NetworkServiceEvents,
     peer_manager::PeerManagerNotification,
     protocols::{
-        network::{NetworkEvents, NewNetworkEvents},
+        network::{NetworkEvents, NewNetworkEvents, RpcError},
         rpc::InboundRpcRequest,
         wire::handshake::v1::ProtocolId,
     },
@@ -36,7 +36,7 @@ use aptos_storage_service_types::{
     },
     Epoch, StorageServiceError, StorageServiceMessage,
 };
-use aptos_time_service::{MockTimeService, TimeService};
+use aptos_time_service::{MockTimeService, TimeService, TimeServiceTrait};
 use aptos_types::{
     account_address::AccountAddress,
     aggregate_signature::AggregateSignature,
@@ -72,7 +72,11 @@ use mockall::{
     Sequence,
 };
 use rand::{rngs::OsRng, Rng};
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::time::timeout;
 
 /// Various test constants for storage
@@ -299,6 +303,28 @@ async fn test_get_server_protocol_version() {
     );
 }
 
+#[tokio::test]
+async fn test_expired_requests_are_skipped() {
+    // Create the storage client and server
+    let (mut mock_client, service, mock_time) = MockClient::new(None, None);
+
+    // Send a request whose deadline passes before the server handles it
+    let deadline = mock_time.now() + Duration::from_secs(10);
+    let storage_request = StorageServiceRequest::new(DataRequest::GetServerProtocolVersion, true);
+    let receiver = mock_client
+        .send_request_with_deadline(storage_request, None, None, Some(deadline))
+        .await;
+    mock_time.advance_secs_async(20).await;
+    tokio::spawn(service.start());
+
+    // Verify the request is skipped
+    let response = timeout(Duration::from_secs(MAX_RESPONSE_TIMEOUT_SECS), receiver)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_matches!(response, Err(RpcError::Expired));
+}
+
 #[tokio::test]
 async fn test_get_states_with_proof() {
     // Test small and large chunk requests
@@ -2192,6 +2218,19 @@ impl MockClient {
         request: StorageServiceRequest,
         peer_id: Option<AccountAddress>,
         network_id: Option<NetworkId>,
+    ) -> Receiver<Result<bytes::Bytes, aptos_network::protocols::network::RpcError>> {
+        self.send_request_with_deadline(request, peer_id, network_id, None)
+            .await
+    }
+
+    /// Send the specified storage request (with the given deadline) and return
+    /// the receiver on which to expect a result.
+    pub async fn send_request_with_deadline(
+        &mut self,
+        request: StorageServiceRequest,
+        peer_id: Option<AccountAddress>,
+        network_id: Option<NetworkId>,
+        deadline: Option<Instant>,
     ) -> Receiver<Result<bytes::Bytes, aptos_network::protocols::network::RpcError>> {
         // Create the inbound rpc request
         let peer_id = peer_id.unwrap_or_else(PeerId::random);
@@ -2205,6 +2244,8 @@ impl MockClient {
             protocol_id,
             data: data.into(),
             res_tx,
+            deadline,
+            correlation_id: 0,
         };
         let notification = PeerManagerNotification::RecvRpc(peer_id, inbound_rpc);
 
@@ -1,6 +1,7 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::validator_verifier::{find_malformed_signature, ValidatorVerifier, VerifyError};
 use aptos_bitvec::BitVec;
 use aptos_crypto::bls12381;
 use aptos_crypto_derive::{BCSCryptoHash, CryptoHasher};
@@ -64,6 +65,32 @@ impl AggregateSignature {
     pub fn sig(&self) -> &Option<bls12381::Signature> {
         &self.sig
     }
+
+    /// Combines two multi-signatures on the same message (e.g., partial quorums collected
+    /// independently) into the multi-signature of all their voters. Fails if they have voters in
+    /// common, as their signatures would be counted twice. The result isn't verified.
+    pub fn merge(
+        &self,
+        other: &AggregateSignature,
+        verifier: &ValidatorVerifier,
+    ) -> Result<AggregateSignature, VerifyError> {
+        if !(&self.validator_bitmask & &other.validator_bitmask).all_zeros() {
+            return Err(VerifyError::DuplicateAuthor);
+        }
+        let validator_bitmask = &self.validator_bitmask | &other.validator_bitmask;
+        ValidatorVerifier::check_num_of_voters(verifier.len() as u16, &validator_bitmask)?;
+        let sig = match (&self.sig, &other.sig) {
+            (Some(sig), Some(other_sig)) => Some(
+                bls12381::Signature::aggregate(vec![sig.clone(), other_sig.clone()])
+                    .map_err(|_| VerifyError::FailedToAggregateSignature(None))?,
+            ),
+            // An empty multi-signature (i.e., without voters) doesn't have a signature
+            (sig, None) if other.validator_bitmask.all_zeros() => sig.clone(),
+            (None, sig) if self.validator_bitmask.all_zeros() => sig.clone(),
+            _ => return Err(VerifyError::EmptySignature),
+        };
+        Ok(AggregateSignature::new(validator_bitmask, sig))
+    }
 }
 
 /// Partial signature from a set of validators. This struct is only used when aggregating the votes
@@ -98,3 +125,155 @@ impl PartialSignatures {
         &self.signatures
     }
 }
+
+/// Partial signatures along with the voting power of their authors, resolved by the verifier as
+/// the signatures are added, so that callers don't have to recompute it from all the authors on
+/// every new signature. All signatures must be added (and removed) with the same verifier.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WeightedPartialSignatures {
+    signatures: PartialSignatures,
+    voting_power: u128,
+}
+
+impl WeightedPartialSignatures {
+    pub fn empty() -> Self {
+        Self {
+            signatures: PartialSignatures::empty(),
+            voting_power: 0,
+        }
+    }
+
+    /// Adds the signature of the validator, and returns the accumulated voting power. Signatures
+    /// of unknown validators, or of validators that already signed, are rejected.
+    pub fn add_signature(
+        &mut self,
+        verifier: &ValidatorVerifier,
+        validator: AccountAddress,
+        signature: bls12381::Signature,
+    ) -> Result<u128, VerifyError> {
+        let voting_power = verifier
+            .get_voting_power(&validator)
+            .ok_or(VerifyError::UnknownAuthor)?;
+        if self.signatures.signatures().contains_key(&validator) {
+            return Err(VerifyError::DuplicateAuthor);
+        }
+        self.signatures.add_signature(validator, signature);
+        self.voting_power += voting_power as u128;
+        Ok(self.voting_power)
+    }
+
+    /// Removes the signature of the validator (if any), e.g., once it failed verification
+    pub fn remove_signature(&mut self, verifier: &ValidatorVerifier, validator: AccountAddress) {
+        if self.signatures.signatures.remove(&validator).is_some() {
+            let voting_power = verifier.get_voting_power(&validator).unwrap_or_default();
+            self.voting_power -= voting_power as u128;
+        }
+    }
+
+    /// Returns the voting power of the validators that signed
+    pub fn voting_power(&self) -> u128 {
+        self.voting_power
+    }
+
+    /// Returns the voting power still needed to reach the quorum voting power of the verifier,
+    /// i.e., zero once a quorum signed
+    pub fn remaining_power_needed(&self, verifier: &ValidatorVerifier) -> u128 {
+        verifier
+            .quorum_voting_power()
+            .saturating_sub(self.voting_power)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    pub fn partial_signatures(&self) -> &PartialSignatures {
+        &self.signatures
+    }
+
+    pub fn into_partial_signatures(self) -> PartialSignatures {
+        self.signatures
+    }
+}
+
+/// Aggregates the signatures of validators on the same message as they arrive (e.g., the votes
+/// collected by a round manager), so that checking for a quorum is cheap, and the multi-signature
+/// is ready as soon as a quorum is reached. Unlike `PartialSignatures`, the signatures can't be
+/// removed once aggregated, so they must be verified before being added.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SignatureAggregator {
+    validator_bitmask: BitVec,
+    aggregated_signature: Option<bls12381::Signature>,
+    voting_power: u128,
+    quorum_voting_power: u128,
+}
+
+impl SignatureAggregator {
+    /// Creates an empty aggregator for the signatures of the validators of the verifier
+    pub fn new(verifier: &ValidatorVerifier) -> Self {
+        Self {
+            validator_bitmask: BitVec::with_num_bits(verifier.len() as u16),
+            aggregated_signature: None,
+            voting_power: 0,
+            quorum_voting_power: verifier.quorum_voting_power(),
+        }
+    }
+
+    /// Aggregates the (verified) signature of the validator, and returns the accumulated voting
+    /// power. Signatures of validators that already signed are ignored.
+    pub fn add_signature(
+        &mut self,
+        verifier: &ValidatorVerifier,
+        validator: AccountAddress,
+        signature: &bls12381::Signature,
+    ) -> Result<u128, VerifyError> {
+        let index = *verifier
+            .address_to_validator_index()
+            .get(&validator)
+            .ok_or(VerifyError::UnknownAuthor)? as u16;
+        if self.validator_bitmask.is_set(index) {
+            return Ok(self.voting_power);
+        }
+        let voting_power = verifier
+            .get_voting_power(&validator)
+            .ok_or(VerifyError::UnknownAuthor)?;
+
+        let aggregated_signature = match self.aggregated_signature.take() {
+            Some(aggregated_signature) => {
+                bls12381::Signature::aggregate(vec![aggregated_signature, signature.clone()])
+                    .map_err(|_| {
+                        VerifyError::FailedToAggregateSignature(find_malformed_signature([(
+                            &validator, signature,
+                        )]))
+                    })?
+            },
+            None => signature.clone(),
+        };
+        self.aggregated_signature = Some(aggregated_signature);
+        self.validator_bitmask.set(index);
+        self.voting_power += voting_power as u128;
+        Ok(self.voting_power)
+    }
+
+    /// Returns the voting power of the validators whose signatures were aggregated
+    pub fn voting_power(&self) -> u128 {
+        self.voting_power
+    }
+
+    /// Returns true iff the aggregated signatures reach the quorum voting power of the verifier
+    pub fn has_quorum(&self) -> bool {
+        self.voting_power >= self.quorum_voting_power
+    }
+
+    pub fn get_num_voters(&self) -> usize {
+        self.validator_bitmask.count_ones() as usize
+    }
+
+    /// Returns the multi-signature of the signatures aggregated so far
+    pub fn multi_signature(&self) -> AggregateSignature {
+        AggregateSignature::new(
+            self.validator_bitmask.clone(),
+            self.aggregated_signature.clone(),
+        )
+    }
+}
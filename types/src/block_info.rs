@@ -106,7 +106,9 @@ impl BlockInfo {
             timestamp_usecs: GENESIS_TIMESTAMP_USECS,
             next_epoch_state: Some(EpochState {
                 epoch: 1,
-                verifier: (&validator_set).into(),
+                verifier: (&validator_set)
+                    .try_into()
+                    .expect("The genesis validator set is valid"),
             }),
         }
     }
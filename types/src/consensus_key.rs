@@ -0,0 +1,238 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Consensus public keys of validators, tagged with their signature scheme.
+//!
+//! Validators only have bls12381 consensus keys today. To allow migrating to another scheme
+//! without breaking the wire format of the `ValidatorVerifier`, the keys are wrapped in a
+//! [`ConsensusPublicKey`], which serializes bls12381 keys exactly as before (i.e., as the bare
+//! key), but also decodes keys tagged with their scheme:
+//!
+//! - In binary formats (e.g., BCS), a tagged key is the tag of its scheme followed by the key.
+//!   Bare keys are recognized by their length, which is never the length of a tagged key.
+//! - In human-readable formats, a tagged key is the name of its scheme and the hex-encoded key,
+//!   separated by a colon (e.g., `bls12381:0x...`).
+//!
+//! Once all nodes decode tagged keys, new schemes can be added (and encoded with their tag).
+
+use anyhow::{anyhow, bail, Result};
+use aptos_crypto::{bls12381, ValidCryptoMaterialStringExt};
+#[cfg(any(test, feature = "fuzzing"))]
+use proptest_derive::Arbitrary;
+use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::{convert::TryFrom, fmt};
+
+/// The signature schemes of consensus keys
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(u8)]
+pub enum ConsensusKeyScheme {
+    Bls12381 = 0,
+}
+
+impl ConsensusKeyScheme {
+    /// Returns the tag of the scheme in binary formats
+    pub fn tag(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the name of the scheme in human-readable formats
+    pub fn name(self) -> &'static str {
+        match self {
+            ConsensusKeyScheme::Bls12381 => "bls12381",
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(ConsensusKeyScheme::Bls12381),
+            _ => bail!("Unsupported consensus key scheme tag: {}", tag),
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "bls12381" => Ok(ConsensusKeyScheme::Bls12381),
+            _ => bail!("Unsupported consensus key scheme: {}", name),
+        }
+    }
+}
+
+impl fmt::Display for ConsensusKeyScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The consensus public key of a validator
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub enum ConsensusPublicKey {
+    Bls12381(bls12381::PublicKey),
+}
+
+impl ConsensusPublicKey {
+    pub fn scheme(&self) -> ConsensusKeyScheme {
+        match self {
+            ConsensusPublicKey::Bls12381(_) => ConsensusKeyScheme::Bls12381,
+        }
+    }
+
+    /// Returns the bls12381 key, if it's a bls12381 key
+    pub fn as_bls12381(&self) -> Option<&bls12381::PublicKey> {
+        match self {
+            ConsensusPublicKey::Bls12381(public_key) => Some(public_key),
+        }
+    }
+
+    /// Returns the key in binary formats: bls12381 keys are bare, other keys are tagged
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            ConsensusPublicKey::Bls12381(public_key) => public_key.to_bytes().to_vec(),
+        }
+    }
+
+    /// Returns the key without the tag of its scheme
+    pub(crate) fn to_raw_bytes(&self) -> Vec<u8> {
+        match self {
+            ConsensusPublicKey::Bls12381(public_key) => public_key.to_bytes().to_vec(),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() == bls12381::PublicKey::LENGTH {
+            return Self::from_scheme_bytes(ConsensusKeyScheme::Bls12381, bytes);
+        }
+        let (tag, key) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty consensus public key"))?;
+        Self::from_scheme_bytes(ConsensusKeyScheme::from_tag(*tag)?, key)
+    }
+
+    /// Returns the key in human-readable formats: bls12381 keys are bare, other keys are tagged
+    fn to_encoded_string(&self) -> Result<String> {
+        match self {
+            ConsensusPublicKey::Bls12381(public_key) => public_key.to_encoded_string(),
+        }
+    }
+
+    fn from_encoded_string(encoded: &str) -> Result<Self> {
+        let (scheme, encoded_key) = match encoded.split_once(':') {
+            Some((name, encoded_key)) => (ConsensusKeyScheme::from_name(name)?, encoded_key),
+            None => (ConsensusKeyScheme::Bls12381, encoded),
+        };
+        match scheme {
+            ConsensusKeyScheme::Bls12381 => Ok(ConsensusPublicKey::Bls12381(
+                bls12381::PublicKey::from_encoded_string(encoded_key)?,
+            )),
+        }
+    }
+
+    pub(crate) fn from_scheme_bytes(scheme: ConsensusKeyScheme, bytes: &[u8]) -> Result<Self> {
+        match scheme {
+            ConsensusKeyScheme::Bls12381 => Ok(ConsensusPublicKey::Bls12381(
+                bls12381::PublicKey::try_from(bytes)?,
+            )),
+        }
+    }
+}
+
+impl From<bls12381::PublicKey> for ConsensusPublicKey {
+    fn from(public_key: bls12381::PublicKey) -> Self {
+        ConsensusPublicKey::Bls12381(public_key)
+    }
+}
+
+impl fmt::Display for ConsensusPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConsensusPublicKey::Bls12381(public_key) => write!(f, "{}", public_key),
+        }
+    }
+}
+
+impl Serialize for ConsensusPublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let encoded = self.to_encoded_string().map_err(S::Error::custom)?;
+            serializer.serialize_str(&encoded)
+        } else {
+            // Keep the container name of the bls12381 keys, so that their format doesn't change
+            serializer
+                .serialize_newtype_struct("PublicKey", serde_bytes::Bytes::new(&self.to_bytes()))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ConsensusPublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let encoded = String::deserialize(deserializer)?;
+            Self::from_encoded_string(&encoded).map_err(D::Error::custom)
+        } else {
+            #[derive(Deserialize)]
+            #[serde(rename = "PublicKey")]
+            struct Value(#[serde(with = "serde_bytes")] Vec<u8>);
+
+            let value = Value::deserialize(deserializer)?;
+            Self::from_bytes(&value.0).map_err(D::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::{PrivateKey, Uniform};
+
+    fn bls12381_key() -> bls12381::PublicKey {
+        bls12381::PrivateKey::generate_for_testing().public_key()
+    }
+
+    #[test]
+    fn test_bls12381_keys_keep_their_format() {
+        let public_key = bls12381_key();
+        let consensus_key = ConsensusPublicKey::from(public_key.clone());
+
+        let bytes = bcs::to_bytes(&consensus_key).unwrap();
+        assert_eq!(bytes, bcs::to_bytes(&public_key).unwrap());
+        assert_eq!(
+            bcs::from_bytes::<ConsensusPublicKey>(&bytes).unwrap(),
+            consensus_key
+        );
+
+        let json = serde_json::to_string(&consensus_key).unwrap();
+        assert_eq!(json, serde_json::to_string(&public_key).unwrap());
+        assert_eq!(
+            serde_json::from_str::<ConsensusPublicKey>(&json).unwrap(),
+            consensus_key
+        );
+    }
+
+    #[test]
+    fn test_tagged_keys_are_decoded() {
+        let public_key = bls12381_key();
+        let consensus_key = ConsensusPublicKey::from(public_key.clone());
+
+        let mut tagged_bytes = vec![ConsensusKeyScheme::Bls12381.tag()];
+        tagged_bytes.extend(public_key.to_bytes());
+        let tagged_bytes = bcs::to_bytes(&serde_bytes::ByteBuf::from(tagged_bytes)).unwrap();
+        assert_eq!(
+            bcs::from_bytes::<ConsensusPublicKey>(&tagged_bytes).unwrap(),
+            consensus_key
+        );
+
+        let tagged_json = format!("\"bls12381:{}\"", public_key.to_encoded_string().unwrap());
+        assert_eq!(
+            serde_json::from_str::<ConsensusPublicKey>(&tagged_json).unwrap(),
+            consensus_key
+        );
+
+        // Unknown schemes are rejected
+        let mut unknown_bytes = vec![7];
+        unknown_bytes.extend(public_key.to_bytes());
+        let unknown_bytes = bcs::to_bytes(&serde_bytes::ByteBuf::from(unknown_bytes)).unwrap();
+        assert!(bcs::from_bytes::<ConsensusPublicKey>(&unknown_bytes).is_err());
+        let unknown_json = format!("\"ed448:{}\"", public_key.to_encoded_string().unwrap());
+        assert!(serde_json::from_str::<ConsensusPublicKey>(&unknown_json).is_err());
+    }
+}
@@ -285,5 +285,39 @@ mod tests {
         // Waypoint after proof range will fail to verify
         let proof_8 = EpochChangeProof::new(valid_ledger_info[..1].to_vec(), /* more */ false);
         assert!(proof_8.verify(&waypoint_for_3_to_4).is_err());
+
+        // Validator verifiers ratchet through the same proofs, up to the last epoch
+        let last_epoch_state = valid_ledger_info[9].ledger_info().next_epoch_state();
+        assert_eq!(
+            validator_verifier[0]
+                .ratchet_epoch_change_proof(all_epoch[0], &proof_1)
+                .ok(),
+            last_epoch_state
+        );
+        assert_eq!(
+            validator_verifier[4]
+                .ratchet_epoch_change_proof(all_epoch[4], &proof_1)
+                .ok(),
+            last_epoch_state
+        );
+        assert_eq!(
+            validator_verifier[2]
+                .ratchet_epoch_change_proof(all_epoch[2], &proof_2)
+                .unwrap(),
+            valid_ledger_info[4]
+                .ledger_info()
+                .next_epoch_state()
+                .unwrap()
+        );
+        for (epoch, proof) in [
+            (all_epoch[0], &proof_3),
+            (all_epoch[3], &proof_4),
+            (all_epoch[9], &proof_5),
+            (all_epoch[0], &proof_6),
+            (all_epoch[9], &proof_8),
+        ] {
+            let verifier = &validator_verifier[(epoch - 1) as usize];
+            assert!(verifier.ratchet_epoch_change_proof(epoch, proof).is_err());
+        }
     }
 }
@@ -0,0 +1,208 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Evidence of equivocation, i.e., of a validator signing two different messages where it may
+//! only sign one (e.g., two proposals or two votes for the same round).
+
+use crate::{
+    account_address::AccountAddress,
+    block_info::Round,
+    validator_verifier::{ValidatorVerifier, VerifyError},
+};
+use aptos_crypto::{bls12381, hash::CryptoHash};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A message that a validator may sign at most once per round of an epoch
+pub trait RoundMessage: CryptoHash + Serialize {
+    fn epoch(&self) -> u64;
+
+    fn round(&self) -> Round;
+}
+
+/// Errors possible when checking an equivocation proof.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EquivocationError {
+    #[error(
+        "The messages are for different rounds: ({}, {}) and ({}, {})",
+        first_epoch,
+        first_round,
+        second_epoch,
+        second_round
+    )]
+    /// The messages aren't for the same round of the same epoch
+    DifferentRounds {
+        first_epoch: u64,
+        first_round: Round,
+        second_epoch: u64,
+        second_round: Round,
+    },
+    #[error("The messages are identical")]
+    /// The author signed the same message twice, which is allowed
+    IdenticalMessages,
+    #[error("Invalid signature: {0}")]
+    /// One of the signatures isn't a valid signature of the author
+    InvalidSignature(VerifyError),
+}
+
+/// A proof that a validator signed two different messages for the same round. It can be
+/// verified by anyone with the verifier of the epoch, so it's suitable for logging and sharing
+/// (e.g., for slashing).
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EquivocationProof<T> {
+    author: AccountAddress,
+    first_message: T,
+    first_signature: bls12381::Signature,
+    second_message: T,
+    second_signature: bls12381::Signature,
+}
+
+impl<T: RoundMessage> EquivocationProof<T> {
+    /// Returns the proof that the author signed both messages, if they're different messages for
+    /// the same round, and both signatures are valid signatures of the author.
+    pub fn new(
+        verifier: &ValidatorVerifier,
+        author: AccountAddress,
+        first: (T, bls12381::Signature),
+        second: (T, bls12381::Signature),
+    ) -> Result<Self, EquivocationError> {
+        let (first_message, first_signature) = first;
+        let (second_message, second_signature) = second;
+        let proof = Self {
+            author,
+            first_message,
+            first_signature,
+            second_message,
+            second_signature,
+        };
+        proof.verify(verifier)?;
+        Ok(proof)
+    }
+
+    /// Verifies the proof, e.g., after it's deserialized
+    pub fn verify(&self, verifier: &ValidatorVerifier) -> Result<(), EquivocationError> {
+        let (first, second) = (&self.first_message, &self.second_message);
+        if first.epoch() != second.epoch() || first.round() != second.round() {
+            return Err(EquivocationError::DifferentRounds {
+                first_epoch: first.epoch(),
+                first_round: first.round(),
+                second_epoch: second.epoch(),
+                second_round: second.round(),
+            });
+        }
+        if first.hash() == second.hash() {
+            return Err(EquivocationError::IdenticalMessages);
+        }
+        verifier
+            .verify(self.author, first, &self.first_signature)
+            .and_then(|_| verifier.verify(self.author, second, &self.second_signature))
+            .map_err(EquivocationError::InvalidSignature)
+    }
+
+    pub fn author(&self) -> AccountAddress {
+        self.author
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.first_message.epoch()
+    }
+
+    pub fn round(&self) -> Round {
+        self.first_message.round()
+    }
+
+    pub fn messages(&self) -> (&T, &T) {
+        (&self.first_message, &self.second_message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator_verifier::random_validator_verifier;
+    use aptos_crypto_derive::{BCSCryptoHash, CryptoHasher};
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, CryptoHasher, BCSCryptoHash)]
+    struct TestVote {
+        epoch: u64,
+        round: Round,
+        value: u64,
+    }
+
+    impl RoundMessage for TestVote {
+        fn epoch(&self) -> u64 {
+            self.epoch
+        }
+
+        fn round(&self) -> Round {
+            self.round
+        }
+    }
+
+    #[test]
+    fn test_equivocation_proof() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(2, None, false);
+        let signer = &validator_signers[0];
+        let signed_vote = |round, value| {
+            let vote = TestVote {
+                epoch: 1,
+                round,
+                value,
+            };
+            let signature = signer.sign(&vote).unwrap();
+            (vote, signature)
+        };
+
+        let proof = EquivocationProof::new(
+            &validator_verifier,
+            signer.author(),
+            signed_vote(5, 1),
+            signed_vote(5, 2),
+        )
+        .unwrap();
+        assert_eq!(
+            (proof.author(), proof.epoch(), proof.round()),
+            (signer.author(), 1, 5)
+        );
+
+        // The proof can be verified once deserialized
+        let proof: EquivocationProof<TestVote> =
+            bcs::from_bytes(&bcs::to_bytes(&proof).unwrap()).unwrap();
+        assert_eq!(proof.verify(&validator_verifier), Ok(()));
+
+        assert_eq!(
+            EquivocationProof::new(
+                &validator_verifier,
+                signer.author(),
+                signed_vote(5, 1),
+                signed_vote(6, 2),
+            ),
+            Err(EquivocationError::DifferentRounds {
+                first_epoch: 1,
+                first_round: 5,
+                second_epoch: 1,
+                second_round: 6,
+            })
+        );
+        assert_eq!(
+            EquivocationProof::new(
+                &validator_verifier,
+                signer.author(),
+                signed_vote(5, 1),
+                signed_vote(5, 1),
+            ),
+            Err(EquivocationError::IdenticalMessages)
+        );
+        assert_eq!(
+            EquivocationProof::new(
+                &validator_verifier,
+                validator_signers[1].author(),
+                signed_vote(5, 1),
+                signed_vote(5, 2),
+            ),
+            Err(EquivocationError::InvalidSignature(
+                VerifyError::InvalidMultiSignature
+            ))
+        );
+    }
+}
@@ -390,34 +390,23 @@ impl Arbitrary for LedgerInfoWithV0 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{validator_signer::ValidatorSigner, validator_verifier::ValidatorConsensusInfo};
+    use crate::validator_verifier::ValidatorVerifierBuilder;
 
     #[test]
     fn test_signatures_hash() {
         let ledger_info = LedgerInfo::new(BlockInfo::empty(), HashValue::random());
 
-        const NUM_SIGNERS: u8 = 7;
-        // Generate NUM_SIGNERS random signers.
-        let validator_signers: Vec<ValidatorSigner> = (0..NUM_SIGNERS)
-            .map(|i| ValidatorSigner::random([i; 32]))
-            .collect();
+        const NUM_SIGNERS: usize = 7;
+        // Let's assume our verifier needs to satisfy at least 5 quorum voting power
+        let (validator_signers, validator_verifier) = ValidatorVerifierBuilder::new(false)
+            .add_validators(NUM_SIGNERS, 1)
+            .quorum_voting_power(5)
+            .build();
         let mut partial_sig = PartialSignatures::empty();
-        let mut validator_infos = vec![];
-
         for validator in validator_signers.iter() {
-            validator_infos.push(ValidatorConsensusInfo::new(
-                validator.author(),
-                validator.public_key(),
-                1,
-            ));
             partial_sig.add_signature(validator.author(), validator.sign(&ledger_info).unwrap());
         }
 
-        // Let's assume our verifier needs to satisfy at least 5 quorum voting power
-        let validator_verifier =
-            ValidatorVerifier::new_with_quorum_voting_power(validator_infos, 5)
-                .expect("Incorrect quorum size.");
-
         let mut aggregated_signature = validator_verifier
             .aggregate_signatures(&partial_sig)
             .unwrap();
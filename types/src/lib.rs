@@ -10,9 +10,11 @@ pub mod account_state;
 pub mod block_info;
 pub mod block_metadata;
 pub mod chain_id;
+pub mod consensus_key;
 pub mod contract_event;
 pub mod epoch_change;
 pub mod epoch_state;
+pub mod equivocation;
 pub mod event;
 pub mod governance;
 pub mod ledger_info;
@@ -30,6 +32,7 @@ pub mod staking_contract;
 pub mod state_proof;
 #[cfg(any(test, feature = "fuzzing"))]
 pub mod test_helpers;
+pub mod threshold_verifier;
 pub mod timestamp;
 pub mod transaction;
 pub mod trusted_state;
@@ -122,6 +122,9 @@ pub enum Protocol {
     // probably need to move network wire into its own crate to avoid circular
     // dependency b/w network and types.
     Handshake(u8),
+    // WebSocket framing on top of the TCP transport, for environments where
+    // only HTTP(S) traffic can get through (e.g., corporate proxies, browsers).
+    Ws,
 }
 
 /// A minimally parsed DNS name. We don't really do any checking other than
@@ -281,6 +284,10 @@ impl NetworkAddress {
             if !is_transport_layer(p) {
                 return Err(ParseError::TransportLayerMissing);
             }
+            // The TCP transport may optionally be wrapped in a WebSocket
+            if matches!(iter.clone().next(), Some(Ws)) {
+                iter.next();
+            }
         }
 
         p = iter.next();
@@ -357,7 +364,8 @@ impl NetworkAddress {
     /// `"/dns/<domain>/tcp/<port>"` or
     /// cfg!(test) `"/memory/<port>"`
     ///
-    /// followed by transport upgrade handshake protocols:
+    /// optionally followed by `"/ws"` for tcp transports, and then by transport
+    /// upgrade handshake protocols:
     ///
     /// `"/noise-ik/<pubkey>/handshake/<version>"`
     ///
@@ -612,6 +620,7 @@ impl fmt::Display for Protocol {
                     .expect("ValidCryptoMaterialStringExt::to_encoded_string is infallible")
             ),
             Handshake(version) => write!(f, "/handshake/{}", version),
+            Ws => write!(f, "/ws"),
         }
     }
 }
@@ -642,6 +651,7 @@ impl Protocol {
                 args.next().ok_or(ParseError::UnexpectedEnd)?,
             )?),
             "handshake" => Protocol::Handshake(parse_one(args)?),
+            "ws" => Protocol::Ws,
             unknown => return Err(ParseError::UnknownProtocolType(unknown.to_string())),
         };
         Ok(protocol)
@@ -836,6 +846,15 @@ pub fn parse_tcp(protos: &[Protocol]) -> Option<((String, u16), &[Protocol])> {
     }
 }
 
+/// parse the `&[Protocol]` into the optional `"/ws"` prefix and unparsed
+/// `&[Protocol]` suffix. Returns `None` if the protocols don't start with `"/ws"`.
+pub fn parse_ws(protos: &[Protocol]) -> Option<&[Protocol]> {
+    match protos.split_first() {
+        Some((Protocol::Ws, suffix)) => Some(suffix),
+        _ => None,
+    }
+}
+
 /// parse the `&[Protocol]` into the `"/noise-ik/<pubkey>"` prefix and
 /// unparsed `&[Protocol]` suffix.
 pub fn parse_noise_ik(protos: &[Protocol]) -> Option<(&x25519::PublicKey, &[Protocol])> {
@@ -867,6 +886,7 @@ fn parse_aptosnet_protos(protos: &[Protocol]) -> Option<&[Protocol]> {
     let transport_suffix = parse_ip_tcp(protos)
         .map(|x| x.1)
         .or_else(|| parse_dns_tcp(protos).map(|x| x.1))
+        .map(|suffix| parse_ws(suffix).unwrap_or(suffix))
         .or_else(|| {
             if cfg!(test) {
                 parse_memory(protos).map(|x| x.1)
@@ -964,6 +984,11 @@ mod test {
                 NoiseIK(pubkey),
                 Handshake(5),
             ]),
+            ("/ip4/1.2.3.4/tcp/443/ws", vec![
+                Ip4(Ipv4Addr::new(1, 2, 3, 4)),
+                Tcp(443),
+                Ws,
+            ]),
         ];
 
         for (addr_str, expected_address) in &test_cases {
@@ -991,6 +1016,9 @@ mod test {
             "/ip4/1.1.1.1.",
             "/ip4/1.1.1.1.1",
             "/ip4/1.1.1.999.1",
+            "/ws",
+            "/memory/1234/ws",
+            "/ip4/1.2.3.4/tcp/443/ws/ws",
         ];
 
         for &addr_str in &test_cases {
@@ -1062,6 +1090,29 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_ws() {
+        let pubkey_str = "080e287879c918794170e258bfaddd75acac5b3e350419044655e4983a487120";
+        let addr = NetworkAddress::from_str(&format!(
+            "/dns/example.com/tcp/443/ws/noise-ik/{}/handshake/0",
+            pubkey_str
+        ))
+        .unwrap();
+        assert!(addr.is_aptosnet_addr());
+        assert_eq!(
+            addr.to_string(),
+            format!(
+                "/dns/example.com/tcp/443/ws/noise-ik/0x{}/handshake/0",
+                pubkey_str
+            )
+        );
+
+        let (_, suffix) = parse_dns_tcp(addr.as_slice()).unwrap();
+        let suffix = parse_ws(suffix).unwrap();
+        assert_eq!(suffix, &addr.as_slice()[3..]);
+        assert!(parse_ws(suffix).is_none());
+    }
+
     #[test]
     fn test_find_noise_proto() {
         let pubkey_str = "080e287879c918794170e258bfaddd75acac5b3e350419044655e4983a487120";
@@ -6,7 +6,7 @@ use crate::{
     account_address::{self, AccountAddress},
     account_config::{AccountResource, CoinStoreResource},
     account_state::AccountState,
-    aggregate_signature::PartialSignatures,
+    aggregate_signature::{AggregateSignature, PartialSignatures},
     block_info::{BlockInfo, Round},
     block_metadata::BlockMetadata,
     chain_id::ChainId,
@@ -25,10 +25,11 @@ use crate::{
     },
     validator_info::ValidatorInfo,
     validator_signer::ValidatorSigner,
-    validator_verifier::{ValidatorConsensusInfo, ValidatorVerifier},
+    validator_verifier::{ValidatorConsensusInfo, ValidatorVerifier, ValidatorVerifierBuilder},
     vm_status::VMStatus,
     write_set::{WriteOp, WriteSet, WriteSetMut},
 };
+use aptos_bitvec::BitVec;
 use aptos_crypto::{
     bls12381::{self, bls12381_keys},
     ed25519::{self, Ed25519PrivateKey, Ed25519PublicKey},
@@ -1005,7 +1006,9 @@ impl BlockInfoGen {
                 .collect();
             let next_epoch_state = EpochState {
                 epoch: current_epoch + 1,
-                verifier: (&ValidatorSet::new(next_validator_infos)).into(),
+                verifier: (&ValidatorSet::new(next_validator_infos))
+                    .try_into()
+                    .unwrap(),
             };
 
             universe.get_and_bump_epoch();
@@ -1186,3 +1189,181 @@ impl Arbitrary for ValidatorVerifier {
             .boxed()
     }
 }
+
+impl Arbitrary for PartialSignatures {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (
+            vec(any::<u8>(), 0..64),
+            vec(
+                (any::<AccountAddress>(), bls12381_keys::keypair_strategy()),
+                0..10,
+            ),
+        )
+            .prop_map(|(message, signers)| {
+                PartialSignatures::new(
+                    signers
+                        .into_iter()
+                        .map(|(address, keypair)| {
+                            let signature = keypair.private_key.sign_arbitrary_message(&message);
+                            (address, signature)
+                        })
+                        .collect(),
+                )
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for AggregateSignature {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (vec(any::<bool>(), 0..256), any::<PartialSignatures>())
+            .prop_map(|(voters, partial_signatures)| {
+                // There's no signature if there are no partial signatures to aggregate
+                let signatures = partial_signatures.signatures().values().cloned().collect();
+                AggregateSignature::new(
+                    BitVec::from(voters),
+                    bls12381::Signature::aggregate(signatures).ok(),
+                )
+            })
+            .boxed()
+    }
+}
+
+/// Returns the signers of `num_validators` validators with random voting powers, and the
+/// verifier of their (default) quorum.
+pub fn arb_validator_signers_and_verifier(
+    num_validators: impl Into<SizeRange>,
+) -> impl Strategy<Value = (Vec<ValidatorSigner>, ValidatorVerifier)> {
+    proptest::collection::btree_map(
+        any::<AccountAddress>(),
+        (bls12381_keys::keypair_strategy(), 1..100u64),
+        num_validators,
+    )
+    .prop_map(|validators| {
+        let mut builder = ValidatorVerifierBuilder::new(false);
+        for (address, (keypair, voting_power)) in validators {
+            builder.add_signer(
+                ValidatorSigner::new(address, keypair.private_key),
+                voting_power,
+            );
+        }
+        builder.build()
+    })
+}
+
+/// The ways in which a generated certificate is subtly invalid
+#[derive(Arbitrary, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CertificateFault {
+    /// The voters don't have the quorum voting power
+    TooLittleVotingPower,
+    /// One of the voters signed another ledger info
+    WrongMessage,
+    /// One of the voters is in the bitmask, but its signature isn't aggregated
+    MissingSignature,
+    /// The bitmask has a voter beyond the validators
+    UnknownVoter,
+}
+
+/// A multi-signature of a ledger info by the validators of a verifier, which is valid unless it
+/// has a fault.
+#[derive(Clone, Debug)]
+pub struct MultiSignatureCertificate {
+    pub verifier: ValidatorVerifier,
+    pub ledger_info: LedgerInfo,
+    pub multi_signature: AggregateSignature,
+    pub fault: Option<CertificateFault>,
+}
+
+impl MultiSignatureCertificate {
+    pub fn ledger_info_with_signatures(&self) -> LedgerInfoWithSignatures {
+        LedgerInfoWithSignatures::new(self.ledger_info.clone(), self.multi_signature.clone())
+    }
+}
+
+/// Returns valid certificates, i.e., multi-signatures with the quorum voting power
+pub fn arb_valid_certificate() -> impl Strategy<Value = MultiSignatureCertificate> {
+    arb_certificate(Just(None))
+}
+
+/// Returns certificates with a single fault, which are otherwise valid
+pub fn arb_invalid_certificate() -> impl Strategy<Value = MultiSignatureCertificate> {
+    arb_certificate(any::<CertificateFault>().prop_map(Some))
+}
+
+fn arb_certificate(
+    fault_strategy: impl Strategy<Value = Option<CertificateFault>>,
+) -> impl Strategy<Value = MultiSignatureCertificate> {
+    (
+        arb_validator_signers_and_verifier(1..20),
+        any::<LedgerInfo>(),
+        fault_strategy,
+        any::<Index>(),
+    )
+        .prop_map(|((signers, verifier), ledger_info, fault, faulty_index)| {
+            let faulty_signer = &signers[faulty_index.index(signers.len())];
+            let mut partial_signatures = PartialSignatures::new(
+                signers
+                    .iter()
+                    .map(|signer| (signer.author(), signer.sign(&ledger_info).unwrap()))
+                    .collect(),
+            );
+            let multi_signature = match fault {
+                None => verifier.aggregate_signatures(&partial_signatures).unwrap(),
+                Some(CertificateFault::TooLittleVotingPower) => {
+                    // Remove voters until the remaining ones are below the quorum
+                    for signer in &signers {
+                        let authors = partial_signatures.signatures().keys();
+                        if verifier.check_voting_power(authors).is_err() {
+                            break;
+                        }
+                        partial_signatures.remove_signature(signer.author());
+                    }
+                    if partial_signatures.is_empty() {
+                        AggregateSignature::new(BitVec::with_num_bits(verifier.len() as u16), None)
+                    } else {
+                        verifier.aggregate_signatures(&partial_signatures).unwrap()
+                    }
+                },
+                Some(CertificateFault::WrongMessage) => {
+                    let other_ledger_info = LedgerInfo::new(
+                        ledger_info.commit_info().clone(),
+                        HashValue::sha3_256_of(ledger_info.consensus_data_hash().as_ref()),
+                    );
+                    partial_signatures.add_signature(
+                        faulty_signer.author(),
+                        faulty_signer.sign(&other_ledger_info).unwrap(),
+                    );
+                    verifier.aggregate_signatures(&partial_signatures).unwrap()
+                },
+                Some(CertificateFault::MissingSignature) => {
+                    let voters = verifier
+                        .aggregate_signatures(&partial_signatures)
+                        .unwrap()
+                        .get_voters_bitvec()
+                        .clone();
+                    partial_signatures.remove_signature(faulty_signer.author());
+                    let signatures = partial_signatures.signatures().values().cloned().collect();
+                    AggregateSignature::new(voters, bls12381::Signature::aggregate(signatures).ok())
+                },
+                Some(CertificateFault::UnknownVoter) => {
+                    let multi_signature =
+                        verifier.aggregate_signatures(&partial_signatures).unwrap();
+                    let mut voters = multi_signature.get_voters_bitvec().clone();
+                    voters.set(verifier.len() as u16);
+                    AggregateSignature::new(voters, multi_signature.sig().clone())
+                },
+            };
+            MultiSignatureCertificate {
+                verifier,
+                ledger_info,
+                multi_signature,
+                fault,
+            }
+        })
+}
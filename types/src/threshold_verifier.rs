@@ -0,0 +1,265 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verification of threshold signatures (e.g., for a randomness beacon), the companion of the
+//! `ValidatorVerifier` for groups whose secret key is shared among the players (e.g., by a DKG),
+//! see `aptos_crypto::bls12381::bls12381_threshold`. Unlike the multi-signatures of validators,
+//! a group signature doesn't identify its signers: it's an ordinary signature under the public
+//! key of the group, whichever `threshold` players signed.
+
+use crate::{
+    account_address::AccountAddress,
+    aggregate_signature::PartialSignatures,
+    validator_verifier::{find_malformed_signature, VerifyError},
+};
+use anyhow::{ensure, Result};
+use aptos_crypto::{
+    bls12381,
+    bls12381::bls12381_threshold::{self, PlayerIndex},
+    hash::CryptoHash,
+    Signature,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// A player of a threshold scheme, i.e., the holder of a share of the secret key of the group
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ThresholdPlayerInfo {
+    pub address: AccountAddress,
+    /// The index of the player's share, see `PlayerIndex`
+    pub player_index: PlayerIndex,
+    /// The public key of the player's share, under which its signature shares are verified
+    pub share_public_key: bls12381::PublicKey,
+}
+
+impl ThresholdPlayerInfo {
+    pub fn new(
+        address: AccountAddress,
+        player_index: PlayerIndex,
+        share_public_key: bls12381::PublicKey,
+    ) -> Self {
+        Self {
+            address,
+            player_index,
+            share_public_key,
+        }
+    }
+}
+
+/// Verifies the signature shares of the players of a group, combines them into the signature of
+/// the group once there are `threshold` of them, and verifies the signatures of the group.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ThresholdVerifier {
+    group_public_key: bls12381::PublicKey,
+    players: BTreeMap<AccountAddress, ThresholdPlayerInfo>,
+    threshold: usize,
+}
+
+impl ThresholdVerifier {
+    /// Fails unless the addresses and the (non-zero) player indices are unique, and the threshold
+    /// is reachable (i.e., between 1 and the number of players).
+    pub fn new(
+        group_public_key: bls12381::PublicKey,
+        players: Vec<ThresholdPlayerInfo>,
+        threshold: usize,
+    ) -> Result<Self> {
+        ensure!(
+            (1..=players.len()).contains(&threshold),
+            "The threshold {} isn't reachable by {} players",
+            threshold,
+            players.len()
+        );
+        let mut player_indices = HashSet::new();
+        for player in &players {
+            ensure!(
+                player.player_index != 0 && player_indices.insert(player.player_index),
+                "Invalid or duplicate player index {}",
+                player.player_index
+            );
+        }
+        let num_players = players.len();
+        let players: BTreeMap<_, _> = players
+            .into_iter()
+            .map(|player| (player.address, player))
+            .collect();
+        ensure!(players.len() == num_players, "Duplicate player addresses");
+        Ok(Self {
+            group_public_key,
+            players,
+            threshold,
+        })
+    }
+
+    pub fn group_public_key(&self) -> &bls12381::PublicKey {
+        &self.group_public_key
+    }
+
+    /// Returns the number of signature shares needed for a group signature
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn get_player_info(&self, author: &AccountAddress) -> Option<&ThresholdPlayerInfo> {
+        self.players.get(author)
+    }
+
+    /// Returns the number of players.
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    /// Verify the correctness of a signature share of a message by a known player.
+    pub fn verify_share<T: Serialize + CryptoHash>(
+        &self,
+        author: AccountAddress,
+        message: &T,
+        signature: &bls12381::Signature,
+    ) -> std::result::Result<(), VerifyError> {
+        let player = self
+            .get_player_info(&author)
+            .ok_or(VerifyError::UnknownAuthor)?;
+        signature
+            .verify(message, &player.share_public_key)
+            .map_err(|_| VerifyError::InvalidSignatureShare)
+    }
+
+    /// Combines (the first `threshold` of) the signature shares of known players into the signature
+    /// of the group. The shares of unknown players are ignored. As with the aggregation of
+    /// multi-signatures, the shares aren't verified, so an invalid share results in an invalid
+    /// group signature.
+    pub fn combine_shares(
+        &self,
+        partial_signatures: &PartialSignatures,
+    ) -> std::result::Result<bls12381::Signature, VerifyError> {
+        let known_shares: Vec<_> = partial_signatures
+            .signatures()
+            .iter()
+            .filter_map(|(author, signature)| {
+                self.get_player_info(author)
+                    .map(|player| (author, player.player_index, signature))
+            })
+            .take(self.threshold)
+            .collect();
+        if known_shares.len() < self.threshold {
+            return Err(VerifyError::TooFewShares {
+                num_shares: known_shares.len(),
+                threshold: self.threshold,
+            });
+        }
+        let shares: Vec<_> = known_shares
+            .iter()
+            .map(|(_, player_index, signature)| (*player_index, *signature))
+            .collect();
+        bls12381_threshold::combine_signature_shares(&shares).map_err(|_| {
+            VerifyError::FailedToAggregateSignature(find_malformed_signature(
+                known_shares
+                    .iter()
+                    .map(|(author, _, signature)| (*author, *signature)),
+            ))
+        })
+    }
+
+    /// Verify the correctness of a signature of a message by the group.
+    pub fn verify_group_signature<T: Serialize + CryptoHash>(
+        &self,
+        message: &T,
+        signature: &bls12381::Signature,
+    ) -> std::result::Result<(), VerifyError> {
+        signature
+            .verify(message, &self.group_public_key)
+            .map_err(|_| VerifyError::InvalidMultiSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator_signer::ValidatorSigner;
+    use aptos_crypto::test_utils::TestAptosCrypto;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_threshold_verifier() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let (group_key, shares) = bls12381_threshold::deal_shares_for_testing(2, 3, &mut rng);
+        let signers: Vec<_> = shares
+            .into_iter()
+            .map(|share| ValidatorSigner::new(AccountAddress::random(), share))
+            .collect();
+        let players = signers
+            .iter()
+            .enumerate()
+            .map(|(i, signer)| {
+                ThresholdPlayerInfo::new(signer.author(), i as u64 + 1, signer.public_key())
+            })
+            .collect::<Vec<_>>();
+        let threshold_verifier =
+            ThresholdVerifier::new(bls12381::PublicKey::from(&group_key), players.clone(), 2)
+                .unwrap();
+
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut partial_signatures = PartialSignatures::empty();
+        for signer in &signers {
+            let signature = signer.sign(&dummy_struct).unwrap();
+            assert_eq!(
+                threshold_verifier.verify_share(signer.author(), &dummy_struct, &signature),
+                Ok(())
+            );
+            partial_signatures.add_signature(signer.author(), signature);
+        }
+        let unknown_signer = ValidatorSigner::random([7; 32]);
+        let unknown_signature = unknown_signer.sign(&dummy_struct).unwrap();
+        assert_eq!(
+            threshold_verifier.verify_share(
+                unknown_signer.author(),
+                &dummy_struct,
+                &unknown_signature
+            ),
+            Err(VerifyError::UnknownAuthor)
+        );
+        assert_eq!(
+            threshold_verifier.verify_share(signers[0].author(), &dummy_struct, &unknown_signature),
+            Err(VerifyError::InvalidSignatureShare)
+        );
+
+        // Any 2 shares combine into the signature of the group
+        let group_signature = threshold_verifier
+            .combine_shares(&partial_signatures)
+            .unwrap();
+        assert_eq!(
+            threshold_verifier.verify_group_signature(&dummy_struct, &group_signature),
+            Ok(())
+        );
+        partial_signatures.remove_signature(signers[0].author());
+        assert_eq!(
+            threshold_verifier.combine_shares(&partial_signatures),
+            Ok(group_signature.clone())
+        );
+
+        // The shares of unknown players are ignored, but don't count towards the threshold
+        partial_signatures.add_signature(unknown_signer.author(), unknown_signature);
+        assert_eq!(
+            threshold_verifier.combine_shares(&partial_signatures),
+            Ok(group_signature)
+        );
+        partial_signatures.remove_signature(signers[1].author());
+        assert_eq!(
+            threshold_verifier.combine_shares(&partial_signatures),
+            Err(VerifyError::TooFewShares {
+                num_shares: 1,
+                threshold: 2,
+            })
+        );
+
+        // Unreachable thresholds and duplicate indices are rejected
+        let group_public_key = threshold_verifier.group_public_key().clone();
+        assert!(ThresholdVerifier::new(group_public_key.clone(), players.clone(), 4).is_err());
+        let mut duplicate_players = players;
+        duplicate_players[1].player_index = 1;
+        assert!(ThresholdVerifier::new(group_public_key, duplicate_players, 2).is_err());
+    }
+}
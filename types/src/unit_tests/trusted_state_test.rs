@@ -11,7 +11,7 @@ use crate::{
     transaction::Version,
     trusted_state::{TrustedState, TrustedStateChange, TrustedStateHasher},
     validator_signer::ValidatorSigner,
-    validator_verifier::{random_validator_verifier, ValidatorConsensusInfo, ValidatorVerifier},
+    validator_verifier::{random_validator_verifier, ValidatorVerifier, ValidatorVerifierBuilder},
     waypoint::Waypoint,
 };
 use aptos_crypto::hash::{CryptoHash, CryptoHasher, HashValue};
@@ -53,20 +53,13 @@ fn arb_validator_sets(
 /// Convert a slice of `ValidatorSigner` (includes the private signing key) into
 /// the public-facing `EpochState` type (just the public key).
 fn into_epoch_state(epoch: u64, signers: &[ValidatorSigner]) -> EpochState {
+    let mut builder = ValidatorVerifierBuilder::new(false);
+    for signer in signers {
+        builder.add_signer(signer.clone(), 1 /* voting power */);
+    }
     EpochState {
         epoch,
-        verifier: ValidatorVerifier::new(
-            signers
-                .iter()
-                .map(|signer| {
-                    ValidatorConsensusInfo::new(
-                        signer.author(),
-                        signer.public_key(),
-                        1, /* voting power */
-                    )
-                })
-                .collect(),
-        ),
+        verifier: builder.build().1,
     }
 }
 
@@ -6,20 +6,43 @@ use crate::validator_signer::ValidatorSigner;
 use crate::{
     account_address::AccountAddress,
     aggregate_signature::{AggregateSignature, PartialSignatures},
+    consensus_key::{ConsensusKeyScheme, ConsensusPublicKey},
+    epoch_change::EpochChangeProof,
+    epoch_state::EpochState,
+    ledger_info::LedgerInfoWithSignatures,
     on_chain_config::ValidatorSet,
 };
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use aptos_bitvec::BitVec;
-use aptos_crypto::{bls12381, bls12381::PublicKey, hash::CryptoHash, Signature, VerifyingKey};
+use aptos_crypto::{
+    bls12381, bls12381::PublicKey, hash::CryptoHash, signing_message, HashValue, Signature,
+    VerifyingKey,
+};
+use aptos_infallible::Mutex;
+use lru::LruCache;
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest_derive::Arbitrary;
+use rayon::prelude::*;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
     fmt,
+    sync::Arc,
 };
 use thiserror::Error;
 
+/// The number of public keys (or signatures) aggregated by each task of a parallel aggregation
+const PARALLEL_AGGREGATION_CHUNK_SIZE: usize = 32;
+
+/// The number of voter bitmasks whose aggregated public key is cached by a `ValidatorVerifier`
+const AGGREGATED_KEY_CACHE_SIZE: usize = 64;
+
+/// The current version of the versioned format of a `ValidatorVerifier`, see
+/// `ValidatorVerifier::to_versioned_bytes`
+pub const VALIDATOR_VERIFIER_FORMAT_VERSION: u8 = 2;
+
 /// Errors possible during signature verification.
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum VerifyError {
@@ -46,16 +69,99 @@ pub enum VerifyError {
     InvalidAggregatedSignature,
     #[error("Inconsistent Block Info")]
     InconsistentBlockInfo,
-    #[error("Failed to aggregate public keys")]
-    FailedToAggregatePubKey,
-    #[error("Failed to aggregate signatures")]
-    FailedToAggregateSignature,
+    #[error("Failed to aggregate public keys (malformed key of {:?})", _0)]
+    /// The public keys couldn't be aggregated, because of the malformed key of the given
+    /// validator (if it could be determined)
+    FailedToAggregatePubKey(Option<AccountAddress>),
+    #[error("Failed to aggregate signatures (malformed signature of {:?})", _0)]
+    /// The signatures couldn't be aggregated, because of the malformed signature of the given
+    /// author (if it could be determined), which can be dropped before aggregating again
+    FailedToAggregateSignature(Option<AccountAddress>),
     #[error("Failed to verify multi-signature")]
     FailedToVerifyMultiSignature,
     #[error("Invalid bitvec from the multi-signature")]
     InvalidBitVec,
     #[error("Failed to verify aggreagated signature")]
     FailedToVerifyAggregatedSignature,
+    #[error("Failed to batch verify signatures")]
+    /// At least one of the batch verified signatures is invalid
+    FailedToBatchVerify,
+    #[error("Author has already signed")]
+    /// The author already contributed a signature
+    DuplicateAuthor,
+    #[error("Invalid quorum policy")]
+    /// The quorum policy can't be satisfied by the validators
+    InvalidQuorumPolicy,
+    #[error("Invalid signatures of authors: {:?}", _0)]
+    /// The signatures of these authors are invalid
+    InvalidSignatures(Vec<AccountAddress>),
+    #[error(
+        "The number of signature shares ({}) is less than the threshold ({})",
+        num_shares,
+        threshold
+    )]
+    /// There aren't enough signature shares to combine into a threshold signature
+    TooFewShares { num_shares: usize, threshold: usize },
+    #[error("Signature share is invalid")]
+    /// The signature share of a player of a threshold scheme is invalid
+    InvalidSignatureShare,
+}
+
+/// The policy that determines the voting power needed for a quorum
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuorumPolicy {
+    /// More than the given fraction of the total voting power, e.g., more than two thirds (i.e.,
+    /// `2f + 1`) or more than one third (i.e., `f + 1`). The fraction must be less than one.
+    Fraction { numerator: u128, denominator: u128 },
+    /// At least the given voting power
+    Absolute(u128),
+}
+
+impl QuorumPolicy {
+    /// More than one third of the total voting power (`f + 1`), i.e., at least one honest voter
+    pub const ONE_THIRD: QuorumPolicy = QuorumPolicy::Fraction {
+        numerator: 1,
+        denominator: 3,
+    };
+    /// More than two thirds of the total voting power (`2f + 1`), i.e., the default quorum
+    pub const TWO_THIRDS: QuorumPolicy = QuorumPolicy::Fraction {
+        numerator: 2,
+        denominator: 3,
+    };
+
+    /// Returns the voting power needed for a quorum out of the total voting power
+    pub fn quorum_voting_power(&self, total_voting_power: u128) -> Result<u128> {
+        match *self {
+            QuorumPolicy::Fraction {
+                numerator,
+                denominator,
+            } => {
+                ensure!(
+                    numerator < denominator,
+                    "The quorum fraction must be less than one: {}/{}",
+                    numerator,
+                    denominator
+                );
+                Ok(total_voting_power * numerator / denominator + 1)
+            },
+            QuorumPolicy::Absolute(quorum_voting_power) => {
+                ensure!(
+                    quorum_voting_power <= total_voting_power,
+                    "Quorum voting power is greater than the sum of all voting power of authors: \
+                     {}, quorum_size: {}.",
+                    total_voting_power,
+                    quorum_voting_power
+                );
+                Ok(quorum_voting_power)
+            },
+        }
+    }
+}
+
+impl Default for QuorumPolicy {
+    fn default() -> Self {
+        QuorumPolicy::TWO_THIRDS
+    }
 }
 
 /// Helper struct to manage validator information for validation
@@ -63,12 +169,21 @@ pub enum VerifyError {
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub struct ValidatorConsensusInfo {
     address: AccountAddress,
-    public_key: PublicKey,
+    /// Serialized as the bare bls12381 key, see `ConsensusPublicKey`
+    public_key: ConsensusPublicKey,
     voting_power: u64,
 }
 
 impl ValidatorConsensusInfo {
     pub fn new(address: AccountAddress, public_key: PublicKey, voting_power: u64) -> Self {
+        Self::new_with_consensus_key(address, public_key.into(), voting_power)
+    }
+
+    pub fn new_with_consensus_key(
+        address: AccountAddress,
+        public_key: ConsensusPublicKey,
+        voting_power: u64,
+    ) -> Self {
         ValidatorConsensusInfo {
             address,
             public_key,
@@ -76,11 +191,200 @@ impl ValidatorConsensusInfo {
         }
     }
 
+    pub fn address(&self) -> AccountAddress {
+        self.address
+    }
+
+    /// Returns the bls12381 consensus key of the validator
     pub fn public_key(&self) -> &PublicKey {
+        match &self.public_key {
+            ConsensusPublicKey::Bls12381(public_key) => public_key,
+        }
+    }
+
+    pub fn consensus_public_key(&self) -> &ConsensusPublicKey {
         &self.public_key
     }
+
+    pub fn voting_power(&self) -> u64 {
+        self.voting_power
+    }
+}
+
+/// A validator whose public key and/or voting power changed between two validator sets
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidatorChange {
+    pub old: ValidatorConsensusInfo,
+    pub new: ValidatorConsensusInfo,
+}
+
+impl ValidatorChange {
+    pub fn public_key_changed(&self) -> bool {
+        self.old.public_key != self.new.public_key
+    }
+
+    pub fn voting_power_changed(&self) -> bool {
+        self.old.voting_power != self.new.voting_power
+    }
+}
+
+/// The differences between two validator sets (e.g., at an epoch change), as returned by
+/// `ValidatorVerifier::diff`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ValidatorSetDiff {
+    /// The validators only in the new set, in the order of the new set
+    pub added: Vec<ValidatorConsensusInfo>,
+    /// The validators only in the old set, in the order of the old set
+    pub removed: Vec<ValidatorConsensusInfo>,
+    /// The validators in both sets whose public key and/or voting power changed, in the order of
+    /// the old set
+    pub changed: Vec<ValidatorChange>,
+}
+
+impl ValidatorSetDiff {
+    /// Returns true iff both validator sets have the same validators, keys and voting powers
+    /// (though possibly in a different order)
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A validator in a `ValidatorVerifierSummary`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ValidatorSummary {
+    pub address: AccountAddress,
+    pub voting_power: u64,
+    /// The share of the total voting power, in percent
+    pub voting_power_percentage: f64,
+}
+
+/// A machine-readable summary of the validators of a verifier and its quorum (e.g., for the
+/// inspection service and explorers), as returned by `ValidatorVerifier::summary`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ValidatorVerifierSummary {
+    /// The validators, in the order of their indices
+    pub validators: Vec<ValidatorSummary>,
+    pub total_voting_power: u128,
+    pub quorum_voting_power: u128,
+    /// The share of the total voting power needed for a quorum, in percent
+    pub quorum_voting_power_percentage: f64,
+}
+
+/// Statistics of the distribution of the voting power among the validators (e.g., for monitoring
+/// decentralization), as returned by `ValidatorVerifier::power_stats`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VotingPowerStats {
+    pub min_voting_power: u64,
+    pub max_voting_power: u64,
+    /// The median voting power, i.e., the mean of the two middle ones for an even number of
+    /// validators
+    pub median_voting_power: f64,
+    /// The Gini coefficient of the voting powers, from 0 when all validators have the same voting
+    /// power, towards 1 as the voting power is concentrated in fewer validators
+    pub gini_coefficient: f64,
+    /// The smallest set of validators that reaches the quorum voting power (i.e., the validators
+    /// with the most voting power, by decreasing voting power), unless it's unreachable
+    pub min_quorum_validators: Option<Vec<AccountAddress>>,
+}
+
+/// An LRU of the aggregated public keys of the voter bitmasks of recently verified multi-signatures.
+/// Certificates within an epoch are mostly signed by the same quorum, so this skips most of the
+/// aggregations. As the clones of a verifier have the same validators, they share the cache.
+#[derive(Clone)]
+struct AggregatedKeyCache(Arc<Mutex<LruCache<BitVec, PublicKey>>>);
+
+impl AggregatedKeyCache {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(LruCache::new(
+            AGGREGATED_KEY_CACHE_SIZE,
+        ))))
+    }
+
+    /// Returns the aggregated public key of the voters, aggregating (and caching) it on a miss
+    fn get_or_aggregate(
+        &self,
+        voters: &BitVec,
+        aggregate: impl FnOnce() -> std::result::Result<PublicKey, VerifyError>,
+    ) -> std::result::Result<PublicKey, VerifyError> {
+        if let Some(aggregated_key) = self.0.lock().get(voters) {
+            return Ok(aggregated_key.clone());
+        }
+        // Aggregate without holding the lock, as it's expensive for large validator sets
+        let aggregated_key = aggregate()?;
+        self.0.lock().put(voters.clone(), aggregated_key.clone());
+        Ok(aggregated_key)
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().len()
+    }
+}
+
+impl fmt::Debug for AggregatedKeyCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AggregatedKeyCache {{ len: {} }}", self.len())
+    }
+}
+
+/// The cache doesn't affect the outcome of any verification, so it's ignored by comparisons
+impl PartialEq for AggregatedKeyCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for AggregatedKeyCache {}
+
+/// An LRU of recently verified multi-signatures, keyed by the hash of their signing message (which
+/// is the `CryptoHash` of typed messages) and their voter bitmask. The same certificates (e.g., the quorum certs of re-fetched or gossiped blocks) are
+/// often verified repeatedly, which then only costs a lookup. As with the aggregated keys, the
+/// clones of a verifier share the cache.
+#[derive(Clone)]
+struct VerifiedMultiSignatureCache(Arc<Mutex<LruCache<(HashValue, BitVec), bls12381::Signature>>>);
+
+impl VerifiedMultiSignatureCache {
+    fn new(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(LruCache::new(capacity))))
+    }
+
+    /// Returns true iff the multi-signature of the voters on the message was verified. Other
+    /// multi-signatures of the same voters on the same message still need to be verified.
+    fn contains(
+        &self,
+        message_hash: HashValue,
+        voters: &BitVec,
+        multi_sig: &bls12381::Signature,
+    ) -> bool {
+        self.0
+            .lock()
+            .get(&(message_hash, voters.clone()))
+            .map_or(false, |verified_multi_sig| verified_multi_sig == multi_sig)
+    }
+
+    fn insert(&self, message_hash: HashValue, voters: BitVec, multi_sig: bls12381::Signature) {
+        self.0.lock().put((message_hash, voters), multi_sig);
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().len()
+    }
+}
+
+impl fmt::Debug for VerifiedMultiSignatureCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VerifiedMultiSignatureCache {{ len: {} }}", self.len())
+    }
+}
+
+/// The cache doesn't affect the outcome of any verification, so it's ignored by comparisons
+impl PartialEq for VerifiedMultiSignatureCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 
+impl Eq for VerifiedMultiSignatureCache {}
+
 /// Supports validation of signatures for known authors with individual voting powers. This struct
 /// can be used for all signature verification operations including block and network signature
 /// verification, respectively.
@@ -97,6 +401,12 @@ pub struct ValidatorVerifier {
     /// In-memory index of account address to its index in the vector, does not go through serde.
     #[serde(skip)]
     address_to_validator_index: HashMap<AccountAddress, usize>,
+    /// In-memory cache of the aggregated public keys of voter bitmasks, does not go through serde.
+    #[serde(skip)]
+    aggregated_key_cache: AggregatedKeyCache,
+    /// Optional in-memory cache of the verified multi-signatures, does not go through serde.
+    #[serde(skip)]
+    verified_multi_signature_cache: Option<VerifiedMultiSignatureCache>,
 }
 
 /// Reconstruct fields from the raw data upon deserialization.
@@ -135,34 +445,39 @@ impl ValidatorVerifier {
             quorum_voting_power,
             total_voting_power,
             address_to_validator_index,
+            aggregated_key_cache: AggregatedKeyCache::new(),
+            verified_multi_signature_cache: None,
         }
     }
 
     /// Initialize with a map of account address to validator info and set quorum size to
     /// default (`2f + 1`) or zero if `address_to_validator_info` is empty.
     pub fn new(validator_infos: Vec<ValidatorConsensusInfo>) -> Self {
-        let total_voting_power = sum_voting_power(&validator_infos);
-        let quorum_voting_power = if validator_infos.is_empty() {
-            0
-        } else {
-            total_voting_power * 2 / 3 + 1
-        };
-        Self::build_index(validator_infos, quorum_voting_power, total_voting_power)
+        Self::new_with_quorum_policy(validator_infos, QuorumPolicy::default())
+            .expect("The default quorum policy is valid")
     }
 
     /// Initializes a validator verifier with a specified quorum voting power.
     pub fn new_with_quorum_voting_power(
         validator_infos: Vec<ValidatorConsensusInfo>,
         quorum_voting_power: u128,
+    ) -> Result<Self> {
+        Self::new_with_quorum_policy(validator_infos, QuorumPolicy::Absolute(quorum_voting_power))
+    }
+
+    /// Initializes a validator verifier with the quorum voting power of the given policy, or zero if
+    /// `validator_infos` is empty.
+    pub fn new_with_quorum_policy(
+        validator_infos: Vec<ValidatorConsensusInfo>,
+        quorum_policy: QuorumPolicy,
     ) -> Result<Self> {
         let total_voting_power = sum_voting_power(&validator_infos);
-        ensure!(
-            quorum_voting_power <= total_voting_power,
-            "Quorum voting power is greater than the sum of all voting power of authors: {}, \
-             quorum_size: {}.",
-            quorum_voting_power,
-            total_voting_power
-        );
+        let quorum_voting_power = quorum_policy.quorum_voting_power(total_voting_power)?;
+        let quorum_voting_power = if validator_infos.is_empty() {
+            0
+        } else {
+            quorum_voting_power
+        };
         Ok(Self::build_index(
             validator_infos,
             quorum_voting_power,
@@ -176,6 +491,46 @@ impl ValidatorVerifier {
         Self::new(validator_infos)
     }
 
+    /// Caches up to `capacity` verified multi-signatures, so that verifying them again only costs a
+    /// lookup. A capacity of zero disables the cache.
+    pub fn with_verified_multi_signature_cache(mut self, capacity: usize) -> Self {
+        self.verified_multi_signature_cache =
+            (capacity > 0).then(|| VerifiedMultiSignatureCache::new(capacity));
+        self
+    }
+
+    /// Returns a view of this verifier in which the given validators (e.g., equivocators) are
+    /// unknown authors, so that their signatures are rejected for the rest of the epoch. Fails if
+    /// the other validators can't reach the quorum.
+    ///
+    /// Only the lookups by address reflect the exclusion: the signature checks,
+    /// `get_validator_info`, `get_public_key`, `get_voting_power` and `address_to_index` treat
+    /// the excluded validators as unknown. Everything indexed by position still describes all the
+    /// validators of the epoch, so that the voter bitmasks keep their meaning: `len`,
+    /// `get_ordered_account_addresses_iter`, `index_to_info`, and the quorum and total voting
+    /// powers are unchanged.
+    pub fn with_excluded(
+        &self,
+        excluded: &[AccountAddress],
+    ) -> std::result::Result<Self, VerifyError> {
+        let mut verifier = self.clone();
+        for address in excluded {
+            verifier.address_to_validator_index.remove(address);
+        }
+        let voting_power = verifier
+            .address_to_validator_index
+            .values()
+            .map(|index| verifier.validator_infos[*index].voting_power as u128)
+            .sum();
+        if voting_power < verifier.quorum_voting_power {
+            return Err(VerifyError::TooLittleVotingPower {
+                voting_power,
+                expected_voting_power: verifier.quorum_voting_power,
+            });
+        }
+        Ok(verifier)
+    }
+
     /// Verify the correctness of a signature of a message by a known author.
     pub fn verify<T: Serialize + CryptoHash>(
         &self,
@@ -183,12 +538,56 @@ impl ValidatorVerifier {
         message: &T,
         signature: &bls12381::Signature,
     ) -> std::result::Result<(), VerifyError> {
-        match self.get_public_key(&author) {
-            Some(public_key) => public_key
-                .verify_struct_signature(message, signature)
-                .map_err(|_| VerifyError::InvalidMultiSignature),
-            None => Err(VerifyError::UnknownAuthor),
+        self.signer_public_key(author)?
+            .verify_struct_signature(message, signature)
+            .map_err(|_| VerifyError::InvalidMultiSignature)
+    }
+
+    /// Same as `verify`, but for a message that's already framed as raw bytes (e.g., by another
+    /// chain), rather than serialized and prefixed as the signing message of a typed message.
+    pub fn verify_raw(
+        &self,
+        author: AccountAddress,
+        message: &[u8],
+        signature: &bls12381::Signature,
+    ) -> std::result::Result<(), VerifyError> {
+        signature
+            .verify_arbitrary_msg(message, self.signer_public_key(author)?)
+            .map_err(|_| VerifyError::InvalidMultiSignature)
+    }
+
+    fn signer_public_key(
+        &self,
+        author: AccountAddress,
+    ) -> std::result::Result<&PublicKey, VerifyError> {
+        let public_key = match self.validator_infos.as_slice() {
+            // Skip the index lookup when there's a single validator (e.g., in test networks)
+            [validator] if self.address_to_validator_index.len() == 1 => {
+                (validator.address == author).then(|| validator.public_key())
+            },
+            _ => self.public_key_ref(&author),
+        };
+        public_key.ok_or(VerifyError::UnknownAuthor)
+    }
+
+    /// Verify the correctness of the signatures of a message by known authors at once, which is
+    /// cheaper than verifying them one by one. On failure, it's unknown which signatures are
+    /// invalid, so callers that need to exclude the invalid ones fall back to `verify`.
+    pub fn batch_verify<T: Serialize + CryptoHash>(
+        &self,
+        message: &T,
+        signatures: &[(AccountAddress, bls12381::Signature)],
+    ) -> std::result::Result<(), VerifyError> {
+        let mut keys_and_signatures = vec![];
+        for (author, signature) in signatures {
+            let public_key = self
+                .public_key_ref(author)
+                .ok_or(VerifyError::UnknownAuthor)?;
+            keys_and_signatures.push((public_key, signature));
         }
+        let message = signing_message(message).map_err(|_| VerifyError::FailedToBatchVerify)?;
+        bls12381::Signature::batch_verify_arbitrary_msg(&message, &keys_and_signatures)
+            .map_err(|_| VerifyError::FailedToBatchVerify)
     }
 
     // Generates a multi signature or aggregate signature
@@ -197,6 +596,25 @@ impl ValidatorVerifier {
     pub fn aggregate_signatures(
         &self,
         partial_signatures: &PartialSignatures,
+    ) -> Result<AggregateSignature, VerifyError> {
+        self.aggregate_signatures_with(partial_signatures, |sigs| {
+            bls12381::Signature::aggregate(sigs.into_iter().cloned().collect())
+        })
+    }
+
+    /// Same as `aggregate_signatures`, but the signatures are aggregated in parallel, which is
+    /// faster for large validator sets.
+    pub fn aggregate_signatures_par(
+        &self,
+        partial_signatures: &PartialSignatures,
+    ) -> Result<AggregateSignature, VerifyError> {
+        self.aggregate_signatures_with(partial_signatures, aggregate_signatures_par)
+    }
+
+    fn aggregate_signatures_with(
+        &self,
+        partial_signatures: &PartialSignatures,
+        aggregate: impl FnOnce(Vec<&bls12381::Signature>) -> Result<bls12381::Signature>,
     ) -> Result<AggregateSignature, VerifyError> {
         let mut sigs = vec![];
         let mut masks = BitVec::with_num_bits(self.len() as u16);
@@ -206,23 +624,89 @@ impl ValidatorVerifier {
                 .get(addr)
                 .ok_or(VerifyError::UnknownAuthor)?;
             masks.set(index as u16);
-            sigs.push(sig.clone());
+            sigs.push(sig);
         }
         // Perform an optimistic aggregation of the signatures without verification.
-        let aggregated_sig = bls12381::Signature::aggregate(sigs)
-            .map_err(|_| VerifyError::FailedToAggregateSignature)?;
+        let aggregated_sig = aggregate(sigs).map_err(|_| {
+            VerifyError::FailedToAggregateSignature(find_malformed_signature(
+                partial_signatures.signatures(),
+            ))
+        })?;
 
         Ok(AggregateSignature::new(masks, Some(aggregated_sig)))
     }
 
     /// This function will successfully return when at least quorum_size signatures of known authors
-    /// are successfully verified. It creates an aggregated public key using the voter bitmask passed
-    /// in the multi-signature and verifies the message passed in the multi-signature using the aggregated
-    /// public key.
+    /// are successfully verified. It creates (or reuses the cached) aggregated public key using the voter
+    /// bitmask passed in the multi-signature and verifies the message passed in the multi-signature using
+    /// the aggregated public key.
     pub fn verify_multi_signatures<T: CryptoHash + Serialize>(
         &self,
         message: &T,
         multi_signature: &AggregateSignature,
+    ) -> std::result::Result<(), VerifyError> {
+        self.verify_multi_signatures_with(
+            &signing_message(message).map_err(|_| VerifyError::InvalidMultiSignature)?,
+            multi_signature,
+            self.quorum_voting_power,
+            aggregate_public_keys,
+        )
+    }
+
+    /// Same as `verify_multi_signatures`, but for a message that's already framed as raw bytes
+    /// (see `verify_raw`).
+    pub fn verify_multi_signatures_raw(
+        &self,
+        message: &[u8],
+        multi_signature: &AggregateSignature,
+    ) -> std::result::Result<(), VerifyError> {
+        self.verify_multi_signatures_with(
+            message,
+            multi_signature,
+            self.quorum_voting_power,
+            aggregate_public_keys,
+        )
+    }
+
+    /// Same as `verify_multi_signatures`, but the voters only need at least `min_voting_power`
+    /// rather than the quorum voting power (e.g., `f + 1` for timeout certificates and failure
+    /// reports).
+    pub fn verify_aggregate_signature_with_threshold<T: CryptoHash + Serialize>(
+        &self,
+        message: &T,
+        multi_signature: &AggregateSignature,
+        min_voting_power: u128,
+    ) -> std::result::Result<(), VerifyError> {
+        self.verify_multi_signatures_with(
+            &signing_message(message).map_err(|_| VerifyError::InvalidMultiSignature)?,
+            multi_signature,
+            min_voting_power,
+            aggregate_public_keys,
+        )
+    }
+
+    /// Same as `verify_multi_signatures`, but the public keys of the voters are aggregated (on a
+    /// cache miss) in parallel, which is faster for large validator sets (e.g., when verifying quorum certs).
+    pub fn verify_multi_signatures_par<T: CryptoHash + Serialize>(
+        &self,
+        message: &T,
+        multi_signature: &AggregateSignature,
+    ) -> std::result::Result<(), VerifyError> {
+        self.verify_multi_signatures_with(
+            &signing_message(message).map_err(|_| VerifyError::InvalidMultiSignature)?,
+            multi_signature,
+            self.quorum_voting_power,
+            aggregate_public_keys_par,
+        )
+    }
+
+    /// Verifies the multi-signature of the (signing) message, see `verify_multi_signatures`
+    fn verify_multi_signatures_with(
+        &self,
+        message: &[u8],
+        multi_signature: &AggregateSignature,
+        min_voting_power: u128,
+        aggregate: impl FnOnce(&[&PublicKey]) -> Result<PublicKey>,
     ) -> std::result::Result<(), VerifyError> {
         // Verify the number of signature is not greater than expected.
         Self::check_num_of_voters(self.len() as u16, multi_signature.get_voters_bitvec())?;
@@ -237,10 +721,10 @@ impl ValidatorVerifier {
             pub_keys.push(validator.public_key());
         }
         // Verify the quorum voting power of the authors
-        self.check_voting_power(authors.iter())?;
+        self.check_voting_power_at_least(authors.iter(), min_voting_power)?;
         #[cfg(any(test, feature = "fuzzing"))]
         {
-            if self.quorum_voting_power == 0 {
+            if min_voting_power == 0 {
                 // This should happen only in case of tests.
                 // TODO(skedia): Clean up the test behaviors to not rely on empty signature
                 // verification
@@ -252,16 +736,85 @@ impl ValidatorVerifier {
             .sig()
             .as_ref()
             .ok_or(VerifyError::EmptySignature)?;
-        // Verify the optimistically aggregated signature.
-        let aggregated_key =
-            PublicKey::aggregate(pub_keys).map_err(|_| VerifyError::FailedToAggregatePubKey)?;
+        // Skip the verification of the multi-signature if it was already verified
+        let voters = multi_signature.get_voters_bitvec();
+        let verified_cache = self
+            .verified_multi_signature_cache
+            .as_ref()
+            .map(|cache| (cache, HashValue::sha3_256_of(message)));
+        if let Some((cache, message_hash)) = &verified_cache {
+            if cache.contains(*message_hash, voters, multi_sig) {
+                return Ok(());
+            }
+        }
+        // Verify the optimistically aggregated signature, reusing the aggregated key of the voters
+        // if they're cached. The key of a single voter doesn't need to be aggregated (or cached).
+        let aggregated_key = match pub_keys.as_slice() {
+            [pub_key] => Cow::Borrowed(*pub_key),
+            _ => Cow::Owned(self.aggregated_key_cache.get_or_aggregate(voters, || {
+                aggregate(&pub_keys).map_err(|_| {
+                    VerifyError::FailedToAggregatePubKey(find_malformed_public_key(
+                        authors.iter().zip(pub_keys.iter().copied()),
+                    ))
+                })
+            })?),
+        };
 
         multi_sig
-            .verify(message, &aggregated_key)
+            .verify_arbitrary_msg(message, aggregated_key.as_ref())
             .map_err(|_| VerifyError::InvalidMultiSignature)?;
+        if let Some((cache, message_hash)) = verified_cache {
+            cache.insert(message_hash, voters.clone(), multi_sig.clone());
+        }
         Ok(())
     }
 
+    /// Aggregates the partial signatures into a multi-signature, and verifies it. If it's invalid
+    /// and `diagnose` is set, the invalid signatures are identified with
+    /// `find_invalid_signatures` (which is slower), and their authors are returned in a
+    /// `VerifyError::InvalidSignatures` error.
+    pub fn aggregate_and_verify_multi_signature<T: CryptoHash + Serialize>(
+        &self,
+        message: &T,
+        partial_signatures: &PartialSignatures,
+        diagnose: bool,
+    ) -> std::result::Result<AggregateSignature, VerifyError> {
+        let multi_signature = self.aggregate_signatures(partial_signatures)?;
+        match self.verify_multi_signatures(message, &multi_signature) {
+            Ok(()) => Ok(multi_signature),
+            Err(VerifyError::InvalidMultiSignature) if diagnose => {
+                Err(VerifyError::InvalidSignatures(
+                    self.find_invalid_signatures(message, partial_signatures)?,
+                ))
+            },
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns the authors of the invalid partial signatures (in the order of their addresses) by
+    /// bisecting the signatures: the signatures are aggregated and verified, and if they're
+    /// invalid, both halves are checked recursively. This verifies O(k log n) multi-signatures
+    /// for k invalid signatures out of n, rather than n signatures. Each reported signature is
+    /// verified on its own, so valid signatures are never reported. Invalid signatures that
+    /// cancel out when aggregated with others in the same half may go unreported.
+    pub fn find_invalid_signatures<T: CryptoHash + Serialize>(
+        &self,
+        message: &T,
+        partial_signatures: &PartialSignatures,
+    ) -> std::result::Result<Vec<AccountAddress>, VerifyError> {
+        let message = signing_message(message).map_err(|_| VerifyError::InvalidMultiSignature)?;
+        let mut signatures = vec![];
+        for (author, signature) in partial_signatures.signatures() {
+            let public_key = self
+                .public_key_ref(author)
+                .ok_or(VerifyError::UnknownAuthor)?;
+            signatures.push((*author, public_key, signature));
+        }
+        let mut invalid_authors = vec![];
+        bisect_invalid_signatures(&message, &signatures, &mut invalid_authors);
+        Ok(invalid_authors)
+    }
+
     pub fn verify_aggregate_signatures<T: CryptoHash + Serialize>(
         &self,
         messages: &[&T],
@@ -294,7 +847,7 @@ impl ValidatorVerifier {
     }
 
     /// Ensure there are not more than the maximum expected voters (all possible signatures).
-    fn check_num_of_voters(
+    pub(crate) fn check_num_of_voters(
         num_validators: u16,
         bitvec: &BitVec,
     ) -> std::result::Result<(), VerifyError> {
@@ -309,12 +862,81 @@ impl ValidatorVerifier {
         Ok(())
     }
 
+    /// Verifies the ledger info that ends the given epoch (whose validators are the ones of this
+    /// verifier), and returns the state of the next epoch, which is then trusted.
+    pub fn verify_epoch_change<'a>(
+        &self,
+        epoch: u64,
+        ledger_info_with_sigs: &'a LedgerInfoWithSignatures,
+    ) -> Result<&'a EpochState> {
+        let ledger_info = ledger_info_with_sigs.ledger_info();
+        ensure!(
+            ledger_info.epoch() == epoch,
+            "LedgerInfo has unexpected epoch {}, expected {}",
+            ledger_info.epoch(),
+            epoch
+        );
+        ledger_info_with_sigs.verify_signatures(self)?;
+        ledger_info
+            .next_epoch_state()
+            .ok_or_else(|| format_err!("LedgerInfo doesn't carry a ValidatorSet"))
+    }
+
+    /// Ratchets through the epoch changes of the proof, starting from the given epoch of this
+    /// verifier: the ledger info ending each epoch is verified with the verifier of that epoch,
+    /// which is then replaced by the verifier of the next epoch. The (already trusted) ledger
+    /// infos of earlier epochs are skipped. Returns the state of the last epoch of the proof, i.e.,
+    /// the final trusted verifier.
+    pub fn ratchet_epoch_change_proof<'a>(
+        &self,
+        epoch: u64,
+        epoch_change_proof: &'a EpochChangeProof,
+    ) -> Result<&'a EpochState> {
+        let mut ledger_infos_with_sigs = epoch_change_proof
+            .ledger_info_with_sigs
+            .iter()
+            .skip_while(|ledger_info_with_sigs| {
+                ledger_info_with_sigs.ledger_info().epoch() < epoch
+            });
+        let first_ledger_info_with_sigs = ledger_infos_with_sigs
+            .next()
+            .ok_or_else(|| format_err!("The EpochChangeProof is empty or stale"))?;
+        let mut epoch_state = self.verify_epoch_change(epoch, first_ledger_info_with_sigs)?;
+        for ledger_info_with_sigs in ledger_infos_with_sigs {
+            epoch_state = epoch_state
+                .verifier
+                .verify_epoch_change(epoch_state.epoch, ledger_info_with_sigs)?;
+        }
+        Ok(epoch_state)
+    }
+
     /// Ensure there is at least quorum_voting_power in the provided signatures and there
     /// are only known authors. According to the threshold verification policy,
     /// invalid public keys are not allowed.
     pub fn check_voting_power<'a>(
         &self,
         authors: impl Iterator<Item = &'a AccountAddress>,
+    ) -> std::result::Result<(), VerifyError> {
+        self.check_voting_power_at_least(authors, self.quorum_voting_power)
+    }
+
+    /// Same as `check_voting_power`, but for the quorum voting power of the given policy rather
+    /// than the one of this verifier (e.g., `f + 1` for timeouts).
+    pub fn check_voting_power_with_policy<'a>(
+        &self,
+        authors: impl Iterator<Item = &'a AccountAddress>,
+        quorum_policy: QuorumPolicy,
+    ) -> std::result::Result<(), VerifyError> {
+        let quorum_voting_power = quorum_policy
+            .quorum_voting_power(self.total_voting_power)
+            .map_err(|_| VerifyError::InvalidQuorumPolicy)?;
+        self.check_voting_power_at_least(authors, quorum_voting_power)
+    }
+
+    fn check_voting_power_at_least<'a>(
+        &self,
+        authors: impl Iterator<Item = &'a AccountAddress>,
+        quorum_voting_power: u128,
     ) -> std::result::Result<(), VerifyError> {
         // Add voting power for valid accounts, exiting early for unknown authors
         let mut aggregated_voting_power = 0;
@@ -325,35 +947,47 @@ impl ValidatorVerifier {
             }
         }
 
-        if aggregated_voting_power < self.quorum_voting_power {
+        if aggregated_voting_power < quorum_voting_power {
             return Err(VerifyError::TooLittleVotingPower {
                 voting_power: aggregated_voting_power,
-                expected_voting_power: self.quorum_voting_power,
+                expected_voting_power: quorum_voting_power,
             });
         }
         Ok(())
     }
 
-    /// Returns the public key for this address.
-    pub fn get_public_key(&self, author: &AccountAddress) -> Option<PublicKey> {
+    /// Returns the validator with this address.
+    pub fn get_validator_info(&self, author: &AccountAddress) -> Option<&ValidatorConsensusInfo> {
         self.address_to_validator_index
             .get(author)
-            .map(|index| self.validator_infos[*index].public_key().clone())
+            .map(|index| &self.validator_infos[*index])
+    }
+
+    /// Returns the public key for this address. Prefer `public_key_ref` to avoid the clone.
+    pub fn get_public_key(&self, author: &AccountAddress) -> Option<PublicKey> {
+        self.public_key_ref(author).cloned()
+    }
+
+    /// Returns a reference to the public key for this address.
+    pub fn public_key_ref(&self, author: &AccountAddress) -> Option<&PublicKey> {
+        self.get_validator_info(author)
+            .map(ValidatorConsensusInfo::public_key)
     }
 
     /// Returns the voting power for this address.
     pub fn get_voting_power(&self, author: &AccountAddress) -> Option<u64> {
-        self.address_to_validator_index
-            .get(author)
-            .map(|index| self.validator_infos[*index].voting_power)
+        self.get_validator_info(author)
+            .map(|info| info.voting_power)
     }
 
-    /// Returns an ordered list of account addresses as an `Iterator`.
+    /// Returns an ordered list of account addresses as an `Iterator`. This includes validators
+    /// excluded with `with_excluded`.
     pub fn get_ordered_account_addresses_iter(&self) -> impl Iterator<Item = AccountAddress> + '_ {
         self.validator_infos.iter().map(|info| info.address)
     }
 
-    /// Returns the number of authors to be validated.
+    /// Returns the number of authors to be validated, including validators excluded with
+    /// `with_excluded`.
     pub fn len(&self) -> usize {
         self.validator_infos.len()
     }
@@ -376,68 +1010,410 @@ impl ValidatorVerifier {
     pub fn address_to_validator_index(&self) -> &HashMap<AccountAddress, usize> {
         &self.address_to_validator_index
     }
-}
 
-/// Returns sum of voting power from Map of validator account addresses, validator consensus info
-fn sum_voting_power(address_to_validator_info: &[ValidatorConsensusInfo]) -> u128 {
-    address_to_validator_info.iter().fold(0, |sum, x| {
-        sum.checked_add(x.voting_power as u128)
-            .expect("sum of all voting power is greater than u64::max")
-    })
-}
+    /// Returns the index of the validator. The indices are stable for the lifetime of the
+    /// verifier, and are the positions of the validators in `get_ordered_account_addresses_iter`
+    /// and in the voter bitmasks of multi-signatures. For a verifier built from a `ValidatorSet`,
+    /// they match the on-chain `validator_index` of the validators, so the index can be exchanged
+    /// instead of the address with nodes of the same epoch.
+    pub fn address_to_index(&self, address: &AccountAddress) -> Option<usize> {
+        self.address_to_validator_index.get(address).copied()
+    }
 
-impl fmt::Display for ValidatorVerifier {
-    fn fmt(&self, f: &mut fmt::Formatter) -> std::fmt::Result {
-        write!(f, "ValidatorSet: [")?;
-        for info in &self.validator_infos {
-            write!(
-                f,
-                "{}: {}, ",
-                info.address.short_str_lossless(),
-                info.voting_power
-            )?;
-        }
-        write!(f, "]")
+    /// Returns the validator with the index, see `address_to_index`
+    pub fn index_to_info(&self, index: usize) -> Option<&ValidatorConsensusInfo> {
+        self.validator_infos.get(index)
     }
-}
 
-/// This does the conversion between move data to the rust data
-impl From<&ValidatorSet> for ValidatorVerifier {
-    fn from(validator_set: &ValidatorSet) -> Self {
-        let sorted_validator_infos: BTreeMap<u64, ValidatorConsensusInfo> = validator_set
-            .payload()
-            .map(|info| {
-                (
-                    info.config().validator_index,
-                    ValidatorConsensusInfo::new(
-                        info.account_address,
-                        info.consensus_public_key().clone(),
-                        info.consensus_voting_power(),
-                    ),
-                )
+    /// Returns the validators by decreasing voting power. Validators with the same voting power
+    /// are in the order of their indices.
+    pub fn get_validators_by_power_desc(
+        &self,
+    ) -> impl Iterator<Item = &ValidatorConsensusInfo> + '_ {
+        let mut validator_infos: Vec<_> = self.validator_infos.iter().collect();
+        // The sort is stable, so ties keep the order of the indices
+        validator_infos.sort_by(|a, b| b.voting_power.cmp(&a.voting_power));
+        validator_infos.into_iter()
+    }
+
+    /// Returns the (at most) `k` validators with the most voting power, by decreasing voting power
+    pub fn top_k_by_power(&self, k: usize) -> impl Iterator<Item = &ValidatorConsensusInfo> + '_ {
+        self.get_validators_by_power_desc().take(k)
+    }
+
+    /// Returns the validators by decreasing voting power, each with the total voting power of the
+    /// validators up to (and including) it.
+    pub fn get_cumulative_voting_powers_desc(
+        &self,
+    ) -> impl Iterator<Item = (&ValidatorConsensusInfo, u128)> + '_ {
+        self.get_validators_by_power_desc()
+            .scan(0, |cumulative_voting_power, info| {
+                *cumulative_voting_power += info.voting_power as u128;
+                Some((info, *cumulative_voting_power))
             })
-            .collect();
-        let validator_infos: Vec<_> = sorted_validator_infos.values().cloned().collect();
-        for info in validator_set.payload() {
-            assert_eq!(
-                validator_infos[info.config().validator_index as usize].address,
-                info.account_address
-            );
+    }
+
+    /// Returns the smallest number of validators whose total voting power is at least the given
+    /// one (i.e., of the validators with the most voting power), or `None` if all the validators
+    /// together don't have it.
+    pub fn num_validators_for_voting_power(&self, voting_power: u128) -> Option<usize> {
+        if voting_power == 0 {
+            return Some(0);
         }
-        ValidatorVerifier::new(validator_infos)
+        self.get_cumulative_voting_powers_desc()
+            .position(|(_, cumulative_voting_power)| cumulative_voting_power >= voting_power)
+            .map(|position| position + 1)
     }
-}
 
-#[cfg(any(test, feature = "fuzzing"))]
-impl From<&ValidatorVerifier> for ValidatorSet {
-    fn from(verifier: &ValidatorVerifier) -> Self {
-        ValidatorSet::new(
-            verifier
-                .get_ordered_account_addresses_iter()
-                .enumerate()
-                .map(|(index, addr)| {
-                    crate::validator_info::ValidatorInfo::new_with_test_network_keys(
-                        addr,
+    /// Returns statistics of the distribution of the voting power, or `None` if there are no
+    /// validators.
+    pub fn power_stats(&self) -> Option<VotingPowerStats> {
+        let mut voting_powers: Vec<_> = self
+            .validator_infos
+            .iter()
+            .map(|info| info.voting_power)
+            .collect();
+        voting_powers.sort_unstable();
+        let num_validators = voting_powers.len();
+        let (min_voting_power, max_voting_power) =
+            (*voting_powers.first()?, *voting_powers.last()?);
+        let median_voting_power = if num_validators % 2 == 0 {
+            (voting_powers[num_validators / 2 - 1] as f64
+                + voting_powers[num_validators / 2] as f64)
+                / 2.0
+        } else {
+            voting_powers[num_validators / 2] as f64
+        };
+        // G = 2 * sum_i(i * x_i) / (n * sum_i(x_i)) - (n + 1) / n, for x_i by increasing order
+        let gini_coefficient = if self.total_voting_power == 0 {
+            0.0
+        } else {
+            let weighted_sum: u128 = voting_powers
+                .iter()
+                .enumerate()
+                .map(|(i, voting_power)| (i as u128 + 1) * *voting_power as u128)
+                .sum();
+            let n = num_validators as f64;
+            2.0 * weighted_sum as f64 / (n * self.total_voting_power as f64) - (n + 1.0) / n
+        };
+        let min_quorum_validators = self
+            .num_validators_for_voting_power(self.quorum_voting_power)
+            .map(|num_validators| {
+                self.top_k_by_power(num_validators)
+                    .map(|info| info.address)
+                    .collect()
+            });
+        Some(VotingPowerStats {
+            min_voting_power,
+            max_voting_power,
+            median_voting_power,
+            gini_coefficient,
+            min_quorum_validators,
+        })
+    }
+
+    /// Returns a machine-readable summary of the validators and the quorum
+    pub fn summary(&self) -> ValidatorVerifierSummary {
+        let percentage = |voting_power: u128| {
+            if self.total_voting_power == 0 {
+                0.0
+            } else {
+                voting_power as f64 * 100.0 / self.total_voting_power as f64
+            }
+        };
+        ValidatorVerifierSummary {
+            validators: self
+                .validator_infos
+                .iter()
+                .map(|info| ValidatorSummary {
+                    address: info.address,
+                    voting_power: info.voting_power,
+                    voting_power_percentage: percentage(info.voting_power as u128),
+                })
+                .collect(),
+            total_voting_power: self.total_voting_power,
+            quorum_voting_power: self.quorum_voting_power,
+            quorum_voting_power_percentage: percentage(self.quorum_voting_power),
+        }
+    }
+
+    /// Returns the summary of the validators and the quorum as JSON, unlike the terse `Display`
+    pub fn to_json_summary(&self) -> String {
+        serde_json::to_string(&self.summary()).expect("The summary is serializable")
+    }
+
+    /// Returns the validators added, removed and changed in the `other` verifier w.r.t. this one,
+    /// e.g., from the verifier of the current epoch to the one of the next epoch.
+    pub fn diff(&self, other: &ValidatorVerifier) -> ValidatorSetDiff {
+        let mut diff = ValidatorSetDiff::default();
+        for info in &self.validator_infos {
+            match other.get_validator_info(&info.address) {
+                Some(other_info) if other_info != info => diff.changed.push(ValidatorChange {
+                    old: info.clone(),
+                    new: other_info.clone(),
+                }),
+                Some(_) => {},
+                None => diff.removed.push(info.clone()),
+            }
+        }
+        diff.added = other
+            .validator_infos
+            .iter()
+            .filter(|info| !self.address_to_validator_index.contains_key(&info.address))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    /// Serializes the verifier in its versioned format, for persisting it (e.g., in the epoch
+    /// states of a database). Unlike the serde format, the versioned format keeps the quorum
+    /// voting power and the scheme of the consensus keys. Version 2 is laid out as:
+    ///
+    /// - the version (1 byte)
+    /// - the length of the header (4 bytes, little-endian), followed by the header: the quorum
+    ///   voting power (16 bytes, little-endian)
+    /// - the number of validators (4 bytes, little-endian)
+    /// - for each validator, the length of its record (4 bytes, little-endian), followed by the
+    ///   record: its address (32 bytes), the tag of its consensus key scheme (1 byte), the length
+    ///   of its consensus key (2 bytes, little-endian) and the key, and its voting power (8 bytes,
+    ///   little-endian)
+    ///
+    /// Later versions may only append fields to the header and the records, and append data
+    /// after the records, so that `from_versioned_bytes` can skip the fields it doesn't know.
+    pub fn to_versioned_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![VALIDATOR_VERIFIER_FORMAT_VERSION];
+        put_section(&mut bytes, &self.quorum_voting_power.to_le_bytes());
+        bytes.extend((self.validator_infos.len() as u32).to_le_bytes());
+        for info in &self.validator_infos {
+            let consensus_key = info.consensus_public_key();
+            let raw_key = consensus_key.to_raw_bytes();
+            let mut record = info.address.to_vec();
+            record.push(consensus_key.scheme().tag());
+            record.extend((raw_key.len() as u16).to_le_bytes());
+            record.extend(raw_key);
+            record.extend(info.voting_power.to_le_bytes());
+            put_section(&mut bytes, &record);
+        }
+        bytes
+    }
+
+    /// Deserializes a verifier serialized in any version of the versioned format. The fields that
+    /// versions newer than `VALIDATOR_VERIFIER_FORMAT_VERSION` appended are ignored.
+    pub fn from_versioned_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let version = take_bytes(&mut reader, 1)?[0];
+        let (validator_infos, quorum_voting_power) = match version {
+            0 => bail!("Unsupported validator verifier version: {}", version),
+            // Version 1 only had bare bls12381 keys, and no length prefixes
+            1 => {
+                let quorum_voting_power = u128::from_le_bytes(take_array(&mut reader)?);
+                let num_validators = u32::from_le_bytes(take_array(&mut reader)?);
+                let mut validator_infos = vec![];
+                for _ in 0..num_validators {
+                    let address =
+                        AccountAddress::try_from(take_bytes(&mut reader, AccountAddress::LENGTH)?)?;
+                    let public_key =
+                        PublicKey::try_from(take_bytes(&mut reader, PublicKey::LENGTH)?)?;
+                    let voting_power = u64::from_le_bytes(take_array(&mut reader)?);
+                    validator_infos.push(ValidatorConsensusInfo::new(
+                        address,
+                        public_key,
+                        voting_power,
+                    ));
+                }
+                (validator_infos, quorum_voting_power)
+            },
+            _ => {
+                let mut header = take_section(&mut reader)?;
+                let quorum_voting_power = u128::from_le_bytes(take_array(&mut header)?);
+                ensure_read(header, version)?;
+                let num_validators = u32::from_le_bytes(take_array(&mut reader)?);
+                let mut validator_infos = vec![];
+                for _ in 0..num_validators {
+                    let mut record = take_section(&mut reader)?;
+                    let address =
+                        AccountAddress::try_from(take_bytes(&mut record, AccountAddress::LENGTH)?)?;
+                    let scheme = ConsensusKeyScheme::from_tag(take_bytes(&mut record, 1)?[0])?;
+                    let key_len = u16::from_le_bytes(take_array(&mut record)?);
+                    let public_key = ConsensusPublicKey::from_scheme_bytes(
+                        scheme,
+                        take_bytes(&mut record, key_len as usize)?,
+                    )?;
+                    let voting_power = u64::from_le_bytes(take_array(&mut record)?);
+                    ensure_read(record, version)?;
+                    validator_infos.push(ValidatorConsensusInfo::new_with_consensus_key(
+                        address,
+                        public_key,
+                        voting_power,
+                    ));
+                }
+                (validator_infos, quorum_voting_power)
+            },
+        };
+        ensure_read(reader, version)?;
+        Self::new_with_quorum_voting_power(validator_infos, quorum_voting_power)
+    }
+}
+
+/// Appends the authors of the invalid signatures to `invalid_authors`, see
+/// `ValidatorVerifier::find_invalid_signatures`
+fn bisect_invalid_signatures(
+    message: &[u8],
+    signatures: &[(AccountAddress, &PublicKey, &bls12381::Signature)],
+    invalid_authors: &mut Vec<AccountAddress>,
+) {
+    match signatures {
+        [] => {},
+        [(author, public_key, signature)] => {
+            if signature.verify_arbitrary_msg(message, public_key).is_err() {
+                invalid_authors.push(*author);
+            }
+        },
+        _ => {
+            let is_valid =
+                PublicKey::aggregate(signatures.iter().map(|(_, key, _)| *key).collect())
+                    .and_then(|aggregated_key| {
+                        bls12381::Signature::aggregate(
+                            signatures
+                                .iter()
+                                .map(|(_, _, sig)| (*sig).clone())
+                                .collect(),
+                        )?
+                        .verify_arbitrary_msg(message, &aggregated_key)
+                    })
+                    .is_ok();
+            if !is_valid {
+                let (left, right) = signatures.split_at(signatures.len() / 2);
+                bisect_invalid_signatures(message, left, invalid_authors);
+                bisect_invalid_signatures(message, right, invalid_authors);
+            }
+        },
+    }
+}
+
+/// Splits the first `len` bytes off the reader
+fn take_bytes<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    ensure!(
+        reader.len() >= len,
+        "Unexpected end of the validator verifier bytes"
+    );
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(bytes)
+}
+
+/// Splits the first `N` bytes off the reader
+fn take_array<const N: usize>(reader: &mut &[u8]) -> Result<[u8; N]> {
+    Ok(<[u8; N]>::try_from(take_bytes(reader, N)?)?)
+}
+
+/// Splits a section, prefixed with its length, off the reader
+fn take_section<'a>(reader: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = u32::from_le_bytes(take_array(reader)?);
+    take_bytes(reader, len as usize)
+}
+
+/// Appends a section, prefixed with its length, to the bytes
+fn put_section(bytes: &mut Vec<u8>, section: &[u8]) {
+    bytes.extend((section.len() as u32).to_le_bytes());
+    bytes.extend(section);
+}
+
+/// Ensures that all bytes of a section were read, unless the section is of a newer version of
+/// the versioned format (whose additional fields are skipped)
+fn ensure_read(section: &[u8], version: u8) -> Result<()> {
+    ensure!(
+        section.is_empty() || version > VALIDATOR_VERIFIER_FORMAT_VERSION,
+        "{} unexpected trailing bytes in a validator verifier of version {}",
+        section.len(),
+        version
+    );
+    Ok(())
+}
+
+/// Returns sum of voting power from Map of validator account addresses, validator consensus info
+fn sum_voting_power(address_to_validator_info: &[ValidatorConsensusInfo]) -> u128 {
+    address_to_validator_info.iter().fold(0, |sum, x| {
+        sum.checked_add(x.voting_power as u128)
+            .expect("sum of all voting power is greater than u64::max")
+    })
+}
+
+impl fmt::Display for ValidatorVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ValidatorSet: [")?;
+        for info in &self.validator_infos {
+            write!(
+                f,
+                "{}: {}, ",
+                info.address.short_str_lossless(),
+                info.voting_power
+            )?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// This does the conversion between move data to the rust data, ordering the validators by their
+/// validator index. Fails if the indices aren't `0..n` (each used once), or if an address is used
+/// more than once. Validators without voting power are kept at their index, so that the bitmasks
+/// of on-chain multi-signatures match the keys of the validators.
+impl TryFrom<&ValidatorSet> for ValidatorVerifier {
+    type Error = anyhow::Error;
+
+    fn try_from(validator_set: &ValidatorSet) -> Result<Self> {
+        let mut sorted_validator_infos = BTreeMap::new();
+        for info in validator_set.payload() {
+            let validator_index = info.config().validator_index;
+            let validator_info = ValidatorConsensusInfo::new(
+                info.account_address,
+                info.consensus_public_key().clone(),
+                info.consensus_voting_power(),
+            );
+            if let Some(other) = sorted_validator_infos.insert(validator_index, validator_info) {
+                bail!(
+                    "Validators {} and {} have the same validator index {}",
+                    other.address,
+                    info.account_address,
+                    validator_index
+                );
+            }
+        }
+        let num_validators = sorted_validator_infos.len() as u64;
+        if let Some((validator_index, info)) = sorted_validator_infos.iter().next_back() {
+            ensure!(
+                *validator_index < num_validators,
+                "Validator {} has the validator index {}, but there are only {} validators",
+                info.address,
+                validator_index,
+                num_validators
+            );
+        }
+
+        let mut addresses = HashSet::new();
+        for info in sorted_validator_infos.values() {
+            ensure!(
+                addresses.insert(info.address),
+                "Validator {} is in the validator set more than once",
+                info.address
+            );
+        }
+        Ok(ValidatorVerifier::new(
+            sorted_validator_infos.into_values().collect(),
+        ))
+    }
+}
+
+#[cfg(any(test, feature = "fuzzing"))]
+impl From<&ValidatorVerifier> for ValidatorSet {
+    fn from(verifier: &ValidatorVerifier) -> Self {
+        ValidatorSet::new(
+            verifier
+                .get_ordered_account_addresses_iter()
+                .enumerate()
+                .map(|(index, addr)| {
+                    crate::validator_info::ValidatorInfo::new_with_test_network_keys(
+                        addr,
                         verifier.get_public_key(&addr).unwrap(),
                         verifier.get_voting_power(&addr).unwrap(),
                         index as u64,
@@ -448,6 +1424,52 @@ impl From<&ValidatorVerifier> for ValidatorSet {
     }
 }
 
+fn aggregate_public_keys(pub_keys: &[&PublicKey]) -> Result<PublicKey> {
+    PublicKey::aggregate(pub_keys.to_vec())
+}
+
+/// Aggregates the public keys in chunks of `PARALLEL_AGGREGATION_CHUNK_SIZE` in parallel, and then
+/// aggregates the partial aggregates.
+fn aggregate_public_keys_par(pub_keys: &[&PublicKey]) -> Result<PublicKey> {
+    let partial_keys = pub_keys
+        .par_chunks(PARALLEL_AGGREGATION_CHUNK_SIZE)
+        .map(|chunk| PublicKey::aggregate(chunk.to_vec()))
+        .collect::<Result<Vec<_>>>()?;
+    PublicKey::aggregate(partial_keys.iter().collect())
+}
+
+/// Aggregates the signatures in chunks of `PARALLEL_AGGREGATION_CHUNK_SIZE` in parallel, and then
+/// aggregates the partial aggregates.
+fn aggregate_signatures_par(sigs: Vec<&bls12381::Signature>) -> Result<bls12381::Signature> {
+    let partial_sigs = sigs
+        .par_chunks(PARALLEL_AGGREGATION_CHUNK_SIZE)
+        .map(|chunk| bls12381::Signature::aggregate(chunk.iter().map(|&sig| sig.clone()).collect()))
+        .collect::<Result<Vec<_>>>()?;
+    bls12381::Signature::aggregate(partial_sigs)
+}
+
+/// Returns the author of the first signature that isn't a valid group element, to pinpoint the
+/// signature that made an aggregation fail. Signatures aren't subgroup-checked when aggregated
+/// (only when verified), so this is only done once an aggregation failed.
+pub(crate) fn find_malformed_signature<'a>(
+    signatures: impl IntoIterator<Item = (&'a AccountAddress, &'a bls12381::Signature)>,
+) -> Option<AccountAddress> {
+    signatures
+        .into_iter()
+        .find(|(_, signature)| signature.subgroup_check().is_err())
+        .map(|(author, _)| *author)
+}
+
+/// Same as `find_malformed_signature`, for the public keys of validators.
+fn find_malformed_public_key<'a>(
+    public_keys: impl IntoIterator<Item = (&'a AccountAddress, &'a PublicKey)>,
+) -> Option<AccountAddress> {
+    public_keys
+        .into_iter()
+        .find(|(_, public_key)| public_key.subgroup_check().is_err())
+        .map(|(author, _)| *author)
+}
+
 /// Helper function to generate LedgerInfoWithSignature from a set of validator signers used for testing
 #[cfg(any(test, feature = "fuzzing"))]
 pub fn generate_validator_verifier(validators: &[ValidatorSigner]) -> ValidatorVerifier {
@@ -472,35 +1494,162 @@ pub fn random_validator_verifier(
     custom_voting_power_quorum: Option<u128>,
     pseudo_random_account_address: bool,
 ) -> (Vec<ValidatorSigner>, ValidatorVerifier) {
-    let mut signers = Vec::new();
-    let mut validator_infos = vec![];
-    for i in 0..count {
-        let random_signer = if pseudo_random_account_address {
-            ValidatorSigner::from_int(i as u8)
-        } else {
-            ValidatorSigner::random([i as u8; 32])
+    let mut builder = ValidatorVerifierBuilder::new(pseudo_random_account_address);
+    builder.add_validators(count, 1);
+    if let Some(custom_voting_power_quorum) = custom_voting_power_quorum {
+        builder.quorum_voting_power(custom_voting_power_quorum);
+    }
+    builder.build()
+}
+
+/// A builder of validator verifiers and the signers of their validators for testing. Validators
+/// are added, removed and updated one by one, and unless a custom quorum voting power is set, the
+/// quorum is recomputed from the voting power of the remaining validators. The signers are
+/// deterministic (the n-th added validator always has the same signer), and the signers of
+/// removed validators aren't reused.
+#[cfg(any(test, feature = "fuzzing"))]
+#[derive(Clone, Debug)]
+pub struct ValidatorVerifierBuilder {
+    /// The validators and their voting power, in the order of their indices
+    validators: Vec<(ValidatorSigner, u64)>,
+    /// The number of validators added so far, which seeds the signer of the next one
+    num_added_validators: usize,
+    pseudo_random_account_address: bool,
+    quorum_voting_power: Option<u128>,
+}
+
+#[cfg(any(test, feature = "fuzzing"))]
+impl ValidatorVerifierBuilder {
+    /// With `pseudo_random_account_address` enabled, logs show 0 -> [0000], 1 -> [1000]
+    pub fn new(pseudo_random_account_address: bool) -> Self {
+        Self {
+            validators: vec![],
+            num_added_validators: 0,
+            pseudo_random_account_address,
+            quorum_voting_power: None,
+        }
+    }
+
+    /// Adds a validator with the given voting power, with the next deterministic signer
+    pub fn add_validator(&mut self, voting_power: u64) -> &mut Self {
+        let signer = self.next_signer();
+        self.num_added_validators += 1;
+        self.add_signer(signer, voting_power)
+    }
+
+    /// Adds `count` validators with the same voting power
+    pub fn add_validators(&mut self, count: usize, voting_power: u64) -> &mut Self {
+        for _ in 0..count {
+            self.add_validator(voting_power);
+        }
+        self
+    }
+
+    /// Adds a validator with the given signer (e.g., a validator of another verifier)
+    pub fn add_signer(&mut self, signer: ValidatorSigner, voting_power: u64) -> &mut Self {
+        assert!(
+            self.position(&signer.author()).is_none(),
+            "Validator {} was already added",
+            signer.author()
+        );
+        self.validators.push((signer, voting_power));
+        self
+    }
+
+    /// Removes the validator, which shifts the indices of the following validators
+    pub fn remove_validator(&mut self, author: &AccountAddress) -> &mut Self {
+        let position = self.expect_position(author);
+        self.validators.remove(position);
+        self
+    }
+
+    pub fn update_voting_power(&mut self, author: &AccountAddress, voting_power: u64) -> &mut Self {
+        let position = self.expect_position(author);
+        self.validators[position].1 = voting_power;
+        self
+    }
+
+    /// Sets a custom quorum voting power, rather than the default quorum of the validators
+    pub fn quorum_voting_power(&mut self, quorum_voting_power: u128) -> &mut Self {
+        self.quorum_voting_power = Some(quorum_voting_power);
+        self
+    }
+
+    /// Returns the signers of the validators, in the order of their indices
+    pub fn signers(&self) -> Vec<ValidatorSigner> {
+        self.validators
+            .iter()
+            .map(|(signer, _)| signer.clone())
+            .collect()
+    }
+
+    /// Returns the signers of the validators (in the order of their indices) and the verifier
+    pub fn build(&self) -> (Vec<ValidatorSigner>, ValidatorVerifier) {
+        let validator_infos = self
+            .validators
+            .iter()
+            .map(|(signer, voting_power)| {
+                ValidatorConsensusInfo::new(signer.author(), signer.public_key(), *voting_power)
+            })
+            .collect();
+        let verifier = match self.quorum_voting_power {
+            Some(quorum_voting_power) => ValidatorVerifier::new_with_quorum_voting_power(
+                validator_infos,
+                quorum_voting_power,
+            )
+            .expect("Unable to create testing validator verifier"),
+            None => ValidatorVerifier::new(validator_infos),
         };
-        validator_infos.push(ValidatorConsensusInfo::new(
-            random_signer.author(),
-            random_signer.public_key(),
-            1,
-        ));
-        signers.push(random_signer);
-    }
-    (signers, match custom_voting_power_quorum {
-        Some(custom_voting_power_quorum) => ValidatorVerifier::new_with_quorum_voting_power(
-            validator_infos,
-            custom_voting_power_quorum,
-        )
-        .expect("Unable to create testing validator verifier"),
-        None => ValidatorVerifier::new(validator_infos),
-    })
+        (self.signers(), verifier)
+    }
+
+    /// Returns the signer of the next added validator. The first 256 signers are the ones of
+    /// `ValidatorSigner::from_int(n)` and `ValidatorSigner::random([n; 32])`, and the next ones are
+    /// seeded with the (little-endian) bytes of the whole counter instead, so they never collide.
+    fn next_signer(&self) -> ValidatorSigner {
+        use aptos_crypto::Uniform;
+
+        let counter = (self.num_added_validators as u64).to_le_bytes();
+        if self.pseudo_random_account_address {
+            let mut address = [0; AccountAddress::LENGTH];
+            address[..counter.len()].copy_from_slice(&counter);
+            ValidatorSigner::new(
+                AccountAddress::new(address),
+                bls12381::PrivateKey::generate_for_testing(),
+            )
+        } else {
+            let seed = match u8::try_from(self.num_added_validators) {
+                Ok(seed) => [seed; 32],
+                Err(_) => {
+                    let mut seed = [0; 32];
+                    seed[..counter.len()].copy_from_slice(&counter);
+                    seed
+                },
+            };
+            ValidatorSigner::random(seed)
+        }
+    }
+
+    fn position(&self, author: &AccountAddress) -> Option<usize> {
+        self.validators
+            .iter()
+            .position(|(signer, _)| signer.author() == *author)
+    }
+
+    fn expect_position(&self, author: &AccountAddress) -> usize {
+        self.position(author)
+            .unwrap_or_else(|| panic!("Unknown validator {}", author))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::validator_signer::ValidatorSigner;
+    use crate::{
+        aggregate_signature::{SignatureAggregator, WeightedPartialSignatures},
+        proptest_types::{arb_invalid_certificate, arb_valid_certificate, CertificateFault},
+        validator_signer::ValidatorSigner,
+    };
     use aptos_crypto::test_utils::{TestAptosCrypto, TEST_SEED};
     use proptest::{collection::vec, prelude::*};
     use std::collections::BTreeMap;
@@ -547,6 +1696,47 @@ mod tests {
         }
     }
 
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20))]
+
+        #[test]
+        fn test_valid_certificates(certificate in arb_valid_certificate()) {
+            prop_assert_eq!(
+                certificate
+                    .verifier
+                    .verify_multi_signatures(&certificate.ledger_info, &certificate.multi_signature),
+                Ok(())
+            );
+            prop_assert!(certificate
+                .ledger_info_with_signatures()
+                .verify_signatures(&certificate.verifier)
+                .is_ok());
+        }
+
+        #[test]
+        fn test_invalid_certificates(certificate in arb_invalid_certificate()) {
+            let result = certificate
+                .verifier
+                .verify_multi_signatures(&certificate.ledger_info, &certificate.multi_signature);
+            match certificate.fault.unwrap() {
+                CertificateFault::TooLittleVotingPower => prop_assert!(matches!(
+                    result,
+                    Err(VerifyError::TooLittleVotingPower { .. })
+                )),
+                CertificateFault::WrongMessage => {
+                    prop_assert_eq!(result, Err(VerifyError::InvalidMultiSignature))
+                },
+                CertificateFault::MissingSignature => prop_assert!(matches!(
+                    result,
+                    Err(VerifyError::InvalidMultiSignature | VerifyError::EmptySignature)
+                )),
+                CertificateFault::UnknownVoter => {
+                    prop_assert_eq!(result, Err(VerifyError::InvalidBitVec))
+                },
+            }
+        }
+    }
+
     #[test]
     fn test_validator() {
         let validator_signer = ValidatorSigner::random(TEST_SEED);
@@ -574,6 +1764,1212 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_batch_verify() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut signatures: Vec<_> = validator_signers
+            .iter()
+            .map(|signer| (signer.author(), signer.sign(&dummy_struct).unwrap()))
+            .collect();
+        assert_eq!(
+            validator_verifier.batch_verify(&dummy_struct, &signatures),
+            Ok(())
+        );
+        assert_eq!(validator_verifier.batch_verify(&dummy_struct, &[]), Ok(()));
+
+        // A signature on a different message fails the whole batch
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        signatures[2].1 = validator_signers[2].sign(&other_struct).unwrap();
+        assert_eq!(
+            validator_verifier.batch_verify(&dummy_struct, &signatures),
+            Err(VerifyError::FailedToBatchVerify)
+        );
+
+        // So does a signature by an unknown author
+        let unknown_validator_signer = ValidatorSigner::random([1; 32]);
+        signatures[2] = (
+            unknown_validator_signer.author(),
+            unknown_validator_signer.sign(&dummy_struct).unwrap(),
+        );
+        assert_eq!(
+            validator_verifier.batch_verify(&dummy_struct, &signatures),
+            Err(VerifyError::UnknownAuthor)
+        );
+    }
+
+    #[test]
+    fn test_parallel_multi_signatures() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(100, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut partial_sig = PartialSignatures::empty();
+        for validator in validator_signers.iter().take(70) {
+            partial_sig.add_signature(validator.author(), validator.sign(&dummy_struct).unwrap());
+        }
+
+        // The parallel aggregation matches the sequential one
+        let multi_sig = validator_verifier
+            .aggregate_signatures_par(&partial_sig)
+            .unwrap();
+        assert_eq!(
+            multi_sig,
+            validator_verifier
+                .aggregate_signatures(&partial_sig)
+                .unwrap()
+        );
+        assert_eq!(
+            validator_verifier.verify_multi_signatures_par(&dummy_struct, &multi_sig),
+            Ok(())
+        );
+
+        // A signature on a different message invalidates the multi-signature
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        partial_sig.add_signature(
+            validator_signers[0].author(),
+            validator_signers[0].sign(&other_struct).unwrap(),
+        );
+        let multi_sig = validator_verifier
+            .aggregate_signatures_par(&partial_sig)
+            .unwrap();
+        assert_eq!(
+            validator_verifier.verify_multi_signatures_par(&dummy_struct, &multi_sig),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+    }
+
+    #[test]
+    fn test_aggregated_key_cache() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let multi_sig = |signers: &[ValidatorSigner], message: &TestAptosCrypto| {
+            let mut partial_sig = PartialSignatures::empty();
+            for validator in signers {
+                partial_sig.add_signature(validator.author(), validator.sign(message).unwrap());
+            }
+            validator_verifier
+                .aggregate_signatures(&partial_sig)
+                .unwrap()
+        };
+
+        // The aggregated key of a quorum is cached once, and shared by the clones of the verifier
+        let first_quorum = multi_sig(&validator_signers[..3], &dummy_struct);
+        let cloned_verifier = validator_verifier.clone();
+        for verifier in [&validator_verifier, &cloned_verifier] {
+            assert_eq!(
+                verifier.verify_multi_signatures(&dummy_struct, &first_quorum),
+                Ok(())
+            );
+        }
+        assert_eq!(validator_verifier.aggregated_key_cache.len(), 1);
+        assert_eq!(validator_verifier, cloned_verifier);
+
+        // Another quorum gets its own entry
+        let second_quorum = multi_sig(&validator_signers[1..], &dummy_struct);
+        assert_eq!(
+            validator_verifier.verify_multi_signatures_par(&dummy_struct, &second_quorum),
+            Ok(())
+        );
+        assert_eq!(validator_verifier.aggregated_key_cache.len(), 2);
+
+        // A cached key doesn't make an invalid multi-signature verify
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        let invalid_quorum = multi_sig(&validator_signers[..3], &other_struct);
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &invalid_quorum),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+        assert_eq!(validator_verifier.aggregated_key_cache.len(), 2);
+    }
+
+    #[test]
+    fn test_verified_multi_signature_cache() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        assert!(validator_verifier.verified_multi_signature_cache.is_none());
+        let validator_verifier = validator_verifier.with_verified_multi_signature_cache(2);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        let multi_sig = |signers: &[ValidatorSigner], message: &TestAptosCrypto| {
+            let mut partial_sig = PartialSignatures::empty();
+            for validator in signers {
+                partial_sig.add_signature(validator.author(), validator.sign(message).unwrap());
+            }
+            validator_verifier
+                .aggregate_signatures(&partial_sig)
+                .unwrap()
+        };
+        let cache_len = || {
+            validator_verifier
+                .verified_multi_signature_cache
+                .as_ref()
+                .unwrap()
+                .len()
+        };
+
+        // Verified multi-signatures are cached once, and shared by the clones of the verifier
+        let quorum = multi_sig(&validator_signers[..3], &dummy_struct);
+        let cloned_verifier = validator_verifier.clone();
+        for verifier in [&validator_verifier, &cloned_verifier] {
+            assert_eq!(
+                verifier.verify_multi_signatures(&dummy_struct, &quorum),
+                Ok(())
+            );
+        }
+        assert_eq!(cache_len(), 1);
+
+        // Invalid multi-signatures aren't cached, and don't verify for cached voters and messages
+        let invalid_quorum = multi_sig(&validator_signers[..3], &other_struct);
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &invalid_quorum),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+        assert_eq!(cache_len(), 1);
+
+        // Cached multi-signatures are still checked for voting power
+        assert_eq!(
+            validator_verifier.verify_aggregate_signature_with_threshold(&dummy_struct, &quorum, 4),
+            Err(VerifyError::TooLittleVotingPower {
+                voting_power: 3,
+                expected_voting_power: 4,
+            })
+        );
+
+        // The cache is bounded
+        for signers in [&validator_signers[1..], &validator_signers[..]] {
+            let quorum = multi_sig(signers, &other_struct);
+            assert_eq!(
+                validator_verifier.verify_multi_signatures(&other_struct, &quorum),
+                Ok(())
+            );
+        }
+        assert_eq!(cache_len(), 2);
+    }
+
+    #[test]
+    fn test_verify_raw() {
+        use aptos_crypto::SigningKey;
+
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let message = b"an externally framed message";
+        let mut partial_signatures = PartialSignatures::empty();
+        for signer in &validator_signers[..3] {
+            let signature = signer.private_key().sign_arbitrary_message(message);
+            assert_eq!(
+                validator_verifier.verify_raw(signer.author(), message, &signature),
+                Ok(())
+            );
+            assert_eq!(
+                validator_verifier.verify_raw(signer.author(), b"another message", &signature),
+                Err(VerifyError::InvalidMultiSignature)
+            );
+            partial_signatures.add_signature(signer.author(), signature);
+        }
+        let unknown_signer = ValidatorSigner::random([7; 32]);
+        assert_eq!(
+            validator_verifier.verify_raw(
+                unknown_signer.author(),
+                message,
+                &unknown_signer.private_key().sign_arbitrary_message(message)
+            ),
+            Err(VerifyError::UnknownAuthor)
+        );
+
+        let multi_signature = validator_verifier
+            .aggregate_signatures(&partial_signatures)
+            .unwrap();
+        assert_eq!(
+            validator_verifier.verify_multi_signatures_raw(message, &multi_signature),
+            Ok(())
+        );
+        assert_eq!(
+            validator_verifier.verify_multi_signatures_raw(b"another message", &multi_signature),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+
+        // Typed messages are signed as their signing message
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let signer = &validator_signers[0];
+        assert_eq!(
+            validator_verifier.verify_raw(
+                signer.author(),
+                &signing_message(&dummy_struct).unwrap(),
+                &signer.sign(&dummy_struct).unwrap()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_merge_multi_signatures() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let multi_signature = |signers: &[ValidatorSigner]| {
+            let mut partial_signatures = PartialSignatures::empty();
+            for signer in signers {
+                partial_signatures
+                    .add_signature(signer.author(), signer.sign(&dummy_struct).unwrap());
+            }
+            validator_verifier
+                .aggregate_signatures(&partial_signatures)
+                .unwrap()
+        };
+
+        // Partial quorums combine into the multi-signature of all their voters
+        let first = multi_signature(&validator_signers[..1]);
+        let second = multi_signature(&validator_signers[1..3]);
+        let merged = first.merge(&second, &validator_verifier).unwrap();
+        assert_eq!(merged, multi_signature(&validator_signers[..3]));
+        assert_eq!(merged, second.merge(&first, &validator_verifier).unwrap());
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &merged),
+            Ok(())
+        );
+
+        // Merging with an empty multi-signature doesn't change it
+        assert_eq!(
+            merged.merge(&AggregateSignature::empty(), &validator_verifier),
+            Ok(merged.clone())
+        );
+
+        // Voters can't be counted twice
+        assert_eq!(
+            merged.merge(
+                &multi_signature(&validator_signers[2..]),
+                &validator_verifier
+            ),
+            Err(VerifyError::DuplicateAuthor)
+        );
+
+        // Voters without a signature can't be merged
+        let unsigned = AggregateSignature::new(second.get_voters_bitvec().clone(), None);
+        assert_eq!(
+            first.merge(&unsigned, &validator_verifier),
+            Err(VerifyError::EmptySignature)
+        );
+    }
+
+    #[test]
+    fn test_signature_aggregator() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut aggregator = SignatureAggregator::new(&validator_verifier);
+        let mut partial_sig = PartialSignatures::empty();
+        assert!(!aggregator.has_quorum());
+
+        for (i, validator) in validator_signers.iter().take(3).enumerate() {
+            let signature = validator.sign(&dummy_struct).unwrap();
+            assert_eq!(
+                aggregator.add_signature(&validator_verifier, validator.author(), &signature),
+                Ok(i as u128 + 1)
+            );
+            // Duplicate signatures don't add voting power
+            assert_eq!(
+                aggregator.add_signature(&validator_verifier, validator.author(), &signature),
+                Ok(i as u128 + 1)
+            );
+            partial_sig.add_signature(validator.author(), signature);
+        }
+        assert!(aggregator.has_quorum());
+        assert_eq!(aggregator.get_num_voters(), 3);
+
+        // The multi-signature matches the one aggregated from the partial signatures
+        let multi_sig = aggregator.multi_signature();
+        assert_eq!(
+            multi_sig,
+            validator_verifier
+                .aggregate_signatures(&partial_sig)
+                .unwrap()
+        );
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &multi_sig),
+            Ok(())
+        );
+
+        let unknown_validator_signer = ValidatorSigner::random([4; 32]);
+        assert_eq!(
+            aggregator.add_signature(
+                &validator_verifier,
+                unknown_validator_signer.author(),
+                &unknown_validator_signer.sign(&dummy_struct).unwrap()
+            ),
+            Err(VerifyError::UnknownAuthor)
+        );
+    }
+
+    #[test]
+    fn test_weighted_partial_signatures() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut partial_sig = WeightedPartialSignatures::empty();
+        assert_eq!(partial_sig.remaining_power_needed(&validator_verifier), 3);
+
+        for validator in validator_signers.iter().take(2) {
+            partial_sig
+                .add_signature(
+                    &validator_verifier,
+                    validator.author(),
+                    validator.sign(&dummy_struct).unwrap(),
+                )
+                .unwrap();
+        }
+        assert_eq!(partial_sig.voting_power(), 2);
+        assert_eq!(partial_sig.remaining_power_needed(&validator_verifier), 1);
+
+        // Duplicate and unknown authors are rejected
+        let validator = &validator_signers[0];
+        assert_eq!(
+            partial_sig.add_signature(
+                &validator_verifier,
+                validator.author(),
+                validator.sign(&dummy_struct).unwrap()
+            ),
+            Err(VerifyError::DuplicateAuthor)
+        );
+        let unknown_validator_signer = ValidatorSigner::random([4; 32]);
+        assert_eq!(
+            partial_sig.add_signature(
+                &validator_verifier,
+                unknown_validator_signer.author(),
+                unknown_validator_signer.sign(&dummy_struct).unwrap()
+            ),
+            Err(VerifyError::UnknownAuthor)
+        );
+        assert_eq!(partial_sig.voting_power(), 2);
+
+        // The voting power matches the one checked by the verifier
+        let validator = &validator_signers[2];
+        assert_eq!(
+            partial_sig.add_signature(
+                &validator_verifier,
+                validator.author(),
+                validator.sign(&dummy_struct).unwrap()
+            ),
+            Ok(3)
+        );
+        assert_eq!(partial_sig.remaining_power_needed(&validator_verifier), 0);
+        assert_eq!(
+            validator_verifier
+                .check_voting_power(partial_sig.partial_signatures().signatures().keys()),
+            Ok(())
+        );
+
+        partial_sig.remove_signature(&validator_verifier, validator.author());
+        partial_sig.remove_signature(&validator_verifier, validator.author());
+        assert_eq!(partial_sig.voting_power(), 2);
+    }
+
+    #[test]
+    fn test_validator_set_diff() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let info = |signer: &ValidatorSigner, voting_power| {
+            ValidatorConsensusInfo::new(signer.author(), signer.public_key(), voting_power)
+        };
+        assert!(validator_verifier.diff(&validator_verifier).is_empty());
+
+        // Remove the first validator, add a new one, rotate the key of the second one and change
+        // the voting power of the third one
+        let new_signer = ValidatorSigner::random([4; 32]);
+        let rotated_signer = ValidatorSigner::new(
+            validator_signers[1].author(),
+            new_signer.private_key().clone(),
+        );
+        let new_verifier = ValidatorVerifier::new(vec![
+            info(&new_signer, 1),
+            info(&validator_signers[3], 1),
+            info(&validator_signers[2], 5),
+            info(&rotated_signer, 1),
+        ]);
+
+        let diff = validator_verifier.diff(&new_verifier);
+        assert_eq!(diff.added, vec![info(&new_signer, 1)]);
+        assert_eq!(diff.removed, vec![info(&validator_signers[0], 1)]);
+        assert_eq!(diff.changed, vec![
+            ValidatorChange {
+                old: info(&validator_signers[1], 1),
+                new: info(&rotated_signer, 1),
+            },
+            ValidatorChange {
+                old: info(&validator_signers[2], 1),
+                new: info(&validator_signers[2], 5),
+            },
+        ]);
+        assert!(diff.changed[0].public_key_changed() && !diff.changed[0].voting_power_changed());
+        assert!(!diff.changed[1].public_key_changed() && diff.changed[1].voting_power_changed());
+
+        // The reverse diff swaps the added and removed validators
+        let reverse_diff = new_verifier.diff(&validator_verifier);
+        assert_eq!(reverse_diff.added, diff.removed);
+        assert_eq!(reverse_diff.removed, diff.added);
+    }
+
+    #[test]
+    fn test_validator_verifier_format() {
+        // The consensus keys of the validators are serialized as before they were tagged with
+        // their scheme
+        #[derive(Serialize)]
+        #[serde(rename = "ValidatorConsensusInfo")]
+        struct LegacyValidatorConsensusInfo {
+            address: AccountAddress,
+            public_key: PublicKey,
+            voting_power: u64,
+        }
+
+        let (validator_signers, validator_verifier) = random_validator_verifier(3, None, false);
+        let legacy_infos: Vec<_> = validator_signers
+            .iter()
+            .map(|signer| LegacyValidatorConsensusInfo {
+                address: signer.author(),
+                public_key: signer.public_key(),
+                voting_power: 1,
+            })
+            .collect();
+        let bytes = bcs::to_bytes(&validator_verifier).unwrap();
+        assert_eq!(bytes, bcs::to_bytes(&legacy_infos).unwrap());
+        assert_eq!(
+            bcs::from_bytes::<ValidatorVerifier>(&bytes).unwrap(),
+            validator_verifier
+        );
+    }
+
+    #[test]
+    fn test_quorum_policies() {
+        let validator_signers: Vec<_> = (0..4).map(|i| ValidatorSigner::random([i; 32])).collect();
+        let validator_infos: Vec<_> = validator_signers
+            .iter()
+            .map(|signer| ValidatorConsensusInfo::new(signer.author(), signer.public_key(), 3))
+            .collect();
+        let quorum_voting_power = |quorum_policy| {
+            ValidatorVerifier::new_with_quorum_policy(validator_infos.clone(), quorum_policy)
+                .map(|verifier| verifier.quorum_voting_power())
+        };
+
+        // The default policy matches the verifier's default quorum
+        assert_eq!(
+            quorum_voting_power(QuorumPolicy::default()).unwrap(),
+            ValidatorVerifier::new(validator_infos.clone()).quorum_voting_power()
+        );
+        assert_eq!(quorum_voting_power(QuorumPolicy::TWO_THIRDS).unwrap(), 9);
+        assert_eq!(quorum_voting_power(QuorumPolicy::ONE_THIRD).unwrap(), 5);
+        assert_eq!(quorum_voting_power(QuorumPolicy::Absolute(12)).unwrap(), 12);
+        assert!(quorum_voting_power(QuorumPolicy::Absolute(13)).is_err());
+        assert!(quorum_voting_power(QuorumPolicy::Fraction {
+            numerator: 3,
+            denominator: 3
+        })
+        .is_err());
+
+        // Two validators aren't a quorum of the verifier, but are more than a third of it
+        let validator_verifier = ValidatorVerifier::new(validator_infos.clone());
+        let authors: Vec<_> = validator_signers
+            .iter()
+            .take(2)
+            .map(|signer| signer.author())
+            .collect();
+        assert_eq!(
+            validator_verifier.check_voting_power(authors.iter()),
+            Err(VerifyError::TooLittleVotingPower {
+                voting_power: 6,
+                expected_voting_power: 9,
+            })
+        );
+        assert_eq!(
+            validator_verifier
+                .check_voting_power_with_policy(authors.iter(), QuorumPolicy::ONE_THIRD),
+            Ok(())
+        );
+        assert_eq!(
+            validator_verifier
+                .check_voting_power_with_policy(authors.iter(), QuorumPolicy::Absolute(13)),
+            Err(VerifyError::InvalidQuorumPolicy)
+        );
+    }
+
+    #[test]
+    fn test_versioned_format() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(3, Some(2), false);
+        let bytes = validator_verifier.to_versioned_bytes();
+
+        // The layout of version 2 must not change, so that persisted verifiers remain readable
+        let mut expected_bytes = vec![2];
+        expected_bytes.extend(16u32.to_le_bytes());
+        expected_bytes.extend(2u128.to_le_bytes());
+        expected_bytes.extend(3u32.to_le_bytes());
+        for signer in &validator_signers {
+            expected_bytes.extend(91u32.to_le_bytes());
+            expected_bytes.extend(signer.author().to_vec());
+            expected_bytes.push(ConsensusKeyScheme::Bls12381.tag());
+            expected_bytes.extend(48u16.to_le_bytes());
+            expected_bytes.extend(signer.public_key().to_bytes());
+            expected_bytes.extend(1u64.to_le_bytes());
+        }
+        assert_eq!(bytes, expected_bytes);
+
+        // Unlike the serde format, the quorum voting power is kept
+        let deserialized_verifier = ValidatorVerifier::from_versioned_bytes(&bytes).unwrap();
+        assert_eq!(deserialized_verifier, validator_verifier);
+        assert_eq!(deserialized_verifier.quorum_voting_power(), 2);
+
+        // Verifiers persisted in version 1 remain readable
+        let mut v1_bytes = vec![1];
+        v1_bytes.extend(2u128.to_le_bytes());
+        v1_bytes.extend(3u32.to_le_bytes());
+        for signer in &validator_signers {
+            v1_bytes.extend(signer.author().to_vec());
+            v1_bytes.extend(signer.public_key().to_bytes());
+            v1_bytes.extend(1u64.to_le_bytes());
+        }
+        assert_eq!(
+            ValidatorVerifier::from_versioned_bytes(&v1_bytes).unwrap(),
+            validator_verifier
+        );
+
+        // Truncated or extended bytes are rejected, as well as unknown schemes
+        assert!(ValidatorVerifier::from_versioned_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut extended_bytes = bytes.clone();
+        extended_bytes.push(0);
+        assert!(ValidatorVerifier::from_versioned_bytes(&extended_bytes).is_err());
+        let mut unknown_scheme_bytes = bytes.clone();
+        unknown_scheme_bytes[1 + 4 + 16 + 4 + 4 + AccountAddress::LENGTH] = 42;
+        assert!(ValidatorVerifier::from_versioned_bytes(&unknown_scheme_bytes).is_err());
+        assert!(ValidatorVerifier::from_versioned_bytes(&[0]).is_err());
+        assert!(ValidatorVerifier::from_versioned_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn test_versioned_format_of_newer_versions() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(3, Some(2), false);
+
+        // A newer version that appends fields to the header and the records, and a section
+        // after the records
+        let mut newer_bytes = vec![VALIDATOR_VERIFIER_FORMAT_VERSION + 1];
+        newer_bytes.extend(20u32.to_le_bytes());
+        newer_bytes.extend(2u128.to_le_bytes());
+        newer_bytes.extend(u32::MAX.to_le_bytes());
+        newer_bytes.extend(3u32.to_le_bytes());
+        for signer in &validator_signers {
+            newer_bytes.extend(99u32.to_le_bytes());
+            newer_bytes.extend(signer.author().to_vec());
+            newer_bytes.push(ConsensusKeyScheme::Bls12381.tag());
+            newer_bytes.extend(48u16.to_le_bytes());
+            newer_bytes.extend(signer.public_key().to_bytes());
+            newer_bytes.extend(1u64.to_le_bytes());
+            newer_bytes.extend(7u64.to_le_bytes());
+        }
+        newer_bytes.extend([1, 2, 3]);
+
+        // The fields of the current version are read, and the others are skipped
+        let deserialized_verifier = ValidatorVerifier::from_versioned_bytes(&newer_bytes).unwrap();
+        assert_eq!(deserialized_verifier, validator_verifier);
+        assert_eq!(deserialized_verifier.quorum_voting_power(), 2);
+
+        // But the fields of the current version must still be complete
+        assert!(ValidatorVerifier::from_versioned_bytes(&newer_bytes[..30]).is_err());
+    }
+
+    #[test]
+    fn test_single_voter_multi_signature() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, Some(1), false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut partial_sig = PartialSignatures::empty();
+        partial_sig.add_signature(
+            validator_signers[2].author(),
+            validator_signers[2].sign(&dummy_struct).unwrap(),
+        );
+        let multi_sig = validator_verifier
+            .aggregate_signatures(&partial_sig)
+            .unwrap();
+
+        // The key of the single voter is used as is, without being aggregated or cached
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &multi_sig),
+            Ok(())
+        );
+        assert_eq!(validator_verifier.aggregated_key_cache.len(), 0);
+
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&other_struct, &multi_sig),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+    }
+
+    #[test]
+    fn test_find_invalid_signatures() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(8, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        let mut partial_sig = PartialSignatures::empty();
+        for validator in &validator_signers {
+            partial_sig.add_signature(validator.author(), validator.sign(&dummy_struct).unwrap());
+        }
+        let multi_sig = validator_verifier
+            .aggregate_and_verify_multi_signature(&dummy_struct, &partial_sig, true)
+            .unwrap();
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &multi_sig),
+            Ok(())
+        );
+        assert_eq!(
+            validator_verifier.find_invalid_signatures(&dummy_struct, &partial_sig),
+            Ok(vec![])
+        );
+
+        // Replace two signatures by signatures on another message
+        let mut invalid_authors = vec![];
+        for validator in [&validator_signers[1], &validator_signers[6]] {
+            partial_sig.remove_signature(validator.author());
+            partial_sig.add_signature(validator.author(), validator.sign(&other_struct).unwrap());
+            invalid_authors.push(validator.author());
+        }
+        invalid_authors.sort();
+
+        assert_eq!(
+            validator_verifier.aggregate_and_verify_multi_signature(
+                &dummy_struct,
+                &partial_sig,
+                false
+            ),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+        assert_eq!(
+            validator_verifier.aggregate_and_verify_multi_signature(
+                &dummy_struct,
+                &partial_sig,
+                true
+            ),
+            Err(VerifyError::InvalidSignatures(invalid_authors.clone()))
+        );
+        assert_eq!(
+            validator_verifier.find_invalid_signatures(&dummy_struct, &partial_sig),
+            Ok(invalid_authors)
+        );
+    }
+
+    #[test]
+    fn test_verify_with_threshold() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(7, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut partial_sig = PartialSignatures::empty();
+        for validator in validator_signers.iter().take(3) {
+            partial_sig.add_signature(validator.author(), validator.sign(&dummy_struct).unwrap());
+        }
+        let multi_sig = validator_verifier
+            .aggregate_signatures(&partial_sig)
+            .unwrap();
+
+        // Three out of seven validators are more than a third, but not a quorum
+        assert_eq!(
+            validator_verifier.verify_multi_signatures(&dummy_struct, &multi_sig),
+            Err(VerifyError::TooLittleVotingPower {
+                voting_power: 3,
+                expected_voting_power: 5,
+            })
+        );
+        assert_eq!(
+            validator_verifier.verify_aggregate_signature_with_threshold(
+                &dummy_struct,
+                &multi_sig,
+                3
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validator_verifier.verify_aggregate_signature_with_threshold(
+                &dummy_struct,
+                &multi_sig,
+                4
+            ),
+            Err(VerifyError::TooLittleVotingPower {
+                voting_power: 3,
+                expected_voting_power: 4,
+            })
+        );
+
+        let other_struct = TestAptosCrypto("Hello, Aptos".to_string());
+        assert_eq!(
+            validator_verifier.verify_aggregate_signature_with_threshold(
+                &other_struct,
+                &multi_sig,
+                3
+            ),
+            Err(VerifyError::InvalidMultiSignature)
+        );
+    }
+
+    #[test]
+    fn test_validator_indices() {
+        let validator_signers: Vec<_> = (0..5).map(|i| ValidatorSigner::random([i; 32])).collect();
+        // The on-chain validator set isn't ordered by validator index
+        let validator_set = ValidatorSet::new(
+            [3, 0, 4, 1, 2]
+                .iter()
+                .map(|index| {
+                    let signer = &validator_signers[*index];
+                    crate::validator_info::ValidatorInfo::new_with_test_network_keys(
+                        signer.author(),
+                        signer.public_key(),
+                        1,
+                        *index as u64,
+                    )
+                })
+                .collect(),
+        );
+        let validator_verifier = ValidatorVerifier::try_from(&validator_set).unwrap();
+
+        for info in validator_set.payload() {
+            let index = validator_verifier
+                .address_to_index(&info.account_address)
+                .unwrap();
+            assert_eq!(index as u64, info.config().validator_index);
+            assert_eq!(
+                validator_verifier.index_to_info(index).unwrap().address(),
+                info.account_address
+            );
+        }
+        assert_eq!(
+            validator_verifier
+                .get_ordered_account_addresses_iter()
+                .collect::<Vec<_>>(),
+            validator_signers
+                .iter()
+                .map(|signer| signer.author())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            validator_verifier.address_to_index(&ValidatorSigner::random([5; 32]).author()),
+            None
+        );
+        assert!(validator_verifier.index_to_info(5).is_none());
+
+        // The indices are the bits of the voters in multi-signatures
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let mut partial_sig = PartialSignatures::empty();
+        partial_sig.add_signature(
+            validator_signers[3].author(),
+            validator_signers[3].sign(&dummy_struct).unwrap(),
+        );
+        let multi_sig = validator_verifier
+            .aggregate_signatures(&partial_sig)
+            .unwrap();
+        assert_eq!(
+            multi_sig
+                .get_voters_bitvec()
+                .iter_ones()
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn test_with_excluded() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(4, None, false);
+        let excluded = validator_signers[0].author();
+        let excluded_verifier = validator_verifier.with_excluded(&[excluded]).unwrap();
+
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+        let partial_signatures = |signers: &[ValidatorSigner]| {
+            PartialSignatures::new(
+                signers
+                    .iter()
+                    .map(|signer| (signer.author(), signer.sign(&dummy_struct).unwrap()))
+                    .collect(),
+            )
+        };
+
+        // The excluded validator is an unknown author
+        let signature = validator_signers[0].sign(&dummy_struct).unwrap();
+        assert_eq!(
+            excluded_verifier.verify(excluded, &dummy_struct, &signature),
+            Err(VerifyError::UnknownAuthor)
+        );
+        assert_eq!(
+            validator_verifier.verify(excluded, &dummy_struct, &signature),
+            Ok(())
+        );
+        assert_eq!(
+            excluded_verifier.aggregate_signatures(&partial_signatures(&validator_signers)),
+            Err(VerifyError::UnknownAuthor)
+        );
+        let multi_signature = validator_verifier
+            .aggregate_signatures(&partial_signatures(&validator_signers))
+            .unwrap();
+        assert_eq!(
+            excluded_verifier.verify_multi_signatures(&dummy_struct, &multi_signature),
+            Err(VerifyError::UnknownAuthor)
+        );
+
+        // Only the lookups by address ignore the excluded validator
+        assert_eq!(excluded_verifier.get_validator_info(&excluded), None);
+        assert_eq!(excluded_verifier.get_public_key(&excluded), None);
+        assert_eq!(excluded_verifier.get_voting_power(&excluded), None);
+        assert_eq!(excluded_verifier.address_to_index(&excluded), None);
+        let excluded_index = validator_verifier.address_to_index(&excluded).unwrap();
+        assert_eq!(
+            excluded_verifier.index_to_info(excluded_index),
+            validator_verifier.index_to_info(excluded_index)
+        );
+        assert_eq!(excluded_verifier.len(), 4);
+        assert!(excluded_verifier
+            .get_ordered_account_addresses_iter()
+            .eq(validator_verifier.get_ordered_account_addresses_iter()));
+        assert_eq!(
+            excluded_verifier.total_voting_power(),
+            validator_verifier.total_voting_power()
+        );
+        for signer in &validator_signers[1..] {
+            assert_eq!(
+                excluded_verifier.address_to_index(&signer.author()),
+                validator_verifier.address_to_index(&signer.author())
+            );
+        }
+
+        // The other validators can still reach the quorum
+        assert_eq!(excluded_verifier.quorum_voting_power(), 3);
+        let multi_signature = excluded_verifier
+            .aggregate_signatures(&partial_signatures(&validator_signers[1..]))
+            .unwrap();
+        assert_eq!(
+            excluded_verifier.verify_multi_signatures(&dummy_struct, &multi_signature),
+            Ok(())
+        );
+
+        // Unless too many validators are excluded
+        assert_eq!(
+            excluded_verifier.with_excluded(&[validator_signers[1].author()]),
+            Err(VerifyError::TooLittleVotingPower {
+                voting_power: 2,
+                expected_voting_power: 3,
+            })
+        );
+
+        // A single validator is unknown once excluded
+        let (validator_signers, validator_verifier) = random_validator_verifier(1, Some(0), false);
+        let author = validator_signers[0].author();
+        let signature = validator_signers[0].sign(&dummy_struct).unwrap();
+        assert_eq!(
+            validator_verifier.with_excluded(&[author]).unwrap().verify(
+                author,
+                &dummy_struct,
+                &signature
+            ),
+            Err(VerifyError::UnknownAuthor)
+        );
+    }
+
+    #[test]
+    fn test_json_summary() {
+        let (validator_signers, validator_verifier) = ValidatorVerifierBuilder::new(false)
+            .add_validators(2, 1)
+            .add_validator(2)
+            .build();
+
+        let summary: ValidatorVerifierSummary =
+            serde_json::from_str(&validator_verifier.to_json_summary()).unwrap();
+        assert_eq!(summary, validator_verifier.summary());
+        assert_eq!(
+            summary.validators,
+            validator_signers
+                .iter()
+                .zip([(1, 25.0), (1, 25.0), (2, 50.0)])
+                .map(
+                    |(signer, (voting_power, voting_power_percentage))| ValidatorSummary {
+                        address: signer.author(),
+                        voting_power,
+                        voting_power_percentage,
+                    }
+                )
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (
+                summary.total_voting_power,
+                summary.quorum_voting_power,
+                summary.quorum_voting_power_percentage
+            ),
+            (4, 3, 75.0)
+        );
+
+        // The percentages of an empty verifier are zero
+        let summary = ValidatorVerifier::new(vec![]).summary();
+        assert!(summary.validators.is_empty());
+        assert_eq!(summary.quorum_voting_power_percentage, 0.0);
+    }
+
+    #[test]
+    fn test_validator_set_conversion() {
+        let validator_signers: Vec<_> = (0..4).map(|i| ValidatorSigner::random([i; 32])).collect();
+        let validator_set = |validators: &[(usize, u64, u64)]| {
+            ValidatorSet::new(
+                validators
+                    .iter()
+                    .map(|(signer, voting_power, index)| {
+                        let signer = &validator_signers[*signer];
+                        crate::validator_info::ValidatorInfo::new_with_test_network_keys(
+                            signer.author(),
+                            signer.public_key(),
+                            *voting_power,
+                            *index,
+                        )
+                    })
+                    .collect(),
+            )
+        };
+
+        // Validators without voting power keep their index
+        let valid_set = validator_set(&[(2, 1, 2), (0, 1, 0), (1, 0, 1), (3, 1, 3)]);
+        let validator_verifier = ValidatorVerifier::try_from(&valid_set).unwrap();
+        assert_eq!(
+            validator_verifier
+                .get_ordered_account_addresses_iter()
+                .collect::<Vec<_>>(),
+            validator_signers
+                .iter()
+                .map(|signer| signer.author())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            validator_verifier.get_voting_power(&validator_signers[1].author()),
+            Some(0)
+        );
+        assert_eq!(
+            validator_verifier.address_to_index(&validator_signers[3].author()),
+            Some(3)
+        );
+
+        // Malformed validator sets are rejected rather than panicking
+        for invalid_set in [
+            // Duplicate index
+            validator_set(&[(0, 1, 0), (1, 1, 0)]),
+            // Missing index
+            validator_set(&[(0, 1, 0), (1, 1, 2)]),
+            // Duplicate address
+            validator_set(&[(0, 1, 0), (0, 1, 1)]),
+        ] {
+            assert!(ValidatorVerifier::try_from(&invalid_set).is_err());
+        }
+    }
+
+    #[test]
+    fn test_borrowing_accessors() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(2, None, false);
+        let author = validator_signers[1].author();
+
+        let info = validator_verifier.get_validator_info(&author).unwrap();
+        assert_eq!(
+            (info.address(), info.voting_power()),
+            (
+                author,
+                validator_verifier.get_voting_power(&author).unwrap()
+            )
+        );
+        assert_eq!(
+            validator_verifier.public_key_ref(&author),
+            Some(&validator_signers[1].public_key())
+        );
+        assert_eq!(
+            validator_verifier.public_key_ref(&author).cloned(),
+            validator_verifier.get_public_key(&author)
+        );
+
+        let unknown_author = ValidatorSigner::random([2; 32]).author();
+        assert_eq!(validator_verifier.get_validator_info(&unknown_author), None);
+        assert_eq!(validator_verifier.public_key_ref(&unknown_author), None);
+    }
+
+    #[test]
+    fn test_validators_by_power() {
+        let (_, validator_verifier) = ValidatorVerifierBuilder::new(false)
+            .add_validator(2)
+            .add_validator(5)
+            .add_validator(2)
+            .add_validator(1)
+            .build();
+        let indices = |validators: Vec<&ValidatorConsensusInfo>| {
+            validators
+                .into_iter()
+                .map(|info| validator_verifier.address_to_index(&info.address).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // Ties are in the order of the indices
+        assert_eq!(
+            indices(validator_verifier.get_validators_by_power_desc().collect()),
+            vec![1, 0, 2, 3]
+        );
+        assert_eq!(
+            indices(validator_verifier.top_k_by_power(2).collect()),
+            vec![1, 0]
+        );
+        assert_eq!(validator_verifier.top_k_by_power(10).count(), 4);
+        assert_eq!(
+            validator_verifier
+                .get_cumulative_voting_powers_desc()
+                .map(|(_, cumulative_voting_power)| cumulative_voting_power)
+                .collect::<Vec<_>>(),
+            vec![5, 7, 9, 10]
+        );
+
+        // The quorum (of 7) needs the two validators with the most voting power
+        assert_eq!(
+            validator_verifier
+                .num_validators_for_voting_power(validator_verifier.quorum_voting_power()),
+            Some(2)
+        );
+        assert_eq!(
+            validator_verifier.num_validators_for_voting_power(0),
+            Some(0)
+        );
+        assert_eq!(
+            validator_verifier.num_validators_for_voting_power(10),
+            Some(4)
+        );
+        assert_eq!(validator_verifier.num_validators_for_voting_power(11), None);
+    }
+
+    #[test]
+    fn test_validator_verifier_builder() {
+        let mut builder = ValidatorVerifierBuilder::new(false);
+        builder.add_validators(4, 1);
+        let (validator_signers, validator_verifier) = builder.build();
+        // The validators are the ones of `random_validator_verifier`
+        assert_eq!(
+            validator_verifier,
+            random_validator_verifier(4, None, false).1
+        );
+        assert_eq!(validator_verifier.quorum_voting_power(), 3);
+
+        // The quorum follows the updates of the validators
+        builder
+            .remove_validator(&validator_signers[0].author())
+            .update_voting_power(&validator_signers[1].author(), 4)
+            .add_validator(3);
+        let (new_signers, new_verifier) = builder.build();
+        let authors = |signers: &[ValidatorSigner]| {
+            signers
+                .iter()
+                .map(|signer| signer.author())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(authors(&new_signers[..3]), authors(&validator_signers[1..]));
+        // The signer of the removed validator isn't reused
+        assert_eq!(
+            new_signers[3].author(),
+            ValidatorSigner::random([4; 32]).author()
+        );
+        assert_eq!(
+            (
+                new_verifier.total_voting_power(),
+                new_verifier.quorum_voting_power()
+            ),
+            (9, 7)
+        );
+        assert_eq!(
+            new_verifier.get_voting_power(&validator_signers[1].author()),
+            Some(4)
+        );
+
+        // Unless the quorum is custom
+        assert_eq!(
+            builder
+                .quorum_voting_power(5)
+                .build()
+                .1
+                .quorum_voting_power(),
+            5
+        );
+
+        // The signers stay distinct past 256 validators
+        for pseudo_random_account_address in [false, true] {
+            let (validator_signers, validator_verifier) =
+                ValidatorVerifierBuilder::new(pseudo_random_account_address)
+                    .add_validators(300, 1)
+                    .build();
+            assert_eq!(validator_verifier.len(), 300);
+            assert_eq!(
+                validator_signers[1].author(),
+                random_validator_verifier(2, None, pseudo_random_account_address).0[1].author()
+            );
+        }
+    }
+
+    #[test]
+    fn test_power_stats() {
+        let verifier_with_powers = |voting_powers: &[u64]| {
+            let mut builder = ValidatorVerifierBuilder::new(false);
+            for voting_power in voting_powers {
+                builder.add_validator(*voting_power);
+            }
+            builder.build()
+        };
+
+        let (validator_signers, validator_verifier) = verifier_with_powers(&[2, 5, 2, 1]);
+        let stats = validator_verifier.power_stats().unwrap();
+        assert_eq!((stats.min_voting_power, stats.max_voting_power), (1, 5));
+        assert_eq!(stats.median_voting_power, 2.0);
+        // 2 * (1 * 1 + 2 * 2 + 3 * 2 + 4 * 5) / (4 * 10) - 5 / 4
+        assert!((stats.gini_coefficient - 0.3).abs() < 1e-9);
+        // The quorum (of 7) needs the two validators with the most voting power
+        assert_eq!(
+            stats.min_quorum_validators,
+            Some(vec![
+                validator_signers[1].author(),
+                validator_signers[0].author()
+            ])
+        );
+
+        let stats = verifier_with_powers(&[3, 3, 3]).1.power_stats().unwrap();
+        assert_eq!(stats.median_voting_power, 3.0);
+        assert!(stats.gini_coefficient.abs() < 1e-9);
+        assert_eq!(stats.min_quorum_validators.map(|v| v.len()), Some(3));
+
+        assert_eq!(verifier_with_powers(&[]).1.power_stats(), None);
+    }
+
+    #[test]
+    fn test_malformed_aggregation_inputs() {
+        let (validator_signers, validator_verifier) = random_validator_verifier(3, None, false);
+        let dummy_struct = TestAptosCrypto("Hello, World".to_string());
+
+        // Nothing to blame when there's nothing to aggregate
+        assert_eq!(
+            validator_verifier.aggregate_signatures(&PartialSignatures::empty()),
+            Err(VerifyError::FailedToAggregateSignature(None))
+        );
+
+        // Valid signatures and keys are never blamed
+        let mut partial_signatures = PartialSignatures::empty();
+        for signer in &validator_signers {
+            partial_signatures.add_signature(signer.author(), signer.sign(&dummy_struct).unwrap());
+        }
+        assert_eq!(
+            find_malformed_signature(partial_signatures.signatures()),
+            None
+        );
+        let authors: Vec<_> = validator_signers
+            .iter()
+            .map(|signer| signer.author())
+            .collect();
+        let public_keys: Vec<_> = validator_signers
+            .iter()
+            .map(|signer| signer.public_key())
+            .collect();
+        assert_eq!(
+            find_malformed_public_key(authors.iter().zip(public_keys.iter())),
+            None
+        );
+
+        // A low-order point (see the bls12381 tests of aptos-crypto) is blamed on its validator
+        let low_order_point = hex::decode(
+            "ae3cd9403b69c20a0d455fd860e977fe6ee7140a7f091f26c860f2caccd3e0a7a7365798ac10df776675b3a67db8faa0",
+        )
+        .unwrap();
+        let mut public_keys = public_keys;
+        public_keys[1] = PublicKey::try_from(low_order_point.as_slice()).unwrap();
+        assert_eq!(
+            find_malformed_public_key(authors.iter().zip(public_keys.iter())),
+            Some(authors[1])
+        );
+    }
+
     #[test]
     fn test_invalid_multi_signatures() {
         let validator_signer = ValidatorSigner::random(TEST_SEED);